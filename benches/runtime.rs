@@ -0,0 +1,225 @@
+//! Benchmarks of representative workloads, run through the `rune` binary.
+//!
+//! The binary is started from a dump of the bootstrapped runtime for every
+//! sample, and the workload times itself with `benchmark-call`, so startup
+//! isn't part of the measurement. Each workload is reported twice: the total
+//! time, and the time spent in garbage collection, so a regression in the VM
+//! can be told apart from one in the GC.
+use criterion::{criterion_group, criterion_main, Criterion, SamplingMode};
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+const RUNE: &str = env!("CARGO_BIN_EXE_rune");
+
+/// The large file read and compiled by the benchmarks.
+const LISP_FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/lisp/subr.el");
+
+/// The lisp directory, for benchmarks that read several files.
+const LISP_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/lisp");
+
+/// Run rune with `args` and return what it printed.
+fn run(args: &[&str]) -> String {
+    let output = Command::new(RUNE).args(args).output().expect("rune should start");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "rune failed: {stderr}");
+    String::from_utf8(output.stdout).expect("output should be utf8")
+}
+
+/// A dump of the bootstrapped runtime, made once and shared by every sample.
+fn dump_file() -> &'static str {
+    static DUMP: OnceLock<PathBuf> = OnceLock::new();
+    let path = DUMP.get_or_init(|| {
+        let path = std::env::temp_dir().join(format!("rune-bench-{}.pdmp", std::process::id()));
+        let expr = format!("(dump-emacs-portable {:?})", path.to_str().unwrap());
+        run(&["--batch", "--eval", &expr]);
+        path
+    });
+    path.to_str().unwrap()
+}
+
+/// The statistics returned by `benchmark-call`.
+struct Stats {
+    elapsed: Duration,
+    gc_elapsed: Duration,
+}
+
+/// Evaluate `setup`, then time `iters` calls of `func` with
+/// `benchmark-call`. Only the calls are measured.
+fn measure(setup: &str, func: &str, iters: u64) -> Stats {
+    let expr = format!("(progn {setup} (prin1 (benchmark-call #'{func} {iters})))");
+    let output = run(&["--dump-file", dump_file(), "--batch", "--eval", &expr]);
+    // Anything the workload printed comes before the statistics
+    let last = output.lines().last().unwrap_or_default();
+    let fields: Vec<&str> = last.trim_matches(|c| c == '(' || c == ')').split(' ').collect();
+    let [elapsed, _gcs, gc_elapsed] = fields[..] else {
+        panic!("benchmark-call returned {last}")
+    };
+    let seconds = |x: &str| Duration::from_secs_f64(x.parse().expect("time should be a float"));
+    Stats { elapsed: seconds(elapsed), gc_elapsed: seconds(gc_elapsed) }
+}
+
+fn bench_workload(c: &mut Criterion, name: &str, setup: &str, func: &str) {
+    let mut group = c.benchmark_group(name);
+    // Every sample starts a new process, so keep the number of them low
+    group.sample_size(10);
+    group.sampling_mode(SamplingMode::Flat);
+    group.bench_function("time", |b| b.iter_custom(|iters| measure(setup, func, iters).elapsed));
+    group.bench_function("gc", |b| b.iter_custom(|iters| measure(setup, func, iters).gc_elapsed));
+    group.finish();
+}
+
+fn read_file(c: &mut Criterion) {
+    let setup = format!(
+        "(set-buffer (get-buffer-create \" bench\"))
+         (insert-file-contents {LISP_FILE:?})
+         (defun bench-read ()
+           (goto-char (point-min))
+           (let ((forms nil))
+             (condition-case nil
+                 (while t (push (read (current-buffer)) forms))
+               (error nil))
+             forms))"
+    );
+    bench_workload(c, "read-file", &setup, "bench-read");
+}
+
+fn parallel_load(c: &mut Criterion) {
+    // Each file is read on its own thread, with its own heap. Compared with
+    // reading them one after another, this shows how much interning the
+    // symbols serializes loading.
+    let setup = format!(
+        "(defvar bench-sources
+           (mapcar (lambda (file)
+                     (with-temp-buffer
+                       (insert-file-contents (expand-file-name file {LISP_DIR:?}))
+                       (buffer-string)))
+                   '(\"subr.el\" \"files.el\" \"custom.el\" \"cus-face.el\"
+                     \"window.el\" \"keymap.el\")))
+         (defun bench-read-source (source)
+           (let ((pos 0))
+             (condition-case nil
+                 (while t (setq pos (cdr (read-from-string source pos))))
+               (error nil))))
+         (defun bench-load-serial ()
+           (mapc #'bench-read-source bench-sources))
+         (defun bench-load-parallel ()
+           (mapc #'thread-join
+                 (mapcar (lambda (source)
+                           (make-thread (lambda () (bench-read-source source))))
+                         bench-sources)))"
+    );
+    bench_workload(c, "load-serial", &setup, "bench-load-serial");
+    bench_workload(c, "load-parallel", &setup, "bench-load-parallel");
+}
+
+fn read_strings(c: &mut Criterion) {
+    // Short literals, like the names and keys in a large data file
+    let setup = r#"(defvar bench-strings
+                     (with-temp-buffer
+                       (insert "(")
+                       (dotimes (i 20000)
+                         (insert (format "(\"key-%d\" \"value\") " (% i 500))))
+                       (insert ")")
+                       (buffer-string)))
+                   (defun bench-read-strings ()
+                     (length (read bench-strings)))"#;
+    bench_workload(c, "read-strings", setup, "bench-read-strings");
+}
+
+fn compile_file(c: &mut Criterion) {
+    let setup = format!(
+        "(set-buffer (get-buffer-create \" bench\"))
+         (insert-file-contents {LISP_FILE:?})
+         (goto-char (point-min))
+         (defvar bench-defuns nil)
+         (condition-case nil
+             (while t
+               (let ((form (read (current-buffer))))
+                 (when (eq (car-safe form) 'defun)
+                   (push (cons 'lambda (cddr form)) bench-defuns))))
+           (error nil))
+         (defun bench-compile ()
+           (dolist (func bench-defuns)
+             (byte-compile func)))"
+    );
+    bench_workload(c, "compile-file", &setup, "bench-compile");
+}
+
+fn numeric_loop(c: &mut Criterion) {
+    let setup = "(defun bench-numeric ()
+                   (let ((i 0) (sum 0))
+                     (while (< i 100000)
+                       (setq sum (+ sum (* i i)))
+                       (setq i (1+ i)))
+                     sum))
+                 (byte-compile 'bench-numeric)";
+    bench_workload(c, "numeric-loop", setup, "bench-numeric");
+}
+
+fn dispatch_loop(c: &mut Criterion) {
+    // Mostly stack, variable and jump instructions, so the time is spent
+    // dispatching rather than in the functions called
+    let setup = "(defvar bench-counter 0)
+                 (defun bench-dispatch ()
+                   (let ((i 0) (vec (make-vector 16 1)) (sum 0))
+                     (while (< i 100000)
+                       (setq sum (+ sum (aref vec (logand i 15))))
+                       (setq bench-counter (1+ bench-counter))
+                       (setq i (1+ i)))
+                     sum))
+                 (byte-compile 'bench-dispatch)";
+    bench_workload(c, "dispatch-loop", setup, "bench-dispatch");
+}
+
+fn list_processing(c: &mut Criterion) {
+    let setup = "(defun bench-lists ()
+                   (let ((list nil))
+                     (dotimes (i 10000)
+                       (push (cons i (number-to-string i)) list))
+                     (length (sort (mapcar #'car (reverse list)) #'>))))
+                 (byte-compile 'bench-lists)";
+    bench_workload(c, "list-processing", setup, "bench-lists");
+}
+
+fn last_elements(c: &mut Criterion) {
+    // `last` walks the list once, compared with finding the link from the
+    // length, which walks it twice
+    let setup = "(defvar bench-list (number-sequence 1 100000))
+                 (defun bench-last ()
+                   (last bench-list 10))
+                 (defun bench-last-with-length ()
+                   (nthcdr (- (safe-length bench-list) 10) bench-list))";
+    bench_workload(c, "last", setup, "bench-last");
+    bench_workload(c, "last-with-length", setup, "bench-last-with-length");
+}
+
+fn hash_table_churn(c: &mut Criterion) {
+    let setup = "(defun bench-hash ()
+                   (let ((table (make-hash-table :test 'equal)))
+                     (dotimes (i 10000)
+                       (puthash (number-to-string i) i table))
+                     (dotimes (i 10000)
+                       (when (= (% i 3) 0)
+                         (remhash (number-to-string i) table)))
+                     (dotimes (i 10000)
+                       (gethash (number-to-string i) table))
+                     (hash-table-count table)))
+                 (byte-compile 'bench-hash)";
+    bench_workload(c, "hash-table-churn", setup, "bench-hash");
+}
+
+criterion_group!(
+    benches,
+    read_file,
+    parallel_load,
+    read_strings,
+    compile_file,
+    numeric_loop,
+    dispatch_loop,
+    list_processing,
+    last_elements,
+    hash_table_churn
+);
+criterion_main!(benches);
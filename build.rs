@@ -2,8 +2,9 @@ use std::ffi::OsStr;
 use std::io::Write as _;
 use std::path::MAIN_SEPARATOR;
 use std::{
+    collections::HashSet,
     fs::{self, File},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 // take an input str and parse it with syn::ExprCall and return the args as strings
@@ -49,6 +50,20 @@ fn convert(lisp_name: impl Into<String>, rust_name: &str) -> String {
     lisp_name
 }
 
+/// Every rust file under `dir`, including subdirectories.
+fn rust_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(rust_files(&path));
+        } else if path.extension().and_then(OsStr::to_str) == Some("rs") {
+            files.push(path);
+        }
+    }
+    files
+}
+
 #[derive(PartialEq)]
 enum DefvarType {
     Bool,
@@ -61,82 +76,95 @@ fn main() {
     let mut all_defvar = Vec::new();
     let mut all_defsym = Vec::new();
 
-    // rerun for all top level files
-    for entry in fs::read_dir("src").unwrap() {
-        let path = entry.unwrap().path();
-        if path.extension().and_then(OsStr::to_str) == Some("rs") {
-            println!("cargo:rerun-if-changed={}", path.display());
+    // Symbols and variables can be defined anywhere in the crate, but
+    // functions only in top level files
+    for path in rust_files(Path::new("src")) {
+        println!("cargo:rerun-if-changed={}", path.display());
+        let top_level = path.parent() == Some(Path::new("src"));
+        let contents = fs::read_to_string(&path).unwrap();
+        for (start, end) in contents.match_indices("\n#[defun") {
+            assert!(top_level, "defun outside of a top level file {path:?}");
+            let non_symbol = |c: char| !(c.is_alphanumeric() || c == '_');
+            let name = get_substring_between_predicate(&contents[start..], "fn ", non_symbol);
+
+            // check if the substring immdiately after end is name
+            let rest = &contents[(start + end.len())..];
+            let lisp_name = if rest.starts_with("(name") {
+                get_substring_between(rest, "name = \"", "\"").to_string()
+            } else {
+                name.replace('_', "-")
+            };
+            // convert the path name to a hierarchy name with slashes replaced with colons
+            let struct_name = {
+                let basename = path.strip_prefix("src/").unwrap().file_stem().unwrap();
+                let import_path = basename.to_str().unwrap().replace(MAIN_SEPARATOR, ":");
+                format!("crate::{import_path}::__subr_{name}")
+            };
+            all_defun.push((struct_name, name.to_string(), lisp_name));
+        }
+        // process all strings starting with defvar
+        for (start, _) in contents.match_indices("\ndefvar") {
+            let defvar_type = if contents[start..].starts_with("\ndefvar_bool!") {
+                DefvarType::Bool
+            } else if contents[start..].starts_with("\ndefvar!") {
+                DefvarType::Other
+            } else {
+                continue;
+            };
+            let body = get_substring_between(&contents[start..], "!", ";");
+            let args = parse_args(body);
+            let len = args.len();
+            let mut fields = args.into_iter();
+            let ident = fields.next().unwrap();
+            let (ident, name, value) = match len {
+                1 => {
+                    let name = map_varname(&ident);
+                    (ident, name, None)
+                }
+                2 => {
+                    let name = map_varname(&ident);
+                    let value = fields.next().unwrap();
+                    (ident, name, Some(value))
+                }
+                3 => {
+                    let name = fields.next().unwrap();
+                    let value = fields.next().unwrap();
+                    (ident, name, Some(value))
+                }
+                _ => panic!("defvar form was too long {path:?}"),
+            };
+            all_defvar.push((ident, name, value, defvar_type));
         }
-    }
 
-    for entry in fs::read_dir("src").unwrap() {
-        let entry = entry.unwrap();
-        let path = entry.path();
-        if path.is_file() {
-            let contents = fs::read_to_string(&path).unwrap();
-            for (start, end) in contents.match_indices("\n#[defun") {
-                let non_symbol = |c: char| !(c.is_alphanumeric() || c == '_');
-                let name = get_substring_between_predicate(&contents[start..], "fn ", non_symbol);
+        // process all strings starting with defsym
+        for (start, _) in contents.match_indices("\ndefsym!") {
+            let body = get_substring_between(&contents[start..], "defsym!", ";");
+            let args = parse_args(body);
+            let mut fields = args.into_iter();
+            let name = fields.next().unwrap();
+            let value = fields.next();
+            all_defsym.push((name, value));
+        }
+    }
 
-                // check if the substring immdiately after end is name
-                let rest = &contents[(start + end.len())..];
-                let lisp_name = if rest.starts_with("(name") {
-                    get_substring_between(rest, "name = \"", "\"").to_string()
-                } else {
-                    name.replace('_', "-")
-                };
-                // convert the path name to a hierarchy name with slashes replaced with colons
-                let struct_name = {
-                    let basename = path.strip_prefix("src/").unwrap().file_stem().unwrap();
-                    let import_path = basename.to_str().unwrap().replace(MAIN_SEPARATOR, ":");
-                    format!("crate::{import_path}::__subr_{name}")
-                };
-                all_defun.push((struct_name, name.to_string(), lisp_name));
-            }
-            // process all strings starting with defvar
-            for (start, _) in contents.match_indices("\ndefvar") {
-                let defvar_type = if contents[start..].starts_with("\ndefvar_bool!") {
-                    DefvarType::Bool
-                } else if contents[start..].starts_with("\ndefvar!") {
-                    DefvarType::Other
-                } else {
-                    continue;
-                };
-                let body = get_substring_between(&contents[start..], "!", ";");
-                let args = parse_args(body);
-                let len = args.len();
-                let mut fields = args.into_iter();
-                let ident = fields.next().unwrap();
-                let (ident, name, value) = match len {
-                    1 => {
-                        let name = map_varname(&ident);
-                        (ident, name, None)
-                    }
-                    2 => {
-                        let name = map_varname(&ident);
-                        let value = fields.next().unwrap();
-                        (ident, name, Some(value))
-                    }
-                    3 => {
-                        let name = fields.next().unwrap();
-                        let value = fields.next().unwrap();
-                        (ident, name, Some(value))
-                    }
-                    _ => panic!("defvar form was too long {path:?}"),
-                };
-                all_defvar.push((ident, name, value, defvar_type));
-            }
+    let defsym_names: Vec<String> = all_defsym
+        .iter()
+        .map(|(sym, name)| match name {
+            Some(name) => name.trim_matches('"').to_string(),
+            None => map_varname(sym),
+        })
+        .collect();
 
-            // process all strings starting with defsym
-            for (start, _) in contents.match_indices("\ndefsym!") {
-                let body = get_substring_between(&contents[start..], "defsym!", ";");
-                let args = parse_args(body);
-                let mut fields = args.into_iter();
-                let name = fields.next().unwrap();
-                let value = fields.next();
-                all_defsym.push((name, value));
-            }
-        }
+    // Catch a symbol defined twice here instead of when the symbol table is
+    // initialized at runtime
+    let mut seen = HashSet::from(["nil", "t"]);
+    let all_names = defsym_names
+        .iter()
+        .chain(all_defvar.iter().map(|x| &x.1))
+        .chain(all_defun.iter().map(|x| &x.2));
+    for name in all_names {
+        let name = name.trim_matches('"');
+        assert!(seen.insert(name), "symbol {name} is defined more than once");
     }
 
     let out_dir = std::env::var("OUT_DIR").unwrap();
@@ -162,11 +190,7 @@ pub(in crate::core) static BUILTIN_SYMBOLS: [SymbolCell; {symbol_len}] = [
     .unwrap();
 
     // write the list of all defsym to the file
-    for (sym, name) in &all_defsym {
-        let sym_name = match name {
-            Some(name) => name.trim_matches('"').to_string(),
-            None => map_varname(sym),
-        };
+    for sym_name in &defsym_names {
         writeln!(f, "    SymbolCell::new_static(\"{sym_name}\"),").unwrap();
     }
 
@@ -226,10 +250,10 @@ pub(crate) fn init_symbols() {{
         "
 /// TODO: Use `LazyLock`: https://github.com/CeleritasCelery/rune/issues/34
 use std::sync::OnceLock;
-static INTERNED_SYMBOLS: OnceLock<std::sync::Mutex<SymbolMap>> = OnceLock::new();
+static INTERNED_SYMBOLS: OnceLock<SymbolMap> = OnceLock::new();
 
-pub(crate) fn interned_symbols() -> &'static std::sync::Mutex<SymbolMap> {{
-    INTERNED_SYMBOLS.get_or_init(|| std::sync::Mutex::new({{
+pub(crate) fn interned_symbols() -> &'static SymbolMap {{
+    INTERNED_SYMBOLS.get_or_init(|| {{
         let size: usize = {symbol_len};
         let mut map = SymbolMapCore::with_capacity(size);
         for sym in &sym::BUILTIN_SYMBOLS {{
@@ -237,9 +261,9 @@ pub(crate) fn interned_symbols() -> &'static std::sync::Mutex<SymbolMap> {{
         }}
         SymbolMap {{
             map,
-            block: Block::new_global(),
+            block: std::sync::Mutex::new(Block::new_global()),
         }}
-    }}))
+    }})
 }}
 "
     )
@@ -65,6 +65,20 @@ pub(crate) fn expand(function: Function, spec: Spec) -> TokenStream {
         Ok(crate::core::object::IntoObject::into_obj(val, cx).into())
     };
 
+    // The fast path is an expression for a `FastFn`
+    let fast = match spec.fast.as_deref().map(syn::parse_str::<syn::Expr>) {
+        Some(Err(e)) => return e.to_compile_error(),
+        Some(Ok(fast)) => quote! {
+            Some({
+                use crate::core::object::FastFn;
+                #fast
+            })
+        },
+        None => quote! {None},
+    };
+
+    let io = spec.io;
+
     let arg_count_guard = match (required as usize, optional as usize, rest) {
         (r, 0, false) => quote! {arg_cnt != #r},
         (0, o, false) => quote! {#o < arg_cnt},
@@ -101,7 +115,9 @@ pub(crate) fn expand(function: Function, spec: Spec) -> TokenStream {
                 optional: #optional,
                 rest: #rest,
                 advice: false,
-            }
+            },
+            fast: #fast,
+            io: #io,
         };
 
         #body
@@ -142,6 +158,12 @@ fn get_arg_conversion(args: &[ArgType]) -> Vec<TokenStream> {
                 Gc::Obj => quote! {&args[(#idx).min(args.len())..]},
                 Gc::Other => unreachable!(),
             },
+            // Keywords<T>
+            ArgType::Keywords => {
+                let bind =
+                    quote! {crate::core::gc::Rt::bind_slice(&args[(#idx).min(args.len())..], cx)};
+                quote! {crate::core::object::Keywords::from_slice(#bind)?}
+            }
             // ArgSlice
             ArgType::ArgSlice => {
                 let positional = args.iter().filter(|x| x.is_positional_arg()).count();
@@ -225,6 +247,7 @@ enum ArgType {
     Slice(Gc),
     SliceRt(Gc),
     ArgSlice,
+    Keywords,
     Option,
     OptionRt,
     Other,
@@ -243,7 +266,7 @@ impl ArgType {
 
     fn is_rest_arg(self) -> bool {
         use ArgType as A;
-        matches!(self, A::SliceRt(_) | A::Slice(_) | A::ArgSlice)
+        matches!(self, A::SliceRt(_) | A::Slice(_) | A::ArgSlice | A::Keywords)
     }
 }
 
@@ -281,7 +304,9 @@ fn check_invariants(args: &[ArgType], sig: &syn::Signature) -> Result<(), Error>
     let is_mut = args.iter().any(|x| matches!(x, ArgType::Context(MUT)));
     if is_mut {
         let mut iter = sig.inputs.iter().zip(args.iter());
-        if let Some((arg, _)) = iter.find(|(_, ty)| matches!(ty, ArgType::Gc(_))) {
+        if let Some((arg, _)) =
+            iter.find(|(_, ty)| matches!(ty, ArgType::Gc(_) | ArgType::Keywords))
+        {
             return Err(Error::new_spanned(
                 arg,
                 "Can't have raw Gc pointer in function with mutable Context",
@@ -368,6 +393,7 @@ fn get_arg_type(ty: &syn::Type) -> Result<ArgType, Error> {
             let name = get_path_ident_name(path);
             match &*name {
                 "ArgSlice" => ArgType::ArgSlice,
+                "Keywords" => ArgType::Keywords,
                 "Rt" | "Rto" => get_rt_type(path, false)?,
                 "Option" => {
                     let outer = path.path.segments.last().unwrap();
@@ -444,11 +470,16 @@ pub(crate) struct Spec {
     name: Option<String>,
     #[darling(default)]
     required: Option<u16>,
+    #[darling(default)]
+    fast: Option<String>,
+    #[darling(default)]
+    io: bool,
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use darling::ast::NestedMeta;
 
     fn test_sig(stream: TokenStream, min: Option<u16>, expect: (u16, u16, bool)) {
         let function: Function = syn::parse2(stream).unwrap();
@@ -472,6 +503,7 @@ mod test {
             Some(2),
             (2, 1, false),
         );
+        test_sig(quote! {fn foo(var0: u8, kw: Keywords<Args>) -> u8 {0}}, None, (1, 0, true));
         test_sig(
             quote! { fn foo(a: &Rt<Slot<Gc<foo>>>, b: &[Rt<Slot<Object>>], env: &Rt<Env>, cx: &mut Context) -> u8 {0} },
             None,
@@ -513,6 +545,7 @@ mod test {
         test_args(quote! {x: &[Gc<T>]}, &[ArgType::Slice(Gc::Other)]);
         test_args(quote! {x: &[u8]}, &[ArgType::Slice(Gc::Other)]);
         test_args(quote! {x: ArgSlice}, &[ArgType::ArgSlice]);
+        test_args(quote! {x: Keywords<Args>}, &[ArgType::Keywords]);
         test_args(quote! {x: &[Rt<Slot<Object>>]}, &[ArgType::SliceRt(Gc::Obj)]);
         test_args(quote! {x: &[Rto<Object>]}, &[ArgType::SliceRt(Gc::Obj)]);
         test_args(quote! {x: &mut Context}, &[ArgType::Context(MUT)]);
@@ -553,6 +586,8 @@ mod test {
         check_error(quote! {fn foo(a: Rt<Slot<Object>>) {}});
         check_error(quote! {fn foo(a: u8, b: &[Object], c: &[Object]) {}});
         check_error(quote! {fn foo(a: u8, b: Option<u8>, c: u8) {}});
+        check_error(quote! {fn foo(a: &[Object], b: Keywords<Args>) {}});
+        check_error(quote! {fn foo(a: Keywords<Args>, cx: &mut Context) {}});
     }
 
     #[test]
@@ -570,4 +605,33 @@ mod test {
         let result = expand(function, spec);
         println!("{result}");
     }
+
+    #[test]
+    fn test_expand_fast() {
+        let stream = quote! { fn lognot(number: i64) -> i64 { !number } };
+        let function: Function = syn::parse2(stream.clone()).unwrap();
+        let spec = Spec { fast: Some("FastFn::Unary(|x| Some(!x))".into()), ..Default::default() };
+        let result = expand(function, spec).to_string();
+        assert!(result.contains("fast : Some"), "{result}");
+
+        let function: Function = syn::parse2(stream).unwrap();
+        let spec = Spec { fast: Some("FastFn::Unary(".into()), ..Default::default() };
+        let result = expand(function, spec).to_string();
+        assert!(result.contains("compile_error"), "{result}");
+    }
+
+    #[test]
+    fn test_expand_io() {
+        let stream = quote! { fn delete_file(file: &str) {} };
+        let function: Function = syn::parse2(stream.clone()).unwrap();
+        let result = expand(function, Spec::default()).to_string();
+        assert!(result.contains("io : false"), "{result}");
+
+        let function: Function = syn::parse2(stream).unwrap();
+        let args = NestedMeta::parse_meta_list(quote! { io }).unwrap();
+        let spec = Spec::from_list(&args).unwrap();
+        assert!(spec.io);
+        let result = expand(function, spec).to_string();
+        assert!(result.contains("io : true"), "{result}");
+    }
 }
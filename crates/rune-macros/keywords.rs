@@ -0,0 +1,134 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::Error;
+
+pub(crate) fn expand(orig: &syn::DeriveInput) -> TokenStream {
+    match derive(orig) {
+        Ok(stream) => stream,
+        Err(e) => e.to_compile_error(),
+    }
+}
+
+fn derive(orig: &syn::DeriveInput) -> Result<TokenStream, Error> {
+    let name = &orig.ident;
+    let syn::Data::Struct(syn::DataStruct { fields: syn::Fields::Named(fields), .. }) = &orig.data
+    else {
+        return Err(Error::new_spanned(orig, "FromKeywords requires a struct with named fields"));
+    };
+
+    if orig.generics.type_params().next().is_some() || orig.generics.const_params().next().is_some()
+    {
+        return Err(Error::new_spanned(&orig.generics, "FromKeywords only supports a lifetime"));
+    }
+    let lifetime = match orig.generics.lifetimes().collect::<Vec<_>>().as_slice() {
+        [] => None,
+        [param] => Some(param.lifetime.clone()),
+        _ => return Err(Error::new_spanned(&orig.generics, "Expected at most one lifetime")),
+    };
+    let (impl_lifetime, struct_generics) = match &lifetime {
+        Some(lt) => (lt.clone(), quote! {<#lt>}),
+        None => (syn::Lifetime::new("'ob", proc_macro2::Span::call_site()), quote! {}),
+    };
+
+    let mut slots = Vec::new();
+    let mut matches = Vec::new();
+    let mut inits = Vec::new();
+    for field in &fields.named {
+        let ident = field.ident.as_ref().unwrap();
+        let slot = format_ident!("__kw_{}", ident);
+        let keyword = format!(":{}", ident.to_string().trim_start_matches("r#").replace('_', "-"));
+        slots.push(quote! { let mut #slot = None; });
+        matches.push(quote! {
+            #keyword => {
+                if #slot.is_none() {
+                    #slot = Some(value);
+                }
+            }
+        });
+        inits.push(match option_inner(&field.ty) {
+            // Option<Object> is `None` only when the keyword is missing, so
+            // that an explicit nil can be told apart from the default
+            Some(inner) if is_object(inner) => quote! { #ident: #slot },
+            Some(_) => quote! {
+                #ident: match #slot {
+                    Some(x) => crate::core::object::Object::try_from_option(x)?,
+                    None => None,
+                }
+            },
+            None => quote! {
+                #ident: std::convert::TryFrom::try_from(#slot.unwrap_or(crate::core::object::NIL))?
+            },
+        });
+    }
+
+    Ok(quote! {
+        #[automatically_derived]
+        impl<#impl_lifetime> crate::core::object::FromKeywords<#impl_lifetime> for #name #struct_generics {
+            fn from_keywords(
+                args: &[crate::core::object::Object<#impl_lifetime>],
+            ) -> anyhow::Result<Self> {
+                if args.len() % 2 != 0 {
+                    anyhow::bail!("Odd number of keyword arguments: {}", args.len());
+                }
+                #(#slots)*
+                for pair in args.chunks(2) {
+                    let (key, value) = (pair[0], pair[1]);
+                    let crate::core::object::ObjectType::Symbol(sym) = key.untag() else {
+                        anyhow::bail!("Invalid keyword argument {key}");
+                    };
+                    match sym.name() {
+                        #(#matches)*
+                        _ => anyhow::bail!("Invalid keyword argument {key}"),
+                    }
+                }
+                Ok(Self { #(#inits),* })
+            }
+        }
+    })
+}
+
+/// If `ty` is `Option<T>`, return `T`
+fn option_inner(ty: &syn::Type) -> Option<&syn::Type> {
+    let syn::Type::Path(path) = ty else { return None };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(generic) => match generic.args.first()? {
+            syn::GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_object(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Path(path) => path.path.segments.last().is_some_and(|x| x.ident == "Object"),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let stream = quote! {
+            struct Args<'ob> {
+                test: Option<Symbol<'ob>>,
+                null_object: Option<Object<'ob>>,
+                size: usize,
+            }
+        };
+        let input: syn::DeriveInput = syn::parse2(stream).unwrap();
+        let result = expand(&input).to_string();
+        assert!(result.contains("\":null-object\""));
+        assert!(!result.contains("compile_error"));
+
+        let input: syn::DeriveInput = syn::parse2(quote! { struct Args(u8); }).unwrap();
+        assert!(expand(&input).to_string().contains("compile_error"));
+    }
+}
@@ -4,11 +4,13 @@
 //!
 //! * [`macro@defun`]: Functions hydrated to emacs lisp.
 //! * [Trace](`macro@Trace`): TODO
+//! * [`macro@FromKeywords`]: Keyword arguments for a `defun`.
 use darling::{ast::NestedMeta, Error, FromMeta};
 use proc_macro::TokenStream;
 use syn::parse_macro_input;
 
 mod defun;
+mod keywords;
 mod trace;
 
 /// ## `#[defun]`
@@ -36,6 +38,26 @@ mod trace;
 ///
 /// The return object is interesting, as it's not so easily inferrable from the signature, but rather from documentation.
 /// In this case, the `make-vector` defun returns a *newly created vector*.
+///
+/// ### Fast path
+///
+/// A numeric function can give the VM a version that works directly on fixnums with `fast`, a
+/// `FastFn` expression. It is called instead of the defun when every argument is a fixnum.
+///
+/// ```ignore
+/// #[defun(name = "+", fast = "FastFn::Binary(i64::checked_add)")]
+/// fn add(vars: &[Number]) -> NumberValue {}
+/// ```
+///
+/// ### IO
+///
+/// A function that reads or writes files, or reaches outside of the interpreter, is marked
+/// with `io`. It can't be called in a sandbox unless IO is allowed.
+///
+/// ```ignore
+/// #[defun(io)]
+/// fn delete_file(filename: &str) -> Result<()> {}
+/// ```
 #[proc_macro_attribute]
 pub fn defun(attr_ts: TokenStream, fn_ts: TokenStream) -> TokenStream {
     let function = parse_macro_input!(fn_ts as defun::Function);
@@ -56,3 +78,29 @@ pub fn trace_derive(stream: TokenStream) -> TokenStream {
     let derived = parse_macro_input!(stream as syn::DeriveInput);
     trace::expand(&derived).into()
 }
+
+/// ## `FromKeywords`
+///
+/// Collects keyword arguments, like `:test #'equal`, into a struct. Each
+/// field is matched by its name in `kebab-case` with a leading colon. A
+/// `defun` takes the struct as its final argument, wrapped in `Keywords`.
+///
+/// Fields of type `Option<T>` are `None` if the keyword is missing or nil,
+/// except `Option<Object>`, which is only `None` if the keyword is missing.
+/// Any other field is converted from nil when its keyword is missing.
+///
+/// ```ignore
+/// #[derive(FromKeywords)]
+/// struct HashTableArgs<'ob> {
+///     test: Option<Symbol<'ob>>,
+///     size: Option<usize>,
+/// }
+///
+/// #[defun]
+/// fn make_hash_table(args: Keywords<HashTableArgs>) -> ... {}
+/// ```
+#[proc_macro_derive(FromKeywords)]
+pub fn from_keywords_derive(stream: TokenStream) -> TokenStream {
+    let derived = parse_macro_input!(stream as syn::DeriveInput);
+    keywords::expand(&derived).into()
+}
@@ -589,6 +589,34 @@ for speeding up processing.")
        `(,head . ,(byte-optimize--rename-var-body var new-var args)))
       (_ form))))
 
+;; `cconv' turns the value of a lexical variable that is never used into
+;; (ignore VALUE), wrapped with its warning about the unused variable.
+(defun byte-optimize--let-value (name form)
+  "Optimize FORM, the value bound to the lexical variable NAME.
+If NAME is never used and FORM has no side effects, return nil, so the
+binding is dropped like any other unused constant binding.  If the side
+effects of FORM prevent that, warn that the binding is kept."
+  (pcase (and (not byte-optimize--inhibit-outside-loop-constprop) form)
+    ((or (and `(ignore ,value) (let warning nil))
+         `(progn ,(and warning `(macroexp--funcall-if-compiled . ,_))
+                 (ignore ,value)))
+     (let ((effect (byte-optimize-form value t)))
+       (cond
+        ((null effect)
+         ;; The binding and its warning are gone, so warn about it now.
+         (when warning
+           (funcall (eval (cadr warning))))
+         nil)
+        (t
+         ;; This replaces the warning about the unused variable.
+         (when (and warning (byte-compile-warning-enabled-p 'lexical name))
+           (byte-compile-warn-x
+            name
+            "Unused lexical variable `%S' kept for the side effects of its value"
+            (bare-symbol name)))
+         `(ignore ,effect)))))
+    (_ (byte-optimize-form form nil))))
+
 (defun byte-optimize-let-form (head form for-effect)
   ;; Recursively enter the optimizer for the bindings and body
   ;; of a let or let*.  This for depth-firstness: forms that
@@ -604,7 +632,7 @@ for speeding up processing.")
         (while bindings
           (let* ((binding (car bindings))
                  (name (car binding))
-                 (expr (byte-optimize-form (cadr binding) nil)))
+                 (expr (byte-optimize--let-value name (cadr binding))))
             (setq bindings (cdr bindings))
             (when (and (eq head 'let*)
                        (memq name byte-optimize--aliased-vars))
@@ -1964,6 +1992,34 @@ See Info node `(elisp) Integer Basics'."
 ;; The variable `byte-boolean-vars' is now primitive and updated
 ;; automatically by DEFVAR_BOOL.
 
+(defun byte-optimize--dead-stack-set-p (rest depth side-effect-free)
+  "Whether the stack slot DEPTH values down is set before it is read.
+REST is the lapcode following a `stack-set' of the slot.  Only the
+straight-line code up to the first jump or tag is searched, and only
+through the operations in SIDE-EFFECT-FREE, since an error could lead
+to a handler that reads the slot."
+  (catch 'dead
+    (dolist (lap rest)
+      (let ((op (car lap)))
+        (cond
+         ((memq op '(byte-dup byte-stack-ref))
+          (when (eql (if (eq op 'byte-dup) 0 (cdr lap)) depth)
+            (throw 'dead nil))
+          (setq depth (1+ depth)))
+         ((eq op 'byte-stack-set)
+          (cond ((zerop depth) (throw 'dead nil))
+                ((eql (cdr lap) depth) (throw 'dead t)))
+          (setq depth (1- depth)))
+         ((memq op side-effect-free)
+          ;; Assume the operation pushes one value, so it pops
+          ;; one more than its stack effect.
+          (let ((effect (aref byte-stack+-info (symbol-value op))))
+            (when (or (null effect) (< depth (- 1 effect)))
+              (throw 'dead nil))
+            (setq depth (+ depth effect))))
+         (t (throw 'dead nil)))))
+    nil))
+
 (defun byte-optimize-lapcode (lap &optional _for-effect)
   "Simple peephole optimizer.  LAP is both modified and returned.
 If FOR-EFFECT is non-nil, the return value is assumed to be of no importance."
@@ -2502,6 +2558,19 @@ If FOR-EFFECT is non-nil, the return value is assumed to be of no importance."
 	  (setq lap (delq lap0 lap))
 	  (byte-compile-log-lap "  %s %s\t-->\t%s" lap0 lap1 lap1))
 
+	 ;;
+	 ;; stack-set-M ... stack-set-N  -->  discard ... stack-set-N
+	 ;; where stack-set-N overwrites the slot set by stack-set-M before
+	 ;; anything reads it.
+	 ;;
+	 ((and (eq (car lap0) 'byte-stack-set)
+	       (byte-optimize--dead-stack-set-p
+	        (cdr rest) (1- (cdr lap0)) side-effect-free))
+	  (setq keep-going t)
+	  (byte-compile-log-lap "  %s ... (overwritten)\t-->\tdiscard" lap0)
+	  (setcar lap0 'byte-discard)
+	  (setcdr lap0 0))
+
 	 ;;
 	 ;; goto-X ... X: discard  ==>  discard goto-Y ... X: discard Y:
 	 ;;
@@ -3578,6 +3578,10 @@ lambda-expression."
 	      "variable reference to %s `%s'")
 	    (if (symbolp var) "constant" "nonvariable")
 	    var)))
+	((and (eq access-type 'assign)
+              (memq var byte-compile-const-variables))
+	 (when (byte-compile-warning-enabled-p 'constants var)
+	   (byte-compile-warn-x var "assignment to constant `%s'" var)))
 	((let ((od (get var 'byte-obsolete-variable)))
            (and od
                 (not (memq var byte-compile-not-obsolete-vars))
@@ -144,21 +144,24 @@ the SEQ moving forward, and the order of arguments to the
 FUNCTION is also reversed.
 
 \n(fn FUNCTION SEQ [KEYWORD VALUE]...)"
-  (cl--parsing-keywords (:from-end (:start 0) :end :initial-value :key) ()
-    (or (listp cl-seq) (setq cl-seq (append cl-seq nil)))
-    (setq cl-seq (cl-subseq cl-seq cl-start cl-end))
-    (if cl-from-end (setq cl-seq (nreverse cl-seq)))
-    (let ((cl-accum (cond ((memq :initial-value cl-keys) cl-initial-value)
-			  (cl-seq (cl--check-key (pop cl-seq)))
-			  (t (funcall cl-func)))))
-      (if cl-from-end
+  ;; RUNE-BOOTSTRAP - calls without keywords use the version in cl_lib.rs
+  (if (null cl-keys)
+      (cl--native-reduce cl-func cl-seq)
+    (cl--parsing-keywords (:from-end (:start 0) :end :initial-value :key) ()
+      (or (listp cl-seq) (setq cl-seq (append cl-seq nil)))
+      (setq cl-seq (cl-subseq cl-seq cl-start cl-end))
+      (if cl-from-end (setq cl-seq (nreverse cl-seq)))
+      (let ((cl-accum (cond ((memq :initial-value cl-keys) cl-initial-value)
+			    (cl-seq (cl--check-key (pop cl-seq)))
+			    (t (funcall cl-func)))))
+	(if cl-from-end
+	    (while cl-seq
+	      (setq cl-accum (funcall cl-func (cl--check-key (pop cl-seq))
+				      cl-accum)))
 	  (while cl-seq
-	    (setq cl-accum (funcall cl-func (cl--check-key (pop cl-seq))
-				    cl-accum)))
-	(while cl-seq
-	  (setq cl-accum (funcall cl-func cl-accum
-				  (cl--check-key (pop cl-seq))))))
-      cl-accum)))
+	    (setq cl-accum (funcall cl-func cl-accum
+				    (cl--check-key (pop cl-seq))))))
+	cl-accum))))
 
 ;;;###autoload
 (defun cl-fill (cl-seq cl-item &rest cl-keys)
@@ -281,7 +284,10 @@ This is a non-destructive function; it makes a copy of SEQ if necessary
 to avoid corrupting the original SEQ.
 \nKeywords supported:  :key :count :start :end :from-end
 \n(fn PREDICATE SEQ [KEYWORD VALUE]...)"
-  (apply 'cl-remove nil cl-list :if cl-pred cl-keys))
+  ;; RUNE-BOOTSTRAP - calls without keywords use the version in cl_lib.rs
+  (if (null cl-keys)
+      (cl--native-remove-if cl-pred cl-list)
+    (apply 'cl-remove nil cl-list :if cl-pred cl-keys)))
 
 ;;;###autoload
 (defun cl-remove-if-not (cl-pred cl-list &rest cl-keys)
@@ -508,8 +514,11 @@ This is a destructive function; it reuses the storage of SEQ whenever possible.
 Return the matching ITEM, or nil if not found.
 \nKeywords supported:  :test :test-not :key :start :end :from-end
 \n(fn ITEM SEQ [KEYWORD VALUE]...)"
-  (let ((cl-pos (apply 'cl-position cl-item cl-seq cl-keys)))
-    (and cl-pos (elt cl-seq cl-pos))))
+  ;; RUNE-BOOTSTRAP - calls without keywords use the version in cl_lib.rs
+  (if (null cl-keys)
+      (cl--native-find cl-item cl-seq)
+    (let ((cl-pos (apply 'cl-position cl-item cl-seq cl-keys)))
+      (and cl-pos (elt cl-seq cl-pos)))))
 
 ;;;###autoload
 (defun cl-find-if (cl-pred cl-list &rest cl-keys)
@@ -533,9 +542,12 @@ Return the matching item, or nil if not found.
 Return the index of the matching item, or nil if not found.
 \nKeywords supported:  :test :test-not :key :start :end :from-end
 \n(fn ITEM SEQ [KEYWORD VALUE]...)"
-  (cl--parsing-keywords (:test :test-not :key :if :if-not
-			(:start 0) :end :from-end) ()
-    (cl--position cl-item cl-seq cl-start cl-end cl-from-end)))
+  ;; RUNE-BOOTSTRAP - calls without keywords use the version in cl_lib.rs
+  (if (null cl-keys)
+      (cl--native-position cl-item cl-seq)
+    (cl--parsing-keywords (:test :test-not :key :if :if-not
+			  (:start 0) :end :from-end) ()
+      (cl--position cl-item cl-seq cl-start cl-end cl-from-end))))
 
 (defun cl--position (cl-item cl-seq cl-start &optional cl-end cl-from-end)
   (if (listp cl-seq)
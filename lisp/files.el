@@ -938,64 +938,65 @@ The path separator is colon in GNU and GNU-like systems."
         (error "No such directory found via CDPATH environment variable: %s" dir)
       (error "No such directory: %s" dir)))))
 
-(defun directory-files-recursively (dir regexp
-                                        &optional include-directories predicate
-                                        follow-symlinks)
-  "Return list of all files under directory DIR whose names match REGEXP.
-This function works recursively.  Files are returned in \"depth
-first\" order, and files from each directory are sorted in
-alphabetical order.  Each file name appears in the returned list
-in its absolute form.
-
-By default, the returned list excludes directories, but if
-optional argument INCLUDE-DIRECTORIES is non-nil, they are
-included.
-
-PREDICATE can be either nil (which means that all subdirectories
-of DIR are descended into), t (which means that subdirectories that
-can't be read are ignored), or a function (which is called with
-the name of each subdirectory, and should return non-nil if the
-subdirectory is to be descended into).
-
-If FOLLOW-SYMLINKS is non-nil, symbolic links that point to
-directories are followed.  Note that this can lead to infinite
-recursion."
-  (let* ((result nil)
-	 (files nil)
-         (dir (directory-file-name dir))
-	 ;; When DIR is "/", remote file names like "/method:" could
-	 ;; also be offered.  We shall suppress them.
-	 (tramp-mode (and tramp-mode (file-remote-p (expand-file-name dir)))))
-    (dolist (file (sort (file-name-all-completions "" dir)
-			'string<))
-      (unless (member file '("./" "../"))
-	(if (directory-name-p file)
-	    (let* ((leaf (substring file 0 (1- (length file))))
-		   (full-file (concat dir "/" leaf)))
-	      ;; Don't follow symlinks to other directories.
-	      (when (and (or (not (file-symlink-p full-file))
-                             (and (file-symlink-p full-file)
-                                  follow-symlinks))
-                         ;; Allow filtering subdirectories.
-                         (or (eq predicate nil)
-                             (eq predicate t)
-                             (funcall predicate full-file)))
-                (let ((sub-files
-                       (if (eq predicate t)
-                           (ignore-error file-error
-                             (directory-files-recursively
-			      full-file regexp include-directories
-                              predicate follow-symlinks))
-                         (directory-files-recursively
-			  full-file regexp include-directories
-                          predicate follow-symlinks))))
-		  (setq result (nconc result sub-files))))
-	      (when (and include-directories
-			 (string-match regexp leaf))
-		(setq result (nconc result (list full-file)))))
-	  (when (string-match regexp file)
-	    (push (concat dir "/" file) files)))))
-    (nconc result (nreverse files))))
+;; RUNE-BOOTSTRAP - directory-files-recursively is defined in dired.rs
+;; (defun directory-files-recursively (dir regexp
+;;                                         &optional include-directories predicate
+;;                                         follow-symlinks)
+;;   "Return list of all files under directory DIR whose names match REGEXP.
+;; This function works recursively.  Files are returned in \"depth
+;; first\" order, and files from each directory are sorted in
+;; alphabetical order.  Each file name appears in the returned list
+;; in its absolute form.
+
+;; By default, the returned list excludes directories, but if
+;; optional argument INCLUDE-DIRECTORIES is non-nil, they are
+;; included.
+
+;; PREDICATE can be either nil (which means that all subdirectories
+;; of DIR are descended into), t (which means that subdirectories that
+;; can't be read are ignored), or a function (which is called with
+;; the name of each subdirectory, and should return non-nil if the
+;; subdirectory is to be descended into).
+
+;; If FOLLOW-SYMLINKS is non-nil, symbolic links that point to
+;; directories are followed.  Note that this can lead to infinite
+;; recursion."
+;;   (let* ((result nil)
+;; 	 (files nil)
+;;          (dir (directory-file-name dir))
+;; 	 ;; When DIR is "/", remote file names like "/method:" could
+;; 	 ;; also be offered.  We shall suppress them.
+;; 	 (tramp-mode (and tramp-mode (file-remote-p (expand-file-name dir)))))
+;;     (dolist (file (sort (file-name-all-completions "" dir)
+;; 			'string<))
+;;       (unless (member file '("./" "../"))
+;; 	(if (directory-name-p file)
+;; 	    (let* ((leaf (substring file 0 (1- (length file))))
+;; 		   (full-file (concat dir "/" leaf)))
+;; 	      ;; Don't follow symlinks to other directories.
+;; 	      (when (and (or (not (file-symlink-p full-file))
+;;                              (and (file-symlink-p full-file)
+;;                                   follow-symlinks))
+;;                          ;; Allow filtering subdirectories.
+;;                          (or (eq predicate nil)
+;;                              (eq predicate t)
+;;                              (funcall predicate full-file)))
+;;                 (let ((sub-files
+;;                        (if (eq predicate t)
+;;                            (ignore-error file-error
+;;                              (directory-files-recursively
+;; 			      full-file regexp include-directories
+;;                               predicate follow-symlinks))
+;;                          (directory-files-recursively
+;; 			  full-file regexp include-directories
+;;                           predicate follow-symlinks))))
+;; 		  (setq result (nconc result sub-files))))
+;; 	      (when (and include-directories
+;; 			 (string-match regexp leaf))
+;; 		(setq result (nconc result (list full-file)))))
+;; 	  (when (string-match regexp file)
+;; 	    (push (concat dir "/" file) files)))))
+;;     (nconc result (nreverse files))))
 
 (defun directory-empty-p (dir)
   "Return t if DIR names an existing directory containing no other files.
@@ -6218,53 +6219,54 @@ Return non-nil if DIR is already a directory."
      (or (file-directory-p dir)
 	 (signal (car err) (cdr err))))))
 
-(defun make-directory (dir &optional parents)
-  "Create the directory DIR and optionally any nonexistent parent dirs.
-
-Interactively, the default choice of directory to create is the
-current buffer's default directory.  That is useful when you have
-visited a file in a nonexistent directory.
-
-Noninteractively, the second (optional) argument PARENTS, if
-non-nil, says whether to create parent directories that don't
-exist.  Interactively, this happens by default.
-
-Return non-nil if PARENTS is non-nil and DIR already exists as a
-directory, and nil if DIR did not already exist but was created.
-Signal an error if unsuccessful."
-  (interactive
-   (list (read-file-name "Make directory: " default-directory default-directory
-			 nil nil)
-	 t))
-  ;; If default-directory is a remote directory,
-  ;; make sure we find its make-directory handler.
-  (setq dir (expand-file-name dir))
-  (let ((mkdir (if-let ((handler (find-file-name-handler dir 'make-directory)))
-		   #'(lambda (dir)
-		       ;; Use 'ignore' since the handler might be designed for
-		       ;; Emacs 28-, so it might return an (undocumented)
-		       ;; non-nil value, whereas the Emacs 29+ convention is
-		       ;; to return nil here.
-		       (ignore (funcall handler 'make-directory dir)))
-                 #'make-directory-internal)))
-    (if (not parents)
-        (funcall mkdir dir)
-      (let ((dir (directory-file-name (expand-file-name dir)))
-            already-dir create-list parent)
-        (while (progn
-                 (setq parent (directory-file-name
-                               (file-name-directory dir)))
-                 (condition-case ()
-                     (ignore (setq already-dir
-                                   (files--ensure-directory mkdir dir)))
-                   (error
-                    ;; Do not loop if root does not exist (Bug#2309).
-                    (not (string= dir parent)))))
-          (setq create-list (cons dir create-list)
-                dir parent))
-        (dolist (dir create-list)
-          (setq already-dir (files--ensure-directory mkdir dir)))
-        already-dir))))
+;; RUNE-BOOTSTRAP - make-directory is defined in fileio.rs
+;; (defun make-directory (dir &optional parents)
+;;   "Create the directory DIR and optionally any nonexistent parent dirs.
+
+;; Interactively, the default choice of directory to create is the
+;; current buffer's default directory.  That is useful when you have
+;; visited a file in a nonexistent directory.
+
+;; Noninteractively, the second (optional) argument PARENTS, if
+;; non-nil, says whether to create parent directories that don't
+;; exist.  Interactively, this happens by default.
+
+;; Return non-nil if PARENTS is non-nil and DIR already exists as a
+;; directory, and nil if DIR did not already exist but was created.
+;; Signal an error if unsuccessful."
+;;   (interactive
+;;    (list (read-file-name "Make directory: " default-directory default-directory
+;; 			 nil nil)
+;; 	 t))
+;;   ;; If default-directory is a remote directory,
+;;   ;; make sure we find its make-directory handler.
+;;   (setq dir (expand-file-name dir))
+;;   (let ((mkdir (if-let ((handler (find-file-name-handler dir 'make-directory)))
+;; 		   #'(lambda (dir)
+;; 		       ;; Use 'ignore' since the handler might be designed for
+;; 		       ;; Emacs 28-, so it might return an (undocumented)
+;; 		       ;; non-nil value, whereas the Emacs 29+ convention is
+;; 		       ;; to return nil here.
+;; 		       (ignore (funcall handler 'make-directory dir)))
+;;                  #'make-directory-internal)))
+;;     (if (not parents)
+;;         (funcall mkdir dir)
+;;       (let ((dir (directory-file-name (expand-file-name dir)))
+;;             already-dir create-list parent)
+;;         (while (progn
+;;                  (setq parent (directory-file-name
+;;                                (file-name-directory dir)))
+;;                  (condition-case ()
+;;                      (ignore (setq already-dir
+;;                                    (files--ensure-directory mkdir dir)))
+;;                    (error
+;;                     ;; Do not loop if root does not exist (Bug#2309).
+;;                     (not (string= dir parent)))))
+;;           (setq create-list (cons dir create-list)
+;;                 dir parent))
+;;         (dolist (dir create-list)
+;;           (setq already-dir (files--ensure-directory mkdir dir)))
+;;         already-dir))))
 
 (defun make-empty-file (filename &optional parents)
   "Create an empty file FILENAME.
@@ -6296,66 +6298,67 @@ non-nil and if FN fails due to a missing file or directory."
       (apply fn args)
     (file-missing (or no-such (signal (car err) (cdr err))))))
 
-(defun delete-directory (directory &optional recursive trash)
-  "Delete the directory named DIRECTORY.  Does not follow symlinks.
-If RECURSIVE is non-nil, delete files in DIRECTORY as well, with
-no error if something else is simultaneously deleting them.
-TRASH non-nil means to trash the directory instead, provided
-`delete-by-moving-to-trash' is non-nil.
-
-When called interactively, TRASH is nil if and only if a prefix
-argument is given, and a further prompt asks the user for
-RECURSIVE if DIRECTORY is nonempty."
-  (interactive
-   (let* ((trashing (and delete-by-moving-to-trash
-			 (null current-prefix-arg)))
-	  (dir (expand-file-name
-		(read-directory-name
-		 (if trashing
-		     "Move directory to trash: "
-		   "Delete directory: ")
-		 default-directory default-directory nil nil))))
-     (list dir
-	   (if (directory-files	dir nil directory-files-no-dot-files-regexp)
-	       (y-or-n-p
-		(format-message "Directory `%s' is not empty, really %s? "
-                                dir (if trashing "trash" "delete")))
-	     nil)
-	   (null current-prefix-arg))))
-  ;; If default-directory is a remote directory, make sure we find its
-  ;; delete-directory handler.
-  (setq directory (directory-file-name (expand-file-name directory)))
-  (let ((handler (find-file-name-handler directory 'delete-directory)))
-    (cond
-     (handler
-      (funcall handler 'delete-directory directory recursive trash))
-     ((and delete-by-moving-to-trash trash)
-      ;; Move non-empty dir to trash only if recursive deletion was
-      ;; requested.  This mimics the non-`delete-by-moving-to-trash'
-      ;; case, where the operation fails in delete-directory-internal.
-      ;; As `move-file-to-trash' trashes directories (empty or
-      ;; otherwise) as a unit, we do not need to recurse here.
-      (if (not (or recursive (directory-empty-p directory)))
-	  (error "Directory is not empty, not moving to trash")
-	(move-file-to-trash directory)))
-     ;; Otherwise, call ourselves recursively if needed.
-     (t
-      (when (or (not recursive) (file-symlink-p directory)
-		(let* ((files
-			(files--force t #'directory-files directory 'full
-				      directory-files-no-dot-files-regexp))
-		       (directory-exists (listp files)))
-		  (when directory-exists
-		    (mapc (lambda (file)
-			    ;; This test is equivalent to but more efficient
-			    ;; than (and (file-directory-p fn)
-			    ;;		 (not (file-symlink-p fn))).
-			    (if (eq t (car (file-attributes file)))
-				(delete-directory file recursive)
-			      (files--force t #'delete-file file)))
-			  files))
-		  directory-exists))
-	(files--force recursive #'delete-directory-internal directory))))))
+;; RUNE-BOOTSTRAP - delete-directory is defined in fileio.rs
+;; (defun delete-directory (directory &optional recursive trash)
+;;   "Delete the directory named DIRECTORY.  Does not follow symlinks.
+;; If RECURSIVE is non-nil, delete files in DIRECTORY as well, with
+;; no error if something else is simultaneously deleting them.
+;; TRASH non-nil means to trash the directory instead, provided
+;; `delete-by-moving-to-trash' is non-nil.
+
+;; When called interactively, TRASH is nil if and only if a prefix
+;; argument is given, and a further prompt asks the user for
+;; RECURSIVE if DIRECTORY is nonempty."
+;;   (interactive
+;;    (let* ((trashing (and delete-by-moving-to-trash
+;; 			 (null current-prefix-arg)))
+;; 	  (dir (expand-file-name
+;; 		(read-directory-name
+;; 		 (if trashing
+;; 		     "Move directory to trash: "
+;; 		   "Delete directory: ")
+;; 		 default-directory default-directory nil nil))))
+;;      (list dir
+;; 	   (if (directory-files	dir nil directory-files-no-dot-files-regexp)
+;; 	       (y-or-n-p
+;; 		(format-message "Directory `%s' is not empty, really %s? "
+;;                                 dir (if trashing "trash" "delete")))
+;; 	     nil)
+;; 	   (null current-prefix-arg))))
+;;   ;; If default-directory is a remote directory, make sure we find its
+;;   ;; delete-directory handler.
+;;   (setq directory (directory-file-name (expand-file-name directory)))
+;;   (let ((handler (find-file-name-handler directory 'delete-directory)))
+;;     (cond
+;;      (handler
+;;       (funcall handler 'delete-directory directory recursive trash))
+;;      ((and delete-by-moving-to-trash trash)
+;;       ;; Move non-empty dir to trash only if recursive deletion was
+;;       ;; requested.  This mimics the non-`delete-by-moving-to-trash'
+;;       ;; case, where the operation fails in delete-directory-internal.
+;;       ;; As `move-file-to-trash' trashes directories (empty or
+;;       ;; otherwise) as a unit, we do not need to recurse here.
+;;       (if (not (or recursive (directory-empty-p directory)))
+;; 	  (error "Directory is not empty, not moving to trash")
+;; 	(move-file-to-trash directory)))
+;;      ;; Otherwise, call ourselves recursively if needed.
+;;      (t
+;;       (when (or (not recursive) (file-symlink-p directory)
+;; 		(let* ((files
+;; 			(files--force t #'directory-files directory 'full
+;; 				      directory-files-no-dot-files-regexp))
+;; 		       (directory-exists (listp files)))
+;; 		  (when directory-exists
+;; 		    (mapc (lambda (file)
+;; 			    ;; This test is equivalent to but more efficient
+;; 			    ;; than (and (file-directory-p fn)
+;; 			    ;;		 (not (file-symlink-p fn))).
+;; 			    (if (eq t (car (file-attributes file)))
+;; 				(delete-directory file recursive)
+;; 			      (files--force t #'delete-file file)))
+;; 			  files))
+;; 		  directory-exists))
+;; 	(files--force recursive #'delete-directory-internal directory))))))
 
 (defun file-equal-p (file1 file2)
   "Return non-nil if files FILE1 and FILE2 name the same file.
@@ -7365,65 +7368,66 @@ by `sh' are supported."
   :type 'string
   :group 'dired)
 
-(defun file-expand-wildcards (pattern &optional full regexp)
-  "Expand (a.k.a. \"glob\") file-name wildcard pattern PATTERN.
-This returns a list of file names that match PATTERN.
-The returned list of file names is sorted in the `string<' order.
-
-PATTERN is, by default, a \"glob\"/wildcard string, e.g.,
-\"/tmp/*.png\" or \"/*/*/foo.png\", but can also be a regular
-expression if the optional REGEXP parameter is non-nil.  In any
-case, the matches are applied per sub-directory, so a match can't
-span a parent/sub directory, which means that a regexp bit can't
-contain the \"/\" character.
-
-The returned list of file names is sorted in the `string<' order.
-
-If PATTERN is written as an absolute file name, the expansions in
-the returned list are also absolute.
-
-If PATTERN is written as a relative file name, it is interpreted
-relative to the current `default-directory'.
-The file names returned are normally also relative to the current
-default directory.  However, if FULL is non-nil, they are absolute."
-  (save-match-data
-    (let* ((nondir (file-name-nondirectory pattern))
-	   (dirpart (file-name-directory pattern))
-	   ;; A list of all dirs that DIRPART specifies.
-	   ;; This can be more than one dir
-	   ;; if DIRPART contains wildcards.
-	   (dirs (if (and dirpart
-			  (string-match "[[*?]" (file-local-name dirpart)))
-		     (mapcar 'file-name-as-directory
-			     (file-expand-wildcards
-                              (directory-file-name dirpart) nil regexp))
-		   (list dirpart)))
-	   contents)
-      (dolist (dir dirs)
-	(when (or (null dir)	; Possible if DIRPART is not wild.
-		  (file-accessible-directory-p dir))
-	  (let ((this-dir-contents
-		 ;; Filter out "." and ".."
-		 (delq nil
-                       (mapcar (lambda (name)
-                                 (unless (string-match "\\`\\.\\.?\\'"
-                                                       (file-name-nondirectory name))
-                                   name))
-			       (directory-files
-                                (or dir ".") full
-                                (if regexp
-                                    ;; We're matching each file name
-                                    ;; element separately.
-                                    (concat "\\`" nondir "\\'")
-				  (wildcard-to-regexp nondir)))))))
-	    (setq contents
-		  (nconc
-		   (if (and dir (not full))
-                       (mapcar (lambda (name) (concat dir name))
-			       this-dir-contents)
-		     this-dir-contents)
-		   contents)))))
-      contents)))
+;; RUNE-BOOTSTRAP - file-expand-wildcards is defined in dired.rs
+;; (defun file-expand-wildcards (pattern &optional full regexp)
+;;   "Expand (a.k.a. \"glob\") file-name wildcard pattern PATTERN.
+;; This returns a list of file names that match PATTERN.
+;; The returned list of file names is sorted in the `string<' order.
+
+;; PATTERN is, by default, a \"glob\"/wildcard string, e.g.,
+;; \"/tmp/*.png\" or \"/*/*/foo.png\", but can also be a regular
+;; expression if the optional REGEXP parameter is non-nil.  In any
+;; case, the matches are applied per sub-directory, so a match can't
+;; span a parent/sub directory, which means that a regexp bit can't
+;; contain the \"/\" character.
+
+;; The returned list of file names is sorted in the `string<' order.
+
+;; If PATTERN is written as an absolute file name, the expansions in
+;; the returned list are also absolute.
+
+;; If PATTERN is written as a relative file name, it is interpreted
+;; relative to the current `default-directory'.
+;; The file names returned are normally also relative to the current
+;; default directory.  However, if FULL is non-nil, they are absolute."
+;;   (save-match-data
+;;     (let* ((nondir (file-name-nondirectory pattern))
+;; 	   (dirpart (file-name-directory pattern))
+;; 	   ;; A list of all dirs that DIRPART specifies.
+;; 	   ;; This can be more than one dir
+;; 	   ;; if DIRPART contains wildcards.
+;; 	   (dirs (if (and dirpart
+;; 			  (string-match "[[*?]" (file-local-name dirpart)))
+;; 		     (mapcar 'file-name-as-directory
+;; 			     (file-expand-wildcards
+;;                               (directory-file-name dirpart) nil regexp))
+;; 		   (list dirpart)))
+;; 	   contents)
+;;       (dolist (dir dirs)
+;; 	(when (or (null dir)	; Possible if DIRPART is not wild.
+;; 		  (file-accessible-directory-p dir))
+;; 	  (let ((this-dir-contents
+;; 		 ;; Filter out "." and ".."
+;; 		 (delq nil
+;;                        (mapcar (lambda (name)
+;;                                  (unless (string-match "\\`\\.\\.?\\'"
+;;                                                        (file-name-nondirectory name))
+;;                                    name))
+;; 			       (directory-files
+;;                                 (or dir ".") full
+;;                                 (if regexp
+;;                                     ;; We're matching each file name
+;;                                     ;; element separately.
+;;                                     (concat "\\`" nondir "\\'")
+;; 				  (wildcard-to-regexp nondir)))))))
+;; 	    (setq contents
+;; 		  (nconc
+;; 		   (if (and dir (not full))
+;;                        (mapcar (lambda (name) (concat dir name))
+;; 			       this-dir-contents)
+;; 		     this-dir-contents)
+;; 		   contents)))))
+;;       contents)))
 
 (defcustom find-sibling-rules nil
   "Rules for finding \"sibling\" files.
@@ -447,25 +447,26 @@ To alter the look of the displayed error messages, you can use
 the `command-error-function' variable."
   (signal 'user-error (list (apply #'format-message format args))))
 
-(defun define-error (name message &optional parent)
-  "Define NAME as a new error signal.
-MESSAGE is a string that will be output to the echo area if such an error
-is signaled without being caught by a `condition-case'.
-PARENT is either a signal or a list of signals from which it inherits.
-Defaults to `error'."
-  (unless parent (setq parent 'error))
-  (let ((conditions
-         (if (consp parent)
-             (apply #'append
-                    (mapcar (lambda (parent)
-                              (cons parent
-                                    (or (get parent 'error-conditions)
-                                        (error "Unknown signal `%s'" parent))))
-                            parent))
-           (cons parent (get parent 'error-conditions)))))
-    (put name 'error-conditions
-         (delete-dups (copy-sequence (cons name conditions))))
-    (when message (put name 'error-message message))))
+;; RUNE-BOOTSTRAP - define-error is defined in eval.rs
+;; (defun define-error (name message &optional parent)
+;;   "Define NAME as a new error signal.
+;; MESSAGE is a string that will be output to the echo area if such an error
+;; is signaled without being caught by a `condition-case'.
+;; PARENT is either a signal or a list of signals from which it inherits.
+;; Defaults to `error'."
+;;   (unless parent (setq parent 'error))
+;;   (let ((conditions
+;;          (if (consp parent)
+;;              (apply #'append
+;;                     (mapcar (lambda (parent)
+;;                               (cons parent
+;;                                     (or (get parent 'error-conditions)
+;;                                         (error "Unknown signal `%s'" parent))))
+;;                             parent))
+;;            (cons parent (get parent 'error-conditions)))))
+;;     (put name 'error-conditions
+;;          (delete-dups (copy-sequence (cons name conditions))))
+;;     (when message (put name 'error-message message))))
 
 ;; We put this here instead of in frame.el so that it's defined even on
 ;; systems where frame.el isn't loaded.
@@ -680,18 +681,19 @@ instead."
   (declare (compiler-macro internal--compiler-macro-cXXr))
   (cdr (cdr (cdr (cdr x)))))
 
-(defun last (list &optional n)
-  "Return the last link of LIST.  Its car is the last element.
-If LIST is nil, return nil.
-If N is non-nil, return the Nth-to-last link of LIST.
-If N is bigger than the length of LIST, return LIST."
-  (declare (side-effect-free t))
-  (if n
-      (and (>= n 0)
-           (let ((m (safe-length list)))
-             (if (< n m) (nthcdr (- m n) list) list)))
-    (and list
-         (nthcdr (1- (safe-length list)) list))))
+;; RUNE-BOOTSTRAP - last is defined in fns.rs
+;; (defun last (list &optional n)
+;;   "Return the last link of LIST.  Its car is the last element.
+;; If LIST is nil, return nil.
+;; If N is non-nil, return the Nth-to-last link of LIST.
+;; If N is bigger than the length of LIST, return LIST."
+;;   (declare (side-effect-free t))
+;;   (if n
+;;       (and (>= n 0)
+;;            (let ((m (safe-length list)))
+;;              (if (< n m) (nthcdr (- m n) list) list)))
+;;     (and list
+;;          (nthcdr (1- (safe-length list)) list))))
 
 (defun butlast (list &optional n)
   "Return a copy of LIST with the last N elements removed.
@@ -4883,6 +4885,27 @@ even if this catches the signal."
                    ,@(cdr handler)))
                handlers)))
 
+(defmacro handler-bind (handlers &rest body)
+  "Setup error HANDLERS around execution of BODY.
+HANDLERS is a list of (CONDITIONS HANDLER) where
+CONDITIONS should be a list of condition names (symbols) or
+a single condition name, and HANDLER is a form whose evaluation
+returns a function.
+When an error is signaled during execution of BODY, if that
+error matches CONDITIONS, then the associated HANDLER
+function is called with the error object as argument.
+HANDLERs can either transfer the control via a non-local exit,
+or return normally.  If a handler returns normally, the search for an
+error handler continues from where it left off."
+  (declare (indent 1) (debug ((&rest (sexp form)) body)))
+  (let ((args '()))
+    (dolist (cond+handler handlers)
+      (let ((handler (car (cdr cond+handler)))
+            (conds (car cond+handler)))
+        (push `',(if (listp conds) conds (list conds)) args)
+        (push handler args)))
+    `(handler-bind-1 (lambda () ,@body) ,@(nreverse args))))
+
 (defmacro with-demoted-errors (format &rest body)
   "Run BODY and demote any errors to simple messages.
 FORMAT is a string passed to `message' to format any error message.
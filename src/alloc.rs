@@ -1,10 +1,13 @@
 //! builtin lisp data structures.
 use crate::core::cons::Cons;
-use crate::core::gc::Context;
+use crate::core::env::{intern, interned_symbols, sym, Env};
+use crate::core::gc::{attach, profiler, Context, Rt, Rto};
 use crate::core::object::{
-    ByteFn, ByteString, FnArgs, Gc, IntoObject, LispVec, Object, RecordBuilder, Symbol, NIL,
+    ByteFn, ByteString, FnArgs, Gc, IntoObject, LispString, LispVec, Object, ObjectType, Record,
+    RecordBuilder, Symbol, NIL,
 };
 use anyhow::{ensure, Result};
+use rune_core::macros::list;
 use rune_macros::defun;
 
 #[defun]
@@ -34,13 +37,17 @@ pub(crate) fn make_closure<'ob>(
     }
 
     unsafe {
-        Ok(ByteFn::make(
+        let mut closure = ByteFn::make(
             prototype.codes(),
             constants.into_obj(cx).untag(),
             prototype.args,
             prototype.depth,
-        )
-        .into_obj(cx))
+        );
+        closure.doc.clone_from(&prototype.doc);
+        if let Some(spec) = prototype.interactive() {
+            closure.set_interactive(spec);
+        }
+        Ok(closure.into_obj(cx))
     }
 }
 
@@ -51,13 +58,20 @@ pub(crate) fn make_byte_code<'ob>(
     byte_code: &'ob ByteString,
     constants: &'ob LispVec,
     depth: usize,
-    _docstring: Option<Object>,
-    _interactive_spec: Option<Object>,
+    docstring: Option<Object>,
+    interactive_spec: Option<Object>,
     _elements: &[Object],
     cx: &'ob Context,
 ) -> Result<&'ob ByteFn> {
+    let byte_code = crate::bytecode::fuse(byte_code, constants.iter().map(|x| x.get()));
     unsafe {
-        let bytefn = ByteFn::make(byte_code, constants, FnArgs::from_arg_spec(arglist)?, depth);
+        let mut bytefn =
+            ByteFn::make(&byte_code, constants, FnArgs::from_arg_spec(arglist)?, depth);
+        // Docstrings can also be a reference into a file, which we don't load
+        bytefn.doc = docstring.and_then(|x| <&str>::try_from(x).ok()).map(Into::into);
+        if let Some(spec) = interactive_spec {
+            bytefn.set_interactive(spec);
+        }
         Ok(bytefn.into_obj(cx).untag())
     }
 }
@@ -80,9 +94,56 @@ fn record<'ob>(type_: Object<'ob>, slots: &[Object<'ob>], cx: &'ob Context) -> R
     RecordBuilder(record)
 }
 
+/// Make a record `#s(KIND ID NAME)` for a runtime object like a thread or a
+/// process, and return it with its ID. The ID is also attached to the record,
+/// where lisp can't change it, so a record that lisp built or modified is never
+/// taken for a runtime object.
+pub(crate) fn make_opaque_record<'ob>(
+    kind: Symbol,
+    name: Object<'ob>,
+    cx: &'ob Context,
+) -> (Object<'ob>, i64) {
+    let mut record = cx.vec_with_capacity(3);
+    record.extend_from_slice(&[kind.into(), NIL, name]);
+    let record = RecordBuilder(record).into_obj(cx);
+    // Only the attached id is used, not the data
+    attach(record.untag(), Box::new(()));
+    let id = i64::from(record.untag().attached().expect("record was just attached").get());
+    record.untag().try_mut().expect("new records are mutable")[1].set(id.into());
+    (record.into(), id)
+}
+
+/// The ID of `record` if it is a runtime object of type `kind`, made by
+/// [`make_opaque_record`].
+pub(crate) fn opaque_record_id(record: &Record, kind: Symbol) -> Option<i64> {
+    if record.len() != 3 || record[0].get() != kind {
+        return None;
+    }
+    let ObjectType::Int(id) = record[1].get().untag() else { return None };
+    let attached = i64::from(record.attached()?.get());
+    (id == attached).then_some(id)
+}
+
+/// Return a copy of OBJECT in pure storage, which the garbage collector never
+/// traces or frees. Strings, conses, vectors, records, floats, and byte-code
+/// functions are copied, anything else is returned unchanged.
 #[defun]
-fn purecopy(obj: Object) -> Object {
-    obj
+fn purecopy<'ob>(obj: Object<'ob>, env: &mut Rt<Env>, cx: &'ob Context) -> Result<Object<'ob>> {
+    match obj.untag() {
+        ObjectType::String(_)
+        | ObjectType::ByteString(_)
+        | ObjectType::Cons(_)
+        | ObjectType::Vec(_)
+        | ObjectType::Record(_)
+        | ObjectType::Float(_)
+        | ObjectType::ByteFn(_) => {
+            let map = interned_symbols();
+            let copy = map.purecopy(obj, cx);
+            env.set_var(sym::PURE_BYTES_USED, cx.add(map.pure_bytes()))?;
+            Ok(copy)
+        }
+        _ => Ok(obj),
+    }
 }
 
 #[defun]
@@ -90,17 +151,98 @@ fn make_symbol<'ob>(name: &str, cx: &'ob Context) -> Symbol<'ob> {
     Symbol::new_uninterned(name, cx)
 }
 
+/// Collect garbage and return information about the heap. Each element is a
+/// list of a name and a value: the number of live objects of each type, the
+/// bytes used by live objects, the bytes free in the current block, the number
+/// of collections done, and the total time spent collecting in seconds. The
+/// last two are also stored in `gcs-done` and `gc-elapsed`.
 #[defun]
-fn garbage_collect(cx: &mut Context) -> bool {
+fn garbage_collect<'ob>(env: &mut Rt<Env>, cx: &'ob mut Context) -> Result<Object<'ob>> {
     cx.garbage_collect(true);
+    let stats = *cx.gc_stats();
+    let counts = stats.counts;
+    let elapsed = stats.total_pause.as_secs_f64();
+    env.set_var(sym::GCS_DONE, cx.add(stats.collections))?;
+    env.set_var(sym::GC_ELAPSED, cx.add(elapsed))?;
+    let cx: &'ob Context = cx;
+    let entry = |name: &str, value: Object<'ob>| list![intern(name, cx), value; cx];
+    Ok(list![
+        entry("conses", cx.add(counts.conses)),
+        entry("floats", cx.add(counts.floats)),
+        entry("strings", cx.add(counts.strings)),
+        entry("vectors", cx.add(counts.vectors)),
+        entry("records", cx.add(counts.records)),
+        entry("hash-tables", cx.add(counts.hash_tables)),
+        entry("symbols", cx.add(counts.symbols)),
+        entry("byte-code-functions", cx.add(counts.byte_fns)),
+        entry("buffers", cx.add(counts.buffers)),
+        entry("bytes-used", cx.add(stats.bytes_used)),
+        entry("bytes-free", cx.add(stats.bytes_free)),
+        entry("gcs-done", cx.add(stats.collections)),
+        entry("gc-elapsed", cx.add(elapsed));
+        cx
+    ])
+}
+
+/// Start recording which functions allocate memory. Anything recorded before
+/// is discarded.
+#[defun]
+fn memory_profiler_start() -> bool {
+    profiler::start();
     true
 }
 
+/// Stop recording allocations. Returns nil if the profiler was not running.
+#[defun]
+fn memory_profiler_stop() -> bool {
+    profiler::stop()
+}
+
+/// Return the allocations recorded by the memory profiler, as a list of
+/// `(FUNCTION COUNT BYTES)` with the largest first. FUNCTION is nil for
+/// allocations made outside of any function.
+#[defun]
+fn memory_profiler_report<'ob>(cx: &'ob Context) -> Object<'ob> {
+    let mut report = NIL;
+    for (name, allocs) in profiler::report().into_iter().rev() {
+        let name = name.map_or(NIL, |name| intern(&name, cx).into());
+        let entry = list![name, allocs.count, allocs.bytes; cx];
+        report = Cons::new(entry, report, cx).into();
+    }
+    report
+}
+
+/// Collect garbage and describe every live object, and what refers to it, as
+/// a graph in the Graphviz DOT format. If FILE is non-nil the graph is written
+/// to it and nil is returned, otherwise the graph is returned as a string.
+#[defun(io)]
+fn memory_report<'ob>(
+    file: Option<&Rto<Gc<&LispString>>>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let file = file.map(|f| f.untag(cx).to_string());
+    let mut dot = Vec::new();
+    cx.dump_heap(&mut dot)?;
+    match file {
+        Some(file) => {
+            std::fs::write(&file, dot)?;
+            Ok(NIL)
+        }
+        None => Ok(cx.add(String::from_utf8(dot)?)),
+    }
+}
+
+defvar!(GC_CONS_THRESHOLD, 800_000);
+defvar!(GC_CONS_PERCENTAGE, 0.1);
+defvar!(GCS_DONE, 0);
+defvar!(GC_ELAPSED, 0.0);
+defvar!(PURE_BYTES_USED, 0);
+
 #[cfg(test)]
 mod test {
     use rune_core::macros::root;
 
-    use crate::core::{env::intern, gc::RootSet, object::ObjectType};
+    use crate::core::gc::RootSet;
 
     use super::*;
 
@@ -123,4 +265,64 @@ mod test {
         assert_eq!(record[1].get(), "slot1");
         assert_eq!(record[2].get(), "slot2");
     }
+
+    #[test]
+    fn test_make_byte_code() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let codes: &ByteString = vec![192_u8, 135].into_obj(cx).untag();
+        let constants: &LispVec = vec![cx.add(5)].into_obj(cx).untag();
+        let func = make_byte_code(0, codes, constants, 1, None, None, &[], cx).unwrap();
+        assert_eq!(func.len(), 4);
+        assert!(crate::data::aref(Object::from(func), 4, cx).is_err());
+
+        let doc = cx.add("Return five.");
+        let func = make_byte_code(257, codes, constants, 2, Some(doc), None, &[], cx).unwrap();
+        let func = Object::from(func);
+        assert_eq!(crate::data::aref(func, 0, cx).unwrap(), 257);
+        assert_eq!(crate::data::aref(func, 3, cx).unwrap(), 2);
+        assert_eq!(crate::data::aref(func, 4, cx).unwrap(), "Return five.");
+        assert_eq!(crate::fns::length(func).unwrap(), 5);
+
+        let spec = cx.add("p");
+        let func = make_byte_code(0, codes, constants, 1, None, Some(spec), &[], cx).unwrap();
+        let func = Object::from(func);
+        assert_eq!(crate::data::aref(func, 4, cx).unwrap(), NIL);
+        assert_eq!(crate::data::aref(func, 5, cx).unwrap(), "p");
+        assert!(crate::data::aref(func, 6, cx).is_err());
+    }
+
+    #[test]
+    fn test_garbage_collect_stats() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        let list = list![1, 2, 3; cx];
+        root!(list, cx);
+        let stats = garbage_collect(env, cx).unwrap();
+        let stats: Vec<_> = stats.as_list().unwrap().map(|x| x.unwrap()).collect();
+        assert_eq!(stats[0].to_string(), "(conses 3)");
+        let gcs = env.vars.get(sym::GCS_DONE).unwrap().bind(cx);
+        assert!(matches!(gcs.untag(), ObjectType::Int(i) if i >= 1));
+        assert_eq!(stats.len(), 13);
+    }
+
+    #[test]
+    fn test_purecopy() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        let list = list!["pure", 1.5; cx];
+        let pure = purecopy(list, env, cx).unwrap();
+        assert_eq!(pure.to_string(), list.to_string());
+        root!(pure, cx);
+        cx.garbage_collect(true);
+        // the pure copy is not part of the local heap
+        assert_eq!(cx.gc_stats().counts.conses, 0);
+        assert_eq!(pure.bind(cx).to_string(), "(\"pure\" 1.5)");
+        let int = cx.add(3);
+        assert_eq!(purecopy(int, env, cx).unwrap(), int);
+    }
 }
@@ -0,0 +1,426 @@
+//! Interface for code that embeds the interpreter.
+//!
+//! The rest of the crate works directly with GC pointers ([`Object`]) that are
+//! only valid while the [`Context`] they came from is borrowed, and with roots
+//! that have to follow a strict stack discipline. None of that can be handed to
+//! code outside of the crate. This module is the boundary: it only deals in
+//! owned [`Value`]s and [`Error`]s, so nothing returned from here can be
+//! invalidated by a garbage collection. Anything meant for embedders should be
+//! added here rather than exposing types from `core`.
+//!
+//! This is the only public module of the library, so changes to the shape of
+//! these items are breaking changes.
+use crate::core::{
+    env::{intern, sym, Env},
+    gc::{attach, with_attached, Context, Rt},
+    object::{HashTable, IntoObject, Object, ObjectType, RecordBuilder, NIL, TRUE},
+};
+use crate::eval::{ErrorType, EvalError};
+use crate::fns::slice_into_list;
+use rune_core::hashmap::HashSet;
+use rune_macros::defun;
+use std::fmt;
+use std::sync::{Arc, Mutex, OnceLock};
+
+mod analysis;
+#[cfg(any(test, feature = "arbitrary"))]
+pub mod arbitrary;
+mod bridge;
+mod foreign;
+mod messages;
+mod native;
+mod plugin;
+mod runtime;
+pub mod serde;
+mod view;
+
+pub use analysis::{Analysis, Diagnostic, Reference, ReferenceKind};
+pub use bridge::{promise, AsyncRuntime, Reply, Resolver};
+pub use foreign::{Foreign, ForeignType};
+pub use messages::{message_sink, set_message_sink, MessageSink};
+pub use native::{FromValue, IntoNativeFn, IntoValue, Rest};
+pub use plugin::{Plugin, Registrar, PLUGIN_API_VERSION};
+pub use runtime::Runtime;
+pub use view::{ConsView, ObjectView, Visitor};
+
+/// An owned view of a lisp value. Converting to a `Value` copies the data out
+/// of the GC heap.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Nil,
+    True,
+    Int(i64),
+    Float(f64),
+    String(String),
+    /// A unibyte string
+    Bytes(Vec<u8>),
+    Symbol(String),
+    List(Vec<Value>),
+    /// A list whose final `cdr` is not nil
+    DottedList(Vec<Value>, Box<Value>),
+    Vector(Vec<Value>),
+    Record(Vec<Value>),
+    HashTable(Vec<(Value, Value)>),
+    /// An object with no readable representation, like a buffer or a builtin
+    /// function. This holds the printed representation.
+    Opaque(String),
+    /// A Rust value, see [`ForeignType`]
+    Foreign(Foreign),
+}
+
+impl Value {
+    /// Copy `obj` into an owned value. Fails if the object is circular.
+    pub(crate) fn from_object(obj: Object) -> Result<Self, Error> {
+        Self::from_object_walk(obj, &mut HashSet::default())
+    }
+
+    fn from_object_walk(obj: Object, path: &mut HashSet<*const u8>) -> Result<Self, Error> {
+        let value = match obj.untag() {
+            ObjectType::NIL => Value::Nil,
+            ObjectType::Symbol(sym::TRUE) => Value::True,
+            ObjectType::Symbol(s) => Value::Symbol(s.name().to_owned()),
+            ObjectType::Int(i) => Value::Int(i),
+            ObjectType::Float(f) => Value::Float(**f),
+            ObjectType::String(s) => Value::String(String::from(&**s)),
+            ObjectType::ByteString(s) => Value::Bytes(s.to_vec()),
+            ObjectType::Cons(cons) => {
+                let mut chain = Vec::new();
+                let mut elements = Vec::new();
+                let mut cons = cons;
+                let tail = loop {
+                    let ptr: *const u8 = (cons as *const _).cast();
+                    if !path.insert(ptr) {
+                        return Err(Error::circular());
+                    }
+                    chain.push(ptr);
+                    elements.push(Self::from_object_walk(cons.car(), path)?);
+                    match cons.cdr().untag() {
+                        ObjectType::Cons(next) => cons = next,
+                        _ => break cons.cdr(),
+                    }
+                };
+                let value = if tail.is_nil() {
+                    Value::List(elements)
+                } else {
+                    let tail = Self::from_object_walk(tail, path)?;
+                    Value::DottedList(elements, Box::new(tail))
+                };
+                for ptr in chain {
+                    path.remove(&ptr);
+                }
+                value
+            }
+            ObjectType::Vec(vec) => {
+                let cells = vec.iter().map(|x| x.get());
+                Value::Vector(Self::from_seq((vec as *const _).cast(), cells, path)?)
+            }
+            ObjectType::Record(record) => {
+                if let Some(foreign) = with_attached(record, Foreign::clone) {
+                    return Ok(Value::Foreign(foreign));
+                }
+                let cells = record.iter().map(|x| x.get());
+                Value::Record(Self::from_seq((record as *const _).cast(), cells, path)?)
+            }
+            ObjectType::HashTable(table) => {
+                let ptr = (table as *const _).cast();
+                if !path.insert(ptr) {
+                    return Err(Error::circular());
+                }
+                let mut entries = Vec::new();
+                for i in 0..table.len() {
+                    let Some((key, value)) = table.get_index(i) else { continue };
+                    let key = Self::from_object_walk(key, path)?;
+                    let value = Self::from_object_walk(value, path)?;
+                    entries.push((key, value));
+                }
+                path.remove(&ptr);
+                Value::HashTable(entries)
+            }
+            ObjectType::ByteFn(_) | ObjectType::SubrFn(_) | ObjectType::Buffer(_) => {
+                Value::Opaque(obj.to_string())
+            }
+        };
+        Ok(value)
+    }
+
+    fn from_seq<'ob>(
+        ptr: *const u8,
+        elements: impl Iterator<Item = Object<'ob>>,
+        path: &mut HashSet<*const u8>,
+    ) -> Result<Vec<Self>, Error> {
+        if !path.insert(ptr) {
+            return Err(Error::circular());
+        }
+        let values = elements.map(|x| Self::from_object_walk(x, path)).collect();
+        path.remove(&ptr);
+        values
+    }
+
+    /// Allocate this value as a lisp object. Opaque values can't be converted
+    /// back because only their printed representation is kept.
+    pub(crate) fn to_object<'ob>(&self, cx: &'ob Context) -> Result<Object<'ob>, Error> {
+        let obj = match self {
+            Value::Nil => NIL,
+            Value::True => TRUE,
+            Value::Int(i) => (*i).into(),
+            Value::Float(f) => cx.add(*f),
+            Value::String(s) => cx.add(s.as_str()),
+            Value::Bytes(b) => cx.add(b.clone()),
+            Value::Symbol(name) => intern(name, cx).into(),
+            Value::List(elements) => slice_into_list(&Self::to_objects(elements, cx)?, None, cx),
+            Value::DottedList(elements, tail) => {
+                let tail = tail.to_object(cx)?;
+                slice_into_list(&Self::to_objects(elements, cx)?, Some(tail), cx)
+            }
+            Value::Vector(elements) => cx.add(Self::to_objects(elements, cx)?),
+            Value::Record(elements) => {
+                let mut record = cx.vec_with_capacity(elements.len());
+                record.extend_from_slice(&Self::to_objects(elements, cx)?);
+                cx.add(RecordBuilder(record))
+            }
+            Value::HashTable(entries) => {
+                let mut table = HashTable::default();
+                for (key, value) in entries {
+                    table.insert(key.to_object(cx)?, value.to_object(cx)?);
+                }
+                table.into_obj(cx).into()
+            }
+            Value::Opaque(repr) => {
+                return Err(Error::Other(format!("Can't convert opaque value {repr} to an object")))
+            }
+            Value::Foreign(foreign) => {
+                let mut record = cx.vec_with_capacity(1);
+                record.push(intern(foreign.type_name(), cx).into());
+                let record = RecordBuilder(record).into_obj(cx);
+                attach(record.untag(), Box::new(foreign.clone()));
+                record.into()
+            }
+        };
+        Ok(obj)
+    }
+
+    fn to_objects<'ob>(values: &[Self], cx: &'ob Context) -> Result<Vec<Object<'ob>>, Error> {
+        values.iter().map(|x| x.to_object(cx)).collect()
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn seq(f: &mut fmt::Formatter, open: &str, values: &[Value], close: &str) -> fmt::Result {
+            f.write_str(open)?;
+            for (i, value) in values.iter().enumerate() {
+                if i != 0 {
+                    f.write_str(" ")?;
+                }
+                write!(f, "{value}")?;
+            }
+            f.write_str(close)
+        }
+        match self {
+            Value::Nil => f.write_str("nil"),
+            Value::True => f.write_str("t"),
+            Value::Int(i) => write!(f, "{i}"),
+            Value::Float(x) if x.fract() == 0.0 => write!(f, "{x:.1}"),
+            Value::Float(x) => write!(f, "{x}"),
+            Value::String(s) => write!(f, "{s:?}"),
+            Value::Bytes(b) => write!(f, "{:?}", String::from_utf8_lossy(b)),
+            Value::Symbol(name) | Value::Opaque(name) => f.write_str(name),
+            Value::Foreign(foreign) => write!(f, "#<{}>", foreign.type_name()),
+            Value::List(values) => seq(f, "(", values, ")"),
+            Value::DottedList(values, tail) => {
+                seq(f, "(", values, "")?;
+                write!(f, " . {tail})")
+            }
+            Value::Vector(values) => seq(f, "[", values, "]"),
+            Value::Record(values) => seq(f, "#s(", values, ")"),
+            Value::HashTable(entries) => {
+                f.write_str("#s(hash-table (")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(" ")?;
+                    }
+                    write!(f, "{key} {value}")?;
+                }
+                f.write_str("))")
+            }
+        }
+    }
+}
+
+/// An error raised while running lisp code.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// An error raised with `signal`. `symbol` is the error symbol.
+    Signal { symbol: String, data: Value },
+    /// A `throw` that had no matching `catch`.
+    Throw { tag: Value, value: Value },
+    /// The source text could not be read.
+    Read(String),
+    /// Any other error from the runtime.
+    Other(String),
+}
+
+impl Error {
+    fn circular() -> Self {
+        Error::Other("Can't convert a circular object".to_owned())
+    }
+
+    /// Convert an error returned from the runtime. Signals and throws store
+    /// their data in the environment, so it is needed to recover them.
+    pub(crate) fn from_runtime(error: anyhow::Error, env: &Rt<Env>, cx: &Context) -> Self {
+        let error = match error.downcast::<EvalError>() {
+            Ok(error) => error,
+            Err(error) => EvalError::new_error(error),
+        };
+        let value = |obj: Object| {
+            Value::from_object(obj).unwrap_or_else(|e| Value::Opaque(e.to_string()))
+        };
+        match error.error {
+            ErrorType::Signal(id) => match env.get_exception(id) {
+                Some((symbol, data)) => Error::Signal {
+                    symbol: symbol.bind(cx).to_string(),
+                    data: value(data.bind(cx)),
+                },
+                None => Error::Other("Signal data was lost".to_owned()),
+            },
+            ErrorType::Throw(id) => match env.get_exception(id) {
+                Some((tag, data)) => {
+                    Error::Throw { tag: value(tag.bind(cx)), value: value(data.bind(cx)) }
+                }
+                None => Error::Other("Throw data was lost".to_owned()),
+            },
+            ErrorType::Err(error) => {
+                let condition = crate::core::error::condition(&error, cx);
+                let (symbol, data) = if let Some(condition) = condition {
+                    condition
+                } else if error.is::<crate::reader::Error>() {
+                    return Error::Read(error.to_string());
+                } else {
+                    return Error::Other(error.to_string());
+                };
+                Error::Signal { symbol: symbol.to_string(), data: value(data) }
+            }
+        }
+    }
+
+    /// Convert back to a runtime error, so that an error from a native
+    /// function can be caught by `condition-case` or `catch`.
+    pub(crate) fn into_runtime(self, env: &mut Rt<Env>, cx: &Context) -> anyhow::Error {
+        match self {
+            Error::Signal { symbol, data } => {
+                let data = data.to_object(cx).unwrap_or(NIL);
+                EvalError::signal(intern(&symbol, cx).into(), data, env).into()
+            }
+            Error::Throw { tag, value } => match (tag.to_object(cx), value.to_object(cx)) {
+                (Ok(tag), Ok(value)) => EvalError::throw(tag, value, env).into(),
+                (Err(e), _) | (_, Err(e)) => e.into(),
+            },
+            Error::Read(msg) | Error::Other(msg) => anyhow::anyhow!(msg),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Signal { symbol, data } => write!(f, "{symbol}: {data}"),
+            Error::Throw { tag, value } => write!(f, "No catch for {tag}: {value}"),
+            Error::Read(msg) => write!(f, "Read error: {msg}"),
+            Error::Other(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A function defined by the embedder.
+pub type NativeFn = Arc<dyn Fn(&[Value]) -> Result<Value, Error> + Send + Sync>;
+
+static NATIVE_FUNCTIONS: OnceLock<Mutex<Vec<NativeFn>>> = OnceLock::new();
+
+fn native_functions() -> &'static Mutex<Vec<NativeFn>> {
+    NATIVE_FUNCTIONS.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+/// Store `func` and return the id that `internal--call-native` uses to call it.
+/// Native functions live for the rest of the program.
+fn register_native(func: NativeFn) -> usize {
+    let mut functions = native_functions().lock().unwrap();
+    functions.push(func);
+    functions.len() - 1
+}
+
+/// Call the native function with `id`. Native functions are wrapped in a
+/// closure that passes their arguments here, because a [`SubrFn`] can only
+/// hold a plain function pointer.
+///
+/// [`SubrFn`]: crate::core::object::SubrFn
+#[defun]
+fn internal__call_native<'ob>(
+    id: usize,
+    args: Object,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> anyhow::Result<Object<'ob>> {
+    let Some(func) = native_functions().lock().unwrap().get(id).cloned() else {
+        anyhow::bail!("No native function with id {id}");
+    };
+    let mut values = Vec::new();
+    for arg in args.as_list()? {
+        values.push(Value::from_object(arg?)?);
+    }
+    match func(&values) {
+        Ok(value) => Ok(value.to_object(cx)?),
+        Err(e) => Err(e.into_runtime(env, cx)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use rune_core::macros::list;
+
+    #[test]
+    fn test_value_round_trip() {
+        sym::init_symbols();
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let values = [
+            Value::Nil,
+            Value::True,
+            Value::Int(7),
+            Value::Float(1.5),
+            Value::String("foo".to_owned()),
+            Value::Symbol("bar".to_owned()),
+            Value::List(vec![Value::Int(1), Value::Nil]),
+            Value::DottedList(vec![Value::Int(1)], Box::new(Value::Int(2))),
+            Value::Vector(vec![Value::String("baz".to_owned())]),
+        ];
+        for value in values {
+            let obj = value.to_object(cx).unwrap();
+            assert_eq!(Value::from_object(obj).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_value_from_object() {
+        sym::init_symbols();
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let list = list![1, "foo", list![2.5; cx]; cx];
+        let value = Value::from_object(list).unwrap();
+        assert_eq!(value.to_string(), "(1 \"foo\" (2.5))");
+        let subr: Object = sym::CAR.func(cx).unwrap().into();
+        assert_eq!(Value::from_object(subr).unwrap(), Value::Opaque("#<subr car>".to_owned()));
+        assert!(Value::Opaque("#<subr car>".to_owned()).to_object(cx).is_err());
+
+        // shared structure is fine, but cycles are not
+        let shared = list![1; cx];
+        assert!(Value::from_object(list![shared, shared; cx]).is_ok());
+        let circular = list![1, 2; cx];
+        let ObjectType::Cons(cons) = circular.untag() else { unreachable!() };
+        cons.set_cdr(circular).unwrap();
+        assert_eq!(Value::from_object(circular), Err(Error::circular()));
+    }
+}
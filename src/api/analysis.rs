@@ -0,0 +1,628 @@
+//! Static analysis of lisp source for editor tooling.
+//!
+//! [`analyze`] reads every form in some source and walks it without
+//! evaluating anything, the way a language server would. It reports where
+//! symbols are defined and used, variables that are not bound, and calls with
+//! the wrong number of arguments. Macro calls are expanded with the macros
+//! defined in the runtime, so the analysis is only as complete as what the
+//! runtime has loaded.
+//!
+//! Positions are byte offsets into the source. The reader only records where
+//! lists that start with a symbol begin, so everything else is reported at the
+//! innermost such list around it. Code from a macro expansion is reported at
+//! the macro call, unless it is a list copied unchanged from the arguments.
+use super::{Error, Value};
+use crate::core::{
+    cons::Cons,
+    env::{intern, sym, Env},
+    gc::{Context, Rt},
+    object::{FunctionType, Object, ObjectType},
+};
+use crate::{eval, interpreter, reader};
+use rune_core::hashmap::{HashMap, HashSet};
+use rune_core::macros::root;
+
+/// How deeply macro expansions can nest before giving up on a form.
+const MAX_EXPANSION_DEPTH: usize = 200;
+
+/// What [`analyze`] found in the source.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Analysis {
+    /// Every definition and use of a symbol, in the order they were walked
+    pub references: Vec<Reference>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// A definition or use of the symbol `name`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub name: String,
+    pub kind: ReferenceKind,
+    pub pos: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferenceKind {
+    /// A `defun`, `defmacro` or `defsubst`
+    FunctionDefinition,
+    /// A `defvar` or `defconst`
+    VariableDefinition,
+    /// A call, or a function quoted with `function`
+    Function,
+    /// A variable that is read or set
+    Variable,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Diagnostic {
+    /// A variable that is not bound locally, and is not defined in the source
+    /// or the runtime.
+    UnboundVariable { name: String, pos: usize },
+    /// A call with a number of arguments the function doesn't accept. `max`
+    /// is `None` when the function takes `&rest` arguments.
+    WrongArity { name: String, pos: usize, given: usize, min: usize, max: Option<usize> },
+    /// Expanding a macro call signaled an error.
+    MacroError { name: String, pos: usize, error: Error },
+}
+
+/// A form copied out of the heap, with the position of the lists the reader
+/// recorded. Macro expansion can collect garbage, so the walk can't hold on to
+/// objects.
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Symbol(String),
+    List(Vec<Node>, Option<usize>),
+    /// Any other value. These are never walked into.
+    Other(Value),
+}
+
+impl Node {
+    fn from_object(obj: Object, positions: &HashMap<*const Cons, usize>) -> Self {
+        let other = || {
+            let value = Value::from_object(obj).unwrap_or_else(|_| Value::Opaque(obj.to_string()));
+            Node::Other(value)
+        };
+        match obj.untag() {
+            ObjectType::NIL | ObjectType::Symbol(sym::TRUE) => other(),
+            ObjectType::Symbol(s) => Node::Symbol(s.name().to_owned()),
+            ObjectType::Cons(cons) => {
+                let mut elements = Vec::new();
+                for elem in cons.elements() {
+                    // dotted and circular lists are data
+                    let Ok(elem) = elem else { return other() };
+                    elements.push(Self::from_object(elem, positions));
+                }
+                Node::List(elements, positions.get(&(cons as *const Cons)).copied())
+            }
+            _ => other(),
+        }
+    }
+
+    fn from_value(value: Value) -> Self {
+        match value {
+            Value::Symbol(name) => Node::Symbol(name),
+            Value::List(elements) => {
+                Node::List(elements.into_iter().map(Self::from_value).collect(), None)
+            }
+            value => Node::Other(value),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        match self {
+            Node::Symbol(name) => Value::Symbol(name.clone()),
+            Node::List(elements, _) => Value::List(elements.iter().map(Self::to_value).collect()),
+            Node::Other(value) => value.clone(),
+        }
+    }
+
+    /// Add every list with a position in this node to `lists`.
+    fn positioned<'a>(&'a self, lists: &mut Vec<(usize, &'a Node)>) {
+        if let Node::List(elements, pos) = self {
+            if let Some(pos) = pos {
+                lists.push((*pos, self));
+            }
+            for elem in elements {
+                elem.positioned(lists);
+            }
+        }
+    }
+
+    /// Replace the lists in this node that are equal to one of `originals`
+    /// with it, so they get back their positions.
+    fn adopt_positions(&mut self, originals: &[(Value, &Node)]) {
+        let Node::List(..) = self else { return };
+        let value = self.to_value();
+        if let Some((_, original)) = originals.iter().find(|(x, _)| *x == value) {
+            *self = (*original).clone();
+        } else if let Node::List(elements, _) = self {
+            for elem in elements {
+                elem.adopt_positions(originals);
+            }
+        }
+    }
+}
+
+/// The elements of `node` if it is a list. `nil` is the empty list.
+fn list_elements(node: &Node) -> Option<&[Node]> {
+    match node {
+        Node::List(elements, _) => Some(elements),
+        Node::Other(Value::Nil) => Some(&[]),
+        _ => None,
+    }
+}
+
+/// The variables bound by the lambda list `args`.
+fn arg_names(args: &Node) -> impl Iterator<Item = &str> {
+    let args = list_elements(args).unwrap_or_default().iter();
+    args.filter_map(|arg| match arg {
+        Node::Symbol(name) if !name.starts_with('&') => Some(name.as_str()),
+        _ => None,
+    })
+}
+
+/// The minimum and maximum number of arguments of the lambda list `args`.
+fn arity(args: &Node) -> Option<(usize, Option<usize>)> {
+    let (mut min, mut optional) = (0, None);
+    for arg in list_elements(args)? {
+        match arg {
+            Node::Symbol(name) if name == "&rest" => return Some((min, None)),
+            Node::Symbol(name) if name == "&optional" => optional = Some(0),
+            Node::Symbol(_) => match &mut optional {
+                Some(count) => *count += 1,
+                None => min += 1,
+            },
+            _ => return None,
+        }
+    }
+    Some((min, Some(min + optional.unwrap_or(0))))
+}
+
+/// The arity of the function `name` in the runtime, if it is defined.
+fn runtime_arity(name: &str, cx: &Context) -> Option<(usize, Option<usize>)> {
+    let func = intern(name, cx).follow_indirect(cx)?;
+    let arity = eval::func_arity(func, cx).ok()?;
+    let ObjectType::Int(min) = arity.car().untag() else { return None };
+    let max = match arity.cdr().untag() {
+        ObjectType::Int(max) => Some(max as usize),
+        _ => None,
+    };
+    Some((min as usize, max))
+}
+
+fn is_macro(name: &str, cx: &Context) -> bool {
+    match intern(name, cx).follow_indirect(cx).map(|x| x.untag()) {
+        Some(FunctionType::Cons(cons)) => cons.car() == sym::MACRO,
+        _ => false,
+    }
+}
+
+/// Expand `node` with `macroexpand-1`, or with `macroexpand` if not `once`.
+fn expand(node: &Node, once: bool, env: &mut Rt<Env>, cx: &mut Context) -> Result<Value, Error> {
+    let form = node.to_value().to_object(cx)?;
+    root!(form, cx);
+    let expanded = if once {
+        eval::macroexpand_1(form, None, cx, env)
+    } else {
+        eval::macroexpand(form, None, cx, env)
+    };
+    match expanded {
+        Ok(expanded) => Value::from_object(expanded),
+        Err(e) => Err(Error::from_runtime(e, env, cx)),
+    }
+}
+
+/// Read every form in `source`, along with the offset where reading it began.
+fn read_forms(source: &str, cx: &Context) -> Result<Vec<(Node, usize)>, Error> {
+    let mut forms = Vec::new();
+    let mut pos = 0;
+    loop {
+        let (obj, end, positions) = match reader::read_with_positions(&source[pos..], cx) {
+            Ok(read) => read,
+            Err(reader::Error::EmptyStream) => return Ok(forms),
+            Err(mut e) => {
+                e.update_pos(pos);
+                return Err(Error::Read(e.to_string()));
+            }
+        };
+        let positions: HashMap<*const Cons, usize> = positions
+            .into_iter()
+            .map(|(cons, offset)| (cons as *const Cons, pos + offset))
+            .collect();
+        forms.push((Node::from_object(obj, &positions), pos));
+        pos += end;
+    }
+}
+
+#[derive(Default)]
+struct Analyzer {
+    analysis: Analysis,
+    /// The variables bound around the form being walked
+    scope: Vec<String>,
+    /// Variables defined with `defvar` or `defconst` in the source
+    variables: HashSet<String>,
+    /// Arities of functions defined in the source
+    arities: HashMap<String, (usize, Option<usize>)>,
+    expansion_depth: usize,
+}
+
+impl Analyzer {
+    /// Find the definitions in `node`, so they can be used before they appear.
+    fn collect_definitions(&mut self, node: &Node) {
+        let Node::List(elements, _) = node else { return };
+        match elements.as_slice() {
+            [Node::Symbol(head), Node::Symbol(name), args, ..]
+                if matches!(head.as_str(), "defun" | "defmacro" | "defsubst") =>
+            {
+                if let Some(arity) = arity(args) {
+                    self.arities.insert(name.clone(), arity);
+                }
+            }
+            [Node::Symbol(head), Node::Symbol(name), ..]
+                if matches!(head.as_str(), "defvar" | "defconst") =>
+            {
+                self.variables.insert(name.clone());
+            }
+            _ => {}
+        }
+        for elem in elements {
+            self.collect_definitions(elem);
+        }
+    }
+
+    fn reference(&mut self, name: &str, kind: ReferenceKind, pos: usize) {
+        self.analysis.references.push(Reference { name: name.to_owned(), kind, pos });
+    }
+
+    fn variable(&mut self, name: &str, pos: usize, env: &Rt<Env>, cx: &Context) {
+        self.reference(name, ReferenceKind::Variable, pos);
+        let bound = name.starts_with(':')
+            || self.scope.iter().any(|x| x == name)
+            || self.variables.contains(name)
+            || {
+                let symbol = intern(name, cx);
+                symbol.is_special() || env.vars.get(symbol).is_some()
+            };
+        if !bound {
+            let name = name.to_owned();
+            self.analysis.diagnostics.push(Diagnostic::UnboundVariable { name, pos });
+        }
+    }
+
+    fn call(&mut self, name: &str, given: usize, pos: usize, cx: &Context) {
+        self.reference(name, ReferenceKind::Function, pos);
+        // Definitions in the source replace the ones in the runtime
+        let arity = self.arities.get(name).copied().or_else(|| runtime_arity(name, cx));
+        let Some((min, max)) = arity else { return };
+        if given < min || max.is_some_and(|max| given > max) {
+            let name = name.to_owned();
+            let diagnostic = Diagnostic::WrongArity { name, pos, given, min, max };
+            self.analysis.diagnostics.push(diagnostic);
+        }
+    }
+
+    fn walk_all(&mut self, nodes: &[Node], pos: usize, env: &mut Rt<Env>, cx: &mut Context) {
+        for node in nodes {
+            self.walk(node, pos, env, cx);
+        }
+    }
+
+    /// Walk `body` with `vars` bound.
+    fn walk_bound<'a>(
+        &mut self,
+        vars: impl IntoIterator<Item = &'a str>,
+        body: &[Node],
+        pos: usize,
+        env: &mut Rt<Env>,
+        cx: &mut Context,
+    ) {
+        let len = self.scope.len();
+        self.scope.extend(vars.into_iter().map(str::to_owned));
+        self.walk_all(body, pos, env, cx);
+        self.scope.truncate(len);
+    }
+
+    /// Walk the rest of a `lambda` form after the symbol.
+    fn walk_lambda(&mut self, args: &[Node], pos: usize, env: &mut Rt<Env>, cx: &mut Context) {
+        let Some((arglist, body)) = args.split_first() else { return };
+        self.walk_bound(arg_names(arglist), body, pos, env, cx);
+    }
+
+    /// Walk the argument of `function`.
+    fn walk_function(&mut self, node: &Node, pos: usize, env: &mut Rt<Env>, cx: &mut Context) {
+        match node {
+            Node::Symbol(name) => self.reference(name, ReferenceKind::Function, pos),
+            Node::List(elements, own) => {
+                if let Some((Node::Symbol(head), args)) = elements.split_first() {
+                    if head == "lambda" {
+                        self.walk_lambda(args, own.unwrap_or(pos), env, cx);
+                    }
+                }
+            }
+            Node::Other(_) => {}
+        }
+    }
+
+    fn walk_let(
+        &mut self,
+        star: bool,
+        args: &[Node],
+        pos: usize,
+        env: &mut Rt<Env>,
+        cx: &mut Context,
+    ) {
+        let Some((bindings, body)) = args.split_first() else { return };
+        let len = self.scope.len();
+        let mut bound = Vec::new();
+        for binding in list_elements(bindings).unwrap_or_default() {
+            let var = match binding {
+                Node::Symbol(var) => var,
+                Node::List(elements, own) => match elements.split_first() {
+                    Some((Node::Symbol(var), init)) => {
+                        self.walk_all(init, own.unwrap_or(pos), env, cx);
+                        var
+                    }
+                    _ => continue,
+                },
+                Node::Other(_) => continue,
+            };
+            // `let*` binds each variable before the next one is initialized
+            if star {
+                self.scope.push(var.clone());
+            } else {
+                bound.push(var.clone());
+            }
+        }
+        self.scope.extend(bound);
+        self.walk_all(body, pos, env, cx);
+        self.scope.truncate(len);
+    }
+
+    fn walk_macro(
+        &mut self,
+        node: &Node,
+        (name, args): (&str, &[Node]),
+        pos: usize,
+        env: &mut Rt<Env>,
+        cx: &mut Context,
+    ) {
+        self.reference(name, ReferenceKind::Function, pos);
+        let expansion = if self.expansion_depth < MAX_EXPANSION_DEPTH {
+            expand(node, true, env, cx)
+        } else {
+            Err(Error::Other("Macro expansion is nested too deeply".to_owned()))
+        };
+        let mut expansion = match expansion {
+            Ok(expansion) => Node::from_value(expansion),
+            Err(error) => {
+                let name = name.to_owned();
+                self.analysis.diagnostics.push(Diagnostic::MacroError { name, pos, error });
+                return;
+            }
+        };
+        let mut lists = Vec::new();
+        for arg in args {
+            arg.positioned(&mut lists);
+        }
+        let originals: Vec<_> = lists.into_iter().map(|(_, x)| (x.to_value(), x)).collect();
+        expansion.adopt_positions(&originals);
+        self.expansion_depth += 1;
+        self.walk(&expansion, pos, env, cx);
+        self.expansion_depth -= 1;
+    }
+
+    fn walk(&mut self, node: &Node, pos: usize, env: &mut Rt<Env>, cx: &mut Context) {
+        let (elements, pos) = match node {
+            Node::Symbol(name) => return self.variable(name, pos, env, cx),
+            Node::List(elements, own) => (elements.as_slice(), own.unwrap_or(pos)),
+            Node::Other(_) => return,
+        };
+        let Some((head, args)) = elements.split_first() else { return };
+        let name = match head {
+            Node::Symbol(name) => name.as_str(),
+            // ((lambda (x) ...) arg)
+            _ => {
+                self.walk_function(head, pos, env, cx);
+                return self.walk_all(args, pos, env, cx);
+            }
+        };
+        match name {
+            "quote" | "declare" | "interactive" => {}
+            "function" => {
+                if let Some(func) = args.first() {
+                    self.walk_function(func, pos, env, cx);
+                }
+            }
+            "lambda" => self.walk_lambda(args, pos, env, cx),
+            "let" | "let*" => self.walk_let(name == "let*", args, pos, env, cx),
+            "defvar" | "defconst" => {
+                if let Some(Node::Symbol(var)) = args.first() {
+                    self.reference(var, ReferenceKind::VariableDefinition, pos);
+                }
+                if let Some(value) = args.get(1) {
+                    self.walk(value, pos, env, cx);
+                }
+            }
+            "defun" | "defmacro" | "defsubst" => {
+                if let Some((Node::Symbol(func), lambda)) = args.split_first() {
+                    self.reference(func, ReferenceKind::FunctionDefinition, pos);
+                    self.walk_lambda(lambda, pos, env, cx);
+                }
+            }
+            "cond" => {
+                for clause in args {
+                    if let Node::List(forms, own) = clause {
+                        self.walk_all(forms, own.unwrap_or(pos), env, cx);
+                    }
+                }
+            }
+            "condition-case" => {
+                let var = match args.first() {
+                    Some(Node::Symbol(var)) => Some(var.as_str()),
+                    _ => None,
+                };
+                if let Some(body) = args.get(1) {
+                    self.walk(body, pos, env, cx);
+                }
+                for handler in args.iter().skip(2) {
+                    // (CONDITIONS BODY...)
+                    if let Node::List(handler, own) = handler {
+                        let body = handler.get(1..).unwrap_or_default();
+                        self.walk_bound(var, body, own.unwrap_or(pos), env, cx);
+                    }
+                }
+            }
+            "cl-destructuring-bind" => {
+                let Some((vars, rest)) = args.split_first() else { return };
+                let Some((value, body)) = rest.split_first() else { return };
+                self.walk(value, pos, env, cx);
+                let mut lists = vec![vars];
+                let mut names = Vec::new();
+                while let Some(list) = lists.pop() {
+                    match list {
+                        Node::Symbol(name) if !name.starts_with('&') => names.push(name.as_str()),
+                        Node::List(elements, _) => lists.extend(elements),
+                        _ => {}
+                    }
+                }
+                self.walk_bound(names, body, pos, env, cx);
+            }
+            _ if interpreter::special_form_p(intern(name, cx).into()) => {
+                self.walk_all(args, pos, env, cx);
+            }
+            _ if is_macro(name, cx) => self.walk_macro(node, (name, args), pos, env, cx),
+            _ => {
+                self.call(name, args.len(), pos, cx);
+                self.walk_all(args, pos, env, cx);
+            }
+        }
+    }
+}
+
+/// Analyze every form in `source` without evaluating it.
+pub(crate) fn analyze(
+    source: &str,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<Analysis, Error> {
+    let forms = read_forms(source, cx)?;
+    let mut analyzer = Analyzer::default();
+    for (form, _) in &forms {
+        analyzer.collect_definitions(form);
+    }
+    for (form, start) in &forms {
+        analyzer.walk(form, *start, env, cx);
+    }
+    Ok(analyzer.analysis)
+}
+
+/// Fully expand the innermost list around `point` that starts with a symbol.
+/// `point` is a byte offset in `source`. Returns `None` if there is no list
+/// there.
+pub(crate) fn expand_at(
+    source: &str,
+    point: usize,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<Option<Value>, Error> {
+    let forms = read_forms(source, cx)?;
+    let mut lists = Vec::new();
+    for (form, _) in &forms {
+        form.positioned(&mut lists);
+    }
+    // Lists nest, so the innermost one around point is the one starting last
+    lists.sort_by_key(|(start, _)| std::cmp::Reverse(*start));
+    for (start, list) in lists {
+        if start > point {
+            continue;
+        }
+        let (_, len) =
+            reader::read(&source[start..], cx).map_err(|e| Error::Read(e.to_string()))?;
+        if point < start + len {
+            return expand(list, false, env, cx).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::Runtime;
+    use super::*;
+
+    #[test]
+    fn test_analyze() {
+        let source = "(defvar analysis-test-var 1)
+(defun analysis-test-fn (a &optional b)
+  (let ((c a))
+    (+ a b c analysis-test-var analysis-test-unbound)))
+(analysis-test-fn 1 2 3)
+(car 1 2)
+(condition-case err (car nil) (error err))";
+        Runtime::with(|rt| {
+            let analysis = rt.analyze(source).unwrap();
+            let pos = |text| source.find(text).unwrap();
+            let unbound = "analysis-test-unbound".to_owned();
+            let diagnostics = [
+                Diagnostic::UnboundVariable { name: unbound, pos: pos("(+ a") },
+                Diagnostic::WrongArity {
+                    name: "analysis-test-fn".to_owned(),
+                    pos: pos("(analysis-test-fn 1"),
+                    given: 3,
+                    min: 1,
+                    max: Some(2),
+                },
+                Diagnostic::WrongArity {
+                    name: "car".to_owned(),
+                    pos: pos("(car 1"),
+                    given: 2,
+                    min: 1,
+                    max: Some(1),
+                },
+            ];
+            assert_eq!(analysis.diagnostics, diagnostics);
+            let definition = Reference {
+                name: "analysis-test-fn".to_owned(),
+                kind: ReferenceKind::FunctionDefinition,
+                pos: pos("(defun"),
+            };
+            assert!(analysis.references.contains(&definition));
+            let variable = Reference {
+                name: "analysis-test-var".to_owned(),
+                kind: ReferenceKind::Variable,
+                pos: pos("(+ a"),
+            };
+            assert!(analysis.references.contains(&variable));
+            assert!(matches!(rt.analyze("(1 2"), Err(Error::Read(_))));
+        });
+    }
+
+    #[test]
+    fn test_analyze_macro() {
+        let source = "(analysis-test-macro (cdr 1 2))";
+        Runtime::with(|rt| {
+            let def = "(fset 'analysis-test-macro (cons 'macro #'(lambda (x) (list 'car x))))";
+            rt.eval(def).unwrap();
+            let analysis = rt.analyze(source).unwrap();
+            // the argument keeps its position in the expansion
+            let diagnostic = Diagnostic::WrongArity {
+                name: "cdr".to_owned(),
+                pos: source.find("(cdr").unwrap(),
+                given: 2,
+                min: 1,
+                max: Some(1),
+            };
+            assert_eq!(analysis.diagnostics, [diagnostic]);
+
+            let cdr = Value::List(vec![
+                Value::Symbol("cdr".to_owned()),
+                Value::Int(1),
+                Value::Int(2),
+            ]);
+            let expansion = Value::List(vec![Value::Symbol("car".to_owned()), cdr.clone()]);
+            assert_eq!(rt.expand_at(source, 3), Ok(Some(expansion)));
+            assert_eq!(rt.expand_at(source, 23), Ok(Some(cdr)));
+            assert_eq!(rt.expand_at(" (car nil)", 0), Ok(None));
+        });
+    }
+}
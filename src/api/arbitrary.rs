@@ -0,0 +1,97 @@
+//! Random lisp data for property tests.
+//!
+//! [`arbitrary_value`] is a [proptest] strategy for owned [`Value`]s, and
+//! [`arbitrary_object`] allocates them in a [`Context`]. Shrinking works on the
+//! owned values, so a failing case is reduced before it is turned into an
+//! object. Only readable data is generated: the printed form of every value
+//! reads back as an `equal` object. Outside of this crate's tests this needs
+//! the `arbitrary` feature, and only [`arbitrary_value`] is available.
+use super::Value;
+use crate::core::object::{MAX_FIXNUM, MIN_FIXNUM};
+#[cfg(test)]
+use crate::core::{gc::Context, object::Object};
+use proptest::collection::vec;
+use proptest::num::f64 as float;
+use proptest::prelude::*;
+
+/// How many levels of lists and vectors can be nested.
+pub const MAX_DEPTH: u32 = 4;
+
+/// Generate a value with lists, vectors and atoms nested up to [`MAX_DEPTH`]
+/// levels deep.
+pub fn arbitrary_value() -> impl Strategy<Value = Value> {
+    // NaN reads back, but it isn't `equal` to itself
+    let not_nan = float::POSITIVE
+        | float::NEGATIVE
+        | float::NORMAL
+        | float::SUBNORMAL
+        | float::ZERO
+        | float::INFINITE;
+    let leaf = prop_oneof![
+        Just(Value::Nil),
+        Just(Value::True),
+        (MIN_FIXNUM..=MAX_FIXNUM).prop_map(Value::Int),
+        not_nan.prop_map(Value::Float),
+        "\\PC{0,10}".prop_map(Value::String),
+        // Starting with a letter keeps the name from reading as a number
+        "[a-z][a-z0-9-]{0,8}".prop_map(Value::Symbol),
+    ];
+    leaf.prop_recursive(MAX_DEPTH, 64, 8, |inner| {
+        prop_oneof![
+            vec(inner.clone(), 0..8).prop_map(Value::List),
+            (vec(inner.clone(), 1..8), inner.clone())
+                .prop_map(|(elements, tail)| Value::DottedList(elements, Box::new(tail))),
+            vec(inner, 0..8).prop_map(Value::Vector),
+        ]
+    })
+}
+
+/// Generate objects allocated in `cx`. The objects are not rooted, so they are
+/// only valid until the next garbage collection.
+#[cfg(test)]
+pub(crate) fn arbitrary_object(cx: &Context) -> impl Strategy<Value = Object<'_>> + '_ {
+    arbitrary_value()
+        .prop_map(move |value| value.to_object(cx).expect("generated values should be readable"))
+}
+
+#[cfg(test)]
+#[cfg(not(miri))]
+mod test {
+    use super::*;
+    use crate::core::env::sym;
+    use crate::core::gc::RootSet;
+    use crate::fns::equal;
+    use crate::reader;
+    use proptest::test_runner::TestRunner;
+
+    #[test]
+    fn test_print_read_round_trip() {
+        sym::init_symbols();
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let mut runner = TestRunner::default();
+        let result = runner.run(&arbitrary_object(cx), |obj| {
+            let printed = obj.to_string();
+            let read = reader::read(&printed, cx).map(|(x, _)| x);
+            prop_assert!(matches!(read, Ok(x) if equal(obj, x)), "{printed} read as {read:?}");
+            Ok(())
+        });
+        result.unwrap();
+    }
+
+    #[test]
+    fn test_equal_symmetric() {
+        sym::init_symbols();
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let mut runner = TestRunner::default();
+        let objects = (arbitrary_object(cx), arbitrary_object(cx));
+        let result = runner.run(&objects, |(obj1, obj2)| {
+            let copy = Value::from_object(obj1).unwrap().to_object(cx).unwrap();
+            prop_assert!(equal(obj1, copy) && equal(copy, obj1));
+            prop_assert_eq!(equal(obj1, obj2), equal(obj2, obj1));
+            Ok(())
+        });
+        result.unwrap();
+    }
+}
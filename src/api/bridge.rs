@@ -0,0 +1,248 @@
+//! Using the interpreter from async code.
+//!
+//! A [`Runtime`] can't leave the thread it was created on, and evaluating lisp
+//! blocks that thread until it is done. [`AsyncRuntime`] owns a runtime on a
+//! thread of its own and sends it work, so awaiting a result never blocks the
+//! executor. Only `std` is used, so it works with any executor.
+//!
+//! In the other direction, lisp can wait for async Rust with a promise. The
+//! embedder makes one with [`promise`], passes the promise value to lisp, and
+//! resolves it from its own task. `(promise-await PROMISE)` blocks the
+//! interpreter thread until then, and returns the value or signals the error
+//! it was resolved with.
+use super::{Error, ForeignType, Runtime, Value};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{mpsc, Arc, Condvar, Mutex, OnceLock};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+
+struct State<T> {
+    value: Option<T>,
+    /// Set once the sender has finished, with or without a value
+    done: bool,
+    waker: Option<Waker>,
+}
+
+/// A value sent once from one thread to another. The receiver can either
+/// block or be polled as a future.
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    ready: Condvar,
+}
+
+impl<T> Shared<T> {
+    fn new() -> Arc<Self> {
+        let state = State { value: None, done: false, waker: None };
+        Arc::new(Self { state: Mutex::new(state), ready: Condvar::new() })
+    }
+
+    fn complete(&self, value: Option<T>) {
+        let mut state = self.state.lock().unwrap();
+        if state.done {
+            return;
+        }
+        state.value = value;
+        state.done = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        self.ready.notify_all();
+    }
+}
+
+/// The sending half of a [`Shared`]. Dropping it without sending wakes the
+/// receiver with nothing.
+struct Sender<T>(Arc<Shared<T>>);
+
+impl<T> Sender<T> {
+    fn send(self, value: T) {
+        self.0.complete(Some(value));
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.0.complete(None);
+    }
+}
+
+/// The result of work sent to an [`AsyncRuntime`].
+pub struct Reply<T>(Arc<Shared<T>>);
+
+impl<T> Future for Reply<T> {
+    type Output = Result<T, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.0.state.lock().unwrap();
+        if !state.done {
+            state.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        match state.value.take() {
+            Some(value) => Poll::Ready(Ok(value)),
+            None => Poll::Ready(Err(Error::Other("The runtime thread stopped".to_owned()))),
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce(&mut Runtime) + Send>;
+
+/// A runtime on its own thread, for use from async code. Work is run in the
+/// order it was sent. Dropping the handle waits for the queued work to finish.
+pub struct AsyncRuntime {
+    jobs: Option<mpsc::Sender<Job>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl AsyncRuntime {
+    /// Start a runtime thread. `init` is run before anything else, for
+    /// example to bootstrap or to define functions.
+    pub fn spawn(init: impl FnOnce(&mut Runtime) + Send + 'static) -> Self {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let thread = thread::spawn(move || {
+            Runtime::with(|rt| {
+                define_promise_functions(rt).expect("promise functions should be defined");
+                init(rt);
+                for job in queue {
+                    job(rt);
+                }
+            });
+        });
+        Self { jobs: Some(jobs), thread: Some(thread) }
+    }
+
+    /// Run `f` on the runtime thread.
+    pub fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Runtime) -> T + Send + 'static,
+    ) -> Reply<T> {
+        let shared = Shared::new();
+        let sender = Sender(shared.clone());
+        let job: Job = Box::new(move |rt| sender.send(f(rt)));
+        // If the thread has stopped the job is dropped, which drops the
+        // sender and resolves the reply with an error
+        _ = self.jobs.as_ref().expect("jobs are only taken on drop").send(job);
+        Reply(shared)
+    }
+
+    /// Evaluate `source` like [`Runtime::eval`].
+    pub fn eval_async(
+        &self,
+        source: impl Into<String>,
+    ) -> impl Future<Output = Result<Value, Error>> + Send {
+        let source = source.into();
+        let reply = self.run(move |rt| rt.eval(&source));
+        async move { reply.await? }
+    }
+}
+
+impl Drop for AsyncRuntime {
+    fn drop(&mut self) {
+        // Closing the queue stops the thread once it is empty
+        drop(self.jobs.take());
+        if let Some(thread) = self.thread.take() {
+            _ = thread.join();
+        }
+    }
+}
+
+type PromiseState = Arc<Shared<Result<Value, Error>>>;
+
+fn promise_type() -> &'static ForeignType<PromiseState> {
+    static TYPE: OnceLock<ForeignType<PromiseState>> = OnceLock::new();
+    TYPE.get_or_init(|| ForeignType::new("promise"))
+}
+
+/// Resolves a promise made with [`promise`]. Dropping it without resolving
+/// makes `promise-await` signal an error.
+pub struct Resolver(Sender<Result<Value, Error>>);
+
+impl Resolver {
+    pub fn resolve(self, result: Result<Value, Error>) {
+        self.0.send(result);
+    }
+}
+
+/// Make a promise, returning the lisp value to pass to `promise-await` and the
+/// resolver for it.
+pub fn promise() -> (Value, Resolver) {
+    let shared = Shared::new();
+    let resolver = Resolver(Sender(shared.clone()));
+    (promise_type().wrap(shared), resolver)
+}
+
+/// Define `promise-await` and `promise-p` in `rt`.
+pub(crate) fn define_promise_functions(rt: &mut Runtime) -> Result<(), Error> {
+    rt.defun("promise-await", |promise: Value| -> Result<Value, Error> {
+        let shared = promise_type().get(&promise)?;
+        let mut state = shared.state.lock().unwrap();
+        while !state.done {
+            state = shared.ready.wait(state).unwrap();
+        }
+        match &state.value {
+            Some(result) => result.clone(),
+            None => Err(Error::Other("Promise was dropped without being resolved".to_owned())),
+        }
+    })?;
+    rt.defun("promise-p", |value: Value| promise_type().is(&value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::task::Wake;
+
+    struct ThreadWaker(thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let cx = &mut Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(value) = future.as_mut().poll(cx) {
+                return value;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn test_eval_async() {
+        let rt = AsyncRuntime::spawn(|rt| {
+            rt.eval("(setq bridge-test-x 2)").unwrap();
+        });
+        assert_eq!(block_on(rt.eval_async("(+ bridge-test-x 1)")), Ok(Value::Int(3)));
+        assert!(matches!(block_on(rt.eval_async("(1 2")), Err(Error::Read(_))));
+        let reply = rt.run(|rt| rt.variable("bridge-test-x"));
+        assert_eq!(block_on(reply), Ok(Some(Value::Int(2))));
+    }
+
+    #[test]
+    fn test_promise() {
+        let rt = AsyncRuntime::spawn(|_| {});
+        let (value, resolver) = promise();
+        let reply = rt.run(move |rt| {
+            rt.set_variable("bridge-test-promise", &value).unwrap();
+            rt.eval("(setq p bridge-test-promise) (and (promise-p p) (1+ (promise-await p)))")
+        });
+        resolver.resolve(Ok(Value::Int(41)));
+        assert_eq!(block_on(reply), Ok(Ok(Value::Int(42))));
+        // awaiting again gives the same value
+        let again = rt.eval_async("(promise-await bridge-test-promise)");
+        assert_eq!(block_on(again), Ok(Value::Int(41)));
+
+        let (value, resolver) = promise();
+        drop(resolver);
+        let reply = rt.run(move |rt| rt.call("promise-await", &[value]));
+        assert!(matches!(block_on(reply), Ok(Err(Error::Other(_)))));
+        let err = block_on(rt.eval_async("(promise-await 1)")).unwrap_err();
+        assert!(matches!(err, Error::Signal { .. }));
+    }
+}
@@ -0,0 +1,94 @@
+//! Rust values that can be passed through lisp.
+use super::{Error, Value};
+use std::any::Any;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// A Rust value owned by lisp. In lisp it is a record whose type is the symbol
+/// `type_name`, so `type-of` and `cl-defstruct` style dispatch work on it. The
+/// value is shared between every copy of the record, and dropped once the
+/// garbage collector has freed all of them and no [`Value`] refers to it.
+#[derive(Clone)]
+pub struct Foreign {
+    type_name: Arc<str>,
+    data: Arc<dyn Any + Send + Sync>,
+}
+
+impl Foreign {
+    pub fn new<T: Any + Send + Sync>(type_name: &str, value: T) -> Self {
+        Self { type_name: type_name.into(), data: Arc::new(value) }
+    }
+
+    pub fn type_name(&self) -> &str {
+        &self.type_name
+    }
+
+    /// The value, if it is a `T`.
+    pub fn downcast<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        self.data.clone().downcast().ok()
+    }
+}
+
+impl PartialEq for Foreign {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.data, &other.data)
+    }
+}
+
+impl fmt::Debug for Foreign {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Foreign({})", self.type_name)
+    }
+}
+
+/// A handle for creating and unpacking foreign values of a single type. The
+/// type name is checked when unpacking, so two types can't be confused even
+/// if their Rust types are the same.
+pub struct ForeignType<T> {
+    name: Arc<str>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for ForeignType<T> {
+    fn clone(&self) -> Self {
+        Self { name: self.name.clone(), marker: PhantomData }
+    }
+}
+
+impl<T: Any + Send + Sync> ForeignType<T> {
+    pub fn new(name: &str) -> Self {
+        Self { name: name.into(), marker: PhantomData }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Wrap `value` so it can be returned to lisp.
+    pub fn wrap(&self, value: T) -> Value {
+        Value::Foreign(Foreign { type_name: self.name.clone(), data: Arc::new(value) })
+    }
+
+    /// Whether `value` is of this type.
+    pub fn is(&self, value: &Value) -> bool {
+        matches!(value, Value::Foreign(f) if *f.type_name == *self.name && f.data.is::<T>())
+    }
+
+    /// Unpack a value created with [`wrap`](Self::wrap). Anything else signals
+    /// `wrong-type-argument` with the predicate `NAME-p`.
+    pub fn get(&self, value: &Value) -> Result<Arc<T>, Error> {
+        match value {
+            Value::Foreign(f) if *f.type_name == *self.name => {
+                if let Some(value) = f.downcast() {
+                    return Ok(value);
+                }
+            }
+            _ => {}
+        }
+        Err(Error::Signal {
+            symbol: "wrong-type-argument".to_owned(),
+            data: Value::List(vec![Value::Symbol(format!("{}-p", self.name)), value.clone()]),
+        })
+    }
+}
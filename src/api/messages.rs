@@ -0,0 +1,76 @@
+//! Where the output of `message` goes.
+//!
+//! An editor shows messages in its echo area, but the interpreter has no UI of
+//! its own. Embedders install a [`MessageSink`] to route messages into theirs.
+//! Until one is installed messages are written to stderr. Either way they are
+//! also logged in the `*Messages*` buffer.
+use std::sync::{Arc, Mutex};
+
+/// Receives every message shown with `message`.
+pub trait MessageSink: Send + Sync {
+    /// Show `text`, which has already been formatted.
+    fn message(&self, text: &str);
+
+    /// Remove the current message, as `(message nil)` does. Sinks that don't
+    /// keep a message on display have nothing to do.
+    fn clear(&self) {}
+}
+
+struct Stderr;
+
+impl MessageSink for Stderr {
+    fn message(&self, text: &str) {
+        eprintln!("{text}");
+    }
+}
+
+static MESSAGE_SINK: Mutex<Option<Arc<dyn MessageSink>>> = Mutex::new(None);
+
+/// Send all messages to `sink`. This applies to every runtime in the process.
+pub fn set_message_sink(sink: impl MessageSink + 'static) {
+    *MESSAGE_SINK.lock().unwrap() = Some(Arc::new(sink));
+}
+
+/// The installed sink. It is cloned out of the lock so that a sink can
+/// replace itself while handling a message.
+pub fn message_sink() -> Arc<dyn MessageSink> {
+    match &*MESSAGE_SINK.lock().unwrap() {
+        Some(sink) => Arc::clone(sink),
+        None => Arc::new(Stderr),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{Runtime, Value};
+
+    #[derive(Default)]
+    struct Collect(Arc<Mutex<Vec<Option<String>>>>);
+
+    impl MessageSink for Collect {
+        fn message(&self, text: &str) {
+            self.0.lock().unwrap().push(Some(text.to_owned()));
+        }
+
+        fn clear(&self) {
+            self.0.lock().unwrap().push(None);
+        }
+    }
+
+    #[test]
+    fn test_message_sink() {
+        let sink = Collect::default();
+        let messages = Arc::clone(&sink.0);
+        set_message_sink(sink);
+        Runtime::with(|rt| {
+            let value = rt.eval("(message \"sink-test %s\" 1)").unwrap();
+            assert_eq!(value, Value::String("sink-test 1".to_owned()));
+            assert_eq!(rt.eval("(message nil)"), Ok(Value::Nil));
+        });
+        let messages = messages.lock().unwrap();
+        // Other tests can send messages to the same sink
+        let sent = messages.iter().position(|x| x.as_deref() == Some("sink-test 1"));
+        assert!(sent.is_some_and(|i| messages[i + 1..].contains(&None)));
+    }
+}
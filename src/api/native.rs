@@ -0,0 +1,280 @@
+//! Conversions for native functions defined with [`Runtime::defun`].
+//!
+//! Any closure whose arguments implement [`FromValue`] and whose return type
+//! implements [`IntoValue`] can be used as a lisp function. Trailing `Option`
+//! arguments are `&optional`, and a closure that takes `&[Value]` receives all
+//! of its arguments unconverted, like `&rest`.
+//!
+//! [`Runtime::defun`]: super::Runtime::defun
+use super::{Error, Foreign, NativeFn, Value};
+use std::sync::Arc;
+
+/// A type that can be converted from a lisp argument.
+pub trait FromValue: Sized {
+    /// Whether the argument can be omitted.
+    const OPTIONAL: bool = false;
+
+    fn from_value(value: Value) -> Result<Self, Error>;
+}
+
+/// A type that can be returned from a native function.
+pub trait IntoValue {
+    fn into_value(self) -> Result<Value, Error>;
+}
+
+fn wrong_type(predicate: &str, value: Value) -> Error {
+    Error::Signal {
+        symbol: "wrong-type-argument".to_owned(),
+        data: Value::List(vec![Value::Symbol(predicate.to_owned()), value]),
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        Ok(value)
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        match value {
+            Value::Int(i) => Ok(i),
+            value => Err(wrong_type("integerp", value)),
+        }
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        match value {
+            Value::Float(f) => Ok(f),
+            Value::Int(i) => Ok(i as f64),
+            value => Err(wrong_type("numberp", value)),
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        Ok(value != Value::Nil)
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        match value {
+            Value::String(s) => Ok(s),
+            value => Err(wrong_type("stringp", value)),
+        }
+    }
+}
+
+impl FromValue for Foreign {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        match value {
+            Value::Foreign(f) => Ok(f),
+            value => Err(wrong_type("recordp", value)),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    const OPTIONAL: bool = true;
+
+    fn from_value(value: Value) -> Result<Self, Error> {
+        match value {
+            Value::Nil => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        match value {
+            Value::Nil => Ok(Vec::new()),
+            Value::List(values) | Value::Vector(values) => {
+                values.into_iter().map(T::from_value).collect()
+            }
+            value => Err(wrong_type("sequencep", value)),
+        }
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Result<Value, Error> {
+        Ok(self)
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Result<Value, Error> {
+        Ok(Value::Nil)
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self) -> Result<Value, Error> {
+        Ok(Value::Int(self))
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Result<Value, Error> {
+        Ok(Value::Float(self))
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Result<Value, Error> {
+        Ok(if self { Value::True } else { Value::Nil })
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Result<Value, Error> {
+        Ok(Value::String(self))
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Result<Value, Error> {
+        Ok(Value::String(self.to_owned()))
+    }
+}
+
+impl IntoValue for Foreign {
+    fn into_value(self) -> Result<Value, Error> {
+        Ok(Value::Foreign(self))
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Result<Value, Error> {
+        match self {
+            Some(value) => value.into_value(),
+            None => Ok(Value::Nil),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Result<Value, Error> {
+        let values = self.into_iter().map(IntoValue::into_value).collect::<Result<_, _>>()?;
+        Ok(Value::List(values))
+    }
+}
+
+impl<T: IntoValue> IntoValue for Result<T, Error> {
+    fn into_value(self) -> Result<Value, Error> {
+        self?.into_value()
+    }
+}
+
+/// A closure that can be defined as a lisp function. `Args` is the tuple of
+/// argument types, which keeps the implementations for different arities
+/// apart.
+pub trait IntoNativeFn<Args> {
+    fn into_native(self, name: &str) -> NativeFn;
+}
+
+/// Marker for closures that take their arguments as a slice.
+pub struct Rest;
+
+impl<F, R> IntoNativeFn<Rest> for F
+where
+    F: Fn(&[Value]) -> R + Send + Sync + 'static,
+    R: IntoValue,
+{
+    fn into_native(self, _name: &str) -> NativeFn {
+        Arc::new(move |args: &[Value]| self(args).into_value())
+    }
+}
+
+/// Check the argument count against the arguments of a native function.
+/// Everything after the last required argument is optional.
+fn check_arity(name: &str, optional: &[bool], count: usize) -> Result<(), Error> {
+    let required = optional.iter().rposition(|&opt| !opt).map_or(0, |i| i + 1);
+    if count < required || count > optional.len() {
+        return Err(Error::Signal {
+            symbol: "wrong-number-of-arguments".to_owned(),
+            data: Value::List(vec![Value::Symbol(name.to_owned()), Value::Int(count as i64)]),
+        });
+    }
+    Ok(())
+}
+
+macro_rules! impl_into_native {
+    ($($ty:ident $var:ident),*) => {
+        impl<F, R, $($ty),*> IntoNativeFn<($($ty,)*)> for F
+        where
+            F: Fn($($ty),*) -> R + Send + Sync + 'static,
+            R: IntoValue,
+            $($ty: FromValue,)*
+        {
+            #[allow(unused_mut, unused_variables)]
+            fn into_native(self, name: &str) -> NativeFn {
+                let name = name.to_owned();
+                Arc::new(move |args: &[Value]| {
+                    check_arity(&name, &[$(<$ty as FromValue>::OPTIONAL),*], args.len())?;
+                    let mut args = args.iter().cloned();
+                    $(let $var = $ty::from_value(args.next().unwrap_or(Value::Nil))?;)*
+                    self($($var),*).into_value()
+                })
+            }
+        }
+    };
+}
+
+impl_into_native!();
+impl_into_native!(A a);
+impl_into_native!(A a, B b);
+impl_into_native!(A a, B b, C c);
+impl_into_native!(A a, B b, C c, D d);
+impl_into_native!(A a, B b, C c, D d, E e);
+impl_into_native!(A a, B b, C c, D d, E e, G g);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::Runtime;
+
+    #[test]
+    fn test_typed_defun() {
+        Runtime::with(|rt| {
+            rt.defun("native-test-add", |a: i64, b: Option<i64>| a + b.unwrap_or(10)).unwrap();
+            rt.defun("native-test-join", |parts: Vec<String>, sep: String| parts.join(&sep))
+                .unwrap();
+            rt.defun("native-test-unit", || ()).unwrap();
+            rt.defun("native-test-fail", |msg: String| -> Result<i64, Error> {
+                Err(Error::Signal {
+                    symbol: "error".to_owned(),
+                    data: Value::List(vec![Value::String(msg)]),
+                })
+            })
+            .unwrap();
+
+            assert_eq!(rt.eval("(native-test-add 1 2)"), Ok(Value::Int(3)));
+            assert_eq!(rt.eval("(native-test-add 1)"), Ok(Value::Int(11)));
+            assert_eq!(rt.eval("(native-test-add 1 nil)"), Ok(Value::Int(11)));
+            let join = rt.eval(r#"(native-test-join '("a" "b") ",")"#);
+            assert_eq!(join, Ok(Value::String("a,b".to_owned())));
+            assert_eq!(rt.eval("(native-test-unit)"), Ok(Value::Nil));
+
+            let err = rt.eval("(native-test-add 1 2 3)").unwrap_err();
+            let data = Value::List(vec![Value::Symbol("native-test-add".to_owned()), Value::Int(3)]);
+            assert_eq!(
+                err,
+                Error::Signal { symbol: "wrong-number-of-arguments".to_owned(), data }
+            );
+            let err = rt.eval("(native-test-add \"a\")").unwrap_err();
+            let data = Value::List(vec![
+                Value::Symbol("integerp".to_owned()),
+                Value::String("a".to_owned()),
+            ]);
+            assert_eq!(err, Error::Signal { symbol: "wrong-type-argument".to_owned(), data });
+            let caught = rt.eval(r#"(condition-case err (native-test-fail "x") (error (cdr err)))"#);
+            assert_eq!(caught, Ok(Value::List(vec![Value::String("x".to_owned())])));
+        });
+    }
+}
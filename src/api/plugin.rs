@@ -0,0 +1,177 @@
+//! Extensions written in Rust and loaded at runtime.
+//!
+//! A plugin is a `cdylib` that exports two functions:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub extern "C" fn rune_plugin_api_version() -> u32 {
+//!     rune::api::PLUGIN_API_VERSION
+//! }
+//!
+//! #[no_mangle]
+//! pub fn rune_plugin_create() -> Box<dyn rune::api::Plugin> {
+//!     Box::new(MyPlugin)
+//! }
+//! ```
+//!
+//! `rune_plugin_create` passes a trait object across the library boundary, so
+//! the plugin has to be built with the same compiler and the same version of
+//! rune. The version function is checked first so that a mismatch is reported
+//! instead of crashing.
+//!
+//! Plugins only see owned [`Value`]s, never objects on the GC heap, so they
+//! can't hold a pointer that a collection would invalidate. Rust data that lisp
+//! needs to hold on to is wrapped with a [`ForeignType`], which the garbage
+//! collector drops once lisp no longer references it.
+use super::{Error, ForeignType, IntoNativeFn, Runtime, Value};
+use std::any::Any;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Bumped when the plugin interface changes in an incompatible way.
+pub const PLUGIN_API_VERSION: u32 = 1;
+
+/// An extension that defines lisp functions and types.
+pub trait Plugin: Send + Sync {
+    /// The name of the plugin. It is `provide`d as a feature after
+    /// [`init`](Plugin::init) succeeds, so lisp code can `require` it.
+    fn name(&self) -> &str;
+
+    /// Define everything the plugin provides.
+    fn init(&self, registrar: &mut Registrar) -> Result<(), Error>;
+}
+
+/// What a plugin can use to set itself up.
+pub struct Registrar<'a, 'brw, 'env, 'rt> {
+    rt: &'a mut Runtime<'brw, 'env, 'rt>,
+}
+
+impl Registrar<'_, '_, '_, '_> {
+    /// Define a lisp function, see [`Runtime::defun`].
+    pub fn defun<Args>(&mut self, name: &str, func: impl IntoNativeFn<Args>) -> Result<(), Error> {
+        self.rt.defun(name, func)
+    }
+
+    /// Define a type of foreign value named `name`, along with the predicate
+    /// `NAME-p`.
+    pub fn define_type<T: Any + Send + Sync>(
+        &mut self,
+        name: &str,
+    ) -> Result<ForeignType<T>, Error> {
+        let ty = ForeignType::new(name);
+        let predicate = ty.clone();
+        self.rt.defun(&format!("{name}-p"), move |value: Value| predicate.is(&value))?;
+        Ok(ty)
+    }
+
+    /// Evaluate lisp code, for the parts of the plugin that are easier to
+    /// write in lisp.
+    pub fn eval(&mut self, source: &str) -> Result<Value, Error> {
+        self.rt.eval(source)
+    }
+}
+
+/// Libraries of loaded plugins. They are never unloaded, because functions
+/// they defined can be called at any time.
+static LIBRARIES: Mutex<Vec<libloading::Library>> = Mutex::new(Vec::new());
+
+type VersionFn = unsafe extern "C" fn() -> u32;
+type CreateFn = unsafe fn() -> Box<dyn Plugin>;
+
+impl Runtime<'_, '_, '_> {
+    /// Initialize `plugin`.
+    pub fn add_plugin(&mut self, plugin: &dyn Plugin) -> Result<(), Error> {
+        plugin.init(&mut Registrar { rt: self })?;
+        self.call("provide", &[Value::Symbol(plugin.name().to_owned())])?;
+        Ok(())
+    }
+
+    /// Load the plugin library at `path` and initialize it.
+    pub fn load_plugin(&mut self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let fail = |e: &dyn std::fmt::Display| {
+            Error::Other(format!("Failed to load plugin {}: {e}", path.display()))
+        };
+        // SAFETY: loading a library runs its initializers. There is no way to
+        // check them, so plugins have to be trusted.
+        let library = unsafe { libloading::Library::new(path) }.map_err(|e| fail(&e))?;
+        let plugin = unsafe {
+            let version = library.get::<VersionFn>(b"rune_plugin_api_version\0");
+            let version = version.map_err(|e| fail(&e))?();
+            if version != PLUGIN_API_VERSION {
+                let msg = format!("plugin API version {version}, expected {PLUGIN_API_VERSION}");
+                return Err(fail(&msg));
+            }
+            let create = library.get::<CreateFn>(b"rune_plugin_create\0");
+            create.map_err(|e| fail(&e))?()
+        };
+        LIBRARIES.lock().unwrap().push(library);
+        // The plugin is dropped here, but what it defined lives in the
+        // library, which stays loaded
+        self.add_plugin(&*plugin)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Counter(Mutex<i64>);
+
+    struct TestPlugin;
+
+    impl Plugin for TestPlugin {
+        fn name(&self) -> &str {
+            "plugin-test"
+        }
+
+        fn init(&self, registrar: &mut Registrar) -> Result<(), Error> {
+            let counter = registrar.define_type::<Counter>("plugin-test-counter")?;
+            let make = counter.clone();
+            registrar.defun("plugin-test-make", move |start: i64| {
+                make.wrap(Counter(Mutex::new(start)))
+            })?;
+            registrar.defun("plugin-test-incr", move |value: Value| -> Result<i64, Error> {
+                let counter = counter.get(&value)?;
+                let mut count = counter.0.lock().unwrap();
+                *count += 1;
+                Ok(*count)
+            })?;
+            let twice = "(defun plugin-test-twice (c) (plugin-test-incr c) (plugin-test-incr c))";
+            registrar.eval(twice)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_plugin() {
+        Runtime::with(|rt| {
+            rt.add_plugin(&TestPlugin).unwrap();
+            let feature = Value::Symbol("plugin-test".to_owned());
+            assert_eq!(rt.eval("(require 'plugin-test)"), Ok(feature));
+            rt.eval("(setq c (plugin-test-make 5))").unwrap();
+            assert_eq!(rt.eval("(plugin-test-twice c)"), Ok(Value::Int(7)));
+            assert_eq!(rt.eval("(plugin-test-counter-p c)"), Ok(Value::True));
+            assert_eq!(rt.eval("(plugin-test-counter-p 1)"), Ok(Value::Nil));
+            assert_eq!(
+                rt.eval("(type-of c)"),
+                Ok(Value::Symbol("plugin-test-counter".to_owned()))
+            );
+            let err = rt.eval("(plugin-test-incr 'a)").unwrap_err();
+            let data = Value::List(vec![
+                Value::Symbol("plugin-test-counter-p".to_owned()),
+                Value::Symbol("a".to_owned()),
+            ]);
+            assert_eq!(err, Error::Signal { symbol: "wrong-type-argument".to_owned(), data });
+            // Copies share the counter and keep it alive after the original
+            // is collected, including copies in the global block
+            rt.eval("(setq d (copy-sequence c) c nil) (garbage-collect)").unwrap();
+            assert_eq!(rt.eval("(plugin-test-incr d)"), Ok(Value::Int(8)));
+            assert_eq!(rt.eval("(plugin-test-incr (purecopy d))"), Ok(Value::Int(9)));
+            rt.eval("(fset 'plugin-test-get (list 'lambda nil (list 'quote d)))").unwrap();
+            rt.eval("(setq d nil) (garbage-collect)").unwrap();
+            assert_eq!(rt.eval("(plugin-test-incr (plugin-test-get))"), Ok(Value::Int(10)));
+            assert!(rt.load_plugin("/nonexistent/plugin.so").is_err());
+        });
+    }
+}
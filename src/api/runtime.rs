@@ -0,0 +1,261 @@
+//! A complete interpreter that can be driven from Rust.
+use super::{analysis, register_native, Analysis, Error, IntoNativeFn, Value};
+use crate::core::{
+    env::{intern, sym, CallFrame, Env},
+    gc::{Context, RootSet, Rt},
+    object::{Function, Gc, LispString, NIL},
+};
+use crate::{interpreter, reader};
+use rune_core::macros::{list, root};
+use std::path::Path;
+
+/// A handle to a running interpreter.
+///
+/// The garbage collector finds live objects through roots on the Rust stack,
+/// so a runtime can't be moved or returned. Instead it is created for the
+/// duration of a closure with [`Runtime::with`]. Everything passed in or out of
+/// a runtime is an owned [`Value`].
+pub struct Runtime<'brw, 'env, 'rt> {
+    env: &'brw mut Rt<Env<'env>>,
+    cx: &'brw mut Context<'rt>,
+}
+
+impl Runtime<'_, '_, '_> {
+    /// Create a runtime and pass it to `f`. The runtime is torn down when `f`
+    /// returns. Only one runtime can exist on a thread at a time.
+    pub fn with<T>(f: impl FnOnce(&mut Runtime) -> T) -> T {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+
+        sym::init_symbols();
+        crate::core::env::init_variables(cx, env);
+        crate::data::defalias(sym::NOT, (sym::NULL).into(), None, env)
+            .expect("null should be defined");
+        crate::emacs::init_process_vars(env, cx);
+        crate::eval::init_errors(env, cx);
+        f(&mut Runtime { env, cx })
+    }
+
+    /// Load `bootstrap.el`, which defines the parts of the standard library
+    /// that are written in lisp.
+    pub fn bootstrap(&mut self) -> Result<(), Error> {
+        crate::buffer::get_buffer_create(self.cx.add("*scratch*"), Some(NIL), self.cx)
+            .map_err(|e| Error::Other(e.to_string()))?;
+        self.load("bootstrap.el")
+    }
+
+    /// Read and evaluate every form in `source`, returning the value of the
+    /// last one.
+    pub fn eval(&mut self, source: &str) -> Result<Value, Error> {
+        let cx = &mut *self.cx;
+        let env = &mut *self.env;
+        let mut pos = 0;
+        let mut last = Value::Nil;
+        loop {
+            let (obj, new_pos) = match reader::read(&source[pos..], cx) {
+                Ok(read) => read,
+                Err(reader::Error::EmptyStream) => return Ok(last),
+                Err(mut e) => {
+                    e.update_pos(pos);
+                    return Err(Error::Read(e.to_string()));
+                }
+            };
+            root!(obj, cx);
+            match interpreter::eval(obj, None, env, cx) {
+                Ok(value) => last = Value::from_object(value)?,
+                Err(e) => return Err(Error::from_runtime(e, env, cx)),
+            }
+            pos += new_pos;
+        }
+    }
+
+    /// Load the lisp file at `path`. Relative paths that don't exist are
+    /// searched for in `load-path`.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let cx = &mut *self.cx;
+        let env = &mut *self.env;
+        let path = path.as_ref().to_string_lossy();
+        let file: Gc<&LispString> = cx.add_as(&*path);
+        root!(file, cx);
+        match crate::lread::load(file, None, Some(()), cx, env) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::from_runtime(e, env, cx)),
+        }
+    }
+
+    /// Call the function named `function` with `args`.
+    pub fn call(&mut self, function: &str, args: &[Value]) -> Result<Value, Error> {
+        let cx = &mut *self.cx;
+        let func: Function = intern(function, cx).into();
+        root!(func, cx);
+        let frame = &mut CallFrame::new(self.env);
+        for arg in args {
+            frame.push_arg(arg.to_object(cx)?);
+        }
+        match func.call(frame, Some(function), cx) {
+            Ok(value) => Value::from_object(value),
+            Err(e) => Err(Error::from_runtime(e.into(), frame, cx)),
+        }
+    }
+
+    /// Intern `name` and return it as a symbol.
+    pub fn intern(&mut self, name: &str) -> Value {
+        Value::Symbol(intern(name, self.cx).to_string())
+    }
+
+    /// The global value of the variable `name`, or `None` if it is void.
+    pub fn variable(&self, name: &str) -> Option<Value> {
+        let symbol = intern(name, self.cx);
+        let value = self.env.vars.get(symbol)?.bind(self.cx);
+        Some(Value::from_object(value).unwrap_or_else(|e| Value::Opaque(e.to_string())))
+    }
+
+    /// Set the global value of the variable `name`.
+    pub fn set_variable(&mut self, name: &str, value: &Value) -> Result<(), Error> {
+        let symbol = intern(name, self.cx);
+        let value = value.to_object(self.cx)?;
+        self.env.set_var(symbol, value).map_err(|e| Error::Other(e.to_string()))
+    }
+
+    /// Analyze the forms in `source` without evaluating them. Macro calls are
+    /// expanded with the macros defined in this runtime. See
+    /// [`Analysis`] for what is reported.
+    pub fn analyze(&mut self, source: &str) -> Result<Analysis, Error> {
+        analysis::analyze(source, self.env, self.cx)
+    }
+
+    /// Fully expand the macro call around the byte offset `point` in
+    /// `source`. Returns `None` if `point` is not inside a form.
+    pub fn expand_at(&mut self, source: &str, point: usize) -> Result<Option<Value>, Error> {
+        analysis::expand_at(source, point, self.env, self.cx)
+    }
+
+    /// Save the functions, global variables, symbol properties and features
+    /// of this runtime to `path`, in the format of `dump-emacs-portable`. A
+    /// later session can pick up where this one left off with
+    /// [`load_state`](Self::load_state) instead of replaying its history.
+    ///
+    /// Functions added with [`defun`](Self::defun) refer to the native
+    /// function by an id that is only valid in this process, so they have to
+    /// be defined again after loading the state in another one.
+    ///
+    /// Buffers and the Rust data of foreign objects can't be saved. They are
+    /// left out, and a description of each is returned.
+    pub fn save_state(&self, path: impl AsRef<Path>) -> Result<Vec<String>, Error> {
+        crate::pdump::dump(path.as_ref(), &*self.env, &*self.cx)
+            .map_err(|e| Error::Other(e.to_string()))
+    }
+
+    /// Restore the state written by [`save_state`](Self::save_state) on top of
+    /// this runtime. The state has to come from the same version of rune.
+    pub fn load_state(&mut self, path: impl AsRef<Path>) -> Result<(), Error> {
+        crate::pdump::restore(path.as_ref(), self.env, self.cx)
+            .map_err(|e| Error::Other(e.to_string()))
+    }
+
+    /// Define a lisp function `name` that calls `func` with its arguments.
+    /// The arguments are converted to the types `func` takes, see
+    /// [`IntoNativeFn`]. Errors returned from `func` are signaled in lisp, so
+    /// they can be handled with `condition-case`.
+    pub fn defun<Args>(&mut self, name: &str, func: impl IntoNativeFn<Args>) -> Result<(), Error> {
+        let id = register_native(func.into_native(name));
+        let cx = &*self.cx;
+        let args = intern("args", cx);
+        // (closure (t) (&rest args) (internal--call-native ID args))
+        let env = list![sym::TRUE; cx];
+        let arglist = list![sym::AND_REST, args; cx];
+        let body = list![sym::INTERNAL__CALL_NATIVE, id, args; cx];
+        let closure = list![sym::CLOSURE, env, arglist, body; cx];
+        crate::data::defalias(intern(name, cx), closure, None, self.env)
+            .map_err(|e| Error::Other(e.to_string()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_runtime() {
+        Runtime::with(|rt| {
+            assert_eq!(rt.eval("(setq x 2) (+ x 1)"), Ok(Value::Int(3)));
+            assert_eq!(rt.variable("x"), Some(Value::Int(2)));
+            rt.set_variable("x", &Value::Int(5)).unwrap();
+            assert_eq!(rt.eval("x"), Ok(Value::Int(5)));
+            assert_eq!(rt.variable("runtime-test-unbound"), None);
+
+            let args = [Value::Int(1), Value::String("a".to_owned())];
+            let list = Value::List(args.to_vec());
+            assert_eq!(rt.call("list", &args), Ok(list));
+            assert_eq!(rt.intern("foo"), Value::Symbol("foo".to_owned()));
+            assert!(matches!(rt.eval("(1 2"), Err(Error::Read(_))));
+            let undefined = Value::Symbol("runtime-test-undefined".to_owned());
+            let data = Value::List(vec![undefined]);
+            let void = Error::Signal { symbol: "void-function".to_owned(), data };
+            assert_eq!(rt.call("runtime-test-undefined", &[]), Err(void));
+        });
+    }
+
+    #[test]
+    fn test_runtime_state() {
+        let path = std::env::temp_dir().join(format!("rune-state-{}", std::process::id()));
+        Runtime::with(|rt| {
+            rt.eval("(defvar state-test-var '(1 \"a\"))").unwrap();
+            rt.eval("(fset 'state-test-func #'(lambda (x) (* x 2)))").unwrap();
+            rt.save_state(&path).unwrap();
+            // function cells are global, so remove it to see that it is restored
+            rt.eval("(fmakunbound 'state-test-func)").unwrap();
+        });
+        Runtime::with(|rt| {
+            assert_eq!(rt.variable("state-test-var"), None);
+            rt.load_state(&path).unwrap();
+            let list = Value::List(vec![Value::Int(1), Value::String("a".to_owned())]);
+            assert_eq!(rt.variable("state-test-var"), Some(list));
+            assert_eq!(rt.call("state-test-func", &[Value::Int(4)]), Ok(Value::Int(8)));
+        });
+        std::fs::remove_file(&path).unwrap();
+        Runtime::with(|rt| assert!(rt.load_state(&path).is_err()));
+    }
+
+    #[test]
+    fn test_runtime_defun() {
+        Runtime::with(|rt| {
+            rt.defun("runtime-test-sum", |args: &[Value]| {
+                let mut sum = 0;
+                for arg in args {
+                    match arg {
+                        Value::Int(i) => sum += i,
+                        other => {
+                            return Err(Error::Signal {
+                                symbol: "wrong-type-argument".to_owned(),
+                                data: Value::List(vec![
+                                    Value::Symbol("integerp".to_owned()),
+                                    other.clone(),
+                                ]),
+                            })
+                        }
+                    }
+                }
+                Ok(Value::Int(sum))
+            })
+            .unwrap();
+            assert_eq!(rt.eval("(runtime-test-sum 1 2 3)"), Ok(Value::Int(6)));
+            assert_eq!(rt.call("runtime-test-sum", &[Value::Int(4)]), Ok(Value::Int(4)));
+            let err = rt.eval("(runtime-test-sum 1 'a)").unwrap_err();
+            assert_eq!(
+                err,
+                Error::Signal {
+                    symbol: "wrong-type-argument".to_owned(),
+                    data: Value::List(vec![
+                        Value::Symbol("integerp".to_owned()),
+                        Value::Symbol("a".to_owned())
+                    ]),
+                }
+            );
+            let caught = rt.eval("(condition-case err (runtime-test-sum 'b) (error (car err)))");
+            assert_eq!(caught, Ok(Value::Symbol("wrong-type-argument".to_owned())));
+        });
+    }
+}
@@ -0,0 +1,662 @@
+//! Conversion between Rust types and lisp objects using serde.
+//!
+//! Rust data is mapped to the lisp types you would write by hand:
+//!
+//! - `bool` becomes `t` or `nil`, and `None` and `()` become `nil`
+//! - sequences and tuples become lists
+//! - structs become plists with keyword keys, like `(:name "foo" :size 2)`
+//! - maps become hash tables
+//! - unit enum variants become symbols, and variants with data become lists
+//!   whose first element is the variant symbol, like `(Move 1 2)`
+use super::{Error, Value};
+use crate::core::{gc::Context, object::Object};
+use serde::de::{
+    self, value::MapDeserializer, value::SeqDeserializer, DeserializeOwned, DeserializeSeed,
+    IntoDeserializer, Visitor,
+};
+use serde::ser::{self, Serialize};
+use std::fmt::Display;
+
+/// Convert `value` to a lisp object allocated in `cx`.
+// Not used by any builtin yet
+#[allow(dead_code)]
+pub(crate) fn to_object<'ob, T>(value: &T, cx: &'ob Context) -> Result<Object<'ob>, Error>
+where
+    T: Serialize + ?Sized,
+{
+    to_value(value)?.to_object(cx)
+}
+
+/// Convert the lisp object `obj` to a Rust value.
+#[allow(dead_code)]
+pub(crate) fn from_object<T: DeserializeOwned>(obj: Object) -> Result<T, Error> {
+    from_value(Value::from_object(obj)?)
+}
+
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<Value, Error> {
+    value.serialize(ValueSerializer)
+}
+
+pub fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, Error> {
+    T::deserialize(value)
+}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error::Other(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error::Other(msg.to_string())
+    }
+}
+
+fn keyword(name: &str) -> Value {
+    Value::Symbol(format!(":{name}"))
+}
+
+struct ValueSerializer;
+
+struct SeqSerializer {
+    variant: Option<&'static str>,
+    values: Vec<Value>,
+}
+
+impl SeqSerializer {
+    fn finish(self) -> Value {
+        match self.variant {
+            Some(variant) => {
+                let mut list = vec![Value::Symbol(variant.to_owned())];
+                list.extend(self.values);
+                Value::List(list)
+            }
+            None => Value::List(self.values),
+        }
+    }
+}
+
+struct MapSerializer {
+    entries: Vec<(Value, Value)>,
+    key: Option<Value>,
+}
+
+impl ser::Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = Error;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = SeqSerializer;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = SeqSerializer;
+    type SerializeStructVariant = SeqSerializer;
+
+    fn serialize_bool(self, v: bool) -> Result<Value, Error> {
+        Ok(if v { Value::True } else { Value::Nil })
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value, Error> {
+        Ok(Value::Int(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value, Error> {
+        match i64::try_from(v) {
+            Ok(v) => Ok(Value::Int(v)),
+            Err(_) => Err(Error::Other(format!("Integer {v} is too large"))),
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value, Error> {
+        Ok(Value::Float(v.into()))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value, Error> {
+        Ok(Value::Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value, Error> {
+        Ok(Value::Int(i64::from(u32::from(v))))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value, Error> {
+        Ok(Value::String(v.to_owned()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value, Error> {
+        Ok(Value::Bytes(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<Value, Error> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, Error> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, Error> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Value, Error> {
+        Ok(Value::Symbol(variant.to_owned()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Value, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value, Error> {
+        let value = value.serialize(ValueSerializer)?;
+        Ok(Value::List(vec![Value::Symbol(variant.to_owned()), value]))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer, Error> {
+        Ok(SeqSerializer { variant: None, values: Vec::with_capacity(len.unwrap_or(0)) })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer, Error> {
+        Ok(SeqSerializer { variant: Some(variant), values: Vec::with_capacity(len) })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<MapSerializer, Error> {
+        Ok(MapSerializer { entries: Vec::with_capacity(len.unwrap_or(0)), key: None })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SeqSerializer, Error> {
+        Ok(SeqSerializer { variant: None, values: Vec::with_capacity(len * 2) })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer, Error> {
+        Ok(SeqSerializer { variant: Some(variant), values: Vec::with_capacity(len * 2) })
+    }
+}
+
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.values.push(value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTupleVariant for SeqSerializer {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeStruct for SeqSerializer {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.values.push(keyword(key));
+        self.values.push(value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeStructVariant for SeqSerializer {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        ser::SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.key = Some(key.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let Some(key) = self.key.take() else {
+            return Err(Error::Other("Map value serialized before its key".to_owned()));
+        };
+        self.entries.push((key, value.serialize(ValueSerializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(Value::HashTable(self.entries))
+    }
+}
+
+/// Split a plist into key value pairs, removing the `:` from keywords.
+fn plist_entries(values: Vec<Value>) -> Result<Vec<(Value, Value)>, Error> {
+    if values.len() % 2 != 0 {
+        return Err(Error::Other("plist has an odd number of elements".to_owned()));
+    }
+    let mut entries = Vec::with_capacity(values.len() / 2);
+    let mut iter = values.into_iter();
+    while let (Some(key), Some(value)) = (iter.next(), iter.next()) {
+        let key = match key {
+            Value::Symbol(name) => match name.strip_prefix(':') {
+                Some(name) => Value::String(name.to_owned()),
+                None => Value::String(name),
+            },
+            key => key,
+        };
+        entries.push((key, value));
+    }
+    Ok(entries)
+}
+
+fn invalid_type(value: &Value, expected: &str) -> Error {
+    Error::Other(format!("Invalid type: expected {expected}, found {value}"))
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Value {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Value {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Value::Nil => visitor.visit_unit(),
+            Value::True => visitor.visit_bool(true),
+            Value::Int(i) => visitor.visit_i64(i),
+            Value::Float(f) => visitor.visit_f64(f),
+            Value::String(s) | Value::Symbol(s) => visitor.visit_string(s),
+            Value::Bytes(b) => visitor.visit_byte_buf(b),
+            Value::List(values) | Value::Vector(values) | Value::Record(values) => {
+                let mut seq = SeqDeserializer::new(values.into_iter());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            Value::HashTable(entries) => {
+                let mut map = MapDeserializer::new(entries.into_iter());
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+            value @ (Value::DottedList(..) | Value::Opaque(_) | Value::Foreign(_)) => {
+                Err(invalid_type(&value, "a readable value"))
+            }
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Value::Nil => visitor.visit_bool(false),
+            Value::True => visitor.visit_bool(true),
+            value => Err(invalid_type(&value, "t or nil")),
+        }
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Value::Int(i) => match u32::try_from(i).ok().and_then(char::from_u32) {
+                Some(chr) => visitor.visit_char(chr),
+                None => Err(Error::Other(format!("Invalid character: {i}"))),
+            },
+            value => value.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Value::Nil => visitor.visit_none(),
+            value => visitor.visit_some(value),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Value::Nil => visitor.visit_seq(SeqDeserializer::new(std::iter::empty::<Value>())),
+            value => value.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let entries = match self {
+            Value::Nil => Vec::new(),
+            Value::HashTable(entries) => entries,
+            Value::List(values) => plist_entries(values)?,
+            value => return Err(invalid_type(&value, "a map")),
+        };
+        let mut map = MapDeserializer::new(entries.into_iter());
+        let value = visitor.visit_map(&mut map)?;
+        map.end()?;
+        Ok(value)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self {
+            Value::Symbol(variant) => visitor.visit_enum(EnumDeserializer { variant, data: None }),
+            Value::List(mut values) if matches!(values.first(), Some(Value::Symbol(_))) => {
+                let Value::Symbol(variant) = values.remove(0) else { unreachable!() };
+                visitor.visit_enum(EnumDeserializer { variant, data: Some(values) })
+            }
+            value => Err(invalid_type(&value, "an enum variant")),
+        }
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Value::Symbol(name) => match name.strip_prefix(':') {
+                Some(name) => visitor.visit_str(name),
+                None => visitor.visit_string(name),
+            },
+            value => value.deserialize_any(visitor),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 str string bytes byte_buf
+        unit unit_struct ignored_any
+    }
+}
+
+struct EnumDeserializer {
+    variant: String,
+    /// The elements following the variant symbol, if it was in a list
+    data: Option<Vec<Value>>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumDeserializer {
+    type Error = Error;
+    type Variant = VariantDeserializer;
+
+    fn variant_seed<S>(self, seed: S) -> Result<(S::Value, VariantDeserializer), Error>
+    where
+        S: DeserializeSeed<'de>,
+    {
+        let variant = seed.deserialize(Value::Symbol(self.variant))?;
+        Ok((variant, VariantDeserializer { data: self.data }))
+    }
+}
+
+struct VariantDeserializer {
+    data: Option<Vec<Value>>,
+}
+
+impl<'de> de::VariantAccess<'de> for VariantDeserializer {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        match self.data {
+            None => Ok(()),
+            Some(data) if data.is_empty() => Ok(()),
+            Some(_) => Err(Error::Other("Expected a unit variant".to_owned())),
+        }
+    }
+
+    fn newtype_variant_seed<S>(self, seed: S) -> Result<S::Value, Error>
+    where
+        S: DeserializeSeed<'de>,
+    {
+        match self.data {
+            Some(mut data) if data.len() == 1 => seed.deserialize(data.remove(0)),
+            _ => Err(Error::Other("Expected a newtype variant".to_owned())),
+        }
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        let mut seq = SeqDeserializer::new(self.data.unwrap_or_default().into_iter());
+        let value = visitor.visit_seq(&mut seq)?;
+        seq.end()?;
+        Ok(value)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let mut map = MapDeserializer::new(plist_entries(self.data.unwrap_or_default())?.into_iter());
+        let value = visitor.visit_map(&mut map)?;
+        map.end()?;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::{
+        env::{intern, sym},
+        gc::RootSet,
+        object::NIL,
+    };
+    use rune_core::macros::list;
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Shape {
+        Point,
+        Circle(f64),
+        Rect(i64, i64),
+        Named { name: String },
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Config {
+        name: String,
+        size: u32,
+        enabled: bool,
+        tags: Vec<String>,
+        parent: Option<String>,
+        shapes: Vec<Shape>,
+        counts: BTreeMap<String, i64>,
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        sym::init_symbols();
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let config = Config {
+            name: "foo".to_owned(),
+            size: 3,
+            enabled: false,
+            tags: vec!["a".to_owned(), "b".to_owned()],
+            parent: None,
+            shapes: vec![
+                Shape::Point,
+                Shape::Circle(1.5),
+                Shape::Rect(1, 2),
+                Shape::Named { name: "bar".to_owned() },
+            ],
+            counts: BTreeMap::from([("x".to_owned(), 1)]),
+        };
+        let obj = to_object(&config, cx).unwrap();
+        let back: Config = from_object(obj).unwrap();
+        assert_eq!(back, config);
+    }
+
+    #[test]
+    fn test_serde_lisp_shape() {
+        sym::init_symbols();
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let value = to_value(&(1, "two", true, None::<i64>)).unwrap();
+        assert_eq!(value.to_string(), "(1 \"two\" t nil)");
+        let value = to_value(&Shape::Rect(1, 2)).unwrap();
+        assert_eq!(value.to_string(), "(Rect 1 2)");
+
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Point {
+            x: i64,
+            y: i64,
+        }
+        let plist = list![intern(":x", cx), 1, intern(":y", cx), 2; cx];
+        assert_eq!(from_object::<Point>(plist).unwrap(), Point { x: 1, y: 2 });
+        let empty: Vec<i64> = from_object(NIL).unwrap();
+        assert!(empty.is_empty());
+        assert!(from_object::<Point>(list![1, 2; cx]).is_err());
+    }
+}
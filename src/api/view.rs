@@ -0,0 +1,226 @@
+//! Borrowed views of values, for matching without cloning.
+//!
+//! [`Value`] stores lists as vectors, which is convenient for building them
+//! but not for code that walks lisp data one cons at a time. An
+//! [`ObjectView`] borrows a value and presents it the way lisp sees it: a list
+//! is a cons whose `cdr` is the rest of the list. Views only borrow the value
+//! they came from, so they can't outlive it and don't touch the GC heap.
+use super::{Foreign, Value};
+
+/// A borrowed view of a [`Value`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ObjectView<'a> {
+    Nil,
+    True,
+    Int(i64),
+    Float(f64),
+    Str(&'a str),
+    /// A unibyte string
+    Bytes(&'a [u8]),
+    Symbol(&'a str),
+    Cons(ConsView<'a>),
+    Vec(&'a [Value]),
+    Record(&'a [Value]),
+    HashTable(&'a [(Value, Value)]),
+    /// The printed representation of an object that can't be read back
+    Opaque(&'a str),
+    Foreign(&'a Foreign),
+}
+
+/// A cons in a list. The list has at least one element left.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConsView<'a> {
+    elements: &'a [Value],
+    /// The final `cdr` of a dotted list
+    tail: Option<&'a Value>,
+}
+
+impl<'a> ConsView<'a> {
+    pub fn car(self) -> ObjectView<'a> {
+        self.elements[0].view()
+    }
+
+    pub fn cdr(self) -> ObjectView<'a> {
+        match (&self.elements[1..], self.tail) {
+            ([], None) => ObjectView::Nil,
+            ([], Some(tail)) => tail.view(),
+            (elements, tail) => ObjectView::Cons(ConsView { elements, tail }),
+        }
+    }
+
+    /// The elements from this cons to the end of the list, not including the
+    /// tail of a dotted list.
+    pub fn elements(self) -> &'a [Value] {
+        self.elements
+    }
+
+    /// The final `cdr`, if the list is dotted.
+    pub fn tail(self) -> Option<&'a Value> {
+        self.tail
+    }
+}
+
+impl Value {
+    pub fn view(&self) -> ObjectView<'_> {
+        ObjectView::from(self)
+    }
+}
+
+impl<'a> From<&'a Value> for ObjectView<'a> {
+    fn from(value: &'a Value) -> Self {
+        match value {
+            Value::Nil => ObjectView::Nil,
+            Value::True => ObjectView::True,
+            Value::Int(i) => ObjectView::Int(*i),
+            Value::Float(f) => ObjectView::Float(*f),
+            Value::String(s) => ObjectView::Str(s),
+            Value::Bytes(b) => ObjectView::Bytes(b),
+            Value::Symbol(s) => ObjectView::Symbol(s),
+            // An empty list is nil
+            Value::List(elements) if elements.is_empty() => ObjectView::Nil,
+            Value::List(elements) => ObjectView::Cons(ConsView { elements, tail: None }),
+            Value::DottedList(elements, tail) if elements.is_empty() => tail.view(),
+            Value::DottedList(elements, tail) => {
+                ObjectView::Cons(ConsView { elements, tail: Some(tail) })
+            }
+            Value::Vector(elements) => ObjectView::Vec(elements),
+            Value::Record(elements) => ObjectView::Record(elements),
+            Value::HashTable(entries) => ObjectView::HashTable(entries),
+            Value::Opaque(repr) => ObjectView::Opaque(repr),
+            Value::Foreign(foreign) => ObjectView::Foreign(foreign),
+        }
+    }
+}
+
+impl<'a> ObjectView<'a> {
+    /// Call the method of `visitor` for this kind of value.
+    pub fn accept<V: Visitor<'a>>(self, visitor: &mut V) -> V::Output {
+        match self {
+            ObjectView::Nil => visitor.visit_nil(),
+            ObjectView::True => visitor.visit_true(),
+            ObjectView::Int(i) => visitor.visit_int(i),
+            ObjectView::Float(f) => visitor.visit_float(f),
+            ObjectView::Str(s) => visitor.visit_str(s),
+            ObjectView::Symbol(s) => visitor.visit_symbol(s),
+            ObjectView::Cons(cons) => visitor.visit_cons(cons),
+            ObjectView::Vec(elements) => visitor.visit_vec(elements),
+            view => visitor.visit_other(view),
+        }
+    }
+
+    /// Copy the viewed data into an owned value.
+    pub fn to_value(self) -> Value {
+        match self {
+            ObjectView::Nil => Value::Nil,
+            ObjectView::True => Value::True,
+            ObjectView::Int(i) => Value::Int(i),
+            ObjectView::Float(f) => Value::Float(f),
+            ObjectView::Str(s) => Value::String(s.to_owned()),
+            ObjectView::Bytes(b) => Value::Bytes(b.to_vec()),
+            ObjectView::Symbol(s) => Value::Symbol(s.to_owned()),
+            ObjectView::Cons(ConsView { elements, tail: None }) => Value::List(elements.to_vec()),
+            ObjectView::Cons(ConsView { elements, tail: Some(tail) }) => {
+                Value::DottedList(elements.to_vec(), Box::new(tail.clone()))
+            }
+            ObjectView::Vec(elements) => Value::Vector(elements.to_vec()),
+            ObjectView::Record(elements) => Value::Record(elements.to_vec()),
+            ObjectView::HashTable(entries) => Value::HashTable(entries.to_vec()),
+            ObjectView::Opaque(repr) => Value::Opaque(repr.to_owned()),
+            ObjectView::Foreign(foreign) => Value::Foreign(foreign.clone()),
+        }
+    }
+}
+
+/// Code that handles each kind of value differently. Every method has a
+/// default that calls [`Visitor::visit_other`], so only the kinds that matter
+/// need to be implemented.
+pub trait Visitor<'a> {
+    type Output;
+
+    /// Called for every kind of value whose method is not implemented.
+    fn visit_other(&mut self, view: ObjectView<'a>) -> Self::Output;
+
+    fn visit_nil(&mut self) -> Self::Output {
+        self.visit_other(ObjectView::Nil)
+    }
+
+    fn visit_true(&mut self) -> Self::Output {
+        self.visit_other(ObjectView::True)
+    }
+
+    fn visit_int(&mut self, i: i64) -> Self::Output {
+        self.visit_other(ObjectView::Int(i))
+    }
+
+    fn visit_float(&mut self, f: f64) -> Self::Output {
+        self.visit_other(ObjectView::Float(f))
+    }
+
+    fn visit_str(&mut self, s: &'a str) -> Self::Output {
+        self.visit_other(ObjectView::Str(s))
+    }
+
+    fn visit_symbol(&mut self, name: &'a str) -> Self::Output {
+        self.visit_other(ObjectView::Symbol(name))
+    }
+
+    fn visit_cons(&mut self, cons: ConsView<'a>) -> Self::Output {
+        self.visit_other(ObjectView::Cons(cons))
+    }
+
+    fn visit_vec(&mut self, elements: &'a [Value]) -> Self::Output {
+        self.visit_other(ObjectView::Vec(elements))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Sums the integers in a tree of lists and vectors.
+    struct Sum;
+
+    impl<'a> Visitor<'a> for Sum {
+        type Output = i64;
+
+        fn visit_other(&mut self, _view: ObjectView<'a>) -> i64 {
+            0
+        }
+
+        fn visit_int(&mut self, i: i64) -> i64 {
+            i
+        }
+
+        fn visit_cons(&mut self, cons: ConsView<'a>) -> i64 {
+            cons.car().accept(self) + cons.cdr().accept(self)
+        }
+
+        fn visit_vec(&mut self, elements: &'a [Value]) -> i64 {
+            elements.iter().map(|x| x.view().accept(self)).sum()
+        }
+    }
+
+    #[test]
+    fn test_cons_view() {
+        let list = Value::DottedList(vec![Value::Int(1), Value::Int(2)], Box::new(Value::Int(3)));
+        let ObjectView::Cons(cons) = list.view() else { panic!("not a cons") };
+        assert_eq!(cons.car(), ObjectView::Int(1));
+        let ObjectView::Cons(rest) = cons.cdr() else { panic!("not a cons") };
+        assert_eq!(rest.elements(), [Value::Int(2)]);
+        assert_eq!(rest.cdr(), ObjectView::Int(3));
+        let tail = Value::DottedList(vec![Value::Int(2)], Box::new(Value::Int(3)));
+        assert_eq!(ObjectView::Cons(rest).to_value(), tail);
+        assert_eq!(Value::List(vec![]).view(), ObjectView::Nil);
+    }
+
+    #[test]
+    fn test_visitor() {
+        let value = Value::List(vec![
+            Value::Int(1),
+            Value::Vector(vec![Value::Int(2), Value::String("x".to_owned())]),
+            Value::DottedList(vec![Value::Int(3)], Box::new(Value::Int(4))),
+        ]);
+        assert_eq!(value.view().accept(&mut Sum), 10);
+        assert_eq!(value.view().to_value(), value);
+    }
+}
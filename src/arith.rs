@@ -1,9 +1,15 @@
 //! Arithmetic operators.
-use crate::core::object::{Gc, IntoObject, Number, NumberType, ObjectType};
+use crate::core::{
+    env::{sym, Env},
+    gc::Rt,
+    object::{Gc, IntoObject, Number, NumberType, ObjectType, MAX_FIXNUM, MIN_FIXNUM, NIL},
+};
+use crate::eval::EvalError;
+use anyhow::Result;
 use float_cmp::ApproxEq;
 use rune_macros::defun;
 use std::cmp::PartialEq;
-use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+use std::ops::{Add, Div, Mul, Rem, Sub};
 
 /// Similar to the object type [NumberType], but contains a float instead of a
 /// reference to a float. This makes it easier to construct and mutate.
@@ -48,38 +54,49 @@ fn arith(
     }
 }
 
+/// Like [`arith`], but `int_fn` returns `None` on overflow. Returns `None` if
+/// an integer result overflows or doesn't fit in a fixnum.
+fn checked_arith(
+    cur: NumberValue,
+    next: NumberValue,
+    int_fn: fn(i64, i64) -> Option<i64>,
+    float_fn: fn(f64, f64) -> f64,
+) -> Option<NumberValue> {
+    use NumberValue as N;
+    match (cur, next) {
+        (N::Int(l), N::Int(r)) => fixnum(int_fn(l, r)?).map(N::Int),
+        (N::Int(l), N::Float(r)) => Some(N::Float(float_fn(l as f64, r))),
+        (N::Float(l), N::Int(r)) => Some(N::Float(float_fn(l, r as f64))),
+        (N::Float(l), N::Float(r)) => Some(N::Float(float_fn(l, r))),
+    }
+}
+
+fn fixnum(x: i64) -> Option<i64> {
+    (MIN_FIXNUM..=MAX_FIXNUM).contains(&x).then_some(x)
+}
+
 //////////////////////////
 // Arithmetic operators //
 //////////////////////////
 
-impl Neg for NumberValue {
-    type Output = Self;
-    fn neg(self) -> Self::Output {
+impl NumberValue {
+    fn checked_neg(self) -> Option<Self> {
         match self {
-            NumberValue::Int(x) => NumberValue::Int(-x),
-            NumberValue::Float(x) => NumberValue::Float(-x),
+            NumberValue::Int(x) => fixnum(x.checked_neg()?).map(NumberValue::Int),
+            NumberValue::Float(x) => Some(NumberValue::Float(-x)),
         }
     }
-}
 
-impl Add for NumberValue {
-    type Output = Self;
-    fn add(self, rhs: Self) -> Self::Output {
-        arith(self, rhs, Add::add, Add::add)
+    fn checked_add(self, rhs: Self) -> Option<Self> {
+        checked_arith(self, rhs, i64::checked_add, Add::add)
     }
-}
 
-impl Sub for NumberValue {
-    type Output = Self;
-    fn sub(self, rhs: Self) -> Self::Output {
-        arith(self, rhs, Sub::sub, Sub::sub)
+    fn checked_sub(self, rhs: Self) -> Option<Self> {
+        checked_arith(self, rhs, i64::checked_sub, Sub::sub)
     }
-}
 
-impl Mul for NumberValue {
-    type Output = Self;
-    fn mul(self, rhs: Self) -> Self::Output {
-        arith(self, rhs, Mul::mul, Mul::mul)
+    fn checked_mul(self, rhs: Self) -> Option<Self> {
+        checked_arith(self, rhs, i64::checked_mul, Mul::mul)
     }
 }
 
@@ -130,29 +147,36 @@ impl PartialOrd for NumberValue {
     }
 }
 
-#[defun(name = "+")]
-pub(crate) fn add(vars: &[Number]) -> NumberValue {
-    vars.iter().fold(NumberValue::Int(0), |acc, x| acc + x.val())
+#[defun(name = "+", fast = "FastFn::Binary(i64::checked_add)")]
+pub(crate) fn add(vars: &[Number], env: &mut Rt<Env>) -> Result<NumberValue> {
+    let sum = vars.iter().try_fold(NumberValue::Int(0), |acc, x| acc.checked_add(x.val()));
+    sum.ok_or_else(|| overflow_error(env))
 }
 
-#[defun(name = "-")]
-pub(crate) fn sub(number: Option<Number>, numbers: &[Number]) -> NumberValue {
-    match number {
+#[defun(name = "-", fast = "FastFn::Binary(i64::checked_sub)")]
+pub(crate) fn sub(
+    number: Option<Number>,
+    numbers: &[Number],
+    env: &mut Rt<Env>,
+) -> Result<NumberValue> {
+    let result = match number {
         Some(num) => {
             let num = num.val();
             if numbers.is_empty() {
-                -num
+                num.checked_neg()
             } else {
-                numbers.iter().fold(num, |acc, x| acc - x.val())
+                numbers.iter().try_fold(num, |acc, x| acc.checked_sub(x.val()))
             }
         }
-        None => NumberValue::Int(0),
-    }
+        None => Some(NumberValue::Int(0)),
+    };
+    result.ok_or_else(|| overflow_error(env))
 }
 
-#[defun(name = "*")]
-pub(crate) fn mul(numbers: &[Number]) -> NumberValue {
-    numbers.iter().fold(NumberValue::Int(1), |acc, x| acc * x.val())
+#[defun(name = "*", fast = "FastFn::Binary(i64::checked_mul)")]
+pub(crate) fn mul(numbers: &[Number], env: &mut Rt<Env>) -> Result<NumberValue> {
+    let product = numbers.iter().try_fold(NumberValue::Int(1), |acc, x| acc.checked_mul(x.val()));
+    product.ok_or_else(|| overflow_error(env))
 }
 
 #[defun(name = "/")]
@@ -160,17 +184,17 @@ pub(crate) fn div(number: Number, divisors: &[Number]) -> NumberValue {
     divisors.iter().fold(number.val(), |acc, x| acc / x.val())
 }
 
-#[defun(name = "1+")]
-pub(crate) fn add_one(number: Number) -> NumberValue {
-    number.val() + NumberValue::Int(1)
+#[defun(name = "1+", fast = "FastFn::Unary(|x| x.checked_add(1))")]
+pub(crate) fn add_one(number: Number, env: &mut Rt<Env>) -> Result<NumberValue> {
+    number.val().checked_add(NumberValue::Int(1)).ok_or_else(|| overflow_error(env))
 }
 
-#[defun(name = "1-")]
-pub(crate) fn sub_one(number: Number) -> NumberValue {
-    number.val() - NumberValue::Int(1)
+#[defun(name = "1-", fast = "FastFn::Unary(|x| x.checked_sub(1))")]
+pub(crate) fn sub_one(number: Number, env: &mut Rt<Env>) -> Result<NumberValue> {
+    number.val().checked_sub(NumberValue::Int(1)).ok_or_else(|| overflow_error(env))
 }
 
-#[defun(name = "=")]
+#[defun(name = "=", fast = "FastFn::Compare(i64::eq)")]
 pub(crate) fn num_eq(number: Number, numbers: &[Number]) -> bool {
     match number.val() {
         NumberValue::Int(num) => numbers.iter().all(|&x| x == num),
@@ -178,7 +202,7 @@ pub(crate) fn num_eq(number: Number, numbers: &[Number]) -> bool {
     }
 }
 
-#[defun(name = "/=")]
+#[defun(name = "/=", fast = "FastFn::Compare(i64::ne)")]
 #[allow(clippy::float_cmp)] // This is a bug in clippy, we are not comparing floats directly
 pub(crate) fn num_ne(number: Number, numbers: &[Number]) -> bool {
     match number.val() {
@@ -194,45 +218,124 @@ fn cmp(number: Number, numbers: &[Number], cmp: fn(&NumberValue, &NumberValue) -
         .is_some()
 }
 
-#[defun(name = "<")]
+#[defun(name = "<", fast = "FastFn::Compare(i64::lt)")]
 pub(crate) fn less_than(number: Number, numbers: &[Number]) -> bool {
     cmp(number, numbers, NumberValue::lt)
 }
 
-#[defun(name = "<=")]
+#[defun(name = "<=", fast = "FastFn::Compare(i64::le)")]
 pub(crate) fn less_than_or_eq(number: Number, numbers: &[Number]) -> bool {
     cmp(number, numbers, NumberValue::le)
 }
 
-#[defun(name = ">")]
+#[defun(name = ">", fast = "FastFn::Compare(i64::gt)")]
 pub(crate) fn greater_than(number: Number, numbers: &[Number]) -> bool {
     cmp(number, numbers, NumberValue::gt)
 }
 
-#[defun(name = ">=")]
+#[defun(name = ">=", fast = "FastFn::Compare(i64::ge)")]
 pub(crate) fn greater_than_or_eq(number: Number, numbers: &[Number]) -> bool {
     cmp(number, numbers, NumberValue::ge)
 }
 
-#[defun]
+#[defun(fast = "FastFn::Binary(|x, y| Some(x | y))")]
 pub(crate) fn logior(ints_or_markers: &[Gc<i64>]) -> i64 {
     ints_or_markers.iter().fold(0, |acc, x| acc | x.untag())
 }
 
-#[defun]
+#[defun(fast = "FastFn::Binary(|x, y| Some(x & y))")]
 fn logand(int_or_markers: &[Gc<i64>]) -> i64 {
     int_or_markers.iter().fold(-1, |accum, x| accum & x.untag())
 }
 
+#[defun(fast = "FastFn::Binary(|x, y| Some(x ^ y))")]
+fn logxor(ints_or_markers: &[Gc<i64>]) -> i64 {
+    ints_or_markers.iter().fold(0, |acc, x| acc ^ x.untag())
+}
+
+#[defun(fast = "FastFn::Unary(|x| Some(!x))")]
+fn lognot(number: i64) -> i64 {
+    !number
+}
+
+defsym!(ARITH_ERROR);
+
+/// The error for an integer divided by zero.
+pub(crate) fn arith_error(env: &mut Rt<Env>) -> anyhow::Error {
+    EvalError::signal(sym::ARITH_ERROR.into(), NIL, env).into()
+}
+
+/// The error for an integer result that doesn't fit in a fixnum, since there
+/// are no bignums yet.
+pub(crate) fn overflow_error(env: &mut Rt<Env>) -> anyhow::Error {
+    EvalError::signal(sym::OVERFLOW_ERROR.into(), NIL, env).into()
+}
+
+/// Return X modulo Y. The result has the sign of Y, so it is the remainder
+/// of dividing X by Y and rounding down.
 #[defun(name = "mod")]
-pub(crate) fn modulo(x: Number, y: Number) -> NumberValue {
-    x.val() % y.val()
+pub(crate) fn modulo(x: Number, y: Number, env: &mut Rt<Env>) -> Result<NumberValue> {
+    // Only integer division by zero is an error, floats give NaN
+    if let (NumberValue::Int(_), NumberValue::Int(0)) = (x.val(), y.val()) {
+        return Err(arith_error(env));
+    }
+    let int_mod = |x: i64, y: i64| {
+        let rem = x.wrapping_rem(y);
+        if rem != 0 && (rem < 0) != (y < 0) {
+            rem + y
+        } else {
+            rem
+        }
+    };
+    let float_mod = |x: f64, y: f64| {
+        let rem = x % y;
+        if rem != 0.0 && (rem < 0.0) != (y < 0.0) {
+            rem + y
+        } else {
+            rem
+        }
+    };
+    Ok(arith(x.val(), y.val(), int_mod, float_mod))
 }
 
-#[defun(name = "%")]
-pub(crate) fn remainder(x: i64, y: i64) -> i64 {
+/// Return the remainder of X divided by Y. The result has the sign of X, so
+/// it is the remainder of dividing and rounding toward zero.
+#[defun(name = "%", fast = "FastFn::Binary(i64::checked_rem)")]
+pub(crate) fn remainder(x: i64, y: i64, env: &mut Rt<Env>) -> Result<i64> {
     // TODO: Handle markers
-    x % y
+    if y == 0 {
+        return Err(arith_error(env));
+    }
+    Ok(x.wrapping_rem(y))
+}
+
+/// Return VALUE with its bits shifted left by COUNT, or right if COUNT is
+/// negative. Shifting right rounds down. A result that doesn't fit in a
+/// fixnum signals `overflow-error`, since there are no bignums yet.
+#[defun]
+pub(crate) fn ash(value: i64, count: i64, env: &mut Rt<Env>) -> Result<i64> {
+    if count <= 0 {
+        // Shifting by 63 already leaves only the sign
+        return Ok(value >> count.unsigned_abs().min(63));
+    }
+    match u32::try_from(count) {
+        _ if value == 0 => Ok(0),
+        Ok(count) if count < 64 && (value << count) >> count == value => {
+            fixnum(value << count).ok_or_else(|| overflow_error(env))
+        }
+        _ => Err(overflow_error(env)),
+    }
+}
+
+/// Like `ash`, but a negative VALUE shifted right is treated as an unsigned
+/// fixnum. This is the same as the definition in `subr.el`, which replaces it
+/// once it is loaded.
+#[defun]
+fn lsh(value: i64, count: i64, env: &mut Rt<Env>) -> Result<i64> {
+    if value < 0 && count < 0 {
+        return ash((value >> 1) & MAX_FIXNUM, count + 1, env);
+    }
+    ash(value, count, env)
 }
 
 #[allow(clippy::trivially_copy_pass_by_ref)]
@@ -255,12 +358,12 @@ fn min_val(x: NumberValue, y: &Number) -> NumberValue {
     }
 }
 
-#[defun]
+#[defun(fast = "FastFn::Binary(|x, y| Some(x.max(y)))")]
 pub(crate) fn max(number_or_marker: Number, number_or_markers: &[Number]) -> NumberValue {
     number_or_markers.iter().fold(number_or_marker.val(), max_val)
 }
 
-#[defun]
+#[defun(fast = "FastFn::Binary(|x, y| Some(x.min(y)))")]
 pub(crate) fn min(number_or_marker: Number, number_or_markers: &[Number]) -> NumberValue {
     number_or_markers.iter().fold(number_or_marker.val(), min_val)
 }
@@ -269,30 +372,50 @@ pub(crate) fn min(number_or_marker: Number, number_or_markers: &[Number]) -> Num
 mod test {
     use super::*;
     use crate::core::gc::{Context, RootSet};
+    use rune_core::macros::root;
 
     #[test]
     fn test_add() {
         let roots = &RootSet::default();
-        let cx = &Context::new(roots);
-        assert_eq!(add(&[]), NumberValue::Int(0));
-        assert_eq!(add(&[7.into(), 13.into()]), NumberValue::Int(20));
-        assert_eq!(add(&[1.into(), cx.add_as(2.5)]), NumberValue::Float(3.5));
-        assert_eq!(add(&[0.into(), (-1).into()]), NumberValue::Int(-1));
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        assert_eq!(add(&[], env).unwrap(), NumberValue::Int(0));
+        assert_eq!(add(&[7.into(), 13.into()], env).unwrap(), NumberValue::Int(20));
+        assert_eq!(add(&[1.into(), cx.add_as(2.5)], env).unwrap(), NumberValue::Float(3.5));
+        assert_eq!(add(&[0.into(), (-1).into()], env).unwrap(), NumberValue::Int(-1));
+        assert!(add(&[MAX_FIXNUM.into(), 1.into()], env).is_err());
+        assert!(add_one(MAX_FIXNUM.into(), env).is_err());
     }
 
     #[test]
     fn test_sub() {
-        assert_eq!(sub(None, &[]), NumberValue::Int(0));
-        assert_eq!(sub(Some(7.into()), &[]), NumberValue::Int(-7));
-        assert_eq!(sub(Some(7.into()), &[13.into()]), NumberValue::Int(-6));
-        assert_eq!(sub(Some(0.into()), &[(-1).into()]), NumberValue::Int(1));
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        assert_eq!(sub(None, &[], env).unwrap(), NumberValue::Int(0));
+        assert_eq!(sub(Some(7.into()), &[], env).unwrap(), NumberValue::Int(-7));
+        assert_eq!(sub(Some(7.into()), &[13.into()], env).unwrap(), NumberValue::Int(-6));
+        assert_eq!(sub(Some(0.into()), &[(-1).into()], env).unwrap(), NumberValue::Int(1));
+        assert!(sub(Some(MIN_FIXNUM.into()), &[], env).is_err());
+        assert!(sub(Some(MIN_FIXNUM.into()), &[1.into()], env).is_err());
+        assert!(sub_one(MIN_FIXNUM.into(), env).is_err());
     }
 
     #[test]
     fn test_mul() {
-        assert_eq!(mul(&[]), NumberValue::Int(1));
-        assert_eq!(mul(&[7.into(), 13.into()]), NumberValue::Int(91));
-        assert_eq!(mul(&[(-1).into(), 1.into()]), NumberValue::Int(-1));
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        assert_eq!(mul(&[], env).unwrap(), NumberValue::Int(1));
+        assert_eq!(mul(&[7.into(), 13.into()], env).unwrap(), NumberValue::Int(91));
+        assert_eq!(mul(&[(-1).into(), 1.into()], env).unwrap(), NumberValue::Int(-1));
+        // Results that don't fit in a fixnum signal overflow-error, whether or
+        // not they fit in an i64
+        assert!(mul(&[MAX_FIXNUM.into(), 2.into()], env).is_err());
+        assert!(mul(&[MAX_FIXNUM.into(), MAX_FIXNUM.into()], env).is_err());
+        let big = MAX_FIXNUM as f64 * 2.0;
+        let product = mul(&[MAX_FIXNUM.into(), cx.add_as(2.0)], env).unwrap();
+        assert_eq!(product, NumberValue::Float(big));
     }
 
     #[test]
@@ -347,5 +470,51 @@ mod test {
         let roots = &RootSet::default();
         let cx = &Context::new(roots);
         assert_eq!(logand(&[258.into_obj(cx), 255.into_obj(cx)]), 2);
+        assert_eq!(logior(&[4.into_obj(cx), 1.into_obj(cx)]), 5);
+        assert_eq!(logxor(&[6.into_obj(cx), 3.into_obj(cx)]), 5);
+        assert_eq!(logxor(&[]), 0);
+        assert_eq!(lognot(5), -6);
+    }
+
+    #[test]
+    fn test_mod() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        assert_eq!(modulo((-7).into(), 3.into(), env).unwrap(), NumberValue::Int(2));
+        assert_eq!(modulo(7.into(), (-3).into(), env).unwrap(), NumberValue::Int(-2));
+        assert_eq!(modulo(6.into(), (-3).into(), env).unwrap(), NumberValue::Int(0));
+        let float = modulo(cx.add_as(-7.5), 2.into(), env).unwrap();
+        assert_eq!(float, NumberValue::Float(0.5));
+        assert!(modulo(1.into(), 0.into(), env).is_err());
+        let NumberValue::Float(nan) = modulo(cx.add_as(1.5), 0.into(), env).unwrap() else {
+            panic!("mod of a float should be a float")
+        };
+        assert!(nan.is_nan());
+        let NumberValue::Float(nan) = modulo(1.into(), cx.add_as(0.0), env).unwrap() else {
+            panic!("mod of a float should be a float")
+        };
+        assert!(nan.is_nan());
+        assert_eq!(remainder(-7, 3, env).unwrap(), -1);
+        assert_eq!(remainder(7, -3, env).unwrap(), 1);
+        assert!(remainder(1, 0, env).is_err());
+    }
+
+    #[test]
+    fn test_ash() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        assert_eq!(ash(4, 1, env).unwrap(), 8);
+        assert_eq!(ash(4, -1, env).unwrap(), 2);
+        assert_eq!(ash(-8, -1, env).unwrap(), -4);
+        assert_eq!(ash(256, -8, env).unwrap(), 1);
+        assert_eq!(ash(-8, 1, env).unwrap(), -16);
+        assert_eq!(ash(-7, -1, env).unwrap(), -4);
+        assert_eq!(ash(-1, -100, env).unwrap(), -1);
+        assert_eq!(ash(0, 100, env).unwrap(), 0);
+        assert!(ash(1, 60, env).is_err());
+        assert_eq!(lsh(-1, -1, env).unwrap(), MAX_FIXNUM);
+        assert_eq!(lsh(-8, 1, env).unwrap(), -16);
     }
 }
@@ -111,13 +111,9 @@ pub(crate) fn get_buffer_create<'ob>(
                 None => {
                     // If not already in the global buffer list, create a new
                     // buffer and add it
-                    let buffer: &'static _ = {
-                        let global = interned_symbols().lock().unwrap();
-                        let buffer = global.create_buffer(name);
-                        // SAFETY: This can be 'static because it is stored in the
-                        // global block. Eventually it will be garbage collected
-                        unsafe { &*(buffer as *const LispBuffer) }
-                    };
+                    // This can be 'static because it is stored in the global
+                    // block. Eventually it will be garbage collected
+                    let buffer: &'static _ = interned_symbols().create_buffer(name);
                     buffer_list.insert(name.to_string(), buffer);
                     Ok(cx.add(buffer))
                 }
@@ -158,7 +154,7 @@ pub(crate) fn get_buffer<'ob>(
 /// visible to users), then if buffer NAME already exists a random number
 /// is first appended to NAME, to speed up finding a non-existent buffer.
 #[defun]
-fn generate_new_buffer_name(name: &str, ignore: Option<&str>) -> String {
+pub(crate) fn generate_new_buffer_name(name: &str, ignore: Option<&str>) -> String {
     unique_buffer_name(name, ignore, &buffers().lock().unwrap())
 }
 
@@ -222,6 +218,10 @@ fn buffer_list<'ob>(_frame: Option<()>, cx: &'ob Context) -> Object<'ob> {
     slice_into_list(&buffer_list, None, cx)
 }
 
+/// Marks an entry on the binding stack that restores a buffer, from
+/// `save-excursion` or `save-current-buffer`.
+defsym!(INTERNAL__SAVED_BUFFER);
+
 // TODO: buffer local
 defvar!(FILL_COLUMN, 70);
 defvar!(INDENT_TABS_MODE);
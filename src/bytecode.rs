@@ -2,21 +2,31 @@
 //! The main bytecode interpeter.
 use crate::core::cons::Cons;
 use crate::core::env::{sym, CallFrame, Env};
-use crate::core::gc::{Context, IntoRoot, Rt, Rto, Slot};
+use crate::core::gc::{profiler, Context, IntoRoot, Rt, Rto, Slot};
+use crate::core::error::{Type, TypeError};
 use crate::core::object::{
-    ByteFn, ByteString, Function, FunctionType, Gc, LispVec, Object, ObjectType, Symbol,
-    WithLifetime, NIL,
+    function_epoch, ByteFn, ByteString, FastFn, Function, FunctionType, Gc, LispVec, Object,
+    ObjectType, RawObj, Record, RecordBuilder, Symbol, WithLifetime, MAX_FIXNUM, MIN_FIXNUM, NIL,
 };
 use crate::eval::{ErrorType, EvalError, EvalResult};
-use anyhow::{bail, Result};
-use rune_core::macros::{bail_err, rebind, root};
+use anyhow::{bail, ensure, Result};
+use rune_core::hashmap::HashMap;
+use rune_core::macros::{bail_err, list, rebind, root};
 use rune_macros::{defun, Trace};
 use sptr::Strict;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
 
+#[cfg(feature = "table-dispatch")]
+mod dispatch;
+mod fuse;
 mod opcode;
 
+pub(crate) use fuse::fuse;
+
 /// An program counter. This is implemented as a bound checked range pointer.
-// TODO: If the GC moves the bytecode, this will be invalid. We need to fix this
+// The op codes of a `ByteFn` are boxed outside of the GC heap, so they keep
+// their address when the function itself is moved by a collection.
 #[derive(Clone, Debug)]
 struct ProgramCounter {
     /// Valid range for this instruction pointer.
@@ -38,44 +48,117 @@ impl ProgramCounter {
         self.pc.addr() - self.range.start.addr()
     }
 
-    fn goto(&mut self, offset: u16) {
+    fn goto(&mut self, offset: u16) -> Result<()> {
+        let len = self.range.end.addr() - self.range.start.addr();
+        ensure!(usize::from(offset) < len, "Invalid bytecode: jump to {offset} past the end");
         unsafe {
             self.pc = self.range.start.add(offset as usize);
-            debug_assert!(self.range.contains(&self.pc));
         }
+        Ok(())
+    }
+
+    /// Fail if fewer than `n` bytes are left. Only malformed bytecode reads
+    /// past the end.
+    fn check(&self, n: usize) -> Result<()> {
+        let left = self.range.end.addr().saturating_sub(self.pc.addr());
+        ensure!(self.pc >= self.range.start && left >= n, "Invalid bytecode: read past the end");
+        Ok(())
     }
 
     /// Take the next byte in the stream
-    fn next(&mut self) -> u8 {
+    fn next(&mut self) -> Result<u8> {
+        self.check(1)?;
         unsafe {
-            debug_assert!(self.range.contains(&self.pc));
             let value = *self.pc;
             self.pc = self.pc.add(1);
-            value
+            Ok(value)
         }
     }
 
-    fn arg1(&mut self) -> u16 {
+    fn arg1(&mut self) -> Result<u16> {
+        self.check(1)?;
         unsafe {
-            debug_assert!(self.range.contains(&self.pc));
             let value = *self.pc;
             self.pc = self.pc.add(1);
-            if cfg!(feature = "debug_bytecode") && crate::debug::debug_enabled() {
-                println!("  arg: {value}");
-            }
-            value.into()
+            trace_event!(arg = value);
+            Ok(value.into())
         }
     }
 
-    fn arg2(&mut self) -> u16 {
+    fn arg2(&mut self) -> Result<u16> {
+        self.check(2)?;
         unsafe {
-            debug_assert!(self.range.contains(&self.pc.add(1)));
             let value = u16::from_le(self.pc.cast::<u16>().read_unaligned());
             self.pc = self.pc.add(2);
-            if cfg!(feature = "debug_bytecode") && crate::debug::debug_enabled() {
-                println!("  arg: {value}");
+            trace_event!(arg = value);
+            Ok(value)
+        }
+    }
+}
+
+/// The function last called by a call site, along with the symbol it was
+/// looked up from.
+#[derive(Clone, Copy)]
+struct CallCache {
+    symbol: RawObj,
+    func: RawObj,
+}
+
+/// Inline caches for calls through symbols, keyed by the address of the call
+/// instruction. Every entry was made in the same
+/// [function epoch](crate::core::object::function_epoch), and they are all
+/// dropped once it changes.
+#[derive(Default)]
+struct CallCaches {
+    epoch: usize,
+    sites: HashMap<usize, CallCache>,
+}
+
+thread_local! {
+    static CALL_CACHE: RefCell<CallCaches> = RefCell::new(CallCaches::default());
+
+    /// Opcodes and interpreted forms that can still run on this thread
+    /// before `excessive-eval` is signaled, or `None` for no limit. Every VM
+    /// and the interpreter draw from it, so nested calls share the budget.
+    static FUEL: Cell<Option<usize>> = const { Cell::new(None) };
+
+    /// If set, running out of fuel signals on every step until the limit is
+    /// changed, so a handler can't keep going. Otherwise it signals once and
+    /// the limit is removed.
+    static STICKY_FUEL: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Limit the opcodes and forms that can run on this thread to `fuel`, or
+/// remove the limit if it is `None`. See [`STICKY_FUEL`] for `sticky`.
+pub(crate) fn set_fuel(fuel: Option<usize>, sticky: bool) {
+    FUEL.set(fuel);
+    STICKY_FUEL.set(sticky);
+}
+
+/// The opcodes and forms that can still run on this thread, or `None` if
+/// there is no limit.
+pub(crate) fn remaining_fuel() -> Option<usize> {
+    FUEL.get()
+}
+
+/// Whether the current limit keeps signaling once it runs out.
+pub(crate) fn sticky_fuel() -> bool {
+    STICKY_FUEL.get()
+}
+
+/// Use up one step of fuel, signaling `excessive-eval` if there is none left.
+pub(crate) fn consume_fuel(env: &mut Rt<Env>) -> Result<(), EvalError> {
+    let Some(fuel) = FUEL.get() else { return Ok(()) };
+    match fuel.checked_sub(1) {
+        Some(fuel) => {
+            FUEL.set(Some(fuel));
+            Ok(())
+        }
+        None => {
+            if !STICKY_FUEL.get() {
+                FUEL.set(None);
             }
-            value
+            Err(EvalError::signal(sym::EXCESSIVE_EVAL.into(), NIL, env))
         }
     }
 }
@@ -90,6 +173,10 @@ struct Handler<'ob> {
     stack_size: usize,
     #[no_trace]
     stack_frame: usize,
+    /// The depth of the binding stack, which is unwound to when the handler
+    /// runs
+    #[no_trace]
+    bindings: usize,
     condition: Slot<Object<'ob>>,
 }
 
@@ -122,6 +209,23 @@ struct VM<'brw, 'env, 'rt> {
     /// The runtime environment
     #[no_trace]
     env: &'brw mut Rt<Env<'env>>,
+    /// Where the generator run by this VM started, if it is running one
+    #[no_trace]
+    generator: Option<GeneratorBase>,
+    /// The state of the generator when it last yielded, in the order of the
+    /// generator record slots from [`FUNC`] on
+    yielded: Vec<Slot<Object<'rt>>>,
+}
+
+/// The state of the environment when a generator was started or resumed. A
+/// generator can only yield if everything above it can be saved in the
+/// generator, which means no handlers or bindings were added since.
+#[derive(Clone, Copy)]
+struct GeneratorBase {
+    /// The stack frame of the generator function
+    frame: usize,
+    bindings: usize,
+    catches: usize,
 }
 
 impl<'brw, 'env> IntoRoot<VM<'brw, 'env, 'static>> for VM<'brw, 'env, '_> {
@@ -132,40 +236,47 @@ impl<'brw, 'env> IntoRoot<VM<'brw, 'env, 'static>> for VM<'brw, 'env, '_> {
 
 impl<'ob> RootedVM<'_, '_, '_> {
     fn varref(&mut self, idx: u16, cx: &'ob Context) -> Result<()> {
-        let symbol = self.get_const(idx as usize, cx);
-        if let ObjectType::Symbol(sym) = symbol.untag() {
-            let Some(var) = self.env.vars.get(sym) else { bail!("Void Variable: {sym}") };
-            let var = var.bind(cx);
-            self.env.stack.push(var);
-            Ok(())
-        } else {
-            unreachable!("Varref was not a symbol: {:?}", symbol);
-        }
+        let symbol: Symbol = self.get_const(idx as usize, cx)?.try_into()?;
+        let Some(var) = self.env.vars.get(symbol) else {
+            let data = list![symbol; cx];
+            return Err(EvalError::signal(sym::VOID_VARIABLE.into(), data, self.env).into());
+        };
+        let var = var.bind(cx);
+        self.env.stack.push(var);
+        Ok(())
     }
 
-    fn varset(&mut self, idx: usize, cx: &Context) -> Result<()> {
-        let obj = self.get_const(idx, cx);
-        let symbol: Symbol = obj.try_into()?;
-        let value = self.env.stack.pop(cx);
-        crate::data::set(symbol, value, self.env)?;
-        Ok(())
+    fn varset(&mut self, idx: usize, cx: &mut Context) -> Result<()> {
+        let symbol: Symbol = self.get_const(idx, cx)?.try_into()?;
+        let value = self.env.stack.pop(cx)?;
+        root!(symbol, cx);
+        root!(value, cx);
+        crate::data::set_var(symbol, value, self.env, cx)
     }
 
-    fn varbind(&mut self, idx: u16, cx: &'ob Context) {
-        let value = self.env.stack.pop(cx);
-        let symbol = self.get_const(idx as usize, cx);
-        let ObjectType::Symbol(sym) = symbol.untag() else {
-            unreachable!("Varbind was not a symbol: {:?}", symbol)
-        };
-        self.env.varbind(sym, value, cx);
+    fn varbind(&mut self, idx: u16, cx: &mut Context) -> Result<()> {
+        let value = self.env.stack.pop(cx)?;
+        let symbol: Symbol = self.get_const(idx as usize, cx)?.try_into()?;
+        root!(symbol, cx);
+        root!(value, cx);
+        crate::data::varbind(symbol, value, self.env, cx)
+    }
+
+    fn unbind(&mut self, idx: u16, cx: &mut Context) -> Result<()> {
+        crate::data::unbind(idx, self.env, cx)
     }
 
-    fn unbind(&mut self, idx: u16, cx: &'ob Context) {
-        self.env.unbind(idx, cx);
+    /// Undo bindings until there are `depth` left.
+    fn unbind_to(&mut self, depth: usize, cx: &mut Context) -> Result<()> {
+        let count = self.env.binding_depth() - depth;
+        crate::data::unbind(count.try_into().expect("too many bindings"), self.env, cx)
     }
 
-    fn get_const(&self, i: usize, cx: &'ob Context) -> Object<'ob> {
-        *self.func.bind(cx).consts().get(i).expect("constant had invalid index")
+    fn get_const(&self, i: usize, cx: &'ob Context) -> Result<Object<'ob>> {
+        match self.func.bind(cx).consts().get(i) {
+            Some(x) => Ok(*x),
+            None => bail!("Invalid bytecode: no constant at index {i}"),
+        }
     }
 
     fn set_current_frame(&mut self, f: &ByteFn, offset: usize) {
@@ -186,11 +297,6 @@ impl<'ob> RootedVM<'_, '_, '_> {
         }
     }
 
-    #[inline(always)]
-    fn debug_enabled() -> bool {
-        cfg!(test) || (cfg!(feature = "debug_bytecode") && crate::debug::debug_enabled())
-    }
-
     /// Prepare the arguments for lisp function call. This means filling all
     /// needed stack slots with `nil` and moving all the `&rest` arguments into
     /// a list.
@@ -221,14 +327,76 @@ impl<'ob> RootedVM<'_, '_, '_> {
         Ok(())
     }
 
-    fn call(&mut self, arg_cnt: u16, cx: &'ob mut Context) -> Result<(), EvalError> {
+    /// Find the function called through `symbol` at the current call site, if
+    /// it is bytecode.
+    fn resolve_call(&self, symbol: Symbol, cx: &'ob Context) -> Option<&'ob ByteFn> {
+        // Uninterned symbols can be moved or freed, so their address can't
+        // identify them
+        if !symbol.interned() {
+            return None;
+        }
+        let site = self.pc.pc.addr();
+        let epoch = function_epoch();
+        let raw_symbol = Object::from(symbol).into_raw();
+        let cached = CALL_CACHE.with_borrow_mut(|cache| {
+            // The functions have changed since the entries were made. Drop
+            // them rather than checking each one, so sites of code that is no
+            // longer called don't pile up.
+            if cache.epoch != epoch {
+                cache.sites.clear();
+                cache.epoch = epoch;
+            }
+            cache.sites.get(&site).copied()
+        });
+        if let Some(entry) = cached.filter(|x| x.symbol == raw_symbol) {
+            // SAFETY: Functions are stored in the global block, which is never
+            // collected. The epoch has not changed, so this is still the
+            // function of the symbol.
+            let func: Object<'ob> = unsafe { Object::from_raw(entry.func) };
+            let ObjectType::ByteFn(func) = func.untag() else { unreachable!() };
+            return Some(func);
+        }
+        let func = symbol.follow_indirect(cx)?;
+        let FunctionType::ByteFn(func) = func.untag() else { return None };
+        let entry = CallCache { symbol: raw_symbol, func: Object::from(func).into_raw() };
+        CALL_CACHE.with_borrow_mut(|cache| cache.sites.insert(site, entry));
+        Some(func)
+    }
+
+    /// Call the function below the top `arg_cnt` arguments. Returns true if
+    /// it was `generator-yield` and the generator was suspended.
+    fn call(&mut self, arg_cnt: u16, cx: &'ob mut Context) -> Result<bool, EvalError> {
         let arg_cnt = usize::from(arg_cnt);
+        if self.env.stack.frame_len() <= arg_cnt {
+            bail_err!("Invalid bytecode: call with {arg_cnt} arguments underflows the stack");
+        }
         let func: Function = self.env.stack[arg_cnt].bind(cx).try_into()?;
-        let name = match func.untag() {
-            FunctionType::Symbol(x) => x.name().to_owned(),
-            _ => String::from("lambda"),
+        if self.generator.is_some()
+            && matches!(func.untag(), FunctionType::Symbol(sym::GENERATOR_YIELD))
+        {
+            self.suspend_generator(arg_cnt, cx)?;
+            return Ok(true);
+        }
+        if self.call_fast(func, arg_cnt, cx)? {
+            return Ok(false);
+        }
+        // Most calls are to interned symbols, so only uninterned names are
+        // copied
+        let (name, next_fn) = match func.untag() {
+            FunctionType::Symbol(x) => {
+                let name = match x.interned_name() {
+                    Some(name) => Cow::Borrowed(name),
+                    None => Cow::Owned(x.name().to_owned()),
+                };
+                (name, self.resolve_call(x, cx))
+            }
+            FunctionType::ByteFn(f) => (Cow::Borrowed("lambda"), Some(f)),
+            _ => (Cow::Borrowed("lambda"), None),
         };
-        if let FunctionType::ByteFn(next_fn) = func.untag() {
+        if let Some(next_fn) = next_fn {
+            // The frame is pushed without going through `Function::call`, so
+            // the limits of a sandbox are checked here
+            crate::sandbox::check_call(next_fn.into(), cx)?;
             // If bytecode, add another frame and resume execution.
             // OpCode::Return will remove the call frame.
             let len = self.env.stack.len();
@@ -239,6 +407,7 @@ impl<'ob> RootedVM<'_, '_, '_> {
             self.env
                 .stack
                 .push_bytecode_frame(frame_start, next_fn.depth, prev_fn, pc_offset);
+            profiler::enter_function(self.env.stack.current_frame(), &name);
             self.prepare_lisp_args(next_fn, arg_cnt, &name, cx)?;
         } else {
             // Otherwise, call the function directly.
@@ -246,623 +415,832 @@ impl<'ob> RootedVM<'_, '_, '_> {
             root!(func, cx);
             let result = func.call(&mut frame, Some(&name), cx)?;
             drop(frame); // removes the arguments from the stack
-            self.env.stack.top().set(result);
+            self.env.stack.top()?.set(result);
             cx.garbage_collect(false);
         }
+        Ok(false)
+    }
+
+    /// Call a builtin through its [`FastFn`] if it has one and the top
+    /// `arg_cnt` arguments are all fixnums. Returns false if the function has
+    /// to be called the generic way.
+    fn call_fast(&mut self, func: Function, arg_cnt: usize, cx: &Context) -> Result<bool> {
+        let func = match func.untag() {
+            FunctionType::Symbol(sym) => match sym.follow_indirect(cx) {
+                Some(func) => func,
+                None => return Ok(false),
+            },
+            _ => func,
+        };
+        let FunctionType::SubrFn(subr) = func.untag() else { return Ok(false) };
+        let Some(fast) = subr.fast else { return Ok(false) };
+        if fast.arg_cnt() != arg_cnt {
+            return Ok(false);
+        }
+        crate::sandbox::check_call(func, cx)?;
+        let mut args = [0; 2];
+        for (i, arg) in args[..arg_cnt].iter_mut().enumerate() {
+            // The first argument is the deepest on the stack
+            let ObjectType::Int(x) = self.env.stack[arg_cnt - 1 - i].bind(cx).untag() else {
+                return Ok(false);
+            };
+            *arg = x;
+        }
+        let fixnum = |x: Option<i64>| x.filter(|x| (MIN_FIXNUM..=MAX_FIXNUM).contains(x));
+        let result = match fast {
+            FastFn::Unary(func) => fixnum(func(args[0])).map(|x| cx.add(x)),
+            FastFn::Binary(func) => fixnum(func(args[0], args[1])).map(|x| cx.add(x)),
+            FastFn::Compare(func) => Some(func(&args[0], &args[1]).into()),
+        };
+        let Some(result) = result else { return Ok(false) };
+        // The result replaces the function below the arguments
+        self.env.stack.remove_top(arg_cnt);
+        self.env.stack.top()?.set(result);
+        Ok(true)
+    }
+
+    /// Save everything above the generator frame in `yielded`, along with the
+    /// value being yielded.
+    fn suspend_generator(&mut self, arg_cnt: usize, cx: &'ob Context) -> Result<()> {
+        let base = self.generator.expect("should only suspend a generator");
+        ensure!(arg_cnt <= 1, "generator-yield takes at most one argument");
+        ensure!(
+            self.handlers.is_empty()
+                && self.env.binding_depth() == base.bindings
+                && self.env.catch_stack.len() == base.catches,
+            "Can't yield from inside condition-case, catch, or a dynamic binding"
+        );
+        let value = if arg_cnt == 1 { self.env.stack.pop(cx)? } else { NIL };
+        // generator-yield itself
+        self.env.stack.pop(cx)?;
+        let (values, frames) = self.env.stack.suspend(base.frame, cx);
+        let state: [Object; 5] = [
+            self.func.bind(cx).into(),
+            self.pc.as_offset().into(),
+            cx.add(values),
+            cx.add(frames),
+            value,
+        ];
+        self.yielded.extend_from_slice(&state);
         Ok(())
     }
 
     fn run(&mut self, cx: &'ob mut Context) -> EvalResult<'ob> {
+        let depth = self.env.binding_depth();
+        let handlers = self.env.handlers.len();
         'main: loop {
             let err = match self.execute_bytecode(cx) {
                 Ok(x) => return Ok(rebind!(x, cx)),
                 Err(e) => e,
             };
 
-            // we will fix this once we can handle different error types
-            #[allow(clippy::never_loop)]
+            // handler-bind handlers run before anything is unwound. After that
+            // only the handlers of this VM are searched.
+            let err = crate::eval::run_handlers(err, self.env, cx);
+            self.env.handlers.truncate(handlers);
+            let Some((symbol, data)) = err.condition(self.env, cx) else {
+                // A watcher that signals while unwinding doesn't replace the
+                // error
+                let _ = self.unbind_to(depth, cx);
+                return Err(err);
+            };
+            let error: Object = Cons::new(symbol, data, cx).into();
+            root!(error, cx);
             while let Some(handler) = self.handlers.bind_mut(cx).pop() {
-                match handler.condition.untag() {
-                    ObjectType::Symbol(sym::ERROR) => {}
-                    ObjectType::Cons(conditions) => {
-                        for condition in conditions {
-                            let condition = condition?;
-                            // TODO: Handle different error symbols
-                            if condition != sym::DEBUG && condition != sym::ERROR {
-                                bail_err!("non-error conditions {condition} not yet supported")
-                            }
-                        }
-                    }
-                    x => bail_err!("Invalid condition handler: {x}"),
+                let conditions = *handler.condition;
+                if !matches!(conditions.untag(), ObjectType::Symbol(_) | ObjectType::Cons(_)) {
+                    bail_err!("Invalid condition handler: {conditions}")
                 }
-
-                let error = if let EvalError { error: ErrorType::Signal(id), .. } = err {
-                    let Some((sym, data)) = self.env.get_exception(id) else {
-                        unreachable!("Exception not found")
-                    };
-                    Cons::new(sym, data, cx)
-                } else {
-                    // TODO: Need to remove the anyhow branch once
-                    // full errors are implemented
-                    Cons::new(sym::ERROR, format!("{err}"), cx)
-                };
+                if !crate::eval::handles(conditions, symbol, self.env, cx)? {
+                    continue;
+                }
+                let (bindings, jump_code) = (handler.bindings, handler.jump_code);
                 self.unwind(handler.stack_frame, cx);
                 self.env.stack.truncate(handler.stack_size);
-                self.env.stack.push(Object::from(error));
-                self.pc.goto(handler.jump_code);
+                if let Err(e) = self.unbind_to(bindings, cx) {
+                    let _ = self.unbind_to(depth, cx);
+                    return Err(e.into());
+                }
+                for outer in self.handlers.iter() {
+                    self.env.handlers.push((outer.condition.bind(cx), NIL));
+                }
+                self.env.stack.push(error.bind(cx));
+                self.pc.goto(jump_code)?;
                 continue 'main;
             }
+            // Bindings and saved buffers are undone even if nothing handled it
+            let _ = self.unbind_to(depth, cx);
             return Err(err);
         }
     }
 
-    #[allow(clippy::too_many_lines)]
     /// The main bytecode execution loop.
+    ///
+    /// By default each instruction is run by [`execute_op`](Self::execute_op),
+    /// where the opcodes are dense, so the match compiles to a single jump
+    /// table. With the `table-dispatch` feature each opcode has its own copy
+    /// of the handler instead, called by the raw byte through
+    /// [`dispatch::TABLE`]. Threaded dispatch, where each handler jumps
+    /// straight to the next one, needs guaranteed tail calls, which stable
+    /// Rust doesn't have.
     fn execute_bytecode(&mut self, cx: &'ob mut Context) -> EvalResult<'ob> {
+        loop {
+            consume_fuel(self.env)?;
+            let code = self.pc.next()?;
+            #[cfg(not(feature = "table-dispatch"))]
+            let result = self.execute_op(code, cx)?;
+            #[cfg(feature = "table-dispatch")]
+            let result = dispatch::TABLE[usize::from(code)](self, cx)?;
+            if let Some(value) = result {
+                return Ok(rebind!(value, cx));
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_lines)]
+    /// Run the instruction `code`, whose opcode was just read. Returns the
+    /// value to return from [`execute_bytecode`](Self::execute_bytecode), if
+    /// it should stop.
+    #[cfg_attr(feature = "table-dispatch", inline(always))]
+    fn execute_op(
+        &mut self,
+        code: u8,
+        cx: &'ob mut Context,
+    ) -> Result<Option<Object<'ob>>, EvalError> {
         use crate::{alloc, arith, data, fns};
         use opcode::OpCode as op;
-        loop {
-            let op = match self.pc.next().try_into() {
-                Ok(x) => x,
-                Err(e) => panic!("Invalid Bytecode: {e}"),
-            };
+        let op = match code.try_into() {
+            Ok(x) => x,
+            Err(e) => bail_err!("Invalid bytecode: {e}"),
+        };
 
-            if Self::debug_enabled() {
-                println!("[");
-                for (idx, x) in self.env.stack.frames().iter().rev().enumerate() {
-                    println!("    {idx}: {x},");
-                }
-                println!("]");
-                let byte_offset = self.pc.pc as i64 - self.pc.range.start as i64 - 1;
-                println!("op :{byte_offset}: {op:?}");
-            }
-            match op {
-                op::StackRef0 => self.env.stack.push_ref(0, cx),
-                op::StackRef1 => self.env.stack.push_ref(1, cx),
-                op::StackRef2 => self.env.stack.push_ref(2, cx),
-                op::StackRef3 => self.env.stack.push_ref(3, cx),
-                op::StackRef4 => self.env.stack.push_ref(4, cx),
-                op::StackRef5 => self.env.stack.push_ref(5, cx),
-                op::StackRefN => {
-                    let idx = self.pc.arg1();
-                    self.env.stack.push_ref(idx, cx);
-                }
-                op::StackRefN2 => {
-                    let idx = self.pc.arg2();
-                    self.env.stack.push_ref(idx, cx);
-                }
-                op::StackSetN => {
-                    let idx = self.pc.arg1();
-                    self.env.stack.set_ref(idx);
-                }
-                op::StackSetN2 => {
-                    let idx = self.pc.arg2();
-                    self.env.stack.set_ref(idx);
-                }
-                op::VarRef0 => self.varref(0, cx)?,
-                op::VarRef1 => self.varref(1, cx)?,
-                op::VarRef2 => self.varref(2, cx)?,
-                op::VarRef3 => self.varref(3, cx)?,
-                op::VarRef4 => self.varref(4, cx)?,
-                op::VarRef5 => self.varref(5, cx)?,
-                op::VarRefN => {
-                    let idx = self.pc.arg1();
-                    self.varref(idx, cx)?;
-                }
-                op::VarRefN2 => {
-                    let idx = self.pc.arg2();
-                    self.varref(idx, cx)?;
-                }
-                op::VarSet0 => self.varset(0, cx)?,
-                op::VarSet1 => self.varset(1, cx)?,
-                op::VarSet2 => self.varset(2, cx)?,
-                op::VarSet3 => self.varset(3, cx)?,
-                op::VarSet4 => self.varset(4, cx)?,
-                op::VarSet5 => self.varset(5, cx)?,
-                op::VarSetN => {
-                    let idx = self.pc.arg1();
-                    self.varset(idx.into(), cx)?;
-                }
-                op::VarSetN2 => {
-                    let idx = self.pc.arg2();
-                    self.varset(idx.into(), cx)?;
-                }
-                op::VarBind0 => self.varbind(0, cx),
-                op::VarBind1 => self.varbind(1, cx),
-                op::VarBind2 => self.varbind(2, cx),
-                op::VarBind3 => self.varbind(3, cx),
-                op::VarBind4 => self.varbind(4, cx),
-                op::VarBind5 => self.varbind(5, cx),
-                op::VarBindN => {
-                    let idx = self.pc.arg1();
-                    self.varbind(idx, cx);
-                }
-                op::VarBindN2 => {
-                    let idx = self.pc.arg2();
-                    self.varbind(idx, cx);
-                }
-                op::Call0 => self.call(0, cx)?,
-                op::Call1 => self.call(1, cx)?,
-                op::Call2 => self.call(2, cx)?,
-                op::Call3 => self.call(3, cx)?,
-                op::Call4 => self.call(4, cx)?,
-                op::Call5 => self.call(5, cx)?,
-                op::CallN => {
-                    let idx = self.pc.arg1();
-                    self.call(idx, cx)?;
-                }
-                op::CallN2 => {
-                    let idx = self.pc.arg2();
-                    self.call(idx, cx)?;
-                }
-                op::Unbind0 => self.unbind(0, cx),
-                op::Unbind1 => self.unbind(1, cx),
-                op::Unbind2 => self.unbind(2, cx),
-                op::Unbind3 => self.unbind(3, cx),
-                op::Unbind4 => self.unbind(4, cx),
-                op::Unbind5 => self.unbind(5, cx),
-                op::UnbindN => {
-                    let idx = self.pc.arg1();
-                    self.unbind(idx, cx);
-                }
-                op::UnbindN2 => {
-                    let idx = self.pc.arg2();
-                    self.unbind(idx, cx);
-                }
-                op::PopHandler => {
-                    self.handlers.pop();
-                }
-                op::PushCondtionCase => {
-                    // pop before getting stack size
-                    let condition = self.env.stack.pop(cx);
-                    let handler = Handler {
-                        jump_code: self.pc.arg2(),
-                        stack_size: self.env.stack.len(),
-                        stack_frame: self.env.stack.current_frame(),
-                        condition: Slot::new(condition),
-                    };
-                    self.handlers.push(handler);
-                }
-                op::PushCatch => todo!("PushCatch bytecode"),
-                op::Nth => {
-                    let list = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    top.set(fns::nth(top.bind_as(cx)?, list.try_into()?)?);
-                }
-                op::Symbolp => {
-                    let top = self.env.stack.top();
-                    top.set(data::symbolp(top.bind(cx)));
-                }
-                op::Consp => {
-                    let top = self.env.stack.top();
-                    top.set(data::consp(top.bind(cx)));
-                }
-                op::Stringp => {
-                    let top = self.env.stack.top();
-                    top.set(data::stringp(top.bind(cx)));
-                }
-                op::Listp => {
-                    let top = self.env.stack.top();
-                    top.set(data::listp(top.bind(cx)));
-                }
-                op::Eq => {
-                    let v1 = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    top.set(fns::eq(top.bind(cx), v1));
-                }
-                op::Memq => {
-                    let list = self.env.stack.pop(cx);
-                    let elt = self.env.stack.top();
-                    elt.set(fns::memq(elt.bind(cx), list.try_into()?)?);
-                }
-                op::Not => {
-                    let top = self.env.stack.top();
-                    top.set(data::null(top.bind(cx)));
-                }
-                op::Car => {
-                    let top = self.env.stack.top();
-                    top.set(data::car(top.bind_as(cx)?));
-                }
-                op::Cdr => {
-                    let top = self.env.stack.top();
-                    top.set(data::cdr(top.bind_as(cx)?));
-                }
-                op::Cons => {
-                    let cdr = self.env.stack.pop(cx);
-                    let car = self.env.stack.top();
-                    car.set(data::cons(car.bind(cx), cdr, cx));
-                }
-                op::List1 => {
-                    let top = self.env.stack.top();
-                    top.set(alloc::list(&[top.bind(cx)], cx));
-                }
-                op::List2 => {
-                    let a2 = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    top.set(alloc::list(&[top.bind(cx), a2], cx));
-                }
-                op::List3 => {
-                    let a3 = self.env.stack.pop(cx);
-                    let a2 = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    top.set(alloc::list(&[top.bind(cx), a2, a3], cx));
-                }
-                op::List4 => {
-                    let a4 = self.env.stack.pop(cx);
-                    let a3 = self.env.stack.pop(cx);
-                    let a2 = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    top.set(alloc::list(&[top.bind(cx), a2, a3, a4], cx));
-                }
-                op::Length => {
-                    let top = self.env.stack.top();
-                    top.set(fns::length(top.bind(cx))? as i64);
-                }
-                op::Aref => {
-                    let idx = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    top.set(data::aref(top.bind(cx), idx.try_into()?, cx)?);
-                }
-                op::Aset => {
-                    let newlet = self.env.stack.pop(cx);
-                    let idx = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    top.set(data::aset(top.bind(cx), idx.try_into()?, newlet)?);
-                }
-                op::SymbolValue => {
-                    let top = self.env.stack.top().bind_as(cx)?;
-                    let value = data::symbol_value(top, self.env, cx).unwrap_or_default();
-                    self.env.stack.top().set(value);
-                }
-                op::SymbolFunction => {
-                    let top = self.env.stack.top();
-                    top.set(data::symbol_function(top.bind_as(cx)?, cx));
-                }
-                op::Set => {
-                    let newlet = self.env.stack.pop(cx);
-                    let top = self.env.stack.top().bind_as(cx)?;
-                    let value = data::set(top, newlet, self.env)?;
-                    self.env.stack.top().set(value);
-                }
-                op::Fset => {
-                    let def = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    top.set::<Object>(data::fset(top.bind_as(cx)?, def)?.into());
-                }
-                op::Get => {
-                    let prop = self.env.stack.pop(cx).try_into()?;
-                    let top = self.env.stack.top().bind_as(cx)?;
-                    let value = data::get(top, prop, self.env, cx);
-                    self.env.stack.top().set(value);
-                }
-                op::Substring => todo!("Substring bytecode"),
-                op::Concat2 => todo!("Concat2 bytecode"),
-                op::Concat3 => todo!("Concat3 bytecode"),
-                op::Concat4 => todo!("Concat4 bytecode"),
-                op::Sub1 => {
-                    let top = self.env.stack.top();
-                    top.set(cx.add(arith::sub_one(top.bind_as(cx)?)));
-                }
-                op::Add1 => {
-                    let top = self.env.stack.top();
-                    top.set(cx.add(arith::add_one(top.bind_as(cx)?)));
-                }
-                op::EqlSign => {
-                    let rhs = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    top.set::<Object>(arith::num_eq(top.bind_as(cx)?, &[rhs.try_into()?]).into());
-                }
-                op::GreaterThan => {
-                    let v1 = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    top.set(arith::greater_than(top.bind_as(cx)?, &[v1.try_into()?]));
-                }
-                op::LessThan => {
-                    let v1 = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    top.set(arith::less_than(top.bind_as(cx)?, &[v1.try_into()?]));
-                }
-                op::LessThanOrEqual => {
-                    let v1 = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    top.set(arith::less_than_or_eq(top.bind_as(cx)?, &[v1.try_into()?]));
-                }
-                op::GreaterThanOrEqual => {
-                    let v1 = &[self.env.stack.pop(cx).try_into()?];
-                    let top = self.env.stack.top();
-                    top.set(arith::greater_than_or_eq(top.bind_as(cx)?, v1));
+        trace_event!(
+            offset = self.pc.pc as i64 - self.pc.range.start as i64 - 1,
+            depth = self.env.stack.len(),
+            ?op,
+        );
+        match op {
+            op::StackRef0 => self.env.stack.push_ref(0, cx),
+            op::StackRef1 => self.env.stack.push_ref(1, cx),
+            op::StackRef2 => self.env.stack.push_ref(2, cx),
+            op::StackRef3 => self.env.stack.push_ref(3, cx),
+            op::StackRef4 => self.env.stack.push_ref(4, cx),
+            op::StackRef5 => self.env.stack.push_ref(5, cx),
+            op::StackRefN => {
+                let idx = self.pc.arg1()?;
+                self.env.stack.push_ref(idx, cx);
+            }
+            op::StackRefN2 => {
+                let idx = self.pc.arg2()?;
+                self.env.stack.push_ref(idx, cx);
+            }
+            op::StackSetN => {
+                let idx = self.pc.arg1()?;
+                self.env.stack.set_ref(idx);
+            }
+            op::StackSetN2 => {
+                let idx = self.pc.arg2()?;
+                self.env.stack.set_ref(idx);
+            }
+            op::VarRef0 => self.varref(0, cx)?,
+            op::VarRef1 => self.varref(1, cx)?,
+            op::VarRef2 => self.varref(2, cx)?,
+            op::VarRef3 => self.varref(3, cx)?,
+            op::VarRef4 => self.varref(4, cx)?,
+            op::VarRef5 => self.varref(5, cx)?,
+            op::VarRefN => {
+                let idx = self.pc.arg1()?;
+                self.varref(idx, cx)?;
+            }
+            op::VarRefN2 => {
+                let idx = self.pc.arg2()?;
+                self.varref(idx, cx)?;
+            }
+            op::VarSet0 => self.varset(0, cx)?,
+            op::VarSet1 => self.varset(1, cx)?,
+            op::VarSet2 => self.varset(2, cx)?,
+            op::VarSet3 => self.varset(3, cx)?,
+            op::VarSet4 => self.varset(4, cx)?,
+            op::VarSet5 => self.varset(5, cx)?,
+            op::VarSetN => {
+                let idx = self.pc.arg1()?;
+                self.varset(idx.into(), cx)?;
+            }
+            op::VarSetN2 => {
+                let idx = self.pc.arg2()?;
+                self.varset(idx.into(), cx)?;
+            }
+            op::VarBind0 => self.varbind(0, cx)?,
+            op::VarBind1 => self.varbind(1, cx)?,
+            op::VarBind2 => self.varbind(2, cx)?,
+            op::VarBind3 => self.varbind(3, cx)?,
+            op::VarBind4 => self.varbind(4, cx)?,
+            op::VarBind5 => self.varbind(5, cx)?,
+            op::VarBindN => {
+                let idx = self.pc.arg1()?;
+                self.varbind(idx, cx)?;
+            }
+            op::VarBindN2 => {
+                let idx = self.pc.arg2()?;
+                self.varbind(idx, cx)?;
+            }
+            op::Call0 => {
+                if self.call(0, cx)? {
+                    return Ok(Some(NIL));
                 }
-                op::Diff => todo!("Diff bytecode"),
-                op::Negate => {
-                    let top = self.env.stack.top();
-                    top.set(cx.add(arith::sub(top.bind_as(cx)?, &[])));
+            }
+            op::Call1 => {
+                if self.call(1, cx)? {
+                    return Ok(Some(NIL));
                 }
-                op::Plus => {
-                    let arg1 = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    let args = &[top.bind_as(cx)?, arg1.try_into()?];
-                    top.set(cx.add(arith::add(args)));
+            }
+            op::ConstantCall1 => {
+                let idx = self.pc.arg1()?;
+                let cnst = self.get_const(idx as usize, cx)?;
+                self.env.stack.push(cnst);
+                if self.call(1, cx)? {
+                    return Ok(Some(NIL));
                 }
-                op::Max => {
-                    let arg1 = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    let args = &[arg1.try_into()?];
-                    top.set(cx.add(arith::max(top.bind_as(cx)?, args)));
+            }
+            op::Call2 => {
+                if self.call(2, cx)? {
+                    return Ok(Some(NIL));
                 }
-                op::Min => {
-                    let arg1 = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    let args = &[arg1.try_into()?];
-                    top.set(cx.add(arith::min(top.bind_as(cx)?, args)));
+            }
+            op::Call3 => {
+                if self.call(3, cx)? {
+                    return Ok(Some(NIL));
                 }
-                op::Multiply => {
-                    let arg1 = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    let args = &[top.bind_as(cx)?, arg1.try_into()?];
-                    top.set(cx.add(arith::mul(args)));
+            }
+            op::Call4 => {
+                if self.call(4, cx)? {
+                    return Ok(Some(NIL));
                 }
-                op::Point => todo!("Point bytecode"),
-                op::GotoChar => todo!("GotoChar bytecode"),
-                op::Insert => todo!("Insert bytecode"),
-                op::PointMax => todo!("PointMax bytecode"),
-                op::PointMin => todo!("PointMin bytecode"),
-                op::CharAfter => todo!("CharAfter bytecode"),
-                op::FollowingChar => todo!("FollowingChar bytecode"),
-                op::PrecedingChar => todo!("PrecedingChar bytecode"),
-                op::CurrentColumn => todo!("CurrentColumn bytecode"),
-                op::IndentTo => todo!("IndentTo bytecode"),
-                op::EndOfLineP => todo!("EndOfLineP bytecode"),
-                op::EndOfBufferP => todo!("EndOfBufferP bytecode"),
-                op::BeginningOfLineP => todo!("BeginningOfLineP bytecode"),
-                op::BeginningOfBufferP => todo!("BeginningOfBufferP bytecode"),
-                op::CurrentBuffer => todo!("CurrentBuffer bytecode"),
-                op::SetBuffer => todo!("SetBuffer bytecode"),
-                op::SaveCurrentBuffer1 => todo!("SaveCurrentBuffer1 bytecode"),
-                op::ForwardChar => todo!("ForwardChar bytecode"),
-                op::ForwardWord => todo!("ForwardWord bytecode"),
-                op::SkipCharsForward => todo!("SkipCharsForward bytecode"),
-                op::SkipCharsBackward => todo!("SkipCharsBackward bytecode"),
-                op::ForwardLine => todo!("ForwardLine bytecode"),
-                op::CharSyntax => todo!("CharSyntax bytecode"),
-                op::BufferSubstring => todo!("BufferSubstring bytecode"),
-                op::DeleteRegion => todo!("DeleteRegion bytecode"),
-                op::NarrowToRegion => todo!("NarrowToRegion bytecode"),
-                op::Widen => todo!("Widen bytecode"),
-                op::EndOfLine => todo!("EndOfLine bytecode"),
-                op::ConstantN2 => {
-                    let idx = self.pc.arg2();
-                    let cnst = self.get_const(idx.into(), cx);
-                    self.env.stack.push(cnst);
+            }
+            op::Call5 => {
+                if self.call(5, cx)? {
+                    return Ok(Some(NIL));
                 }
-                op::Goto => {
-                    let offset = self.pc.arg2();
-                    self.pc.goto(offset);
+            }
+            op::CallN => {
+                let idx = self.pc.arg1()?;
+                if self.call(idx, cx)? {
+                    return Ok(Some(NIL));
                 }
-                op::GotoIfNil => {
-                    let cond = self.env.stack.pop(cx);
-                    let offset = self.pc.arg2();
-                    if cond.is_nil() {
-                        self.pc.goto(offset);
-                    }
+            }
+            op::CallN2 => {
+                let idx = self.pc.arg2()?;
+                if self.call(idx, cx)? {
+                    return Ok(Some(NIL));
                 }
-                op::GotoIfNonNil => {
-                    let cond = self.env.stack.pop(cx);
-                    let offset = self.pc.arg2();
-                    if !cond.is_nil() {
-                        self.pc.goto(offset);
-                    }
+            }
+            op::Unbind0 => self.unbind(0, cx)?,
+            op::Unbind1 => self.unbind(1, cx)?,
+            op::Unbind2 => self.unbind(2, cx)?,
+            op::Unbind3 => self.unbind(3, cx)?,
+            op::Unbind4 => self.unbind(4, cx)?,
+            op::Unbind5 => self.unbind(5, cx)?,
+            op::UnbindN => {
+                let idx = self.pc.arg1()?;
+                self.unbind(idx, cx)?;
+            }
+            op::UnbindN2 => {
+                let idx = self.pc.arg2()?;
+                self.unbind(idx, cx)?;
+            }
+            op::PopHandler => {
+                self.handlers.pop();
+                self.env.handlers.pop();
+            }
+            op::PushCondtionCase => {
+                // pop before getting stack size
+                let condition = self.env.stack.pop(cx)?;
+                let handler = Handler {
+                    jump_code: self.pc.arg2()?,
+                    stack_size: self.env.stack.len(),
+                    stack_frame: self.env.stack.current_frame(),
+                    bindings: self.env.binding_depth(),
+                    condition: Slot::new(condition),
+                };
+                self.handlers.push(handler);
+                self.env.handlers.push((condition, NIL));
+            }
+            op::PushCatch => bail_err!("PushCatch bytecode is not implemented"),
+            op::Nth => {
+                let list = self.env.stack.pop(cx)?;
+                let top = self.env.stack.top()?;
+                top.set(fns::nth(top.bind_as(cx)?, list.try_into()?)?);
+            }
+            op::Symbolp => {
+                let top = self.env.stack.top()?;
+                top.set(data::symbolp(top.bind(cx)));
+            }
+            op::Consp => {
+                let top = self.env.stack.top()?;
+                top.set(data::consp(top.bind(cx)));
+            }
+            op::Stringp => {
+                let top = self.env.stack.top()?;
+                top.set(data::stringp(top.bind(cx)));
+            }
+            op::Listp => {
+                let top = self.env.stack.top()?;
+                top.set(data::listp(top.bind(cx)));
+            }
+            op::Eq => {
+                let v1 = self.env.stack.pop(cx)?;
+                let top = self.env.stack.top()?;
+                top.set(fns::eq(top.bind(cx), v1));
+            }
+            op::Memq => {
+                let list = self.env.stack.pop(cx)?;
+                let elt = self.env.stack.top()?;
+                elt.set(fns::memq(elt.bind(cx), list.try_into()?)?);
+            }
+            op::Not => {
+                let top = self.env.stack.top()?;
+                top.set(data::null(top.bind(cx)));
+            }
+            op::Car => {
+                let top = self.env.stack.top()?;
+                top.set(data::car(top.bind_as(cx)?));
+            }
+            op::Cdr => {
+                let top = self.env.stack.top()?;
+                top.set(data::cdr(top.bind_as(cx)?));
+            }
+            op::StackRefCar => {
+                let idx = self.pc.arg1()?;
+                let list = self.env.stack[idx as usize].bind_as(cx)?;
+                self.env.stack.push(data::car(list));
+            }
+            op::StackRefCdr => {
+                let idx = self.pc.arg1()?;
+                let list = self.env.stack[idx as usize].bind_as(cx)?;
+                self.env.stack.push(data::cdr(list));
+            }
+            op::Cons => {
+                let cdr = self.env.stack.pop(cx)?;
+                let car = self.env.stack.top()?;
+                car.set(data::cons(car.bind(cx), cdr, cx));
+            }
+            op::List1 => {
+                let top = self.env.stack.top()?;
+                top.set(alloc::list(&[top.bind(cx)], cx));
+            }
+            op::List2 => {
+                let a2 = self.env.stack.pop(cx)?;
+                let top = self.env.stack.top()?;
+                top.set(alloc::list(&[top.bind(cx), a2], cx));
+            }
+            op::List3 => {
+                let a3 = self.env.stack.pop(cx)?;
+                let a2 = self.env.stack.pop(cx)?;
+                let top = self.env.stack.top()?;
+                top.set(alloc::list(&[top.bind(cx), a2, a3], cx));
+            }
+            op::List4 => {
+                let a4 = self.env.stack.pop(cx)?;
+                let a3 = self.env.stack.pop(cx)?;
+                let a2 = self.env.stack.pop(cx)?;
+                let top = self.env.stack.top()?;
+                top.set(alloc::list(&[top.bind(cx), a2, a3, a4], cx));
+            }
+            op::Length => {
+                let top = self.env.stack.top()?;
+                top.set(fns::length(top.bind(cx))? as i64);
+            }
+            op::Aref => {
+                let idx = self.env.stack.pop(cx)?;
+                let top = self.env.stack.top()?;
+                top.set(data::aref(top.bind(cx), idx.try_into()?, cx)?);
+            }
+            op::StackRefAref => {
+                let idx = self.pc.arg1()?;
+                let idx = self.env.stack[idx as usize].bind(cx);
+                let top = self.env.stack.top()?;
+                top.set(data::aref(top.bind(cx), idx.try_into()?, cx)?);
+            }
+            op::Aset => {
+                let newlet = self.env.stack.pop(cx)?;
+                let idx = self.env.stack.pop(cx)?;
+                let top = self.env.stack.top()?;
+                top.set(data::aset(top.bind(cx), idx.try_into()?, newlet)?);
+            }
+            op::SymbolValue => {
+                let top = self.env.stack.top()?.bind_as(cx)?;
+                let value = data::symbol_value(top, self.env, cx).unwrap_or_default();
+                self.env.stack.top()?.set(value);
+            }
+            op::SymbolFunction => {
+                let top = self.env.stack.top()?;
+                top.set(data::symbol_function(top.bind_as(cx)?, cx));
+            }
+            op::Set => {
+                let newlet = self.env.stack.pop(cx)?;
+                let place: Symbol = self.env.stack.top()?.bind_as(cx)?;
+                root!(place, cx);
+                root!(newlet, cx);
+                data::set_var(place, newlet, self.env, cx)?;
+                self.env.stack.top()?.set(newlet.bind(cx));
+            }
+            op::Fset => {
+                let def = self.env.stack.pop(cx)?;
+                let top = self.env.stack.top()?;
+                top.set::<Object>(data::fset(top.bind_as(cx)?, def)?.into());
+            }
+            op::Get => {
+                let prop = self.env.stack.pop(cx)?.try_into()?;
+                let top = self.env.stack.top()?.bind_as(cx)?;
+                let value = data::get(top, prop, self.env, cx);
+                self.env.stack.top()?.set(value);
+            }
+            op::Substring => bail_err!("Substring bytecode is not implemented"),
+            op::Concat2 => bail_err!("Concat2 bytecode is not implemented"),
+            op::Concat3 => bail_err!("Concat3 bytecode is not implemented"),
+            op::Concat4 => bail_err!("Concat4 bytecode is not implemented"),
+            op::Sub1 => {
+                let value = arith::sub_one(self.env.stack.top()?.bind_as(cx)?, self.env)?;
+                self.env.stack.top()?.set(cx.add(value));
+            }
+            op::Add1 => {
+                let value = arith::add_one(self.env.stack.top()?.bind_as(cx)?, self.env)?;
+                self.env.stack.top()?.set(cx.add(value));
+            }
+            op::Add1StackSet => {
+                let idx = self.pc.arg1()?;
+                // skip the padding byte
+                self.pc.next()?;
+                let value = arith::add_one(self.env.stack.top()?.bind_as(cx)?, self.env)?;
+                self.env.stack.top()?.set(cx.add(value));
+                self.env.stack.set_ref(idx);
+            }
+            op::EqlSign => {
+                let rhs = self.env.stack.pop(cx)?;
+                let top = self.env.stack.top()?;
+                top.set::<Object>(arith::num_eq(top.bind_as(cx)?, &[rhs.try_into()?]).into());
+            }
+            op::GreaterThan => {
+                let v1 = self.env.stack.pop(cx)?;
+                let top = self.env.stack.top()?;
+                top.set(arith::greater_than(top.bind_as(cx)?, &[v1.try_into()?]));
+            }
+            op::LessThan => {
+                let v1 = self.env.stack.pop(cx)?;
+                let top = self.env.stack.top()?;
+                top.set(arith::less_than(top.bind_as(cx)?, &[v1.try_into()?]));
+            }
+            op::LessThanGotoIfNil => {
+                let v1 = self.env.stack.pop(cx)?;
+                let top = self.env.stack.pop(cx)?;
+                let offset = self.pc.arg2()?;
+                // skip the padding byte
+                self.pc.next()?;
+                if !arith::less_than(top.try_into()?, &[v1.try_into()?]) {
+                    self.pc.goto(offset)?;
                 }
-                op::GotoIfNilElsePop => {
-                    let offset = self.pc.arg2();
-                    if self.env.stack[0].bind(cx).is_nil() {
-                        self.pc.goto(offset);
-                    } else {
-                        self.env.stack.pop(cx);
-                    }
+            }
+            op::LessThanOrEqual => {
+                let v1 = self.env.stack.pop(cx)?;
+                let top = self.env.stack.top()?;
+                top.set(arith::less_than_or_eq(top.bind_as(cx)?, &[v1.try_into()?]));
+            }
+            op::GreaterThanOrEqual => {
+                let v1 = &[self.env.stack.pop(cx)?.try_into()?];
+                let top = self.env.stack.top()?;
+                top.set(arith::greater_than_or_eq(top.bind_as(cx)?, v1));
+            }
+            op::Diff => bail_err!("Diff bytecode is not implemented"),
+            op::Negate => {
+                let value = arith::sub(self.env.stack.top()?.bind_as(cx)?, &[], self.env)?;
+                self.env.stack.top()?.set(cx.add(value));
+            }
+            op::Plus => {
+                let arg1 = self.env.stack.pop(cx)?;
+                let args = &[self.env.stack.top()?.bind_as(cx)?, arg1.try_into()?];
+                let value = arith::add(args, self.env)?;
+                self.env.stack.top()?.set(cx.add(value));
+            }
+            op::StackRefAdd => {
+                let idx = self.pc.arg1()?;
+                let arg1 = self.env.stack[idx as usize].bind(cx);
+                let args = &[self.env.stack.top()?.bind_as(cx)?, arg1.try_into()?];
+                let value = arith::add(args, self.env)?;
+                self.env.stack.top()?.set(cx.add(value));
+            }
+            op::Max => {
+                let arg1 = self.env.stack.pop(cx)?;
+                let top = self.env.stack.top()?;
+                let args = &[arg1.try_into()?];
+                top.set(cx.add(arith::max(top.bind_as(cx)?, args)));
+            }
+            op::Min => {
+                let arg1 = self.env.stack.pop(cx)?;
+                let top = self.env.stack.top()?;
+                let args = &[arg1.try_into()?];
+                top.set(cx.add(arith::min(top.bind_as(cx)?, args)));
+            }
+            op::Multiply => {
+                let arg1 = self.env.stack.pop(cx)?;
+                let args = &[self.env.stack.top()?.bind_as(cx)?, arg1.try_into()?];
+                let value = arith::mul(args, self.env)?;
+                self.env.stack.top()?.set(cx.add(value));
+            }
+            op::Point => bail_err!("Point bytecode is not implemented"),
+            op::GotoChar => bail_err!("GotoChar bytecode is not implemented"),
+            op::Insert => bail_err!("Insert bytecode is not implemented"),
+            op::PointMax => bail_err!("PointMax bytecode is not implemented"),
+            op::PointMin => bail_err!("PointMin bytecode is not implemented"),
+            op::CharAfter => bail_err!("CharAfter bytecode is not implemented"),
+            op::FollowingChar => bail_err!("FollowingChar bytecode is not implemented"),
+            op::PrecedingChar => bail_err!("PrecedingChar bytecode is not implemented"),
+            op::CurrentColumn => bail_err!("CurrentColumn bytecode is not implemented"),
+            op::IndentTo => bail_err!("IndentTo bytecode is not implemented"),
+            op::EndOfLineP => bail_err!("EndOfLineP bytecode is not implemented"),
+            op::EndOfBufferP => bail_err!("EndOfBufferP bytecode is not implemented"),
+            op::BeginningOfLineP => bail_err!("BeginningOfLineP bytecode is not implemented"),
+            op::BeginningOfBufferP => {
+                bail_err!("BeginningOfBufferP bytecode is not implemented")
+            }
+            op::CurrentBuffer => bail_err!("CurrentBuffer bytecode is not implemented"),
+            op::SetBuffer => bail_err!("SetBuffer bytecode is not implemented"),
+            op::SaveCurrentBuffer1 => self.env.save_buffer(false, cx),
+            op::ForwardChar => bail_err!("ForwardChar bytecode is not implemented"),
+            op::ForwardWord => bail_err!("ForwardWord bytecode is not implemented"),
+            op::SkipCharsForward => bail_err!("SkipCharsForward bytecode is not implemented"),
+            op::SkipCharsBackward => bail_err!("SkipCharsBackward bytecode is not implemented"),
+            op::ForwardLine => bail_err!("ForwardLine bytecode is not implemented"),
+            op::CharSyntax => bail_err!("CharSyntax bytecode is not implemented"),
+            op::BufferSubstring => bail_err!("BufferSubstring bytecode is not implemented"),
+            op::DeleteRegion => bail_err!("DeleteRegion bytecode is not implemented"),
+            op::NarrowToRegion => bail_err!("NarrowToRegion bytecode is not implemented"),
+            op::Widen => bail_err!("Widen bytecode is not implemented"),
+            op::EndOfLine => bail_err!("EndOfLine bytecode is not implemented"),
+            op::ConstantN2 => {
+                let idx = self.pc.arg2()?;
+                let cnst = self.get_const(idx.into(), cx)?;
+                self.env.stack.push(cnst);
+            }
+            op::Goto => {
+                let offset = self.pc.arg2()?;
+                self.pc.goto(offset)?;
+            }
+            op::GotoIfNil => {
+                let cond = self.env.stack.pop(cx)?;
+                let offset = self.pc.arg2()?;
+                if cond.is_nil() {
+                    self.pc.goto(offset)?;
                 }
-                op::GotoIfNonNilElsePop => {
-                    let offset = self.pc.arg2();
-                    if self.env.stack[0].bind(cx).is_nil() {
-                        self.env.stack.pop(cx);
-                    } else {
-                        self.pc.goto(offset);
-                    }
+            }
+            op::DupGotoIfNil => {
+                let offset = self.pc.arg2()?;
+                // skip the padding byte
+                self.pc.next()?;
+                if self.env.stack[0].bind(cx).is_nil() {
+                    self.pc.goto(offset)?;
                 }
-                op::Return => {
-                    if let Some((f, offset)) = self.env.stack.prev_bytecode_frame() {
-                        self.set_current_frame(f.bind(cx), offset);
-                        let top = self.env.stack.top().bind(cx);
-                        self.env.stack.pop_frame();
-                        self.env.stack.push(top);
-                    } else {
-                        let top = self.env.stack.pop(cx);
-                        return Ok(top);
-                    }
+            }
+            op::GotoIfNonNil => {
+                let cond = self.env.stack.pop(cx)?;
+                let offset = self.pc.arg2()?;
+                if !cond.is_nil() {
+                    self.pc.goto(offset)?;
                 }
-                op::Discard => {
-                    self.env.stack.pop(cx);
+            }
+            op::GotoIfNilElsePop => {
+                let offset = self.pc.arg2()?;
+                if self.env.stack[0].bind(cx).is_nil() {
+                    self.pc.goto(offset)?;
+                } else {
+                    self.env.stack.pop(cx)?;
                 }
-                op::DiscardN => {
-                    let arg = self.pc.arg1();
-                    let cur_len = self.env.stack.len();
-                    let keep_tos = (arg & 0x80) != 0;
-                    let count = (arg & 0x7F) as usize;
-                    if keep_tos {
-                        let top = self.env.stack.top().bind(cx);
-                        self.env.stack.truncate(cur_len - count);
-                        self.env.stack.top().set(top);
-                    } else {
-                        self.env.stack.truncate(cur_len - count);
-                    }
+            }
+            op::GotoIfNonNilElsePop => {
+                let offset = self.pc.arg2()?;
+                if self.env.stack[0].bind(cx).is_nil() {
+                    self.env.stack.pop(cx)?;
+                } else {
+                    self.pc.goto(offset)?;
                 }
-                op::Duplicate => {
-                    let top = self.env.stack[0].bind(cx);
+            }
+            op::Return => {
+                if let Some((f, offset)) = self.env.stack.prev_bytecode_frame() {
+                    self.set_current_frame(f.bind(cx), offset);
+                    let top = self.env.stack.top()?.bind(cx);
+                    self.env.stack.pop_frame();
                     self.env.stack.push(top);
+                } else {
+                    let top = self.env.stack.pop(cx)?;
+                    return Ok(Some(top));
                 }
-                op::SaveExcursion => todo!("SaveExcursion bytecode"),
-                op::SaveRestriction => todo!("SaveRestriction bytecode"),
-                op::UnwindProtect => todo!("UnwindProtect bytecode"),
-                op::SetMarker => todo!("SetMarker bytecode"),
-                op::MatchBeginning => todo!("MatchBeginning bytecode"),
-                op::MatchEnd => todo!("MatchEnd bytecode"),
-                op::Upcase => todo!("Upcase bytecode"),
-                op::Downcase => todo!("Downcase bytecode"),
-                op::StringEqlSign => todo!("StringEqlSign bytecode"),
-                op::StringLessThan => todo!("StringLessThan bytecode"),
-                op::Equal => {
-                    let rhs = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    top.set(fns::equal(top.bind(cx), rhs));
-                }
-                op::Nthcdr => {
-                    let list = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    top.set(fns::nthcdr(top.bind_as(cx)?, list.try_into()?)?.copy_as_obj(cx));
-                }
-                op::Elt => {
-                    let n = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    top.set(fns::elt(top.bind(cx), n.try_into()?, cx)?);
-                }
-                op::Member => {
-                    let list = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    top.set(fns::member(top.bind(cx), list.try_into()?)?);
-                }
-                op::Assq => {
-                    let alist = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    top.set(fns::assq(top.bind(cx), alist.try_into()?)?);
-                }
-                op::Nreverse => {
-                    let elt = self.env.stack.top();
-                    elt.set(fns::nreverse(elt.bind_as(cx)?)?);
-                }
-                op::Setcar => {
-                    let newcar = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    top.set(data::setcar(top.bind_as(cx)?, newcar)?);
-                }
-                op::Setcdr => {
-                    let newcdr = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    top.set(data::setcdr(top.bind_as(cx)?, newcdr)?);
-                }
-                op::CarSafe => {
-                    let top = self.env.stack.top();
-                    top.set(data::car_safe(top.bind(cx)));
-                }
-                op::CdrSafe => {
-                    let top = self.env.stack.top();
-                    top.set(data::cdr_safe(top.bind(cx)));
-                }
-                op::Nconc => {
-                    let list2 = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    top.set(fns::nconc(&[top.bind_as(cx)?, list2.try_into()?])?);
-                }
-                op::Quo => todo!("Quo bytecode"),
-                op::Rem => todo!("Rem bytecode"),
-                op::Numberp => {
-                    let top = self.env.stack.top();
-                    top.set(data::numberp(top.bind(cx)));
-                }
-                op::Integerp => {
-                    let top = self.env.stack.top();
-                    top.set(data::integerp(top.bind(cx)));
-                }
-                op::ListN => {
-                    let size = self.pc.arg1() as usize;
-                    let slice = Rt::bind_slice(&self.env.stack[..size], cx);
-                    let list = alloc::list(slice, cx);
-                    let len = self.env.stack.len();
-                    self.env.stack.truncate(len - (size - 1));
-                    self.env.stack.top().set(list);
+            }
+            op::Discard => {
+                self.env.stack.pop(cx)?;
+            }
+            op::DiscardN => {
+                let arg = self.pc.arg1()?;
+                let cur_len = self.env.stack.len();
+                let keep_tos = (arg & 0x80) != 0;
+                let count = (arg & 0x7F) as usize;
+                if keep_tos {
+                    let top = self.env.stack.top()?.bind(cx);
+                    self.env.stack.truncate(cur_len - count);
+                    self.env.stack.top()?.set(top);
+                } else {
+                    self.env.stack.truncate(cur_len - count);
                 }
-                op::ConcatN => todo!("ConcatN bytecode"),
-                op::InsertN => todo!("InsertN bytecode"),
-                op::Switch => {
-                    let ObjectType::HashTable(table) = self.env.stack.pop(cx).untag() else {
-                        unreachable!("switch table was not a hash table")
+            }
+            op::Duplicate => {
+                let top = self.env.stack[0].bind(cx);
+                self.env.stack.push(top);
+            }
+            op::SaveExcursion => self.env.save_buffer(true, cx),
+            op::SaveRestriction => bail_err!("SaveRestriction bytecode is not implemented"),
+            op::UnwindProtect => bail_err!("UnwindProtect bytecode is not implemented"),
+            op::SetMarker => bail_err!("SetMarker bytecode is not implemented"),
+            op::MatchBeginning => bail_err!("MatchBeginning bytecode is not implemented"),
+            op::MatchEnd => bail_err!("MatchEnd bytecode is not implemented"),
+            op::Upcase => bail_err!("Upcase bytecode is not implemented"),
+            op::Downcase => bail_err!("Downcase bytecode is not implemented"),
+            op::StringEqlSign => bail_err!("StringEqlSign bytecode is not implemented"),
+            op::StringLessThan => bail_err!("StringLessThan bytecode is not implemented"),
+            op::Equal => {
+                let rhs = self.env.stack.pop(cx)?;
+                let top = self.env.stack.top()?;
+                top.set(fns::equal(top.bind(cx), rhs));
+            }
+            op::Nthcdr => {
+                let list = self.env.stack.pop(cx)?;
+                let top = self.env.stack.top()?;
+                top.set(fns::nthcdr(top.bind_as(cx)?, list.try_into()?)?.copy_as_obj(cx));
+            }
+            op::Elt => {
+                let n = self.env.stack.pop(cx)?;
+                let top = self.env.stack.top()?;
+                top.set(fns::elt(top.bind(cx), n.try_into()?, cx)?);
+            }
+            op::Member => {
+                let list = self.env.stack.pop(cx)?;
+                let top = self.env.stack.top()?;
+                top.set(fns::member(top.bind(cx), list.try_into()?)?);
+            }
+            op::Assq => {
+                let alist = self.env.stack.pop(cx)?;
+                let top = self.env.stack.top()?;
+                top.set(fns::assq(top.bind(cx), alist.try_into()?)?);
+            }
+            op::Nreverse => {
+                let elt = self.env.stack.top()?;
+                elt.set(fns::nreverse(elt.bind_as(cx)?)?);
+            }
+            op::Setcar => {
+                let newcar = self.env.stack.pop(cx)?;
+                let top = self.env.stack.top()?;
+                top.set(data::setcar(top.bind_as(cx)?, newcar)?);
+            }
+            op::Setcdr => {
+                let newcdr = self.env.stack.pop(cx)?;
+                let top = self.env.stack.top()?;
+                top.set(data::setcdr(top.bind_as(cx)?, newcdr)?);
+            }
+            op::CarSafe => {
+                let top = self.env.stack.top()?;
+                top.set(data::car_safe(top.bind(cx)));
+            }
+            op::CdrSafe => {
+                let top = self.env.stack.top()?;
+                top.set(data::cdr_safe(top.bind(cx)));
+            }
+            op::Nconc => {
+                let list2 = self.env.stack.pop(cx)?;
+                let top = self.env.stack.top()?;
+                top.set(fns::nconc(&[top.bind_as(cx)?, list2.try_into()?])?);
+            }
+            op::Quo => bail_err!("Quo bytecode is not implemented"),
+            op::Rem => {
+                let rhs: i64 = self.env.stack.pop(cx)?.try_into()?;
+                let lhs: i64 = self.env.stack.top()?.bind(cx).try_into()?;
+                let value = arith::remainder(lhs, rhs, self.env)?;
+                self.env.stack.top()?.set::<Object>(value.into());
+            }
+            op::Numberp => {
+                let top = self.env.stack.top()?;
+                top.set(data::numberp(top.bind(cx)));
+            }
+            op::Integerp => {
+                let top = self.env.stack.top()?;
+                top.set(data::integerp(top.bind(cx)));
+            }
+            op::ListN => {
+                let size = self.pc.arg1()? as usize;
+                let slice = Rt::bind_slice(&self.env.stack[..size], cx);
+                let list = alloc::list(slice, cx);
+                let len = self.env.stack.len();
+                self.env.stack.truncate(len - (size - 1));
+                self.env.stack.top()?.set(list);
+            }
+            op::ConcatN => bail_err!("ConcatN bytecode is not implemented"),
+            op::InsertN => bail_err!("InsertN bytecode is not implemented"),
+            op::Switch => {
+                let table = self.env.stack.pop(cx)?;
+                let ObjectType::HashTable(table) = table.untag() else {
+                    bail_err!(TypeError::new(Type::HashTable, table))
+                };
+                let cond = self.env.stack.pop(cx)?;
+                if let Some(offset) = table.get(cond) {
+                    let ObjectType::Int(offset) = offset.untag() else {
+                        bail_err!(TypeError::new(Type::Int, offset))
                     };
-                    let cond = self.env.stack.pop(cx);
-                    if let Some(offset) = table.get(cond) {
-                        let ObjectType::Int(offset) = offset.untag() else {
-                            unreachable!("switch value was not a int")
-                        };
-                        self.pc.goto(offset as u16);
-                    }
-                }
-                op::Constant0
-                | op::Constant1
-                | op::Constant2
-                | op::Constant3
-                | op::Constant4
-                | op::Constant5
-                | op::Constant6
-                | op::Constant7
-                | op::Constant8
-                | op::Constant9
-                | op::Constant10
-                | op::Constant11
-                | op::Constant12
-                | op::Constant13
-                | op::Constant14
-                | op::Constant15
-                | op::Constant16
-                | op::Constant17
-                | op::Constant18
-                | op::Constant19
-                | op::Constant20
-                | op::Constant21
-                | op::Constant22
-                | op::Constant23
-                | op::Constant24
-                | op::Constant25
-                | op::Constant26
-                | op::Constant27
-                | op::Constant28
-                | op::Constant29
-                | op::Constant30
-                | op::Constant31
-                | op::Constant32
-                | op::Constant33
-                | op::Constant34
-                | op::Constant35
-                | op::Constant36
-                | op::Constant37
-                | op::Constant38
-                | op::Constant39
-                | op::Constant40
-                | op::Constant41
-                | op::Constant42
-                | op::Constant43
-                | op::Constant44
-                | op::Constant45
-                | op::Constant46
-                | op::Constant47
-                | op::Constant48
-                | op::Constant49
-                | op::Constant50
-                | op::Constant51
-                | op::Constant52
-                | op::Constant53
-                | op::Constant54
-                | op::Constant55
-                | op::Constant56
-                | op::Constant57
-                | op::Constant58
-                | op::Constant59
-                | op::Constant60
-                | op::Constant61
-                | op::Constant62
-                | op::Constant63 => {
-                    let idx = (op as u8) - (op::Constant0 as u8);
-                    let cnst = self.get_const(idx as usize, cx);
-                    self.env.stack.push(cnst);
+                    let Ok(offset) = u16::try_from(offset) else {
+                        bail_err!("Invalid bytecode: jump to {offset} past the end")
+                    };
+                    self.pc.goto(offset)?;
                 }
             }
+            op::Constant0
+            | op::Constant1
+            | op::Constant2
+            | op::Constant3
+            | op::Constant4
+            | op::Constant5
+            | op::Constant6
+            | op::Constant7
+            | op::Constant8
+            | op::Constant9
+            | op::Constant10
+            | op::Constant11
+            | op::Constant12
+            | op::Constant13
+            | op::Constant14
+            | op::Constant15
+            | op::Constant16
+            | op::Constant17
+            | op::Constant18
+            | op::Constant19
+            | op::Constant20
+            | op::Constant21
+            | op::Constant22
+            | op::Constant23
+            | op::Constant24
+            | op::Constant25
+            | op::Constant26
+            | op::Constant27
+            | op::Constant28
+            | op::Constant29
+            | op::Constant30
+            | op::Constant31
+            | op::Constant32
+            | op::Constant33
+            | op::Constant34
+            | op::Constant35
+            | op::Constant36
+            | op::Constant37
+            | op::Constant38
+            | op::Constant39
+            | op::Constant40
+            | op::Constant41
+            | op::Constant42
+            | op::Constant43
+            | op::Constant44
+            | op::Constant45
+            | op::Constant46
+            | op::Constant47
+            | op::Constant48
+            | op::Constant49
+            | op::Constant50
+            | op::Constant51
+            | op::Constant52
+            | op::Constant53
+            | op::Constant54
+            | op::Constant55
+            | op::Constant56
+            | op::Constant57
+            | op::Constant58
+            | op::Constant59
+            | op::Constant60
+            | op::Constant61
+            | op::Constant62
+            | op::Constant63 => {
+                let idx = (op as u8) - (op::Constant0 as u8);
+                let cnst = self.get_const(idx as usize, cx)?;
+                self.env.stack.push(cnst);
+            }
         }
+        Ok(None)
+    }
+}
+
+/// Element `index` of a byte-code function as lisp sees it. The op codes are
+/// returned with any fused instructions expanded, so they match what the
+/// function was created with.
+pub(crate) fn byte_fn_index<'ob>(
+    func: &ByteFn,
+    index: usize,
+    cx: &'ob Context,
+) -> Option<Object<'ob>> {
+    match index {
+        1 => Some(cx.add(fuse::defuse(func.codes()))),
+        _ => func.index(index, cx),
     }
 }
 
@@ -907,12 +1285,207 @@ pub(crate) fn call<'ob>(
         func: Slot::new(func),
         env: frame,
         handlers: Vec::new(),
+        generator: None,
+        yielded: Vec::new(),
     };
     root!(vm, cx);
     vm.prepare_lisp_args(func, arg_cnt, name, cx)?;
     vm.run(cx).map_err(|e| e.add_trace(name, vm.env.stack.current_args()))
 }
 
+// Generators are only the primitives that suspend and resume bytecode.
+// Nothing defines `iter-defun`, `iter-yield` or `iter-next` with them yet;
+// Emacs defines those in generator.el with a CPS transform, which isn't in
+// this tree. A library for them would expand `iter-yield` to
+// `generator-yield` in a byte-compiled function, and call `generator-next`
+// from `iter-next`.
+
+// Slots of a generator record
+const STATE: usize = 1;
+const FUNC: usize = 2;
+const PC: usize = 3;
+/// The arguments before the generator has started, and the saved stack after
+const VALUES: usize = 4;
+const FRAMES: usize = 5;
+/// The value passed to `generator-yield`
+const VALUE: usize = 6;
+const GENERATOR_LEN: usize = 7;
+
+// Generator states
+const NEW: i64 = 0;
+const SUSPENDED: i64 = 1;
+const RUNNING: i64 = 2;
+const DONE: i64 = 3;
+
+fn generator_record(obj: Object) -> Result<&Record> {
+    match obj.untag() {
+        ObjectType::Record(record)
+            if record.len() == GENERATOR_LEN && record[0].get() == sym::GENERATOR =>
+        {
+            Ok(record)
+        }
+        _ => bail!("Not a generator: {obj}"),
+    }
+}
+
+/// Make a generator that calls FUNCTION with ARGS. FUNCTION has to be
+/// byte-compiled. It is run by `generator-next`, and suspended whenever it
+/// calls `generator-yield`.
+#[defun]
+fn make_generator<'ob>(
+    function: Object,
+    args: &[Object],
+    cx: &'ob Context,
+) -> Result<RecordBuilder<'ob>> {
+    let ObjectType::ByteFn(_) = function.untag() else {
+        bail!(TypeError::new(Type::Func, function))
+    };
+    let mut record = cx.vec_with_capacity(GENERATOR_LEN);
+    record.push(sym::GENERATOR.into());
+    record.push(NEW.into());
+    record.push(function);
+    record.push(0.into());
+    record.push(crate::fns::slice_into_list(args, None, cx));
+    record.push(NIL);
+    record.push(NIL);
+    Ok(RecordBuilder(record))
+}
+
+/// Run GENERATOR until it yields, and return the yielded value. If it was
+/// suspended, its call to `generator-yield` returns VALUE. When the function
+/// of the generator returns, `iter-end-of-sequence` is signaled with the
+/// return value, and with nil on every later call.
+#[defun]
+fn generator_next<'ob>(
+    generator: &Rto<Object>,
+    value: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let record = generator_record(generator.bind(cx))?;
+    let state = record[STATE].get();
+    if state == DONE {
+        return Err(EvalError::signal(sym::ITER_END_OF_SEQUENCE.into(), NIL, env).into());
+    }
+    ensure!(state != RUNNING, "Generator is already running");
+    let ObjectType::ByteFn(func) = record[FUNC].get().untag() else {
+        bail!("Invalid generator function: {}", record[FUNC].get())
+    };
+    // The stack is saved as vectors, and before starting the arguments are a
+    // list
+    let saved = match (record[VALUES].get().untag(), record[FRAMES].get().untag()) {
+        (ObjectType::Vec(values), ObjectType::Vec(frames)) if state == SUSPENDED => {
+            let values: Vec<Object> = values.iter().map(|x| x.get()).collect();
+            let frames: Vec<Object> = frames.iter().map(|x| x.get()).collect();
+            Some((values, frames))
+        }
+        _ if state == NEW => None,
+        _ => bail!("Invalid generator state: {}", record[STATE].get()),
+    };
+    let pc = match record[PC].get().untag() {
+        ObjectType::Int(pc) => usize::try_from(pc).ok().filter(|x| *x < func.codes().len()),
+        _ => None,
+    };
+    let Some(pc) = pc else { bail!("Invalid generator program counter: {}", record[PC].get()) };
+    let args = record[VALUES].get();
+    let starting = saved.is_none();
+    record.try_mut()?[STATE].set(RUNNING.into());
+
+    let frame = &mut CallFrame::new(env);
+    let base = GeneratorBase {
+        frame: frame.stack.current_frame(),
+        bindings: frame.binding_depth(),
+        catches: frame.catch_stack.len(),
+    };
+    let mut result = Ok(());
+    match &saved {
+        Some((values, frames)) => {
+            result = frame.stack.resume(values, frames);
+            if result.is_ok() {
+                frame.stack.push(value.map_or(NIL, |x| x.bind(cx)));
+            }
+        }
+        None => {
+            for arg in args.as_list()? {
+                frame.push_arg(arg?);
+            }
+            frame.finalize_arguments();
+        }
+    }
+    let arg_cnt = frame.stack.arg_count();
+    let vm = VM {
+        pc: ProgramCounter::with_offset(func.codes(), pc),
+        func: Slot::new(func),
+        env: frame,
+        handlers: Vec::new(),
+        generator: Some(base),
+        yielded: Vec::new(),
+    };
+    root!(vm, cx);
+    if result.is_ok() && starting {
+        vm.env.stack.set_depth(func.depth);
+        result = vm.prepare_lisp_args(func, arg_cnt, "generator", cx);
+    }
+    let result = match result {
+        Ok(()) => match vm.run(cx) {
+            Ok(x) => Ok(rebind!(x, cx)),
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(EvalError::from(e)),
+    };
+
+    let record = generator_record(generator.bind(cx))?;
+    let slots = record.try_mut()?;
+    if !vm.yielded.is_empty() {
+        for (slot, obj) in slots[FUNC..].iter().zip(vm.yielded.iter()) {
+            slot.set(obj.bind(cx));
+        }
+        slots[STATE].set(SUSPENDED.into());
+        return Ok(slots[VALUE].get());
+    }
+    for slot in &slots[FUNC..] {
+        slot.set(NIL);
+    }
+    slots[STATE].set(DONE.into());
+    match result {
+        Ok(value) => {
+            Err(EvalError::signal(sym::ITER_END_OF_SEQUENCE.into(), value, vm.env).into())
+        }
+        Err(e) => {
+            vm.env.stack.unwind_frames(base.frame);
+            Err(e.into())
+        }
+    }
+}
+
+/// Suspend the current generator and return VALUE from the `generator-next`
+/// call that ran it. This returns the value passed to the next call of
+/// `generator-next`. Only byte-compiled code run by the generator can yield,
+/// and not from inside `condition-case`, `catch`, or a dynamic binding.
+#[defun]
+fn generator_yield(_value: Option<Object>) -> Result<()> {
+    bail!("generator-yield called outside of a generator")
+}
+
+/// Limit the bytecode instructions and interpreted forms that can run on
+/// this thread to FUEL. Running out signals `excessive-eval` once and removes
+/// the limit. If FUEL is nil there is no limit.
+#[defun]
+fn set_bytecode_fuel(fuel: Option<usize>) {
+    set_fuel(fuel, false);
+}
+
+/// Return how many bytecode instructions and interpreted forms can still run
+/// on this thread, or nil if there is no limit.
+#[defun]
+fn bytecode_fuel() -> Option<usize> {
+    remaining_fuel()
+}
+
+defsym!(GENERATOR);
+defsym!(ITER_END_OF_SEQUENCE);
+defsym!(EXCESSIVE_EVAL);
+
 #[cfg(test)]
 mod test {
     use crate::core::{
@@ -1083,6 +1656,126 @@ mod test {
         // (lambda (x &optional y) (+ x y))
         make_bytecode!(bytecode, 513, [StackRef1, StackRef1, Plus, Return], [], cx);
         check_bytecode!(bytecode, [1, 2], 3, cx);
+
+        // (lambda () (+ 1 2 3 4))
+        make_bytecode!(
+            bytecode,
+            0,
+            [Constant0, Constant1, Constant2, Constant3, Constant4, Call4, Return],
+            [sym::ADD, 1, 2, 3, 4],
+            cx
+        );
+        check_bytecode!(bytecode, [], 10, cx);
+
+        // (lambda () (+ 1 2 3 4 5))
+        make_bytecode!(
+            bytecode,
+            0,
+            [Constant0, Constant1, Constant2, Constant3, Constant4, Constant5, Call5, Return],
+            [sym::ADD, 1, 2, 3, 4, 5],
+            cx
+        );
+        check_bytecode!(bytecode, [], 15, cx);
+
+        // (lambda () (+ 1 2 3 4 5 6))
+        make_bytecode!(
+            bytecode,
+            0,
+            [
+                Constant0, Constant1, Constant2, Constant3, Constant4, Constant5, Constant6, CallN,
+                6, Return
+            ],
+            [sym::ADD, 1, 2, 3, 4, 5, 6],
+            cx
+        );
+        check_bytecode!(bytecode, [], 21, cx);
+
+        // (lambda () (+ 1 2 3 4 5 6 7)) with a two byte argument count
+        make_bytecode!(
+            bytecode,
+            0,
+            [
+                Constant0, Constant1, Constant2, Constant3, Constant4, Constant5, Constant6,
+                Constant7, CallN2, 7, 0, Return
+            ],
+            [sym::ADD, 1, 2, 3, 4, 5, 6, 7],
+            cx
+        );
+        check_bytecode!(bytecode, [], 28, cx);
+
+        // A bytecode function with six arguments called from bytecode
+        // (lambda (a b c d e f) (list a f))
+        make_bytecode!(
+            inner,
+            1542,
+            [Constant0, StackRef6, StackRef2, Call2, Return],
+            [sym::LIST],
+            cx
+        );
+        // (lambda () (funcall INNER 1 2 3 4 5 6))
+        make_bytecode!(
+            outer,
+            0,
+            [
+                Constant0, Constant1, Constant2, Constant3, Constant4, Constant5, Constant6, CallN,
+                6, Return
+            ],
+            [inner.bind(cx), 1, 2, 3, 4, 5, 6],
+            cx
+        );
+        let expect = list![1, 6; cx];
+        check_bytecode!(outer, [], expect, cx);
+    }
+
+    #[test]
+    fn test_collect_in_nested_call() {
+        use OpCode::*;
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        // (lambda () (garbage-collect) "inner")
+        make_bytecode!(
+            inner,
+            0,
+            [Constant0, Call0, Discard, Constant1, Return],
+            [sym::GARBAGE_COLLECT, "inner"],
+            cx
+        );
+        // (lambda () (list (funcall INNER) (funcall INNER)))
+        make_bytecode!(
+            outer,
+            0,
+            [Constant0, Constant1, Call0, Constant1, Call0, Call2, Return],
+            [sym::LIST, inner.bind(cx)],
+            cx
+        );
+        let expect = list!["inner", "inner"; cx];
+        check_bytecode!(outer, [], expect, cx);
+    }
+
+    #[test]
+    fn test_call_cache() {
+        use crate::core::env::intern;
+        use OpCode::*;
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        let name = "bytecode-call-cache-test";
+        make_bytecode!(first, 0, [Constant0, Return], [1], cx);
+        make_bytecode!(second, 0, [Constant0, Return], [2], cx);
+        // (lambda () (bytecode-call-cache-test))
+        make_bytecode!(caller, 0, [Constant0, Call0, Return], [intern(name, cx)], cx);
+        make_bytecode!(other, 0, [Constant0, Call0, Return], [intern(name, cx)], cx);
+        crate::data::fset(intern(name, cx), first.bind(cx).into()).unwrap();
+        check_bytecode!(caller, [], 1, cx);
+        check_bytecode!(caller, [], 1, cx);
+        check_bytecode!(other, [], 1, cx);
+        // redefining the function invalidates the cache
+        crate::data::fset(intern(name, cx), second.bind(cx).into()).unwrap();
+        check_bytecode!(caller, [], 2, cx);
+        // and drops the entries of the other call sites
+        assert_eq!(CALL_CACHE.with_borrow(|cache| cache.sites.len()), 1);
+        intern(name, cx).unbind_func();
     }
 
     #[test]
@@ -1208,6 +1901,47 @@ mod test {
         check_bytecode!(bytecode, [sym::FLOOR], "floor", cx);
     }
 
+    #[test]
+    fn test_handler_unbinds() {
+        use OpCode as O;
+
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        let err = Cons::new1(sym::ERROR, cx);
+        let var = crate::core::env::intern("bytecode-test-handler-var", cx);
+
+        // (lambda () (condition-case nil
+        //                (let ((bytecode-test-handler-var 1)) (car 1))
+        //              (error (boundp 'bytecode-test-handler-var))))
+        make_bytecode!(
+            bytecode,
+            0,
+            [
+                O::Constant0,
+                O::PushCondtionCase,
+                0x0C,
+                0x0,
+                O::Constant2,
+                O::VarBind1,
+                O::Constant3,
+                O::Constant2,
+                O::Call1,
+                O::Unbind1,
+                O::PopHandler,
+                O::Return,
+                O::Discard,
+                O::Constant4,
+                O::Constant1,
+                O::Call1,
+                O::Return
+            ],
+            [err, var, 1, sym::CAR, sym::BOUNDP],
+            cx
+        );
+        check_bytecode!(bytecode, [], false, cx);
+    }
+
     #[test]
     fn test_recursive_handlers() {
         use OpCode as O;
@@ -1248,4 +1982,261 @@ mod test {
         root!(inner, cx);
         check_bytecode!(outer, [inner], 7, cx);
     }
+
+    #[test]
+    fn test_fused_instructions() {
+        use OpCode::*;
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        let list = list![1, 2; cx];
+        // (lambda (x y) (and x (+ (car '(1 2)) y)))
+        make_bytecode!(
+            bytecode,
+            514,
+            [
+                StackRef1, Duplicate, GotoIfNil, 0x0C, 0x00, Discard, Constant0, Constant1, Call1,
+                StackRef1, Plus, Return, Return
+            ],
+            [sym::CAR, list],
+            cx
+        );
+        let codes = bytecode.bind(cx).codes();
+        assert_eq!(codes[1], DupGotoIfNil as u8);
+        assert_eq!(codes[7], ConstantCall1 as u8);
+        assert_eq!(codes[9], StackRefAdd as u8);
+        check_bytecode!(bytecode, [true, 4], 5, cx);
+        check_bytecode!(bytecode, [false, 4], false, cx);
+        // the original codes are shown to lisp
+        let expect = vec![
+            StackRef1 as u8,
+            Duplicate as u8,
+            GotoIfNil as u8,
+            0x0C,
+            0x00,
+            Discard as u8,
+            Constant0 as u8,
+            Constant1 as u8,
+            Call1 as u8,
+            StackRef1 as u8,
+            Plus as u8,
+            Return as u8,
+            Return as u8,
+        ];
+        let codes = byte_fn_index(bytecode.bind(cx), 1, cx).unwrap();
+        assert_eq!(codes, cx.add(expect));
+    }
+
+    #[test]
+    fn test_fused_loops() {
+        use OpCode::*;
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        let list = list![1, 2, 3; cx];
+        // (lambda () (let ((sum 0)) (dolist (x '(1 2 3)) (setq sum (+ sum x))) sum))
+        make_bytecode!(
+            bytecode,
+            0,
+            [
+                Constant0, Constant1, Duplicate, GotoIfNil, 0x13, 0x00, Duplicate, Car, StackRef2,
+                Plus, StackSetN, 0x02, Duplicate, Cdr, StackSetN, 0x01, Goto, 0x02, 0x00, Discard,
+                Return
+            ],
+            [0, list],
+            cx
+        );
+        let codes = bytecode.bind(cx).codes();
+        assert_eq!(codes[6], StackRefCar as u8);
+        assert_eq!(codes[12], StackRefCdr as u8);
+        check_bytecode!(bytecode, [], 6, cx);
+
+        let vec: Object = cx.add(vec![cx.add(1), cx.add(2), cx.add(3), cx.add(4)]);
+        // (lambda (n) (let ((sum 0)) (dotimes (i n) (setq sum (+ sum (aref [1 2 3 4] i)))) sum))
+        make_bytecode!(
+            bytecode,
+            257,
+            [
+                Constant0, Constant0, Duplicate, StackRef3, LessThan, GotoIfNil, 0x16, 0x00,
+                Constant1, StackRef1, Aref, StackRef2, Plus, StackSetN, 0x02, Duplicate, Add1,
+                StackSetN, 0x01, Goto, 0x02, 0x00, Discard, Return
+            ],
+            [0, vec],
+            cx
+        );
+        let codes = bytecode.bind(cx).codes();
+        assert_eq!(codes[4], LessThanGotoIfNil as u8);
+        assert_eq!(codes[9], StackRefAref as u8);
+        assert_eq!(codes[16], Add1StackSet as u8);
+        check_bytecode!(bytecode, [3], 6, cx);
+        check_bytecode!(bytecode, [0], 0, cx);
+    }
+
+    #[test]
+    fn test_fast_call() {
+        use OpCode::*;
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        // (lambda (f x y) (funcall f x y))
+        make_bytecode!(bytecode, 771, [StackRef2, StackRef2, StackRef2, Call2, Return], [], cx);
+        check_bytecode!(bytecode, [sym::ADD, 1, 2], 3, cx);
+        check_bytecode!(bytecode, [sym::LOGAND, 12, 10], 8, cx);
+        check_bytecode!(bytecode, [sym::LESS_THAN, 1, 2], true, cx);
+        check_bytecode!(bytecode, [sym::REMAINDER, -7, 2], -1, cx);
+        // Arguments that aren't fixnums take the generic path
+        check_bytecode!(bytecode, [sym::ADD, 1, 2.5], 3.5, cx);
+        check_bytecode!(bytecode, [sym::MUL, MAX_FIXNUM, 2.0], MAX_FIXNUM as f64 * 2.0, cx);
+        // as do calls with a different number of arguments
+        make_bytecode!(bytecode, 257, [Constant0, StackRef1, Call1, Return], [sym::SUB], cx);
+        check_bytecode!(bytecode, [3], -3, cx);
+        // and results that don't fit in a fixnum, which signal overflow-error
+        root!(env, new(Env), cx);
+        for func in [sym::MUL, sym::ADD] {
+            // (lambda () (funcall FUNC MAX_FIXNUM 2))
+            make_bytecode!(
+                bytecode,
+                0,
+                [Constant0, Constant1, Constant2, Call2, Return],
+                [func, MAX_FIXNUM, 2],
+                cx
+            );
+            let err = call(bytecode, 0, "test", &mut CallFrame::new(env), cx).unwrap_err();
+            let Some(EvalError { error: ErrorType::Signal(id), .. }) = err.downcast_ref() else {
+                panic!("expected a signal: {err}")
+            };
+            let (error, _) = env.get_exception(*id).unwrap();
+            assert_eq!(error.bind(cx), sym::OVERFLOW_ERROR);
+        }
+    }
+
+    #[test]
+    fn test_generator() {
+        use OpCode::*;
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        // (lambda (x) (generator-yield x) (1+ x))
+        make_bytecode!(
+            inner,
+            257,
+            [Constant0, StackRef1, Call1, Discard, Duplicate, Add1, Return],
+            [sym::GENERATOR_YIELD],
+            cx
+        );
+        // (lambda (x) (+ (funcall INNER x) (generator-yield 10)))
+        make_bytecode!(
+            outer,
+            257,
+            [Constant0, StackRef1, Call1, Constant1, Constant2, Call1, Plus, Return],
+            [inner.bind(cx), sym::GENERATOR_YIELD, 10],
+            cx
+        );
+        let generator = make_generator(outer.bind(cx).into(), &[5.into()], cx).unwrap();
+        let generator = cx.add(generator);
+        root!(generator, cx);
+        // the first yield is from inside the nested call
+        let value = rebind!(generator_next(generator, None, env, cx).unwrap());
+        assert_eq!(value, 5);
+        assert_eq!(env.stack.len(), 0);
+        cx.garbage_collect(true);
+        let value = rebind!(generator_next(generator, None, env, cx).unwrap());
+        assert_eq!(value, 10);
+        let seven = cx.add(7);
+        root!(seven, cx);
+        let err = generator_next(generator, Some(&*seven), env, cx).unwrap_err();
+        let Some(EvalError { error: ErrorType::Signal(id), .. }) = err.downcast_ref() else {
+            panic!("expected a signal: {err}")
+        };
+        let (error, data) = env.get_exception(*id).unwrap();
+        assert_eq!(error.bind(cx), sym::ITER_END_OF_SEQUENCE);
+        assert_eq!(data.bind(cx), 13);
+        assert!(generator_next(generator, None, env, cx).is_err());
+
+        // (lambda () (generator-yield 1)) outside of a generator
+        make_bytecode!(
+            bytecode,
+            0,
+            [Constant0, Constant1, Call1, Return],
+            [sym::GENERATOR_YIELD, 1],
+            cx
+        );
+        let frame = &mut CallFrame::new(env);
+        assert!(call(bytecode, 0, "test", frame, cx).is_err());
+    }
+
+    #[test]
+    fn test_byte_code() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        // (byte-code "\300\207" [5] 1)
+        let codes = vec![OpCode::Constant0 as u8, OpCode::Return as u8].into_obj(cx);
+        let constants = vec![cx.add(5)].into_obj(cx);
+        root!(codes, cx);
+        root!(constants, cx);
+        assert_eq!(byte_code(codes, constants, 1, env, cx).unwrap(), 5);
+    }
+
+    #[test]
+    fn test_invalid_bytecode() {
+        use OpCode::*;
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        // A constant that doesn't exist
+        make_bytecode!(bytecode, 0, [Constant1, Return], [5], cx);
+        assert!(call(bytecode, 0, "test", &mut CallFrame::new(env), cx).is_err());
+        // A variable that isn't a symbol
+        make_bytecode!(bytecode, 0, [VarRef0, Return], [5], cx);
+        assert!(call(bytecode, 0, "test", &mut CallFrame::new(env), cx).is_err());
+        make_bytecode!(bytecode, 0, [Constant0, VarBind0, Return], [5], cx);
+        assert!(call(bytecode, 0, "test", &mut CallFrame::new(env), cx).is_err());
+        // A jump past the end
+        make_bytecode!(bytecode, 0, [Goto, 0xFF, 0x0, Return], [], cx);
+        assert!(call(bytecode, 0, "test", &mut CallFrame::new(env), cx).is_err());
+        // Running off the end, and an argument that is cut short
+        make_bytecode!(bytecode, 0, [Constant0], [5], cx);
+        assert!(call(bytecode, 0, "test", &mut CallFrame::new(env), cx).is_err());
+        make_bytecode!(bytecode, 0, [Constant0, StackRefN2, 0x0], [5], cx);
+        assert!(call(bytecode, 0, "test", &mut CallFrame::new(env), cx).is_err());
+        // Popping more than the frame holds
+        make_bytecode!(bytecode, 0, [Discard, Return], [], cx);
+        assert!(call(bytecode, 0, "test", &mut CallFrame::new(env), cx).is_err());
+        make_bytecode!(bytecode, 0, [Constant0, Call2, Return], [sym::CONS], cx);
+        assert!(call(bytecode, 0, "test", &mut CallFrame::new(env), cx).is_err());
+    }
+
+    #[test]
+    fn test_fuel() {
+        use OpCode::*;
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        make_bytecode!(bytecode, 0, [Constant0, Return], [5], cx);
+        set_fuel(Some(10), false);
+        assert_eq!(rebind!(call(bytecode, 0, "test", &mut CallFrame::new(env), cx).unwrap()), 5);
+        assert_eq!(remaining_fuel(), Some(8));
+        // (lambda () (while t))
+        make_bytecode!(loop_forever, 0, [Goto, 0x0, 0x0], [], cx);
+        let err = call(loop_forever, 0, "test", &mut CallFrame::new(env), cx).unwrap_err();
+        let (symbol, _) = err.condition(env, cx).unwrap();
+        let expect: Object = sym::EXCESSIVE_EVAL.into();
+        assert_eq!(symbol, expect);
+        // It only signals once
+        assert_eq!(remaining_fuel(), None);
+        assert_eq!(rebind!(call(bytecode, 0, "test", &mut CallFrame::new(env), cx).unwrap()), 5);
+
+        // Unless the limit is sticky
+        set_fuel(Some(10), true);
+        assert!(call(loop_forever, 0, "test", &mut CallFrame::new(env), cx).is_err());
+        assert_eq!(remaining_fuel(), Some(0));
+        assert!(call(bytecode, 0, "test", &mut CallFrame::new(env), cx).is_err());
+        set_fuel(None, false);
+        assert_eq!(bytecode_fuel(), None);
+    }
 }
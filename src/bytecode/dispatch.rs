@@ -0,0 +1,53 @@
+//! Dispatch through a table with a handler for every opcode, used with the
+//! `table-dispatch` feature.
+//!
+//! Each handler is [`RootedVM::execute_op`] specialized to one byte. It is
+//! inlined with the byte as a constant, so decoding the opcode and the match
+//! fold away and only the code of that instruction is left. The loop then
+//! calls the handler for the next byte directly. That replaces the decoding,
+//! the bounds check and the jump through the match's table with one indirect
+//! call, and gives each instruction its own branch for the predictor, at the
+//! cost of a larger binary.
+use super::RootedVM;
+use crate::core::gc::Context;
+use crate::core::object::Object;
+use crate::eval::EvalError;
+
+pub(super) type OpHandler = for<'ob> fn(
+    &mut RootedVM<'_, '_, '_>,
+    &'ob mut Context<'_>,
+) -> Result<Option<Object<'ob>>, EvalError>;
+
+fn handler<'ob, const CODE: u8>(
+    vm: &mut RootedVM<'_, '_, '_>,
+    cx: &'ob mut Context<'_>,
+) -> Result<Option<Object<'ob>>, EvalError> {
+    vm.execute_op(CODE, cx)
+}
+
+macro_rules! table {
+    ($($high:literal)*) => {
+        [$(
+            handler::<{ $high * 16 }>,
+            handler::<{ $high * 16 + 1 }>,
+            handler::<{ $high * 16 + 2 }>,
+            handler::<{ $high * 16 + 3 }>,
+            handler::<{ $high * 16 + 4 }>,
+            handler::<{ $high * 16 + 5 }>,
+            handler::<{ $high * 16 + 6 }>,
+            handler::<{ $high * 16 + 7 }>,
+            handler::<{ $high * 16 + 8 }>,
+            handler::<{ $high * 16 + 9 }>,
+            handler::<{ $high * 16 + 10 }>,
+            handler::<{ $high * 16 + 11 }>,
+            handler::<{ $high * 16 + 12 }>,
+            handler::<{ $high * 16 + 13 }>,
+            handler::<{ $high * 16 + 14 }>,
+            handler::<{ $high * 16 + 15 }>,
+        )*]
+    };
+}
+
+/// The handler for each byte. Bytes that are not an opcode have a handler
+/// that returns the same error as the match.
+pub(super) static TABLE: [OpHandler; 256] = table!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15);
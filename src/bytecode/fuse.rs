@@ -0,0 +1,323 @@
+//! Fusing common pairs of instructions into superinstructions.
+//!
+//! Each fused instruction is exactly as long as the pair it replaces, so jump
+//! offsets don't change and the original code can be recovered for display. A
+//! pair is only fused if nothing jumps to its second instruction.
+use super::opcode::OpCode as op;
+use crate::core::object::{Object, ObjectType};
+use rune_core::hashmap::HashSet;
+
+const CONSTANT0: u8 = op::Constant0 as u8;
+const CONSTANT_MAX: u8 = CONSTANT0 + 63;
+
+/// Number of operand bytes that follow the opcode `code`.
+fn operand_len(code: u8) -> Option<usize> {
+    let Ok(code) = op::try_from(code) else { return None };
+    let len = match code {
+        op::StackRefN
+        | op::VarRefN
+        | op::VarSetN
+        | op::VarBindN
+        | op::CallN
+        | op::UnbindN
+        | op::ListN
+        | op::ConcatN
+        | op::InsertN
+        | op::StackSetN
+        | op::DiscardN
+        | op::ConstantCall1
+        | op::StackRefAdd
+        | op::StackRefCar
+        | op::StackRefCdr
+        | op::StackRefAref => 1,
+        op::StackRefN2
+        | op::VarRefN2
+        | op::VarSetN2
+        | op::VarBindN2
+        | op::CallN2
+        | op::UnbindN2
+        | op::PushCondtionCase
+        | op::PushCatch
+        | op::ConstantN2
+        | op::Goto
+        | op::GotoIfNil
+        | op::GotoIfNonNil
+        | op::GotoIfNilElsePop
+        | op::GotoIfNonNilElsePop
+        | op::StackSetN2
+        | op::Add1StackSet => 2,
+        op::DupGotoIfNil | op::LessThanGotoIfNil => 3,
+        _ => 0,
+    };
+    Some(len)
+}
+
+/// The offset of every instruction, or `None` if the code can't be decoded.
+fn instructions(codes: &[u8]) -> Option<Vec<usize>> {
+    let mut starts = Vec::new();
+    let mut i = 0;
+    while i < codes.len() {
+        starts.push(i);
+        i += 1 + operand_len(codes[i])?;
+    }
+    (i == codes.len()).then_some(starts)
+}
+
+fn read_u16(codes: &[u8], i: usize) -> usize {
+    usize::from(u16::from_le_bytes([codes[i], codes[i + 1]]))
+}
+
+/// Every offset that could be jumped to.
+fn jump_targets<'ob>(
+    codes: &[u8],
+    starts: &[usize],
+    consts: impl Iterator<Item = Object<'ob>>,
+) -> HashSet<usize> {
+    let mut targets = HashSet::default();
+    for &i in starts {
+        if let Ok(
+            op::Goto
+            | op::GotoIfNil
+            | op::GotoIfNonNil
+            | op::GotoIfNilElsePop
+            | op::GotoIfNonNilElsePop
+            | op::PushCondtionCase
+            | op::PushCatch,
+        ) = op::try_from(codes[i])
+        {
+            targets.insert(read_u16(codes, i + 1));
+        }
+    }
+    // The jump tables of `switch` are constants
+    for cnst in consts {
+        if let ObjectType::HashTable(table) = cnst.untag() {
+            for i in 0..table.len() {
+                if let Some((_, value)) = table.get_index(i) {
+                    if let ObjectType::Int(target) = value.untag() {
+                        targets.extend(usize::try_from(target));
+                    }
+                }
+            }
+        }
+    }
+    targets
+}
+
+/// The stack index pushed by `code` if it is `dup` or a short stack-ref.
+fn stack_ref_index(code: u8) -> Option<u8> {
+    match code {
+        1..=5 => Some(code),
+        _ if code == op::Duplicate as u8 => Some(0),
+        _ => None,
+    }
+}
+
+/// Replace common pairs of instructions in `codes` with fused instructions.
+/// Code that can't be decoded is returned unchanged.
+pub(crate) fn fuse<'ob>(codes: &[u8], consts: impl Iterator<Item = Object<'ob>>) -> Vec<u8> {
+    let mut fused = codes.to_vec();
+    let Some(starts) = instructions(codes) else { return fused };
+    let targets = jump_targets(codes, &starts, consts);
+    let mut pairs = starts.windows(2);
+    while let Some(&[i, next]) = pairs.next() {
+        if targets.contains(&next) {
+            continue;
+        }
+        let replaced = match (codes[i], codes[next]) {
+            (c @ CONSTANT0..=CONSTANT_MAX, call) if call == op::Call1 as u8 => {
+                fused[i] = op::ConstantCall1 as u8;
+                fused[next] = c - CONSTANT0;
+                true
+            }
+            (r @ 1..=5, plus) if plus == op::Plus as u8 => {
+                fused[i] = op::StackRefAdd as u8;
+                fused[next] = r;
+                true
+            }
+            (dup, goto) if dup == op::Duplicate as u8 && goto == op::GotoIfNil as u8 => {
+                fused[i] = op::DupGotoIfNil as u8;
+                fused[i + 1] = codes[next + 1];
+                fused[i + 2] = codes[next + 2];
+                fused[i + 3] = 0;
+                true
+            }
+            // Incrementing and testing the counter of `dotimes`
+            (add1, set) if add1 == op::Add1 as u8 && set == op::StackSetN as u8 => {
+                fused[i] = op::Add1StackSet as u8;
+                fused[i + 1] = codes[next + 1];
+                fused[i + 2] = 0;
+                true
+            }
+            (less, goto) if less == op::LessThan as u8 && goto == op::GotoIfNil as u8 => {
+                fused[i] = op::LessThanGotoIfNil as u8;
+                fused[i + 1] = codes[next + 1];
+                fused[i + 2] = codes[next + 2];
+                fused[i + 3] = 0;
+                true
+            }
+            // The loops of `dolist` and `cl-loop` take the `car` and `cdr` of a
+            // list, or index a vector, held on the stack
+            (r, code) => match (stack_ref_index(r), op::try_from(code)) {
+                (Some(idx), Ok(code @ (op::Car | op::Cdr | op::Aref))) => {
+                    fused[i] = match code {
+                        op::Car => op::StackRefCar,
+                        op::Cdr => op::StackRefCdr,
+                        _ => op::StackRefAref,
+                    } as u8;
+                    fused[next] = idx;
+                    true
+                }
+                _ => false,
+            },
+        };
+        // The second instruction is now part of the first
+        if replaced {
+            pairs.next();
+        }
+    }
+    fused
+}
+
+/// Expand fused instructions back into the pairs they replaced.
+pub(crate) fn defuse(codes: &[u8]) -> Vec<u8> {
+    let mut original = codes.to_vec();
+    let Some(starts) = instructions(codes) else { return original };
+    for i in starts {
+        match op::try_from(codes[i]) {
+            Ok(op::ConstantCall1) => {
+                original[i] = CONSTANT0 + codes[i + 1];
+                original[i + 1] = op::Call1 as u8;
+            }
+            Ok(op::StackRefAdd) => {
+                original[i] = op::StackRef0 as u8 + codes[i + 1];
+                original[i + 1] = op::Plus as u8;
+            }
+            Ok(op::DupGotoIfNil) => {
+                original[i] = op::Duplicate as u8;
+                original[i + 1] = op::GotoIfNil as u8;
+                original[i + 2] = codes[i + 1];
+                original[i + 3] = codes[i + 2];
+            }
+            Ok(op::StackRefCar) => defuse_stack_ref(&mut original[i..], codes[i + 1], op::Car),
+            Ok(op::StackRefCdr) => defuse_stack_ref(&mut original[i..], codes[i + 1], op::Cdr),
+            Ok(op::StackRefAref) => defuse_stack_ref(&mut original[i..], codes[i + 1], op::Aref),
+            Ok(op::Add1StackSet) => {
+                original[i] = op::Add1 as u8;
+                original[i + 1] = op::StackSetN as u8;
+                original[i + 2] = codes[i + 1];
+            }
+            Ok(op::LessThanGotoIfNil) => {
+                original[i] = op::LessThan as u8;
+                original[i + 1] = op::GotoIfNil as u8;
+                original[i + 2] = codes[i + 1];
+                original[i + 3] = codes[i + 2];
+            }
+            _ => {}
+        }
+    }
+    original
+}
+
+/// Expand a stack-ref of `idx` fused with `code` at the start of `original`.
+fn defuse_stack_ref(original: &mut [u8], idx: u8, code: op) {
+    original[0] = match idx {
+        0 => op::Duplicate as u8,
+        _ => op::StackRef0 as u8 + idx,
+    };
+    original[1] = code as u8;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bytecode::opcode::OpCode::*;
+    use crate::core::gc::{Context, RootSet};
+    use crate::core::object::HashTable;
+
+    #[test]
+    fn test_fuse() {
+        let codes = [
+            Constant2 as u8,
+            Call1 as u8,
+            StackRef2 as u8,
+            Plus as u8,
+            Duplicate as u8,
+            GotoIfNil as u8,
+            0x00,
+            0x00,
+            Return as u8,
+        ];
+        let fused = fuse(&codes, std::iter::empty());
+        let expect = [
+            ConstantCall1 as u8,
+            2,
+            StackRefAdd as u8,
+            2,
+            DupGotoIfNil as u8,
+            0x00,
+            0x00,
+            0x00,
+            Return as u8,
+        ];
+        assert_eq!(fused, expect);
+        assert_eq!(defuse(&fused), codes);
+    }
+
+    #[test]
+    fn test_fuse_loops() {
+        let codes = [
+            Duplicate as u8,
+            Car as u8,
+            StackRef1 as u8,
+            Cdr as u8,
+            StackRef3 as u8,
+            Aref as u8,
+            Add1 as u8,
+            StackSetN as u8,
+            0x02,
+            LessThan as u8,
+            GotoIfNil as u8,
+            0x00,
+            0x00,
+        ];
+        let fused = fuse(&codes, std::iter::empty());
+        let expect = [
+            StackRefCar as u8,
+            0,
+            StackRefCdr as u8,
+            1,
+            StackRefAref as u8,
+            3,
+            Add1StackSet as u8,
+            0x02,
+            0x00,
+            LessThanGotoIfNil as u8,
+            0x00,
+            0x00,
+            0x00,
+        ];
+        assert_eq!(fused, expect);
+        assert_eq!(defuse(&fused), codes);
+    }
+
+    #[test]
+    fn test_jump_target_not_fused() {
+        // the Call1 at offset 1 is a jump target
+        let codes = [Constant0 as u8, Call1 as u8, Goto as u8, 0x01, 0x00];
+        assert_eq!(fuse(&codes, std::iter::empty()), codes);
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let mut table = HashTable::default();
+        table.insert(1.into(), 3.into());
+        let table = cx.add(table);
+        let codes = [Constant0 as u8, Duplicate as u8, Constant1 as u8, Call1 as u8, Return as u8];
+        assert_eq!(fuse(&codes, std::iter::once(table)), codes);
+    }
+
+    #[test]
+    fn test_invalid_code_not_fused() {
+        let codes = [Constant0 as u8, Call1 as u8, GotoIfNil as u8];
+        assert_eq!(fuse(&codes, std::iter::empty()), codes);
+        assert_eq!(defuse(&codes), codes);
+    }
+}
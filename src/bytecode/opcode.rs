@@ -188,14 +188,15 @@ pub(crate) enum OpCode {
     // Unused181,
     DiscardN = 182,
     Switch = 183,
-    // Unused184,
-    // Unused185,
-    // Unused186,
-    // Unused187,
-    // Unused188,
-    // Unused189,
-    // Unused190,
-    // Unused191,
+    // Fused instructions, see `fuse.rs`
+    ConstantCall1 = 184,
+    StackRefAdd = 185,
+    DupGotoIfNil = 186,
+    StackRefCar = 187,
+    StackRefCdr = 188,
+    StackRefAref = 189,
+    Add1StackSet = 190,
+    LessThanGotoIfNil = 191,
     Constant0 = 192,
     Constant1 = 193,
     Constant2 = 194,
@@ -0,0 +1,247 @@
+//! Calling functions as commands.
+//!
+//! A command is a function with an `interactive` declaration. The spec inside
+//! it says how to get the arguments when the command is run by the user
+//! instead of called from lisp. It is either a string of code letters, one per
+//! line, or a form that evaluates to the list of arguments.
+use crate::core::{
+    cons::Cons,
+    env::{sym, CallFrame, Env},
+    gc::{Context, Rt, Rto, Slot},
+    object::{Function, FunctionType, Object, ObjectType, NIL},
+};
+use anyhow::{bail, Result};
+use rune_core::macros::{rebind, root};
+use rune_macros::defun;
+
+defvar!(CURRENT_PREFIX_ARG);
+defsym!(READ_STRING);
+defsym!(DECLARE);
+
+/// The `interactive` form in a lambda `body`. It comes after the docstring and
+/// any `declare` forms.
+fn body_interactive(body: Object) -> Option<&Cons> {
+    let mut forms = body.as_list().ok()?;
+    while let Some(Ok(form)) = forms.next() {
+        match form.untag() {
+            ObjectType::String(_) => continue,
+            ObjectType::Cons(cons) if cons.car() == sym::DECLARE => continue,
+            ObjectType::Cons(cons) if cons.car() == sym::INTERACTIVE => return Some(cons),
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// The spec of `func` if it is a command. `(interactive)` has a spec of nil.
+fn interactive_spec(func: Function) -> Option<Object> {
+    match func.untag() {
+        FunctionType::ByteFn(func) => func.interactive(),
+        FunctionType::Cons(form) => {
+            let ObjectType::Cons(rest) = form.cdr().untag() else { return None };
+            let body = match form.car().untag() {
+                // (lambda ARGS . BODY)
+                ObjectType::Symbol(sym::LAMBDA) => rest.cdr(),
+                // (closure ENV ARGS . BODY)
+                ObjectType::Symbol(sym::CLOSURE) => match rest.cdr().untag() {
+                    ObjectType::Cons(rest) => rest.cdr(),
+                    _ => return None,
+                },
+                _ => return None,
+            };
+            let interactive = body_interactive(body)?;
+            match interactive.cdr().untag() {
+                ObjectType::Cons(spec) => Some(spec.car()),
+                _ => Some(NIL),
+            }
+        }
+        FunctionType::SubrFn(_) | FunctionType::Symbol(_) => None,
+    }
+}
+
+#[defun]
+fn commandp(function: Object, for_call_interactively: Option<()>, cx: &Context) -> bool {
+    // Strings and vectors are keyboard macros
+    if matches!(function.untag(), ObjectType::String(_) | ObjectType::Vec(_)) {
+        return for_call_interactively.is_none();
+    }
+    let Ok(func) = Function::try_from(function) else { return false };
+    let func = match func.untag() {
+        FunctionType::Symbol(symbol) => match symbol.follow_indirect(cx) {
+            Some(func) => func,
+            None => return false,
+        },
+        _ => func,
+    };
+    match func.untag() {
+        // (autoload FILE DOC INTERACTIVE TYPE)
+        FunctionType::Cons(form) if form.car() == sym::AUTOLOAD => {
+            let mut elements = form.elements().skip(3);
+            matches!(elements.next(), Some(Ok(interactive)) if interactive != NIL)
+        }
+        _ => interactive_spec(func).is_some(),
+    }
+}
+
+#[defun]
+fn prefix_numeric_value(raw: Object) -> i64 {
+    match raw.untag() {
+        ObjectType::NIL => 1,
+        ObjectType::Int(n) => n,
+        ObjectType::Symbol(sym::SUB) => -1,
+        ObjectType::Cons(cons) => match cons.car().untag() {
+            ObjectType::Int(n) => n,
+            _ => 1,
+        },
+        _ => 1,
+    }
+}
+
+#[defun]
+fn call_interactively<'ob>(
+    function: &Rto<Object>,
+    _record_flag: Option<()>,
+    _keys: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let func = match function.untag(cx) {
+        ObjectType::Symbol(symbol) => match symbol.follow_indirect(cx) {
+            Some(func) => func,
+            None => bail!("Symbol's function definition is void: {symbol}"),
+        },
+        _ => function.bind(cx).try_into()?,
+    };
+    root!(func, cx);
+    if let FunctionType::Cons(form) = func.untag(cx) {
+        if form.car() == sym::AUTOLOAD {
+            crate::eval::autoload_do_load(func.cast(), None, None, env, cx)?;
+            let symbol: Function = function.bind(cx).try_into()?;
+            let loaded = match symbol.untag() {
+                FunctionType::Symbol(symbol) => symbol.follow_indirect(cx),
+                _ => None,
+            };
+            let Some(loaded) = loaded else { bail!("Autoloading failed to define {function}") };
+            func.set(loaded);
+        }
+    }
+    let Some(spec) = interactive_spec(func.bind(cx)) else {
+        bail!("Wrong type argument: commandp, {function}")
+    };
+    root!(args, new(Vec<Slot<Object>>), cx);
+    match spec.untag() {
+        ObjectType::String(codes) => {
+            let codes = codes.to_string();
+            interactive_args(&codes, args, env, cx)?;
+        }
+        ObjectType::NIL => {}
+        _ => {
+            root!(spec, cx);
+            let list = rebind!(crate::interpreter::eval(spec, None, env, cx)?);
+            for arg in list.as_list()? {
+                args.push(arg?);
+            }
+        }
+    }
+    let frame = &mut CallFrame::new(env);
+    for arg in args.iter() {
+        frame.push_arg(arg);
+    }
+    func.call(frame, None, cx).map_err(Into::into)
+}
+
+/// Collect the arguments for a string spec. Each line starts with a code
+/// letter that is followed by a prompt.
+fn interactive_args(
+    codes: &str,
+    args: &mut Rt<Vec<Slot<Object>>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    // These prefixes are about the buffer and the mark, which we don't have
+    let codes = codes.trim_start_matches(['*', '@', '^']);
+    if codes.is_empty() {
+        return Ok(());
+    }
+    for line in codes.split('\n') {
+        let mut chars = line.chars();
+        let code = chars.next();
+        let prompt = chars.as_str();
+        let prefix = crate::data::symbol_value(sym::CURRENT_PREFIX_ARG, env, cx);
+        match code {
+            Some('p') => args.push(prefix_numeric_value(prefix.unwrap_or_default()).into()),
+            Some('P') => args.push(prefix.unwrap_or_default()),
+            Some('i') => args.push(NIL),
+            Some('s') => {
+                let Some(reader) = sym::READ_STRING.follow_indirect(cx) else {
+                    bail!("Interactive code `s' needs `read-string' to be defined")
+                };
+                root!(reader, cx);
+                let frame = &mut CallFrame::new(env);
+                frame.push_arg(cx.add(prompt));
+                let value = rebind!(reader.call(frame, None, cx)?);
+                args.push(value);
+            }
+            Some('r') => bail!("The mark is not set now, so there is no region"),
+            Some(c) => bail!("Invalid control letter `{c}' in interactive calling string"),
+            None => bail!("Empty line in interactive calling string"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::interpreter::eval_to_string;
+
+    #[test]
+    fn test_commandp() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        assert_eq!(eval_to_string("(commandp (lambda () (interactive) 1))", env, cx).unwrap(), "t");
+        let declare = "(commandp (lambda () \"doc\" (declare (pure t)) (interactive \"p\")))";
+        assert_eq!(eval_to_string(declare, env, cx).unwrap(), "t");
+        assert_eq!(eval_to_string("(commandp (lambda () 1))", env, cx).unwrap(), "nil");
+        assert_eq!(eval_to_string("(commandp #'car)", env, cx).unwrap(), "nil");
+        assert_eq!(eval_to_string("(commandp \"keys\")", env, cx).unwrap(), "t");
+        assert_eq!(eval_to_string("(commandp \"keys\" t)", env, cx).unwrap(), "nil");
+        let alias = "(progn (defalias 'callint-cmd #'(lambda () (interactive) 1))
+                            (commandp 'callint-cmd))";
+        assert_eq!(eval_to_string(alias, env, cx).unwrap(), "t");
+    }
+
+    #[test]
+    fn test_call_interactively() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        let prefix = "(let ((current-prefix-arg '(4)))
+                        (equal (call-interactively
+                                (lambda (n raw) (interactive \"p\nP\") (list n raw)))
+                               '(4 (4))))";
+        assert_eq!(eval_to_string(prefix, env, cx).unwrap(), "t");
+        let default = "(eq (call-interactively (lambda (n) (interactive \"*p\") n)) 1)";
+        assert_eq!(eval_to_string(default, env, cx).unwrap(), "t");
+        let form = "(eq (call-interactively (lambda (a b) (interactive (list 1 2)) (+ a b))) 3)";
+        assert_eq!(eval_to_string(form, env, cx).unwrap(), "t");
+        let string = "(progn (defalias 'read-string #'(lambda (prompt) (concat prompt \"!\")))
+                             (equal (call-interactively (lambda (s) (interactive \"sName\") s))
+                                    \"Name!\"))";
+        assert_eq!(eval_to_string(string, env, cx).unwrap(), "t");
+        assert!(eval_to_string("(call-interactively (lambda () 1))", env, cx).is_err());
+        let region = "(call-interactively (lambda (x) (interactive \"r\") x))";
+        assert!(eval_to_string(region, env, cx).is_err());
+        let unknown = "(call-interactively (lambda (x) (interactive \"q\") x))";
+        assert!(eval_to_string(unknown, env, cx).is_err());
+    }
+
+    #[test]
+    fn test_prefix_numeric_value() {
+        assert_eq!(prefix_numeric_value(NIL), 1);
+        assert_eq!(prefix_numeric_value(sym::SUB.into()), -1);
+        assert_eq!(prefix_numeric_value(3.into()), 3);
+    }
+}
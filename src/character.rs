@@ -1,10 +1,20 @@
 //! Character and string utilities.
 use crate::core::{
-    gc::Context,
-    object::{int_to_char, Gc, Object, ObjectType},
+    env::{intern, sym, Env},
+    gc::{Context, Rt},
+    object::{int_to_char, Gc, Object, ObjectType, Symbol, NIL},
 };
 use anyhow::Result;
 use rune_macros::defun;
+use std::cmp::Ordering;
+
+mod tables;
+
+defsym!(GENERAL_CATEGORY);
+defsym!(CANONICAL_COMBINING_CLASS);
+defsym!(DECIMAL_DIGIT_VALUE);
+defsym!(UPPERCASE);
+defsym!(LOWERCASE);
 
 #[defun]
 fn unibyte_string(bytes: &[Gc<i64>]) -> Result<Vec<u8>> {
@@ -59,3 +69,211 @@ fn make_string<'ob>(
         Ok(cx.add(string))
     }
 }
+
+/// The entry of `table` whose range contains `c`. The ranges are sorted and
+/// don't overlap.
+fn find<T>(table: &[T], c: u32, range: impl Fn(&T) -> (u32, u32)) -> Option<&T> {
+    let i = table
+        .binary_search_by(|entry| match range(entry) {
+            (_, end) if end < c => Ordering::Less,
+            (start, _) if start > c => Ordering::Greater,
+            _ => Ordering::Equal,
+        })
+        .ok()?;
+    Some(&table[i])
+}
+
+fn in_ranges(table: &[(u32, u32)], c: u32) -> bool {
+    find(table, c, |&range| range).is_some()
+}
+
+fn category_entry(c: char) -> Option<&'static (u32, u32, &'static str)> {
+    find(&tables::GENERAL_CATEGORY, c.into(), |&(start, end, _)| (start, end))
+}
+
+/// The two letter name of the Unicode general category of `c`, like `Lu`.
+pub(crate) fn general_category(c: char) -> &'static str {
+    category_entry(c).map_or("Cn", |&(_, _, category)| category)
+}
+
+/// The number of columns `c` takes on display. Control characters are shown
+/// as `^C`, and the C1 controls as an octal escape like `\200`.
+pub(crate) fn display_width(c: char, tab_width: usize) -> usize {
+    match c {
+        '\t' => tab_width,
+        '\n' => 0,
+        '\0'..='\x1F' | '\x7F' => 2,
+        '\u{80}'..='\u{9F}' => 4,
+        _ if in_ranges(&tables::ZERO_WIDTH, c.into()) => 0,
+        _ if in_ranges(&tables::WIDE, c.into()) => 2,
+        _ => 1,
+    }
+}
+
+/// The value of `tab-width`, falling back to 8 when it is not a sensible
+/// width, like the display does.
+fn tab_width(env: &Rt<Env>, cx: &Context) -> usize {
+    match env.vars.get(sym::TAB_WIDTH).map(|x| x.bind(cx).untag()) {
+        Some(ObjectType::Int(width @ 1..=1000)) => width as usize,
+        _ => 8,
+    }
+}
+
+/// Return the width of CHAR in columns when it is displayed. Wide East Asian
+/// characters take two columns and combining marks take none.
+#[defun]
+fn char_width(chr: i64, env: &Rt<Env>, cx: &Context) -> Result<usize> {
+    Ok(display_width(int_to_char(chr)?, tab_width(env, cx)))
+}
+
+/// Return the width of STRING in columns when it is displayed, or of the
+/// characters FROM..TO in it. See `char-width`.
+#[defun]
+fn string_width(
+    string: &str,
+    from: Option<i64>,
+    to: Option<i64>,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<usize> {
+    let (from, to) = crate::fns::char_range(from, to, string.chars().count())?;
+    let tab_width = tab_width(env, cx);
+    Ok(string.chars().take(to).skip(from).map(|c| display_width(c, tab_width)).sum())
+}
+
+/// Return t if CHAR is an uppercase letter.
+#[defun]
+fn char_uppercase_p(chr: i64) -> Result<bool> {
+    Ok(int_to_char(chr)?.is_uppercase())
+}
+
+/// The simple case mapping of `c`, which is `c` itself when it has none or
+/// when it maps to more than one character.
+fn simple_case(c: char, mut mapping: impl Iterator<Item = char>) -> char {
+    match (mapping.next(), mapping.next()) {
+        (Some(mapped), None) => mapped,
+        _ => c,
+    }
+}
+
+/// Return the Unicode property PROPNAME of CHAR. The properties are
+/// `general-category`, a symbol like `Lu`, `canonical-combining-class`,
+/// `decimal-digit-value`, and the simple case mappings `uppercase` and
+/// `lowercase`. Other properties are nil.
+#[defun]
+fn get_char_code_property<'ob>(
+    chr: i64,
+    propname: Symbol,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let c = int_to_char(chr)?;
+    let value = match propname {
+        sym::GENERAL_CATEGORY => intern(general_category(c), cx).into(),
+        sym::CANONICAL_COMBINING_CLASS => {
+            let class = find(&tables::COMBINING_CLASS, c.into(), |&(start, end, _)| (start, end));
+            i64::from(class.map_or(0, |&(_, _, class)| class)).into()
+        }
+        sym::DECIMAL_DIGIT_VALUE => match category_entry(c) {
+            // Decimal digits are assigned in runs from zero to nine
+            Some(&(start, _, "Nd")) => i64::from((u32::from(c) - start) % 10).into(),
+            _ => NIL,
+        },
+        sym::UPPERCASE => i64::from(u32::from(simple_case(c, c.to_uppercase()))).into(),
+        sym::LOWERCASE => i64::from(u32::from(simple_case(c, c.to_lowercase()))).into(),
+        _ => NIL,
+    };
+    Ok(value)
+}
+
+/// Return a description of VALUE of the character property PROPERTY, or nil
+/// if there is none. Only the values of `general-category` are described.
+#[defun]
+fn char_code_property_description(property: Symbol, value: Symbol) -> Option<&'static str> {
+    if property != sym::GENERAL_CATEGORY {
+        return None;
+    }
+    let description = match value.name() {
+        "Lu" => "Letter, Uppercase",
+        "Ll" => "Letter, Lowercase",
+        "Lt" => "Letter, Titlecase",
+        "Lm" => "Letter, Modifier",
+        "Lo" => "Letter, Other",
+        "Mn" => "Mark, Nonspacing",
+        "Mc" => "Mark, Spacing Combining",
+        "Me" => "Mark, Enclosing",
+        "Nd" => "Number, Decimal Digit",
+        "Nl" => "Number, Letter",
+        "No" => "Number, Other",
+        "Pc" => "Punctuation, Connector",
+        "Pd" => "Punctuation, Dash",
+        "Ps" => "Punctuation, Open",
+        "Pe" => "Punctuation, Close",
+        "Pi" => "Punctuation, Initial quote",
+        "Pf" => "Punctuation, Final quote",
+        "Po" => "Punctuation, Other",
+        "Sm" => "Symbol, Math",
+        "Sc" => "Symbol, Currency",
+        "Sk" => "Symbol, Modifier",
+        "So" => "Symbol, Other",
+        "Zs" => "Separator, Space",
+        "Zl" => "Separator, Line",
+        "Zp" => "Separator, Paragraph",
+        "Cc" => "Other, Control",
+        "Cf" => "Other, Format",
+        "Cs" => "Other, Surrogate",
+        "Co" => "Other, Private Use",
+        "Cn" => "Other, Not Assigned",
+        _ => return None,
+    };
+    Some(description)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+
+    #[test]
+    fn test_general_category() {
+        assert_eq!(general_category('A'), "Lu");
+        assert_eq!(general_category('é'), "Ll");
+        assert_eq!(general_category('٣'), "Nd");
+        assert_eq!(general_category('\u{301}'), "Mn");
+        assert_eq!(general_category('\u{378}'), "Cn");
+        assert_eq!(general_category('\u{10FFFF}'), "Cn");
+    }
+
+    #[test]
+    fn test_char_width() {
+        assert_eq!(display_width('a', 8), 1);
+        assert_eq!(display_width('中', 8), 2);
+        assert_eq!(display_width('\u{301}', 8), 0);
+        assert_eq!(display_width('\t', 4), 4);
+        assert_eq!(display_width('\x01', 8), 2);
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        rune_core::macros::root!(env, new(Env), cx);
+        assert_eq!(string_width("a中e\u{301}", None, None, env, cx).unwrap(), 4);
+        assert_eq!(string_width("a中e", Some(1), Some(2), env, cx).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_char_code_property() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        sym::init_symbols();
+        let get = |c: char, prop| get_char_code_property(c as i64, prop, cx).unwrap();
+        assert_eq!(get('a', sym::GENERAL_CATEGORY), intern("Ll", cx));
+        assert_eq!(get('\u{301}', sym::CANONICAL_COMBINING_CLASS), 230);
+        assert_eq!(get('٣', sym::DECIMAL_DIGIT_VALUE), 3);
+        assert_eq!(get('a', sym::DECIMAL_DIGIT_VALUE), NIL);
+        assert_eq!(get('a', sym::UPPERCASE), 'A' as i64);
+        assert_eq!(get('ß', sym::UPPERCASE), 'ß' as i64);
+        assert!(char_uppercase_p('Ä' as i64).unwrap());
+        assert!(!char_uppercase_p('1' as i64).unwrap());
+        let category = intern("Lu", cx);
+        let description = char_code_property_description(sym::GENERAL_CATEGORY, category);
+        assert_eq!(description, Some("Letter, Uppercase"));
+    }
+}
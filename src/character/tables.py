@@ -0,0 +1,68 @@
+"""Generate tables.rs from the Unicode character database.
+
+Run from this directory with `python3 tables.py > tables.rs`. The tables use
+the database that ships with Python's `unicodedata` module.
+"""
+import unicodedata
+
+MAX = 0x110000
+
+
+def ranges(value, skip):
+    """The runs of codepoints with the same `value`, leaving out `skip`."""
+    runs = []
+    for c in range(MAX):
+        v = value(chr(c))
+        if runs and runs[-1][2] == v:
+            runs[-1][1] = c
+        else:
+            runs.append([c, c, v])
+    return [run for run in runs if run[2] != skip]
+
+
+def table(name, doc, ty, entries, per_line):
+    print(f"/// {doc}")
+    print("#[rustfmt::skip]")
+    print(f"pub(super) static {name}: [{ty}; {len(entries)}] = [")
+    for i in range(0, len(entries), per_line):
+        print("    " + " ".join(e + "," for e in entries[i : i + per_line]))
+    print("];")
+
+
+print(f"//! Unicode {unicodedata.unidata_version} character properties.")
+print("//!")
+print("//! Generated by `tables.py`, don't edit by hand.")
+print()
+table(
+    "GENERAL_CATEGORY",
+    "The general category of each assigned codepoint. Others are `Cn`.",
+    "(u32, u32, &str)",
+    [f'(0x{s:X}, 0x{e:X}, "{v}")' for s, e, v in ranges(unicodedata.category, "Cn")],
+    3,
+)
+print()
+table(
+    "COMBINING_CLASS",
+    "The nonzero canonical combining classes.",
+    "(u32, u32, u8)",
+    [f"(0x{s:X}, 0x{e:X}, {v})" for s, e, v in ranges(unicodedata.combining, 0)],
+    3,
+)
+print()
+table(
+    "WIDE",
+    "Characters that are wide or fullwidth in East Asian text.",
+    "(u32, u32)",
+    [f"(0x{s:X}, 0x{e:X})" for s, e, _ in
+     ranges(lambda c: unicodedata.east_asian_width(c) in "WF", False)],
+    4,
+)
+print()
+table(
+    "ZERO_WIDTH",
+    "Nonspacing and enclosing marks and format characters, which take no space.",
+    "(u32, u32)",
+    [f"(0x{s:X}, 0x{e:X})" for s, e, _ in
+     ranges(lambda c: unicodedata.category(c) in ("Mn", "Me", "Cf"), False)],
+    4,
+)
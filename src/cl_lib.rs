@@ -0,0 +1,235 @@
+//! Native versions of the hottest `cl-lib` functions.
+//!
+//! The sequence functions in `cl-seq.el` take keywords for the test, key and
+//! bounds, and parsing them on every call is slow. When a call has no
+//! keywords, the lisp definition hands off to the `cl--native-` function here,
+//! which uses `eql` on the whole sequence. `cl-getf`, `cl-some` and
+//! `cl-every` belong to `cl-extra.el`, which is not in this tree, so they are
+//! defined here under their own names.
+use crate::core::{
+    env::{ArgSlice, CallFrame, Env},
+    error::{Type, TypeError},
+    gc::{Context, Rt, Rto, Slot},
+    object::{Function, List, Object, ObjectType, NIL},
+};
+use crate::fns::{eq, eql, slice_into_list};
+use crate::seq::as_sequence;
+use anyhow::Result;
+use rune_core::macros::{call, root};
+use rune_macros::defun;
+
+fn position(item: Object, seq: Object) -> Result<Option<usize>> {
+    match seq.untag() {
+        ObjectType::NIL => Ok(None),
+        ObjectType::Cons(cons) => {
+            for (i, elem) in cons.elements().enumerate() {
+                if eql(item, elem?) {
+                    return Ok(Some(i));
+                }
+            }
+            Ok(None)
+        }
+        ObjectType::Vec(vec) => Ok(vec.iter().position(|x| eql(item, x.get()))),
+        ObjectType::String(s) => match item.untag() {
+            ObjectType::Int(c) => Ok(s.chars().position(|x| i64::from(u32::from(x)) == c)),
+            _ => Ok(None),
+        },
+        other => Err(TypeError::new(Type::Sequence, other).into()),
+    }
+}
+
+/// Return the index of the first element of SEQ that is `eql` to ITEM, or
+/// nil if there is none.
+#[defun]
+fn cl__native_position(item: Object, seq: Object) -> Result<Option<usize>> {
+    position(item, seq)
+}
+
+/// Return the first element of SEQ that is `eql` to ITEM, or nil if there is
+/// none.
+#[defun]
+fn cl__native_find<'ob>(
+    item: Object<'ob>,
+    seq: Object<'ob>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    match position(item, seq)? {
+        Some(i) => crate::fns::elt(seq, i, cx),
+        None => Ok(NIL),
+    }
+}
+
+/// Return a copy of SEQ without the elements that satisfy PREDICATE. If no
+/// element does, SEQ itself is returned.
+#[defun]
+fn cl__native_remove_if<'ob>(
+    predicate: &Rto<Function>,
+    seq: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    root!(elems, new(Vec<Slot<Object>>), cx);
+    elems.extend_from_slice(&as_sequence(seq.bind(cx))?.elements()?);
+    let mut keep = Vec::with_capacity(elems.len());
+    for i in 0..elems.len() {
+        keep.push(call!(predicate, &elems[i]; env, cx)?.is_nil());
+    }
+    if keep.iter().all(|x| *x) {
+        return Ok(seq.bind(cx));
+    }
+    let kept = elems.iter().zip(&keep).filter(|x| *x.1).map(|x| x.0.bind(cx));
+    match seq.bind(cx).untag() {
+        ObjectType::Vec(_) => Ok(cx.add(kept.collect::<Vec<_>>())),
+        ObjectType::String(s) => {
+            let chars = s.chars().zip(&keep).filter(|x| *x.1).map(|x| x.0);
+            Ok(cx.add(chars.collect::<String>()))
+        }
+        _ => Ok(slice_into_list(&kept.collect::<Vec<_>>(), None, cx)),
+    }
+}
+
+/// Combine the elements of SEQ from the left by calling FUNCTION with the
+/// result so far and the next element. An empty SEQ returns the result of
+/// calling FUNCTION with no arguments, and a SEQ with one element returns
+/// that element.
+#[defun]
+fn cl__native_reduce<'ob>(
+    function: &Rto<Function>,
+    seq: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    root!(elems, new(Vec<Slot<Object>>), cx);
+    elems.extend_from_slice(&as_sequence(seq.bind(cx))?.elements()?);
+    if elems.is_empty() {
+        return Ok(call!(function; env, cx)?);
+    }
+    let first = elems[0].bind(cx);
+    root!(acc, first, cx);
+    for i in 1..elems.len() {
+        let result = call!(function, &*acc, &elems[i]; env, cx)?;
+        acc.set(result);
+    }
+    Ok(acc.bind(cx))
+}
+
+/// Search PLIST for property TAG and return its value, or DEF if TAG is not
+/// there. Properties are compared with `eq`.
+#[defun]
+fn cl_getf<'ob>(plist: Object<'ob>, tag: Object, def: Option<Object<'ob>>) -> Result<Object<'ob>> {
+    let plist: List = plist.try_into()?;
+    let mut iter = plist.elements();
+    while let Some(prop) = iter.next() {
+        let Some(value) = iter.next() else { break };
+        if eq(prop?, tag) {
+            return Ok(value?);
+        }
+    }
+    Ok(def.unwrap_or(NIL))
+}
+
+/// Call `predicate` on the elements of `seq` and `rest` in parallel, up to the
+/// end of the shortest one. Return the first result that is nil if
+/// `stop_on_nil`, or else the first one that isn't.
+fn first_result<'ob>(
+    predicate: &Rto<Function>,
+    seq: &Rto<Object>,
+    rest: ArgSlice,
+    stop_on_nil: bool,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Option<Object<'ob>>> {
+    let rest = env.stack.arg_slice(rest).iter().map(|x| x.bind(cx));
+    let seqs: Vec<Object> = std::iter::once(seq.bind(cx)).chain(rest).collect();
+    // The elements of every sequence, one after the other
+    root!(elems, new(Vec<Slot<Object>>), cx);
+    let mut starts = Vec::with_capacity(seqs.len());
+    let mut len = usize::MAX;
+    for seq in seqs {
+        let seq = as_sequence(seq)?.elements()?;
+        starts.push(elems.len());
+        len = len.min(seq.len());
+        elems.extend_from_slice(&seq);
+    }
+    root!(found, new(Vec<Slot<Object>>), cx);
+    for i in 0..len {
+        let frame = &mut CallFrame::new(env);
+        for start in &starts {
+            frame.push_arg(&elems[start + i]);
+        }
+        let result = predicate.call(frame, None, cx)?;
+        if result.is_nil() == stop_on_nil {
+            found.push(result);
+            break;
+        }
+    }
+    Ok(found.first().map(|x| x.bind(cx)))
+}
+
+/// Call PREDICATE on each element of SEQ, and return the first non-nil
+/// result. With more sequences, PREDICATE is called with an element of each.
+#[defun]
+fn cl_some<'ob>(
+    predicate: &Rto<Function>,
+    seq: &Rto<Object>,
+    rest: ArgSlice,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    Ok(first_result(predicate, seq, rest, false, env, cx)?.unwrap_or(NIL))
+}
+
+/// Return t if PREDICATE is true for every element of SEQ. With more
+/// sequences, PREDICATE is called with an element of each.
+#[defun]
+fn cl_every(
+    predicate: &Rto<Function>,
+    seq: &Rto<Object>,
+    rest: ArgSlice,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    Ok(first_result(predicate, seq, rest, true, env, cx)?.is_none())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::{env::sym, gc::RootSet};
+    use crate::interpreter::eval_to_string;
+
+    #[test]
+    fn test_sequences() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        assert_eq!(eval_to_string("(cl--native-position 3 [1 2 3])", env, cx).unwrap(), "2");
+        assert_eq!(eval_to_string("(cl--native-position ?b \"abc\")", env, cx).unwrap(), "1");
+        assert_eq!(eval_to_string("(cl--native-position 4 '(1 2 3))", env, cx).unwrap(), "nil");
+        assert_eq!(eval_to_string("(cl--native-find 2.0 '(1 2.0))", env, cx).unwrap(), "2.0");
+        let src = "(cl--native-remove-if #'(lambda (x) (eq x ?a)) \"banana\")";
+        assert_eq!(eval_to_string(src, env, cx).unwrap(), "\"bnn\"");
+        let src = "(cl--native-remove-if #'(lambda (x) (> x 1)) [1 2 3 0])";
+        assert_eq!(eval_to_string(src, env, cx).unwrap(), "[1 0]");
+        assert_eq!(eval_to_string("(cl--native-reduce #'+ '(1 2 3))", env, cx).unwrap(), "6");
+        assert_eq!(eval_to_string("(cl--native-reduce #'+ nil)", env, cx).unwrap(), "0");
+        assert_eq!(eval_to_string("(cl--native-reduce #'+ [5])", env, cx).unwrap(), "5");
+    }
+
+    #[test]
+    fn test_extra() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        assert_eq!(eval_to_string("(cl-getf '(:a 1 :b nil) :b 5)", env, cx).unwrap(), "nil");
+        assert_eq!(eval_to_string("(cl-getf '(:a 1) :c 5)", env, cx).unwrap(), "5");
+        let src = "(cl-some #'(lambda (x y) (and (> x y) x)) '(1 5 3) [2 4 6])";
+        assert_eq!(eval_to_string(src, env, cx).unwrap(), "5");
+        assert_eq!(eval_to_string("(cl-some #'numberp '(a b))", env, cx).unwrap(), "nil");
+        assert_eq!(eval_to_string("(cl-every #'numberp \"ab\")", env, cx).unwrap(), "t");
+        assert_eq!(eval_to_string("(cl-every #'numberp '(1 a))", env, cx).unwrap(), "nil");
+        assert_eq!(eval_to_string("(cl-every #'eq '(1 2) '(1 2 3))", env, cx).unwrap(), "t");
+    }
+}
@@ -0,0 +1,166 @@
+//! The `rune` executable, a command line like `emacs` with a REPL.
+use crate::core::{
+    cons::Cons,
+    env::{sym, Env},
+    gc::{Context, RootSet, Rt},
+    object::{Gc, LispString, NIL},
+};
+use crate::eval::{ErrorType, EvalError};
+use crate::{buffer, command_line, interpreter, pdump, reader};
+use clap::{CommandFactory, FromArgMatches, Parser};
+use rune_core::macros::root;
+use std::io::{self, Write};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Load FILE. Files to load, expressions to evaluate and functions to call
+    /// are run in the order they are given
+    #[arg(short, long, value_name = "FILE")]
+    load: Vec<String>,
+    /// Evaluate EXPR
+    #[arg(long, value_name = "EXPR")]
+    eval: Vec<String>,
+    /// Call FUNCTION with no arguments
+    #[arg(short, long, value_name = "FUNCTION")]
+    funcall: Vec<String>,
+    #[arg(short, long)]
+    repl: bool,
+    /// Don't print the values of loaded files and evaluated expressions
+    #[arg(long)]
+    batch: bool,
+    #[arg(short, long)]
+    no_bootstrap: bool,
+    /// Restore a state saved with `dump-emacs-portable` instead of loading the
+    /// standard library
+    #[arg(long, value_name = "FILE")]
+    dump_file: Option<String>,
+    /// Arguments for lisp code, available in `command-line-args-left`
+    #[arg(last = true, value_name = "ARGS")]
+    args: Vec<String>,
+}
+
+/// Run the interpreter with the arguments from the command line.
+pub fn run() -> Result<(), ()> {
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    #[cfg(feature = "tracing")]
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(io::stderr)
+        .init();
+
+    let roots = &RootSet::default();
+    let cx = &mut Context::new(roots);
+    root!(env, new(Env), cx);
+
+    sym::init_symbols();
+    crate::core::env::init_variables(cx, env);
+    crate::data::defalias(sym::NOT, (sym::NULL).into(), None, env)
+        .expect("null should be defined");
+    crate::emacs::init_process_vars(env, cx);
+    crate::eval::init_errors(env, cx);
+    command_line::set_args(&args.args, env, cx).expect("command line variables should be settable");
+
+    if let Some(file) = &args.dump_file {
+        restore_dump(file, env, cx)?;
+    } else if !args.no_bootstrap {
+        bootstrap(env, cx)?;
+    }
+
+    for action in command_line::actions(&matches) {
+        if let Err(e) = command_line::run(&action, args.batch, env, cx) {
+            report_error(e, env, cx);
+            return Err(());
+        }
+    }
+
+    if args.repl {
+        repl(env, cx);
+    }
+    Ok(())
+}
+
+fn parens_closed(buffer: &str) -> bool {
+    let open = buffer.chars().filter(|&x| x == '(').count();
+    let close = buffer.chars().filter(|&x| x == ')').count();
+    open <= close
+}
+
+fn repl(env: &mut Rt<Env>, cx: &mut Context) {
+    let mut buffer = String::new();
+    let stdin = io::stdin();
+    loop {
+        print!("> ");
+        io::stdout().flush().unwrap();
+        stdin.read_line(&mut buffer).unwrap();
+        if buffer.trim() == "exit" {
+            return;
+        }
+        if buffer.trim().is_empty() {
+            continue;
+        }
+        if !parens_closed(&buffer) {
+            continue;
+        }
+        let (obj, _) = match reader::read(&buffer, cx) {
+            Ok(obj) => obj,
+            Err(e) => {
+                eprintln!("Error: {e}");
+                buffer.clear();
+                continue;
+            }
+        };
+
+        root!(obj, cx);
+        match interpreter::eval(obj, None, env, cx) {
+            Ok(val) => println!("{val}"),
+            Err(e) => report_error(e, env, cx),
+        }
+        buffer.clear();
+    }
+}
+
+fn load(file: &str, cx: &mut Context, env: &mut Rt<Env>) -> Result<(), ()> {
+    let file: Gc<&LispString> = cx.add_as(file);
+    root!(file, cx);
+    match crate::lread::load(file, None, None, cx, env) {
+        Ok(val) => {
+            println!("{val}");
+            Ok(())
+        }
+        Err(e) => {
+            report_error(e, env, cx);
+            Err(())
+        }
+    }
+}
+
+fn report_error(e: anyhow::Error, env: &Rt<Env>, cx: &Context) {
+    let e = match e.downcast::<EvalError>() {
+        Ok(e) => e,
+        Err(e) => return eprintln!("Error: {e}"),
+    };
+    // A signal has to be looked up in the environment to show what it was
+    let signal = matches!(e.error, ErrorType::Signal(_)).then(|| e.condition(env, cx)).flatten();
+    match signal {
+        Some((symbol, data)) => eprintln!("Error: {}", Cons::new(symbol, data, cx)),
+        None => eprintln!("Error: {e}"),
+    }
+    e.print_backtrace();
+}
+
+fn bootstrap(env: &mut Rt<Env>, cx: &mut Context) -> Result<(), ()> {
+    buffer::get_buffer_create(cx.add("*scratch*"), Some(NIL), cx).unwrap();
+    load("bootstrap.el", cx, env)
+}
+
+fn restore_dump(file: &str, env: &mut Rt<Env>, cx: &mut Context) -> Result<(), ()> {
+    buffer::get_buffer_create(cx.add("*scratch*"), Some(NIL), cx).unwrap();
+    pdump::restore(std::path::Path::new(file), env, cx).map_err(|e| eprintln!("Error: {e}"))
+}
+
+#[test]
+fn verify_cli() {
+    Args::command().debug_assert()
+}
@@ -0,0 +1,266 @@
+//! Coding systems, which convert between text and the bytes of files.
+//!
+//! A coding system is named by a symbol like `utf-8-unix`. The base name picks
+//! the encoding, and a `-unix`, `-dos` or `-mac` suffix picks how lines end in
+//! the bytes: text always ends lines with `\n`, `-dos` bytes with `\r\n`, and
+//! `-mac` bytes with `\r`. Without a suffix line ends are left alone. File
+//! reads and writes use `coding-system-for-read` and `coding-system-for-write`,
+//! and default to utf-8.
+use crate::core::{
+    env::{sym, Env},
+    error::{Type, TypeError},
+    gc::{Context, Rt},
+    object::{Object, ObjectType, Symbol},
+};
+use anyhow::{bail, ensure, Result};
+use rune_macros::defun;
+
+defvar!(CODING_SYSTEM_FOR_READ);
+defvar!(CODING_SYSTEM_FOR_WRITE);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Encoding {
+    Utf8,
+    Latin1,
+    /// With a `signature`, decoding uses the byte order mark if there is
+    /// one, and encoding writes one.
+    Utf16 {
+        big_endian: bool,
+        signature: bool,
+    },
+    /// The bytes are the text. Characters past 255 are written as utf-8.
+    Binary,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Eol {
+    Unchanged,
+    Unix,
+    Dos,
+    Mac,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct Coding {
+    encoding: Encoding,
+    eol: Eol,
+}
+
+impl Coding {
+    pub(crate) const UTF_8: Self = Coding { encoding: Encoding::Utf8, eol: Eol::Unchanged };
+    pub(crate) const BINARY: Self = Coding { encoding: Encoding::Binary, eol: Eol::Unchanged };
+
+    /// The coding system named `name`, or `None` if there is none.
+    fn from_name(name: &str) -> Option<Self> {
+        let (base, eol) = match name.rsplit_once('-') {
+            Some((base, "unix")) => (base, Eol::Unix),
+            Some((base, "dos")) => (base, Eol::Dos),
+            Some((base, "mac")) => (base, Eol::Mac),
+            _ => (name, Eol::Unchanged),
+        };
+        let utf16 = |big_endian, signature| Encoding::Utf16 { big_endian, signature };
+        let encoding = match base {
+            "utf-8" | "utf-8-emacs" | "mule-utf-8" | "prefer-utf-8" | "undecided" => Encoding::Utf8,
+            "latin-1" | "iso-latin-1" | "iso-8859-1" => Encoding::Latin1,
+            "utf-16" => utf16(true, true),
+            "utf-16be" => utf16(true, false),
+            "utf-16le" => utf16(false, false),
+            "utf-16be-with-signature" => utf16(true, true),
+            "utf-16le-with-signature" => utf16(false, true),
+            "raw-text" => Encoding::Binary,
+            "binary" | "no-conversion" if eol == Eol::Unchanged => Encoding::Binary,
+            _ => return None,
+        };
+        Some(Coding { encoding, eol })
+    }
+
+    pub(crate) fn from_symbol(name: Symbol) -> Result<Self> {
+        match Self::from_name(name.name()) {
+            Some(coding) => Ok(coding),
+            None => bail!("Invalid coding system: {name}"),
+        }
+    }
+
+    /// The coding system in `variable`, or utf-8 when it is nil.
+    pub(crate) fn from_variable(variable: Symbol, env: &Rt<Env>, cx: &Context) -> Result<Self> {
+        match env.vars.get(variable).map(|x| x.bind(cx).untag()) {
+            None | Some(ObjectType::NIL) => Ok(Self::UTF_8),
+            Some(ObjectType::Symbol(name)) => Self::from_symbol(name),
+            Some(other) => Err(TypeError::new(Type::Symbol, other).into()),
+        }
+    }
+
+    fn is_binary(self) -> bool {
+        self.encoding == Encoding::Binary
+    }
+
+    /// Decode `bytes` into text. Bytes that are invalid in the encoding become
+    /// the replacement character, and binary bytes become the characters with
+    /// the same codes.
+    pub(crate) fn decode(self, bytes: &[u8]) -> String {
+        let text = match self.encoding {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Latin1 | Encoding::Binary => bytes.iter().map(|&b| char::from(b)).collect(),
+            Encoding::Utf16 { mut big_endian, signature } => {
+                let mut bytes = bytes;
+                if signature {
+                    match bytes {
+                        [0xFE, 0xFF, rest @ ..] => (big_endian, bytes) = (true, rest),
+                        [0xFF, 0xFE, rest @ ..] => (big_endian, bytes) = (false, rest),
+                        _ => {}
+                    }
+                }
+                let units = bytes.chunks(2).map(|pair| match (pair, big_endian) {
+                    ([hi, lo], true) | ([lo, hi], false) => u16::from_be_bytes([*hi, *lo]),
+                    // An odd trailing byte is not a character
+                    _ => 0xFFFD,
+                });
+                let chars = char::decode_utf16(units);
+                chars.map(|x| x.unwrap_or(char::REPLACEMENT_CHARACTER)).collect()
+            }
+        };
+        match self.eol {
+            Eol::Dos => text.replace("\r\n", "\n"),
+            Eol::Mac => text.replace('\r', "\n"),
+            Eol::Unix | Eol::Unchanged => text,
+        }
+    }
+
+    /// Encode `text` into bytes. Characters that latin-1 can't represent are
+    /// encoded as `?`.
+    pub(crate) fn encode(self, text: &str) -> Vec<u8> {
+        let text = match self.eol {
+            Eol::Dos => text.replace('\n', "\r\n"),
+            Eol::Mac => text.replace('\n', "\r"),
+            Eol::Unix | Eol::Unchanged => text.to_owned(),
+        };
+        match self.encoding {
+            Encoding::Utf8 => text.into_bytes(),
+            Encoding::Latin1 => text.chars().map(|c| u8::try_from(c).unwrap_or(b'?')).collect(),
+            Encoding::Utf16 { big_endian, signature } => {
+                let bom = signature.then_some(0xFEFF);
+                let units = bom.into_iter().chain(text.encode_utf16());
+                let bytes = |x: u16| if big_endian { x.to_be_bytes() } else { x.to_le_bytes() };
+                units.flat_map(bytes).collect()
+            }
+            Encoding::Binary => {
+                let mut bytes = Vec::with_capacity(text.len());
+                for c in text.chars() {
+                    match u8::try_from(c) {
+                        Ok(b) => bytes.push(b),
+                        Err(_) => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                    }
+                }
+                bytes
+            }
+        }
+    }
+}
+
+/// Return t if OBJECT is nil or the name of a coding system.
+#[defun]
+fn coding_system_p(object: Object) -> bool {
+    match object.untag() {
+        ObjectType::NIL => true,
+        ObjectType::Symbol(name) => Coding::from_name(name.name()).is_some(),
+        _ => false,
+    }
+}
+
+/// Decode STRING, which holds bytes in CODING-SYSTEM, into text. The text is
+/// a unibyte string when the coding system is binary. If CODING-SYSTEM is nil
+/// STRING is returned unchanged.
+#[defun]
+fn decode_coding_string<'ob>(
+    string: Object<'ob>,
+    coding_system: Symbol,
+    _nocopy: Option<()>,
+    buffer: Option<()>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    ensure!(buffer.is_none(), "decode-coding-string into a buffer is not implemented");
+    let bytes: &[u8] = match string.untag() {
+        ObjectType::String(s) => s.as_bytes(),
+        ObjectType::ByteString(s) => s.inner(),
+        other => bail!(TypeError::new(Type::String, other)),
+    };
+    if coding_system == sym::NIL {
+        return Ok(string);
+    }
+    let coding = Coding::from_symbol(coding_system)?;
+    let text = coding.decode(bytes);
+    if coding.is_binary() {
+        Ok(cx.add(coding.encode(&text)))
+    } else {
+        Ok(cx.add(text))
+    }
+}
+
+/// Encode STRING into the bytes of CODING-SYSTEM, and return them as a
+/// unibyte string. If CODING-SYSTEM is nil STRING is returned unchanged.
+#[defun]
+fn encode_coding_string<'ob>(
+    string: Object<'ob>,
+    coding_system: Symbol,
+    _nocopy: Option<()>,
+    buffer: Option<()>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    ensure!(buffer.is_none(), "encode-coding-string into a buffer is not implemented");
+    let text = match string.untag() {
+        ObjectType::String(s) => s,
+        ObjectType::ByteString(_) => return Ok(string),
+        other => bail!(TypeError::new(Type::String, other)),
+    };
+    if coding_system == sym::NIL {
+        return Ok(string);
+    }
+    Ok(cx.add(Coding::from_symbol(coding_system)?.encode(text)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn coding(name: &str) -> Coding {
+        Coding::from_name(name).unwrap()
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(coding("utf-8"), Coding::UTF_8);
+        assert_eq!(coding("utf-8-dos").eol, Eol::Dos);
+        let utf16le = Encoding::Utf16 { big_endian: false, signature: false };
+        assert_eq!(coding("utf-16le").encoding, utf16le);
+        assert!(coding("no-conversion").is_binary());
+        assert_eq!(Coding::from_name("no-conversion-dos"), None);
+        assert_eq!(Coding::from_name("shift_jis"), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let text = "é中\nb";
+        for name in ["utf-8", "utf-8-dos", "latin-1", "utf-16", "utf-16le", "utf-16be-mac"] {
+            let coding = coding(name);
+            let expect = if name == "latin-1" { "é?\nb" } else { text };
+            assert_eq!(coding.decode(&coding.encode(text)), expect, "{name}");
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(coding("utf-8-dos").encode("a\nb"), b"a\r\nb");
+        assert_eq!(coding("latin-1").encode("é"), [0xE9]);
+        assert_eq!(coding("utf-16").encode("a"), [0xFE, 0xFF, 0, b'a']);
+        assert_eq!(coding("utf-16le").encode("a"), [b'a', 0]);
+        assert_eq!(coding("binary").encode("\u{FF}a"), [0xFF, b'a']);
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(coding("utf-16").decode(&[0xFF, 0xFE, b'a', 0]), "a");
+        assert_eq!(coding("utf-16").decode(&[0, b'a']), "a");
+        assert_eq!(coding("utf-8").decode(&[b'a', 0xFF]), "a\u{FFFD}");
+        assert_eq!(coding("latin-1-mac").decode(&[0xE9, b'\r']), "é\n");
+    }
+}
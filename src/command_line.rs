@@ -0,0 +1,116 @@
+//! Processing the command line like `emacs --batch`.
+//!
+//! Expressions to evaluate, files to load and functions to call are run in
+//! the order they were given, so that a later argument can use what an
+//! earlier one defined. Arguments after `--` are left for lisp code in
+//! `command-line-args-left`.
+use crate::core::{
+    env::{intern, sym, Env},
+    gc::{Context, Rt},
+    object::{Function, Gc, LispString, Object},
+};
+use crate::{interpreter, reader};
+use anyhow::{bail, Result};
+use clap::ArgMatches;
+use rune_core::macros::{call, root};
+
+/// An argument that is run after startup.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Action {
+    Eval(String),
+    Load(String),
+    Funcall(String),
+}
+
+/// The actions in `matches`, in the order they appear on the command line.
+pub(crate) fn actions(matches: &ArgMatches) -> Vec<Action> {
+    let mut actions = Vec::new();
+    let kinds: [(&str, fn(String) -> Action); 3] =
+        [("eval", Action::Eval), ("load", Action::Load), ("funcall", Action::Funcall)];
+    for (id, action) in kinds {
+        let Some(indices) = matches.indices_of(id) else { continue };
+        let values = matches.get_many::<String>(id).into_iter().flatten();
+        actions.extend(indices.zip(values.cloned().map(action)));
+    }
+    actions.sort_by_key(|(index, _)| *index);
+    actions.into_iter().map(|(_, action)| action).collect()
+}
+
+/// Set `command-line-args` to the whole command line and
+/// `command-line-args-left` to `rest`, the arguments for lisp code.
+pub(crate) fn set_args(rest: &[String], env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let all: Vec<Object> = std::env::args().map(|x| cx.add(x)).collect();
+    env.set_var(sym::COMMAND_LINE_ARGS, crate::fns::slice_into_list(&all, None, cx))?;
+    let rest: Vec<Object> = rest.iter().map(|x| cx.add(x.as_str())).collect();
+    env.set_var(sym::COMMAND_LINE_ARGS_LEFT, crate::fns::slice_into_list(&rest, None, cx))
+}
+
+/// Run `action`. Unless `batch`, the value it returns is printed.
+pub(crate) fn run(action: &Action, batch: bool, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let value = match action {
+        Action::Eval(expr) => {
+            let (form, end) = reader::read(expr, cx)?;
+            if !expr[end..].trim().is_empty() {
+                bail!("Trailing garbage following expression: {}", &expr[end..]);
+            }
+            root!(form, cx);
+            interpreter::eval(form, None, env, cx)?
+        }
+        Action::Load(file) => {
+            let file: Gc<&LispString> = cx.add_as(file.as_str());
+            root!(file, cx);
+            crate::lread::load(file, None, None, cx, env)?
+        }
+        Action::Funcall(name) => {
+            let func: Function = Object::from(intern(name, cx)).try_into()?;
+            root!(func, cx);
+            call!(func; name.as_str(), env, cx)?
+        }
+    };
+    if !batch {
+        println!("{value}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use clap::{Arg, ArgAction, Command};
+
+    #[test]
+    fn test_actions() {
+        let command = Command::new("rune").args(
+            ["eval", "load", "funcall"].map(|id| Arg::new(id).long(id).action(ArgAction::Append)),
+        );
+        let matches = command.get_matches_from([
+            "rune", "--load", "a.el", "--eval", "(b)", "--funcall", "c", "--load", "d.el",
+        ]);
+        let expect = [
+            Action::Load("a.el".into()),
+            Action::Eval("(b)".into()),
+            Action::Funcall("c".into()),
+            Action::Load("d.el".into()),
+        ];
+        assert_eq!(actions(&matches), expect);
+    }
+
+    #[test]
+    fn test_run() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        set_args(&["x".into(), "y".into()], env, cx).unwrap();
+        let set = Action::Eval("(defalias 'command-line-test #'(lambda () (setq left \
+                                command-line-args-left)))"
+            .into());
+        run(&set, true, env, cx).unwrap();
+        run(&Action::Funcall("command-line-test".into()), true, env, cx).unwrap();
+        let left = env.vars.get(intern("left", cx)).unwrap().bind(cx);
+        assert_eq!(left.to_string(), "(\"x\" \"y\")");
+        assert!(run(&Action::Eval("(car 1) 2".into()), true, env, cx).is_err());
+        assert!(run(&Action::Eval("(car 1)".into()), true, env, cx).is_err());
+    }
+}
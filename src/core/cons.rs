@@ -65,7 +65,7 @@ impl Cons {
         let car = car.into_obj(cx).into();
         let cdr = cdr.into_obj(cx).into();
         let cons = unsafe { Cons::new_unchecked(car, cdr) };
-        Cons(GcHeap::new(cons, C)).into_obj(cx).untag()
+        cx.profiled(|| Cons(GcHeap::new(cons, C)).into_obj(cx).untag())
     }
 
     /// Create a new cons cell with the cdr set to nil
@@ -76,7 +76,7 @@ impl Cons {
     {
         let car = car.into_obj(cx).into();
         let cons = unsafe { Cons::new_unchecked(car, NIL) };
-        Cons(GcHeap::new(cons, C)).into_obj(cx).untag()
+        cx.profiled(|| Cons(GcHeap::new(cons, C)).into_obj(cx).untag())
     }
 
     pub(in crate::core) fn mark_const(&mut self) {
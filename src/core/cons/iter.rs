@@ -1,19 +1,22 @@
 use super::super::object::{List, ListType, Object, ObjectType};
 use super::Cons;
-use crate::core::gc::Rto;
+use crate::core::gc::{Held, Rto};
 use anyhow::Result;
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub(crate) struct ConsIter<'ob> {
     cons: Option<Result<&'ob Cons, ConsError>>,
     fast: Option<&'ob Cons>,
+    /// The start of the list, given as the data of a circular list error
+    head: Option<&'ob Cons>,
 }
 
 /// An iterator over cons cells. This iterator will detect circular lists and
 /// non-nil list terminators.
 impl<'ob> ConsIter<'ob> {
     fn new(cons: Option<&'ob Cons>) -> Self {
-        Self { cons: cons.map(Ok), fast: cons }
+        Self { cons: cons.map(Ok), fast: cons, head: cons }
     }
 
     pub(crate) fn fallible(self) -> fallible_iterator::Convert<Self> {
@@ -25,9 +28,9 @@ impl<'ob> Iterator for ConsIter<'ob> {
     type Item = Result<&'ob Cons, ConsError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let cons = match self.cons? {
-            Ok(c) => c,
-            Err(e) => return Some(Err(e)),
+        let cons = match self.cons.as_ref()? {
+            Ok(c) => *c,
+            Err(e) => return Some(Err(e.clone())),
         };
         self.cons = match cons.cdr().untag() {
             ObjectType::Cons(next) => Some(Ok(next)),
@@ -37,9 +40,10 @@ impl<'ob> Iterator for ConsIter<'ob> {
 
         // Floyds cycle detection algorithm
         self.fast = advance(advance(self.fast));
-        if let (Some(Ok(slow)), Some(fast)) = (self.cons, self.fast) {
-            if std::ptr::eq(slow, fast) {
-                self.cons = Some(Err(ConsError::CircularList));
+        if let (Some(Ok(slow)), Some(fast)) = (&self.cons, self.fast) {
+            if std::ptr::eq(*slow, fast) {
+                let head = self.head.expect("a circular list is not empty");
+                self.cons = Some(Err(ConsError::circular(head.into())));
             }
         }
         Some(Ok(cons))
@@ -66,7 +70,7 @@ impl ElemIter<'_> {
 
     /// Take the rest of the list as a cons.
     pub(crate) fn rest(&self) -> Result<Option<&Cons>, ConsError> {
-        self.0.cons.transpose()
+        self.0.cons.clone().transpose()
     }
 
     pub(crate) fn fallible(self) -> fallible_iterator::Convert<Self> {
@@ -82,34 +86,52 @@ impl<'ob> Iterator for ElemIter<'ob> {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub(crate) enum ConsError {
     NonNilCdr,
-    CircularList,
+    /// The list, signaled as `(circular-list LIST)`. A rooted iterator gives
+    /// the part of the list where it found the cycle, since it doesn't keep
+    /// the start.
+    CircularList(Arc<Held>),
+}
+
+impl ConsError {
+    fn circular(list: Object) -> Self {
+        ConsError::CircularList(Arc::new(Held::new(list)))
+    }
 }
 
 impl std::fmt::Display for ConsError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             ConsError::NonNilCdr => write!(f, "non-nil cdr at end of list"),
-            ConsError::CircularList => write!(f, "Circular list"),
+            ConsError::CircularList(_) => write!(f, "Circular list"),
         }
     }
 }
 
 impl std::error::Error for ConsError {}
 
+/// A rooted iterator over the elements of a list. Cycles are found with
+/// Brent's algorithm: each cons is compared with a saved one, which is moved
+/// forward whenever the number of steps since it was saved reaches a power of
+/// two. The saved cons is rooted, since conses can move while the iterator is
+/// suspended.
 pub(crate) struct ElemStreamIter<'rt> {
     elem: Option<&'rt mut Rto<Object<'static>>>,
     cons: Option<Result<&'rt mut Rto<&'static Cons>, ConsError>>,
+    saved: Option<&'rt mut Rto<&'static Cons>>,
+    steps: usize,
+    limit: usize,
 }
 
 impl<'rt> ElemStreamIter<'rt> {
     pub(crate) fn new(
         elem: Option<&'rt mut Rto<Object<'static>>>,
         cons: Option<&'rt mut Rto<&'static Cons>>,
+        saved: Option<&'rt mut Rto<&'static Cons>>,
     ) -> Self {
-        Self { elem, cons: cons.map(Ok) }
+        Self { elem, cons: cons.map(Ok), saved, steps: 0, limit: 1 }
     }
 }
 
@@ -121,7 +143,7 @@ impl<'rt> fallible_streaming_iterator::FallibleStreamingIterator for ElemStreamI
         if let Some(cons) = &mut self.cons {
             let cons = match cons {
                 Ok(x) => x,
-                Err(e) => return Err(*e),
+                Err(e) => return Err(e.clone()),
             };
             let elem = self.elem.as_mut().expect("Element should never be None while Cons is Some");
             let car = unsafe { cons.bind_unchecked().car() };
@@ -131,6 +153,18 @@ impl<'rt> fallible_streaming_iterator::FallibleStreamingIterator for ElemStreamI
                     // dissociate the borrow of cons from cell
                     let x = unsafe { std::mem::transmute::<&Cons, &Cons>(next) };
                     cons.set(x);
+                    let saved =
+                        self.saved.as_mut().expect("Saved should never be None while Cons is Some");
+                    if std::ptr::eq(x, unsafe { saved.bind_unchecked() }) {
+                        self.cons = Some(Err(ConsError::circular(x.into())));
+                        return Ok(());
+                    }
+                    self.steps += 1;
+                    if self.steps == self.limit {
+                        saved.set(x);
+                        self.steps = 0;
+                        self.limit *= 2;
+                    }
                 }
                 ObjectType::NIL => self.cons = None,
                 _ => self.cons = Some(Err(ConsError::NonNilCdr)),
@@ -209,8 +243,10 @@ macro_rules! rooted_iter {
         // Create roots, but don't initialize them
         let mut elem;
         let mut cons;
+        let mut saved;
         let mut root_elem;
         let mut root_cons;
+        let mut root_saved;
         // use match to ensure that $value is not evaled inside the unsafe block
         let slot = match $value {
             value => unsafe { $crate::core::gc::IntoRoot::into_root(value) },
@@ -224,12 +260,18 @@ macro_rules! rooted_iter {
             unsafe {
                 elem = $crate::core::gc::Slot::new(object::NIL);
                 cons = $crate::core::gc::Slot::new(object::WithLifetime::with_lifetime(head));
+                saved = $crate::core::gc::Slot::new(object::WithLifetime::with_lifetime(head));
                 root_elem = gc::__StackRoot::new(&mut elem, $cx.get_root_set());
                 root_cons = gc::__StackRoot::new(&mut cons, $cx.get_root_set());
-                cons::ElemStreamIter::new(Some(root_elem.as_mut()), Some(root_cons.as_mut()))
+                root_saved = gc::__StackRoot::new(&mut saved, $cx.get_root_set());
+                cons::ElemStreamIter::new(
+                    Some(root_elem.as_mut()),
+                    Some(root_cons.as_mut()),
+                    Some(root_saved.as_mut()),
+                )
             }
         } else {
-            $crate::core::cons::ElemStreamIter::new(None, None)
+            $crate::core::cons::ElemStreamIter::new(None, None, None)
         };
     };
 }
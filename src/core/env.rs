@@ -1,8 +1,11 @@
-use super::gc::{Context, ObjectMap, Rto, Slot};
-use super::object::{LispBuffer, Object, OpenBuffer, Symbol, WithLifetime};
-use anyhow::{anyhow, Result};
+use super::cons::Cons;
+use super::error::ConstantError;
+use super::gc::{self, Context, ObjectMap, Rto, Slot};
+use super::object::{LispBuffer, Object, ObjectType, OpenBuffer, Symbol, WithLifetime, NIL};
+use anyhow::Result;
 use rune_macros::Trace;
 
+mod bootstrap;
 mod stack;
 mod symbol_map;
 pub(crate) use stack::*;
@@ -14,27 +17,80 @@ pub(crate) struct Env<'a> {
     pub(crate) vars: ObjectMap<Slot<Symbol<'a>>, Slot<Object<'a>>>,
     pub(crate) props: PropertyMap<'a>,
     pub(crate) catch_stack: Vec<Slot<Object<'a>>>,
+    /// Variables defined with `defconst`. Like `nil` and keywords they can't
+    /// be set, though they can still be let-bound.
+    pub(crate) constants: ObjectMap<Slot<Symbol<'a>>, ()>,
+    /// The active `condition-case` and `handler-bind` handlers, innermost last,
+    /// as their conditions and the function of the `handler-bind`. A
+    /// `condition-case` has nil for the function.
+    pub(crate) handlers: Vec<(Slot<Object<'a>>, Slot<Object<'a>>)>,
     exception: (Slot<Object<'a>>, Slot<Object<'a>>),
     #[no_trace]
     exception_id: u32,
     binding_stack: Vec<(Slot<Symbol<'a>>, Option<Slot<Object<'a>>>)>,
+    /// The functions watching each variable, added with `add-variable-watcher`.
+    /// They are called by the functions in [`data`](crate::data) that change
+    /// variables, not by the methods here.
+    pub(crate) watchers: ObjectMap<Slot<Symbol<'a>>, Vec<Slot<Object<'a>>>>,
     pub(crate) match_data: Slot<Object<'a>>,
     #[no_trace]
     pub(crate) current_buffer: Option<OpenBuffer<'a>>,
     pub(crate) stack: LispStack<'a>,
+    /// Global references held by dynamic modules. Freed references are nil
+    /// and their index is kept in `free_module_refs` to be reused.
+    pub(crate) module_refs: Vec<Slot<Object<'a>>>,
+    /// Whether each of `module_refs` is in use. A reference can hold nil, so
+    /// the value can't tell.
+    #[no_trace]
+    pub(crate) module_refs_live: Vec<bool>,
+    #[no_trace]
+    pub(crate) free_module_refs: Vec<usize>,
+    /// Callbacks of background work that hasn't finished, indexed by the id
+    /// of the work. Finished entries are nil.
+    pub(crate) callbacks: Vec<Slot<Object<'a>>>,
+}
+
+/// A few variables control the runtime itself, so it has to know when they
+/// change. `None` means the variable was made void.
+fn update_runtime_var(symbol: Symbol, value: Option<Object>) {
+    match symbol {
+        sym::GC_CONS_THRESHOLD => {
+            let bytes = match value.map(Object::untag) {
+                Some(ObjectType::Int(i)) => usize::try_from(i).unwrap_or(0),
+                _ => gc::GC_CONS_THRESHOLD,
+            };
+            gc::set_gc_cons_threshold(bytes);
+        }
+        sym::GC_CONS_PERCENTAGE => {
+            let percentage = match value.map(Object::untag) {
+                Some(ObjectType::Float(f)) => **f,
+                Some(ObjectType::Int(i)) => i as f64,
+                _ => gc::GC_CONS_PERCENTAGE,
+            };
+            gc::set_gc_cons_percentage(percentage.max(0.0));
+        }
+        sym::FEATURES => crate::data::sync_features(value),
+        _ => {}
+    }
 }
 
 // RootedEnv created by #[derive(Trace)]
 impl<'a> RootedEnv<'a> {
     pub(crate) fn set_var(&mut self, sym: Symbol, value: Object) -> Result<()> {
-        if sym.is_const() {
-            Err(anyhow!("Attempt to set a constant symbol: {sym}"))
+        if self.is_constant(sym) {
+            Err(ConstantError::new(sym).into())
         } else {
+            update_runtime_var(sym, Some(value));
             self.vars.insert(sym, value);
             Ok(())
         }
     }
 
+    /// Make `sym` void, like `makunbound`.
+    pub(crate) fn remove_var(&mut self, sym: Symbol) {
+        self.vars.remove(sym);
+    }
+
     pub(crate) fn set_prop(&mut self, symbol: Symbol, propname: Symbol, value: Object) {
         match self.props.get_mut(symbol) {
             Some(plist) => match plist.iter_mut().find(|x| x.0 == propname) {
@@ -61,21 +117,81 @@ impl<'a> RootedEnv<'a> {
     pub(crate) fn varbind(&mut self, var: Symbol, value: Object, cx: &Context) {
         let prev_value = self.vars.get(var).map(|x| x.bind(cx));
         self.binding_stack.push((var, prev_value));
+        update_runtime_var(var, Some(value));
         self.vars.insert(var, value);
     }
 
+    /// Save the current buffer, and its point if `point`, like
+    /// `save-excursion`. They are restored by the `unbind` that removes this
+    /// entry, so the same unwinding that undoes `let` undoes this.
+    pub(crate) fn save_buffer(&mut self, point: bool, cx: &Context) {
+        let saved = match &self.current_buffer {
+            Some(buffer) if point => {
+                let cursor = buffer.text.cursor().chars();
+                Cons::new(buffer.lisp_buffer(cx), cursor, cx).into()
+            }
+            Some(buffer) => cx.add(buffer.lisp_buffer(cx)),
+            None => NIL,
+        };
+        self.binding_stack.push((sym::INTERNAL__SAVED_BUFFER, Some(saved)));
+    }
+
+    fn restore_buffer(&mut self, saved: Object) {
+        let (buffer, point) = match saved.untag() {
+            ObjectType::Cons(cons) => (cons.car(), cons.cdr()),
+            _ => (saved, NIL),
+        };
+        let ObjectType::Buffer(buffer) = buffer.untag() else { return };
+        // A buffer that was killed can't be made current again
+        if self.set_buffer(buffer).is_err() {
+            return;
+        }
+        let Some(current) = &mut self.current_buffer else { return };
+        if let ObjectType::Int(point) = point.untag() {
+            current.text.set_cursor((point as usize).min(current.text.len_chars()));
+        }
+    }
+
     pub(crate) fn unbind(&mut self, count: u16, cx: &Context) {
         for _ in 0..count {
             match self.binding_stack.bind_mut(cx).pop() {
+                Some((sym, val)) if *sym == sym::INTERNAL__SAVED_BUFFER => {
+                    self.restore_buffer(val.map_or(NIL, |x| *x));
+                }
                 Some((sym, val)) => match val {
-                    Some(val) => self.vars.insert(*sym, *val),
-                    None => self.vars.remove(*sym),
+                    Some(val) => {
+                        update_runtime_var(*sym, Some(*val));
+                        self.vars.insert(*sym, *val);
+                    }
+                    None => {
+                        update_runtime_var(*sym, None);
+                        self.vars.remove(*sym);
+                    }
                 },
                 None => panic!("Binding stack was empty"),
             }
         }
     }
 
+    /// The variable that the next `unbind` restores and the value it gets,
+    /// which is nil if it becomes void. `None` if the next binding isn't a
+    /// variable.
+    pub(crate) fn next_unbinding<'ob>(
+        &self,
+        cx: &'ob Context,
+    ) -> Option<(Symbol<'ob>, Object<'ob>)> {
+        let (sym, val) = self.binding_stack.bind_ref(cx).last()?;
+        if **sym == sym::INTERNAL__SAVED_BUFFER {
+            return None;
+        }
+        Some((**sym, val.as_ref().map_or(NIL, |x| **x)))
+    }
+
+    /// Number of dynamic bindings currently in effect.
+    pub(crate) fn binding_depth(&self) -> usize {
+        self.binding_stack.len()
+    }
+
     pub(crate) fn defvar(&mut self, var: Symbol, value: Object) -> Result<()> {
         // TOOD: Handle `eval-sexp` on defvar, which should always update the
         // value
@@ -94,6 +210,24 @@ impl<'a> RootedEnv<'a> {
         Ok(())
     }
 
+    /// Whether `sym` is a constant that can't be set.
+    pub(crate) fn is_constant(&self, sym: Symbol) -> bool {
+        sym.is_const() || self.constants.get(sym).is_some()
+    }
+
+    /// Define `var` as a constant. Unlike [`defvar`](Self::defvar) this always
+    /// sets the value, so evaluating a `defconst` again updates it.
+    pub(crate) fn defconst(&mut self, var: Symbol, value: Object) -> Result<()> {
+        if var.is_const() {
+            return Err(ConstantError::new(var).into());
+        }
+        update_runtime_var(var, Some(value));
+        self.vars.insert(var, value);
+        var.make_special();
+        self.constants.insert(var, ());
+        Ok(())
+    }
+
     pub(crate) fn set_buffer(&mut self, buffer: &LispBuffer) -> Result<()> {
         if let Some(current) = &self.current_buffer {
             if buffer == current {
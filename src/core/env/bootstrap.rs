@@ -0,0 +1,47 @@
+//! Symbols that the bootstrap lisp files use heavily but that are not defined
+//! by any builtin. Declaring them here makes them part of [`sym`](super::sym),
+//! so they are allocated statically, can be matched on in Rust, and reading
+//! them never has to take the intern lock.
+
+// definitions
+defsym!(DEFUN);
+defsym!(DEFMACRO);
+defsym!(DEFSUBST);
+
+// common macros and functions
+defsym!(NOT);
+defsym!(WHEN);
+defsym!(UNLESS);
+defsym!(DOLIST);
+defsym!(DOTIMES);
+defsym!(PUSH);
+defsym!(POP);
+defsym!(CADR);
+defsym!(CDDR);
+
+// `declare' specs
+defsym!(INDENT);
+defsym!(DOC_STRING);
+defsym!(PURE);
+defsym!(SIDE_EFFECT_FREE);
+defsym!(OBSOLETE);
+
+// keywords of `defcustom' and `defgroup'
+defsym!(KW_GROUP);
+defsym!(KW_TAG);
+defsym!(KW_VERSION);
+defsym!(KW_PACKAGE_VERSION);
+defsym!(KW_INITIALIZE);
+defsym!(KW_SET);
+defsym!(KW_SET_AFTER);
+defsym!(KW_GET);
+defsym!(KW_SAFE);
+defsym!(KW_RISKY);
+defsym!(KW_LOCAL);
+defsym!(KW_OPTIONS);
+defsym!(KW_REQUIRE);
+defsym!(KW_LINK);
+defsym!(KW_LOAD);
+
+// keywords of the sequence functions
+defsym!(KW_KEY);
@@ -1,7 +1,8 @@
 use crate::core::{
-    gc::{Context, IntoRoot, Rt, Rto, Slot},
-    object::{ByteFn, Object, WithLifetime, NIL},
+    gc::{profiler, Context, IntoRoot, Rt, Rto, Slot},
+    object::{ByteFn, Object, ObjectType, WithLifetime, NIL},
 };
+use anyhow::{bail, ensure, Result};
 use rune_macros::Trace;
 use std::ops::{Deref, DerefMut, Index, IndexMut, RangeBounds, RangeTo};
 
@@ -79,6 +80,34 @@ impl<'old, 'new> WithLifetime<'new> for FrameStore<'old> {
     }
 }
 
+/// Number of objects used to encode each frame of a suspended stack.
+const FRAME_LEN: usize = 6;
+
+fn encode_frame<'ob>(
+    out: &mut Vec<Object<'ob>>,
+    frame: Frame,
+    base: usize,
+    bytecode: Option<(&'ob ByteFn, usize)>,
+) {
+    let (func, pc) = bytecode.map_or((NIL, 0), |(f, pc)| (f.into(), pc));
+    out.push((frame.start - base).into());
+    out.push((frame.end - base).into());
+    out.push(usize::from(frame.arg_cnt.0).into());
+    out.push(usize::from(frame.arg_cnt.1).into());
+    out.push(func);
+    out.push(pc.into());
+}
+
+/// Decode a program counter, checking that it is inside the code of `func`.
+fn offset_pc(obj: Object, func: &ByteFn) -> Result<usize> {
+    match obj.untag() {
+        ObjectType::Int(pc) if usize::try_from(pc).is_ok_and(|x| x < func.codes().len()) => {
+            Ok(pc as usize)
+        }
+        _ => bail!("Invalid suspended program counter: {obj}"),
+    }
+}
+
 /// Type representing a slice of arguments on the stack. Used to avoid
 /// allocations and copies when calling functions.
 #[derive(Copy, Clone)]
@@ -164,6 +193,7 @@ impl<'a> RootedLispStack<'a> {
         self.vec.truncate(self.current.start);
         self.current = self.frames.last().unwrap().frame;
         self.frames.pop();
+        profiler::leave_frames(self.frames.len());
     }
 
     pub(crate) fn get_bytecode_frame(&self, idx: usize) -> Option<(&Rto<&'a ByteFn>, usize)> {
@@ -189,6 +219,84 @@ impl<'a> RootedLispStack<'a> {
         assert!(frame < self.current_frame());
         self.current = self.frames[frame].frame;
         self.frames.truncate(frame);
+        profiler::leave_frames(frame);
+    }
+
+    /// Remove frame `base` and every frame above it, along with their values,
+    /// so that they can be restored later with [`resume`](Self::resume). The
+    /// frame below `base` becomes current again. Returns the values and the
+    /// frames, which are encoded as groups of [`FRAME_LEN`] objects: the start,
+    /// end, argument count, whether there are rest arguments, and the function
+    /// and program counter to return to (or `nil`). Offsets are relative to
+    /// the start of frame `base`.
+    pub(crate) fn suspend<'ob>(
+        &mut self,
+        base: usize,
+        cx: &'ob Context,
+    ) -> (Vec<Object<'ob>>, Vec<Object<'ob>>) {
+        assert!(base <= self.current_frame());
+        let start = self.frames.get(base).map_or(self.current.start, |x| x.frame.start);
+        let mut frames = Vec::new();
+        for i in base..self.frames.len() {
+            let store = &self.frames[i];
+            let bytecode = store.bytecode.as_ref().map(|x| (x.func.bind(cx), x.pc_offset));
+            encode_frame(&mut frames, store.frame, start, bytecode);
+        }
+        encode_frame(&mut frames, self.current, start, None);
+        let values = self.vec[start..].iter().map(|x| x.bind(cx)).collect();
+        if base < self.frames.len() {
+            self.current = self.frames[base].frame;
+            self.frames.truncate(base);
+            profiler::leave_frames(base);
+        }
+        self.vec.truncate(start);
+        (values, frames)
+    }
+
+    /// Restore frames removed by [`suspend`](Self::suspend) in place of the
+    /// current frame, which has to be empty.
+    pub(crate) fn resume(&mut self, values: &[Object], frames: &[Object]) -> Result<()> {
+        ensure!(self.arg_count() == 0, "Can't resume frames over arguments");
+        ensure!(
+            !frames.is_empty() && frames.len() % FRAME_LEN == 0,
+            "Invalid suspended frames"
+        );
+        let start = self.current.start;
+        let int = |obj: Object| -> Result<usize> {
+            let ObjectType::Int(i) = obj.untag() else { bail!("Invalid suspended frame: {obj}") };
+            Ok(usize::try_from(i)?)
+        };
+        let mut decoded = Vec::new();
+        let mut prev_start = 0;
+        for chunk in frames.chunks(FRAME_LEN) {
+            let (frame_start, frame_end) = (int(chunk[0])?, int(chunk[1])?);
+            let in_order = prev_start <= frame_start && frame_start <= frame_end;
+            ensure!(in_order && frame_start <= values.len(), "Invalid suspended frame bounds");
+            prev_start = frame_start;
+            let frame = Frame {
+                start: start + frame_start,
+                end: start + frame_end,
+                arg_cnt: (u16::try_from(int(chunk[2])?)?, chunk[3] != 0),
+            };
+            let bytecode = match chunk[4].untag() {
+                ObjectType::ByteFn(func) => Some((func, offset_pc(chunk[5], func)?)),
+                ObjectType::NIL => None,
+                x => bail!("Invalid suspended frame function: {x}"),
+            };
+            decoded.push((frame, bytecode));
+        }
+        let (current, _) = decoded.pop().unwrap();
+        for (frame, bytecode) in decoded {
+            let store = match bytecode {
+                Some((func, pc)) => FrameStore::new_bytecode(frame, func, pc),
+                None => FrameStore::new(frame),
+            };
+            self.frames.push(store);
+        }
+        self.current = current;
+        self.set_depth(self.current.end - self.current.start);
+        self.vec.extend_from_slice(values);
+        Ok(())
     }
 
     pub(crate) fn len(&self) -> usize {
@@ -221,14 +329,21 @@ impl<'a> RootedLispStack<'a> {
         self.vec.push(value);
     }
 
-    pub(crate) fn pop<'ob>(&mut self, cx: &'ob Context) -> Object<'ob> {
-        assert!(self.len() > self.current.start);
-        *self.vec.bind_mut(cx).pop().unwrap()
+    /// Remove the top of the stack. Fails instead of reaching into the
+    /// previous frame, which only malformed bytecode can do.
+    pub(crate) fn pop<'ob>(&mut self, cx: &'ob Context) -> Result<Object<'ob>> {
+        ensure!(self.len() > self.current.start, "Invalid bytecode: stack underflow");
+        Ok(*self.vec.bind_mut(cx).pop().unwrap())
+    }
+
+    pub(crate) fn top(&mut self) -> Result<&mut Rto<Object<'a>>> {
+        ensure!(self.len() > self.current.start, "Invalid bytecode: stack underflow");
+        Ok(self.vec.last_mut().unwrap())
     }
 
-    pub(crate) fn top(&mut self) -> &mut Rto<Object<'a>> {
-        assert!(self.len() > self.current.start);
-        self.vec.last_mut().unwrap()
+    /// The number of objects in the current frame.
+    pub(crate) fn frame_len(&self) -> usize {
+        self.len() - self.current.start
     }
 
     pub(crate) fn offset_end(&self, i: usize) -> usize {
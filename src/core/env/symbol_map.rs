@@ -1,80 +1,148 @@
 use crate::core::{
     gc::{Block, Context},
-    object::{CloneIn, Function, LispBuffer, Symbol, WithLifetime},
+    object::{CloneIn, Function, LispBuffer, Object, Symbol, WithLifetime},
 };
 use anyhow::Result;
 use rune_core::hashmap::HashMap;
+use std::hash::BuildHasher;
+use std::sync::{Mutex, MutexGuard, RwLock};
+
+/// The number of shards runtime symbols are split into. Interning only locks
+/// the shard that the name hashes to, so threads reading different files
+/// rarely wait on each other.
+const SHARDS: usize = 16;
 
 pub(crate) struct SymbolMap {
     map: SymbolMapCore,
-    block: Block<true>,
+    block: Mutex<Block<true>>,
 }
 
+/// A symbol stored in the intern table.
+#[derive(Copy, Clone)]
+struct Interned(Symbol<'static>);
+
+// SAFETY: Interned symbols live in the builtin table or the global block,
+// neither of which is ever freed or moved, and the mutable parts of a symbol
+// cell are atomic.
+unsafe impl Sync for Interned {}
+
+type Shard = RwLock<HashMap<&'static str, Interned>>;
+
 struct SymbolMapCore {
-    map: HashMap<&'static str, Symbol<'static>>,
+    /// The builtin symbols. These are added before the map is shared and never
+    /// change after that, so they are looked up without taking a lock.
+    statics: HashMap<&'static str, Interned>,
+    /// Symbols interned at runtime, split by the hash of their name.
+    shards: [Shard; SHARDS],
 }
 
 impl SymbolMapCore {
     fn with_capacity(cap: usize) -> Self {
         Self {
-            map: HashMap::with_capacity_and_hasher(cap, std::hash::BuildHasherDefault::default()),
+            statics: HashMap::with_capacity_and_hasher(cap, Default::default()),
+            shards: std::array::from_fn(|_| Shard::default()),
         }
     }
 
+    fn shard(&self, name: &str) -> &Shard {
+        let hash = self.statics.hasher().hash_one(name);
+        &self.shards[hash as usize % SHARDS]
+    }
+
     fn get(&self, name: &str) -> Option<Symbol> {
-        self.map.get(name).map(|x| unsafe { x.with_lifetime() })
-    }
-
-    fn intern<'ob>(&mut self, name: &str, block: &Block<true>, cx: &'ob Context) -> Symbol<'ob> {
-        match self.get(name) {
-            Some(x) => cx.bind(x),
-            None => {
-                let name = name.to_owned();
-                // Leak the memory so that it is static
-                let static_name: &'static str = unsafe {
-                    let name_ptr: *const str = Box::into_raw(name.into_boxed_str());
-                    &*name_ptr
-                };
-                let sym = Symbol::new(static_name, block);
-                self.map.insert(static_name, unsafe { sym.with_lifetime() });
-                cx.bind(sym)
-            }
+        let found = match self.statics.get(name) {
+            Some(x) => Some(*x),
+            None => self.shard(name).read().unwrap().get(name).copied(),
+        };
+        found.map(|x| unsafe { x.0.with_lifetime() })
+    }
+
+    fn intern<'ob>(&self, name: &str, block: &Mutex<Block<true>>, cx: &'ob Context) -> Symbol<'ob> {
+        if let Some(x) = self.get(name) {
+            return cx.bind(x);
+        }
+        let mut shard = self.shard(name).write().unwrap();
+        // Another thread could have interned it before we took the lock
+        if let Some(x) = shard.get(name) {
+            return cx.bind(x.0);
         }
+        let name = name.to_owned();
+        // Leak the memory so that it is static
+        let static_name: &'static str = unsafe {
+            let name_ptr: *const str = Box::into_raw(name.into_boxed_str());
+            &*name_ptr
+        };
+        let block = block.lock().unwrap();
+        // SAFETY: The global block is never freed
+        let sym = unsafe { Symbol::new(static_name, &*block).with_lifetime() };
+        shard.insert(static_name, Interned(sym));
+        cx.bind(sym)
     }
 
     fn pre_init(&mut self, sym: Symbol<'static>) {
         use std::collections::hash_map::Entry;
         let name = sym.get().name();
-        let entry = self.map.entry(name);
+        let entry = self.statics.entry(name);
         assert!(matches!(entry, Entry::Vacant(_)), "Attempt to intitalize {name} twice");
-        entry.or_insert_with(|| sym);
+        entry.or_insert_with(|| Interned(sym));
     }
 }
 
 impl SymbolMap {
-    pub(crate) fn intern<'ob>(&mut self, name: &str, cx: &'ob Context) -> Symbol<'ob> {
+    pub(crate) fn intern<'ob>(&self, name: &str, cx: &'ob Context) -> Symbol<'ob> {
         self.map.intern(name, &self.block, cx)
     }
 
     pub(crate) fn set_func(&self, symbol: Symbol, func: Function) -> Result<()> {
-        let new_func = func.clone_in(&self.block);
-        self.block.uninterned_symbol_map.clear();
+        let block = self.block.lock().unwrap();
+        let new_func = func.clone_in(&*block);
+        block.uninterned_symbol_map.clear();
         // SAFETY: The object is marked read-only, we have cloned in the map's
         // context, and it is const, so calling this function is safe.
         unsafe { symbol.set_func(new_func) }
     }
 
-    pub(crate) fn global_block(&self) -> &Block<true> {
-        &self.block
+    /// Copy `obj` into the global block. Objects there are never moved or
+    /// traced by the garbage collector, so this is for data that lives for the
+    /// rest of the program.
+    pub(crate) fn purecopy<'ob>(&self, obj: Object, cx: &'ob Context) -> Object<'ob> {
+        let block = self.block.lock().unwrap();
+        let new_obj: Object = obj.clone_in(&*block);
+        block.uninterned_symbol_map.clear();
+        // The global block is never freed, so the copy can live as long as
+        // any object in `cx`
+        cx.bind(new_obj)
+    }
+
+    /// Bytes allocated in the global block.
+    pub(crate) fn pure_bytes(&self) -> usize {
+        self.block.lock().unwrap().objects.allocated_bytes()
+    }
+
+    pub(crate) fn global_block(&self) -> MutexGuard<'_, Block<true>> {
+        self.block.lock().unwrap()
     }
 
     pub(crate) fn create_buffer(&self, name: &str) -> &LispBuffer {
-        LispBuffer::create(name.to_owned(), &self.block)
+        let block = self.block.lock().unwrap();
+        let buffer = LispBuffer::create(name.to_owned(), &block);
+        // SAFETY: The global block is never freed, so the buffer lives as long
+        // as the map does.
+        unsafe { &*(buffer as *const LispBuffer) }
     }
 
     pub(crate) fn get(&self, name: &str) -> Option<Symbol> {
         self.map.get(name)
     }
+
+    /// Every interned symbol.
+    pub(crate) fn symbols<'ob>(&self, cx: &'ob Context) -> Vec<Symbol<'ob>> {
+        let mut symbols: Vec<_> = self.map.statics.values().map(|x| cx.bind(x.0)).collect();
+        for shard in &self.map.shards {
+            symbols.extend(shard.read().unwrap().values().map(|x| cx.bind(x.0)));
+        }
+        symbols
+    }
 }
 
 // This file includes all symbol definitions. Generated by build.rs
@@ -82,7 +150,7 @@ include!(concat!(env!("OUT_DIR"), "/sym.rs"));
 
 /// Intern a new symbol based on `name`
 pub(crate) fn intern<'ob>(name: &str, cx: &'ob Context) -> Symbol<'ob> {
-    interned_symbols().lock().unwrap().intern(name, cx)
+    interned_symbols().intern(name, cx)
 }
 
 #[cfg(test)]
@@ -107,6 +175,41 @@ mod test {
         intern("foo", cx);
     }
 
+    #[test]
+    fn test_builtin() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        assert_eq!(intern("defun", cx), sym::DEFUN);
+        assert_eq!(intern(":group", cx), sym::KW_GROUP);
+        assert!(sym::NIL.is_builtin());
+        assert!(sym::KW_KEY.is_builtin());
+        assert!(!intern("test-builtin-runtime", cx).is_builtin());
+    }
+
+    #[test]
+    fn test_parallel_intern() {
+        let names: Vec<String> = (0..200).map(|i| format!("parallel-intern-{i}")).collect();
+        let intern_all = |names: &[String]| {
+            let roots = &RootSet::default();
+            let cx = &Context::new(roots);
+            let builtin = intern("car", cx).as_ptr().addr();
+            let interned: Vec<_> = names.iter().map(|x| intern(x, cx).as_ptr().addr()).collect();
+            (builtin, interned)
+        };
+        let results: Vec<_> = std::thread::scope(|s| {
+            let threads: Vec<_> = (0..8).map(|_| s.spawn(|| intern_all(&names))).collect();
+            threads.into_iter().map(|x| x.join().unwrap()).collect()
+        });
+        assert_eq!(results[0].0, sym::CAR.as_ptr().addr());
+        assert!(results.iter().all(|x| *x == results[0]));
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let symbols = interned_symbols().symbols(cx);
+        for name in &names {
+            assert_eq!(symbols.iter().filter(|x| x.name() == name).count(), 1);
+        }
+    }
+
     #[test]
     fn symbol_func() {
         let roots = &RootSet::default();
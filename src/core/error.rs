@@ -1,3 +1,9 @@
+use super::{
+    env::{intern, sym},
+    gc::{Context, Held},
+    object::{Object, Symbol},
+};
+use rune_core::macros::list;
 use std::fmt::{Display, Formatter};
 
 /// The function or form has the wrong number of arguments.
@@ -21,6 +27,44 @@ impl ArgError {
     pub(crate) fn new(expect: u16, actual: u16, name: impl AsRef<str>) -> ArgError {
         Self { expect, actual, name: name.as_ref().to_owned() }
     }
+
+    /// The condition this error signals, `(wrong-number-of-arguments NAME
+    /// ACTUAL)`.
+    pub(crate) fn condition<'ob>(&self, cx: &'ob Context) -> (Symbol<'static>, Object<'ob>) {
+        let data = list![intern(&self.name, cx), i64::from(self.actual); cx];
+        (sym::WRONG_NUMBER_OF_ARGUMENTS, data)
+    }
+}
+
+/// A constant variable, like `nil`, a keyword, or one made with `defconst`,
+/// was set.
+#[derive(Debug, PartialEq)]
+pub(crate) struct ConstantError {
+    /// The symbol that was set, given as the data of the condition
+    symbol: Held,
+    name: String,
+}
+
+impl std::error::Error for ConstantError {}
+
+impl Display for ConstantError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "Attempt to set a constant symbol: {}", self.name)
+    }
+}
+
+impl ConstantError {
+    pub(crate) fn new(symbol: Symbol) -> ConstantError {
+        Self { symbol: Held::new(symbol.into()), name: symbol.name().to_owned() }
+    }
+
+    /// The condition this error signals, `(setting-constant SYMBOL)`. If
+    /// SYMBOL is no longer available, because the error outlived its context,
+    /// it is interned from its name instead.
+    pub(crate) fn condition<'ob>(&self, cx: &'ob Context) -> (Symbol<'static>, Object<'ob>) {
+        let symbol = self.symbol.get(cx).unwrap_or_else(|| intern(&self.name, cx).into());
+        (sym::SETTING_CONSTANT, list![symbol; cx])
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -40,22 +84,93 @@ pub(crate) enum Type {
     Number,
     List,
     Buffer,
+    Stream,
+}
+
+impl Type {
+    /// The predicate that Emacs reports for an object that is not this type.
+    fn predicate(&self) -> &'static str {
+        match self {
+            Type::Int => "integerp",
+            Type::Char => "characterp",
+            Type::Cons => "consp",
+            Type::Vec => "vectorp",
+            Type::Record => "recordp",
+            Type::HashTable => "hash-table-p",
+            Type::Sequence => "sequencep",
+            Type::BufferOrName => "stringp",
+            Type::String => "stringp",
+            Type::Symbol => "symbolp",
+            Type::Float => "floatp",
+            Type::Func => "functionp",
+            Type::Number => "numberp",
+            Type::List => "listp",
+            Type::Buffer => "bufferp",
+            Type::Stream => "streamp",
+        }
+    }
+}
+
+/// Objects printed in errors are cut off after this many characters.
+const MAX_PRINT_LEN: usize = 80;
+
+/// Print `obj`, cut off after [`MAX_PRINT_LEN`] characters. Printing stops
+/// there, so a large object is never printed in full.
+fn truncated(obj: impl Display) -> String {
+    use std::fmt::Write as _;
+
+    struct Limited(String, usize);
+
+    impl std::fmt::Write for Limited {
+        fn write_str(&mut self, s: &str) -> std::fmt::Result {
+            for c in s.chars() {
+                if self.1 == MAX_PRINT_LEN {
+                    return Err(std::fmt::Error);
+                }
+                self.0.push(c);
+                self.1 += 1;
+            }
+            Ok(())
+        }
+    }
+
+    let mut out = Limited(String::new(), 0);
+    if write!(out, "{obj}").is_err() {
+        out.0.push_str("...");
+    }
+    out.0
 }
 
 /// Error provided if object was the wrong type
 #[derive(Debug, PartialEq)]
 pub(crate) struct TypeError {
+    /// The error symbol, `wrong-type-argument` or `invalid-function`
+    symbol: Symbol<'static>,
     expect: Type,
     actual: Type,
+    /// The object that was rejected, given as the data of the condition
+    object: Held,
+    /// The object printed for the message
     print: String,
+    /// The function that rejected the object
+    operation: Option<String>,
+    /// The innermost form that was being evaluated
+    form: Option<String>,
 }
 
 impl std::error::Error for TypeError {}
 
 impl Display for TypeError {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        let Self { expect, actual, print } = self;
-        write!(f, "expected {expect:?}, found {actual:?}: {print}")
+        let Self { symbol: _, expect, actual: _, object: _, print, operation, form } = self;
+        write!(f, "wrong-type-argument {} {print}", expect.predicate())?;
+        if let Some(operation) = operation {
+            write!(f, " for `{operation}'")?;
+        }
+        if let Some(form) = form {
+            write!(f, " in {form}")?;
+        }
+        Ok(())
     }
 }
 
@@ -66,6 +181,122 @@ impl TypeError {
         T: Into<super::object::ObjectType<'ob>>,
     {
         let obj = obj.into();
-        Self { expect, actual: obj.get_type(), print: obj.to_string() }
+        let symbol = match expect {
+            Type::Func => sym::INVALID_FUNCTION,
+            _ => sym::WRONG_TYPE_ARGUMENT,
+        };
+        Self {
+            symbol,
+            expect,
+            actual: obj.get_type(),
+            print: truncated(obj),
+            object: Held::new(obj.into()),
+            operation: None,
+            form: None,
+        }
+    }
+
+    /// The condition this error signals, `(wrong-type-argument PREDICATE
+    /// VALUE)`, or `(invalid-function VALUE)` if VALUE was called. If VALUE
+    /// is no longer available, because the error outlived its context, the
+    /// printed object is given instead.
+    pub(crate) fn condition<'ob>(&self, cx: &'ob Context) -> (Symbol<'static>, Object<'ob>) {
+        let value = self.object.get(cx).unwrap_or_else(|| cx.add(self.print.as_str()));
+        if self.symbol == sym::INVALID_FUNCTION {
+            return (self.symbol, list![value; cx]);
+        }
+        (self.symbol, list![intern(self.expect.predicate(), cx), value; cx])
+    }
+
+    /// Record the function that rejected the object, unless an inner one
+    /// already did.
+    pub(crate) fn set_operation(&mut self, name: &str) {
+        self.operation.get_or_insert_with(|| name.to_owned());
+    }
+
+    /// Record the form that was being evaluated, unless an inner form already
+    /// was.
+    pub(crate) fn set_form(&mut self, form: super::object::Object) {
+        if self.form.is_none() {
+            self.form = Some(truncated(form));
+        }
+    }
+}
+
+/// The condition an error from rust signals, as the error symbol and its
+/// data, if it is one of the errors in this module. This is the one place
+/// that knows about all of them, so every error is signaled the same way
+/// whether it is caught in lisp or returned to an embedder.
+pub(crate) fn condition<'ob>(
+    error: &anyhow::Error,
+    cx: &'ob Context,
+) -> Option<(Symbol<'static>, Object<'ob>)> {
+    if let Some(e) = error.downcast_ref::<TypeError>() {
+        Some(e.condition(cx))
+    } else if let Some(e) = error.downcast_ref::<ArgError>() {
+        Some(e.condition(cx))
+    } else {
+        error.downcast_ref::<ConstantError>().map(|e| e.condition(cx))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::core::object::ObjectType;
+
+    #[test]
+    fn test_type_error() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let mut err = TypeError::new(Type::List, Object::from(5));
+        assert_eq!(err.to_string(), "wrong-type-argument listp 5");
+        err.set_form(list![intern("let", cx), list![intern("foo", cx), 1; cx]; cx]);
+        // Only the innermost form is kept
+        err.set_form(list![1, 2; cx]);
+        assert_eq!(err.to_string(), "wrong-type-argument listp 5 in (let (foo 1))");
+        err.set_operation("car");
+        err.set_operation("cdr");
+        assert_eq!(err.to_string(), "wrong-type-argument listp 5 for `car' in (let (foo 1))");
+        let long: String = "x".repeat(200);
+        let err = TypeError::new(Type::Int, cx.add(long.as_str()));
+        assert!(err.to_string().ends_with("...") && err.to_string().len() < 120);
+    }
+
+    #[test]
+    fn test_condition() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        let (symbol, data) = TypeError::new(Type::List, Object::from(5)).condition(cx);
+        assert_eq!(symbol, sym::WRONG_TYPE_ARGUMENT);
+        assert_eq!(data, list![intern("listp", cx), 5; cx]);
+        let (symbol, data) = TypeError::new(Type::Func, Object::from(5)).condition(cx);
+        assert_eq!(symbol, sym::INVALID_FUNCTION);
+        assert_eq!(data, list![5; cx]);
+        // The data is the object that was rejected, not a copy of it
+        let string = cx.add("foo");
+        let err = TypeError::new(Type::Int, string);
+        cx.garbage_collect(true);
+        let (_, data) = err.condition(cx);
+        let value = data.as_cons().cdr().as_cons().car();
+        assert_eq!(value, "foo");
+        let buffer = crate::buffer::get_buffer_create(cx.add("test-condition"), None, cx).unwrap();
+        let (_, data) = TypeError::new(Type::Int, buffer).condition(cx);
+        assert_eq!(data.as_cons().cdr().as_cons().car(), buffer);
+        let (symbol, data) = ArgError::new(1, 3, "car").condition(cx);
+        assert_eq!(symbol, sym::WRONG_NUMBER_OF_ARGUMENTS);
+        assert_eq!(data, list![intern("car", cx), 3; cx]);
+        let (symbol, data) = ConstantError::new(sym::TRUE).condition(cx);
+        assert_eq!(symbol, sym::SETTING_CONSTANT);
+        assert_eq!(data, list![sym::TRUE; cx]);
+        // An uninterned symbol is reported as itself, not the interned one
+        let uninterned = Symbol::new_uninterned("nil", cx);
+        let err = ConstantError::new(uninterned);
+        cx.garbage_collect(true);
+        let (_, data) = err.condition(cx);
+        let ObjectType::Symbol(reported) = data.as_cons().car().untag() else { unreachable!() };
+        assert!(!reported.interned());
+        assert_eq!(reported.name(), "nil");
     }
 }
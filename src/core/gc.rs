@@ -2,7 +2,9 @@ mod root;
 mod trace;
 #[macro_use]
 mod context;
+mod dump;
 mod heap;
+pub(crate) mod profiler;
 pub(crate) use context::*;
 pub(crate) use heap::*;
 pub(crate) use root::*;
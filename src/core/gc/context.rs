@@ -1,13 +1,21 @@
+use super::profiler;
 use super::GcState;
+use super::ObjectCounts;
+use super::Slot;
 use super::Trace;
 use crate::core::object::GcString;
 use crate::core::object::LispHashTable;
-use crate::core::object::{Gc, IntoObject, Object, UninternedSymbolMap, WithLifetime};
+use crate::core::object::Record;
+use crate::core::object::{Gc, IntoObject, Object, RawObj, UninternedSymbolMap, WithLifetime};
 use bumpalo::collections::Vec as GcVec;
+use rune_core::hashmap::{HashMap, HashSet};
+use std::any::Any;
 use std::cell::{Cell, RefCell};
 use std::fmt::Debug;
+use std::num::NonZeroU32;
 use std::ops::Deref;
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64};
+use std::time::{Duration, Instant};
 
 /// A global store of all gc roots. This struct should be passed to the [Context]
 /// when it is created.
@@ -50,11 +58,67 @@ unsafe impl<const C: bool> Send for Block<C> {}
 pub(crate) struct Context<'rt> {
     pub(crate) block: Block<false>,
     root_set: &'rt RootSet,
-    next_limit: usize,
+    /// Bytes of live data after the last collection
+    live_bytes: usize,
+    /// How fast the last collection copied live data, in bytes per second
+    copy_rate: Option<f64>,
+    stats: GcStats,
+}
+
+/// Totals for all collections by a [`Context`].
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct GcStats {
+    /// Number of collections done
+    pub(crate) collections: usize,
+    /// Time spent collecting
+    pub(crate) total_pause: Duration,
+    /// Live objects found by the last collection
+    pub(crate) counts: ObjectCounts,
+    /// Bytes used by live objects after the last collection
+    pub(crate) bytes_used: usize,
+    /// Bytes that can be allocated before the heap has to grow
+    pub(crate) bytes_free: usize,
+}
+
+/// When to collect, as set by `gc-cons-threshold` and `gc-cons-percentage`.
+/// A collection happens once the bytes allocated since the last one exceed
+/// both the threshold and the percentage of the live heap.
+#[derive(Debug, Clone, Copy)]
+struct GcLimits {
+    threshold: usize,
+    percentage: f64,
+}
+
+/// Default value of `gc-cons-threshold`
+pub(crate) const GC_CONS_THRESHOLD: usize = 800_000;
+/// Default value of `gc-cons-percentage`
+pub(crate) const GC_CONS_PERCENTAGE: f64 = 0.1;
+
+/// Bytes per second that [`Context::collect_budgeted`] assumes can be copied
+/// before the first collection has measured it. This is on the slow side, so
+/// that the first pause is not underestimated.
+const ASSUMED_COPY_RATE: f64 = 100_000_000.0;
+
+thread_local! {
+    static GC_LIMITS: Cell<GcLimits> = const {
+        Cell::new(GcLimits { threshold: GC_CONS_THRESHOLD, percentage: GC_CONS_PERCENTAGE })
+    };
+}
+
+/// Set the number of bytes that can be allocated before collecting.
+pub(crate) fn set_gc_cons_threshold(bytes: usize) {
+    GC_LIMITS.with(|x| x.set(GcLimits { threshold: bytes, ..x.get() }));
+}
+
+/// Set the fraction of the live heap that can be allocated before collecting.
+pub(crate) fn set_gc_cons_percentage(percentage: f64) {
+    GC_LIMITS.with(|x| x.set(GcLimits { percentage, ..x.get() }));
 }
 
 impl<'rt> Drop for Context<'rt> {
     fn drop(&mut self) {
+        // Objects held by errors that outlive the context can't be kept
+        HELD.with_borrow_mut(|held| held.clear());
         self.garbage_collect(true);
         if self.block.objects.allocated_bytes() == 0 {
             return;
@@ -72,6 +136,108 @@ thread_local! {
     static SINGLETON_CHECK: Cell<bool> = const { Cell::new(false) };
 }
 
+/// Identifies the Rust data attached to a record with [`attach`]. It is
+/// stored in the record itself, so every copy of the record refers to the
+/// same data.
+pub(crate) type AttachId = NonZeroU32;
+
+/// Ids are unique across threads, so a record shared through the global block
+/// can't be mistaken for one made on another thread.
+static NEXT_ATTACH_ID: AtomicU32 = AtomicU32::new(1);
+
+struct Attached {
+    data: Box<dyn Any>,
+    /// A record with this id was copied into the global block, which is never
+    /// collected, so the data has to live as long as the thread.
+    pinned: bool,
+}
+
+thread_local! {
+    /// Rust data attached to records with [`attach`], by the id stored in the
+    /// records. These are weak references: they don't keep a record alive,
+    /// and the data is dropped once no record with its id survives a
+    /// collection.
+    static ATTACHED: RefCell<HashMap<AttachId, Attached>> = RefCell::new(HashMap::default());
+}
+
+/// Attach `data` to `record` and every later copy of it. It will be dropped
+/// when they have all been garbage collected.
+pub(crate) fn attach(record: &Record, data: Box<dyn Any>) {
+    let id = NEXT_ATTACH_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let id = AttachId::new(id).expect("attach ids should not run out");
+    record.set_attached(Some(id));
+    ATTACHED.with_borrow_mut(|attached| attached.insert(id, Attached { data, pinned: false }));
+}
+
+/// Call `f` with the data attached to `record`, if there is data of type `T`.
+pub(crate) fn with_attached<T: Any, U>(record: &Record, f: impl FnOnce(&T) -> U) -> Option<U> {
+    let id = record.attached()?;
+    ATTACHED.with_borrow(|attached| attached.get(&id)?.data.downcast_ref::<T>().map(f))
+}
+
+/// Keep the data with `id` alive for the rest of the thread, because a record
+/// referring to it was copied into the global block.
+pub(crate) fn pin_attached(id: AttachId) {
+    ATTACHED.with_borrow_mut(|attached| {
+        if let Some(entry) = attached.get_mut(&id) {
+            entry.pinned = true;
+        }
+    });
+}
+
+/// Drop the attached data of records that didn't survive a collection. `live`
+/// is the ids found in the records that did.
+fn sweep_attached(live: &HashSet<AttachId>) {
+    let dead: Vec<Attached> = ATTACHED.with_borrow_mut(|attached| {
+        let ids: Vec<AttachId> = attached
+            .iter()
+            .filter(|(id, entry)| !entry.pinned && !live.contains(*id))
+            .map(|(id, _)| *id)
+            .collect();
+        ids.iter().filter_map(|id| attached.remove(id)).collect()
+    });
+    // Drop outside of the borrow, because the destructors could look at
+    // other attached data
+    drop(dead);
+}
+
+/// Ids are unique across threads, so a [`Held`] object that was sent to
+/// another thread is never found in that thread's table.
+static NEXT_HELD_ID: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// Objects kept alive by a [`Held`]. They are traced like roots.
+    static HELD: RefCell<HashMap<u64, Slot<Object<'static>>>> = RefCell::new(HashMap::default());
+}
+
+/// An object that is kept alive without a root, for data that can't follow
+/// the stack discipline of roots, like the object in an error that is being
+/// returned. The object is traced by every collection until this is dropped.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Held(u64);
+
+impl Held {
+    pub(crate) fn new(obj: Object) -> Self {
+        let id = NEXT_HELD_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let obj = unsafe { obj.with_lifetime() };
+        HELD.with_borrow_mut(|held| held.insert(id, Slot::new(obj)));
+        Self(id)
+    }
+
+    /// The held object, or `None` if it was held on another thread or the
+    /// context it belonged to was dropped.
+    pub(crate) fn get<'ob>(&self, cx: &'ob Context) -> Option<Object<'ob>> {
+        HELD.with_borrow(|held| held.get(&self.0).map(|obj| cx.bind(**obj)))
+    }
+}
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        // The table is already gone if this is dropped while the thread exits
+        let _ = HELD.try_with(|held| held.borrow_mut().remove(&self.0));
+    }
+}
+
 /// Ensure there is only one global context.
 static GLOBAL_CHECK: AtomicBool = AtomicBool::new(false);
 
@@ -107,7 +273,7 @@ impl<const CONST: bool> Block<CONST> {
         T: IntoObject<Out<'ob> = Tx>,
         Gc<Tx>: Into<Object<'ob>>,
     {
-        obj.into_obj(self).into()
+        self.profiled(|| obj.into_obj(self).into())
     }
 
     pub(crate) fn add_as<'ob, T, Tx, V>(&'ob self, obj: T) -> Gc<V>
@@ -115,7 +281,21 @@ impl<const CONST: bool> Block<CONST> {
         T: IntoObject<Out<'ob> = Tx>,
         Gc<Tx>: Into<Gc<V>>,
     {
-        obj.into_obj(self).into()
+        self.profiled(|| obj.into_obj(self).into())
+    }
+
+    /// Run `alloc`, and record the memory it allocated if the allocation
+    /// profiler is running.
+    #[inline]
+    pub(crate) fn profiled<T>(&self, alloc: impl FnOnce() -> T) -> T {
+        if !profiler::enabled() {
+            return alloc();
+        }
+        let used = |bump: &bumpalo::Bump| bump.allocated_bytes() - bump.chunk_capacity();
+        let before = used(&self.objects);
+        let value = alloc();
+        profiler::record(used(&self.objects).saturating_sub(before));
+        value
     }
 
     /// Create a new String whose backing storage is already part of the GC
@@ -138,15 +318,23 @@ impl<const CONST: bool> Block<CONST> {
 }
 
 impl<'ob, 'rt> Context<'rt> {
-    const MIN_GC_BYTES: usize = 2000;
-    const GC_GROWTH_FACTOR: usize = 12; // divide by 10
     pub(crate) fn new(roots: &'rt RootSet) -> Self {
-        Self { block: Block::new_local(), root_set: roots, next_limit: Self::MIN_GC_BYTES }
+        Self::from_parts(Block::new_local(), roots)
     }
 
     pub(crate) fn from_block(block: Block<false>, roots: &'rt RootSet) -> Self {
         Block::assert_unique();
-        Context { block, root_set: roots, next_limit: Self::MIN_GC_BYTES }
+        Self::from_parts(block, roots)
+    }
+
+    fn from_parts(block: Block<false>, roots: &'rt RootSet) -> Self {
+        Context {
+            block,
+            root_set: roots,
+            live_bytes: 0,
+            copy_rate: None,
+            stats: GcStats::default(),
+        }
     }
 
     pub(crate) fn bind<T>(&'ob self, obj: T) -> <T as WithLifetime>::Out
@@ -160,14 +348,39 @@ impl<'ob, 'rt> Context<'rt> {
         self.root_set
     }
 
+    pub(crate) fn gc_stats(&self) -> &GcStats {
+        &self.stats
+    }
+
+    /// Bytes allocated in this context, including garbage that has not been
+    /// collected yet.
+    pub(crate) fn allocated_bytes(&self) -> usize {
+        self.block.objects.allocated_bytes()
+    }
+
     pub(crate) fn garbage_collect(&mut self, force: bool) {
-        let bytes = self.block.objects.allocated_bytes();
-        if cfg!(not(test)) && !force && bytes < self.next_limit {
+        if cfg!(not(test)) && !force && !self.over_limit() {
             return;
         }
+        self.collect(GcState::new());
+    }
+
+    /// Whether enough was allocated since the last collection to need another,
+    /// by the limits from `gc-cons-threshold` and `gc-cons-percentage`.
+    fn over_limit(&self) -> bool {
+        let consed = self.block.objects.allocated_bytes().saturating_sub(self.live_bytes);
+        let limits = GC_LIMITS.get();
+        let limit = limits.threshold.max((self.live_bytes as f64 * limits.percentage) as usize);
+        consed >= limit
+    }
 
-        let mut state = GcState::new();
-        for x in self.root_set.roots.borrow().iter() {
+    /// Run a collection with `state`, and return the log of live objects if
+    /// `state` was created to keep one.
+    pub(in crate::core) fn collect(&mut self, mut state: GcState) -> Option<Vec<(usize, RawObj)>> {
+        let _span = trace_span!("gc", before = self.block.objects.allocated_bytes());
+        let start = Instant::now();
+        for (i, x) in self.root_set.roots.borrow().iter().enumerate() {
+            state.current_root = i;
             // SAFETY: The contract of root structs will ensure that it removes
             // itself from this list before it drops.
             unsafe {
@@ -175,9 +388,10 @@ impl<'ob, 'rt> Context<'rt> {
             }
         }
 
+        HELD.with_borrow(|held| held.values().for_each(|obj| obj.trace(&mut state)));
         state.trace_stack();
 
-        self.next_limit = (state.to_space.allocated_bytes() * Self::GC_GROWTH_FACTOR) / 10;
+        self.live_bytes = state.to_space.allocated_bytes();
         self.block.drop_stack.borrow_mut().clear();
         // Find all hashtables that have not been moved (i.e. They are no longer
         // accessible) and drop them. Otherwise, update the object pointer.
@@ -191,8 +405,50 @@ impl<'ob, 'rt> Context<'rt> {
                 false
             }
         });
+        sweep_attached(&state.attached);
 
         self.block.objects = state.to_space;
+        let elapsed = start.elapsed();
+        if !elapsed.is_zero() && self.live_bytes > 0 {
+            self.copy_rate = Some(self.live_bytes as f64 / elapsed.as_secs_f64());
+        }
+        self.stats.collections += 1;
+        self.stats.total_pause += elapsed;
+        self.stats.counts = state.counts;
+        self.stats.bytes_used = self.live_bytes;
+        self.stats.bytes_free = self.block.objects.chunk_capacity();
+        trace_event!(live_bytes = self.live_bytes, ?elapsed, "collected");
+        state.log
+    }
+
+    /// Collect garbage if it is expected to take less than `budget`. This is
+    /// meant for an embedder that has idle time, like an editor between
+    /// keystrokes, so that it can collect when a pause won't be noticed.
+    /// Returns whether a collection happened.
+    ///
+    /// This is not incremental. The collector copies all live data in one pass
+    /// and can't be paused partway through, so the pause is estimated from the
+    /// speed of the last collection, or from [`ASSUMED_COPY_RATE`] and
+    /// everything allocated so far if there wasn't one. If it doesn't fit
+    /// nothing is done, unless the allocation limit has been reached. Then a
+    /// full collection is done regardless of `budget`, so a live set that
+    /// has outgrown the budget is still collected.
+    pub(crate) fn collect_budgeted(&mut self, budget: Duration) -> bool {
+        if self.block.objects.allocated_bytes() <= self.live_bytes {
+            // Nothing was allocated since the last collection
+            return false;
+        }
+        if !self.over_limit() {
+            let (bytes, rate) = match self.copy_rate {
+                Some(rate) => (self.live_bytes, rate),
+                None => (self.block.objects.allocated_bytes(), ASSUMED_COPY_RATE),
+            };
+            if Duration::from_secs_f64(bytes as f64 / rate) > budget {
+                return false;
+            }
+        }
+        self.collect(GcState::new());
+        true
     }
 }
 
@@ -227,8 +483,9 @@ mod test {
 
     use crate::core::{
         cons::Cons,
-        object::{HashTable, ObjectType, Symbol},
+        object::{HashTable, ObjectType, RecordBuilder, Symbol, NIL},
     };
+    use std::rc::Rc;
 
     use super::*;
     fn bind_to_mut<'ob>(cx: &'ob mut Context) -> Object<'ob> {
@@ -288,4 +545,57 @@ mod test {
         assert_eq!(**float, 1.5);
         assert_eq!(int, 1);
     }
+
+    #[test]
+    fn test_attached_data() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        let data = Rc::new(7);
+        let mut vec = cx.vec_with_capacity(1);
+        vec.push(cx.add("attached"));
+        let record = RecordBuilder(vec).into_obj(cx);
+        attach(record.untag(), Box::new(data.clone()));
+        let obj: Object = record.into();
+        root!(obj, cx);
+        cx.garbage_collect(true);
+        let ObjectType::Record(record) = obj.bind(cx).untag() else { unreachable!() };
+        assert_eq!(with_attached(record, |x: &Rc<i32>| **x), Some(7));
+        assert_eq!(with_attached(record, |x: &String| x.clone()), None);
+        obj.set(NIL);
+        cx.garbage_collect(true);
+        assert_eq!(Rc::strong_count(&data), 1);
+    }
+
+    #[test]
+    fn test_collect_budgeted() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(vec, new(Vec), cx);
+        for i in 0..100 {
+            vec.push(list![i, "string"; cx]);
+        }
+        assert!(cx.collect_budgeted(Duration::from_secs(60)));
+        // nothing new to collect
+        assert!(!cx.collect_budgeted(Duration::from_secs(60)));
+        _ = cx.add("garbage");
+        assert!(!cx.collect_budgeted(Duration::ZERO));
+        assert!(cx.collect_budgeted(Duration::from_secs(60)));
+        // past the allocation limit the budget is ignored
+        set_gc_cons_threshold(0);
+        set_gc_cons_percentage(0.0);
+        _ = cx.add("garbage");
+        assert!(cx.collect_budgeted(Duration::ZERO));
+        set_gc_cons_threshold(GC_CONS_THRESHOLD);
+        set_gc_cons_percentage(GC_CONS_PERCENTAGE);
+    }
+
+    #[test]
+    fn test_collect_budgeted_first() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        _ = cx.add("garbage");
+        // Without a previous collection the pause is still estimated
+        assert!(!cx.collect_budgeted(Duration::ZERO));
+        assert!(cx.collect_budgeted(Duration::from_secs(60)));
+    }
 }
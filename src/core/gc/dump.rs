@@ -0,0 +1,142 @@
+//! Writing the live heap as a graph, for finding what keeps objects alive.
+use super::{Context, GcState};
+use crate::core::object::{Object, ObjectType};
+use rune_core::hashmap::{HashMap, HashSet};
+use sptr::Strict;
+use std::io::{self, Write};
+
+/// The objects an object refers to.
+fn children(obj: Object) -> Vec<Object> {
+    match obj.untag() {
+        ObjectType::Cons(cons) => vec![cons.car(), cons.cdr()],
+        ObjectType::Vec(vec) => vec.iter().map(|x| x.get()).collect(),
+        ObjectType::Record(record) => record.iter().map(|x| x.get()).collect(),
+        ObjectType::HashTable(table) => {
+            let mut children = Vec::new();
+            for i in 0..table.len() {
+                if let Some((key, value)) = table.get_index(i) {
+                    children.push(key);
+                    children.push(value);
+                }
+            }
+            children
+        }
+        ObjectType::ByteFn(func) => func.consts().to_vec(),
+        _ => Vec::new(),
+    }
+}
+
+fn label(obj: Object) -> String {
+    const MAX: usize = 20;
+    let short = |s: &str| {
+        let mut text: String = s.chars().take(MAX).collect();
+        if s.chars().nth(MAX).is_some() {
+            text.push_str("...");
+        }
+        format!("{text:?}")
+    };
+    let label = match obj.untag() {
+        ObjectType::Cons(_) => "cons".to_owned(),
+        ObjectType::Vec(vec) => format!("vector [{}]", vec.len()),
+        ObjectType::Record(record) => {
+            let type_name = record.first().map(|x| x.get()).unwrap_or_default();
+            format!("record {type_name}")
+        }
+        ObjectType::HashTable(table) => format!("hash-table [{}]", table.len()),
+        ObjectType::String(s) => format!("string {}", short(s)),
+        ObjectType::ByteString(s) => format!("unibyte-string [{}]", s.len()),
+        ObjectType::Symbol(s) => format!("symbol {}", s.name()),
+        ObjectType::ByteFn(_) => "byte-code-function".to_owned(),
+        ObjectType::Buffer(_) => "buffer".to_owned(),
+        ObjectType::Float(f) => format!("float {}", **f),
+        ObjectType::Int(i) => format!("integer {i}"),
+        ObjectType::SubrFn(f) => format!("subr {}", f.name),
+    };
+    // Escape for a DOT string
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn node_id(obj: Object) -> String {
+    format!("n{:x}", obj.into_ptr().addr())
+}
+
+impl Context<'_> {
+    /// Collect garbage and write every live object to `writer` as a graph in
+    /// the DOT format. There is an edge from each object to the objects it
+    /// refers to, and from each root to the objects that no other object
+    /// refers to. Objects outside the local heap, like interned symbols, are
+    /// included when something refers to them, but not followed.
+    pub(crate) fn dump_heap(&mut self, writer: &mut impl Write) -> io::Result<()> {
+        let mut state = GcState::new();
+        state.log = Some(Vec::new());
+        let log = self.collect(state).unwrap_or_default();
+        // SAFETY: the log holds the new address of every object, and nothing
+        // has been allocated since the collection
+        let live: Vec<(usize, Object)> =
+            log.into_iter().map(|(root, raw)| (root, unsafe { Object::from_raw(raw) })).collect();
+        let live_ids: HashSet<String> = live.iter().map(|(_, obj)| node_id(*obj)).collect();
+
+        writeln!(writer, "digraph heap {{")?;
+        let mut referenced = HashSet::default();
+        let mut external = HashMap::default();
+        let mut edges = Vec::new();
+        for (_, obj) in &live {
+            let id = node_id(*obj);
+            writeln!(writer, "  {id} [label=\"{}\"];", label(*obj))?;
+            for child in children(*obj) {
+                if matches!(child.untag(), ObjectType::Int(_)) || child.is_nil() {
+                    continue;
+                }
+                let child_id = node_id(child);
+                if !live_ids.contains(&child_id) {
+                    external.entry(child_id.clone()).or_insert_with(|| label(child));
+                }
+                referenced.insert(child_id.clone());
+                edges.push((id.clone(), child_id));
+            }
+        }
+        for (id, label) in &external {
+            writeln!(writer, "  {id} [label=\"{label}\", style=dashed];")?;
+        }
+        let mut roots = HashSet::default();
+        for (root, obj) in &live {
+            let id = node_id(*obj);
+            if !referenced.contains(&id) {
+                if roots.insert(*root) {
+                    writeln!(writer, "  root{root} [label=\"root {root}\", shape=box];")?;
+                }
+                writeln!(writer, "  root{root} -> {id};")?;
+            }
+        }
+        for (from, to) in edges {
+            writeln!(writer, "  {from} -> {to};")?;
+        }
+        writeln!(writer, "}}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use rune_core::macros::{list, root};
+
+    #[test]
+    fn test_dump_heap() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        let inner = list!["leaf"; cx];
+        let outer = list![inner, 1.5; cx];
+        root!(outer, cx);
+        let mut out = Vec::new();
+        cx.dump_heap(&mut out).unwrap();
+        let dot = String::from_utf8(out).unwrap();
+        assert!(dot.starts_with("digraph heap {"));
+        assert!(dot.contains("label=\"string \\\"leaf\\\"\""));
+        assert!(dot.contains("label=\"float 1.5\""));
+        // only the outer list is held directly by the root
+        assert_eq!(dot.matches("root0 -> ").count(), 1);
+        assert_eq!(dot.matches(" -> ").count(), 5);
+        assert_eq!(outer.bind(cx).to_string(), "((\"leaf\") 1.5)");
+    }
+}
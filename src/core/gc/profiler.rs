@@ -0,0 +1,285 @@
+//! Optional profilers that record which lisp function allocated memory, and
+//! how often each function is called and how long it takes.
+//!
+//! The function names are kept on a stack alongside the frames of the
+//! [`LispStack`](crate::core::env::LispStack). Each name is tagged with the
+//! frame depth it was called at, so popping or unwinding frames only has to
+//! drop the names above the new depth. When neither profiler is running the
+//! hooks only check two flags.
+use rune_core::hashmap::HashMap;
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Memory allocated by one function.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) struct Allocations {
+    pub(crate) count: usize,
+    pub(crate) bytes: usize,
+}
+
+/// A function being called.
+struct Entry {
+    depth: usize,
+    name: Box<str>,
+    /// The node in the call tree and when the call started, if the call
+    /// profiler was running
+    call: Option<(usize, Instant)>,
+}
+
+/// A node in the call tree. A function has a separate node for every path
+/// it was called through.
+struct Node {
+    name: Box<str>,
+    calls: usize,
+    total: Duration,
+    children: HashMap<Box<str>, usize>,
+}
+
+#[derive(Default)]
+struct State {
+    stack: Vec<Entry>,
+    /// Allocations by function name. `None` is for allocations made outside
+    /// of any function.
+    table: HashMap<Option<Box<str>>, Allocations>,
+    nodes: Vec<Node>,
+    /// Nodes of functions called with no profiled caller
+    roots: HashMap<Box<str>, usize>,
+}
+
+impl State {
+    /// Remove the entries for frame `depth` and every frame above it.
+    fn pop_frames(&mut self, depth: usize) {
+        while self.stack.last().is_some_and(|entry| entry.depth >= depth) {
+            let entry = self.stack.pop().unwrap();
+            if let Some((node, start)) = entry.call {
+                if calls_enabled() {
+                    self.nodes[node].total += start.elapsed();
+                }
+            }
+        }
+    }
+
+    /// The node for `name` called from `parent`, counting the call.
+    fn call_node(&mut self, parent: Option<usize>, name: &str) -> usize {
+        let len = self.nodes.len();
+        let children = match parent {
+            Some(parent) => &mut self.nodes[parent].children,
+            None => &mut self.roots,
+        };
+        let node = *children.entry(name.into()).or_insert(len);
+        if node == len {
+            let children = HashMap::default();
+            self.nodes.push(Node { name: name.into(), calls: 0, total: Duration::ZERO, children });
+        }
+        self.nodes[node].calls += 1;
+        node
+    }
+
+    fn call_profile(&self, children: &HashMap<Box<str>, usize>) -> Vec<CallProfile> {
+        let mut profiles: Vec<_> = children
+            .values()
+            .map(|&i| {
+                let node = &self.nodes[i];
+                let children = self.call_profile(&node.children);
+                let callees = children.iter().map(|x| x.total).sum();
+                CallProfile {
+                    name: node.name.clone(),
+                    calls: node.calls,
+                    total: node.total,
+                    self_time: node.total.saturating_sub(callees),
+                    children,
+                }
+            })
+            .collect();
+        profiles.sort_by(|a, b| b.total.cmp(&a.total).then_with(|| a.name.cmp(&b.name)));
+        profiles
+    }
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+    /// The call profiler only times the thread that started it, since the
+    /// call tree of each thread is separate.
+    static CALLS_ENABLED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether the memory profiler is running.
+#[inline]
+pub(crate) fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+#[inline]
+fn calls_enabled() -> bool {
+    CALLS_ENABLED.get()
+}
+
+/// Whether either profiler needs the function stack.
+#[inline]
+fn tracking() -> bool {
+    enabled() || calls_enabled()
+}
+
+/// Start recording allocations, discarding anything recorded before.
+pub(crate) fn start() {
+    STATE.with_borrow_mut(|state| {
+        if !tracking() {
+            state.stack.clear();
+        }
+        state.table = HashMap::default();
+    });
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Stop recording allocations. Returns whether the profiler was running.
+pub(crate) fn stop() -> bool {
+    ENABLED.swap(false, Ordering::Relaxed)
+}
+
+/// Start recording function calls, discarding anything recorded before.
+/// Functions that are already running are not part of the report.
+pub(crate) fn start_calls() {
+    STATE.with_borrow_mut(|state| {
+        if tracking() {
+            state.stack.iter_mut().for_each(|entry| entry.call = None);
+        } else {
+            state.stack.clear();
+        }
+        state.nodes.clear();
+        state.roots = HashMap::default();
+    });
+    CALLS_ENABLED.set(true);
+}
+
+/// Stop recording function calls. Returns whether the profiler was running.
+pub(crate) fn stop_calls() -> bool {
+    CALLS_ENABLED.replace(false)
+}
+
+/// Record that the function `name` was called in frame `depth`.
+pub(crate) fn enter_function(depth: usize, name: &str) {
+    if tracking() {
+        STATE.with_borrow_mut(|state| {
+            state.pop_frames(depth);
+            let call = calls_enabled().then(|| {
+                let parent = state.stack.last().and_then(|entry| entry.call).map(|x| x.0);
+                (state.call_node(parent, name), Instant::now())
+            });
+            state.stack.push(Entry { depth, name: name.into(), call });
+        });
+    }
+}
+
+/// Record that all frames above `depth` have returned.
+#[inline]
+pub(crate) fn leave_frames(depth: usize) {
+    if tracking() {
+        STATE.with_borrow_mut(|state| state.pop_frames(depth + 1));
+    }
+}
+
+/// Record an allocation of `bytes` by the current function.
+pub(crate) fn record(bytes: usize) {
+    STATE.with_borrow_mut(|state| {
+        let name = state.stack.last().map(|entry| entry.name.clone());
+        let entry = state.table.entry(name).or_default();
+        entry.count += 1;
+        entry.bytes += bytes;
+    });
+}
+
+/// The allocations recorded so far, largest first.
+pub(crate) fn report() -> Vec<(Option<Box<str>>, Allocations)> {
+    let mut report: Vec<_> =
+        STATE.with_borrow(|state| state.table.iter().map(|(k, v)| (k.clone(), *v)).collect());
+    report.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then_with(|| a.0.cmp(&b.0)));
+    report
+}
+
+/// The time spent in a function called through one path.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CallProfile {
+    pub(crate) name: Box<str>,
+    pub(crate) calls: usize,
+    /// Time spent in the function, including its callees
+    pub(crate) total: Duration,
+    /// Time spent in the function itself
+    pub(crate) self_time: Duration,
+    /// The functions it called, longest first
+    pub(crate) children: Vec<CallProfile>,
+}
+
+/// The call tree recorded so far, longest first. Only calls that have
+/// returned are timed.
+pub(crate) fn call_report() -> Vec<CallProfile> {
+    STATE.with_borrow(|state| state.call_profile(&state.roots))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_profiler_stack() {
+        start();
+        record(8);
+        enter_function(1, "outer");
+        enter_function(2, "inner");
+        record(16);
+        record(16);
+        leave_frames(1);
+        record(4);
+        // a stale entry at the same depth is replaced
+        enter_function(2, "other");
+        record(1);
+        leave_frames(0);
+        assert!(stop());
+        let report = report();
+        let get = |name: Option<&str>| {
+            report.iter().find(|(n, _)| n.as_deref() == name).map(|(_, a)| *a).unwrap()
+        };
+        assert_eq!(report[0].0.as_deref(), Some("inner"));
+        assert_eq!(get(Some("inner")), Allocations { count: 2, bytes: 32 });
+        assert_eq!(get(Some("outer")), Allocations { count: 1, bytes: 4 });
+        assert_eq!(get(Some("other")), Allocations { count: 1, bytes: 1 });
+        assert_eq!(get(None), Allocations { count: 1, bytes: 8 });
+        assert!(!stop());
+    }
+
+    #[test]
+    fn test_call_profiler() {
+        start_calls();
+        enter_function(1, "outer");
+        enter_function(2, "inner");
+        leave_frames(1);
+        enter_function(2, "inner");
+        enter_function(3, "leaf");
+        leave_frames(1);
+        // a stale entry at the same depth is replaced
+        enter_function(2, "other");
+        enter_function(2, "inner");
+        leave_frames(0);
+        enter_function(1, "inner");
+        leave_frames(0);
+        assert!(stop_calls());
+        let report = call_report();
+        let find = |profiles: &[CallProfile], name: &str| {
+            profiles.iter().find(|x| &*x.name == name).cloned().unwrap()
+        };
+        assert_eq!(report.len(), 2);
+        let outer = find(&report, "outer");
+        assert_eq!(outer.calls, 1);
+        assert_eq!(outer.children.len(), 2);
+        let inner = find(&outer.children, "inner");
+        assert_eq!(inner.calls, 3);
+        assert_eq!(find(&inner.children, "leaf").calls, 1);
+        assert_eq!(find(&outer.children, "other").calls, 1);
+        assert_eq!(find(&report, "inner").calls, 1);
+        let callees: Duration = outer.children.iter().map(|x| x.total).sum();
+        assert_eq!(outer.self_time, outer.total - callees);
+        assert!(!stop_calls());
+    }
+}
@@ -84,6 +84,10 @@ where
     }
 }
 
+impl IntoRoot<()> for () {
+    unsafe fn into_root(self) {}
+}
+
 impl<'a> IntoRoot<Slot<Object<'a>>> for bool {
     unsafe fn into_root(self) -> Slot<Object<'a>> {
         Slot::new(self.into())
@@ -596,6 +600,10 @@ impl<T> Rt<Vec<T>> {
         self.inner_mut().swap_remove(index);
     }
 
+    pub(crate) fn remove(&mut self, index: usize) {
+        self.inner_mut().remove(index);
+    }
+
     pub(crate) fn reserve(&mut self, additional: usize) {
         self.inner_mut().reserve(additional);
     }
@@ -703,6 +711,12 @@ where
     pub(crate) fn remove<Q: IntoRoot<K>>(&mut self, k: Q) {
         self.as_mut().swap_remove(unsafe { &k.into_root() });
     }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&Rt<K>, &Rt<V>)> {
+        use std::ptr::from_ref;
+        let inner = unsafe { &*from_ref(self.as_ref()).cast::<IndexMap<Rt<K>, Rt<V>>>() };
+        inner.iter()
+    }
 }
 
 impl<K, V> Trace for ObjectMap<K, V>
@@ -6,14 +6,42 @@ pub(crate) trait Trace {
     fn trace(&self, state: &mut GcState);
 }
 
+/// The number of live objects of each type found by a collection.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) struct ObjectCounts {
+    pub(crate) conses: usize,
+    pub(crate) floats: usize,
+    pub(crate) strings: usize,
+    pub(crate) vectors: usize,
+    pub(crate) records: usize,
+    pub(crate) hash_tables: usize,
+    pub(crate) symbols: usize,
+    pub(crate) byte_fns: usize,
+    pub(crate) buffers: usize,
+}
+
 pub(crate) struct GcState {
     stack: Vec<RawObj>,
     pub(in crate::core) to_space: bumpalo::Bump,
+    pub(in crate::core) counts: ObjectCounts,
+    /// Every live object found, along with the index of the root it was
+    /// first reached from. Only kept when dumping the heap.
+    pub(in crate::core) log: Option<Vec<(usize, RawObj)>>,
+    pub(in crate::core) current_root: usize,
+    /// The ids of the data attached to live records
+    pub(in crate::core) attached: HashSet<super::AttachId>,
 }
 
 impl GcState {
     pub fn new() -> Self {
-        GcState { stack: Vec::new(), to_space: bumpalo::Bump::new() }
+        GcState {
+            stack: Vec::new(),
+            to_space: bumpalo::Bump::new(),
+            counts: ObjectCounts::default(),
+            log: None,
+            current_root: 0,
+            attached: HashSet::default(),
+        }
     }
 
     pub fn push(&mut self, obj: Object) {
@@ -28,6 +56,10 @@ impl GcState {
     }
 }
 
+impl Trace for () {
+    fn trace(&self, _: &mut GcState) {}
+}
+
 impl Trace for usize {
     fn trace(&self, _: &mut GcState) {}
 }
@@ -173,11 +173,10 @@ impl Eq for LispBufferInner {}
 impl Display for LispBufferInner {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let data = self.text_buffer.lock().unwrap();
-        let name = match data.as_ref() {
-            Some(buf) => &buf.name,
-            None => "deleted buffer",
-        };
-        write!(f, "#<{name}>")
+        match data.as_ref() {
+            Some(buf) => write!(f, "#<buffer {}>", buf.name),
+            None => write!(f, "#<killed buffer>"),
+        }
     }
 }
 
@@ -93,6 +93,33 @@ impl<'ob> TryFrom<Object<'ob>> for Option<()> {
     }
 }
 
+/// Keyword arguments, like `:test #'equal`, collected into `T`. When used as
+/// the last argument of a `defun`, all remaining arguments are parsed as
+/// keywords. Derive `FromKeywords` on `T` to define the keywords it accepts.
+pub(crate) struct Keywords<T>(pub(crate) T);
+
+/// Implemented with `#[derive(FromKeywords)]`.
+pub(crate) trait FromKeywords<'ob>: Sized {
+    fn from_keywords(args: &[Object<'ob>]) -> anyhow::Result<Self>;
+}
+
+impl<T> Keywords<T> {
+    pub(crate) fn from_slice<'ob>(args: &[Object<'ob>]) -> anyhow::Result<Self>
+    where
+        T: FromKeywords<'ob>,
+    {
+        T::from_keywords(args).map(Keywords)
+    }
+}
+
+impl<T> std::ops::Deref for Keywords<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
 /// This function is required because we have no specialization yet.
 /// Essentially this let's us convert one type to another "in place"
 /// without the need to allocate a new slice. We ensure that the two
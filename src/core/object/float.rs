@@ -34,14 +34,78 @@ impl<'new> CloneIn<'new, &'new LispFloat> for LispFloat {
 }
 
 impl Display for LispFloat {
+    /// Print the float like Emacs does: the shortest `%g` output that reads
+    /// back as the same float, with `.0` added if it looks like an integer.
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let float = **self;
-        if float.fract() == 0.0_f64 {
-            write!(f, "{float:.1}")
+        let sign = if float.is_sign_negative() { "-" } else { "" };
+        if float.is_nan() {
+            return write!(f, "{sign}0.0e+NaN");
+        }
+        if float.is_infinite() {
+            return write!(f, "{sign}1.0e+INF");
+        }
+        let mut print = (15..17)
+            .map(|precision| format_general(float, precision, false))
+            .find(|x| x.parse() == Ok(float))
+            .unwrap_or_else(|| format_general(float, 17, false));
+        if !print.contains(['.', 'e']) {
+            print.push_str(".0");
+        }
+        f.write_str(&print)
+    }
+}
+
+/// Format `float` like `%.Ne` in C, with `precision` digits after the point
+/// and at least two digits in the exponent.
+pub(crate) fn format_exponent(float: f64, precision: usize) -> String {
+    let print = format!("{float:.precision$e}");
+    let Some((mantissa, exponent)) = print.split_once('e') else { return print };
+    let exponent: i32 = exponent.parse().unwrap();
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{mantissa}e{sign}{:02}", exponent.abs())
+}
+
+/// Format `float` like `%.Ng` in C, with `precision` significant digits.
+/// Trailing zeros are removed unless `alternate` is true.
+pub(crate) fn format_general(float: f64, precision: usize, alternate: bool) -> String {
+    let precision = precision.max(1);
+    let trim = |print: &str| {
+        if alternate || !print.contains('.') {
+            print.to_owned()
         } else {
-            write!(f, "{float}")
+            print.trim_end_matches('0').trim_end_matches('.').to_owned()
         }
+    };
+    let exponent_form = format_exponent(float, precision - 1);
+    // The exponent after rounding to `precision` digits
+    let exponent = match exponent_form.split_once('e') {
+        Some((_, exponent)) if float != 0.0 => exponent.parse().unwrap_or(0),
+        _ => 0,
+    };
+    if exponent < -4 || exponent >= precision as i32 {
+        let (mantissa, exponent) = exponent_form.split_once('e').unwrap();
+        format!("{}e{exponent}", trim(mantissa))
+    } else {
+        let decimals = (precision as i32 - 1 - exponent) as usize;
+        trim(&format!("{float:.decimals$}"))
+    }
+}
+
+/// Parse a float in lisp syntax, like `1.5`, `-2e3` or `1.0e+INF`.
+pub(crate) fn parse_float(text: &str) -> Option<f64> {
+    if let Some(mantissa) = text.strip_suffix("e+INF") {
+        return Some(f64::INFINITY.copysign(parse_float(mantissa)?));
+    }
+    if let Some(mantissa) = text.strip_suffix("e+NaN") {
+        return Some(f64::NAN.copysign(parse_float(mantissa)?));
     }
+    // Rust also parses words like `inf` and `nan`, which are symbols in lisp
+    let numeric = |b: u8| b.is_ascii_digit() || matches!(b, b'+' | b'-' | b'.' | b'e' | b'E');
+    if !text.bytes().all(numeric) {
+        return None;
+    }
+    text.parse().ok()
 }
 
 impl Debug for LispFloat {
@@ -49,3 +113,51 @@ impl Debug for LispFloat {
         write!(f, "{self}")
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn print(float: f64) -> String {
+        LispFloat::new(float, false).to_string()
+    }
+
+    #[test]
+    fn test_print() {
+        assert_eq!(print(1.0), "1.0");
+        assert_eq!(print(-0.0), "-0.0");
+        assert_eq!(print(0.1), "0.1");
+        assert_eq!(print(1.0 / 3.0), "0.3333333333333333");
+        assert_eq!(print(100.0), "100.0");
+        assert_eq!(print(1e14), "100000000000000.0");
+        assert_eq!(print(1e15), "1e+15");
+        assert_eq!(print(1.5e-7), "1.5e-07");
+        assert_eq!(print(f64::INFINITY), "1.0e+INF");
+        assert_eq!(print(f64::NEG_INFINITY), "-1.0e+INF");
+        assert_eq!(print(f64::NAN), "0.0e+NaN");
+        for float in [0.1 + 0.2, 1e300, 5e-324, 123_456.789, f64::MAX] {
+            assert_eq!(parse_float(&print(float)), Some(float));
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format_exponent(1234.5, 2), "1.23e+03");
+        assert_eq!(format_exponent(0.0, 0), "0e+00");
+        assert_eq!(format_general(1234.5, 6, false), "1234.5");
+        assert_eq!(format_general(0.0001, 6, false), "0.0001");
+        assert_eq!(format_general(0.00001, 6, false), "1e-05");
+        assert_eq!(format_general(999_999.5, 6, false), "1e+06");
+        assert_eq!(format_general(1.5, 3, true), "1.50");
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_float("-2e3"), Some(-2000.0));
+        assert_eq!(parse_float("-1.0e+INF"), Some(f64::NEG_INFINITY));
+        assert!(parse_float("0.0e+NaN").unwrap().is_nan());
+        assert_eq!(parse_float("inf"), None);
+        assert_eq!(parse_float("nan"), None);
+        assert_eq!(parse_float("e+INF"), None);
+    }
+}
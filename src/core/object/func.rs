@@ -5,7 +5,7 @@ use super::{
     },
     display_slice, CloneIn, IntoObject, LispVec, ObjCell,
 };
-use super::{Object, WithLifetime};
+use super::{Object, WithLifetime, NIL};
 use crate::{
     core::{
         env::Env,
@@ -29,6 +29,11 @@ pub(crate) struct ByteFnPrototype {
     pub(super) op_codes: Box<[u8]>,
     // TODO: remove a level of pointer indirection here.
     pub(super) constants: Slot<&'static LispVec>,
+    /// The docstring given when the function was defined
+    #[no_trace]
+    pub(crate) doc: Option<Box<str>>,
+    /// The argument to `interactive` if this function is a command
+    pub(super) interactive: Option<Slot<Object<'static>>>,
 }
 
 macro_attr! {
@@ -71,6 +76,8 @@ impl ByteFn {
             op_codes,
             args,
             depth,
+            doc: None,
+            interactive: None,
         }
     }
 }
@@ -80,30 +87,63 @@ impl ByteFnPrototype {
         &self.op_codes
     }
 
+    pub(crate) fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+
+    pub(crate) fn interactive<'ob>(&'ob self) -> Option<Object<'ob>> {
+        self.interactive.as_ref().map(|spec| unsafe { (**spec).with_lifetime() })
+    }
+
+    // SAFETY: The same as for the constants in `ByteFn::make`, the spec must be
+    // part of the same block as the function.
+    pub(crate) unsafe fn set_interactive(&mut self, spec: Object) {
+        self.interactive = Some(unsafe { Slot::new(spec.with_lifetime()) });
+    }
+
     pub(crate) fn consts<'ob>(&'ob self) -> &'ob [Object<'ob>] {
         unsafe { std::mem::transmute::<&'ob [ObjCell], &'ob [Object<'ob>]>(&self.constants) }
     }
 
+    /// The slots in the order of `make-byte-code`: the arg spec, code,
+    /// constants, depth, docstring and interactive spec.
     pub(crate) fn index<'ob>(&self, index: usize, cx: &'ob Context) -> Option<Object<'ob>> {
+        if index >= self.len() {
+            return None;
+        }
         match index {
             0 => Some((self.args.into_arg_spec() as i64).into()),
             1 => Some(cx.add(self.codes().to_vec())),
             2 => Some(cx.add(self.consts())),
             3 => Some(self.depth.into()),
+            4 => Some(self.doc().map_or(NIL, |doc| cx.add(doc))),
+            5 => self.interactive.as_ref().map(|spec| unsafe { (**spec).with_lifetime() }),
             _ => None,
         }
     }
 
+    /// The number of slots. Like in Emacs, the docstring and interactive
+    /// spec only have a slot if they are needed.
     pub(crate) const fn len(&self) -> usize {
-        4
+        if self.interactive.is_some() {
+            6
+        } else if self.doc.is_some() {
+            5
+        } else {
+            4
+        }
     }
 }
 
 impl<'new> CloneIn<'new, &'new Self> for ByteFn {
     fn clone_in<const C: bool>(&self, bk: &'new Block<C>) -> super::Gc<&'new Self> {
         let constants = self.constants.clone_in(bk);
-        let byte_fn =
+        let mut byte_fn =
             unsafe { ByteFn::make(&self.op_codes, constants.untag(), self.args, self.depth) };
+        byte_fn.doc.clone_from(&self.doc);
+        if let Some(spec) = self.interactive() {
+            unsafe { byte_fn.set_interactive(spec.clone_in(bk)) };
+        }
         byte_fn.into_obj(bk)
     }
 }
@@ -186,11 +226,40 @@ impl FnArgs {
 pub(crate) type BuiltInFn =
     for<'ob> fn(usize, &mut Rt<Env>, &'ob mut Context) -> Result<Object<'ob>>;
 
-#[derive(Eq)]
+/// A monomorphic version of a builtin that works on fixnums. When every
+/// argument is a fixnum, the VM calls it directly instead of converting the
+/// arguments through the generic [`BuiltInFn`]. It is only used when called
+/// with exactly as many arguments as it takes. `None`, or a result outside the
+/// fixnum range, means the generic function has to be called instead, so it
+/// can signal an error or return a float.
+#[derive(Copy, Clone)]
+pub(crate) enum FastFn {
+    Unary(fn(i64) -> Option<i64>),
+    Binary(fn(i64, i64) -> Option<i64>),
+    /// A predicate that returns `t` or `nil`
+    Compare(fn(&i64, &i64) -> bool),
+}
+
+impl FastFn {
+    /// Number of arguments this function takes.
+    pub(crate) fn arg_cnt(self) -> usize {
+        match self {
+            FastFn::Unary(_) => 1,
+            FastFn::Binary(_) | FastFn::Compare(_) => 2,
+        }
+    }
+}
+
 pub(crate) struct SubrFn {
     pub(crate) subr: BuiltInFn,
     pub(crate) args: FnArgs,
     pub(crate) name: &'static str,
+    /// A fast path for calls with fixnum arguments, set with
+    /// `#[defun(fast = ...)]`
+    pub(crate) fast: Option<FastFn>,
+    /// Whether it reads or writes files, or reaches outside of the
+    /// interpreter, set with `#[defun(io)]`. A sandbox doesn't allow these.
+    pub(crate) io: bool,
 }
 define_unbox!(SubrFn, Func, &'ob SubrFn);
 
@@ -234,6 +303,8 @@ impl PartialEq for SubrFn {
     }
 }
 
+impl Eq for SubrFn {}
+
 #[cfg(test)]
 mod test {
     use super::*;
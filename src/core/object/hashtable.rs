@@ -99,12 +99,11 @@ impl<'a> HashTableCore<'a> {
                 table.borrow_mut().inner.insert(key, value)
             }
             HashTableType::Global(table) => {
-                let map = interned_symbols().lock().unwrap();
-                let block = map.global_block();
+                let block = interned_symbols().global_block();
                 // Need to clone these objects in the global block since this
                 // hashtable is globally shared
-                let key = unsafe { key.clone_in(block).with_lifetime() };
-                let value = unsafe { value.clone_in(block).with_lifetime() };
+                let key = unsafe { key.clone_in(&*block).with_lifetime() };
+                let value = unsafe { value.clone_in(&*block).with_lifetime() };
                 table.lock().unwrap().inner.insert(key, value)
             }
         };
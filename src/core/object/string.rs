@@ -7,6 +7,12 @@ use std::ops::Deref;
 use std::ptr::NonNull;
 
 pub(crate) type GcString<'a> = bumpalo::collections::String<'a>;
+
+/// A lisp string. The text is allocated in the same arena as the object, so
+/// creating a short string is only a pointer bump and there is nothing to free
+/// individually. Strings are mutable, so equal strings are never shared,
+/// though with the `intern-literals` feature they can share their text. See
+/// `LiteralStr`.
 pub(crate) struct LispString(GcHeap<LispStringInner>);
 
 // This type needs to be this complex due to to string mutation.
@@ -16,7 +22,19 @@ pub(crate) struct LispString(GcHeap<LispStringInner>);
 //
 // Case 2: The new char is a different size:
 // Need to allocate a new string and update the cell to point to that.
-struct LispStringInner(Cell<*mut str>);
+struct LispStringInner {
+    text: Cell<*mut str>,
+    /// The last (character, byte) position pair that was looked up. Strings
+    /// are indexed by character, and most code walks through a string in
+    /// order, so counting from here makes the next lookup cheap. It has to be
+    /// reset if a character changes size.
+    position: Cell<(usize, usize)>,
+    /// The text is interned literal text shared with other strings. It is
+    /// not part of the heap, so it is not copied when the string is moved,
+    /// and it has to be copied before it is changed.
+    #[cfg(feature = "intern-literals")]
+    shared: bool,
+}
 
 impl Markable for LispString {
     type Value = std::ptr::NonNull<LispString>;
@@ -26,13 +44,7 @@ impl Markable for LispString {
             AllocState::Forwarded(f) => Some((f.cast::<Self>(), false)),
             AllocState::Global => None,
             AllocState::Unmoved => {
-                let ptr = {
-                    let mut new = GcString::from_str_in(self, to_space);
-                    let lisp_str = unsafe { LispString::new(new.as_mut_str(), false) };
-                    std::mem::forget(new);
-                    let alloc = to_space.alloc(lisp_str);
-                    NonNull::from(alloc)
-                };
+                let ptr = NonNull::from(self.copy_to(to_space));
                 self.0.forward(ptr.cast::<u8>());
                 Some((ptr, true))
             }
@@ -80,26 +92,120 @@ impl Deref for LispString {
 
 impl LispString {
     pub(in crate::core) unsafe fn new(string: *mut str, constant: bool) -> Self {
-        Self(GcHeap::new(LispStringInner(Cell::new(string)), constant))
+        let inner = LispStringInner {
+            text: Cell::new(string),
+            position: Cell::new((0, 0)),
+            #[cfg(feature = "intern-literals")]
+            shared: false,
+        };
+        Self(GcHeap::new(inner, constant))
+    }
+
+    /// A string whose text is the interned `text`.
+    #[cfg(feature = "intern-literals")]
+    pub(in crate::core) fn new_shared(text: &'static str, constant: bool) -> Self {
+        let inner = LispStringInner {
+            text: Cell::new((text as *const str).cast_mut()),
+            position: Cell::new((0, 0)),
+            shared: true,
+        };
+        Self(GcHeap::new(inner, constant))
+    }
+
+    /// Allocate a copy of this string in `objects`. The text is copied too,
+    /// unless it is shared.
+    fn copy_to<'a>(&self, objects: &'a bumpalo::Bump) -> &'a mut LispString {
+        #[cfg(feature = "intern-literals")]
+        if self.0.shared {
+            let text = unsafe { &*self.0.text.get() };
+            return objects.alloc(LispString::new_shared(text, false));
+        }
+        let mut new = GcString::from_str_in(self, objects);
+        let lisp_str = unsafe { LispString::new(new.as_mut_str(), false) };
+        std::mem::forget(new);
+        objects.alloc(lisp_str)
     }
 
     pub(crate) fn inner(&self) -> &str {
-        unsafe { &*self.0 .0.get() }
+        unsafe { &*self.0.text.get() }
     }
 }
 
 impl LispString {
+    /// The length in characters.
     pub(crate) fn len(&self) -> usize {
         self.chars().count()
     }
+
+    /// The byte position of the character at `index`, or `None` if the string
+    /// is shorter. The length gives the byte position of the end.
+    pub(crate) fn char_to_byte(&self, index: usize) -> Option<usize> {
+        let text = self.inner();
+        let (char_pos, byte_pos) = self.0.position.get();
+        let byte = if index >= char_pos {
+            let mut starts = text[byte_pos..].char_indices().map(|(i, _)| byte_pos + i);
+            starts.chain(std::iter::once(text.len())).nth(index - char_pos)?
+        } else if index >= char_pos / 2 {
+            text[..byte_pos].char_indices().rev().nth(char_pos - index - 1)?.0
+        } else {
+            text.char_indices().nth(index)?.0
+        };
+        self.0.position.set((index, byte));
+        Some(byte)
+    }
+
+    /// The character at `index`.
+    pub(crate) fn char_at(&self, index: usize) -> Option<char> {
+        let byte = self.char_to_byte(index)?;
+        self.inner()[byte..].chars().next()
+    }
 }
 
 impl<'new> CloneIn<'new, &'new Self> for LispString {
     fn clone_in<const C: bool>(&self, bk: &'new Block<C>) -> super::Gc<&'new Self> {
+        #[cfg(feature = "intern-literals")]
+        if self.0.shared {
+            return LiteralStr(unsafe { &*self.0.text.get() }).into_obj(bk);
+        }
         GcString::from_str_in(self.inner(), &bk.objects).into_obj(bk)
     }
 }
 
+/// String literals longer than this are not interned, so that the table only
+/// grows with short text like names and keys.
+#[cfg(feature = "intern-literals")]
+const MAX_LITERAL_LEN: usize = 64;
+
+/// The text of a string literal, interned with the `intern-literals` feature.
+/// Each distinct text is stored once for the life of the program, like the
+/// names of symbols, and every string made from it points to it. The strings
+/// are still separate objects, so `eq` is unchanged, but each one is only a
+/// header in the heap, and the collector never copies the text.
+#[cfg(feature = "intern-literals")]
+pub(crate) struct LiteralStr(pub(in crate::core) &'static str);
+
+#[cfg(feature = "intern-literals")]
+impl LiteralStr {
+    /// The interned text equal to `text`, or `None` if it is too long to be
+    /// interned.
+    pub(crate) fn intern(text: &str) -> Option<Self> {
+        use rune_core::hashmap::HashSet;
+        use std::sync::{Mutex, OnceLock};
+        static LITERALS: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+
+        if text.len() > MAX_LITERAL_LEN {
+            return None;
+        }
+        let mut literals = LITERALS.get_or_init(Mutex::default).lock().unwrap();
+        if let Some(text) = literals.get(text) {
+            return Some(Self(text));
+        }
+        let text: &'static str = Box::leak(text.into());
+        literals.insert(text);
+        Some(Self(text))
+    }
+}
+
 impl AsRef<str> for LispString {
     fn as_ref(&self) -> &str {
         self
@@ -204,6 +310,7 @@ impl Debug for ByteString {
 mod test {
     use super::*;
     use crate::core::gc::{Context, RootSet};
+    use crate::core::object::Gc;
     use rune_core::macros::root;
 
     #[test]
@@ -221,6 +328,22 @@ mod test {
         assert_eq!(s1, s2);
     }
 
+    #[test]
+    fn test_char_to_byte() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let string: Gc<&LispString> = cx.add_as("aé中b");
+        let string = string.untag();
+        assert_eq!(string.char_to_byte(3), Some(6));
+        assert_eq!(string.char_to_byte(1), Some(1));
+        assert_eq!(string.char_to_byte(2), Some(3));
+        assert_eq!(string.char_to_byte(0), Some(0));
+        assert_eq!(string.char_to_byte(4), Some(7));
+        assert_eq!(string.char_to_byte(5), None);
+        let chars: Vec<_> = (0..5).map(|i| string.char_at(i)).collect();
+        assert_eq!(chars, [Some('a'), Some('é'), Some('中'), Some('b'), None]);
+    }
+
     #[test]
     fn test_byte_string_aliasing() {
         let roots = &RootSet::default();
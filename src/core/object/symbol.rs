@@ -8,7 +8,7 @@ use std::cell::Cell;
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
-use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
 
 mod sealed {
     use super::{AtomicBool, AtomicPtr, SymbolName};
@@ -72,6 +72,12 @@ impl<'a> Symbol<'a> {
         }
     }
 
+    /// Whether this is one of the [`BUILTIN_SYMBOLS`]. Those are statically
+    /// allocated, so the garbage collector never needs to look at them.
+    pub(crate) fn is_builtin(self) -> bool {
+        self.data.addr() < std::mem::size_of_val(&BUILTIN_SYMBOLS)
+    }
+
     pub(in crate::core) fn as_ptr(self) -> *const u8 {
         self.data.cast()
     }
@@ -101,6 +107,8 @@ impl<'a> Symbol<'a> {
 }
 
 unsafe impl Send for Symbol<'_> {}
+// Symbol is Copy, so sharing a reference is no different than sending it
+unsafe impl Sync for Symbol<'_> {}
 
 // implement withlifetime for symbol
 impl<'old, 'new> WithLifetime<'new> for Symbol<'old> {
@@ -113,7 +121,9 @@ impl<'old, 'new> WithLifetime<'new> for Symbol<'old> {
 
 impl Trace for Symbol<'_> {
     fn trace(&self, state: &mut GcState) {
-        self.get().trace(state);
+        if !self.is_builtin() {
+            self.get().trace(state);
+        }
     }
 }
 
@@ -121,6 +131,9 @@ impl<'a> Markable for Symbol<'a> {
     type Value = Symbol<'a>;
 
     fn move_value(&self, to_space: &bumpalo::Bump) -> Option<(Self::Value, bool)> {
+        if self.is_builtin() {
+            return None;
+        }
         let val = self.get().move_value(to_space);
         val.map(|(ptr, moved)| (unsafe { Self::from_ptr(ptr.as_ptr()) }, moved))
     }
@@ -287,6 +300,15 @@ impl SymbolCellInner {
         }
     }
 
+    /// The name of the symbol if it is interned. Interned names are never
+    /// freed, so this can be held without copying it.
+    pub(crate) fn interned_name(&self) -> Option<&'static str> {
+        match &self.name {
+            SymbolName::Interned(x) => Some(x),
+            SymbolName::Uninterned(_) => None,
+        }
+    }
+
     pub(crate) fn interned(&self) -> bool {
         matches!(self.name, SymbolName::Interned(_))
     }
@@ -339,16 +361,28 @@ impl SymbolCellInner {
         };
         let val = func.into_ptr().cast_mut();
         fn_cell.store(val, Ordering::Release);
+        FUNCTION_EPOCH.fetch_add(1, Ordering::Release);
         Ok(())
     }
 
     pub(crate) fn unbind_func(&self) {
         if let Some(func) = &self.func {
             func.store(Self::NULL, Ordering::Release);
+            FUNCTION_EPOCH.fetch_add(1, Ordering::Release);
         }
     }
 }
 
+/// Incremented whenever the function of a symbol changes.
+static FUNCTION_EPOCH: AtomicUsize = AtomicUsize::new(0);
+
+/// A counter that changes whenever any symbol's function is set or unbound.
+/// Anything derived from looking up functions is valid as long as this stays
+/// the same.
+pub(crate) fn function_epoch() -> usize {
+    FUNCTION_EPOCH.load(Ordering::Acquire)
+}
+
 impl fmt::Display for SymbolCellInner {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.name())
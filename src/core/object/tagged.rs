@@ -22,7 +22,7 @@ use sptr::Strict;
 use std::marker::PhantomData;
 use std::{fmt, ptr::NonNull};
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub(crate) struct RawObj {
     ptr: *const u8,
 }
@@ -54,6 +54,12 @@ pub(crate) const TRUE: Object<'static> =
 /// something that is managed by the GC. It is intended to be pointer sized, and
 /// have a lifetime tied to the context which manages garbage collections. A Gc
 /// can be reinterpreted as any type that shares the same tag.
+///
+/// The value is a single word. The low 8 bits are the `Tag` and the rest is
+/// either a pointer shifted left by 8 or, for fixnums, the integer itself.
+/// Floats are boxed. Storing them as immediates with NaN-boxing would leave
+/// only 48 bits for pointers and would make every untag check for a float
+/// first, so it would need to show a win on the VM benchmarks first.
 #[derive(Copy, Clone)]
 pub(crate) struct Gc<T> {
     ptr: *const u8,
@@ -173,6 +179,12 @@ impl<'a, T: 'a + Copy> From<&Gc<T>> for ObjectType<'a> {
     }
 }
 
+impl<'a> From<ObjectType<'a>> for Object<'a> {
+    fn from(x: ObjectType<'a>) -> Self {
+        x.tag()
+    }
+}
+
 ////////////////////////
 // Traits for Objects //
 ////////////////////////
@@ -421,6 +433,16 @@ impl IntoObject for &str {
     }
 }
 
+#[cfg(feature = "intern-literals")]
+impl IntoObject for super::LiteralStr {
+    type Out<'ob> = <String as IntoObject>::Out<'ob>;
+
+    fn into_obj<const C: bool>(self, block: &Block<C>) -> Gc<Self::Out<'_>> {
+        let ptr = block.objects.alloc(LispString::new_shared(self.0, C));
+        unsafe { Self::Out::tag_ptr(ptr) }
+    }
+}
+
 impl IntoObject for Vec<u8> {
     type Out<'ob> = &'ob ByteString;
 
@@ -725,8 +747,8 @@ impl<'a> TaggedPtr for NumberType<'a> {
     }
 }
 
-const MAX_FIXNUM: i64 = i64::MAX >> 8;
-const MIN_FIXNUM: i64 = i64::MIN >> 8;
+pub(crate) const MAX_FIXNUM: i64 = i64::MAX >> 8;
+pub(crate) const MIN_FIXNUM: i64 = i64::MIN >> 8;
 
 impl TaggedPtr for i64 {
     type Ptr = i64;
@@ -1333,18 +1355,57 @@ where
 
 impl<T> Trace for Gc<T> {
     fn trace(&self, state: &mut GcState) {
+        // Objects are only traced the first time they are moved, so this counts
+        // each live object once
+        if let Some(log) = &mut state.log {
+            log.push((state.current_root, Gc::into_raw(self.as_obj())));
+        }
+        let counts = &mut state.counts;
         match self.as_obj().untag() {
             ObjectType::Int(_) | ObjectType::SubrFn(_) => {}
-            ObjectType::Float(x) => x.trace(state),
-            ObjectType::String(x) => x.trace(state),
-            ObjectType::ByteString(x) => x.trace(state),
-            ObjectType::Vec(vec) => vec.trace(state),
-            ObjectType::Record(x) => x.trace(state),
-            ObjectType::HashTable(x) => x.trace(state),
-            ObjectType::Cons(x) => x.trace(state),
-            ObjectType::Symbol(x) => x.trace(state),
-            ObjectType::ByteFn(x) => x.trace(state),
-            ObjectType::Buffer(x) => x.trace(state),
+            ObjectType::Float(x) => {
+                counts.floats += 1;
+                x.trace(state);
+            }
+            ObjectType::String(x) => {
+                counts.strings += 1;
+                x.trace(state);
+            }
+            ObjectType::ByteString(x) => {
+                counts.strings += 1;
+                x.trace(state);
+            }
+            ObjectType::Vec(vec) => {
+                counts.vectors += 1;
+                vec.trace(state);
+            }
+            ObjectType::Record(x) => {
+                counts.records += 1;
+                if let Some(id) = x.attached() {
+                    state.attached.insert(id);
+                }
+                x.trace(state);
+            }
+            ObjectType::HashTable(x) => {
+                counts.hash_tables += 1;
+                x.trace(state);
+            }
+            ObjectType::Cons(x) => {
+                counts.conses += 1;
+                x.trace(state);
+            }
+            ObjectType::Symbol(x) => {
+                counts.symbols += 1;
+                x.trace(state);
+            }
+            ObjectType::ByteFn(x) => {
+                counts.byte_fns += 1;
+                x.trace(state);
+            }
+            ObjectType::Buffer(x) => {
+                counts.buffers += 1;
+                x.trace(state);
+            }
         }
     }
 }
@@ -1543,7 +1604,7 @@ impl ObjectType<'_> {
             ObjectType::Vec(x) => x.display_walk(f, seen),
             ObjectType::Record(x) => x.display_walk(f, seen),
             ObjectType::HashTable(x) => x.display_walk(f, seen),
-            ObjectType::String(x) => write!(f, "\"{x}\""),
+            ObjectType::String(x) => write_quoted(f, x),
             ObjectType::ByteString(x) => write!(f, "\"{x}\""),
             ObjectType::Symbol(x) => D::fmt(x, f),
             ObjectType::ByteFn(x) => D::fmt(x, f),
@@ -1554,6 +1615,19 @@ impl ObjectType<'_> {
     }
 }
 
+/// Write `string` in quotes, escaping the characters that would otherwise end
+/// it, so that it reads back as the same string.
+fn write_quoted(f: &mut fmt::Formatter, string: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for chr in string.chars() {
+        if matches!(chr, '"' | '\\') {
+            f.write_str("\\")?;
+        }
+        write!(f, "{chr}")?;
+    }
+    f.write_str("\"")
+}
+
 impl<'ob> ListType<'ob> {
     #[cfg(test)]
     pub(crate) fn car(self) -> Object<'ob> {
@@ -1580,6 +1654,19 @@ mod test {
         assert_eq!(MIN_FIXNUM.tag().untag(), MIN_FIXNUM);
     }
 
+    #[test]
+    fn test_word_layout() {
+        use super::{Object, NIL, TRUE};
+        use crate::core::env::sym;
+        assert_eq!(std::mem::size_of::<Object>(), std::mem::size_of::<u64>());
+        assert_eq!(NIL, Object::from(sym::NIL));
+        assert_eq!(TRUE, Object::from(sym::TRUE));
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let x = cx.add(MAX_FIXNUM);
+        assert!(matches!(x.untag(), super::ObjectType::Int(MAX_FIXNUM)));
+    }
+
     #[test]
     fn test_print_circle() {
         let roots = &RootSet::default();
@@ -1,6 +1,6 @@
 use super::{CloneIn, Gc, IntoObject, MutObjCell, ObjCell, Object};
 use crate::{
-    core::gc::{Block, GcHeap, GcState, Trace},
+    core::gc::{pin_attached, AttachId, Block, GcHeap, GcState, Trace},
     NewtypeMarkable,
 };
 use anyhow::{anyhow, Result};
@@ -13,12 +13,14 @@ use std::{
     cell::Cell,
     fmt::{self, Write},
     ops::Deref,
-    ptr::addr_of,
+    ptr::{addr_of, NonNull},
 };
 
 #[derive(Eq)]
 pub(crate) struct LispVecInner {
     is_const: bool,
+    /// Only used by records, see [`attach`](crate::core::gc::attach)
+    attached: Cell<Option<AttachId>>,
     inner: Cell<*const [ObjCell]>,
 }
 
@@ -115,7 +117,7 @@ impl fmt::Debug for LispVecInner {
 impl LispVecInner {
     unsafe fn new(ptr: *const [Object], is_const: bool) -> Self {
         let ptr = ptr as *mut [ObjCell];
-        Self { is_const, inner: Cell::new(ptr) }
+        Self { is_const, attached: Cell::new(None), inner: Cell::new(ptr) }
     }
 
     pub(super) fn display_walk(
@@ -148,11 +150,29 @@ macro_attr! {
     pub(crate) struct Record(GcHeap<LispVecInner>);
 }
 
+impl Record {
+    /// The id of the Rust data attached to this record, if any.
+    pub(crate) fn attached(&self) -> Option<AttachId> {
+        self.attached.get()
+    }
+
+    pub(crate) fn set_attached(&self, id: Option<AttachId>) {
+        self.attached.set(id);
+    }
+}
+
 impl<'new> CloneIn<'new, &'new Self> for Record {
     fn clone_in<const C: bool>(&self, bk: &'new Block<C>) -> Gc<&'new Self> {
         let mut vec = GcVec::with_capacity_in(self.len(), &bk.objects);
         vec.extend(self.iter().map(|x| x.get().clone_in(bk)));
-        RecordBuilder(vec).into_obj(bk)
+        let record = RecordBuilder(vec).into_obj(bk);
+        if let Some(id) = self.attached() {
+            record.untag().set_attached(Some(id));
+            if C {
+                pin_attached(id);
+            }
+        }
+        record
     }
 }
 
@@ -163,11 +183,23 @@ impl fmt::Display for Record {
 }
 
 impl Record {
+    pub(in crate::core) fn forwarding_ptr(&self) -> Option<NonNull<u8>> {
+        use crate::core::gc::AllocState as A;
+        match self.0.allocation_state() {
+            A::Forwarded(f) => Some(f),
+            A::Global => panic!("global record allocation found in local heap"),
+            A::Unmoved => None,
+        }
+    }
+
     fn display_walk(&self, f: &mut fmt::Formatter, seen: &mut HashSet<*const u8>) -> fmt::Result {
         let ptr = (self as *const Self).cast();
         if seen.contains(&ptr) {
             return write!(f, "#0");
         }
+        if let Some(form) = crate::print::opaque_record(self) {
+            return f.write_str(&form);
+        }
         seen.insert(ptr);
         write!(f, "#s(")?;
         for (i, x) in self.iter().enumerate() {
@@ -0,0 +1,241 @@
+//! Coverage of interpreted lisp code.
+//!
+//! `coverage-load` loads a file like `load`, but first registers every form
+//! read from it as a coverage point, keyed by its position in the file. Each
+//! time the interpreter evaluates a registered form it counts a hit. Forms are
+//! evaluated without eager macro expansion, because expanding rebuilds every
+//! form that contains a macro call and the point would be lost.
+//!
+//! Points are found by the identity of the conses that were read, and conses
+//! move during garbage collection. So the conses are kept in a lisp hash table
+//! in `internal--coverage-forms`, whose keys are rehashed by the collector.
+use crate::core::{
+    env::{intern, sym, Env},
+    gc::{Context, Rt, Rto},
+    object::{Gc, HashTable, IntoObject, LispHashTable, LispString, Object, ObjectType, NIL},
+};
+use crate::{interpreter, reader};
+use anyhow::{bail, Context as _, Result};
+use rune_core::macros::{list, root};
+use rune_macros::defun;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+defvar!(INTERNAL__COVERAGE_FORMS);
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// A form registered for coverage.
+struct Point {
+    file: Arc<str>,
+    /// Lines start at 1 and columns at 0, like Emacs numbers them
+    line: usize,
+    column: usize,
+    /// The function the form calls. Lists like `let` bindings also start with
+    /// a symbol, so points that were never hit are only reported if this is a
+    /// function.
+    head: Box<str>,
+    hits: usize,
+}
+
+static POINTS: Mutex<Vec<Point>> = Mutex::new(Vec::new());
+
+#[inline]
+pub(crate) fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Count a hit if `form` is a coverage point.
+pub(crate) fn record(form: Object, env: &Rt<Env>, cx: &Context) {
+    let Some(table) = env.vars.get(sym::INTERNAL__COVERAGE_FORMS) else { return };
+    let ObjectType::HashTable(table) = table.bind(cx).untag() else { return };
+    if let Some(ObjectType::Int(i)) = table.get(form).map(Object::untag) {
+        if let Some(point) = POINTS.lock().unwrap().get_mut(i as usize) {
+            point.hits += 1;
+        }
+    }
+}
+
+fn coverage_forms<'ob>(env: &mut Rt<Env>, cx: &'ob Context) -> Result<&'ob LispHashTable> {
+    let table = env.vars.get(sym::INTERNAL__COVERAGE_FORMS).map_or(NIL, |x| x.bind(cx));
+    if let ObjectType::HashTable(table) = table.untag() {
+        return Ok(table);
+    }
+    let table = HashTable::default().into_obj(cx);
+    env.set_var(sym::INTERNAL__COVERAGE_FORMS, table.into())?;
+    Ok(table.untag())
+}
+
+/// The byte offset of the start of each line in `text`.
+fn line_starts(text: &str) -> Vec<usize> {
+    std::iter::once(0).chain(text.match_indices('\n').map(|(i, _)| i + 1)).collect()
+}
+
+fn line_column(text: &str, line_starts: &[usize], offset: usize) -> (usize, usize) {
+    let line = line_starts.partition_point(|&start| start <= offset);
+    let column = text[line_starts[line - 1]..offset].chars().count();
+    (line, column)
+}
+
+/// Load FILE, registering every form in it for coverage. Hits are counted
+/// until `coverage-reset`.
+#[defun(io)]
+fn coverage_load(
+    file: &Rto<Gc<&LispString>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let file: &str = file.untag(cx);
+    let file: Arc<str> = file.into();
+    let contents =
+        std::fs::read_to_string(&*file).with_context(|| format!("Couldn't open file {file}"))?;
+    let lines = line_starts(&contents);
+    ENABLED.store(true, Ordering::Relaxed);
+    let mut pos = 0;
+    loop {
+        let (obj, end, positions) = match reader::read_with_positions(&contents[pos..], cx) {
+            Ok(read) => read,
+            Err(reader::Error::EmptyStream) => return Ok(true),
+            Err(mut e) => {
+                e.update_pos(pos);
+                bail!(e);
+            }
+        };
+        // Nothing can be collected before the forms are in the table
+        let table = coverage_forms(env, cx)?;
+        let mut points = POINTS.lock().unwrap();
+        for (cons, offset) in positions {
+            let (line, column) = line_column(&contents, &lines, pos + offset);
+            let head = match cons.car().untag() {
+                ObjectType::Symbol(head) => head.name().into(),
+                _ => unreachable!("Only lists that start with a symbol are kept"),
+            };
+            table.insert(cons.into(), (points.len() as i64).into());
+            points.push(Point { file: file.clone(), line, column, head, hits: 0 });
+        }
+        drop(points);
+        root!(obj, cx);
+        interpreter::eval(obj, None, env, cx)?;
+        pos += end;
+    }
+}
+
+/// Call `f` with the points to report, sorted by position.
+fn with_reported_points<T>(cx: &Context, f: impl FnOnce(Vec<&Point>) -> T) -> T {
+    let points = POINTS.lock().unwrap();
+    let mut reported: Vec<&Point> = points
+        .iter()
+        .filter(|point| {
+            let head = intern(&point.head, cx);
+            point.hits > 0
+                || head.follow_indirect(cx).is_some()
+                || interpreter::special_form_p(head.into())
+        })
+        .collect();
+    reported.sort_by(|a, b| (&a.file, a.line, a.column).cmp(&(&b.file, b.line, b.column)));
+    f(reported)
+}
+
+/// Return the coverage recorded so far, as a list of `(FILE LINE COLUMN
+/// HITS)` for every form in the files loaded with `coverage-load`.
+#[defun]
+fn coverage_report<'ob>(cx: &'ob Context) -> Object<'ob> {
+    with_reported_points(cx, |points| {
+        let entries: Vec<Object> = points
+            .iter()
+            .map(|point| list![&*point.file, point.line, point.column, point.hits; cx])
+            .collect();
+        crate::fns::slice_into_list(&entries, None, cx)
+    })
+}
+
+/// Write the coverage recorded so far to FILE in the LCOV format, which most
+/// coverage tools read. The count for a line is the most hits of any form
+/// that starts on it.
+#[defun(io)]
+fn coverage_write_lcov(file: &str, cx: &Context) -> Result<bool> {
+    let lcov = with_reported_points(cx, |points| {
+        let mut files: BTreeMap<&str, BTreeMap<usize, usize>> = BTreeMap::new();
+        for point in points {
+            let hits = files.entry(&*point.file).or_default().entry(point.line).or_default();
+            *hits = point.hits.max(*hits);
+        }
+        let mut lcov = String::new();
+        for (file, lines) in files {
+            writeln!(lcov, "SF:{file}").unwrap();
+            for (line, hits) in &lines {
+                writeln!(lcov, "DA:{line},{hits}").unwrap();
+            }
+            let hit = lines.values().filter(|hits| **hits > 0).count();
+            writeln!(lcov, "LH:{hit}\nLF:{}\nend_of_record", lines.len()).unwrap();
+        }
+        lcov
+    });
+    std::fs::write(file, lcov).with_context(|| format!("Couldn't write file {file}"))?;
+    Ok(true)
+}
+
+/// Stop counting hits and discard all coverage data.
+#[defun]
+fn coverage_reset(env: &mut Rt<Env>) -> Result<()> {
+    ENABLED.store(false, Ordering::Relaxed);
+    POINTS.lock().unwrap().clear();
+    env.set_var(sym::INTERNAL__COVERAGE_FORMS, NIL)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+
+    #[test]
+    fn test_line_column() {
+        let text = "(a)\n  (b\n (c))";
+        let lines = line_starts(text);
+        assert_eq!(line_column(text, &lines, 0), (1, 0));
+        assert_eq!(line_column(text, &lines, 6), (2, 2));
+        assert_eq!(line_column(text, &lines, 11), (3, 1));
+    }
+
+    #[test]
+    fn test_coverage() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        let path = std::env::temp_dir().join(format!("rune-coverage-{}.el", std::process::id()));
+        let source = "(defalias 'coverage-test-fn
+  #'(lambda (x)
+      (if x
+          (car x)
+        (cdr x))))
+(coverage-test-fn '(1))
+(coverage-test-fn '(2))
+";
+        std::fs::write(&path, source).unwrap();
+        let file = path.to_string_lossy().to_string();
+        let file: Gc<&LispString> = cx.add_as(file.as_str());
+        root!(file, cx);
+        assert!(coverage_load(file, env, cx).unwrap());
+        let report = coverage_report(cx).to_string();
+        let name = path.to_string_lossy();
+        for (line, column, hits) in [(1, 0, 1), (3, 6, 2), (4, 10, 2), (5, 8, 0), (6, 0, 1)] {
+            let entry = format!("(\"{name}\" {line} {column} {hits})");
+            assert!(report.contains(&entry), "{entry} not in {report}");
+        }
+
+        let lcov_path = path.with_extension("info");
+        coverage_write_lcov(&lcov_path.to_string_lossy(), cx).unwrap();
+        let lcov = std::fs::read_to_string(&lcov_path).unwrap();
+        assert!(lcov.starts_with(&format!("SF:{name}\n")));
+        assert!(lcov.contains("DA:4,2\nDA:5,0\n"));
+        assert!(lcov.ends_with("end_of_record\n"));
+
+        coverage_reset(env).unwrap();
+        assert_eq!(coverage_report(cx), NIL);
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(lcov_path).unwrap();
+    }
+}
@@ -2,12 +2,18 @@
 use crate::core::{
     cons::Cons,
     env::{interned_symbols, sym, Env},
-    error::{Type, TypeError},
-    gc::{Context, Rt},
-    object::{List, ListType, Number, Object, ObjectType, SubrFn, Symbol, WithLifetime, NIL},
+    error::{ConstantError, Type, TypeError},
+    gc::{Context, Rt, Rto, Slot},
+    object::{
+        parse_float, Function, Gc, List, ListType, Number, Object, ObjectType, SubrFn, Symbol,
+        WithLifetime, MAX_FIXNUM, MIN_FIXNUM, NIL,
+    },
 };
-use anyhow::{anyhow, Result};
+use crate::rooted_iter;
+use anyhow::{anyhow, ensure, Result};
+use fallible_streaming_iterator::FallibleStreamingIterator;
 use rune_core::hashmap::HashSet;
+use rune_core::macros::{call, root};
 use rune_macros::defun;
 use std::sync::Mutex;
 use std::sync::OnceLock;
@@ -16,7 +22,7 @@ static FEATURES: OnceLock<Mutex<HashSet<Symbol<'static>>>> = OnceLock::new();
 
 /// Rust translation of the `features` variable: A list of symbols are the features
 /// of the executing Emacs. Used by [`featurep`](`crate::fns::featurep`) and [`require`](`crate::fns::require`),
-/// altered by [`provide`]. Vended through a helper function to avoid calling `get_or_init` on each of the calls
+/// altered by [`provide`] and by setting the variable from lisp. Vended through a helper function to avoid calling `get_or_init` on each of the calls
 /// to `lock()` on the Mutex.
 ///
 /// TODO: Use `LazyLock`: <https://github.com/CeleritasCelery/rune/issues/34>
@@ -30,8 +36,7 @@ pub(crate) fn fset<'ob>(symbol: Symbol<'ob>, definition: Object) -> Result<Symbo
         symbol.unbind_func();
     } else {
         let func = definition.try_into()?;
-        let map = interned_symbols().lock().unwrap();
-        map.set_func(symbol, func)?;
+        interned_symbols().set_func(symbol, func)?;
     }
     Ok(symbol)
 }
@@ -40,19 +45,26 @@ pub(crate) fn fset<'ob>(symbol: Symbol<'ob>, definition: Object) -> Result<Symbo
 pub(crate) fn defalias<'ob>(
     symbol: Symbol<'ob>,
     definition: Object,
-    _docstring: Option<&str>,
+    docstring: Option<Object>,
+    env: &mut Rt<Env>,
 ) -> Result<Symbol<'ob>> {
+    if let Some(doc) = docstring.filter(|x| *x != NIL) {
+        env.set_prop(symbol, sym::FUNCTION_DOCUMENTATION, doc);
+    }
     fset(symbol, definition)
 }
 
 #[defun]
 pub(crate) fn set<'ob>(
-    place: Symbol,
-    newlet: Object<'ob>,
+    place: &Rto<Gc<Symbol>>,
+    newlet: &Rto<Object>,
     env: &mut Rt<Env>,
+    cx: &'ob mut Context,
 ) -> Result<Object<'ob>> {
-    env.set_var(place, newlet)?;
-    Ok(newlet)
+    let place = place.untag(cx);
+    root!(place, cx);
+    set_var(place, newlet, env, cx)?;
+    Ok(newlet.bind(cx))
 }
 
 #[defun]
@@ -142,9 +154,131 @@ pub(crate) fn boundp(symbol: Symbol, env: &Rt<Env>) -> bool {
 }
 
 #[defun]
-pub(crate) fn makunbound<'ob>(symbol: Symbol<'ob>, env: &mut Rt<Env>) -> Symbol<'ob> {
-    env.vars.remove(symbol);
-    symbol
+pub(crate) fn makunbound<'ob>(
+    symbol: &Rto<Gc<Symbol>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Symbol<'ob>> {
+    let symbol = symbol.untag(cx);
+    root!(symbol, cx);
+    root!(value, NIL, cx);
+    notify_watchers(symbol, value, sym::MAKUNBOUND, env, cx)?;
+    env.remove_var(symbol.bind(cx));
+    Ok(symbol.bind(cx))
+}
+
+/// Call WATCH-FUNCTION when SYMBOL changes. It is passed the symbol, the new
+/// value, the operation (`set', `let', `unlet' or `makunbound'), and the
+/// buffer, which is always nil. It runs just before the change, so the
+/// variable still has its old value.
+#[defun]
+fn add_variable_watcher(symbol: Symbol, watch_function: Object, env: &mut Rt<Env>, cx: &Context) {
+    match env.watchers.get_mut(symbol) {
+        Some(watchers) => {
+            if !watchers.iter().any(|x| crate::fns::equal(x.bind(cx), watch_function)) {
+                watchers.push(watch_function);
+            }
+        }
+        None => env.watchers.insert(symbol, vec![watch_function]),
+    }
+}
+
+#[defun]
+fn remove_variable_watcher(
+    symbol: Symbol,
+    watch_function: Object,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) {
+    let Some(watchers) = env.watchers.get_mut(symbol) else { return };
+    let idx = watchers.iter().position(|x| crate::fns::equal(x.bind(cx), watch_function));
+    if let Some(idx) = idx {
+        watchers.remove(idx);
+    }
+    if watchers.is_empty() {
+        env.watchers.remove(symbol);
+    }
+}
+
+#[defun]
+fn get_variable_watchers<'ob>(symbol: Symbol, env: &Rt<Env>, cx: &'ob Context) -> Object<'ob> {
+    match env.watchers.get(symbol) {
+        Some(watchers) => {
+            let watchers: Vec<_> = watchers.iter().map(|x| x.bind(cx)).collect();
+            crate::fns::slice_into_list(&watchers, None, cx)
+        }
+        None => NIL,
+    }
+}
+
+/// Call the watchers of `var` before it is changed by `operation`, with the
+/// value it is about to have. Like in Emacs they run before the change, so
+/// they can still see the old value.
+fn notify_watchers(
+    var: &Rto<Symbol>,
+    value: &Rto<Object>,
+    operation: Symbol,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let Some(funcs) = env.watchers.get(var.bind(cx)) else { return Ok(()) };
+    // A watcher can add or remove watchers, so call the ones there were to
+    // begin with
+    root!(watchers, new(Vec<Slot<Object>>), cx);
+    for func in funcs.iter() {
+        watchers.push(func.bind(cx));
+    }
+    for func in watchers.iter() {
+        let func: Function = func.bind(cx).try_into()?;
+        root!(func, cx);
+        let (symbol, value) = (var.bind(cx), value.bind(cx));
+        call!(func, symbol, value, operation, NIL; env, cx)?;
+    }
+    Ok(())
+}
+
+/// Set `var` to `value` like `set`, calling its watchers first.
+pub(crate) fn set_var(
+    var: &Rto<Symbol>,
+    value: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let symbol = var.bind(cx);
+    if env.is_constant(symbol) {
+        return Err(ConstantError::new(symbol).into());
+    }
+    notify_watchers(var, value, sym::SET, env, cx)?;
+    env.set_var(var.bind(cx), value.bind(cx))
+}
+
+/// Bind `var` to `value` like `let`, calling its watchers first.
+pub(crate) fn varbind(
+    var: &Rto<Symbol>,
+    value: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    notify_watchers(var, value, sym::LET, env, cx)?;
+    env.varbind(var.bind(cx), value.bind(cx), cx);
+    Ok(())
+}
+
+/// Undo the last `count` bindings, calling the watchers of each variable
+/// before its value is restored. Every binding is undone even if a watcher
+/// signals, and the first error is returned.
+pub(crate) fn unbind(count: u16, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let mut result = Ok(());
+    for _ in 0..count {
+        if let Some((var, value)) = env.next_unbinding(cx) {
+            root!(var, cx);
+            root!(value, cx);
+            let notified = notify_watchers(var, value, sym::UNLET, env, cx);
+            result = result.and(notified);
+        }
+        env.unbind(1, cx);
+    }
+    result
 }
 
 #[defun]
@@ -257,29 +391,76 @@ pub(crate) fn multibyte_string_p(object: Object) -> bool {
     matches!(object.untag(), ObjectType::String(_))
 }
 
+/// Parse the number at the start of STRING. Leading spaces and tabs and
+/// anything after the number are ignored, and 0 is returned if there is no
+/// number. BASE is the radix of integers, from 2 to 16. Floats are only read
+/// in base 10.
 #[defun]
-fn string_to_number<'ob>(string: &str, base: Option<i64>, cx: &'ob Context) -> Number<'ob> {
-    // TODO: Handle trailing characters, which should be ignored
+fn string_to_number<'ob>(
+    string: &str,
+    base: Option<i64>,
+    cx: &'ob Context,
+) -> Result<Number<'ob>> {
     let base = base.unwrap_or(10);
-    let string = string.trim();
-    match i64::from_str_radix(string, base as u32) {
-        Ok(x) => x.into(),
-        Err(_) => match string.parse::<f64>() {
-            Ok(x) => cx.add_as(x),
-            Err(_) => 0.into(),
-        },
+    ensure!((2..=16).contains(&base), "Invalid base for string-to-number: {base}");
+    let string = string.trim_start_matches([' ', '\t']);
+    if base != 10 {
+        let (sign, digits) = match string.strip_prefix('-') {
+            Some(digits) => (-1, digits),
+            None => (1, string.strip_prefix('+').unwrap_or(string)),
+        };
+        let end = digits.find(|c: char| !c.is_digit(base as u32)).unwrap_or(digits.len());
+        let value = i64::from_str_radix(&digits[..end], base as u32).unwrap_or(0);
+        return Ok((sign * value).into());
+    }
+    let number = number_prefix(string);
+    match number.strip_suffix('.').unwrap_or(number).parse::<i64>() {
+        Ok(x) if (MIN_FIXNUM..=MAX_FIXNUM).contains(&x) => Ok(x.into()),
+        // There are no bignums, so large integers are read as floats
+        _ => Ok(parse_float(number).map_or(0.into(), |x| cx.add_as(x))),
     }
 }
 
+/// The number at the start of `text`: a sign, digits with an optional
+/// fraction, and an optional exponent.
+fn number_prefix(text: &str) -> &str {
+    let bytes = text.as_bytes();
+    let digits = |i: usize| i + bytes[i..].iter().take_while(|b| b.is_ascii_digit()).count();
+    let mut end = digits(usize::from(matches!(bytes.first(), Some(b'+' | b'-'))));
+    if bytes.get(end) == Some(&b'.') {
+        end = digits(end + 1);
+    }
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let exponent = &text[end + 1..];
+        if exponent.starts_with("+INF") || exponent.starts_with("+NaN") {
+            end += 5;
+        } else {
+            let start = end + 1 + usize::from(matches!(exponent.bytes().next(), Some(b'+' | b'-')));
+            if digits(start) > start {
+                end = digits(start);
+            }
+        }
+    }
+    &text[..end]
+}
+
 #[defun]
 pub(crate) fn defvar<'ob>(
-    symbol: Symbol,
-    initvalue: Option<Object<'ob>>,
-    _docstring: Option<&str>,
+    symbol: &Rto<Gc<Symbol>>,
+    initvalue: Option<&Rto<Object>>,
+    docstring: Option<&str>,
     env: &mut Rt<Env>,
+    cx: &'ob mut Context,
 ) -> Result<Object<'ob>> {
-    let value = initvalue.unwrap_or_default();
-    set(symbol, value, env)
+    let symbol = symbol.untag(cx);
+    if let Some(doc) = docstring {
+        env.set_prop(symbol, sym::VARIABLE_DOCUMENTATION, cx.add(doc));
+    }
+    root!(symbol, cx);
+    let value = initvalue.map_or(NIL, |x| x.bind(cx));
+    root!(value, cx);
+    set_var(symbol, value, env, cx)?;
+    Ok(value.bind(cx))
 }
 
 #[defun]
@@ -301,17 +482,6 @@ fn subr_arity<'ob>(subr: &SubrFn, cx: &'ob Context) -> Object<'ob> {
     Cons::new(min, max, cx).into()
 }
 
-#[defun]
-fn ash(value: i64, count: i64) -> i64 {
-    let shift = if count >= 0 { std::ops::Shl::shl } else { std::ops::Shr::shr };
-    let result = shift(value.abs(), count.abs());
-    if value >= 0 {
-        result
-    } else {
-        -result
-    }
-}
-
 #[defun]
 pub(crate) fn aset<'ob>(
     array: Object<'ob>,
@@ -360,7 +530,7 @@ pub(crate) fn aref<'ob>(array: Object<'ob>, idx: usize, cx: &'ob Context) -> Res
                 Err(anyhow!("index {idx} is out of bounds. Length was {len}"))
             }
         },
-        ObjectType::String(string) => match string.chars().nth(idx) {
+        ObjectType::String(string) => match string.char_at(idx) {
             Some(x) => Ok((i64::from(x as u32)).into()),
             None => {
                 let len = string.len();
@@ -374,7 +544,7 @@ pub(crate) fn aref<'ob>(array: Object<'ob>, idx: usize, cx: &'ob Context) -> Res
                 Err(anyhow!("index {idx} is out of bounds. Length was {len}"))
             }
         },
-        ObjectType::ByteFn(fun) => match fun.index(idx, cx) {
+        ObjectType::ByteFn(fun) => match crate::bytecode::byte_fn_index(fun, idx, cx) {
             Some(x) => Ok(x),
             None => Err(anyhow!("index {idx} is out of bounds")),
         },
@@ -383,7 +553,7 @@ pub(crate) fn aref<'ob>(array: Object<'ob>, idx: usize, cx: &'ob Context) -> Res
 }
 
 #[defun]
-fn type_of(object: Object) -> Object {
+pub(crate) fn type_of(object: Object) -> Object {
     match object.untag() {
         ObjectType::Int(_) => sym::INTEGER.into(),
         ObjectType::Float(_) => sym::FLOAT.into(),
@@ -410,13 +580,59 @@ pub(crate) fn indirect_function<'ob>(object: Object<'ob>, cx: &'ob Context) -> O
     }
 }
 
-#[defun]
-pub(crate) fn provide<'ob>(feature: Symbol<'ob>, _subfeatures: Option<&Cons>) -> Symbol<'ob> {
-    let mut features = features().lock().unwrap();
+/// Add `feature` to the global feature set without touching the `features`
+/// variable or running any `after-load-alist` functions.
+pub(crate) fn add_feature(feature: Symbol) {
     // TODO: SYMBOL - need to trace this
     let feat = unsafe { feature.with_lifetime() };
-    features.insert(feat);
-    feature
+    features().lock().unwrap().insert(feat);
+}
+
+/// Make the feature set match `value`, the new value of the `features`
+/// variable. `None` means it was made void.
+pub(crate) fn sync_features(value: Option<Object>) {
+    let mut features = features().lock().unwrap();
+    features.clear();
+    let Some(Ok(list)) = value.map(Object::as_list) else { return };
+    for feature in list.flatten() {
+        if let ObjectType::Symbol(feature) = feature.untag() {
+            // TODO: SYMBOL - need to trace this
+            features.insert(unsafe { feature.with_lifetime() });
+        }
+    }
+}
+
+/// Announce that FEATURE is a feature of the current Emacs. SUBFEATURES is
+/// stored as the `subfeatures` property of FEATURE. The functions waiting for
+/// FEATURE in `after-load-alist` are called.
+#[defun]
+pub(crate) fn provide<'ob>(
+    feature: &Rto<Gc<Symbol>>,
+    subfeatures: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Symbol<'ob>> {
+    let feat = feature.untag(cx);
+    let list = env.vars.get(sym::FEATURES).map_or(NIL, |x| x.bind(cx));
+    if crate::fns::memq(feat.into(), list.try_into()?)?.is_nil() {
+        // Insert directly so the other features of the process are not
+        // replaced by the features of this thread
+        let list: Object = Cons::new(feat, list, cx).into();
+        env.vars.insert(sym::FEATURES, list);
+    }
+    add_feature(feat);
+    if let Some(subfeatures) = subfeatures {
+        env.set_prop(feat, sym::SUBFEATURES, subfeatures.bind(cx));
+    }
+    let alist = env.vars.get(sym::AFTER_LOAD_ALIST).map_or(NIL, |x| x.bind(cx));
+    if let ObjectType::Cons(entry) = crate::fns::assq(feat.into(), alist.try_into()?)?.untag() {
+        rooted_iter!(functions, entry.cdr(), cx);
+        while let Some(function) = functions.next()? {
+            let function = function.try_as()?;
+            call!(function; env, cx)?;
+        }
+    }
+    Ok(feature.untag(cx))
 }
 
 #[defun]
@@ -481,20 +697,8 @@ fn symbol_with_pos_p(_sym: Object) -> bool {
     false
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-
-    #[test]
-    fn test_ash() {
-        assert_eq!(ash(4, 1), 8);
-        assert_eq!(ash(4, -1), 2);
-        assert_eq!(ash(-8, -1), -4);
-        assert_eq!(ash(256, -8), 1);
-        assert_eq!(ash(-8, 1), -16);
-    }
-}
-
+defvar!(FEATURES);
+defsym!(SUBFEATURES);
 defsym!(MANY);
 defsym!(INTEGER);
 defsym!(SYMBOL);
@@ -502,3 +706,30 @@ defsym!(COMPILED_FUNCTION);
 defsym!(HASH_TABLE);
 defsym!(BUFFER);
 defsym!(SUBR);
+defsym!(UNLET);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+
+    #[test]
+    fn test_string_to_number() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let number = |string, base| string_to_number(string, base, cx).unwrap().to_string();
+        assert_eq!(number(" 12abc", None), "12");
+        assert_eq!(number("1.", None), "1");
+        assert_eq!(number("-1.5e3x", None), "-1500.0");
+        assert_eq!(number(".5", None), "0.5");
+        assert_eq!(number("2e", None), "2");
+        assert_eq!(number("1.0e+INF", None), "1.0e+INF");
+        assert_eq!(number("-0.0e+NaN", None), "-0.0e+NaN");
+        assert_eq!(number("abc", None), "0");
+        assert_eq!(number("ff", Some(16)), "255");
+        assert_eq!(number("-12z", Some(8)), "-10");
+        assert_eq!(number("1.5", Some(16)), "1");
+        assert_eq!(number("100000000000000000000", None), "1e+20");
+        assert!(string_to_number("1", Some(17), cx).is_err());
+    }
+}
@@ -19,6 +19,13 @@ pub(crate) fn disable_debug() {
     FLAG.store(false, Ordering::Release);
 }
 
+/// Keeps a span created by `trace_span!` entered until it is dropped.
+#[must_use]
+pub(crate) struct SpanGuard {
+    #[cfg(feature = "tracing")]
+    pub(crate) _span: tracing::span::EnteredSpan,
+}
+
 macro_rules! debug {
     ($($arg:tt)*) => {{
         if crate::debug::debug_enabled() {
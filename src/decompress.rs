@@ -0,0 +1,229 @@
+//! Compressing and decompressing gzip and zlib data.
+//!
+//! This needs the `zlib` feature, which pulls in flate2. Without it
+//! `zlib-available-p` is nil and the other functions signal an error. Binary
+//! data is held in buffers and multibyte strings as the characters 0 to 255,
+//! the same as the `binary` coding system.
+use crate::coding::Coding;
+use crate::core::{
+    env::Env,
+    error::{Type, TypeError},
+    gc::{Context, Rt},
+    object::{Object, ObjectType, NIL, TRUE},
+};
+use anyhow::{bail, Result};
+use rune_macros::defun;
+
+/// Decompress `data`, which is gzip or zlib depending on its header. Returns
+/// the decompressed bytes, and if the data is invalid or truncated, the number
+/// of bytes that were left.
+#[cfg(feature = "zlib")]
+fn decompress(data: &[u8]) -> Result<(Vec<u8>, Option<usize>)> {
+    use flate2::bufread::{MultiGzDecoder, ZlibDecoder};
+    use std::io::Read;
+    let mut decompressed = Vec::new();
+    // `read_to_end` keeps what was decompressed before an error
+    let remaining = if data.starts_with(&[0x1f, 0x8b]) {
+        let mut decoder = MultiGzDecoder::new(data);
+        let result = decoder.read_to_end(&mut decompressed);
+        result.err().map(|_| decoder.get_ref().len())
+    } else {
+        let mut decoder = ZlibDecoder::new(data);
+        let result = decoder.read_to_end(&mut decompressed);
+        result.err().map(|_| decoder.get_ref().len())
+    };
+    Ok((decompressed, remaining))
+}
+
+#[cfg(not(feature = "zlib"))]
+fn decompress(_data: &[u8]) -> Result<(Vec<u8>, Option<usize>)> {
+    bail!("zlib is not available in this build of rune")
+}
+
+/// Compress `data` in the gzip format, or in the zlib format if `gzip` is
+/// false.
+#[cfg(feature = "zlib")]
+fn compress(data: &[u8], gzip: bool) -> Result<Vec<u8>> {
+    use flate2::bufread::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use std::io::Read;
+    let mut compressed = Vec::new();
+    if gzip {
+        GzEncoder::new(data, Compression::default()).read_to_end(&mut compressed)?;
+    } else {
+        ZlibEncoder::new(data, Compression::default()).read_to_end(&mut compressed)?;
+    }
+    Ok(compressed)
+}
+
+#[cfg(not(feature = "zlib"))]
+fn compress(_data: &[u8], _gzip: bool) -> Result<Vec<u8>> {
+    bail!("zlib is not available in this build of rune")
+}
+
+fn string_bytes(string: Object) -> Result<Vec<u8>> {
+    match string.untag() {
+        ObjectType::String(string) => Ok(Coding::BINARY.encode(string)),
+        ObjectType::ByteString(string) => Ok(string.to_vec()),
+        _ => Err(TypeError::new(Type::String, string).into()),
+    }
+}
+
+/// Return t if rune was built with zlib support.
+#[defun]
+fn zlib_available_p() -> bool {
+    cfg!(feature = "zlib")
+}
+
+/// Replace the region from START to END of the current buffer with the
+/// result of `transform` on its bytes. If `transform` returns `None` the
+/// buffer is not changed. Point stays where it was, unless the region shrinks
+/// past it.
+fn transform_region(
+    start: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    transform: impl FnOnce(&[u8]) -> Result<Option<Vec<u8>>>,
+) -> Result<()> {
+    let Some(buffer) = env.current_buffer.as_mut() else { bail!("No current buffer") };
+    let (start, end) = (start.min(end), start.max(end));
+    let (s1, s2) = buffer.slice_with_gap(start, end)?;
+    let bytes = Coding::BINARY.encode(&format!("{s1}{s2}"));
+    let Some(bytes) = transform(&bytes)? else { return Ok(()) };
+    let point = buffer.text.cursor().chars();
+    buffer.delete(start, end)?;
+    buffer.text.set_cursor(start - 1);
+    buffer.text.insert(&Coding::BINARY.decode(&bytes));
+    buffer.text.set_cursor(point.min(buffer.text.len_chars()));
+    Ok(())
+}
+
+/// Decompress the gzip or zlib data between START and END in the current
+/// buffer, and replace it with the result. Return t if it was decompressed.
+/// If the data is invalid, return nil and leave it alone, or if ALLOW-PARTIAL
+/// is non-nil replace it with whatever could be decompressed and return the
+/// number of bytes that were not.
+#[defun]
+fn zlib_decompress_region(
+    start: usize,
+    end: usize,
+    allow_partial: Option<()>,
+    env: &mut Rt<Env>,
+) -> Result<Object<'static>> {
+    let mut result = TRUE;
+    transform_region(start, end, env, |bytes| {
+        let (decompressed, remaining) = decompress(bytes)?;
+        match remaining {
+            None => Ok(Some(decompressed)),
+            Some(_) if allow_partial.is_none() => {
+                result = NIL;
+                Ok(None)
+            }
+            Some(remaining) => {
+                result = (remaining as i64).into();
+                Ok(Some(decompressed))
+            }
+        }
+    })?;
+    Ok(result)
+}
+
+/// Compress the text between START and END in the current buffer, and replace
+/// it with the result. The data is in the zlib format, or gzip if GZIP is
+/// non-nil.
+#[defun]
+fn zlib_compress_region(
+    start: usize,
+    end: usize,
+    gzip: Option<()>,
+    env: &mut Rt<Env>,
+) -> Result<()> {
+    transform_region(start, end, env, |bytes| Ok(Some(compress(bytes, gzip.is_some())?)))
+}
+
+/// Decompress STRING, which holds gzip or zlib data, and return the result as
+/// a unibyte string. If the data is invalid return nil, or if ALLOW-PARTIAL is
+/// non-nil whatever could be decompressed.
+#[defun]
+fn zlib_decompress_string<'ob>(
+    string: Object,
+    allow_partial: Option<()>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let (decompressed, remaining) = decompress(&string_bytes(string)?)?;
+    if remaining.is_some() && allow_partial.is_none() {
+        return Ok(NIL);
+    }
+    Ok(cx.add(decompressed))
+}
+
+/// Compress STRING and return the result as a unibyte string. The data is in
+/// the zlib format, or gzip if GZIP is non-nil.
+#[defun]
+fn zlib_compress_string<'ob>(
+    string: Object,
+    gzip: Option<()>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    Ok(cx.add(compress(&string_bytes(string)?, gzip.is_some())?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    #[cfg(not(feature = "zlib"))]
+    fn test_unavailable() {
+        let roots = &crate::core::gc::RootSet::default();
+        let cx = &Context::new(roots);
+        assert!(!zlib_available_p());
+        assert!(zlib_compress_string(cx.add("hello"), None, cx).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "zlib")]
+    fn test_string_round_trip() {
+        let roots = &crate::core::gc::RootSet::default();
+        let cx = &Context::new(roots);
+        assert!(zlib_available_p());
+        let text = "hello hello hello hello";
+        for gzip in [None, Some(())] {
+            let compressed = zlib_compress_string(cx.add(text), gzip, cx).unwrap();
+            let ObjectType::ByteString(bytes) = compressed.untag() else { unreachable!() };
+            assert_eq!(bytes.starts_with(&[0x1f, 0x8b]), gzip.is_some());
+            let decompressed = zlib_decompress_string(compressed, None, cx).unwrap();
+            let ObjectType::ByteString(decompressed) = decompressed.untag() else { unreachable!() };
+            assert_eq!(&**decompressed, text.as_bytes());
+
+            // truncated data
+            let truncated = cx.add(bytes[..bytes.len() - 6].to_vec());
+            assert_eq!(zlib_decompress_string(truncated, None, cx).unwrap(), NIL);
+            assert!(zlib_decompress_string(truncated, Some(()), cx).is_ok());
+        }
+        assert_eq!(zlib_decompress_string(cx.add("not compressed"), None, cx).unwrap(), NIL);
+    }
+
+    #[test]
+    #[cfg(feature = "zlib")]
+    fn test_region_round_trip() {
+        use crate::buffer::{get_buffer_create, set_buffer};
+        use rune_core::macros::root;
+        let roots = &crate::core::gc::RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        let buffer = get_buffer_create(cx.add("test_zlib_region"), Some(NIL), cx).unwrap();
+        set_buffer(buffer, env, cx).unwrap();
+        env.current_buffer.as_mut().unwrap().text.insert("<data data data data>");
+
+        zlib_compress_region(2, 21, Some(()), env).unwrap();
+        assert_ne!(env.current_buffer.as_ref().unwrap(), "<data data data data>");
+        let end = env.current_buffer.as_ref().unwrap().text.len_chars();
+        assert_eq!(zlib_decompress_region(2, end, None, env).unwrap(), TRUE);
+        assert_eq!(env.current_buffer.as_ref().unwrap(), "<data data data data>");
+
+        // invalid data is left alone
+        assert_eq!(zlib_decompress_region(2, 21, None, env).unwrap(), NIL);
+        assert_eq!(env.current_buffer.as_ref().unwrap(), "<data data data data>");
+    }
+}
@@ -0,0 +1,301 @@
+//! Destructuring lambda lists.
+//!
+//! `cl-destructuring-bind` and `cl-defmacro` take lambda lists that can be
+//! nested and can have `&key` and `&aux` parameters as well as `&optional` and
+//! `&rest`. [`parse`] reads one into a [`LambdaList`] so that Rust code, like
+//! native macros, can inspect it. The interpreter binds them natively instead of
+//! expanding them into chains of `car` and `cdr` calls.
+use crate::core::{
+    cons::Cons,
+    env::{intern, sym},
+    gc::Context,
+    object::{List, Object, ObjectType, Symbol, NIL},
+};
+use anyhow::{bail, ensure, Result};
+
+/// Where a parameter appears in a lambda list. Each section is started by a
+/// marker like `&optional`, and they have to be in this order.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub(crate) enum Section {
+    Required,
+    Optional,
+    Rest,
+    Key,
+    Aux,
+}
+
+impl Section {
+    /// The section started by `marker`, if it is a lambda list marker other
+    /// than `&allow-other-keys`.
+    pub(crate) fn from_marker(marker: Symbol) -> Option<Self> {
+        match marker {
+            sym::AND_OPTIONAL => Some(Self::Optional),
+            sym::AND_REST | sym::AND_BODY => Some(Self::Rest),
+            sym::AND_KEY => Some(Self::Key),
+            sym::AND_AUX => Some(Self::Aux),
+            _ => None,
+        }
+    }
+}
+
+/// A variable, or a nested lambda list that destructures the value.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Pattern<'ob> {
+    Var(Symbol<'ob>),
+    List(Box<LambdaList<'ob>>),
+}
+
+/// An `&optional`, `&key`, or `&aux` parameter.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Param<'ob> {
+    pub(crate) pattern: Pattern<'ob>,
+    /// The form evaluated for the value when none is given
+    pub(crate) default: Object<'ob>,
+    /// The variable bound to whether a value was given
+    pub(crate) supplied: Option<Symbol<'ob>>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct LambdaList<'ob> {
+    pub(crate) required: Vec<Pattern<'ob>>,
+    pub(crate) optional: Vec<Param<'ob>>,
+    /// From `&rest`, `&body`, or a dotted tail
+    pub(crate) rest: Option<Pattern<'ob>>,
+    /// Keyword parameters, along with the keyword that sets them
+    pub(crate) keys: Vec<(Symbol<'ob>, Param<'ob>)>,
+    pub(crate) allow_other_keys: bool,
+    pub(crate) aux: Vec<Param<'ob>>,
+}
+
+impl<'ob> LambdaList<'ob> {
+    /// Every variable bound by this lambda list, in the order they are bound.
+    pub(crate) fn variables(&self) -> Vec<Symbol<'ob>> {
+        fn pattern_vars<'ob>(pattern: &Pattern<'ob>, vars: &mut Vec<Symbol<'ob>>) {
+            match pattern {
+                Pattern::Var(var) => vars.push(*var),
+                Pattern::List(list) => vars.extend(list.variables()),
+            }
+        }
+        fn param_vars<'ob>(param: &Param<'ob>, vars: &mut Vec<Symbol<'ob>>) {
+            pattern_vars(&param.pattern, vars);
+            vars.extend(param.supplied);
+        }
+        let mut vars = Vec::new();
+        self.required.iter().for_each(|x| pattern_vars(x, &mut vars));
+        self.optional.iter().for_each(|x| param_vars(x, &mut vars));
+        self.rest.iter().for_each(|x| pattern_vars(x, &mut vars));
+        self.keys.iter().for_each(|(_, x)| param_vars(x, &mut vars));
+        self.aux.iter().for_each(|x| param_vars(x, &mut vars));
+        vars
+    }
+}
+
+/// Parse a destructuring lambda list.
+pub(crate) fn parse<'ob>(list: Object<'ob>, cx: &'ob Context) -> Result<LambdaList<'ob>> {
+    let mut parsed = LambdaList::default();
+    let mut section = Section::Required;
+    let mut last: Option<&Cons> = None;
+    for cons in List::try_from(list)?.conses() {
+        let Ok(cons) = cons else {
+            // (a b . c)
+            let tail = last.map_or(list, |x| x.cdr());
+            let ObjectType::Symbol(var) = tail.untag() else {
+                bail!("Invalid lambda list: {list}")
+            };
+            ensure!(section <= Section::Optional, "Misplaced dotted tail in {list}");
+            parsed.rest = Some(Pattern::Var(var));
+            return Ok(parsed);
+        };
+        last = Some(cons);
+        let param = cons.car();
+        if let ObjectType::Symbol(marker) = param.untag() {
+            if marker == sym::AND_ALLOW_OTHER_KEYS {
+                ensure!(section == Section::Key, "Misplaced {marker} in {list}");
+                parsed.allow_other_keys = true;
+                continue;
+            }
+            if let Some(next) = Section::from_marker(marker) {
+                ensure!(section < next, "Misplaced {marker} in {list}");
+                ensure!(
+                    section != Section::Rest || parsed.rest.is_some(),
+                    "Missing variable after &rest in {list}"
+                );
+                section = next;
+                continue;
+            }
+        }
+        match section {
+            Section::Required => parsed.required.push(parse_pattern(param, cx)?),
+            Section::Optional => parsed.optional.push(parse_param(param, cx)?),
+            Section::Rest => {
+                ensure!(parsed.rest.is_none(), "Found multiple arguments after &rest in {list}");
+                parsed.rest = Some(parse_pattern(param, cx)?);
+            }
+            Section::Key => {
+                let (head, default, supplied) = param_parts(param)?;
+                let (keyword, pattern) = key_parts(head, cx)?;
+                let pattern = parse_pattern(pattern, cx)?;
+                parsed.keys.push((keyword, Param { pattern, default, supplied }));
+            }
+            Section::Aux => {
+                let param = parse_param(param, cx)?;
+                ensure!(param.supplied.is_none(), "Invalid &aux parameter in {list}");
+                parsed.aux.push(param);
+            }
+        }
+    }
+    ensure!(
+        section != Section::Rest || parsed.rest.is_some(),
+        "Missing variable after &rest in {list}"
+    );
+    Ok(parsed)
+}
+
+fn parse_pattern<'ob>(obj: Object<'ob>, cx: &'ob Context) -> Result<Pattern<'ob>> {
+    match obj.untag() {
+        ObjectType::Symbol(var) => Ok(Pattern::Var(var)),
+        ObjectType::Cons(_) => Ok(Pattern::List(Box::new(parse(obj, cx)?))),
+        _ => bail!("Invalid lambda list parameter: {obj}"),
+    }
+}
+
+fn parse_param<'ob>(obj: Object<'ob>, cx: &'ob Context) -> Result<Param<'ob>> {
+    let (head, default, supplied) = param_parts(obj)?;
+    Ok(Param { pattern: parse_pattern(head, cx)?, default, supplied })
+}
+
+/// Split a parameter like `(var default supplied-p)` into its parts. A lone
+/// variable has a default of `nil`.
+pub(crate) fn param_parts(obj: Object) -> Result<(Object, Object, Option<Symbol>)> {
+    let ObjectType::Cons(_) = obj.untag() else { return Ok((obj, NIL, None)) };
+    let parts = obj.as_list()?.collect::<Result<Vec<_>, _>>()?;
+    ensure!(parts.len() <= 3, "Invalid lambda list parameter: {obj}");
+    let supplied = match parts.get(2) {
+        Some(var) => Some(Symbol::try_from(*var)?),
+        None => None,
+    };
+    Ok((parts[0], parts.get(1).copied().unwrap_or(NIL), supplied))
+}
+
+/// The keyword and pattern of the head of a `&key` parameter, which is either
+/// a variable or `(keyword pattern)`.
+pub(crate) fn key_parts<'ob>(
+    head: Object<'ob>,
+    cx: &'ob Context,
+) -> Result<(Symbol<'ob>, Object<'ob>)> {
+    match head.untag() {
+        ObjectType::Symbol(var) => Ok((intern(&format!(":{}", var.name()), cx), head)),
+        ObjectType::Cons(_) => {
+            let parts = head.as_list()?.collect::<Result<Vec<_>, _>>()?;
+            let [keyword, pattern] = parts[..] else { bail!("Invalid &key parameter: {head}") };
+            Ok((keyword.try_into()?, pattern))
+        }
+        _ => bail!("Invalid &key parameter: {head}"),
+    }
+}
+
+/// The value of `keyword` in the keyword arguments `plist`. The first
+/// occurrence is used.
+pub(crate) fn key_value<'ob>(plist: Object<'ob>, keyword: Symbol) -> Result<Option<Object<'ob>>> {
+    let mut iter = plist.as_list()?;
+    while let Some(key) = iter.next() {
+        let Some(value) = iter.next() else { bail!("Odd number of keyword arguments: {plist}") };
+        if key? == keyword {
+            return Ok(Some(value?));
+        }
+    }
+    Ok(None)
+}
+
+/// Check that every key in the keyword arguments `plist` is one of
+/// `keywords`, unless other keys are allowed.
+pub(crate) fn check_keys(plist: Object, keywords: &[Symbol], allow_other_keys: bool) -> Result<()> {
+    if allow_other_keys || key_value(plist, sym::KW_ALLOW_OTHER_KEYS)?.is_some_and(|x| x != NIL) {
+        return Ok(());
+    }
+    let mut iter = plist.as_list()?;
+    while let Some(key) = iter.next() {
+        let key = key?;
+        ensure!(
+            keywords.iter().any(|x| key == *x),
+            "Keyword argument {key} is not one of {keywords:?}"
+        );
+        iter.next();
+    }
+    Ok(())
+}
+
+defsym!(CL_DESTRUCTURING_BIND);
+defsym!(AND_KEY, "&key");
+defsym!(AND_BODY, "&body");
+defsym!(AND_AUX, "&aux");
+defsym!(AND_ALLOW_OTHER_KEYS, "&allow-other-keys");
+defsym!(KW_ALLOW_OTHER_KEYS);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+
+    fn read<'ob>(string: &str, cx: &'ob Context) -> Object<'ob> {
+        crate::reader::read(string, cx).unwrap().0
+    }
+
+    #[test]
+    fn test_parse() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        sym::init_symbols();
+        let list = "(a (b . c) &optional (d 1 d-p) &rest r \
+                    &key e ((:f g) 2) &allow-other-keys &aux (h 3))";
+        let list = read(list, cx);
+        let parsed = parse(list, cx).unwrap();
+        assert_eq!(parsed.required.len(), 2);
+        let Pattern::List(nested) = &parsed.required[1] else { panic!("expected a nested list") };
+        assert!(matches!(nested.rest, Some(Pattern::Var(_))));
+        assert_eq!(parsed.optional[0].default, 1);
+        assert_eq!(parsed.keys[0].0, intern(":e", cx));
+        assert_eq!(parsed.keys[1].0, intern(":f", cx));
+        assert_eq!(parsed.keys[1].1.default, 2);
+        assert!(parsed.allow_other_keys);
+        let names: Vec<_> = parsed.variables().iter().map(|x| x.name().to_owned()).collect();
+        assert_eq!(names, ["a", "b", "c", "d", "d-p", "r", "e", "g", "h"]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        sym::init_symbols();
+        for list in [
+            "(&optional a &optional b)",
+            "(&rest)",
+            "(&rest a b)",
+            "(&key a &rest b)",
+            "(a . 1)",
+            "(&key a . b)",
+            "(&allow-other-keys)",
+            "(&aux (a 1 a-p))",
+            "(1)",
+        ] {
+            assert!(parse(read(list, cx), cx).is_err(), "{list} should not parse");
+        }
+    }
+
+    #[test]
+    fn test_keys() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        sym::init_symbols();
+        let a = intern(":a", cx);
+        let b = intern(":b", cx);
+        let plist = read("(:a 1 :b 2 :a 3)", cx);
+        assert_eq!(key_value(plist, a).unwrap(), Some(1.into()));
+        assert_eq!(key_value(plist, intern(":c", cx)).unwrap(), None);
+        assert!(key_value(read("(:a)", cx), a).is_err());
+        assert!(check_keys(plist, &[a, b], false).is_ok());
+        assert!(check_keys(plist, &[a], false).is_err());
+        assert!(check_keys(plist, &[a], true).is_ok());
+        assert!(check_keys(read("(:b 2 :allow-other-keys t)", cx), &[a], false).is_ok());
+    }
+}
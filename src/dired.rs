@@ -1,28 +1,49 @@
 use crate::core::{
-    gc::Context,
-    object::{Object, NIL, TRUE},
+    env::{sym, Env},
+    gc::{Context, Rt, Rto},
+    object::{Function, Gc, LispString, Object, NIL, TRUE},
 };
-use rune_core::macros::list;
+use crate::fileio::{expand_file_name, file_handler};
+use crate::fns::slice_into_list;
+use crate::search::lisp_regex_to_rust;
+use crate::timefns::LispTime;
+use anyhow::{Context as _, Result};
+use fancy_regex::Regex;
+use rune_core::macros::{call, list, root};
 use rune_macros::defun;
+use std::fs::Metadata;
 use std::path::Path;
 
-#[defun]
-fn file_attributes<'ob>(filename: &str, _id_format: Option<()>, cx: &'ob Context) -> Object<'ob> {
+/// Return a list of attributes of file FILENAME, or nil if it does not exist.
+/// Symbolic links are not followed. ID-FORMAT is ignored and the user and
+/// group ids are always integers.
+#[defun(io)]
+fn file_attributes<'ob>(
+    filename: &str,
+    id_format: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    if let Some(handler) = file_handler(filename, sym::FILE_ATTRIBUTES, env, cx)? {
+        root!(handler, cx);
+        let operation = Object::from(sym::FILE_ATTRIBUTES);
+        let filename = cx.add(filename);
+        return Ok(call!(handler, operation, filename, id_format.is_some(); env, cx)?);
+    }
     let file = Path::new(filename);
-    if file.exists() {
-        metadata_attributes(file, cx)
-    } else {
-        NIL
+    match file.symlink_metadata() {
+        Ok(metadata) => Ok(metadata_attributes(file, &metadata, cx)),
+        Err(_) => Ok(NIL),
     }
 }
 
 #[cfg(unix)]
-fn metadata_attributes<'ob>(file: &Path, cx: &'ob Context) -> Object<'ob> {
+fn metadata_attributes<'ob>(file: &Path, metadata: &Metadata, cx: &'ob Context) -> Object<'ob> {
     use std::os::unix::fs::MetadataExt;
-    let metadata = &file.metadata().unwrap();
+    let time = |secs, nsecs: i64| LispTime::new(secs, nsecs * 1000).into_obj(cx);
 
     //  0. t for directory, string (name linked to) for symbolic link, or nil.
-    let file_type = get_file_type(file, cx);
+    let file_type = get_file_type(file, metadata, cx);
     //  1. Number of hardlinks to file.
     let links = metadata.nlink();
     //  2. File uid as a string or (if ID-FORMAT is integer or a string value
@@ -32,17 +53,17 @@ fn metadata_attributes<'ob>(file: &Path, cx: &'ob Context) -> Object<'ob> {
     let gid = metadata.gid();
     //  4. Last access time, in the style of current-time.
     //   (See a note below about access time on FAT-based filesystems.)
-    let atime = metadata.atime();
+    let atime = time(metadata.atime(), metadata.atime_nsec());
     //  5. Last modification time, likewise.  This is the time of the last
     //   change to the file's contents.
-    let mtime = metadata.mtime();
+    let mtime = time(metadata.mtime(), metadata.mtime_nsec());
     //  6. Last status change time, likewise.  This is the time of last change
     //   to the file's attributes: owner and group, access mode bits, etc.
-    let ctime = metadata.ctime();
+    let ctime = time(metadata.ctime(), metadata.ctime_nsec());
     //  7. Size in bytes, as an integer.
     let size = metadata.size();
     //  8. File modes, as a string of ten letters or dashes as in ls -l.
-    let mode = metadata.mode();
+    let mode = mode_string(metadata.mode());
     //  9. An unspecified value, present only for backward compatibility.
     // 10. inode number, as a nonnegative integer.
     let inode = metadata.ino();
@@ -52,38 +73,487 @@ fn metadata_attributes<'ob>(file: &Path, cx: &'ob Context) -> Object<'ob> {
 }
 
 #[cfg(windows)]
-fn metadata_attributes<'ob>(file: &Path, cx: &'ob Context) -> Object<'ob> {
-    use std::os::windows::fs::MetadataExt;
-    let metadata = &file.metadata().unwrap();
+fn metadata_attributes<'ob>(file: &Path, metadata: &Metadata, cx: &'ob Context) -> Object<'ob> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    let time = |time: std::io::Result<SystemTime>| {
+        let since_epoch = time.ok().and_then(|x| x.duration_since(UNIX_EPOCH).ok());
+        let since_epoch = since_epoch.unwrap_or_default();
+        let secs = i64::try_from(since_epoch.as_secs()).unwrap_or(i64::MAX);
+        LispTime::new(secs, i64::from(since_epoch.subsec_nanos()) * 1000).into_obj(cx)
+    };
 
     //  0. t for directory, string (name linked to) for symbolic link, or nil.
-    let file_type = get_file_type(file, cx);
-    // TODO: implement the rest of the attributes
+    let file_type = get_file_type(file, metadata, cx);
     //  1. Number of hardlinks to file.
     //  2. File uid as a string or (if ID-FORMAT is integer or a string value
     //   cannot be looked up) as an integer.
     //  3. File gid, likewise.
+    // TODO: these are not exposed by the standard library on windows
+    let (links, uid, gid) = (1, 0, 0);
     //  4. Last access time, in the style of current-time.
     //   (See a note below about access time on FAT-based filesystems.)
+    let atime = time(metadata.accessed());
     //  5. Last modification time, likewise.  This is the time of the last
     //   change to the file's contents.
+    let mtime = time(metadata.modified());
     //  6. Last status change time, likewise.  This is the time of last change
     //   to the file's attributes: owner and group, access mode bits, etc.
+    let ctime = time(metadata.created());
     //  7. Size in bytes, as an integer.
+    let size = metadata.len();
     //  8. File modes, as a string of ten letters or dashes as in ls -l.
+    let kind = if metadata.is_dir() {
+        'd'
+    } else if metadata.is_symlink() {
+        'l'
+    } else {
+        '-'
+    };
+    let write = if metadata.permissions().readonly() { '-' } else { 'w' };
+    let mode = format!("{kind}r{write}xr{write}xr{write}x");
     //  9. An unspecified value, present only for backward compatibility.
     // 10. inode number, as a nonnegative integer.
     // 11. Filesystem device identifier, as an integer or a cons cell of integers.
-    panic!("file-attributes are not yet implemented for non-unix systems");
+    let (inode, dev) = (0, 0);
+    list![file_type, links, uid, gid, atime, mtime, ctime, size, mode, TRUE, inode, dev; cx]
 }
 
-fn get_file_type<'ob>(file: &Path, cx: &'ob Context) -> Object<'ob> {
-    if file.is_dir() {
+/// Format `mode` like the first column of `ls -l`.
+#[cfg(unix)]
+fn mode_string(mode: u32) -> String {
+    let kind = match mode & 0o170000 {
+        0o040000 => 'd',
+        0o120000 => 'l',
+        0o020000 => 'c',
+        0o060000 => 'b',
+        0o010000 => 'p',
+        0o140000 => 's',
+        _ => '-',
+    };
+    let mut out = String::from(kind);
+    // setuid, setgid and sticky replace the execute bit of their class
+    for (shift, special, flag) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let bits = mode >> shift;
+        out.push(if bits & 0o4 == 0 { '-' } else { 'r' });
+        out.push(if bits & 0o2 == 0 { '-' } else { 'w' });
+        out.push(match (mode & special != 0, bits & 0o1 != 0) {
+            (true, true) => flag,
+            (true, false) => flag.to_ascii_uppercase(),
+            (false, true) => 'x',
+            (false, false) => '-',
+        });
+    }
+    out
+}
+
+fn get_file_type<'ob>(file: &Path, metadata: &Metadata, cx: &'ob Context) -> Object<'ob> {
+    if metadata.is_dir() {
         TRUE
-    } else if file.is_symlink() {
-        let target = file.read_link().unwrap();
-        cx.add(target.to_str().unwrap())
+    } else if metadata.is_symlink() {
+        match file.read_link() {
+            Ok(target) => cx.add(target.to_string_lossy().into_owned()),
+            Err(_) => NIL,
+        }
     } else {
         NIL
     }
 }
+
+struct Walk<'a, 'ob> {
+    regexp: Regex,
+    include_directories: bool,
+    follow_symlinks: bool,
+    ignore_errors: bool,
+    predicate: Option<&'a Rto<Function<'ob>>>,
+}
+
+impl Walk<'_, '_> {
+    /// Add the matching files under `dir` to `found`. The contents of
+    /// subdirectories come before the files in `dir` itself.
+    fn walk(
+        &self,
+        dir: &str,
+        found: &mut Vec<String>,
+        env: &mut Rt<Env>,
+        cx: &mut Context,
+    ) -> Result<()> {
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(dir).with_context(|| format!("Opening directory: {dir}"))? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // follows symlinks, so links to directories are directories
+            let is_dir = entry.path().is_dir();
+            entries.push((name, is_dir));
+        }
+        // sort like `file-name-all-completions`, where directories end in a slash
+        entries.sort_by_cached_key(
+            |(name, is_dir)| {
+                if *is_dir {
+                    format!("{name}/")
+                } else {
+                    name.clone()
+                }
+            },
+        );
+
+        let mut files = Vec::new();
+        for (name, is_dir) in entries {
+            let full_name = format!("{dir}/{name}");
+            if !is_dir {
+                if self.regexp.is_match(&name)? {
+                    files.push(full_name);
+                }
+                continue;
+            }
+            if self.follow_symlinks || !Path::new(&full_name).is_symlink() {
+                let descend = match self.predicate {
+                    Some(predicate) => {
+                        let arg = cx.add(full_name.as_str());
+                        !call!(predicate, arg; env, cx)?.is_nil()
+                    }
+                    None => true,
+                };
+                if descend {
+                    let len = found.len();
+                    if let Err(e) = self.walk(&full_name, found, env, cx) {
+                        if !self.ignore_errors {
+                            return Err(e);
+                        }
+                        found.truncate(len);
+                    }
+                }
+            }
+            if self.include_directories && self.regexp.is_match(&name)? {
+                found.push(full_name);
+            }
+        }
+        found.append(&mut files);
+        Ok(())
+    }
+}
+
+/// Return the files under DIR whose names match REGEXP. The search is depth
+/// first, and the files in a subdirectory come before the files in its
+/// parent. If INCLUDE-DIRECTORIES is non-nil, matching directories are
+/// included after their contents.
+///
+/// If PREDICATE is t, errors from unreadable directories are ignored. If it is
+/// a function, it is called with each subdirectory and the subdirectory is
+/// only searched if it returns non-nil. Symbolic links to directories are only
+/// followed if FOLLOW-SYMLINKS is non-nil.
+#[defun(io)]
+fn directory_files_recursively<'ob>(
+    dir: &Rto<Gc<&LispString>>,
+    regexp: &Rto<Gc<&LispString>>,
+    include_directories: Option<()>,
+    predicate: Option<&Rto<Object>>,
+    follow_symlinks: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let dir: &str = dir.untag(cx);
+    let dir = match dir.trim_end_matches('/') {
+        "" if dir.starts_with('/') => String::new(),
+        "" => String::from("."),
+        trimmed => trimmed.to_owned(),
+    };
+    let regexp: &str = regexp.untag(cx);
+    let regexp = Regex::new(&lisp_regex_to_rust(regexp))?;
+    let (ignore_errors, predicate) = match predicate {
+        Some(predicate) if predicate.bind(cx) == TRUE => (true, None),
+        Some(predicate) => (false, Some(predicate.try_as()?)),
+        None => (false, None),
+    };
+    let walk = Walk {
+        regexp,
+        include_directories: include_directories.is_some(),
+        follow_symlinks: follow_symlinks.is_some(),
+        ignore_errors,
+        predicate,
+    };
+    let mut found = Vec::new();
+    walk.walk(&dir, &mut found, env, cx)?;
+    let found: Vec<Object> = found.into_iter().map(|x| cx.add(x)).collect();
+    Ok(slice_into_list(&found, None, cx))
+}
+
+/// Match `ch` against the character class that follows a `[` in a wildcard.
+/// Return whether it matched and the length of the class including the
+/// closing `]`, or `None` if the class is not terminated and the `[` is
+/// literal.
+fn match_class(class: &[char], ch: char) -> Option<(bool, usize)> {
+    let (negated, start) = match class.first() {
+        Some('!' | '^') => (true, 1),
+        _ => (false, 0),
+    };
+    // a `]` at the start of the class is part of it
+    let end = start + 1 + class.get(start + 1..)?.iter().position(|&c| c == ']')?;
+    let set = &class[start..end];
+    let mut matched = false;
+    let mut i = 0;
+    while i < set.len() {
+        if i + 2 < set.len() && set[i + 1] == '-' {
+            matched |= (set[i]..=set[i + 2]).contains(&ch);
+            i += 3;
+        } else {
+            matched |= set[i] == ch;
+            i += 1;
+        }
+    }
+    Some((matched != negated, end + 1))
+}
+
+/// Whether `name` matches the shell wildcard `pattern`. `*` matches any run of
+/// characters, `?` any single character, and `[...]` any character in the set.
+fn wildcard_match(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // the pattern position after the last `*`, and where its match ends
+    let mut backtrack = None;
+    while n < name.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => match match_class(&pattern[p + 1..], name[n]) {
+                Some((matched, len)) => matched.then_some(len + 1),
+                None => (name[n] == '[').then_some(1),
+            },
+            Some(&c) => (c == name[n]).then_some(1),
+            None => None,
+        };
+        match (step, backtrack) {
+            (Some(len), _) => {
+                p += len;
+                n += 1;
+            }
+            // let the last `*` match one more character
+            (None, Some((star, end))) => {
+                p = star;
+                n = end + 1;
+                backtrack = Some((star, end + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+enum FileMatcher {
+    Wildcard(Vec<char>),
+    Regexp(Regex),
+}
+
+impl FileMatcher {
+    fn is_match(&self, name: &str) -> Result<bool> {
+        match self {
+            FileMatcher::Wildcard(pattern) => {
+                Ok(wildcard_match(pattern, &name.chars().collect::<Vec<_>>()))
+            }
+            FileMatcher::Regexp(regexp) => Ok(regexp.is_match(name)?),
+        }
+    }
+}
+
+fn expand_wildcards(
+    pattern: &str,
+    full: bool,
+    regexp: bool,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<Vec<String>> {
+    let (dirpart, nondir) = match pattern.rfind('/') {
+        Some(i) => (Some(&pattern[..=i]), &pattern[i + 1..]),
+        None => (None, pattern),
+    };
+    // the directory part can have wildcards too, and name several directories
+    let dirs = match dirpart {
+        Some(dirpart) if dirpart.contains(['[', '*', '?']) => {
+            let parent = dirpart.trim_end_matches('/');
+            let parents = expand_wildcards(parent, false, regexp, env, cx)?;
+            parents.into_iter().map(|x| Some(format!("{x}/"))).collect()
+        }
+        dirpart => vec![dirpart.map(ToOwned::to_owned)],
+    };
+    let matcher = if regexp {
+        let regexp = lisp_regex_to_rust(&format!("\\`{nondir}\\'"));
+        FileMatcher::Regexp(Regex::new(&regexp)?)
+    } else {
+        FileMatcher::Wildcard(nondir.chars().collect())
+    };
+
+    let mut found = Vec::new();
+    for dir in dirs {
+        let dir_name = expand_file_name(dir.as_deref().unwrap_or("."), None, env, cx)?;
+        // directories that can't be read have no matches
+        let Ok(entries) = std::fs::read_dir(&dir_name) else { continue };
+        for entry in entries {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if !matcher.is_match(&name)? {
+                continue;
+            }
+            let name = match &dir {
+                Some(dir) => format!("{dir}{name}"),
+                None => name,
+            };
+            if full {
+                found.push(expand_file_name(&name, None, env, cx)?);
+            } else {
+                found.push(name);
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// Expand the file name wildcard PATTERN, and return the sorted list of file
+/// names that match it. `*` matches any run of characters, `?` any single
+/// character and `[...]` any character in the set, which can be negated with
+/// `!` or `^`. Like in Emacs, wildcards match hidden files, but `.` and `..`
+/// are never returned.
+///
+/// Matching is done one directory level at a time, so wildcards never match a
+/// `/`, but the directory part of PATTERN can have wildcards too. If REGEXP is
+/// non-nil, each level of PATTERN is a regexp instead of a wildcard.
+///
+/// Relative patterns are expanded in `default-directory` and return relative
+/// file names, unless FULL is non-nil.
+#[defun(io)]
+fn file_expand_wildcards<'ob>(
+    pattern: &str,
+    full: Option<()>,
+    regexp: Option<()>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let found = expand_wildcards(pattern, full.is_some(), regexp.is_some(), env, cx)?;
+    let found: Vec<Object> = found.into_iter().map(|x| cx.add(x)).collect();
+    Ok(slice_into_list(&found, None, cx))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_mode_string() {
+        assert_eq!(mode_string(0o100644), "-rw-r--r--");
+        assert_eq!(mode_string(0o040755), "drwxr-xr-x");
+        assert_eq!(mode_string(0o120777), "lrwxrwxrwx");
+        assert_eq!(mode_string(0o104755), "-rwsr-xr-x");
+        assert_eq!(mode_string(0o041777), "drwxrwxrwt");
+        assert_eq!(mode_string(0o102644), "-rw-r-Sr--");
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        let check = |pattern: &str, name: &str| {
+            let pattern: Vec<_> = pattern.chars().collect();
+            wildcard_match(&pattern, &name.chars().collect::<Vec<_>>())
+        };
+        assert!(check("*.el", "files.el"));
+        assert!(check("*.el", ".el"));
+        assert!(!check("*.el", "files.elc"));
+        assert!(check("a*b*c", "aXbYbZc"));
+        assert!(!check("a*b*c", "aXbYbZ"));
+        assert!(check("?.rs", "a.rs"));
+        assert!(!check("?.rs", "ab.rs"));
+        assert!(check("[abc]x", "bx"));
+        assert!(!check("[abc]x", "dx"));
+        assert!(check("[!abc]x", "dx"));
+        assert!(check("[^abc]x", "dx"));
+        assert!(check("[a-c]", "b"));
+        assert!(!check("[a-c]", "-"));
+        assert!(check("[]]", "]"));
+        assert!(check("[x", "[x"));
+        assert!(check("*", ""));
+        assert!(check("**", "abc"));
+    }
+
+    #[test]
+    #[cfg(not(miri))]
+    fn test_file_expand_wildcards() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        let base = std::env::temp_dir().join(format!("rune-wildcards-{}", std::process::id()));
+        let base = base.to_str().unwrap();
+        std::fs::create_dir_all(format!("{base}/one")).unwrap();
+        std::fs::create_dir_all(format!("{base}/two")).unwrap();
+        for file in ["one/a.el", "one/b.txt", "two/c.el", "two/.hidden.el", "top.el"] {
+            std::fs::write(format!("{base}/{file}"), "").unwrap();
+        }
+        let expand = |pattern: &str, regexp| {
+            let pattern = format!("{base}/{pattern}");
+            let found = expand_wildcards(&pattern, false, regexp, env, cx).unwrap();
+            found
+                .iter()
+                .map(|x| x.strip_prefix(base).unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(expand("*.el", false), ["/top.el"]);
+        assert_eq!(expand("*/*.el", false), ["/one/a.el", "/two/.hidden.el", "/two/c.el"]);
+        assert_eq!(expand("t?o/[a-c].el", false), ["/two/c.el"]);
+        assert_eq!(expand("*/[!a-c]*", false), ["/two/.hidden.el"]);
+        assert_eq!(expand("one/.*\\.el", true), ["/one/a.el"]);
+        assert!(expand("missing/*", false).is_empty());
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    #[cfg(not(miri))]
+    fn test_directory_files_recursively() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        let base = std::env::temp_dir().join(format!("rune-dired-{}", std::process::id()));
+        let base = base.to_str().unwrap();
+        std::fs::create_dir_all(format!("{base}/sub/deeper")).unwrap();
+        std::fs::write(format!("{base}/b.el"), "").unwrap();
+        std::fs::write(format!("{base}/a.txt"), "text").unwrap();
+        std::fs::write(format!("{base}/sub/c.el"), "").unwrap();
+        std::fs::write(format!("{base}/sub/deeper/d.el"), "").unwrap();
+
+        let dir: Gc<&LispString> = cx.add_as(base);
+        root!(dir, cx);
+        let regexp: Gc<&LispString> = cx.add_as("\\.el\\'");
+        root!(regexp, cx);
+        let files = directory_files_recursively(dir, regexp, None, None, None, env, cx).unwrap();
+        let expect = list![
+            format!("{base}/sub/deeper/d.el"),
+            format!("{base}/sub/c.el"),
+            format!("{base}/b.el");
+            cx
+        ];
+        assert_eq!(files, expect);
+
+        let regexp: Gc<&LispString> = cx.add_as("e");
+        root!(regexp, cx);
+        let files = directory_files_recursively(dir, regexp, Some(()), None, None, env, cx);
+        let expect = list![
+            format!("{base}/sub/deeper/d.el"),
+            format!("{base}/sub/deeper"),
+            format!("{base}/sub/c.el"),
+            format!("{base}/b.el");
+            cx
+        ];
+        assert_eq!(files.unwrap(), expect);
+
+        let attrs = file_attributes(&format!("{base}/a.txt"), None, env, cx).unwrap();
+        let attrs: Vec<_> = attrs.as_list().unwrap().map(|x| x.unwrap()).collect();
+        assert_eq!(attrs.len(), 12);
+        assert_eq!(attrs[0], NIL);
+        assert_eq!(attrs[7], 4);
+        let attrs = file_attributes(&format!("{base}/sub"), None, env, cx).unwrap();
+        assert_eq!(attrs.as_list().unwrap().next().unwrap().unwrap(), TRUE);
+        let missing = file_attributes(&format!("{base}/missing"), None, env, cx).unwrap();
+        assert_eq!(missing, NIL);
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+}
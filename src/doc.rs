@@ -0,0 +1,148 @@
+//! Documentation strings.
+//!
+//! Variable docs are stored in the `variable-documentation` property of the
+//! symbol. Function docs live in the function itself: a leading string in the
+//! body of a lambda, or the docstring slot of a [`ByteFn`]. `defalias` can
+//! override that with the `function-documentation` property.
+use crate::core::{
+    cons::Cons,
+    env::{sym, Env},
+    gc::{Context, Rt, Rto},
+    object::{FunctionType, Object, ObjectType, Symbol, NIL},
+};
+use anyhow::{bail, Result};
+use rune_core::macros::root;
+use rune_macros::defun;
+
+defsym!(VARIABLE_DOCUMENTATION);
+defsym!(FUNCTION_DOCUMENTATION);
+
+/// The docstring at the start of a function `body`. A body that is only a
+/// string returns it rather than being documented by it.
+fn body_doc(body: Object) -> Option<Object> {
+    let ObjectType::Cons(cons) = body.untag() else { return None };
+    match cons.car().untag() {
+        ObjectType::String(_) if cons.cdr() != NIL => Some(cons.car()),
+        _ => None,
+    }
+}
+
+/// The docstring of a lambda or closure form.
+fn lambda_doc(form: &Cons) -> Option<Object> {
+    // (lambda ARGS . BODY)
+    let ObjectType::Cons(rest) = form.cdr().untag() else { return None };
+    match form.car().untag() {
+        ObjectType::Symbol(sym::LAMBDA) => body_doc(rest.cdr()),
+        // (closure ENV ARGS . BODY)
+        ObjectType::Symbol(sym::CLOSURE) => {
+            let ObjectType::Cons(rest) = rest.cdr().untag() else { return None };
+            body_doc(rest.cdr())
+        }
+        // (macro . FUNCTION)
+        ObjectType::Symbol(sym::MACRO) => match form.cdr().untag() {
+            ObjectType::Cons(func) => lambda_doc(func),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[defun]
+fn documentation<'ob>(
+    function: &Rto<Object>,
+    // We don't implement `substitute-command-keys`, so docs are always raw
+    _raw: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    if let ObjectType::Symbol(symbol) = function.untag(cx) {
+        let doc = crate::data::get(symbol, sym::FUNCTION_DOCUMENTATION, env, cx);
+        if doc != NIL {
+            root!(doc, cx);
+            return eval_doc(doc, env, cx);
+        }
+    }
+    let func = match function.untag(cx) {
+        ObjectType::Symbol(symbol) => match symbol.follow_indirect(cx) {
+            Some(func) => func,
+            None => bail!("Symbol's function definition is void: {symbol}"),
+        },
+        _ => function.bind(cx).try_into()?,
+    };
+    let doc = match func.untag() {
+        FunctionType::ByteFn(func) => func.doc().map(|doc| cx.add(doc)),
+        FunctionType::Cons(form) => lambda_doc(form),
+        FunctionType::SubrFn(_) | FunctionType::Symbol(_) => None,
+    };
+    Ok(doc.unwrap_or(NIL))
+}
+
+#[defun]
+fn documentation_property<'ob>(
+    symbol: Symbol,
+    prop: Symbol,
+    _raw: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let doc = crate::data::get(symbol, prop, env, cx);
+    root!(doc, cx);
+    eval_doc(doc, env, cx)
+}
+
+/// Documentation properties that are not strings are forms that compute the
+/// docstring.
+fn eval_doc<'ob>(
+    doc: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    match doc.untag(cx) {
+        ObjectType::String(_) | ObjectType::NIL => Ok(doc.bind(cx)),
+        _ => crate::interpreter::eval(doc, None, env, cx),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::interpreter::eval_to_string;
+
+    #[test]
+    fn test_documentation() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        let lambda = "(equal (documentation (lambda (x) \"add one\" (1+ x))) \"add one\")";
+        assert_eq!(eval_to_string(lambda, env, cx).unwrap(), "t");
+        let not_doc = "(documentation (lambda () \"not a doc\"))";
+        assert_eq!(eval_to_string(not_doc, env, cx).unwrap(), "nil");
+        let doc = "(progn (defalias 'doc-test-fn #'(lambda () \"doc\" nil))
+                          (equal (documentation 'doc-test-fn) \"doc\"))";
+        assert_eq!(eval_to_string(doc, env, cx).unwrap(), "t");
+        let alias = "(progn (defalias 'doc-test-alias #'car \"first\")
+                            (equal (documentation 'doc-test-alias) \"first\"))";
+        assert_eq!(eval_to_string(alias, env, cx).unwrap(), "t");
+        assert_eq!(eval_to_string("(documentation #'car)", env, cx).unwrap(), "nil");
+    }
+
+    #[test]
+    fn test_documentation_property() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        let var = "(progn (defvar doc-test-var 1 \"A variable\")
+                          (equal (documentation-property 'doc-test-var 'variable-documentation)
+                                 \"A variable\"))";
+        assert_eq!(eval_to_string(var, env, cx).unwrap(), "t");
+        let constant = "(progn (defconst doc-test-const 1 \"A constant\")
+                               (equal (get 'doc-test-const 'variable-documentation)
+                                      \"A constant\"))";
+        assert_eq!(eval_to_string(constant, env, cx).unwrap(), "t");
+        let form = "(progn (put 'doc-test-form 'variable-documentation '(concat \"a\" \"b\"))
+                           (equal (documentation-property 'doc-test-form 'variable-documentation)
+                                  \"ab\"))";
+        assert_eq!(eval_to_string(form, env, cx).unwrap(), "t");
+    }
+}
@@ -1,24 +1,188 @@
 //! Buffer editing utilities.
 use crate::core::{
-    env::{ArgSlice, Env},
+    env::{sym, ArgSlice, Env},
     gc::{Context, Rt},
-    object::{Object, ObjectType},
+    object::{format_exponent, format_general, Object, ObjectType, NIL},
 };
-use anyhow::{bail, ensure, Result};
+use anyhow::{anyhow, bail, ensure, Result};
 use rune_macros::defun;
-use std::{fmt::Write as _, io::Write};
 
+/// Show a message formatted like `format`. It goes to the
+/// [`MessageSink`](crate::api::MessageSink) and is logged in `*Messages*`. A
+/// nil `format_string` clears the message instead.
 #[defun]
-fn message(format_string: &str, args: &[Object]) -> Result<String> {
-    let message = format(format_string, args)?;
-    println!("MESSAGE: {message}");
-    std::io::stdout().flush()?;
-    Ok(message)
+fn message<'ob>(
+    format_string: Option<&str>,
+    args: ArgSlice,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let sink = crate::api::message_sink();
+    let Some(format_string) = format_string else {
+        sink.clear();
+        return Ok(NIL);
+    };
+    let message = format(format_string, Rt::bind_slice(env.stack.arg_slice(args), cx))?;
+    log_message(&message, env, cx)?;
+    sink.message(&message);
+    Ok(cx.add(message))
+}
+
+defvar!(MESSAGE_LOG_MAX, 1000);
+
+/// Append `message` to the end of the `*Messages*` buffer, unless
+/// `message-log-max` is nil.
+fn log_message(message: &str, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    if env.vars.get(sym::MESSAGE_LOG_MAX).is_some_and(|x| x.bind(cx) == NIL) {
+        return Ok(());
+    }
+    // TODO: delete old lines when there are more than `message-log-max`
+    let buffer = crate::buffer::get_buffer_create(cx.add("*Messages*"), Some(NIL), cx)?;
+    let ObjectType::Buffer(buffer) = buffer.untag() else { unreachable!() };
+    env.with_buffer_mut(Some(buffer), |buffer| {
+        let end = buffer.text.len_chars();
+        buffer.text.set_cursor(end);
+        buffer.text.insert(message);
+        buffer.text.insert_char('\n');
+    });
+    Ok(())
 }
 
 defvar!(MESSAGE_NAME);
 defvar!(MESSAGE_TYPE, "new message");
 
+/// A conversion in a format string, like `%-8.3f`.
+struct Spec<'a> {
+    /// `-` pads on the right, `0` pads numbers with zeros, `+` or a space go
+    /// before non-negative numbers, and `#` picks the alternate form
+    flags: &'a str,
+    width: usize,
+    precision: Option<usize>,
+    conversion: char,
+}
+
+impl<'a> Spec<'a> {
+    /// Parse the spec that follows a `%`, and return it with its length.
+    fn parse(text: &'a str) -> Result<(Self, usize)> {
+        let digits = |text: &str| text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+        let flags = text.find(|c| !"-0+ #".contains(c)).unwrap_or(text.len());
+        let (flags, rest) = text.split_at(flags);
+        let (width, mut rest) = rest.split_at(digits(rest));
+        let mut precision = None;
+        if let Some(after) = rest.strip_prefix('.') {
+            let (digits, after) = after.split_at(digits(after));
+            precision = Some(digits.parse().unwrap_or(0));
+            rest = after;
+        }
+        let Some(conversion) = rest.chars().next() else {
+            bail!("Format string ends in middle of format specifier")
+        };
+        let len = text.len() - rest.len() + conversion.len_utf8();
+        let width = width.parse().unwrap_or(0);
+        Ok((Spec { flags, width, precision, conversion }, len))
+    }
+
+    fn flag(&self, flag: char) -> bool {
+        self.flags.contains(flag)
+    }
+
+    /// The sign to print before a number.
+    fn sign(&self, negative: bool) -> &'static str {
+        if negative {
+            "-"
+        } else if self.flag('+') {
+            "+"
+        } else if self.flag(' ') {
+            " "
+        } else {
+            ""
+        }
+    }
+
+    /// Format `object` with this spec.
+    fn format(&self, object: Object) -> Result<String> {
+        let float = |object: Object| match object.untag() {
+            ObjectType::Int(i) => Ok(i as f64),
+            ObjectType::Float(f) => Ok(**f),
+            _ => Err(anyhow!("Format specifier doesn't match argument type")),
+        };
+        let int = |object: Object| match object.untag() {
+            ObjectType::Int(i) => Ok(i),
+            ObjectType::Float(f) => Ok(f.trunc() as i64),
+            _ => Err(anyhow!("Format specifier doesn't match argument type")),
+        };
+        // The sign and the digits of a number
+        let (sign, body) = match self.conversion {
+            's' | 'S' => {
+                let print = match object.untag() {
+                    ObjectType::String(string) if self.conversion == 's' => string.to_string(),
+                    obj => obj.to_string(),
+                };
+                let print = match self.precision {
+                    Some(precision) => print.chars().take(precision).collect(),
+                    None => print,
+                };
+                return Ok(self.pad("", print, false));
+            }
+            'c' => {
+                let c = u32::try_from(int(object)?).ok().and_then(char::from_u32);
+                let Some(c) = c else { bail!("Invalid character: {object}") };
+                return Ok(self.pad("", c.to_string(), false));
+            }
+            'd' => {
+                let int = int(object)?;
+                (self.sign(int < 0), int.unsigned_abs().to_string())
+            }
+            'o' | 'x' | 'X' => {
+                let int = int(object)?;
+                let abs = int.unsigned_abs();
+                let body = match (self.conversion, self.flag('#')) {
+                    ('o', false) => format!("{abs:o}"),
+                    ('o', true) => format!("0{abs:o}"),
+                    ('x', false) => format!("{abs:x}"),
+                    ('x', true) => format!("0x{abs:x}"),
+                    (_, false) => format!("{abs:X}"),
+                    (_, true) => format!("0X{abs:X}"),
+                };
+                (self.sign(int < 0), body)
+            }
+            'f' | 'e' | 'g' => {
+                let float = float(object)?;
+                let abs = float.abs();
+                let precision = self.precision.unwrap_or(6);
+                let body = match self.conversion {
+                    _ if float.is_nan() => "nan".to_owned(),
+                    _ if float.is_infinite() => "inf".to_owned(),
+                    'f' => format!("{abs:.precision$}"),
+                    'e' => format_exponent(abs, precision),
+                    _ => format_general(abs, precision, self.flag('#')),
+                };
+                (self.sign(float.is_sign_negative() && !float.is_nan()), body)
+            }
+            c => bail!("Invalid format operation %{c}"),
+        };
+        Ok(self.pad(sign, body, self.flag('0')))
+    }
+
+    /// Pad `sign` and `body` to the width of the spec. With `zero`, zeros go
+    /// between the sign and the body.
+    fn pad(&self, sign: &str, body: String, zero: bool) -> String {
+        let len = sign.chars().count() + body.chars().count();
+        let padding = self.width.saturating_sub(len);
+        if self.flag('-') {
+            format!("{sign}{body}{}", " ".repeat(padding))
+        } else if zero {
+            format!("{sign}{}{body}", "0".repeat(padding))
+        } else {
+            format!("{}{sign}{body}", " ".repeat(padding))
+        }
+    }
+}
+
+/// Format a string out of a format-string and arguments. Conversions are
+/// written `%[FLAGS][WIDTH][.PRECISION]CHARACTER`, where CHARACTER is one of
+/// `s` and `S` for any object, `d`, `o`, `x`, `X` and `c` for integers, and
+/// `f`, `e` and `g` for numbers. `%%` is a single `%`.
 #[defun]
 fn format(string: &str, objects: &[Object]) -> Result<String> {
     let mut result = String::new();
@@ -45,17 +209,15 @@ fn format(string: &str, objects: &[Object]) -> Result<String> {
         // "%%" inserts a single "%" in the output
         if *specifier == b'%' {
             result.push('%');
-        } else {
-            // TODO: currently handles all format types the same. Need to check the modifier characters.
-            let Some(val) = arguments.next() else {
-                bail!("Not enough arguments for format string")
-            };
-            match val.untag() {
-                ObjectType::String(string) => write!(result, "{string}")?,
-                obj => write!(result, "{obj}")?,
-            }
+            remaining = &remaining[start + 2..];
+            continue;
         }
-        remaining = &remaining[start + 2..];
+        let (spec, len) = Spec::parse(&remaining[start + 1..])?;
+        let Some(val) = arguments.next() else {
+            bail!("Not enough arguments for format string")
+        };
+        result += &spec.format(*val)?;
+        remaining = &remaining[start + 1 + len..];
     }
     result += remaining;
     ensure!(arguments.next().is_none(), "Too many arguments for format string");
@@ -184,6 +346,27 @@ mod test {
         assert!(format("`%s' %s%s%s", &[0.into(), 1.into(), 2.into(), 3.into()]).is_ok());
     }
 
+    #[test]
+    fn test_format_spec() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let string = cx.add("abc");
+        assert_eq!(&format("%S %s", &[string, string]).unwrap(), "\"abc\" abc");
+        assert_eq!(&format("%-5s|%.2s", &[string, string]).unwrap(), "abc  |ab");
+        let ints = [(-42).into(), 3.into(), 3.into()];
+        assert_eq!(&format("%05d %+d % d", &ints).unwrap(), "-0042 +3  3");
+        let ints = [255.into(), 255.into(), 8.into(), 97.into()];
+        assert_eq!(&format("%x %#X %o %c", &ints).unwrap(), "ff 0XFF 10 a");
+        let float = cx.add(1234.5678);
+        let floats = [float, float, float];
+        assert_eq!(&format("%.2f|%e|%g", &floats).unwrap(), "1234.57|1.234568e+03|1234.57");
+        assert_eq!(&format("%8.3g|%g", &[float, cx.add(0.00001)]).unwrap(), "1.23e+03|1e-05");
+        assert_eq!(&format("%f", &[cx.add(f64::NEG_INFINITY)]).unwrap(), "-inf");
+        assert!(format("%d", &[string]).is_err());
+        assert!(format("%q", &[string]).is_err());
+        assert!(format("%-", &[string]).is_err());
+    }
+
     #[test]
     fn test_insert() {
         let roots = &RootSet::default();
@@ -217,4 +400,20 @@ mod test {
         delete_region(2, 4, env).unwrap();
         assert_eq!(env.current_buffer.as_ref().unwrap(), "hlo world");
     }
+
+    #[test]
+    fn test_message_log() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        env.stack.push(2);
+        let message = message(Some("log-test %s"), ArgSlice::new(1), env, cx).unwrap();
+        assert!(matches!(message.untag(), ObjectType::String(s) if s == "log-test 2"));
+        let buffer = get_buffer_create(cx.add("*Messages*"), Some(NIL), cx).unwrap();
+        let ObjectType::Buffer(buffer) = buffer.untag() else { unreachable!() };
+        let logged =
+            env.with_buffer_mut(Some(buffer), |b| b.text.as_str().contains("log-test 2\n"));
+        assert_eq!(logged, Some(true));
+    }
 }
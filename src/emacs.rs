@@ -1,13 +1,183 @@
 //! The Emacs environment and runtime.
+use crate::core::{
+    env::{intern, sym, Env},
+    gc::{Context, Rt},
+    object::{Object, ObjectType, NIL},
+};
+use crate::fns::slice_into_list;
+use crate::timefns::set_time_zone_rule;
+use anyhow::{ensure, Result};
 use rune_macros::defun;
 
-#[defun]
+#[defun(io)]
 fn kill_emacs() {}
 
+/// The value of `system-type` for the platform rune was built for.
+const SYSTEM_TYPE_NAME: &str = if cfg!(target_os = "linux") {
+    "gnu/linux"
+} else if cfg!(target_os = "macos") {
+    "darwin"
+} else if cfg!(windows) {
+    "windows-nt"
+} else if cfg!(any(target_os = "freebsd", target_os = "netbsd", target_os = "openbsd")) {
+    "berkeley-unix"
+} else {
+    "unknown"
+};
+
+/// The environment of the process as a list of "NAME=VALUE" strings.
+fn environment_list<'ob>(cx: &'ob Context) -> Object<'ob> {
+    let vars: Vec<Object> = std::env::vars_os()
+        .map(|(name, value)| {
+            cx.add(format!("{}={}", name.to_string_lossy(), value.to_string_lossy()))
+        })
+        .collect();
+    slice_into_list(&vars, None, cx)
+}
+
+/// Set the variables that describe the running process. This is called at
+/// startup after the builtin variables are initialized.
+pub(crate) fn init_process_vars(env: &mut Rt<Env>, cx: &Context) {
+    // Two copies, so changing one list doesn't change the other
+    env.vars.insert(sym::PROCESS_ENVIRONMENT, environment_list(cx));
+    env.vars.insert(sym::INITIAL_ENVIRONMENT, environment_list(cx));
+    env.vars.insert(sym::SYSTEM_TYPE, Object::from(intern(SYSTEM_TYPE_NAME, cx)));
+}
+
+/// The name of the variable set by an entry of `process-environment`. An entry
+/// with no "=" removes the variable.
+fn entry_name(entry: &str) -> &str {
+    entry.split_once('=').map_or(entry, |x| x.0)
+}
+
+/// Get the value of environment variable VARIABLE from ENV, or
+/// `process-environment` if ENV is nil. Value is nil if VARIABLE is undefined.
+#[defun]
+fn getenv_internal(
+    variable: &str,
+    environment: Option<Object>,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<Option<String>> {
+    let environment = match environment {
+        Some(environment) => environment,
+        None => env.vars.get(sym::PROCESS_ENVIRONMENT).map_or(NIL, |x| x.bind(cx)),
+    };
+    for entry in environment.as_list()? {
+        if let ObjectType::String(entry) = entry?.untag() {
+            if entry_name(entry) == variable {
+                return Ok(entry.split_once('=').map(|x| x.1.to_owned()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Get the value of environment variable VARIABLE, or nil if it is undefined.
+/// FRAME is ignored.
+#[defun]
+fn getenv(
+    variable: &str,
+    _frame: Option<Object>,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<Option<String>> {
+    getenv_internal(variable, None, env, cx)
+}
+
+/// Set environment variable VARIABLE to VALUE in `process-environment`. If
+/// VALUE is nil the variable is removed. Return VALUE. Setting `TZ` also
+/// calls `set-time-zone-rule`. SUBSTITUTE-ENV-VARS is not supported yet.
+#[defun(io)]
+fn setenv(
+    variable: &str,
+    value: Option<&str>,
+    substitute_env_vars: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<Option<String>> {
+    ensure!(substitute_env_vars.is_none(), "setenv: SUBSTITUTE-ENV-VARS is not supported");
+    ensure!(!variable.contains('='), "Environment variable name `{variable}' contains `='");
+    if variable == "TZ" {
+        set_time_zone_rule(value.map(|x| cx.add(x)))?;
+    }
+    let new_entry = match value {
+        Some(value) => cx.add(format!("{variable}={value}")),
+        None => cx.add(variable),
+    };
+    let current = env.vars.get(sym::PROCESS_ENVIRONMENT).map_or(NIL, |x| x.bind(cx));
+    let mut entries = Vec::new();
+    let mut found = false;
+    for entry in current.as_list()? {
+        let entry = entry?;
+        match entry.untag() {
+            ObjectType::String(name) if entry_name(name) == variable => {
+                if !found {
+                    entries.push(new_entry);
+                }
+                found = true;
+            }
+            _ => entries.push(entry),
+        }
+    }
+    if !found {
+        entries.insert(0, new_entry);
+    }
+    env.set_var(sym::PROCESS_ENVIRONMENT, slice_into_list(&entries, None, cx))?;
+    Ok(value.map(ToOwned::to_owned))
+}
+
+/// Return the process ID of rune, as an integer.
+#[defun]
+fn getpid() -> i64 {
+    i64::from(std::process::id())
+}
+
 defvar!(EMACS_VERSION, "27.1");
-defvar!(SYSTEM_TYPE, "darwin");
+defvar!(RUNE_VERSION, env!("CARGO_PKG_VERSION"));
+defvar!(SYSTEM_TYPE);
+defvar!(PROCESS_ENVIRONMENT);
+defvar!(INITIAL_ENVIRONMENT);
 defvar!(DUMP_MODE);
 defvar!(COMMAND_LINE_ARGS, list![""]);
+defvar!(COMMAND_LINE_ARGS_LEFT);
 defvar!(DEFAULT_DIRECTORY, "");
 defvar_bool!(NONINTERACTIVE, true);
 defvar!(AFTER_INIT_TIME);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use rune_core::macros::root;
+
+    #[test]
+    fn test_environment() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        init_process_vars(env, cx);
+        assert_eq!(getenv("PATH", None, env, cx).unwrap(), std::env::var("PATH").ok());
+        assert_eq!(getenv("RUNE_TEST_UNSET", None, env, cx).unwrap(), None);
+
+        setenv("RUNE_TEST_VAR", Some("1"), None, env, cx).unwrap();
+        assert_eq!(getenv("RUNE_TEST_VAR", None, env, cx).unwrap().as_deref(), Some("1"));
+        setenv("RUNE_TEST_VAR", Some("2=3"), None, env, cx).unwrap();
+        assert_eq!(getenv("RUNE_TEST_VAR", None, env, cx).unwrap().as_deref(), Some("2=3"));
+        setenv("RUNE_TEST_VAR", None, None, env, cx).unwrap();
+        assert_eq!(getenv("RUNE_TEST_VAR", None, env, cx).unwrap(), None);
+        assert!(setenv("A=B", Some("1"), None, env, cx).is_err());
+        assert!(setenv("RUNE_TEST_VAR", Some("$HOME"), Some(()), env, cx).is_err());
+        // an unsupported time zone rule leaves the environment unchanged
+        let tz = getenv("TZ", None, env, cx).unwrap();
+        assert!(setenv("TZ", Some("Invalid/Zone"), None, env, cx).is_err());
+        assert_eq!(getenv("TZ", None, env, cx).unwrap(), tz);
+
+        // the initial environment is not changed
+        let initial = env.vars.get(sym::INITIAL_ENVIRONMENT).unwrap().bind(cx);
+        assert_eq!(getenv_internal("RUNE_TEST_VAR", Some(initial), env, cx).unwrap(), None);
+        let system_type = env.vars.get(sym::SYSTEM_TYPE).unwrap().bind(cx);
+        assert_eq!(system_type, intern(SYSTEM_TYPE_NAME, cx));
+        assert_eq!(getpid(), i64::from(std::process::id()));
+    }
+}
@@ -0,0 +1,497 @@
+//! Runtime support for ERT test suites.
+//!
+//! Test suites written for ERT, the Emacs Lisp Regression Testing library, can
+//! run without loading `ert.el`. `ert-deftest` registers a test, and the
+//! assertions `should`, `should-not` and `should-error` signal
+//! `ert-test-failed` with the form that did not hold. When the asserted form is
+//! a function call, its arguments are evaluated first so that the failure shows
+//! the values the function was called with, like ERT does.
+//! `ert-run-tests-batch` runs the registered tests and reports the results with
+//! `message`.
+use crate::core::{
+    cons::Cons,
+    env::{sym, CallFrame, Env},
+    error::ArgError,
+    gc::{Context, Rt, Rto},
+    object::{Function, FunctionType, Object, ObjectType, Symbol, NIL},
+};
+use crate::eval::{EvalError, EvalResult};
+use crate::interpreter::{special_form_p, Interpreter, SpecialForm};
+use crate::rooted_iter;
+use anyhow::{bail, Result};
+use fallible_streaming_iterator::FallibleStreamingIterator;
+use fancy_regex::Regex;
+use rune_core::macros::{bail_err, call, list, rebind, root};
+use rune_macros::defun;
+use std::time::Instant;
+
+defsym!(ERT_DEFTEST);
+defsym!(SHOULD);
+defsym!(SHOULD_NOT);
+defsym!(SHOULD_ERROR);
+defsym!(SKIP_UNLESS);
+defsym!(ERT_TEST_FAILED);
+defsym!(ERT_TEST_SKIPPED);
+defsym!(ERT__TEST);
+defsym!(ERT__EXPECTED_RESULT);
+defsym!(KW_FORM);
+defsym!(KW_VALUE);
+defsym!(KW_CONDITION);
+defsym!(KW_FAIL_REASON);
+defsym!(KW_TYPE);
+defsym!(KW_EXCLUDE_SUBTYPES);
+defsym!(KW_EXPECTED_RESULT);
+defsym!(KW_TAGS);
+defsym!(KW_PASSED);
+defsym!(KW_FAILED);
+
+// The names of the registered tests, most recently defined first
+defvar!(ERT__TESTS);
+
+/// The special forms defined by ERT. The interpreter registers them before it
+/// looks up its first special form.
+pub(crate) const SPECIAL_FORMS: [(Symbol<'static>, SpecialForm); 5] = [
+    (sym::ERT_DEFTEST, ert_deftest),
+    (sym::SHOULD, should),
+    (sym::SHOULD_NOT, should_not),
+    (sym::SHOULD_ERROR, should_error),
+    (sym::SKIP_UNLESS, skip_unless),
+];
+
+fn ert_deftest<'ob>(
+    interp: &mut Interpreter<'_, '_>,
+    forms: &Rto<Object>,
+    cx: &'ob mut Context,
+) -> EvalResult<'ob> {
+    // (ert-deftest NAME () [DOCSTRING] [:KEYWORD VALUE]... BODY...)
+    let ObjectType::Cons(def) = forms.untag(cx) else {
+        bail_err!(ArgError::new(2, 0, "ert-deftest"))
+    };
+    let name: Symbol = def.car().try_into()?;
+    let ObjectType::Cons(def) = def.cdr().untag() else {
+        bail_err!(ArgError::new(2, 1, "ert-deftest"))
+    };
+    if def.car() != NIL {
+        bail_err!("Test {name} can't take arguments: {}", def.car());
+    }
+    let mut body = def.cdr();
+    let mut expected: Object = sym::KW_PASSED.into();
+    while let ObjectType::Cons(cons) = body.untag() {
+        let ObjectType::Cons(next) = cons.cdr().untag() else { break };
+        match cons.car().untag() {
+            ObjectType::String(_) => body = cons.cdr(),
+            ObjectType::Symbol(sym::KW_EXPECTED_RESULT) => {
+                expected = next.car();
+                body = next.cdr();
+            }
+            ObjectType::Symbol(sym::KW_TAGS) => body = next.cdr(),
+            _ => break,
+        }
+    }
+    let lambda = Cons::new(sym::LAMBDA, Cons::new(NIL, body, cx), cx);
+    let function = list![sym::FUNCTION, lambda; cx];
+    root!(name, cx);
+    root!(expected, cx);
+    root!(function, cx);
+    let test = interp.eval_form(function, cx)?;
+    root!(test, cx);
+    let expected = rebind!(interp.eval_form(expected, cx)?);
+    let env = interp.env();
+    let name = name.bind(cx);
+    env.set_prop(name, sym::ERT__TEST, test.bind(cx));
+    env.set_prop(name, sym::ERT__EXPECTED_RESULT, expected);
+    let tests = env.vars.get(sym::ERT__TESTS).map_or(NIL, |x| x.bind(cx));
+    if !tests.as_list()?.any(|x| x.is_ok_and(|x| x == name)) {
+        env.set_var(sym::ERT__TESTS, Cons::new(name, tests, cx).into())?;
+    }
+    Ok(name.into())
+}
+
+/// Evaluate the asserted `form`. When it calls a function the arguments are
+/// evaluated here, and `shown` is set to the call with their values instead of
+/// to the form itself.
+fn eval_assertion<'ob>(
+    interp: &mut Interpreter<'_, '_>,
+    form: &Rto<Object>,
+    shown: &mut Rto<Object>,
+    cx: &'ob mut Context,
+) -> EvalResult<'ob> {
+    shown.set(form.bind(cx));
+    let ObjectType::Cons(call) = form.untag(cx) else { return interp.eval_form(form, cx) };
+    let ObjectType::Symbol(head) = call.car().untag() else { return interp.eval_form(form, cx) };
+    let func = match head.follow_indirect(cx) {
+        _ if special_form_p(head.into()) => None,
+        // Macros have to see the forms, not their values
+        Some(func) => match func.untag() {
+            FunctionType::Cons(cons) if cons.car() == sym::MACRO || cons.car() == sym::AUTOLOAD => {
+                None
+            }
+            _ => Some(func),
+        },
+        None => None,
+    };
+    let Some(func) = func else { return interp.eval_form(form, cx) };
+    let name = head.name().to_owned();
+    root!(func, cx);
+    root!(head, cx);
+    rooted_iter!(args, call.cdr(), cx);
+    root!(values, new(Vec), cx);
+    while let Some(arg) = args.next()? {
+        let value = interp.eval_form(arg, cx)?;
+        values.push(value);
+    }
+    let args = crate::fns::slice_into_list(Rt::bind_slice(values, cx), None, cx);
+    let call: Object = Cons::new(head.bind(cx), args, cx).into();
+    shown.set(call);
+    let frame = &mut CallFrame::new(interp.env());
+    frame.push_arg_slice(Rt::bind_slice(values, cx));
+    func.call(frame, Some(&name), cx)
+}
+
+/// Signal `error` with the plist `info`, which starts with the assertion.
+fn signal(error: Symbol, info: &[Object], env: &mut Rt<Env>, cx: &Context) -> EvalError {
+    let info = crate::fns::slice_into_list(info, None, cx);
+    EvalError::signal(error.into(), list![info; cx], env)
+}
+
+fn should<'ob>(
+    interp: &mut Interpreter<'_, '_>,
+    forms: &Rto<Object>,
+    cx: &'ob mut Context,
+) -> EvalResult<'ob> {
+    check(sym::SHOULD, interp, forms, cx)
+}
+
+fn should_not<'ob>(
+    interp: &mut Interpreter<'_, '_>,
+    forms: &Rto<Object>,
+    cx: &'ob mut Context,
+) -> EvalResult<'ob> {
+    check(sym::SHOULD_NOT, interp, forms, cx)
+}
+
+fn skip_unless<'ob>(
+    interp: &mut Interpreter<'_, '_>,
+    forms: &Rto<Object>,
+    cx: &'ob mut Context,
+) -> EvalResult<'ob> {
+    check(sym::SKIP_UNLESS, interp, forms, cx)
+}
+
+/// `(should FORM)`, `(should-not FORM)` and `(skip-unless FORM)`.
+fn check<'ob>(
+    assertion: Symbol,
+    interp: &mut Interpreter<'_, '_>,
+    forms: &Rto<Object>,
+    cx: &'ob mut Context,
+) -> EvalResult<'ob> {
+    let len = forms.bind(cx).as_list()?.len()? as u16;
+    if len != 1 {
+        bail_err!(ArgError::new(1, len, assertion.name()))
+    }
+    let form = forms.bind(cx).as_cons().car();
+    root!(form, cx);
+    root!(shown, NIL, cx);
+    let value = rebind!(eval_assertion(interp, form, shown, cx)?);
+    let holds = if assertion == sym::SHOULD_NOT { value == NIL } else { value != NIL };
+    if holds {
+        return Ok(value);
+    }
+    let error =
+        if assertion == sym::SKIP_UNLESS { sym::ERT_TEST_SKIPPED } else { sym::ERT_TEST_FAILED };
+    let original: Object = Cons::new(assertion, forms.bind(cx), cx).into();
+    let info = [original, sym::KW_FORM.into(), shown.bind(cx), sym::KW_VALUE.into(), value];
+    Err(signal(error, &info, interp.env(), cx))
+}
+
+/// The error signaled by `err` as `(ERROR-SYMBOL . DATA)`, or `None` if it is a
+/// `throw`.
+fn error_condition<'ob>(err: &EvalError, env: &Rt<Env>, cx: &'ob Context) -> Option<Object<'ob>> {
+    let (symbol, data) = err.condition(env, cx)?;
+    Some(Cons::new(symbol, data, cx).into())
+}
+
+/// Whether an error signaled with `symbol` has one of `types`, which is a
+/// symbol or a list of symbols. Unless `exact`, the conditions the error
+/// inherits with `define-error` also match.
+fn has_type(
+    symbol: Object,
+    types: Object,
+    exact: bool,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    if !exact {
+        return crate::eval::handles(types, symbol, env, cx);
+    }
+    match types.untag() {
+        ObjectType::Cons(types) => {
+            for x in types {
+                if x? == symbol {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        }
+        _ => Ok(types == symbol),
+    }
+}
+
+fn should_error<'ob>(
+    interp: &mut Interpreter<'_, '_>,
+    forms: &Rto<Object>,
+    cx: &'ob mut Context,
+) -> EvalResult<'ob> {
+    // (should-error FORM [:type TYPE] [:exclude-subtypes BOOL])
+    rooted_iter!(args, forms, cx);
+    let Some(form) = args.next()? else { bail_err!(ArgError::new(1, 0, "should-error")) };
+    let form = form.bind(cx);
+    root!(form, cx);
+    root!(types, NIL, cx);
+    root!(exclude_subtypes, NIL, cx);
+    while let Some(keyword) = args.next()? {
+        let slot = match keyword.untag(cx) {
+            ObjectType::Symbol(sym::KW_TYPE) => &mut *types,
+            ObjectType::Symbol(sym::KW_EXCLUDE_SUBTYPES) => &mut *exclude_subtypes,
+            other => bail_err!("Invalid keyword for should-error: {other}"),
+        };
+        let Some(value) = args.next()? else { bail_err!("Missing value for should-error keyword") };
+        let value = rebind!(interp.eval_form(value, cx)?);
+        slot.set(value);
+    }
+    root!(shown, NIL, cx);
+    let err = match eval_assertion(interp, form, shown, cx) {
+        Ok(value) => {
+            let value = rebind!(value, cx);
+            let original: Object = Cons::new(sym::SHOULD_ERROR, forms.bind(cx), cx).into();
+            let reason = cx.add("did not signal an error");
+            let info = [
+                original,
+                sym::KW_FORM.into(),
+                shown.bind(cx),
+                sym::KW_VALUE.into(),
+                value,
+                sym::KW_FAIL_REASON.into(),
+                reason,
+            ];
+            return Err(signal(sym::ERT_TEST_FAILED, &info, interp.env(), cx));
+        }
+        Err(err) => err,
+    };
+    let Some(condition) = error_condition(&err, interp.env(), cx) else { return Err(err) };
+    let exact = exclude_subtypes.bind(cx) != NIL;
+    let error = condition.as_cons().car();
+    if types.bind(cx) == NIL || has_type(error, types.bind(cx), exact, interp.env(), cx)? {
+        return Ok(condition);
+    }
+    let original: Object = Cons::new(sym::SHOULD_ERROR, forms.bind(cx), cx).into();
+    let reason = cx.add("the error signaled did not have the expected type");
+    let info = [
+        original,
+        sym::KW_FORM.into(),
+        shown.bind(cx),
+        sym::KW_CONDITION.into(),
+        condition,
+        sym::KW_FAIL_REASON.into(),
+        reason,
+    ];
+    Err(signal(sym::ERT_TEST_FAILED, &info, interp.env(), cx))
+}
+
+/// Fail the current test. DATA describes the failure.
+#[defun]
+fn ert_fail(data: Object, env: &mut Rt<Env>, cx: &Context) -> Result<bool> {
+    Err(EvalError::signal(sym::ERT_TEST_FAILED.into(), list![data; cx], env).into())
+}
+
+/// Skip the current test. DATA describes why.
+#[defun]
+fn ert_skip(data: Object, env: &mut Rt<Env>, cx: &Context) -> Result<bool> {
+    Err(EvalError::signal(sym::ERT_TEST_SKIPPED.into(), list![data; cx], env).into())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Outcome {
+    Passed,
+    Failed,
+    Skipped,
+}
+
+/// Run the tests matched by `selector`, reporting each result like ERT does in
+/// batch mode. Returns the number of results that were not as expected.
+fn run_tests(selector: Option<&Rto<Object>>, env: &mut Rt<Env>, cx: &mut Context) -> Result<usize> {
+    let selector = selector.map_or(NIL, |x| x.bind(cx));
+    let regex = match selector.untag() {
+        ObjectType::String(regexp) => Some(Regex::new(&crate::search::lisp_regex_to_rust(regexp))?),
+        ObjectType::Symbol(_) => None,
+        _ => bail!("Unsupported test selector: {selector}"),
+    };
+    root!(tests, new(Vec), cx);
+    let registered = env.vars.get(sym::ERT__TESTS).map_or(NIL, |x| x.bind(cx));
+    for name in registered.as_list()? {
+        let name = name?;
+        let ObjectType::Symbol(symbol) = name.untag() else { continue };
+        let selected = match &regex {
+            Some(regex) => regex.is_match(symbol.name())?,
+            None => selector == NIL || selector == sym::TRUE || name == selector,
+        };
+        if selected {
+            tests.push(name);
+        }
+    }
+    // Run them in the order they were defined
+    tests.reverse();
+
+    let sink = crate::api::message_sink();
+    let total = tests.len();
+    sink.message(&format!("Running {total} tests"));
+    let start = Instant::now();
+    let mut unexpected = Vec::new();
+    let mut skipped = 0;
+    for i in 0..total {
+        let symbol: Symbol = tests[i].bind(cx).try_into()?;
+        let name = symbol.name().to_owned();
+        let expected = crate::data::get(symbol, sym::ERT__EXPECTED_RESULT, env, cx);
+        let expect_failure = expected == sym::KW_FAILED;
+        let test: Function = crate::data::get(symbol, sym::ERT__TEST, env, cx).try_into()?;
+        root!(test, cx);
+        let test_start = Instant::now();
+        let (outcome, condition) = match call!(test; name.as_str(), env, cx) {
+            Ok(_) => (Outcome::Passed, None),
+            Err(err) => match error_condition(&err, env, cx) {
+                Some(condition) if condition.as_cons().car() == sym::ERT_TEST_SKIPPED => {
+                    (Outcome::Skipped, None)
+                }
+                Some(condition) => (Outcome::Failed, Some(condition.to_string())),
+                None => (Outcome::Failed, Some(err.to_string().trim_end().to_owned())),
+            },
+        };
+        let elapsed = test_start.elapsed().as_secs_f64();
+        let as_expected = match outcome {
+            Outcome::Passed => !expect_failure,
+            Outcome::Failed => expect_failure,
+            Outcome::Skipped => true,
+        };
+        let status = match outcome {
+            Outcome::Passed => "passed",
+            Outcome::Failed => "failed",
+            Outcome::Skipped => "skipped",
+        };
+        // Unexpected results are shouted
+        let status = if as_expected { status.to_owned() } else { status.to_uppercase() };
+        if let (false, Some(condition)) = (as_expected, condition) {
+            sink.message(&format!("Test {name} condition:\n    {condition}"));
+        }
+        sink.message(&format!("{status:>9}  {}/{total}  {name} ({elapsed:.6} sec)", i + 1));
+        if outcome == Outcome::Skipped {
+            skipped += 1;
+        } else if !as_expected {
+            unexpected.push(format!("{status:>9}  {name}"));
+        }
+    }
+
+    let expected = total - unexpected.len() - skipped;
+    let skipped = if skipped == 0 { String::new() } else { format!(", {skipped} skipped") };
+    let elapsed = start.elapsed().as_secs_f64();
+    let count = unexpected.len();
+    sink.message(&format!(
+        "\nRan {total} tests, {expected} results as expected, {count} unexpected{skipped} \
+         ({elapsed:.6} sec)"
+    ));
+    if count > 0 {
+        sink.message(&format!("{count} unexpected results:\n{}\n", unexpected.join("\n")));
+    }
+    Ok(count)
+}
+
+/// Run the tests matched by SELECTOR and report the results with `message`.
+/// SELECTOR is nil or t for every test, a regexp matched against the test
+/// names, or the name of a single test. Returns t if every result was as
+/// expected.
+#[defun]
+fn ert_run_tests_batch(
+    selector: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    Ok(run_tests(selector, env, cx)? == 0)
+}
+
+/// Like `ert-run-tests-batch`, then exit. The exit code is 0 if every result
+/// was as expected, 1 if any was not, and 2 if the tests could not be run.
+#[defun(io)]
+fn ert_run_tests_batch_and_exit(
+    selector: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> bool {
+    let code = match run_tests(selector, env, cx) {
+        Ok(0) => 0,
+        Ok(_) => 1,
+        Err(e) => {
+            crate::api::message_sink().message(&format!("Error running tests: {e}"));
+            2
+        }
+    };
+    std::process::exit(code)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::interpreter::eval_to_string;
+
+    #[test]
+    fn test_should() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        assert_eq!(eval_to_string("(equal (should (+ 1 2)) 3)", env, cx).unwrap(), "t");
+        assert_eq!(eval_to_string("(null (should-not (car nil)))", env, cx).unwrap(), "t");
+        let failed = "(equal (condition-case err (should (equal (+ 1 1) 3)) (error err))
+                            '(ert-test-failed ((should (equal (+ 1 1) 3))
+                                               :form (equal 2 3) :value nil)))";
+        assert_eq!(eval_to_string(failed, env, cx).unwrap(), "t");
+        let skipped = "(eq (car (condition-case err (skip-unless nil) (error err)))
+                           'ert-test-skipped)";
+        assert_eq!(eval_to_string(skipped, env, cx).unwrap(), "t");
+    }
+
+    #[test]
+    fn test_should_error() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        let signaled = "(equal (should-error (signal 'wrong-type-argument '(x))
+                                             :type 'wrong-type-argument)
+                               '(wrong-type-argument x))";
+        assert_eq!(eval_to_string(signaled, env, cx).unwrap(), "t");
+        let none = "(equal (condition-case err (should-error (+ 1 2)) (error err))
+                          '(ert-test-failed ((should-error (+ 1 2)) :form (+ 1 2) :value 3
+                                             :fail-reason \"did not signal an error\")))";
+        assert_eq!(eval_to_string(none, env, cx).unwrap(), "t");
+        let wrong_type = "(condition-case nil
+                              (progn (should-error (signal 'void-variable '(x))
+                                                   :type 'wrong-type-argument)
+                                     nil)
+                            (error t))";
+        assert_eq!(eval_to_string(wrong_type, env, cx).unwrap(), "t");
+    }
+
+    #[test]
+    fn test_run_tests() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        let run = "(progn (ert-deftest ert-test-pass () \"Passes\" (should t))
+                          (ert-deftest ert-test-expected-failure ()
+                            :expected-result :failed
+                            (should nil))
+                          (ert-deftest ert-test-skip () (skip-unless nil))
+                          (and (ert-run-tests-batch \"^ert-test-\")
+                               (progn (ert-deftest ert-test-fail () (should (= 1 2)))
+                                      (not (ert-run-tests-batch 'ert-test-fail)))))";
+        assert_eq!(eval_to_string(run, env, cx).unwrap(), "t");
+    }
+}
@@ -1,8 +1,8 @@
 //! Lisp evaluation primitives.
 use crate::core::cons::{Cons, ConsError};
-use crate::core::env::{sym, ArgSlice, CallFrame, Env};
+use crate::core::env::{intern, sym, ArgSlice, CallFrame, Env};
 use crate::core::error::{ArgError, Type, TypeError};
-use crate::core::gc::{Rt, Rto};
+use crate::core::gc::{profiler, Rt, Rto, Slot};
 use crate::core::object::{display_slice, FnArgs, Function, LispString, ObjectType, Symbol, NIL};
 use crate::core::{
     gc::Context,
@@ -13,7 +13,7 @@ use crate::rooted_iter;
 use anyhow::{anyhow, bail, ensure, Result};
 use fallible_iterator::FallibleIterator;
 use fallible_streaming_iterator::FallibleStreamingIterator;
-use rune_core::macros::{bail_err, call, list, root};
+use rune_core::macros::{call, list, rebind, root};
 use rune_macros::defun;
 use std::fmt::{Display, Formatter};
 
@@ -21,6 +21,8 @@ use std::fmt::{Display, Formatter};
 pub(crate) struct EvalError {
     backtrace: Vec<Box<str>>,
     pub(crate) error: ErrorType,
+    /// Whether the `handler-bind` handlers for this error were already called
+    handled: bool,
 }
 
 #[derive(Debug)]
@@ -45,18 +47,23 @@ impl Display for EvalError {
 
 impl EvalError {
     pub(crate) fn new_error(error: anyhow::Error) -> Self {
-        Self { backtrace: Vec::new(), error: ErrorType::Err(error) }
+        Self { backtrace: Vec::new(), error: ErrorType::Err(error), handled: false }
     }
 
     pub(crate) fn signal(error_symbol: Object, data: Object, env: &mut Rt<Env>) -> Self {
         Self {
             backtrace: Vec::new(),
             error: ErrorType::Signal(env.set_exception(error_symbol, data)),
+            handled: false,
         }
     }
 
     pub(crate) fn throw(tag: Object, data: Object, env: &mut Rt<Env>) -> Self {
-        Self { backtrace: Vec::new(), error: ErrorType::Throw(env.set_exception(tag, data)) }
+        Self {
+            backtrace: Vec::new(),
+            error: ErrorType::Throw(env.set_exception(tag, data)),
+            handled: false,
+        }
     }
 
     pub(crate) fn new(error: impl Into<Self>) -> Self {
@@ -64,17 +71,52 @@ impl EvalError {
     }
 
     pub(crate) fn with_trace(error: anyhow::Error, name: &str, args: &[Rto<Object>]) -> Self {
-        let display = display_slice(args);
-        let trace = format!("{name} {display}").into_boxed_str();
-        Self { backtrace: vec![trace], error: ErrorType::Err(error) }
+        Self::new_error(error).add_trace(name, args)
     }
 
     pub(crate) fn add_trace(mut self, name: &str, args: &[Rto<Object>]) -> Self {
         let display = display_slice(args);
         self.backtrace.push(format!("{name} {display}").into_boxed_str());
+        if let Some(e) = self.type_error_mut() {
+            e.set_operation(name);
+        }
+        self
+    }
+
+    /// Record `form` as the form that a type error happened in.
+    pub(crate) fn with_form(mut self, form: Object) -> Self {
+        if let Some(e) = self.type_error_mut() {
+            e.set_form(form);
+        }
         self
     }
 
+    /// The condition this error signals, as the error symbol and its data.
+    /// Type and argument errors from rust have their usual conditions, and
+    /// other rust errors are an `error` with the message. Throws are not
+    /// conditions.
+    pub(crate) fn condition<'ob>(
+        &self,
+        env: &Rt<Env>,
+        cx: &'ob Context,
+    ) -> Option<(Object<'ob>, Object<'ob>)> {
+        match &self.error {
+            ErrorType::Signal(id) => {
+                let (symbol, data) = env.get_exception(*id)?;
+                Some((symbol.bind(cx), data.bind(cx)))
+            }
+            ErrorType::Err(e) => anyhow_condition(e, env, cx),
+            ErrorType::Throw(_) => None,
+        }
+    }
+
+    fn type_error_mut(&mut self) -> Option<&mut TypeError> {
+        match &mut self.error {
+            ErrorType::Err(e) => e.downcast_mut::<TypeError>(),
+            _ => None,
+        }
+    }
+
     pub(crate) fn print_backtrace(&self) {
         println!("BEGIN_BACKTRACE");
         for (i, x) in self.backtrace.iter().enumerate() {
@@ -84,9 +126,267 @@ impl EvalError {
     }
 }
 
+/// The condition for an error returned from rust. See [`EvalError::condition`].
+pub(crate) fn anyhow_condition<'ob>(
+    error: &anyhow::Error,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Option<(Object<'ob>, Object<'ob>)> {
+    if let Some(e) = error.downcast_ref::<EvalError>() {
+        return e.condition(env, cx);
+    }
+    let (symbol, data) = if let Some(condition) = crate::core::error::condition(error, cx) {
+        condition
+    } else if let Some(ConsError::CircularList(list)) = error.downcast_ref::<ConsError>() {
+        (sym::CIRCULAR_LIST, list.get(cx).map_or(NIL, |list| list![list; cx]))
+    } else {
+        (sym::ERROR, list![error.to_string(); cx])
+    };
+    Some((symbol.into(), data))
+}
+
+/// Whether a handler for `conditions`, a condition name or a list of them,
+/// handles an error signaled with `symbol`. `t` handles everything. Otherwise
+/// one of the conditions has to be `symbol` or one of the conditions it
+/// inherits with `define-error`. Errors that were not defined that way are
+/// still an `error`, unless they are a `quit`.
+pub(crate) fn handles(
+    conditions: Object,
+    symbol: Object,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    let conditions: Vec<Object> = match conditions.untag() {
+        ObjectType::Cons(conditions) => conditions.elements().collect::<Result<_, _>>()?,
+        _ => vec![conditions],
+    };
+    if conditions.contains(&sym::TRUE.into()) || conditions.contains(&symbol) {
+        return Ok(true);
+    }
+    let ObjectType::Symbol(symbol) = symbol.untag() else { return Ok(false) };
+    let inherited = crate::data::get(symbol, sym::ERROR_CONDITIONS, env, cx);
+    if inherited.is_nil() {
+        return Ok(symbol != sym::QUIT && conditions.contains(&sym::ERROR.into()));
+    }
+    for condition in inherited.as_list()? {
+        if conditions.contains(&condition?) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Call the `handler-bind` handlers for `err`, innermost first, before anything
+/// is unwound. The search stops at a `condition-case` that handles it. A
+/// handler that exits nonlocally replaces `err` with its own error. While a
+/// handler runs, it and the handlers inside it are not active. Each error is
+/// only handled once, at the innermost place this is called.
+pub(crate) fn run_handlers(mut err: EvalError, env: &mut Rt<Env>, cx: &mut Context) -> EvalError {
+    if err.handled {
+        return err;
+    }
+    err.handled = true;
+    if env.handlers.iter().all(|x| x.1.bind(cx).is_nil()) {
+        return err;
+    }
+    let Some((symbol, data)) = err.condition(env, cx) else { return err };
+    let error: Object = Cons::new(symbol, data, cx).into();
+    root!(error, cx);
+    let mut idx = env.handlers.len();
+    while idx > 0 {
+        idx -= 1;
+        let (conditions, handler) = (env.handlers[idx].0.bind(cx), env.handlers[idx].1.bind(cx));
+        let symbol = error.bind(cx).as_cons().car();
+        if !handles(conditions, symbol, env, cx).unwrap_or(false) {
+            continue;
+        }
+        if handler.is_nil() {
+            break;
+        }
+        let Ok(handler) = Function::try_from(handler) else { continue };
+        root!(handler, cx);
+        root!(hidden, new(Vec<(Slot<Object>, Slot<Object>)>), cx);
+        for x in &env.handlers[idx..] {
+            hidden.push((x.0.bind(cx), x.1.bind(cx)));
+        }
+        env.handlers.truncate(idx);
+        let result = call!(handler, error.bind(cx); env, cx).err();
+        env.handlers.truncate(idx);
+        for x in hidden.iter() {
+            env.handlers.push((x.0.bind(cx), x.1.bind(cx)));
+        }
+        if let Some(e) = result {
+            return e;
+        }
+    }
+    err
+}
+
+/// Call BODYFUN with handlers that run where an error is signaled, before it
+/// is unwound. HANDLERS alternate between a condition or list of conditions
+/// and the function called with the error. This is the function behind
+/// `handler-bind`.
+#[defun]
+fn handler_bind_1<'ob>(
+    bodyfun: &Rto<Function>,
+    handlers: ArgSlice,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    ensure!(handlers.len() % 2 == 0, "Handlers of handler-bind-1 must come in pairs");
+    let args = Rt::bind_slice(env.stack.arg_slice(handlers), cx);
+    let mut pairs = Vec::new();
+    // The first handler is the innermost
+    for pair in args.chunks(2).rev() {
+        let handler: Function = pair[1].try_into()?;
+        pairs.push((pair[0], Object::from(handler)));
+    }
+    let depth = env.handlers.len();
+    for pair in pairs {
+        env.handlers.push(pair);
+    }
+    let result = bodyfun.call(&mut CallFrame::new(env), None, cx);
+    let err = match result {
+        Ok(x) => {
+            env.handlers.truncate(depth);
+            return Ok(rebind!(x, cx));
+        }
+        Err(e) => e,
+    };
+    let err = run_handlers(err, env, cx);
+    env.handlers.truncate(depth);
+    Err(err.into())
+}
+
+/// The errors signaled by builtins, with their message and the error they
+/// inherit from. They are defined in order, so a parent comes before its
+/// children.
+const STANDARD_ERRORS: &[(&str, &str, Option<&str>)] = &[
+    ("error", "error", None),
+    ("quit", "Quit", None),
+    ("minibuffer-quit", "Quit", Some("quit")),
+    ("user-error", "", Some("error")),
+    ("wrong-length-argument", "Wrong length argument", Some("error")),
+    ("wrong-type-argument", "Wrong type argument", Some("error")),
+    ("args-out-of-range", "Args out of range", Some("error")),
+    ("void-function", "Symbol's function definition is void", Some("error")),
+    (
+        "cyclic-function-indirection",
+        "Symbol's chain of function indirections contains a loop",
+        Some("error"),
+    ),
+    (
+        "cyclic-variable-indirection",
+        "Symbol's chain of variable indirections contains a loop",
+        Some("error"),
+    ),
+    ("circular-list", "List contains a loop", Some("error")),
+    ("void-variable", "Symbol's value as variable is void", Some("error")),
+    ("setting-constant", "Attempt to set a constant symbol", Some("error")),
+    ("invalid-read-syntax", "Invalid read syntax", Some("error")),
+    ("invalid-function", "Invalid function", Some("error")),
+    ("wrong-number-of-arguments", "Wrong number of arguments", Some("error")),
+    ("no-catch", "No catch for tag", Some("error")),
+    ("end-of-file", "End of file during parsing", Some("error")),
+    ("arith-error", "Arithmetic error", Some("error")),
+    ("domain-error", "Arithmetic domain error", Some("arith-error")),
+    ("range-error", "Arithmetic range error", Some("arith-error")),
+    ("overflow-error", "Arithmetic overflow error", Some("range-error")),
+    ("underflow-error", "Arithmetic underflow error", Some("range-error")),
+    ("singularity-error", "Arithmetic singularity error", Some("domain-error")),
+    ("beginning-of-buffer", "Beginning of buffer", Some("error")),
+    ("end-of-buffer", "End of buffer", Some("error")),
+    ("buffer-read-only", "Buffer is read-only", Some("error")),
+    ("text-read-only", "Text is read-only", Some("buffer-read-only")),
+    ("search-failed", "Search failed", Some("error")),
+    ("invalid-regexp", "Invalid regexp", Some("error")),
+    ("mark-inactive", "The mark is not active now", Some("error")),
+    ("scan-error", "Scan error", Some("error")),
+    ("recursion-error", "Excessive recursive calling error", Some("error")),
+    (
+        "excessive-lisp-nesting",
+        "Lisp nesting exceeds `max-lisp-eval-depth'",
+        Some("recursion-error"),
+    ),
+    ("file-error", "File error", Some("error")),
+    ("file-already-exists", "File already exists", Some("file-error")),
+    ("file-date-error", "Cannot set file date", Some("file-error")),
+    ("file-missing", "No such file or directory", Some("file-error")),
+    ("permission-denied", "Cannot access file or directory", Some("file-error")),
+];
+
+/// Give `name` the error conditions of `parents` and `message`. With no
+/// parents it is a root condition, like `error`.
+fn put_error(
+    name: Symbol,
+    message: Object,
+    parents: &[Object],
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let mut conditions: Vec<Object> = vec![name.into()];
+    for &parent in parents {
+        let ObjectType::Symbol(symbol) = parent.untag() else {
+            bail!(TypeError::new(Type::Symbol, parent))
+        };
+        let inherited = crate::data::get(symbol, sym::ERROR_CONDITIONS, env, cx);
+        // A single parent doesn't have to be an error yet
+        ensure!(parents.len() == 1 || !inherited.is_nil(), "Unknown signal `{parent}'");
+        conditions.push(parent);
+        for condition in inherited.as_list()? {
+            conditions.push(condition?);
+        }
+    }
+    let mut unique: Vec<Object> = Vec::new();
+    for condition in conditions {
+        if !unique.contains(&condition) {
+            unique.push(condition);
+        }
+    }
+    let conditions = crate::fns::slice_into_list(&unique, None, cx);
+    env.set_prop(name, sym::ERROR_CONDITIONS, conditions);
+    if !message.is_nil() {
+        env.set_prop(name, sym::ERROR_MESSAGE, message);
+    }
+    Ok(())
+}
+
+/// Define the errors signaled by builtins, with the same conditions and
+/// messages as in Emacs.
+pub(crate) fn init_errors(env: &mut Rt<Env>, cx: &Context) {
+    for (name, message, parent) in STANDARD_ERRORS {
+        let parents: Vec<Object> = parent.iter().map(|x| intern(x, cx).into()).collect();
+        put_error(intern(name, cx), cx.add(*message), &parents, env, cx)
+            .expect("builtin errors should be valid");
+    }
+}
+
+/// Define NAME as a new error signal. MESSAGE is the message shown when the
+/// error is not handled. PARENT is an error or a list of errors that NAME
+/// inherits the conditions of, and defaults to `error`.
+#[defun]
+pub(crate) fn define_error(
+    name: Symbol,
+    message: Object,
+    parent: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let parent = parent.unwrap_or(NIL);
+    let parents = match parent.untag() {
+        ObjectType::NIL => vec![sym::ERROR.into()],
+        ObjectType::Cons(parents) => parents.elements().collect::<Result<_, _>>()?,
+        _ => vec![parent],
+    };
+    put_error(name, message, &parents, env, cx)
+}
+
 impl From<anyhow::Error> for EvalError {
     fn from(e: anyhow::Error) -> Self {
-        Self::new_error(e)
+        match e.downcast::<Self>() {
+            Ok(e) => e,
+            Err(e) => Self::new_error(e),
+        }
     }
 }
 
@@ -235,6 +535,45 @@ fn run_hook_with_args<'ob>(
     Ok(NIL)
 }
 
+/// Whether `func` is an autoload stub, `(autoload FILE DOCSTRING INTERACTIVE
+/// TYPE)`.
+pub(crate) fn is_autoload(func: Object) -> bool {
+    matches!(func.untag(), ObjectType::Cons(cons) if cons.car() == sym::AUTOLOAD)
+}
+
+/// Load the file named by the autoload stub `fundef`, and return the name.
+fn load_autoload_file(fundef: &Rto<Object>, env: &mut Rt<Env>, cx: &mut Context) -> Result<String> {
+    let ObjectType::Cons(cons) = fundef.untag(cx) else { bail!("Malformed autoload") };
+    let file: Gc<&LispString> = match cons.elements().nth(1) {
+        Some(x) => x?.try_into()?,
+        None => bail!("Malformed autoload"),
+    };
+    let name = file.untag().to_string();
+    root!(file, cx);
+    crate::lread::load(file, None, Some(()), cx, env)?;
+    Ok(name)
+}
+
+/// The definition of `symbol` after its autoload stub loaded `file`.
+fn autoloaded<'ob>(symbol: Symbol, file: &str, cx: &'ob Context) -> Result<Function<'ob>> {
+    match symbol.follow_indirect(cx) {
+        Some(func) if !is_autoload(func.into()) => Ok(func),
+        _ => bail!("Autoloading file {file} failed to define function {symbol}"),
+    }
+}
+
+/// Load the file of `fundef`, the autoload stub in the function cell of
+/// `symbol`, and return the definition that replaced it.
+pub(crate) fn autoload_function<'ob>(
+    symbol: &Rto<Symbol>,
+    fundef: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Function<'ob>> {
+    let file = load_autoload_file(fundef, env, cx)?;
+    autoloaded(symbol.bind(cx), &file, cx)
+}
+
 #[defun]
 pub(crate) fn autoload_do_load<'ob>(
     fundef: &Rto<Object>,
@@ -243,34 +582,21 @@ pub(crate) fn autoload_do_load<'ob>(
     env: &mut Rt<Env>,
     cx: &'ob mut Context,
 ) -> Result<Object<'ob>> {
-    // TODO: want to handle the case where the file is already loaded.
-    match fundef.untag(cx) {
-        ObjectType::Cons(cons) if cons.car() == sym::AUTOLOAD => {
-            ensure!(macro_only.is_none(), "autoload-do-load macro-only is not yet implemented");
-            let mut iter = cons.elements();
-            iter.next(); // autoload
-            let file: Gc<&LispString> = match iter.next() {
-                Some(x) => x?.try_into()?,
-                None => bail!("Malformed autoload"),
-            };
-            ensure!(
-                iter.fallible().all(|x| Ok(x.is_nil()))?,
-                "autoload arguments are not yet implemented"
-            );
-            root!(file, cx);
-            crate::lread::load(file, None, None, cx, env)?;
-            match funname {
-                Some(func) => match func.untag(cx).func(cx) {
-                    Some(x) => Ok(x.into()),
-                    None => Err(anyhow!("autoload of {func} did not provide a definition")),
-                },
-                _ => Ok(NIL),
-            }
-        }
-        _ => Ok(fundef.bind(cx)),
+    if !is_autoload(fundef.bind(cx)) {
+        return Ok(fundef.bind(cx));
+    }
+    ensure!(macro_only.is_none(), "autoload-do-load macro-only is not yet implemented");
+    let file = load_autoload_file(fundef, env, cx)?;
+    match funname {
+        Some(func) => Ok(autoloaded(func.untag(cx), &file, cx)?.into()),
+        None => Ok(NIL),
     }
 }
 
+/// Define FUNCTION to autoload from FILE. The first time FUNCTION is called
+/// FILE is loaded, and it must define FUNCTION. DOCSTRING, INTERACTIVE and
+/// TYPE describe the function until then. Does nothing if FUNCTION is already
+/// defined other than as an autoload.
 #[defun]
 fn autoload<'ob>(
     function: Symbol<'ob>,
@@ -280,7 +606,7 @@ fn autoload<'ob>(
     load_type: Option<Object>,
     cx: &'ob Context,
 ) -> Result<Symbol<'ob>> {
-    if function.has_func() {
+    if function.func(cx).is_some_and(|func| !is_autoload(func.into())) {
         Ok(sym::NIL)
     } else {
         let autoload = list![sym::AUTOLOAD, file, docstring, interactive, load_type; cx];
@@ -288,33 +614,71 @@ fn autoload<'ob>(
     }
 }
 
+/// Whether `func` is an autoload stub for a macro.
+fn is_macro_autoload(func: Object) -> bool {
+    let ObjectType::Cons(cons) = func.untag() else { return false };
+    // (autoload FILE DOCSTRING INTERACTIVE TYPE)
+    cons.car() == sym::AUTOLOAD
+        && matches!(cons.elements().nth(4), Some(Ok(t)) if t == sym::MACRO || t == sym::TRUE)
+}
+
+/// Expand FORM once if it is a macro call, and return it unchanged otherwise.
+/// ENVIRONMENT is an alist of `(NAME . FUNCTION)` that shadows the global
+/// macros. A nil FUNCTION means NAME is not expanded. An autoloaded macro is
+/// loaded first.
 #[defun]
-pub(crate) fn macroexpand<'ob>(
+pub(crate) fn macroexpand_1<'ob>(
     form: &Rto<Object>,
     environment: Option<&Rto<Object>>,
     cx: &'ob mut Context,
     env: &mut Rt<Env>,
 ) -> Result<Object<'ob>> {
     let ObjectType::Cons(cons) = form.untag(cx) else { return Ok(form.bind(cx)) };
-    let ObjectType::Symbol(sym) = cons.car().untag() else { return Ok(form.bind(cx)) };
-    // shadow the macro based on ENVIRONMENT
-    let func = match environment {
-        Some(env) => match assq(sym.into(), env.bind(cx).try_into()?)?.untag() {
-            ObjectType::Cons(cons) => Some(cons.cdr().try_into()?),
-            _ => get_macro_func(sym, cx),
+    let ObjectType::Symbol(name) = cons.car().untag() else { return Ok(form.bind(cx)) };
+    let shadowed = match environment {
+        Some(env) => assq(name.into(), env.bind(cx).try_into()?)?,
+        None => NIL,
+    };
+    let macro_func = match shadowed.untag() {
+        ObjectType::Cons(entry) if entry.cdr().is_nil() => return Ok(form.bind(cx)),
+        ObjectType::Cons(entry) => entry.cdr().try_into()?,
+        _ => match name.follow_indirect(cx) {
+            Some(func) if is_macro_autoload(func.into()) => {
+                let fundef: Object = func.into();
+                root!(fundef, cx);
+                root!(name, cx);
+                autoload_function(name, fundef, env, cx)?;
+                return macroexpand_1(form, environment, cx, env);
+            }
+            _ => match get_macro_func(name, cx) {
+                Some(func) => func,
+                None => return Ok(form.bind(cx)),
+            },
         },
-        _ => get_macro_func(sym, cx),
     };
-    let Some(macro_func) = func else { return Ok(form.bind(cx)) };
     let mut iter = cons.cdr().as_list()?.fallible();
     let mut frame = CallFrame::new(env);
     while let Some(arg) = iter.next()? {
         frame.push_arg(arg);
     }
     root!(macro_func, cx);
-    let name = sym.name().to_owned();
+    let name = name.name().to_owned();
+    let span = trace_span!("macroexpand", macro_name = name.as_str());
     let new_form = macro_func.call(&mut frame, Some(&name), cx)?;
-    drop(frame);
+    drop(span);
+    Ok(new_form)
+}
+
+/// Expand FORM until it is no longer a macro call. ENVIRONMENT is used like
+/// in `macroexpand-1`.
+#[defun]
+pub(crate) fn macroexpand<'ob>(
+    form: &Rto<Object>,
+    environment: Option<&Rto<Object>>,
+    cx: &'ob mut Context,
+    env: &mut Rt<Env>,
+) -> Result<Object<'ob>> {
+    let new_form = macroexpand_1(form, environment, cx, env)?;
     root!(new_form, cx); // polonius
     if eq(new_form.bind(cx), form.bind(cx)) {
         Ok(form.bind(cx))
@@ -336,7 +700,7 @@ fn get_macro_func<'ob>(name: Symbol, cx: &'ob Context) -> Option<Function<'ob>>
 }
 
 #[defun]
-fn func_arity<'ob>(function: Function, cx: &'ob Context) -> Result<&'ob Cons> {
+pub(crate) fn func_arity<'ob>(function: Function, cx: &'ob Context) -> Result<&'ob Cons> {
     let from_args = |args: FnArgs| {
         let min = args.required;
         if args.rest {
@@ -397,25 +761,45 @@ fn special_variable_p(symbol: Symbol) -> bool {
     symbol.is_special()
 }
 
+/// The function form of `defconst` that byte-compiled code calls.
+#[defun(name = "defconst-1")]
+fn defconst_1<'ob>(
+    symbol: Symbol<'ob>,
+    initvalue: Object,
+    docstring: Option<Object>,
+    env: &mut Rt<Env>,
+) -> Result<Symbol<'ob>> {
+    if let Some(doc) = docstring.filter(|x| matches!(x.untag(), ObjectType::String(_))) {
+        env.set_prop(symbol, sym::VARIABLE_DOCUMENTATION, doc);
+    }
+    env.defconst(symbol, initvalue)?;
+    Ok(symbol)
+}
+
 #[defun]
-fn set_default_toplevel_value<'ob>(
-    symbol: Symbol,
-    value: Object,
-    env: &'ob mut Rt<Env>,
-) -> Result<Object<'ob>> {
-    env.set_var(symbol, value)?;
-    Ok(NIL)
+fn set_default_toplevel_value(
+    symbol: &Rto<Gc<Symbol>>,
+    value: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let symbol = symbol.untag(cx);
+    root!(symbol, cx);
+    crate::data::set_var(symbol, value, env, cx)
 }
 
 #[defun]
 fn set_default<'ob>(
-    symbol: Symbol,
-    value: Object<'ob>,
-    env: &'ob mut Rt<Env>,
+    symbol: &Rto<Gc<Symbol>>,
+    value: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
 ) -> Result<Object<'ob>> {
     // TODO: implement buffer local variables
-    env.set_var(symbol, value)?;
-    Ok(value)
+    let symbol = symbol.untag(cx);
+    root!(symbol, cx);
+    crate::data::set_var(symbol, value, env, cx)?;
+    Ok(value.bind(cx))
 }
 
 impl Rto<Function<'_>> {
@@ -427,9 +811,13 @@ impl Rto<Function<'_>> {
     ) -> EvalResult<'ob> {
         debug!("calling: {self}");
         let name = name.unwrap_or("lambda");
+        let _span = trace_span!("call", function = name);
         frame.finalize_arguments();
+        profiler::enter_function(frame.stack.current_frame(), name);
         let arg_cnt = frame.arg_count();
         cx.garbage_collect(false);
+        crate::sandbox::check_call(self.bind(cx), cx)
+            .map_err(|e| add_trace(e, name, frame.arg_slice()))?;
         match self.untag(cx) {
             FunctionType::ByteFn(f) => {
                 root!(f, cx);
@@ -444,16 +832,20 @@ impl Rto<Function<'_>> {
                     .map_err(|e| e.add_trace(name, frame.arg_slice()))
             }
             FunctionType::Symbol(sym) => {
-                let Some(func) = sym.follow_indirect(cx) else { bail_err!("Void Function: {sym}") };
+                let Some(func) = sym.follow_indirect(cx) else {
+                    return Err(EvalError::signal(
+                        sym::VOID_FUNCTION.into(),
+                        list![sym; cx],
+                        frame,
+                    ));
+                };
                 match func.untag() {
                     FunctionType::Cons(cons) if cons.car() == sym::AUTOLOAD => {
-                        // TODO: inifinite loop if autoload does not resolve
                         root!(sym, cx);
-                        crate::eval::autoload_do_load(self.cast(), None, None, frame, cx)
+                        let fundef: Object = func.into();
+                        root!(fundef, cx);
+                        let func = autoload_function(sym, fundef, frame, cx)
                             .map_err(|e| add_trace(e, name, frame.arg_slice()))?;
-                        let Some(func) = sym.bind(cx).follow_indirect(cx) else {
-                            bail_err!("autoload for {sym} failed to define function")
-                        };
                         root!(func, cx);
                         let name = sym.bind(cx).name().to_owned();
                         func.call(frame, Some(&name), cx)
@@ -507,8 +899,18 @@ defsym!(INTERACTIVE);
 defsym!(CATCH);
 defsym!(THROW);
 defsym!(ERROR);
+defsym!(ERROR_CONDITIONS);
+defsym!(ERROR_MESSAGE);
+defsym!(USER_ERROR);
+defsym!(END_OF_FILE);
+defsym!(FILE_ERROR);
+defsym!(QUIT);
 defsym!(DEBUG);
 defsym!(VOID_VARIABLE);
+defsym!(VOID_FUNCTION);
+defsym!(INVALID_FUNCTION);
+defsym!(CIRCULAR_LIST);
+defsym!(SETTING_CONSTANT);
 
 defvar!(DEBUG_ON_ERROR, false);
 defvar!(INTERNAL_MAKE_INTERPRETED_CLOSURE_FUNCTION);
@@ -4,13 +4,21 @@ use crate::core::{
     env::{sym, Env},
     error::{Type, TypeError},
     gc::{Context, Rt},
-    object::{Number, Object, ObjectType},
+    object::{Function, Number, Object, ObjectType, Symbol, NIL},
 };
-use anyhow::{bail, ensure, Result};
+use crate::coding::Coding;
+use crate::fns::memq;
+use crate::search::lisp_regex_to_rust;
+use anyhow::{bail, ensure, Context as _, Result};
+use fancy_regex::Regex;
+use rune_core::macros::{call, list, root};
 use rune_macros::defun;
 use std::path::{Component, Path, MAIN_SEPARATOR};
 
 defvar!(FILE_NAME_HANDLER_ALIST);
+defvar!(INHIBIT_FILE_NAME_HANDLERS);
+defvar!(INHIBIT_FILE_NAME_OPERATION);
+defsym!(OPERATIONS);
 
 #[defun]
 pub(crate) fn expand_file_name(
@@ -54,12 +62,17 @@ fn file_name_as_directory(filename: &str) -> String {
     }
 }
 
-#[defun]
-fn file_directory_p(filename: &str) -> bool {
+#[defun(io)]
+fn file_directory_p(filename: &str, env: &mut Rt<Env>, cx: &mut Context) -> Result<bool> {
+    if let Some(handler) = file_handler(filename, sym::FILE_DIRECTORY_P, env, cx)? {
+        root!(handler, cx);
+        let operation = Object::from(sym::FILE_DIRECTORY_P);
+        return Ok(!call!(handler, operation, cx.add(filename); env, cx)?.is_nil());
+    }
     if filename.is_empty() {
-        true
+        Ok(true)
     } else {
-        Path::new(filename).is_dir()
+        Ok(Path::new(filename).is_dir())
     }
 }
 
@@ -135,22 +148,174 @@ fn directory_name_p(name: &str) -> bool {
     name.ends_with(MAIN_SEPARATOR)
 }
 
+/// Return the handler in `file-name-handler-alist` for FILENAME, or nil if
+/// there is none. Each element is (REGEXP . HANDLER), and when several
+/// regexps match FILENAME the one whose match starts last wins. A handler
+/// with an `operations` property only handles the operations listed there,
+/// and the handlers in `inhibit-file-name-handlers` are skipped when
+/// OPERATION is `inhibit-file-name-operation`.
 #[defun]
-fn find_file_name_handler(_filename: &str, _operation: Object) {
-    // TODO: implement file-name-handler-alist
+fn find_file_name_handler<'ob>(
+    filename: &str,
+    operation: Object,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let var = |name| env.vars.get(name).map_or(NIL, |x| x.bind(cx));
+    let inhibited = if var(sym::INHIBIT_FILE_NAME_OPERATION) == operation {
+        var(sym::INHIBIT_FILE_NAME_HANDLERS)
+    } else {
+        NIL
+    };
+    let mut handler = NIL;
+    let mut handler_pos = None;
+    for elt in var(sym::FILE_NAME_HANDLER_ALIST).as_list()? {
+        let ObjectType::Cons(elt) = elt?.untag() else { continue };
+        let ObjectType::String(regexp) = elt.car().untag() else { continue };
+        let regexp = Regex::new(&lisp_regex_to_rust(regexp))?;
+        let Some(pos) = regexp.find(filename)?.map(|x| x.start()) else { continue };
+        if handler_pos.is_some_and(|x| pos <= x) {
+            continue;
+        }
+        let operations = match elt.cdr().untag() {
+            ObjectType::Symbol(name) => crate::data::get(name, sym::OPERATIONS, env, cx),
+            _ => NIL,
+        };
+        if !operations.is_nil() && memq(operation, operations.try_into()?)?.is_nil() {
+            continue;
+        }
+        if memq(elt.cdr(), inhibited.try_into()?)?.is_nil() {
+            handler = elt.cdr();
+            handler_pos = Some(pos);
+        }
+    }
+    Ok(handler)
 }
 
-#[defun]
-fn file_symlink_p(filename: &str) -> bool {
-    Path::new(filename).is_symlink()
+/// The handler for OPERATION on FILENAME from `file-name-handler-alist`. File
+/// primitives call it with the operation and their arguments instead of
+/// touching the filesystem themselves.
+pub(crate) fn file_handler<'ob>(
+    filename: &str,
+    operation: Symbol,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Option<Function<'ob>>> {
+    let handler = find_file_name_handler(filename, operation.into(), env, cx)?;
+    if handler.is_nil() {
+        Ok(None)
+    } else {
+        Ok(Some(handler.try_into()?))
+    }
 }
 
-#[defun]
-fn file_name_case_insensitive_p(filename: &str) -> bool {
+#[defun(io)]
+fn file_symlink_p(filename: &str, env: &mut Rt<Env>, cx: &mut Context) -> Result<bool> {
+    if let Some(handler) = file_handler(filename, sym::FILE_SYMLINK_P, env, cx)? {
+        root!(handler, cx);
+        let operation = Object::from(sym::FILE_SYMLINK_P);
+        return Ok(!call!(handler, operation, cx.add(filename); env, cx)?.is_nil());
+    }
+    Ok(Path::new(filename).is_symlink())
+}
+
+/// Return t if FILE1 was modified more recently than FILE2. If FILE1 does not
+/// exist return nil, otherwise if FILE2 does not exist return t.
+#[defun(io)]
+fn file_newer_than_file_p(
+    file1: &str,
+    file2: &str,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    for file in [file1, file2] {
+        if let Some(handler) = file_handler(file, sym::FILE_NEWER_THAN_FILE_P, env, cx)? {
+            root!(handler, cx);
+            let operation = Object::from(sym::FILE_NEWER_THAN_FILE_P);
+            let (file1, file2) = (cx.add(file1), cx.add(file2));
+            return Ok(!call!(handler, operation, file1, file2; env, cx)?.is_nil());
+        }
+    }
+    let modified = |file: &str| std::fs::metadata(file).and_then(|x| x.modified()).ok();
+    match (modified(file1), modified(file2)) {
+        (None, _) => Ok(false),
+        (Some(_), None) => Ok(true),
+        (Some(time1), Some(time2)) => Ok(time1 > time2),
+    }
+}
+
+/// Create the directory DIR. If PARENTS is non-nil, missing parent directories
+/// are created as well, and it is not an error if DIR already exists. Return
+/// non-nil if PARENTS is non-nil and DIR already existed.
+#[defun(io)]
+fn make_directory(
+    dir: &str,
+    parents: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    if let Some(handler) = file_handler(dir, sym::MAKE_DIRECTORY, env, cx)? {
+        root!(handler, cx);
+        let operation = Object::from(sym::MAKE_DIRECTORY);
+        let dir = cx.add(dir);
+        return Ok(!call!(handler, operation, dir, parents.is_some(); env, cx)?.is_nil());
+    }
+    let dir = expand_file_name(dir, None, env, cx)?;
+    if parents.is_some() {
+        if Path::new(&dir).is_dir() {
+            return Ok(true);
+        }
+        std::fs::create_dir_all(&dir)
+    } else {
+        std::fs::create_dir(&dir)
+    }
+    .with_context(|| format!("Creating directory: {dir}"))?;
+    Ok(false)
+}
+
+/// Delete the directory DIRECTORY. If RECURSIVE is non-nil, its contents are
+/// deleted as well, otherwise it must be empty. Symbolic links are deleted and
+/// not followed. TRASH is ignored.
+#[defun(io)]
+fn delete_directory(
+    directory: &str,
+    recursive: Option<()>,
+    trash: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    if let Some(handler) = file_handler(directory, sym::DELETE_DIRECTORY, env, cx)? {
+        root!(handler, cx);
+        let operation = Object::from(sym::DELETE_DIRECTORY);
+        let directory = cx.add(directory);
+        call!(handler, operation, directory, recursive.is_some(), trash.is_some(); env, cx)?;
+        return Ok(());
+    }
+    let directory = expand_file_name(directory, None, env, cx)?;
+    let path = Path::new(&directory);
+    if recursive.is_some() && !path.is_symlink() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_dir(path)
+    }
+    .with_context(|| format!("Removing directory: {directory}"))
+}
+
+#[defun(io)]
+fn file_name_case_insensitive_p(
+    filename: &str,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    if let Some(handler) = file_handler(filename, sym::FILE_NAME_CASE_INSENSITIVE_P, env, cx)? {
+        root!(handler, cx);
+        let operation = Object::from(sym::FILE_NAME_CASE_INSENSITIVE_P);
+        return Ok(!call!(handler, operation, cx.add(filename); env, cx)?.is_nil());
+    }
     if !Path::new(filename).exists() {
-        return false;
+        return Ok(false);
     }
-    case_insensitive(filename)
+    Ok(case_insensitive(filename))
 }
 
 #[cfg(target_os = "macos")]
@@ -191,10 +356,81 @@ fn case_insensitive(_filename: &str) -> bool {
 #[test]
 #[cfg(not(miri))]
 fn test_case_sensative_call() {
-    let _ = file_name_case_insensitive_p("/");
+    use crate::core::gc::RootSet;
+    let roots = &RootSet::default();
+    let cx = &mut Context::new(roots);
+    root!(env, new(Env), cx);
+    let _ = file_name_case_insensitive_p("/", env, cx);
 }
 
-#[defun]
+#[test]
+#[cfg(not(miri))]
+fn test_make_delete_directory() {
+    use crate::core::gc::RootSet;
+    let roots = &RootSet::default();
+    let cx = &mut Context::new(roots);
+    root!(env, new(Env), cx);
+    let base = std::env::temp_dir().join(format!("rune-fileio-{}", std::process::id()));
+    let base = base.to_str().unwrap();
+    let nested = format!("{base}/a/b");
+    assert!(make_directory(&nested, None, env, cx).is_err());
+    assert!(!make_directory(&nested, Some(()), env, cx).unwrap());
+    assert!(make_directory(&nested, Some(()), env, cx).unwrap());
+
+    let file = format!("{nested}/file");
+    let missing = format!("{base}/missing");
+    std::fs::write(&file, "").unwrap();
+    assert!(file_newer_than_file_p(&file, &missing, env, cx).unwrap());
+    assert!(!file_newer_than_file_p(&missing, &file, env, cx).unwrap());
+    assert!(!file_newer_than_file_p(&file, &file, env, cx).unwrap());
+
+    assert!(delete_directory(base, None, None, env, cx).is_err());
+    delete_directory(base, Some(()), None, env, cx).unwrap();
+    assert!(!Path::new(base).exists());
+}
+
+#[test]
+fn test_file_name_handler() {
+    use crate::core::{env::intern, gc::RootSet};
+    let roots = &RootSet::default();
+    let cx = &mut Context::new(roots);
+    sym::init_symbols();
+    root!(env, new(Env), cx);
+    let source = r#"
+(defalias 'fileio-test-handler #'(lambda (operation &rest args) (list operation args)))
+(defalias 'fileio-test-other #'(lambda (operation &rest _args) operation))
+(put 'fileio-test-other 'operations '(file-symlink-p))
+(setq file-name-handler-alist
+      '(("\\`/test:" . fileio-test-handler) ("\\.other\\'" . fileio-test-other)))
+(setq fileio-test-attributes (file-attributes "/test:foo"))"#;
+    crate::lread::load_internal(source, cx, env).unwrap();
+    let handler = Object::from(intern("fileio-test-handler", cx));
+    let other = Object::from(intern("fileio-test-other", cx));
+    let directory_p = Object::from(sym::FILE_DIRECTORY_P);
+    let symlink_p = Object::from(sym::FILE_SYMLINK_P);
+    let find = find_file_name_handler("/test:foo", directory_p, env, cx).unwrap();
+    assert_eq!(find, handler);
+    // the match that starts last wins, if it handles the operation
+    let find = find_file_name_handler("/test:foo.other", symlink_p, env, cx).unwrap();
+    assert_eq!(find, other);
+    let find = find_file_name_handler("/test:foo.other", directory_p, env, cx).unwrap();
+    assert_eq!(find, handler);
+    assert_eq!(find_file_name_handler("/foo", directory_p, env, cx).unwrap(), NIL);
+
+    let attributes = env.vars.get(intern("fileio-test-attributes", cx)).unwrap().bind(cx);
+    assert_eq!(attributes.to_string(), "(file-attributes (\"/test:foo\" nil))");
+    assert!(file_directory_p("/test:foo", env, cx).unwrap());
+
+    let source = "
+(setq inhibit-file-name-handlers '(fileio-test-handler)
+      inhibit-file-name-operation 'file-directory-p)";
+    crate::lread::load_internal(source, cx, env).unwrap();
+    assert!(!file_directory_p("/test:foo", env, cx).unwrap());
+    let find = find_file_name_handler("/test:foo", symlink_p, env, cx).unwrap();
+    assert_eq!(find, handler);
+}
+
+#[defun(io)]
 #[allow(clippy::too_many_arguments)]
 fn write_region(
     start: i64,
@@ -204,31 +440,71 @@ fn write_region(
     visit: Option<()>,
     lockname: Option<()>,
     mustbenew: Option<()>,
-    env: &Rt<Env>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
 ) -> Result<()> {
-    use std::io::Write;
+    if let Some(handler) = file_handler(filename, sym::WRITE_REGION, env, cx)? {
+        root!(handler, cx);
+        let operation = Object::from(sym::WRITE_REGION);
+        let flags = [append, visit, lockname, mustbenew].map(|x| x.is_some());
+        let [append, visit, lockname, mustbenew] = flags;
+        let filename = cx.add(filename);
+        call!(
+            handler, operation, start, end, filename, append, visit, lockname, mustbenew; env, cx
+        )?;
+        return Ok(());
+    }
     ensure!(append.is_none(), "append not implemented");
     ensure!(visit.is_none(), "visit not implemented");
     ensure!(lockname.is_none(), "lockname not implemented");
     ensure!(mustbenew.is_none(), "mustbenew not implemented");
-    // Open filename for writing
-    let mut file = std::fs::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(filename)
-        .unwrap();
-    env.with_buffer(None, |b| -> Result<()> {
-        let (s1, s2) = b.slice_with_gap(start as usize, end as usize)?;
-        write!(file, "{s1}").unwrap();
-        write!(file, "{s2}").unwrap();
-        Ok(())
-    })
-    .unwrap()?;
+    let coding = Coding::from_variable(sym::CODING_SYSTEM_FOR_WRITE, env, cx)?;
+    let text = env
+        .with_buffer(None, |b| -> Result<String> {
+            let (s1, s2) = b.slice_with_gap(start as usize, end as usize)?;
+            Ok(format!("{s1}{s2}"))
+        })
+        .unwrap()?;
+    std::fs::write(filename, coding.encode(&text))
+        .with_context(|| format!("Couldn't write file {filename}"))?;
     Ok(())
 }
 
-/// Concatenate components to directory, inserting path separators as required.
+/// Insert the contents of FILENAME after point, decoded with
+/// `coding-system-for-read`. Returns a list of the absolute file name and the
+/// number of characters inserted.
+#[defun(io)]
+fn insert_file_contents<'ob>(
+    filename: &str,
+    visit: Option<()>,
+    beg: Option<()>,
+    end: Option<()>,
+    replace: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    if let Some(handler) = file_handler(filename, sym::INSERT_FILE_CONTENTS, env, cx)? {
+        root!(handler, cx);
+        let operation = Object::from(sym::INSERT_FILE_CONTENTS);
+        let flags = [visit, beg, end, replace].map(|x| x.is_some());
+        let [visit, beg, end, replace] = flags;
+        let filename = cx.add(filename);
+        return Ok(call!(handler, operation, filename, visit, beg, end, replace; env, cx)?);
+    }
+    ensure!(visit.is_none(), "visit not implemented");
+    ensure!(beg.is_none() && end.is_none(), "beg and end not implemented");
+    ensure!(replace.is_none(), "replace not implemented");
+    let filename = expand_file_name(filename, None, env, cx)?;
+    let coding = Coding::from_variable(sym::CODING_SYSTEM_FOR_READ, env, cx)?;
+    let bytes = std::fs::read(&filename).with_context(|| format!("Couldn't open file {filename}"))?;
+    let text = coding.decode(&bytes);
+    let Some(buffer) = env.current_buffer.as_mut() else { bail!("No current buffer") };
+    let point = buffer.text.cursor().chars();
+    buffer.text.insert(&text);
+    buffer.text.set_cursor(point);
+    Ok(list![filename, text.chars().count(); cx])
+}
+
 #[defun]
 fn file_name_concat(directory: &str, rest_components: &[Object]) -> Result<String> {
     let mut path = String::from(directory);
@@ -253,5 +529,3 @@ fn file_name_concat(directory: &str, rest_components: &[Object]) -> Result<Strin
 
 // TODO: file-relative-name -- requires knowing the current buffer's default directory
 // TODO: file-name-sans-versions
-// TODO: find-file-name-handler: https://www.gnu.org/software/emacs/manual/html_node/elisp/Magic-File-Names.html
-//   required by file-name-extension  & file-name-sans-extension library & file-relative-name functions (among others)
@@ -1,11 +1,11 @@
 use rune_macros::defun;
 
-#[defun]
+#[defun(io)]
 fn lock_buffer(_file: Option<()>) {
     // TODO: implement
 }
 
-#[defun]
+#[defun(io)]
 fn unlock_buffer() {
     // TODO: implement
 }
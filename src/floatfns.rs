@@ -1,13 +1,16 @@
 //! Operations on floats.
 use crate::{
-    arith::NumberValue,
+    arith::{arith_error, NumberValue},
     core::{
         cons::Cons,
-        gc::Context,
-        object::{Number, NumberType, Object},
+        env::{sym, Env},
+        gc::{Context, Rt},
+        object::{Number, NumberType, Object, MAX_FIXNUM, MIN_FIXNUM, NIL},
     },
+    eval::EvalError,
 };
 
+use anyhow::Result;
 use rune_macros::defun;
 
 #[inline(always)]
@@ -54,11 +57,27 @@ fn round(arg: Number) -> i64 {
     }
 }
 
+/// Convert `float`, which has no fraction, to an integer. Floats that are not
+/// finite or are too large for a fixnum signal `overflow-error`.
+fn to_fixnum(float: f64, env: &mut Rt<Env>) -> Result<i64> {
+    if float.is_finite() && (MIN_FIXNUM as f64..=MAX_FIXNUM as f64).contains(&float) {
+        Ok(float as i64)
+    } else {
+        Err(EvalError::signal(sym::OVERFLOW_ERROR.into(), NIL, env).into())
+    }
+}
+
+/// Truncate ARG toward zero, after dividing it by DIVISOR if that is given.
 #[defun]
-fn truncate(arg: Number) -> i64 {
-    match arg.untag() {
-        NumberType::Int(i) => i,
-        NumberType::Float(f) => f.trunc() as i64,
+fn truncate(arg: Number, divisor: Option<Number>, env: &mut Rt<Env>) -> Result<i64> {
+    let num = match divisor.map(Number::val) {
+        Some(NumberValue::Int(0)) => return Err(arith_error(env)),
+        Some(div) => arg.val() / div,
+        None => arg.val(),
+    };
+    match num {
+        NumberValue::Int(i) => Ok(i),
+        NumberValue::Float(f) => to_fixnum(f.trunc(), env),
     }
 }
 
@@ -159,8 +178,16 @@ fn abs(arg: Number) -> NumberValue {
 
 #[defun]
 fn ldexp(s: Number, e: i64) -> f64 {
-    // TODO: overflow check -> bail!?
-    coerce(s) * 2f64.powi(e as i32)
+    // Scale in steps, so a power of two that doesn't fit in a float can still
+    // give a result that does
+    let mut float = coerce(s);
+    let mut e = e.clamp(-2200, 2200);
+    while e != 0 && float != 0.0 && float.is_finite() {
+        let step = e.clamp(-1000, 1000);
+        float *= 2f64.powi(step as i32);
+        e -= step;
+    }
+    float
 }
 
 #[defun]
@@ -170,17 +197,21 @@ fn logb(arg: Number) -> i64 {
     l2.floor() as i64
 }
 
-// Rust does not have frexp, so we have to implement it ourselves
-// Source: https://stackoverflow.com/questions/55690397/where-is-the-frexp-function-for-f32-in-rust
+// Rust does not have frexp, so we have to implement it ourselves. The
+// significand is the float with its exponent bits replaced by those of 0.5.
 fn frexp_f(s: f64) -> (f64, i64) {
-    if 0.0 == s {
-        (s, 0)
-    } else {
-        let lg2 = s.abs().log2();
-        let x = (lg2 - lg2.floor() - 1.0).exp2();
-        let exp = lg2.floor() + 1.0;
-        (s.signum() * x, exp as i64)
+    const EXPONENT: u64 = 0x7FF << 52;
+    if s == 0.0 || !s.is_finite() {
+        return (s, 0);
     }
+    let bits = s.to_bits();
+    let exponent = ((bits & EXPONENT) >> 52) as i64;
+    if exponent == 0 {
+        // Subnormal floats have no implicit leading bit, so normalize first
+        let (significand, exponent) = frexp_f(s * 2f64.powi(64));
+        return (significand, exponent - 64);
+    }
+    (f64::from_bits((bits & !EXPONENT) | (1022 << 52)), exponent - 1022)
 }
 
 #[defun]
@@ -189,3 +220,33 @@ fn frexp<'ob>(x: Number, cx: &'ob Context) -> Object<'ob> {
     let (significand, exponent) = frexp_f(f);
     Cons::new(significand, exponent, cx).into()
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use rune_core::macros::root;
+
+    #[test]
+    fn test_frexp_ldexp() {
+        assert_eq!(frexp_f(8.0), (0.5, 4));
+        assert_eq!(frexp_f(-3.0), (-0.75, 2));
+        assert_eq!(frexp_f(f64::MIN_POSITIVE / 4.0), (0.5, -1023));
+        assert_eq!(ldexp(1.into(), -1074), 5e-324);
+        assert_eq!(ldexp(1.into(), 1024), f64::INFINITY);
+        assert_eq!(ldexp(0.into(), 5000), 0.0);
+    }
+
+    #[test]
+    fn test_truncate() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        assert_eq!(truncate(cx.add_as(-7.5), None, env).unwrap(), -7);
+        assert_eq!(truncate(7.into(), Some(2.into()), env).unwrap(), 3);
+        assert_eq!(truncate(7.into(), Some(cx.add_as(2.0)), env).unwrap(), 3);
+        assert!(truncate(7.into(), Some(0.into()), env).is_err());
+        assert!(truncate(cx.add_as(f64::NAN), None, env).is_err());
+        assert!(truncate(cx.add_as(1e30), None, env).is_err());
+    }
+}
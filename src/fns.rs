@@ -7,7 +7,7 @@ use crate::{
         gc::{Context, Rt, Rto},
         object::{
             Function, Gc, HashTable, IntoObject, LispHashTable, LispString, LispVec, List,
-            ListType, Object, ObjectType, Symbol, WithLifetime, NIL,
+            ListType, Object, ObjectType, RecordBuilder, Symbol, WithLifetime, NIL, TRUE,
         },
     },
     data::aref,
@@ -18,6 +18,9 @@ use fallible_iterator::FallibleIterator;
 use fallible_streaming_iterator::FallibleStreamingIterator;
 use rune_core::macros::{call, list, rebind, root};
 use rune_macros::defun;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::ffi::CString;
 
 #[defun]
 fn identity(arg: Object) -> Object {
@@ -118,9 +121,164 @@ fn string_to_multibyte(string: &LispString) -> &LispString {
 }
 
 #[defun]
-fn string_search(needle: &str, haystack: &str, start_pos: Option<usize>) -> Option<usize> {
-    let start = start_pos.unwrap_or(0);
-    haystack[start..].find(needle).map(|x| x + start)
+fn string_search(
+    needle: &str,
+    haystack: &LispString,
+    start_pos: Option<usize>,
+) -> Result<Option<usize>> {
+    let start_pos = start_pos.unwrap_or(0);
+    let Some(start) = haystack.char_to_byte(start_pos) else {
+        bail!("Args out of range: {haystack}, {start_pos}")
+    };
+    let Some(found) = haystack[start..].find(needle) else { return Ok(None) };
+    Ok(Some(start_pos + haystack[start..start + found].chars().count()))
+}
+
+/// The character positions START..END of a string with `len` characters.
+/// Negative positions count from the end.
+pub(crate) fn char_range(
+    start: Option<i64>,
+    end: Option<i64>,
+    len: usize,
+) -> Result<(usize, usize)> {
+    let len = len as i64;
+    let resolve = |pos: i64| if pos < 0 { pos + len } else { pos };
+    let from = resolve(start.unwrap_or(0));
+    let to = resolve(end.unwrap_or(len));
+    ensure!(0 <= from && from <= to && to <= len, "Args out of range: {start:?}, {end:?}");
+    Ok((from as usize, to as usize))
+}
+
+/// Compare the characters START1..END1 of STRING1 with START2..END2 of
+/// STRING2. Returns t if they are the same. Otherwise returns -N if STRING1
+/// is less than STRING2 and N if it is greater, where N - 1 is the number of
+/// characters that matched. With IGNORE-CASE, characters are compared after
+/// they are upcased.
+#[defun]
+fn compare_strings(
+    string1: &str,
+    start1: Option<i64>,
+    end1: Option<i64>,
+    string2: &str,
+    start2: Option<i64>,
+    end2: Option<i64>,
+    ignore_case: Option<()>,
+) -> Result<Object<'static>> {
+    let (len1, len2) = (string1.chars().count(), string2.chars().count());
+    // An end past the end of the string is treated as the end
+    let (end1, end2) = (end1.map(|x| x.min(len1 as i64)), end2.map(|x| x.min(len2 as i64)));
+    let (from1, to1) = char_range(start1, end1, len1)?;
+    let (from2, to2) = char_range(start2, end2, len2)?;
+    let fold = |c: char| match ignore_case {
+        Some(()) => c.to_uppercase().next().unwrap_or(c),
+        None => c,
+    };
+    let mut chars1 = string1.chars().take(to1).skip(from1).map(fold);
+    let mut chars2 = string2.chars().take(to2).skip(from2).map(fold);
+    let mut n: i64 = 1;
+    loop {
+        let order = match (chars1.next(), chars2.next()) {
+            (None, None) => return Ok(TRUE),
+            (Some(c1), Some(c2)) => c1.cmp(&c2),
+            (None, Some(_)) => Ordering::Less,
+            (Some(_), None) => Ordering::Greater,
+        };
+        match order {
+            Ordering::Equal => n += 1,
+            Ordering::Less => return Ok((-n).into()),
+            Ordering::Greater => return Ok(n.into()),
+        }
+    }
+}
+
+/// The Levenshtein distance between STRING1 and STRING2, the number of
+/// insertions, deletions and substitutions that turn one into the other.
+/// Characters are compared, or bytes with BYTECOMPARE.
+#[defun]
+fn string_distance(string1: &str, string2: &str, bytecompare: Option<()>) -> usize {
+    match bytecompare {
+        Some(()) => levenshtein(string1.as_bytes(), string2.as_bytes()),
+        None => {
+            let chars1: Vec<char> = string1.chars().collect();
+            let chars2: Vec<char> = string2.chars().collect();
+            levenshtein(&chars1, &chars2)
+        }
+    }
+}
+
+fn levenshtein<T: PartialEq>(a: &[T], b: &[T]) -> usize {
+    // The distances from a prefix of `a` to every prefix of `b`
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let substitute = diagonal + usize::from(x != y);
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Return t if STRING1 sorts before STRING2 in the collation order of
+/// LOCALE. When LOCALE is nil, the locale is taken from the environment like
+/// `setlocale` does. The "C" and "POSIX" locales sort by codepoint. With
+/// IGNORE-CASE, the strings are compared after they are downcased.
+#[defun]
+fn string_collate_lessp(
+    string1: &str,
+    string2: &str,
+    locale: Option<&str>,
+    ignore_case: Option<()>,
+) -> Result<bool> {
+    Ok(collate(string1, string2, locale, ignore_case.is_some())?.is_lt())
+}
+
+/// Return t if STRING1 and STRING2 are equal in the collation order of
+/// LOCALE. See `string-collate-lessp`.
+#[defun]
+fn string_collate_equalp(
+    string1: &str,
+    string2: &str,
+    locale: Option<&str>,
+    ignore_case: Option<()>,
+) -> Result<bool> {
+    Ok(collate(string1, string2, locale, ignore_case.is_some())?.is_eq())
+}
+
+fn collate(s1: &str, s2: &str, locale: Option<&str>, ignore_case: bool) -> Result<Ordering> {
+    let (s1, s2) = match ignore_case {
+        true => (Cow::Owned(s1.to_lowercase()), Cow::Owned(s2.to_lowercase())),
+        false => (Cow::Borrowed(s1), Cow::Borrowed(s2)),
+    };
+    let name = match locale {
+        Some(locale) => locale.to_owned(),
+        None => ["LC_ALL", "LC_COLLATE", "LANG"]
+            .into_iter()
+            .find_map(|var| std::env::var(var).ok().filter(|x| !x.is_empty()))
+            .unwrap_or_default(),
+    };
+    if matches!(name.as_str(), "" | "C" | "POSIX") {
+        return Ok(s1.cmp(&s2));
+    }
+    let (c_name, c1, c2) = (CString::new(name.as_str())?, CString::new(&*s1)?, CString::new(&*s2)?);
+    let null = std::ptr::null_mut();
+    let new = unsafe { libc::newlocale(libc::LC_COLLATE_MASK, c_name.as_ptr(), null) };
+    if new.is_null() {
+        // An unknown locale in the environment is ignored, like setlocale does
+        ensure!(locale.is_none(), "Invalid locale: {name}");
+        return Ok(s1.cmp(&s2));
+    }
+    // strcoll uses the locale of the current thread
+    let order = unsafe {
+        let old = libc::uselocale(new);
+        let order = libc::strcoll(c1.as_ptr(), c2.as_ptr());
+        libc::uselocale(old);
+        libc::freelocale(new);
+        order
+    };
+    Ok(order.cmp(&0))
 }
 
 #[defun]
@@ -148,7 +306,7 @@ pub(crate) fn mapcar<'ob>(
             root!(fun, cx);
             root!(outputs, new(Vec), cx);
             for i in 0..len {
-                let val = fun.bind(cx).index(i, cx).unwrap();
+                let val = crate::bytecode::byte_fn_index(fun.bind(cx), i, cx).unwrap();
                 let output = call!(function, val; env, cx)?;
                 outputs.push(output);
             }
@@ -273,6 +431,39 @@ fn take<'ob>(n: i64, list: List<'ob>, cx: &'ob Context) -> Result<Object<'ob>> {
     Ok(build_list(list.elements().take(n), cx)?)
 }
 
+/// Truncate LIST to its first N elements and return it. LIST is modified.
+#[defun]
+fn ntake<'ob>(n: i64, list: List<'ob>) -> Result<Object<'ob>> {
+    let Some(n) = usize::try_from(n).ok().filter(|&n| n > 0) else { return Ok(NIL) };
+    if let Some(cons) = list.conses().fallible().nth(n - 1)? {
+        cons.set_cdr(NIL)?;
+    }
+    Ok(list.into())
+}
+
+/// Return the last link of LIST, or the Nth-to-last link if N is given. If N
+/// is at least the length of LIST, return LIST.
+#[defun]
+fn last<'ob>(list: List<'ob>, n: Option<i64>) -> Object<'ob> {
+    let Ok(n) = usize::try_from(n.unwrap_or(1)) else { return NIL };
+    // Move a lead N links ahead and then walk both to the end. This finds the
+    // link in one pass instead of taking the length first. Like
+    // `safe-length`, a circular list ends where the cycle is found.
+    let mut lead = list.conses();
+    for _ in 0..n {
+        if !matches!(lead.next(), Some(Ok(_))) {
+            return list.into();
+        }
+    }
+    let mut tail: Object = list.into();
+    while let Some(Ok(_)) = lead.next() {
+        if let ObjectType::Cons(cons) = tail.untag() {
+            tail = cons.cdr();
+        }
+    }
+    tail
+}
+
 #[defun]
 pub(crate) fn append<'ob>(
     append: Object<'ob>,
@@ -498,10 +689,32 @@ pub(crate) fn defvaralias<'ob>(
     new_alias
 }
 
+/// Return t if FEATURE is present in this Emacs. If SUBFEATURE is non-nil,
+/// it must also be a member of the `subfeatures` property of FEATURE.
 #[defun]
-// TODO: implement
-pub(crate) fn featurep(_feature: Symbol, _subfeature: Option<Symbol>) {}
+pub(crate) fn featurep(
+    feature: Symbol,
+    subfeature: Option<Object>,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    // TODO: Fix this unsafe into_root
+    let feat = unsafe { feature.with_lifetime() };
+    if !crate::data::features().lock().unwrap().contains(&feat) {
+        return Ok(false);
+    }
+    match subfeature {
+        Some(subfeature) => {
+            let subfeatures = crate::data::get(feature, sym::SUBFEATURES, env, cx);
+            Ok(!member(subfeature, subfeatures.try_into()?)?.is_nil())
+        }
+        None => Ok(true),
+    }
+}
 
+/// If FEATURE is not already present, load FILENAME, or the file named after
+/// FEATURE, and signal an error if it does not provide FEATURE. When the file
+/// can't be found, NOERROR means to return nil instead of signaling.
 #[defun]
 pub(crate) fn require<'ob>(
     feature: &Rto<Gc<Symbol>>,
@@ -509,11 +722,9 @@ pub(crate) fn require<'ob>(
     noerror: Option<()>,
     env: &mut Rt<Env>,
     cx: &'ob mut Context,
-) -> Result<Symbol<'ob>> {
-    // TODO: Fix this unsafe into_root
-    let feat = unsafe { feature.untag(cx).with_lifetime() };
-    if crate::data::features().lock().unwrap().contains(&feat) {
-        return Ok(feature.untag(cx));
+) -> Result<Object<'ob>> {
+    if featurep(feature.untag(cx), None, env, cx)? {
+        return Ok(feature.untag(cx).into());
     }
     let file = match filename {
         Some(file) => file.untag(cx),
@@ -521,10 +732,15 @@ pub(crate) fn require<'ob>(
     };
     let file = file.into_obj(cx);
     root!(file, cx);
-    match crate::lread::load(file, noerror, None, cx, env) {
-        Ok(_) => Ok(feature.untag(cx)),
-        Err(e) => Err(e),
+    if !crate::lread::load(file, noerror, Some(()), cx, env)? {
+        return Ok(NIL);
     }
+    let feature = feature.untag(cx);
+    ensure!(
+        featurep(feature, None, env, cx)?,
+        "Required feature `{feature}' was not provided"
+    );
+    Ok(feature.into())
 }
 
 #[defun]
@@ -584,7 +800,11 @@ pub(crate) fn length(sequence: Object) -> Result<usize> {
 
 #[defun]
 pub(crate) fn safe_length(sequence: Object) -> usize {
-    length(sequence).unwrap_or(0)
+    match sequence.untag() {
+        // A circular list counts the conses up to where the cycle was found
+        ObjectType::Cons(x) => x.conses().take_while(Result::is_ok).count(),
+        _ => length(sequence).unwrap_or(0),
+    }
 }
 
 #[defun]
@@ -742,6 +962,14 @@ fn copy_sequence<'ob>(arg: Object<'ob>, cx: &'ob Context) -> Result<Object<'ob>>
             }
             Ok(slice_into_list(&elements, tail, cx))
         }
+        ObjectType::Record(x) => {
+            let mut record = cx.vec_with_capacity(x.len());
+            record.extend(x.iter().map(|x| x.get()));
+            let copy = RecordBuilder(record).into_obj(cx);
+            // The copy shares any Rust data attached to the original
+            copy.untag().set_attached(x.attached());
+            Ok(copy.into())
+        }
         ObjectType::String(x) => Ok(cx.add(x.to_owned())),
         ObjectType::NIL => Ok(NIL),
         _ => Err(TypeError::new(Type::Sequence, arg).into()),
@@ -749,20 +977,11 @@ fn copy_sequence<'ob>(arg: Object<'ob>, cx: &'ob Context) -> Result<Object<'ob>>
 }
 
 #[defun]
-fn substring(string: &str, from: Option<usize>, to: Option<usize>) -> Result<String> {
-    if from.unwrap_or_default() > string.len() || to.unwrap_or_default() > string.len() {
-        bail!("substring args out of range for {string} : {from:?} {to:?}");
-    }
-    let new_string = match (from, to) {
-        (None, None) => string,
-        (None, Some(t)) => &string[..t],
-        (Some(f), None) => &string[f..],
-        (Some(f), Some(t)) => {
-            let range = if f > t { t..f } else { f..t };
-            &string[range]
-        }
-    };
-    Ok(new_string.to_owned())
+fn substring(string: &LispString, from: Option<i64>, to: Option<i64>) -> Result<String> {
+    let (from, to) = char_range(from, to, string.len())?;
+    let start = string.char_to_byte(from).unwrap();
+    let end = string.char_to_byte(to).unwrap();
+    Ok(string[start..end].to_owned())
 }
 
 defsym!(MD5);
@@ -774,13 +993,205 @@ defsym!(SHA512);
 
 #[defun]
 fn secure_hash_algorithms<'ob>(cx: &'ob Context) -> Object<'ob> {
-    // https://crates.io/crates/md-5
-    // https://crates.io/crates/sha1
-    // https://crates.io/crates/sha2
-    // https://crates.io/crates/digest ?
     list![sym::MD5, sym::SHA1, sym::SHA224, sym::SHA256, sym::SHA384, sym::SHA512; cx]
 }
 
+/// The bytes of OBJECT between START and END. For strings these are
+/// character indexes, and for buffers they are positions.
+fn object_bytes(
+    object: Object,
+    start: Option<i64>,
+    end: Option<i64>,
+    env: &Rt<Env>,
+) -> Result<Vec<u8>> {
+    match object.untag() {
+        ObjectType::String(string) => {
+            let (from, to) = char_range(start, end, string.len())?;
+            let from = string.char_to_byte(from).unwrap();
+            let to = string.char_to_byte(to).unwrap();
+            Ok(string.as_bytes()[from..to].to_vec())
+        }
+        ObjectType::ByteString(string) => {
+            let (from, to) = char_range(start, end, string.len())?;
+            Ok(string[from..to].to_vec())
+        }
+        ObjectType::Buffer(buffer) => {
+            let text = env.with_buffer(Some(buffer), |b| -> Result<Vec<u8>> {
+                let beg = start.unwrap_or(1);
+                let end = end.unwrap_or(b.text.len_chars() as i64 + 1);
+                let (s1, s2) = b.slice_with_gap(beg.min(end) as usize, beg.max(end) as usize)?;
+                Ok([s1.as_bytes(), s2.as_bytes()].concat())
+            });
+            let Some(text) = text else { bail!("Selecting deleted buffer") };
+            text
+        }
+        _ => Err(TypeError::new(Type::BufferOrName, object).into()),
+    }
+}
+
+fn digest(algorithm: Symbol, bytes: &[u8]) -> Result<Vec<u8>> {
+    use sha2::Digest;
+    let digest = match algorithm {
+        sym::MD5 => ::md5::Md5::digest(bytes).to_vec(),
+        sym::SHA1 => sha1::Sha1::digest(bytes).to_vec(),
+        sym::SHA224 => sha2::Sha224::digest(bytes).to_vec(),
+        sym::SHA256 => sha2::Sha256::digest(bytes).to_vec(),
+        sym::SHA384 => sha2::Sha384::digest(bytes).to_vec(),
+        sym::SHA512 => sha2::Sha512::digest(bytes).to_vec(),
+        _ => bail!("Invalid algorithm arg: {algorithm}"),
+    };
+    Ok(digest)
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    use std::fmt::Write as _;
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(hex, "{byte:02x}").unwrap();
+    }
+    hex
+}
+
+/// Return the hash of OBJECT, a string or buffer, using ALGORITHM, one of
+/// `secure-hash-algorithms`. START and END select part of OBJECT. The hash is
+/// a string of hex digits, or a unibyte string of the raw bytes if BINARY is
+/// non-nil. Multibyte text is hashed as UTF-8.
+#[defun]
+fn secure_hash<'ob>(
+    algorithm: Symbol,
+    object: Object,
+    start: Option<i64>,
+    end: Option<i64>,
+    binary: Option<()>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let digest = digest(algorithm, &object_bytes(object, start, end, env)?)?;
+    if binary.is_some() {
+        Ok(cx.add(digest))
+    } else {
+        Ok(cx.add(hex_string(&digest)))
+    }
+}
+
+/// Return the MD5 hash of OBJECT, a string or buffer, as a string of hex
+/// digits. START and END select part of OBJECT. Multibyte text is always
+/// hashed as UTF-8, so CODING-SYSTEM and NOERROR are ignored.
+#[defun]
+fn md5(
+    object: Object,
+    start: Option<i64>,
+    end: Option<i64>,
+    _coding_system: Option<()>,
+    _noerror: Option<()>,
+    env: &Rt<Env>,
+) -> Result<String> {
+    let digest = digest(sym::MD5, &object_bytes(object, start, end, env)?)?;
+    Ok(hex_string(&digest))
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BASE64URL: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// The bytes of a string to be base64 encoded. Multibyte strings can only
+/// hold ASCII, other text has to be encoded first.
+fn base64_input(string: Object) -> Result<&[u8]> {
+    match string.untag() {
+        ObjectType::String(string) => {
+            ensure!(string.is_ascii(), "Multibyte character in data for base64 encoding");
+            Ok(string.as_bytes())
+        }
+        ObjectType::ByteString(string) => Ok(string.inner()),
+        _ => Err(TypeError::new(Type::String, string).into()),
+    }
+}
+
+fn base64_encode(bytes: &[u8], alphabet: &[u8; 64], pad: bool, line_break: bool) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for (i, chunk) in bytes.chunks(3).enumerate() {
+        // lines are 76 characters, which is 19 chunks
+        if line_break && i > 0 && i % 19 == 0 {
+            encoded.push('\n');
+        }
+        let byte = |i: usize| u32::from(chunk.get(i).copied().unwrap_or(0));
+        let group = byte(0) << 16 | byte(1) << 8 | byte(2);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (group >> (18 - 6 * i)) & 0x3f;
+                encoded.push(char::from(alphabet[index as usize]));
+            } else if pad {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Base64 encode STRING and return the result. The lines are broken every 76
+/// characters unless NO-LINE-BREAK is non-nil. STRING must be unibyte or
+/// ASCII.
+#[defun]
+fn base64_encode_string(string: Object, no_line_break: Option<()>) -> Result<String> {
+    Ok(base64_encode(base64_input(string)?, BASE64, true, no_line_break.is_none()))
+}
+
+/// Base64url encode STRING and return the result. The padding is left off if
+/// NO-PAD is non-nil. STRING must be unibyte or ASCII.
+#[defun]
+fn base64url_encode_string(string: Object, no_pad: Option<()>) -> Result<String> {
+    Ok(base64_encode(base64_input(string)?, BASE64URL, no_pad.is_none(), false))
+}
+
+/// Base64 decode STRING and return the result as a unibyte string. If
+/// BASE64URL is non-nil the base64url alphabet is used. Whitespace is
+/// skipped, and so are other invalid characters if IGNORE-INVALID is non-nil.
+#[defun]
+fn base64_decode_string<'ob>(
+    string: Object,
+    base64url: Option<()>,
+    ignore_invalid: Option<()>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let text = match string.untag() {
+        ObjectType::String(string) => string.as_bytes(),
+        ObjectType::ByteString(string) => string.inner(),
+        _ => bail!(TypeError::new(Type::String, string)),
+    };
+    let (url, ignore_invalid) = (base64url.is_some(), ignore_invalid.is_some());
+    let mut decoded = Vec::with_capacity(text.len() / 4 * 3);
+    let (mut group, mut bits) = (0u32, 0);
+    let mut padded = false;
+    for &c in text {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' if !url => 62,
+            b'/' if !url => 63,
+            b'-' if url => 62,
+            b'_' if url => 63,
+            b'=' => {
+                padded = true;
+                continue;
+            }
+            b' ' | b'\t' | b'\n' | b'\x0c' | b'\r' => continue,
+            _ if ignore_invalid => continue,
+            _ => bail!("Invalid base64 data"),
+        };
+        ensure!(!padded || ignore_invalid, "Invalid base64 data");
+        group = group << 6 | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((group >> bits) as u8);
+            group &= (1 << bits) - 1;
+        }
+    }
+    // a single character can't hold a whole byte
+    ensure!(bits < 6 || ignore_invalid, "Invalid base64 data");
+    Ok(cx.add(decoded))
+}
+
 #[defun]
 fn enable_debug() -> bool {
     crate::debug::enable_debug();
@@ -800,7 +1211,7 @@ fn disable_debug() -> bool {
 
 #[cfg(test)]
 mod test {
-    use crate::core::{gc::RootSet, object::TRUE};
+    use crate::core::{env::intern, gc::RootSet, object::TRUE};
     use rune_core::macros::root;
 
     use super::*;
@@ -839,6 +1250,126 @@ mod test {
         assert_eq!(res.untag().car(), 2);
     }
 
+    #[test]
+    fn test_ntake() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let list = list![1, 2, 3, 4; cx];
+        assert_eq!(ntake(2, list.try_into().unwrap()).unwrap(), list![1, 2; cx]);
+        assert_eq!(list, list![1, 2; cx]);
+        assert_eq!(ntake(5, list.try_into().unwrap()).unwrap(), list![1, 2; cx]);
+        assert_eq!(ntake(0, list.try_into().unwrap()).unwrap(), NIL);
+    }
+
+    #[test]
+    fn test_last() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let list = list![1, 2, 3; cx];
+        let last = |n| super::last(list.try_into().unwrap(), n);
+        assert_eq!(last(None), list![3; cx]);
+        assert_eq!(last(Some(2)), list![2, 3; cx]);
+        assert_eq!(last(Some(3)), list);
+        assert_eq!(last(Some(10)), list);
+        assert_eq!(last(Some(0)), NIL);
+        assert_eq!(last(Some(-1)), NIL);
+        assert_eq!(super::last(ListType::empty(), None), NIL);
+        let dotted = Cons::new(1, 2, cx).into();
+        assert_eq!(super::last(dotted, Some(0)), 2);
+    }
+
+    #[test]
+    fn test_features() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        let source = "
+(setq after-load-alist
+      (list (list 'fns-test-feature (lambda () (setq fns-test-loaded t)))))
+(provide 'fns-test-feature '(sub))
+(setq fns-test-required (require 'fns-test-feature))
+(setq fns-test-missing (require 'fns-test-missing nil t))";
+        crate::lread::load_internal(source, cx, env).unwrap();
+        let var = |name| env.vars.get(intern(name, cx)).unwrap().bind(cx);
+        assert_eq!(var("fns-test-loaded"), TRUE);
+        assert_eq!(var("fns-test-required"), intern("fns-test-feature", cx));
+        assert_eq!(var("fns-test-missing"), NIL);
+
+        let feature = intern("fns-test-feature", cx);
+        let features = env.vars.get(sym::FEATURES).unwrap().bind(cx);
+        assert!(!memq(feature.into(), features.try_into().unwrap()).unwrap().is_nil());
+        assert!(featurep(feature, None, env, cx).unwrap());
+        assert!(featurep(feature, Some(intern("sub", cx).into()), env, cx).unwrap());
+        assert!(!featurep(feature, Some(intern("other", cx).into()), env, cx).unwrap());
+        assert!(!featurep(intern("fns-test-missing", cx), None, env, cx).unwrap());
+    }
+
+    #[test]
+    fn test_secure_hash() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        let abc = cx.add("abc");
+        let hash = |algorithm| secure_hash(algorithm, abc, None, None, None, env, cx).unwrap();
+        assert_eq!(hash(sym::MD5), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(hash(sym::SHA1), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(hash(sym::SHA224), "23097d223405d8228642a477bda255b32aadbce4bda0b3f7e36c9da7");
+        let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(hash(sym::SHA256), sha256);
+        assert!(secure_hash(sym::NIL, abc, None, None, None, env, cx).is_err());
+
+        let binary = secure_hash(sym::SHA256, abc, None, None, Some(()), env, cx).unwrap();
+        let ObjectType::ByteString(binary) = binary.untag() else { unreachable!() };
+        assert_eq!(binary.len(), 32);
+        assert_eq!(binary[0], 0xba);
+
+        let padded = cx.add("xabcx");
+        let md5_abc = md5(padded, Some(1), Some(-1), None, None, env).unwrap();
+        assert_eq!(md5_abc, "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(
+            md5(cx.add(""), None, None, None, None, env).unwrap(),
+            "d41d8cd98f00b204e9800998ecf8427e"
+        );
+    }
+
+    #[test]
+    fn test_base64() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let encode = |string: &str| base64_encode_string(cx.add(string), None).unwrap();
+        assert_eq!(encode(""), "");
+        assert_eq!(encode("hi"), "aGk=");
+        assert_eq!(encode("hello"), "aGVsbG8=");
+        assert_eq!(encode("hello!"), "aGVsbG8h");
+        assert!(base64_encode_string(cx.add("λ"), None).is_err());
+        // lines are broken after 76 characters
+        assert!(!encode(&"a".repeat(57)).contains('\n'));
+        assert_eq!(encode(&"a".repeat(58)).find('\n'), Some(76));
+        let long = base64_encode_string(cx.add("a".repeat(58)), Some(())).unwrap();
+        assert!(!long.contains('\n'));
+
+        let bytes = cx.add(vec![0xfb_u8, 0xff]);
+        assert_eq!(base64_encode_string(bytes, None).unwrap(), "+/8=");
+        assert_eq!(base64url_encode_string(bytes, None).unwrap(), "-_8=");
+        assert_eq!(base64url_encode_string(bytes, Some(())).unwrap(), "-_8");
+
+        let decode = |string: &str, url, ignore| {
+            let decoded = base64_decode_string(cx.add(string), url, ignore, cx)?;
+            let ObjectType::ByteString(decoded) = decoded.untag() else { unreachable!() };
+            Ok::<_, anyhow::Error>(decoded.to_vec())
+        };
+        assert_eq!(decode("aGVsbG8=", None, None).unwrap(), b"hello");
+        assert_eq!(decode("aGVs\nbG8", None, None).unwrap(), b"hello");
+        assert_eq!(decode("-_8", Some(()), None).unwrap(), [0xfb, 0xff]);
+        assert_eq!(decode("+/8=", None, None).unwrap(), [0xfb, 0xff]);
+        assert!(decode("-_8", None, None).is_err());
+        assert!(decode("aGk=aGk=", None, None).is_err());
+        assert!(decode("aGVsbG8*", None, None).is_err());
+        assert_eq!(decode("aGVsbG8*", None, Some(())).unwrap(), b"hello");
+        assert!(decode("aGk=a", None, None).is_err());
+    }
+
     #[test]
     fn test_reverse() {
         let roots = &RootSet::default();
@@ -1023,4 +1554,57 @@ mod test {
         let result = copy_alist(list, cx).unwrap();
         assert_eq!(alist, result);
     }
+
+    #[test]
+    fn test_compare_strings() {
+        assert_eq!(compare_strings("abc", None, None, "abc", None, None, None).unwrap(), TRUE);
+        assert_eq!(compare_strings("abd", None, None, "abc", None, None, None).unwrap(), 3);
+        assert_eq!(compare_strings("ab", None, None, "abc", None, None, None).unwrap(), -3);
+        assert_eq!(compare_strings("xbc", Some(1), None, "bc", None, Some(9), None).unwrap(), TRUE);
+        assert_eq!(compare_strings("ABC", None, None, "abc", None, None, Some(())).unwrap(), TRUE);
+        assert_eq!(compare_strings("ab", Some(-1), None, "b", None, None, None).unwrap(), TRUE);
+        assert!(compare_strings("ab", Some(3), None, "b", None, None, None).is_err());
+    }
+
+    #[test]
+    fn test_string_search() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let string: Gc<&LispString> = cx.add_as("é中bé中");
+        let string = string.untag();
+        assert_eq!(string_search("中", string, None).unwrap(), Some(1));
+        assert_eq!(string_search("中", string, Some(2)).unwrap(), Some(4));
+        assert_eq!(string_search("x", string, None).unwrap(), None);
+        assert!(string_search("中", string, Some(6)).is_err());
+    }
+
+    #[test]
+    fn test_substring() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let string: Gc<&LispString> = cx.add_as("aé中b");
+        let string = string.untag();
+        assert_eq!(substring(string, Some(1), Some(3)).unwrap(), "é中");
+        assert_eq!(substring(string, Some(-2), None).unwrap(), "中b");
+        assert_eq!(substring(string, None, Some(-3)).unwrap(), "a");
+        assert!(substring(string, Some(2), Some(1)).is_err());
+        assert!(substring(string, None, Some(5)).is_err());
+    }
+
+    #[test]
+    fn test_string_distance() {
+        assert_eq!(string_distance("kitten", "sitting", None), 3);
+        assert_eq!(string_distance("", "abc", None), 3);
+        assert_eq!(string_distance("ab", "ab", None), 0);
+        assert_eq!(string_distance("é", "e", None), 1);
+        assert_eq!(string_distance("é", "e", Some(())), 2);
+    }
+
+    #[test]
+    fn test_string_collate() {
+        assert!(string_collate_lessp("B", "a", Some("C"), None).unwrap());
+        assert!(!string_collate_lessp("B", "a", Some("POSIX"), Some(())).unwrap());
+        assert!(string_collate_equalp("abc", "ABC", Some("C"), Some(())).unwrap());
+        assert!(string_collate_lessp("a", "b", Some("no-such-locale"), None).is_err());
+    }
 }
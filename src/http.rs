@@ -0,0 +1,332 @@
+//! A minimal HTTP client.
+//!
+//! `http-fetch` makes a request and waits for the response, and
+//! `http-fetch-async` makes it in the background and passes the response to a
+//! callback (see [`crate::process`]). A response is a plist with the
+//! properties `:status`, `:reason`, `:headers` and `:body`. The headers are an
+//! alist of lowercase names and values, and the body is a string, or the
+//! buffer it was inserted into.
+//!
+//! This needs the `http` feature, which pulls in ureq. Without it both
+//! functions signal an error.
+use crate::buffer::get_buffer_create;
+use crate::coding::Coding;
+use crate::core::{
+    cons::Cons,
+    env::{sym, Env},
+    error::{Type, TypeError},
+    gc::{Context, Rt},
+    object::{Function, Object, ObjectType, NIL},
+};
+use crate::fns::slice_into_list;
+use crate::process::Finish;
+use anyhow::{anyhow, ensure, Result};
+use rune_core::macros::list;
+use rune_macros::defun;
+use std::time::Duration;
+
+defsym!(KW_STATUS);
+defsym!(KW_REASON);
+defsym!(KW_HEADERS);
+defsym!(KW_BODY);
+defsym!(KW_ERROR);
+
+const UNAVAILABLE: &str = "HTTP is not available in this build of rune";
+
+/// How long to wait to connect, and between reads of the response.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+struct Request {
+    url: String,
+    method: String,
+    headers: Vec<(String, String)>,
+    data: Option<Vec<u8>>,
+}
+
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+struct Response {
+    status: u16,
+    reason: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn new(
+        url: &str,
+        method: Option<&str>,
+        headers: Option<Object>,
+        data: Option<Object>,
+    ) -> Result<Self> {
+        let mut header_list = Vec::new();
+        for header in headers.unwrap_or(NIL).as_list()? {
+            let header = header?;
+            let ObjectType::Cons(cons) = header.untag() else {
+                return Err(TypeError::new(Type::Cons, header).into());
+            };
+            let name: &str = cons.car().try_into()?;
+            let value: &str = cons.cdr().try_into()?;
+            header_list.push((name.to_owned(), value.to_owned()));
+        }
+        let data = match data.map(Object::untag) {
+            None | Some(ObjectType::NIL) => None,
+            Some(ObjectType::String(data)) => Some(data.as_bytes().to_vec()),
+            Some(ObjectType::ByteString(data)) => Some(data.to_vec()),
+            Some(other) => return Err(TypeError::new(Type::String, other).into()),
+        };
+        Ok(Self {
+            url: url.to_owned(),
+            method: method.unwrap_or("GET").to_owned(),
+            headers: header_list,
+            data,
+        })
+    }
+
+    #[cfg(feature = "http")]
+    fn send(self) -> Result<Response> {
+        use std::io::Read;
+        let agent =
+            ureq::AgentBuilder::new().timeout_connect(TIMEOUT).timeout_read(TIMEOUT).build();
+        let mut request = agent.request(&self.method, &self.url);
+        for (name, value) in &self.headers {
+            request = request.set(name, value);
+        }
+        let result = match &self.data {
+            Some(data) => request.send_bytes(data),
+            None => request.call(),
+        };
+        // Error statuses are still responses
+        let response = match result {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(err) => return Err(err.into()),
+        };
+        let mut headers: Vec<(String, String)> = Vec::new();
+        for name in response.headers_names() {
+            let name = name.to_ascii_lowercase();
+            // `all` has every value of a repeated header
+            if headers.iter().any(|x| x.0 == name) {
+                continue;
+            }
+            for value in response.all(&name) {
+                headers.push((name.clone(), value.to_owned()));
+            }
+        }
+        let status = response.status();
+        let reason = response.status_text().to_owned();
+        let mut body = Vec::new();
+        response.into_reader().read_to_end(&mut body)?;
+        Ok(Response { status, reason, headers, body })
+    }
+
+    #[cfg(not(feature = "http"))]
+    fn send(self) -> Result<Response> {
+        anyhow::bail!(UNAVAILABLE)
+    }
+}
+
+impl Response {
+    /// The response as a plist. If `buffer` is the name of a buffer, the body is
+    /// inserted into it.
+    fn into_plist<'ob>(
+        self,
+        buffer: Option<String>,
+        env: &mut Rt<Env>,
+        cx: &'ob Context,
+    ) -> Result<Object<'ob>> {
+        let headers: Vec<Object> = self
+            .headers
+            .into_iter()
+            .map(|(name, value)| Cons::new(name, value, cx).into())
+            .collect();
+        let headers = slice_into_list(&headers, None, cx);
+        let body = match buffer {
+            Some(name) => {
+                let buffer = get_buffer_create(cx.add(name), Some(NIL), cx)?;
+                let ObjectType::Buffer(lisp_buffer) = buffer.untag() else { unreachable!() };
+                let text = String::from_utf8(self.body)
+                    .unwrap_or_else(|e| Coding::BINARY.decode(e.as_bytes()));
+                env.with_buffer_mut(Some(lisp_buffer), |b| b.text.insert(&text))
+                    .ok_or_else(|| anyhow!("Selecting deleted buffer"))?;
+                buffer
+            }
+            None => match String::from_utf8(self.body) {
+                Ok(text) => cx.add(text),
+                Err(e) => cx.add(e.into_bytes()),
+            },
+        };
+        let status = i64::from(self.status);
+        Ok(list![
+            sym::KW_STATUS, status,
+            sym::KW_REASON, self.reason,
+            sym::KW_HEADERS, headers,
+            sym::KW_BODY, body; cx
+        ])
+    }
+}
+
+/// The name of BUFFER, which is a buffer or the name of one.
+fn buffer_name(buffer: Object, env: &Rt<Env>) -> Result<String> {
+    match buffer.untag() {
+        ObjectType::String(name) => Ok(name.to_string()),
+        ObjectType::Buffer(b) => {
+            let name = env.with_buffer(Some(b), |b| b.name.clone());
+            name.ok_or_else(|| anyhow!("Selecting deleted buffer"))
+        }
+        _ => Err(TypeError::new(Type::BufferOrName, buffer).into()),
+    }
+}
+
+/// The outcome of a request made by `http-fetch-async`.
+struct Fetched {
+    response: Result<Response>,
+    buffer: Option<String>,
+}
+
+impl Finish for Fetched {
+    fn finish<'ob>(self: Box<Self>, env: &mut Rt<Env>, cx: &'ob Context) -> Result<Object<'ob>> {
+        match self.response {
+            Ok(response) => response.into_plist(self.buffer, env, cx),
+            Err(err) => Ok(list![sym::KW_ERROR, err.to_string(); cx]),
+        }
+    }
+}
+
+/// Make an HTTP request to URL and return the response. METHOD is a string
+/// like "POST", and defaults to "GET". HEADERS is an alist of header names and
+/// values, and DATA is a string to send as the body. If BUFFER is non-nil the
+/// body of the response is inserted into it, or the buffer with that name,
+/// instead of being returned as a string.
+#[defun(io)]
+fn http_fetch<'ob>(
+    url: &str,
+    method: Option<&str>,
+    headers: Option<Object>,
+    data: Option<Object>,
+    buffer: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let request = Request::new(url, method, headers, data)?;
+    let buffer = buffer.map(|x| buffer_name(x, env)).transpose()?;
+    request.send()?.into_plist(buffer, env, cx)
+}
+
+/// Make an HTTP request like `http-fetch`, but in the background. CALLBACK is
+/// called with the response once it has arrived and the current thread waits
+/// with `accept-process-output` or `sleep-for`. If the request fails, it is
+/// called with `(:error MESSAGE)` instead. Return the process making the
+/// request.
+#[defun(io)]
+#[allow(clippy::too_many_arguments)]
+fn http_fetch_async<'ob>(
+    url: &str,
+    callback: Function,
+    method: Option<&str>,
+    headers: Option<Object>,
+    data: Option<Object>,
+    buffer: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    ensure!(cfg!(feature = "http"), UNAVAILABLE);
+    let request = Request::new(url, method, headers, data)?;
+    let buffer = buffer.map(|x| buffer_name(x, env)).transpose()?;
+    let work = move || Fetched { response: request.send(), buffer };
+    Ok(crate::process::spawn("http-fetch", callback, env, cx, work))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use rune_core::macros::root;
+
+    #[test]
+    #[cfg(not(feature = "http"))]
+    fn test_unavailable() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        assert!(http_fetch("http://localhost/", None, None, None, None, env, cx).is_err());
+    }
+
+    /// Answer one request with `response`. The returned handle gives the
+    /// request, which is read until it ends with `end`.
+    #[cfg(feature = "http")]
+    fn serve(
+        end: &'static str,
+        response: &'static str,
+    ) -> (String, std::thread::JoinHandle<String>) {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/path", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(end.as_bytes()) {
+                let len = stream.read(&mut buf).unwrap();
+                assert_ne!(len, 0, "connection closed early");
+                request.extend_from_slice(&buf[..len]);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (url, handle)
+    }
+
+    #[test]
+    #[cfg(feature = "http")]
+    fn test_fetch() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        let response = "HTTP/1.1 404 Not Found\r\nContent-Length: 4\r\nX-Test: a\r\n\r\ngone";
+        let (url, server) = serve("\r\n\r\nhello", response);
+        let headers = list![Cons::new("X-Request", "1", cx); cx];
+        let data = cx.add("hello");
+        let response = http_fetch(&url, Some("POST"), Some(headers), Some(data), None, env, cx);
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /path HTTP/1.1\r\n"));
+        assert!(request.to_ascii_lowercase().contains("\r\nx-request: 1\r\n"));
+        let expect = "(:status 404 :reason \"Not Found\" \
+                      :headers ((\"content-length\" . \"4\") (\"x-test\" . \"a\")) \
+                      :body \"gone\")";
+        assert_eq!(response.unwrap().to_string(), expect);
+    }
+
+    #[test]
+    #[cfg(feature = "http")]
+    fn test_fetch_async() {
+        use crate::core::env::intern;
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        sym::init_symbols();
+        let source = "(defalias 'http-test-callback (lambda (x) (setq http-test-response x)))";
+        crate::lread::load_internal(source, cx, env).unwrap();
+        let (url, server) = serve("\r\n\r\n", "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+        let buffer = cx.add("test_http_async");
+        let callback = intern("http-test-callback", cx);
+        let process =
+            http_fetch_async(&url, callback.into(), None, None, None, Some(buffer), env, cx);
+        assert_eq!(cx.add(process.unwrap()).to_string(), "#<process http-fetch>");
+        assert!(server.join().unwrap().starts_with("GET /path HTTP/1.1\r\n"));
+        assert!(crate::process::run_events(None, env, cx).unwrap());
+
+        let response = env.vars.get(intern("http-test-response", cx)).unwrap().bind(cx);
+        assert!(response.to_string().starts_with("(:status 200 :reason \"OK\" :headers"));
+        let buffer = crate::buffer::get_buffer(cx.add("test_http_async"), cx).unwrap();
+        let ObjectType::Buffer(buffer) = buffer.untag() else { unreachable!() };
+        assert_eq!(env.with_buffer(Some(buffer), |b| b == "ok"), Some(true));
+
+        // connection errors are passed to the callback
+        let callback = intern("http-test-callback", cx);
+        http_fetch_async("http://127.0.0.1:1/", callback.into(), None, None, None, None, env, cx)
+            .unwrap();
+        assert!(crate::process::run_events(None, env, cx).unwrap());
+        let response = env.vars.get(intern("http-test-response", cx)).unwrap().bind(cx);
+        assert!(response.to_string().starts_with("(:error "));
+    }
+}
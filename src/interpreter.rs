@@ -6,9 +6,11 @@ use crate::{
         error::{ArgError, Type, TypeError},
         gc::{Context, Rt, Rto, Slot},
         object::{
-            Function, FunctionType, Gc, List, ListType, Object, ObjectType, Symbol, NIL, TRUE,
+            Function, FunctionType, Gc, List, ListType, Object, ObjectType, Symbol, WithLifetime,
+            NIL, TRUE,
         },
     },
+    destructure::{self, Section},
     eval::{add_trace, ErrorType, EvalError, EvalResult},
     rooted_iter,
 };
@@ -17,15 +19,139 @@ use anyhow::Result as AnyResult;
 use anyhow::{bail, ensure};
 use fallible_iterator::FallibleIterator;
 use fallible_streaming_iterator::FallibleStreamingIterator;
-use rune_core::macros::{bail_err, call, error, rebind, root};
+use rune_core::hashmap::HashMap;
+use rune_core::macros::{bail_err, call, error, list, rebind, root};
 use rune_macros::defun;
+use std::sync::{Mutex, OnceLock};
 
-struct Interpreter<'brw, 'rt> {
+pub(crate) struct Interpreter<'brw, 'rt> {
     vars: &'brw mut Rt<Vec<Slot<&'rt Cons>>>,
     env: &'brw mut Rt<Env<'rt>>,
+    /// Whether variables are bound lexically. With dynamic binding `vars` is
+    /// always empty and functions don't capture anything.
+    lexical: bool,
+}
+
+/// A special form implemented outside of the interpreter. It is passed the
+/// unevaluated forms (the `cdr` of the call) and is responsible for evaluating
+/// them itself.
+pub(crate) type SpecialForm =
+    for<'ob> fn(&mut Interpreter<'_, '_>, &Rto<Object>, &'ob mut Context) -> EvalResult<'ob>;
+
+/// Special forms that are built into the interpreter. These can't be
+/// overridden by [`register_special_form`].
+const BUILTIN_SPECIAL_FORMS: [(Symbol<'static>, SpecialForm); 25] = [
+    (sym::QUOTE, |interp, forms, cx| interp.quote(forms.bind(cx))),
+    (sym::LET, |interp, forms, cx| interp.eval_let(forms, true, cx)),
+    (sym::LET_STAR, |interp, forms, cx| interp.eval_let(forms, false, cx)),
+    (sym::IF, |interp, forms, cx| interp.eval_if(forms, cx)),
+    (sym::AND, |interp, forms, cx| interp.eval_and(forms, cx)),
+    (sym::OR, |interp, forms, cx| interp.eval_or(forms, cx)),
+    (sym::COND, |interp, forms, cx| interp.eval_cond(forms, cx)),
+    (sym::WHILE, |interp, forms, cx| interp.eval_while(forms, cx)),
+    (sym::PROGN, |interp, forms, cx| interp.eval_progn(forms, cx)),
+    (sym::INLINE, |interp, forms, cx| interp.eval_progn(forms, cx)),
+    (sym::PROG1, |interp, forms, cx| interp.eval_progx(forms, 1, cx)),
+    (sym::PROG2, |interp, forms, cx| interp.eval_progx(forms, 2, cx)),
+    (sym::SETQ, |interp, forms, cx| interp.setq(forms, cx)),
+    (sym::DEFVAR, |interp, forms, cx| interp.defvar(forms, false, cx)),
+    (sym::DEFCONST, |interp, forms, cx| interp.defvar(forms, true, cx)),
+    (sym::FUNCTION, |interp, forms, cx| interp.eval_function(forms, cx)),
+    (sym::INTERACTIVE, |_, _, _| Ok(NIL)), // TODO: implement
+    (sym::CATCH, |interp, forms, cx| interp.catch(forms, cx)),
+    (sym::THROW, |interp, forms, cx| interp.throw(forms.bind(cx), cx)),
+    (sym::CONDITION_CASE, |interp, forms, cx| interp.condition_case(forms, cx)),
+    (sym::SAVE_CURRENT_BUFFER, |interp, forms, cx| {
+        interp.save_current_buffer(forms, cx)
+    }),
+    (sym::SAVE_EXCURSION, |interp, forms, cx| interp.save_excursion(forms, cx)),
+    (sym::UNWIND_PROTECT, |interp, forms, cx| interp.unwind_protect(forms, cx)),
+    (sym::CL_DESTRUCTURING_BIND, |interp, forms, cx| {
+        interp.destructuring_bind(forms, cx)
+    }),
+    (sym::WITH_OUTPUT_TO_STRING, |interp, forms, cx| {
+        interp.with_output_to_string(forms, cx)
+    }),
+];
+
+/// The special forms that are defined in Rust, both builtin and from other
+/// subsystems.
+fn static_special_forms() -> impl Iterator<Item = (Symbol<'static>, SpecialForm)> {
+    BUILTIN_SPECIAL_FORMS
+        .into_iter()
+        .chain(crate::ert::SPECIAL_FORMS)
+        .chain(crate::stream::SPECIAL_FORMS)
+        .chain(crate::profiler::SPECIAL_FORMS)
+}
+
+/// Forms passed to [`register_special_form`]. This becomes `None` once
+/// [`SPECIAL_FORMS`] is built.
+static REGISTERED: Mutex<Option<Vec<(Symbol<'static>, SpecialForm)>>> =
+    Mutex::new(Some(Vec::new()));
+
+/// Every special form, read by both the interpreter and `special-form-p`. It
+/// is built the first time a special form is looked up and never changes
+/// after that, so lookups don't take a lock.
+static SPECIAL_FORMS: OnceLock<HashMap<Symbol<'static>, SpecialForm>> = OnceLock::new();
+
+fn special_forms() -> &'static HashMap<Symbol<'static>, SpecialForm> {
+    SPECIAL_FORMS.get_or_init(|| {
+        let registered = REGISTERED.lock().unwrap().take().unwrap_or_default();
+        static_special_forms().chain(registered).collect()
+    })
+}
+
+/// Register `form` as the implementation of the special form `name`. This lets
+/// embedders define their own special forms without editing the interpreter.
+/// Forms have to be registered before the first form is evaluated, and can't
+/// replace an existing special form. `name` has to be interned, since the
+/// registry outlives any context.
+pub(crate) fn register_special_form(name: Symbol, form: SpecialForm) -> AnyResult<()> {
+    ensure!(name.interned(), "Special form name {name} is not interned");
+    // SAFETY: Interned symbols are in the global block, which is never freed
+    let name = unsafe { name.with_lifetime() };
+    let mut registered = REGISTERED.lock().unwrap();
+    let Some(registered) = registered.as_mut() else {
+        bail!("Can't register special form {name} after evaluation has started")
+    };
+    let defined = static_special_forms().chain(registered.iter().copied()).any(|x| x.0 == name);
+    ensure!(!defined, "Special form {name} is already defined");
+    registered.push((name, form));
+    Ok(())
+}
+
+fn special_form(name: Symbol) -> Option<SpecialForm> {
+    // SAFETY: The symbol is only compared with the keys, and is not stored
+    let name = unsafe { name.with_lifetime() };
+    special_forms().get(&name).copied()
 }
 
 #[defun]
+pub(crate) fn special_form_p(object: Object) -> bool {
+    match object.untag() {
+        ObjectType::Symbol(sym) => special_form(sym).is_some(),
+        _ => false,
+    }
+}
+
+/// Evaluate FORM and return its value. If LEXICAL is nil FORM uses dynamic
+/// binding. Otherwise it uses lexical binding, and if LEXICAL is an alist it
+/// holds the lexical variables that FORM starts with.
+#[defun(name = "eval")]
+fn lisp_eval<'ob>(
+    form: &Rto<Object>,
+    lexical: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>, anyhow::Error> {
+    match lexical {
+        Some(lexical) => eval(form, Some(lexical), env, cx),
+        None => eval_dynamic(form, env, cx),
+    }
+}
+
+/// Evaluate `form` with lexical binding. `lexical` is an alist of the lexical
+/// variables it starts with.
 pub(crate) fn eval<'ob>(
     form: &Rto<Object>,
     lexical: Option<&Rto<Object>>,
@@ -41,12 +167,45 @@ pub(crate) fn eval<'ob>(
             }
         }
     }
-    let mut interpreter = Interpreter { vars, env };
+    let mut interpreter = Interpreter { vars, env, lexical: true };
     interpreter.eval_form(form, cx).map_err(Into::into)
 }
 
-impl Interpreter<'_, '_> {
-    fn eval_form<'ob>(&mut self, rt: &Rto<Object>, cx: &'ob mut Context) -> EvalResult<'ob> {
+/// Read and evaluate `src` with lexical binding, and return the printed value.
+/// This is for the tests of builtins defined outside of the interpreter.
+#[cfg(test)]
+pub(crate) fn eval_to_string(src: &str, env: &mut Rt<Env>, cx: &mut Context) -> AnyResult<String> {
+    sym::init_symbols();
+    let obj = crate::reader::read(src, cx).unwrap().0;
+    root!(obj, cx);
+    Ok(eval(obj, None, env, cx)?.to_string())
+}
+
+/// Evaluate `form` with dynamic binding.
+pub(crate) fn eval_dynamic<'ob>(
+    form: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>, anyhow::Error> {
+    cx.garbage_collect(false);
+    root!(vars, new(Vec<Slot<&Cons>>), cx);
+    let mut interpreter = Interpreter { vars, env, lexical: false };
+    interpreter.eval_form(form, cx).map_err(Into::into)
+}
+
+impl<'rt> Interpreter<'_, 'rt> {
+    /// The environment of the forms being evaluated, for special forms that are
+    /// implemented outside of the interpreter.
+    pub(crate) fn env(&mut self) -> &mut Rt<Env<'rt>> {
+        self.env
+    }
+
+    pub(crate) fn eval_form<'ob>(
+        &mut self,
+        rt: &Rto<Object>,
+        cx: &'ob mut Context,
+    ) -> EvalResult<'ob> {
+        crate::bytecode::consume_fuel(self.env)?;
         match rt.untag(cx) {
             ObjectType::Symbol(sym) => self.var_ref(sym, cx),
             ObjectType::Cons(_) => {
@@ -59,41 +218,42 @@ impl Interpreter<'_, '_> {
 
     pub(crate) fn eval_sexp<'ob>(
         &mut self,
-        cons: &Rto<Gc<&Cons>>,
+        form: &Rto<Gc<&Cons>>,
         cx: &'ob mut Context,
     ) -> EvalResult<'ob> {
+        match self.eval_cons(form, cx) {
+            Ok(x) => Ok(rebind!(x, cx)),
+            Err(e) => {
+                let e = e.with_form(form.bind(cx).into());
+                Err(crate::eval::run_handlers(e, self.env, cx))
+            }
+        }
+    }
+
+    fn eval_cons<'ob>(&mut self, cons: &Rto<Gc<&Cons>>, cx: &'ob mut Context) -> EvalResult<'ob> {
         let cons = cons.bind(cx);
+        if crate::coverage::enabled() {
+            crate::coverage::record(cons.into(), self.env, cx);
+        }
         let forms = cons.cdr();
         root!(forms, cx);
         match cons.car().untag() {
-            ObjectType::Symbol(sym) => match sym {
-                sym::QUOTE => self.quote(forms.bind(cx)),
-                sym::LET => self.eval_let(forms, true, cx),
-                sym::LET_STAR => self.eval_let(forms, false, cx),
-                sym::IF => self.eval_if(forms, cx),
-                sym::AND => self.eval_and(forms, cx),
-                sym::OR => self.eval_or(forms, cx),
-                sym::COND => self.eval_cond(forms, cx),
-                sym::WHILE => self.eval_while(forms, cx),
-                sym::PROGN | sym::INLINE => self.eval_progn(forms, cx),
-                sym::PROG1 => self.eval_progx(forms, 1, cx),
-                sym::PROG2 => self.eval_progx(forms, 2, cx),
-                sym::SETQ => self.setq(forms, cx),
-                sym::DEFVAR | sym::DEFCONST => self.defvar(forms, cx),
-                sym::FUNCTION => self.eval_function(forms, cx),
-                sym::INTERACTIVE => Ok(NIL), // TODO: implement
-                sym::CATCH => self.catch(forms, cx),
-                sym::THROW => self.throw(forms.bind(cx), cx),
-                sym::CONDITION_CASE => self.condition_case(forms, cx),
-                sym::SAVE_CURRENT_BUFFER => self.save_current_buffer(forms, cx),
-                sym::SAVE_EXCURSION => self.save_excursion(forms, cx),
-                sym::UNWIND_PROTECT => self.unwind_protect(forms, cx),
-                _ => {
-                    root!(sym, cx);
-                    self.eval_call(sym, forms, cx)
+            ObjectType::Symbol(sym) => {
+                if let Some(form) = special_form(sym) {
+                    return form(self, forms, cx);
                 }
-            },
-            other => Err(error!("Invalid Function: {other}")),
+                root!(sym, cx);
+                if self.lexical {
+                    if let Some(lambda) = non_escaping_lambda(sym.bind(cx), forms.bind(cx), cx) {
+                        root!(lambda, cx);
+                        return self.eval_inline_lambda(sym, lambda, forms, cx);
+                    }
+                }
+                self.eval_call(sym, forms, cx)
+            }
+            other => {
+                Err(EvalError::signal(sym::INVALID_FUNCTION.into(), list![other; cx], self.env))
+            }
         }
     }
 
@@ -139,19 +299,38 @@ impl Interpreter<'_, '_> {
         }
     }
 
-    fn defvar<'ob>(&mut self, obj: &Rto<Object>, cx: &'ob mut Context) -> EvalResult<'ob> {
+    /// `defvar`, or `defconst` if `constant`.
+    fn defvar<'ob>(
+        &mut self,
+        obj: &Rto<Object>,
+        constant: bool,
+        cx: &'ob mut Context,
+    ) -> EvalResult<'ob> {
+        let form_name = if constant { "defconst" } else { "defvar" };
         rooted_iter!(forms, obj, cx);
         // (defvar x ...)                 // (defvar)
-        let Some(sym) = forms.next()? else { bail_err!(ArgError::new(1, 0, "defvar")) };
+        let Some(sym) = forms.next()? else { bail_err!(ArgError::new(1, 0, form_name)) };
         let name: Symbol = sym.bind(cx).try_into()?;
         root!(name, cx);
         let value = match forms.next()? {
             // (defvar x y)
             Some(value) => rebind!(self.eval_form(value, cx)?),
+            // (defconst x)
+            None if constant => bail_err!(ArgError::new(2, 1, form_name)),
             // (defvar x)
             None => NIL,
         };
-        self.env.defvar(name.bind(cx), value)?;
+        // (defvar x y "doc")
+        if let Some(doc) = forms.next()? {
+            if let ObjectType::String(_) = doc.untag(cx) {
+                self.env.set_prop(name.bind(cx), sym::VARIABLE_DOCUMENTATION, doc.bind(cx));
+            }
+        }
+        if constant {
+            self.env.defconst(name.bind(cx), value)?;
+        } else {
+            self.env.defvar(name.bind(cx), value)?;
+        }
         Ok(value)
     }
 
@@ -168,9 +347,9 @@ impl Interpreter<'_, '_> {
 
         match func.untag(cx) {
             FunctionType::Cons(cons) if cons.car() == sym::AUTOLOAD => {
-                crate::eval::autoload_do_load(func.cast(), None, None, self.env, cx)
+                crate::eval::autoload_function(sym, func.cast(), self.env, cx)
                     .map_err(|e| add_trace(e, "autoload", &[]))?;
-                func.set(sym.bind(cx).follow_indirect(cx).unwrap());
+                return self.eval_call(sym, args, cx);
             }
             FunctionType::Cons(form) if form.car() == sym::MACRO => {
                 let mcro: Function = form.cdr().try_into()?;
@@ -182,7 +361,9 @@ impl Interpreter<'_, '_> {
                 }
                 root!(mcro, cx);
                 let name = sym.bind(cx).name().to_owned();
+                let span = trace_span!("macroexpand", macro_name = name.as_str());
                 let value = mcro.call(&mut frame, Some(&name), cx)?;
+                drop(span);
                 drop(frame);
                 root!(value, cx);
                 return self.eval_form(value, cx);
@@ -202,11 +383,80 @@ impl Interpreter<'_, '_> {
         func.call(frame, Some(&name), cx)
     }
 
-    fn eval_function<'ob>(
+    /// Evaluate a call to `func` whose first argument is a lambda that can't
+    /// escape, found by [`non_escaping_lambda`]. `lambda` is its `(ARGS . BODY)`.
+    /// Instead of making a closure, the body is run inline with the arguments
+    /// bound on top of the current variables, like a `let`.
+    fn eval_inline_lambda<'ob>(
+        &mut self,
+        func: &Rto<Symbol>,
+        lambda: &Rto<&Cons>,
+        forms: &Rto<Object>,
+        cx: &'ob mut Context,
+    ) -> EvalResult<'ob> {
+        rooted_iter!(iter, forms, cx);
+        let lambda_form = iter.next()?.unwrap().bind(cx);
+        root!(lambda_form, cx);
+        root!(args, new(Vec), cx);
+        while let Some(x) = iter.next()? {
+            let result = self.eval_form(x, cx)?;
+            args.push(result);
+        }
+        if func.bind(cx) == sym::FUNCALL {
+            return self.call_inline(lambda, args, cx);
+        }
+        // mapcar or mapc
+        let [sequence] = Rt::bind_slice(args, cx) else {
+            bail_err!(ArgError::new(2, args.len() as u16 + 1, func.bind(cx).name()))
+        };
+        let ObjectType::Cons(sequence) = sequence.untag() else {
+            // Anything that isn't a non-empty list is left to the function
+            let closure = rebind!(self.eval_form(lambda_form, cx)?);
+            let sequence = Rt::bind_slice(args, cx)[0];
+            let Some(function) = func.bind(cx).func(cx) else {
+                bail_err!("Invalid function: {func}")
+            };
+            root!(function, cx);
+            return call!(function, closure, sequence; self.env, cx);
+        };
+        root!(sequence, cx);
+        rooted_iter!(elements, sequence.bind(cx), cx);
+        root!(outputs, new(Vec), cx);
+        while let Some(elem) = elements.next()? {
+            args.truncate(0);
+            args.push(elem);
+            let output = self.call_inline(lambda, args, cx)?;
+            outputs.push(output);
+        }
+        if func.bind(cx) == sym::MAPC {
+            return Ok(sequence.bind(cx).into());
+        }
+        Ok(crate::fns::slice_into_list(Rt::bind_slice(outputs, cx), None, cx))
+    }
+
+    /// Run the body of `lambda`, an `(ARGS . BODY)` that was never made into a
+    /// closure, with `args`.
+    fn call_inline<'ob>(
         &mut self,
-        obj: &Rto<Object<'ob>>,
+        lambda: &Rto<&Cons>,
+        args: &Rt<Vec<Slot<Object>>>,
         cx: &'ob mut Context,
     ) -> EvalResult<'ob> {
+        let prev_len = self.vars.len();
+        let mut vars = Vec::new();
+        let arg_list = lambda.bind(cx).car();
+        bind_args(arg_list, Rt::bind_slice(args, cx), &mut vars, "lambda", cx)?;
+        for var in vars {
+            self.vars.push(var);
+        }
+        rooted_iter!(body, lambda.bind(cx).cdr(), cx);
+        let result = self.implicit_progn(body, cx);
+        // The parameters go out of scope even if the body signaled
+        self.vars.truncate(prev_len);
+        result
+    }
+
+    fn eval_function<'ob>(&mut self, obj: &Rto<Object>, cx: &'ob mut Context) -> EvalResult<'ob> {
         let mut forms = obj.bind(cx).as_list()?;
         let len = forms.len()? as u16;
         if len != 1 {
@@ -216,7 +466,8 @@ impl Interpreter<'_, '_> {
         let form = forms.next().unwrap()?;
         root!(form, cx); // Polonius
         let ObjectType::Cons(cons) = form.bind(cx).untag() else { return Ok(form.bind(cx)) };
-        if cons.car() != sym::LAMBDA {
+        // With dynamic binding a lambda stays a list
+        if cons.car() != sym::LAMBDA || !self.lexical {
             return Ok(form.bind(cx));
         }
         root!(doc, cons.cdr(), cx);
@@ -316,7 +567,11 @@ impl Interpreter<'_, '_> {
         }
     }
 
-    fn eval_progn<'ob>(&mut self, obj: &Rto<Object>, cx: &'ob mut Context) -> EvalResult<'ob> {
+    pub(crate) fn eval_progn<'ob>(
+        &mut self,
+        obj: &Rto<Object>,
+        cx: &'ob mut Context,
+    ) -> EvalResult<'ob> {
         rooted_iter!(forms, obj, cx);
         self.implicit_progn(forms, cx)
     }
@@ -404,8 +659,9 @@ impl Interpreter<'_, '_> {
                     root!(var, cx);
                     root!(val, cx);
                     let val = rebind!(self.eval_form(val, cx)?);
-                    self.var_set(var.bind(cx), val, cx)?;
-                    last_value.set(val);
+                    root!(val, cx);
+                    self.var_set(var, val, cx)?;
+                    last_value.set(val.bind(cx));
                 }
                 (_, Some(_)) => bail_err!(TypeError::new(Type::Symbol, var)),
                 (_, None) => bail_err!(ArgError::new(arg_cnt, arg_cnt + 1, "setq")),
@@ -428,7 +684,7 @@ impl Interpreter<'_, '_> {
         Ok(first.map(|first| (first, second)))
     }
 
-    fn var_ref<'ob>(&self, sym: Symbol, cx: &'ob Context) -> EvalResult<'ob> {
+    fn var_ref<'ob>(&mut self, sym: Symbol, cx: &'ob Context) -> EvalResult<'ob> {
         if sym.is_const() {
             Ok(sym.into())
         } else {
@@ -437,20 +693,29 @@ impl Interpreter<'_, '_> {
                 Some(value) => Ok(value),
                 None => match self.env.vars.get(sym) {
                     Some(v) => Ok(v.bind(cx)),
-                    None => Err(error!("Void variable: {sym}")),
+                    None => {
+                        Err(EvalError::signal(sym::VOID_VARIABLE.into(), list![sym; cx], self.env))
+                    }
                 },
             }
         }
     }
 
-    fn var_set(&mut self, name: Symbol, new_value: Object, cx: &Context) -> AnyResult<()> {
+    fn var_set(
+        &mut self,
+        name: &Rto<Symbol>,
+        new_value: &Rto<Object>,
+        cx: &mut Context,
+    ) -> AnyResult<()> {
+        let symbol = name.bind(cx);
         let mut iter = self.vars.iter().rev();
-        match iter.find(|cons| (cons.car(cx) == name)) {
+        match iter.find(|cons| (cons.car(cx) == symbol)) {
             Some(value) => {
+                let new_value = new_value.bind(cx);
                 value.bind(cx).set_cdr(new_value).expect("variables should never be immutable");
                 Ok(())
             }
-            None => self.env.set_var(name, new_value),
+            None => crate::data::set_var(name, new_value, self.env, cx),
         }
     }
 
@@ -478,10 +743,11 @@ impl Interpreter<'_, '_> {
             self.let_bind_serial(obj, cx)
         }?;
         let obj = rebind!(self.implicit_progn(iter, cx)?);
+        root!(obj, cx);
         // Remove old bindings
         self.vars.truncate(prev_len);
-        self.env.unbind(varbind_count, cx);
-        Ok(obj)
+        crate::data::unbind(varbind_count, self.env, cx)?;
+        Ok(obj.bind(cx))
     }
 
     fn let_bind_serial(&mut self, form: &Rto<Object>, cx: &mut Context) -> Result<u16, EvalError> {
@@ -493,13 +759,17 @@ impl Interpreter<'_, '_> {
                 ObjectType::Cons(_) => {
                     let cons = binding.as_cons();
                     let val = rebind!(self.let_bind_value(cons, cx)?);
+                    root!(val, cx);
                     let var: Symbol =
                         cons.untag(cx).car().try_into().context("let variable must be a symbol")?;
-                    varbind_count += self.create_let_binding(var, val, cx);
+                    root!(var, cx);
+                    varbind_count += self.create_let_binding(var, val, cx)?;
                 }
                 // (let (x))
                 ObjectType::Symbol(sym) => {
-                    varbind_count += self.create_let_binding(sym, NIL, cx);
+                    root!(sym, cx);
+                    root!(val, NIL, cx);
+                    varbind_count += self.create_let_binding(sym, val, cx)?;
                 }
                 // (let (1))
                 x => bail_err!(TypeError::new(Type::Cons, x)),
@@ -534,20 +804,200 @@ impl Interpreter<'_, '_> {
             }
         }
         let mut sum = 0;
-        for (var, val) in let_bindings.bind_ref(cx) {
-            sum += self.create_let_binding(**var, **val, cx);
+        for i in 0..let_bindings.len() {
+            let (var, val) = &let_bindings.bind_ref(cx)[i];
+            let (var, val) = (**var, **val);
+            root!(var, cx);
+            root!(val, cx);
+            sum += self.create_let_binding(var, val, cx)?;
         }
         Ok(sum)
     }
 
-    fn create_let_binding(&mut self, var: Symbol, val: Object, cx: &Context) -> u16 {
-        if var.is_special() {
-            self.env.varbind(var, val, cx);
+    fn create_let_binding(
+        &mut self,
+        var: &Rto<Symbol>,
+        val: &Rto<Object>,
+        cx: &mut Context,
+    ) -> Result<u16, EvalError> {
+        if var.bind(cx).is_special() || !self.lexical {
+            crate::data::varbind(var, val, self.env, cx)?;
             // return 1 if the variable is bound
-            1
+            Ok(1)
         } else {
-            self.vars.push(Cons::new(var, val, cx));
-            0
+            let binding = Cons::new(var.bind(cx), val.bind(cx), cx);
+            self.vars.push(binding);
+            Ok(0)
+        }
+    }
+
+    /// Evaluate `(cl-destructuring-bind ARGS EXPR BODY...)`, binding the
+    /// variables of the lambda list ARGS to the parts of the value of EXPR.
+    fn destructuring_bind<'ob>(
+        &mut self,
+        form: &Rto<Object>,
+        cx: &'ob mut Context,
+    ) -> EvalResult<'ob> {
+        rooted_iter!(forms, form, cx);
+        let Some(args) = forms.next()? else {
+            bail_err!(ArgError::new(2, 0, "cl-destructuring-bind"))
+        };
+        // Check the whole lambda list before binding anything
+        destructure::parse(args.bind(cx), cx)?;
+        let args = args.bind(cx);
+        root!(args, cx);
+        let Some(expr) = forms.next()? else {
+            bail_err!(ArgError::new(2, 1, "cl-destructuring-bind"))
+        };
+        let value = rebind!(self.eval_form(expr, cx)?);
+        root!(value, cx);
+        let prev_len = self.vars.len();
+        let varbind_count = self.destructure(args, value, cx)?;
+        let obj = rebind!(self.implicit_progn(forms, cx)?);
+        root!(obj, cx);
+        // Remove old bindings
+        self.vars.truncate(prev_len);
+        crate::data::unbind(varbind_count, self.env, cx)?;
+        Ok(obj.bind(cx))
+    }
+
+    /// Bind the variables of the lambda list `pattern` to the parts of
+    /// `value`, returning the number of dynamic bindings made. The lambda
+    /// list has already been checked by [`destructure::parse`].
+    fn destructure(
+        &mut self,
+        pattern: &Rto<Object>,
+        value: &Rto<Object>,
+        cx: &mut Context,
+    ) -> Result<u16, EvalError> {
+        let mut varbind_count = 0;
+        let mut section = Section::Required;
+        let mut has_keys = false;
+        let mut allow_other_keys = false;
+        let params = pattern.bind(cx);
+        root!(params, cx);
+        let rest = value.bind(cx);
+        root!(rest, cx);
+        root!(param, NIL, cx);
+        root!(keywords, new(Vec<Slot<Symbol>>), cx);
+        loop {
+            match params.bind(cx).untag() {
+                ObjectType::Cons(cons) => {
+                    param.set(cons.car());
+                    params.set(cons.cdr());
+                }
+                // (a b . c)
+                ObjectType::Symbol(var) => {
+                    root!(var, cx);
+                    return Ok(varbind_count + self.create_let_binding(var, rest, cx)?);
+                }
+                _ => break,
+            }
+            if let ObjectType::Symbol(marker) = param.bind(cx).untag() {
+                if marker == sym::AND_ALLOW_OTHER_KEYS {
+                    allow_other_keys = true;
+                    continue;
+                }
+                if let Some(next) = Section::from_marker(marker) {
+                    section = next;
+                    has_keys |= next == Section::Key;
+                    continue;
+                }
+            }
+            match section {
+                Section::Required => {
+                    let ObjectType::Cons(cons) = rest.bind(cx).untag() else {
+                        let param = param.bind(cx);
+                        bail_err!("Missing value for {param} in {}", value.bind(cx))
+                    };
+                    rest.set(cons.cdr());
+                    let arg = cons.car();
+                    root!(arg, cx);
+                    varbind_count += self.bind_pattern(param, arg, cx)?;
+                }
+                Section::Optional => {
+                    let (arg, supplied) = match rest.bind(cx).untag() {
+                        ObjectType::Cons(cons) => {
+                            rest.set(cons.cdr());
+                            (cons.car(), true)
+                        }
+                        _ => (NIL, false),
+                    };
+                    root!(arg, cx);
+                    varbind_count += self.bind_param(param, arg, supplied, false, cx)?;
+                }
+                Section::Rest => {
+                    let arg = rest.bind(cx);
+                    root!(arg, cx);
+                    varbind_count += self.bind_pattern(param, arg, cx)?;
+                }
+                Section::Key => {
+                    let (head, _, _) = destructure::param_parts(param.bind(cx))?;
+                    let (keyword, _) = destructure::key_parts(head, cx)?;
+                    keywords.push(keyword);
+                    let arg = destructure::key_value(rest.bind(cx), keyword)?;
+                    let supplied = arg.is_some();
+                    let arg = arg.unwrap_or(NIL);
+                    root!(arg, cx);
+                    varbind_count += self.bind_param(param, arg, supplied, true, cx)?;
+                }
+                Section::Aux => {
+                    root!(arg, NIL, cx);
+                    varbind_count += self.bind_param(param, arg, false, false, cx)?;
+                }
+            }
+        }
+        if has_keys {
+            let keywords: Vec<Symbol> = keywords.bind_ref(cx).iter().map(|x| **x).collect();
+            destructure::check_keys(rest.bind(cx), &keywords, allow_other_keys)?;
+        } else if section < Section::Rest && rest.bind(cx) != NIL {
+            let pattern = pattern.bind(cx);
+            bail_err!("Too many values for {pattern}: {}", value.bind(cx));
+        }
+        Ok(varbind_count)
+    }
+
+    /// Bind an `&optional`, `&key`, or `&aux` parameter to `value`, or to its
+    /// default if no value was `supplied`.
+    fn bind_param(
+        &mut self,
+        param: &Rto<Object>,
+        value: &mut Rto<Object>,
+        supplied: bool,
+        key: bool,
+        cx: &mut Context,
+    ) -> Result<u16, EvalError> {
+        let (head, default, _) = destructure::param_parts(param.bind(cx))?;
+        let pattern = if key { destructure::key_parts(head, cx)?.1 } else { head };
+        root!(pattern, cx);
+        if !supplied && default != NIL {
+            root!(default, cx);
+            let default = rebind!(self.eval_form(default, cx)?);
+            value.set(default);
+        }
+        let mut varbind_count = self.bind_pattern(pattern, value, cx)?;
+        if let (_, _, Some(var)) = destructure::param_parts(param.bind(cx))? {
+            let supplied = if supplied { TRUE } else { NIL };
+            root!(var, cx);
+            root!(supplied, cx);
+            varbind_count += self.create_let_binding(var, supplied, cx)?;
+        }
+        Ok(varbind_count)
+    }
+
+    /// Bind `pattern`, which is a variable or a nested lambda list, to `value`.
+    fn bind_pattern(
+        &mut self,
+        pattern: &Rto<Object>,
+        value: &Rto<Object>,
+        cx: &mut Context,
+    ) -> Result<u16, EvalError> {
+        match pattern.bind(cx).untag() {
+            ObjectType::Symbol(var) => {
+                root!(var, cx);
+                self.create_let_binding(var, value, cx)
+            }
+            _ => self.destructure(pattern, value, cx),
         }
     }
 
@@ -600,16 +1050,7 @@ impl Interpreter<'_, '_> {
     }
 
     fn save_excursion<'ob>(&mut self, form: &Rto<Object>, cx: &'ob mut Context) -> EvalResult<'ob> {
-        let point = self.env.current_buffer.as_ref().map(|b| b.text.cursor());
-        let buffer = self.env.current_buffer.as_ref().map(|b| (b.lisp_buffer(cx)));
-        root!(buffer, cx);
-        let result = rebind!(self.eval_progn(form, cx)?);
-        if let Some(buffer) = buffer.bind_ref(cx) {
-            self.env.set_buffer(buffer)?;
-            let buf = self.env.current_buffer.as_mut().unwrap();
-            buf.text.set_cursor(point.unwrap().chars());
-        }
-        Ok(result)
+        self.eval_saving_buffer(form, true, cx)
     }
 
     fn save_current_buffer<'ob>(
@@ -617,13 +1058,46 @@ impl Interpreter<'_, '_> {
         form: &Rto<Object>,
         cx: &'ob mut Context,
     ) -> EvalResult<'ob> {
-        let buffer = self.env.current_buffer.as_ref().map(|x| x.lisp_buffer(cx));
-        root!(buffer, cx);
-        let result = rebind!(self.eval_progn(form, cx)?);
-        if let Some(buffer) = buffer.bind_ref(cx) {
-            self.env.set_buffer(buffer)?;
+        self.eval_saving_buffer(form, false, cx)
+    }
+
+    /// Evaluate the body, then make the buffer that was current before
+    /// current again, and restore its point if `point`. This happens even if
+    /// the body signals an error.
+    fn eval_saving_buffer<'ob>(
+        &mut self,
+        form: &Rto<Object>,
+        point: bool,
+        cx: &'ob mut Context,
+    ) -> EvalResult<'ob> {
+        root!(value, None::<Object>, cx);
+        self.env.save_buffer(point, cx);
+        let result = self.eval_progn(form, cx).map(|x| value.set(Some(x)));
+        self.env.unbind(1, cx);
+        result?;
+        match &**value {
+            Some(x) => Ok(x.bind(cx)),
+            None => Ok(NIL),
         }
-        Ok(result)
+    }
+
+    /// Evaluate the body with `standard-output` bound to a temporary buffer and
+    /// return the text that was printed to it.
+    fn with_output_to_string<'ob>(
+        &mut self,
+        form: &Rto<Object>,
+        cx: &'ob mut Context,
+    ) -> EvalResult<'ob> {
+        let buffer = crate::print::string_output_buffer(cx)?;
+        root!(buffer, cx);
+        root!(var, sym::STANDARD_OUTPUT, cx);
+        crate::data::varbind(var, buffer, self.env, cx)?;
+        let result = self.eval_progn(form, cx).map(|_| ());
+        let unbound = crate::data::unbind(1, self.env, cx);
+        let text = crate::print::take_string_output(buffer.bind(cx), self.env)?;
+        result?;
+        unbound?;
+        Ok(cx.add(text))
     }
 
     fn condition_case<'ob>(&mut self, form: &Rto<Object>, cx: &'ob mut Context) -> EvalResult<'ob> {
@@ -633,54 +1107,60 @@ impl Interpreter<'_, '_> {
         let Some(bodyform) = forms.next()? else {
             bail_err!(ArgError::new(2, 1, "condition-case"))
         };
-        let err = match self.eval_form(bodyform, cx) {
+        // Let handler-bind handlers outside of this know which errors it
+        // handles
+        let depth = self.env.handlers.len();
+        for handler in form.bind(cx).as_list()?.skip(2) {
+            if let ObjectType::Cons(handler) = handler?.untag() {
+                self.env.handlers.push((handler.car(), NIL));
+            }
+        }
+        let result = self.eval_form(bodyform, cx);
+        self.env.handlers.truncate(depth);
+        let err = match result {
             Ok(x) => return Ok(rebind!(x, cx)),
             Err(e) => e,
         };
-        if matches!(err.error, ErrorType::Throw(_)) {
-            return Err(err);
-        }
+        let Some((symbol, data)) = err.condition(self.env, cx) else { return Err(err) };
+        let error: Object = Cons::new(symbol, data, cx).into();
+        root!(error, cx);
         while let Some(handler) = forms.next()? {
             match handler.untag(cx) {
                 ObjectType::Cons(cons) => {
-                    // Check that conditions match
-                    let condition = cons.car();
-                    match condition.untag() {
-                        ObjectType::Symbol(sym::ERROR | sym::VOID_VARIABLE) => {}
+                    let conditions = cons.car();
+                    let handled = match conditions.untag() {
                         // TODO: Remove this once error handling is correctly implemented
-                        ObjectType::Symbol(s) if s.name() == "cl--generic-cyclic-definition" => {}
-                        ObjectType::Cons(conditions) => {
-                            for condition in conditions {
-                                let condition = condition?;
-                                // TODO: Handle different error symbols
-                                if condition != sym::DEBUG && condition != sym::ERROR {
-                                    bail_err!("non-error conditions {condition} not yet supported")
-                                }
-                            }
+                        ObjectType::Symbol(s) if s.name() == "cl--generic-cyclic-definition" => {
+                            true
                         }
-                        _ => bail_err!("Invalid condition handler: {condition}"),
-                    }
-                    // Call handlers with error
-                    let error = if let ErrorType::Signal(id) = err.error {
-                        let Some((sym, data)) = self.env.get_exception(id) else {
-                            unreachable!("Exception not found")
-                        };
-                        Cons::new(sym, data, cx)
-                    } else {
-                        // TODO: Need to remove the anyhow branch once
-                        // full errors are implemented
-                        Cons::new(sym::ERROR, format!("{err}"), cx)
+                        ObjectType::Symbol(_) | ObjectType::Cons(_) => {
+                            let symbol = error.bind(cx).as_cons().car();
+                            crate::eval::handles(conditions, symbol, self.env, cx)?
+                        }
+                        _ => bail_err!("Invalid condition handler: {conditions}"),
                     };
-                    let binding = Cons::new(var, Cons::new1(error, cx), cx);
-                    self.vars.push(binding);
+                    if !handled {
+                        continue;
+                    }
                     let list: List = match cons.cdr().try_into() {
                         Ok(x) => x,
                         Err(_) => return Ok(NIL),
                     };
                     rooted_iter!(handlers, list, cx);
-                    let result = self.implicit_progn(handlers, cx)?;
-                    self.vars.pop();
-                    return Ok(result);
+                    let var: Symbol = var.bind(cx).try_into()?;
+                    let prev_len = self.vars.len();
+                    let varbind_count = match var {
+                        sym::NIL => 0,
+                        _ => {
+                            root!(var, cx);
+                            self.create_let_binding(var, error, cx)?
+                        }
+                    };
+                    let result = rebind!(self.implicit_progn(handlers, cx)?);
+                    root!(result, cx);
+                    self.vars.truncate(prev_len);
+                    crate::data::unbind(varbind_count, self.env, cx)?;
+                    return Ok(result.bind(cx));
                 }
                 ObjectType::NIL => {}
                 invalid => bail_err!("Invalid condition handler: {invalid}"),
@@ -700,15 +1180,43 @@ pub(crate) fn call_closure<'ob>(
     cx.garbage_collect(false);
     let closure: &Cons = closure.untag(cx);
     match closure.car().untag() {
-        ObjectType::Symbol(sym::CLOSURE) => {
+        ObjectType::Symbol(head @ (sym::CLOSURE | sym::LAMBDA)) => {
+            let is_closure = head == sym::CLOSURE;
             rooted_iter!(forms, closure.cdr(), cx);
             let args = Rt::bind_slice(&env.stack[..arg_cnt], cx);
-            let vars = bind_variables(&mut forms, args, name, cx)?;
-            debug!("call vars: {vars:?}");
-            root!(vars, cx);
-            Interpreter { vars, env }.implicit_progn(forms, cx)
+            if is_closure {
+                let vars = bind_variables(&mut forms, args, name, cx)?;
+                debug!("call vars: {vars:?}");
+                root!(vars, cx);
+                return Interpreter { vars, env, lexical: true }.implicit_progn(forms, cx);
+            }
+            // A lambda that was never made into a closure, like a quoted one or
+            // one made with dynamic binding, binds its arguments dynamically
+            let Some(arg_list) = forms.next()? else { bail_err!("Lambda missing argument list") };
+            let mut args_vars = Vec::new();
+            bind_args(arg_list.bind(cx), args, &mut args_vars, name, cx)?;
+            root!(args_vars, cx);
+            for i in 0..args_vars.len() {
+                let binding: &Cons = *args_vars.bind_ref(cx)[i];
+                let (var, value): (Symbol, _) = (binding.car().try_into()?, binding.cdr());
+                root!(var, cx);
+                root!(value, cx);
+                crate::data::varbind(var, value, env, cx)?;
+            }
+            let varbind_count = args_vars.len() as u16;
+            root!(vars, new(Vec<Slot<&Cons>>), cx);
+            root!(value, None::<Object>, cx);
+            let mut interpreter = Interpreter { vars, env: &mut *env, lexical: false };
+            let result = interpreter.implicit_progn(forms, cx).map(|x| value.set(Some(x)));
+            let unbound = crate::data::unbind(varbind_count, env, cx);
+            result?;
+            unbound?;
+            match &**value {
+                Some(x) => Ok(x.bind(cx)),
+                None => Ok(NIL),
+            }
         }
-        other => Err(TypeError::new(Type::Func, other).into()),
+        _ => Err(TypeError::new(Type::Func, ObjectType::Cons(closure)).into()),
     }
 }
 
@@ -750,6 +1258,45 @@ fn parse_closure_env(obj: Object) -> AnyResult<Vec<&Cons>> {
     Ok(env)
 }
 
+/// Escape analysis for a call to `func` with the argument forms `args`.
+/// `funcall`, `mapcar` and `mapc` call their function argument before they
+/// return and don't keep it anywhere, so if that argument is a literal
+/// `#'(lambda ...)` the lambda can't outlive the call and doesn't need a
+/// closure. Return its `(ARGS . BODY)`.
+fn non_escaping_lambda<'ob>(
+    func: Symbol,
+    args: Object<'ob>,
+    cx: &'ob Context,
+) -> Option<&'ob Cons> {
+    if !matches!(func, sym::FUNCALL | sym::MAPCAR | sym::MAPC) {
+        return None;
+    }
+    // The function could have been redefined to something that keeps it
+    if !matches!(func.func(cx)?.untag(), FunctionType::SubrFn(_)) {
+        return None;
+    }
+    let ObjectType::Cons(args) = args.untag() else { return None };
+    let ObjectType::Cons(form) = args.car().untag() else { return None };
+    let ObjectType::Cons(quoted) = form.cdr().untag() else { return None };
+    if form.car() != sym::FUNCTION || !quoted.cdr().is_nil() {
+        return None;
+    }
+    let ObjectType::Cons(lambda) = quoted.car().untag() else { return None };
+    let ObjectType::Cons(def) = lambda.cdr().untag() else { return None };
+    if lambda.car() != sym::LAMBDA {
+        return None;
+    }
+    // A docstring built with (:documentation FORM) needs a real closure
+    if let ObjectType::Cons(body) = def.cdr().untag() {
+        if let ObjectType::Cons(first) = body.car().untag() {
+            if first.car() == sym::KW_DOCUMENTATION {
+                return None;
+            }
+        }
+    }
+    Some(def)
+}
+
 fn bind_args<'a>(
     arg_list: Object,
     args: &[Object<'a>],
@@ -949,6 +1496,69 @@ mod test {
         check_interpreter("(let ((i 3) (x 0)) (while (progn (setq x (1- x)) (> i 0)) (setq x (+ x i) i (1- i) )) x)", 2, cx);
     }
 
+    #[test]
+    fn registered_special_forms() {
+        fn test_progn<'ob>(
+            interp: &mut Interpreter,
+            forms: &Rto<Object>,
+            cx: &'ob mut Context,
+        ) -> EvalResult<'ob> {
+            interp.eval_progn(forms, cx)
+        }
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        let uninterned = Symbol::new_uninterned("test-uninterned-progn", cx);
+        assert!(register_special_form(uninterned, test_progn).is_err());
+        assert!(register_special_form(sym::IF, test_progn).is_err());
+        assert!(register_special_form(sym::SHOULD, test_progn).is_err());
+        // Looking up a special form freezes the table
+        assert!(special_form_p(sym::IF.into()));
+        assert!(special_form_p(sym::STREAM_CONS.into()));
+        assert!(!special_form_p(sym::CAR.into()));
+        let name = intern("test-registered-progn", cx);
+        assert!(register_special_form(name, test_progn).is_err());
+        assert!(!special_form_p(name.into()));
+        // The builtin and subsystem forms don't share any names
+        assert_eq!(special_forms().len(), static_special_forms().count());
+    }
+
+    #[test]
+    fn destructuring_bind() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        check_interpreter("(cl-destructuring-bind (a b) '(1 2) (+ a b))", 3, cx);
+        check_interpreter(
+            "(cl-destructuring-bind (a (b c) . d) '(1 (2 3) 4 5) (+ a b c (length d)))",
+            8,
+            cx,
+        );
+        check_interpreter(
+            "(cl-destructuring-bind (a &optional (b (1+ a)) (c 10 c-p)) '(1)
+               (+ a b c (if c-p 100 0)))",
+            13,
+            cx,
+        );
+        check_interpreter(
+            "(cl-destructuring-bind (&key a ((:bee b) 5) (c 3 c-p)) '(:a 1 :c 7)
+               (+ a b c (if c-p 100 0)))",
+            113,
+            cx,
+        );
+        check_interpreter(
+            "(cl-destructuring-bind (x &rest r &key y &allow-other-keys) '(1 :y 2 :z 3)
+               (+ x y (length r)))",
+            7,
+            cx,
+        );
+        check_interpreter("(cl-destructuring-bind (a &aux (b (* a 2))) '(3) (+ a b))", 9, cx);
+        check_error("(cl-destructuring-bind (a b) '(1) a)", cx);
+        check_interpreter("(special-form-p 'cl-destructuring-bind)", true, cx);
+        check_error("(cl-destructuring-bind (a) '(1 2) a)", cx);
+        check_error("(cl-destructuring-bind (&key a) '(:b 1) a)", cx);
+        check_error("(cl-destructuring-bind (&rest) nil)", cx);
+    }
+
     #[test]
     fn special_forms() {
         let roots = &RootSet::default();
@@ -1074,6 +1684,207 @@ mod test {
         check_error("(condition-case nil (if))", cx);
         check_error("(condition-case nil (if) nil)", cx);
         check_error("(condition-case nil (if) 5 (error 7))", cx);
+        check_interpreter("(condition-case nil (car 5) (wrong-type-argument 3))", 3, cx);
+        check_interpreter("(condition-case nil (car 5) (void-variable 2) (t 4))", 4, cx);
+        check_interpreter(
+            "(condition-case e (car 5) (error (equal e '(wrong-type-argument listp 5))))",
+            true,
+            cx,
+        );
+        check_interpreter(
+            "(condition-case e unbound-var (void-variable (eq (car (cdr e)) 'unbound-var)))",
+            true,
+            cx,
+        );
+        check_interpreter(
+            "(condition-case nil (condition-case nil (if) (void-variable 1)) (error 2))",
+            2,
+            cx,
+        );
+        check_error("(condition-case nil (signal 'quit nil) (error 1))", cx);
+    }
+
+    #[test]
+    fn test_define_error() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        let define = "(define-error 'test-a \"A\") (define-error 'test-b \"B\" 'test-a)";
+        let test = format!("(progn {define} (condition-case nil (signal 'test-b nil) (test-a 1)))");
+        check_interpreter(&test, 1, cx);
+        let test = format!("(progn {define} (condition-case nil (signal 'test-b nil) (error 2)))");
+        check_interpreter(&test, 2, cx);
+        let test =
+            format!("(progn {define} (condition-case nil (signal 'test-a nil) (test-b 3) (t 4)))");
+        check_interpreter(&test, 4, cx);
+        let test = format!(
+            "(progn {define} (equal (get 'test-b 'error-conditions) '(test-b test-a error)))"
+        );
+        check_interpreter(&test, true, cx);
+        // every parent in a list has to be an error already
+        check_error(
+            &format!("(progn {define} (define-error 'test-c nil '(test-b arith-error)))"),
+            cx,
+        );
+    }
+
+    #[test]
+    fn test_defconst() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        check_interpreter("(progn (defconst dc-a 1) (defconst dc-a 2) dc-a)", 2, cx);
+        check_interpreter("(progn (defconst dc-b 1) (let ((dc-b 2)) dc-b))", 2, cx);
+        check_error("(progn (defconst dc-c 1) (setq dc-c 2))", cx);
+        check_error("(progn (defconst dc-d 1) (set 'dc-d 2))", cx);
+        check_error("(defconst dc-e)", cx);
+        check_error("(defconst :dc-f 1)", cx);
+        let test = "(condition-case e (progn (defconst dc-g 1) (setq dc-g 2))
+                      (setting-constant (equal e '(setting-constant dc-g))))";
+        check_interpreter(test, true, cx);
+        check_interpreter("(condition-case nil (setq t 2) (setting-constant 3))", 3, cx);
+        check_interpreter("(condition-case nil (set :dc-h 2) (setting-constant 4))", 4, cx);
+    }
+
+    #[test]
+    fn test_variable_watchers() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        let test = "(let ((log nil))
+                      (defvar vw-x 0)
+                      (add-variable-watcher 'vw-x #'(lambda (&rest args)
+                                                      (setq log (cons args log))))
+                      (setq vw-x 1)
+                      (let ((vw-x 2)) nil)
+                      (set 'vw-x 3)
+                      (makunbound 'vw-x)
+                      (equal (reverse log) '((vw-x 1 set nil) (vw-x 2 let nil) (vw-x 1 unlet nil)
+                                             (vw-x 3 set nil) (vw-x nil makunbound nil))))";
+        check_interpreter(test, true, cx);
+        let test = "(progn (add-variable-watcher 'vw-y 'car) (add-variable-watcher 'vw-y 'car)
+                           (equal (get-variable-watchers 'vw-y) '(car)))";
+        check_interpreter(test, true, cx);
+        let test = "(progn (add-variable-watcher 'vw-z 'car) (remove-variable-watcher 'vw-z 'car)
+                           (get-variable-watchers 'vw-z))";
+        check_interpreter(test, false, cx);
+        // watchers run before the change, so they see the old value
+        let test = "(let ((log nil))
+                      (defvar vw-v 0)
+                      (add-variable-watcher 'vw-v #'(lambda (_sym new _op _buf)
+                                                      (setq log (cons (list vw-v new) log))))
+                      (setq vw-v 1)
+                      (let ((vw-v 2)) nil)
+                      (equal (reverse log) '((0 1) (1 2) (2 1))))";
+        check_interpreter(test, true, cx);
+        // errors in a watcher are signaled by the form that made the change,
+        // which then doesn't happen
+        check_error("(progn (add-variable-watcher 'vw-w 'car) (setq vw-w 1) nil)", cx);
+        let test = "(progn (defvar vw-u 0) (add-variable-watcher 'vw-u 'car)
+                           (list (condition-case nil (progn (setq vw-u 1) 'after) (error 'caught))
+                                 vw-u))";
+        check_interpreter(test, list![intern("caught", cx), 0; cx], cx);
+    }
+
+    #[test]
+    fn test_inline_lambda() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        // Count the closures made. Lambdas that don't escape are run inline.
+        let test = "(progn
+                      (defvar il-count 0)
+                      (setq internal-make-interpreted-closure-function
+                            #'(lambda (fun env)
+                                (setq il-count (1+ il-count))
+                                (cons 'closure (cons env (cdr fun)))))
+                      (let ((y 10) (sum 0))
+                        (list (funcall #'(lambda (x &optional z &rest r) (list x z r)) 1 2 3 4)
+                              (mapcar #'(lambda (x) (+ x y)) '(1 2 3))
+                              (progn (mapc #'(lambda (x) (setq sum (+ sum x))) '(1 2 3)) sum)
+                              (funcall (funcall #'(lambda (x) #'(lambda () x)) 5))
+                              il-count)))";
+        let list = list![list![1, 2, list![3, 4; cx]; cx], list![11, 12, 13; cx], 6, 5, 1; cx];
+        check_interpreter(test, list, cx);
+        // A lambda that escapes is a closure
+        let test = "(progn
+                      (defvar il-count 0)
+                      (setq internal-make-interpreted-closure-function
+                            #'(lambda (fun env)
+                                (setq il-count (1+ il-count))
+                                (cons 'closure (cons env (cdr fun)))))
+                      (let ((f #'(lambda (x) x)))
+                        (list (funcall f 1) il-count)))";
+        check_interpreter(test, list![1, 1; cx], cx);
+        check_interpreter("(mapcar #'(lambda (x) x) nil)", false, cx);
+        check_error("(funcall #'(lambda (x) x))", cx);
+        check_error("(mapcar #'(lambda (x) x) 1)", cx);
+        // The parameters don't stay in scope after the body signals
+        let test = "(let ((x 1))
+                      (condition-case nil (funcall #'(lambda (x) (error \"e\")) 2) (error x)))";
+        check_interpreter(test, 1, cx);
+    }
+
+    #[test]
+    fn test_handler_bind() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        // the handler runs before the let binding is unwound
+        let test = "(progn (defvar hb-var 'outer) (defvar hb-seen nil)
+                     (condition-case nil
+                         (handler-bind-1 #'(lambda () (let ((hb-var 'inner)) (signal 'hb-err nil)))
+                                         '(hb-err)
+                                         #'(lambda (err) (setq hb-seen (list (car err) hb-var))))
+                       (error (cons hb-var hb-seen))))";
+        let expect = list![intern("outer", cx), intern("hb-err", cx), intern("inner", cx); cx];
+        root!(expect, cx);
+        check_interpreter(test, expect, cx);
+        // each handler runs once, and the error keeps going after it returns
+        let test = "(let ((n 0))
+                      (condition-case nil
+                          (handler-bind-1 #'(lambda () (progn (list (car 1))))
+                                          '(error) #'(lambda (_) (setq n (+ n 1))))
+                        (error n)))";
+        check_interpreter(test, 1, cx);
+        // a condition-case inside handles the error first
+        let test = "(let ((ran nil))
+                      (list (handler-bind-1 #'(lambda () (condition-case nil (car 1) (error 1)))
+                                            '(error) #'(lambda (_) (setq ran t)))
+                            ran))";
+        let expect = list![1, false; cx];
+        root!(expect, cx);
+        check_interpreter(test, expect, cx);
+        // a handler can exit nonlocally
+        let test = "(catch 'done
+                      (handler-bind-1 #'(lambda () (car 1))
+                                      '(error) #'(lambda (_) (throw 'done 2))))";
+        check_interpreter(test, 2, cx);
+        // handlers that don't match are skipped
+        let test = "(condition-case nil
+                        (handler-bind-1 #'(lambda () (car 1))
+                                        '(arith-error) #'(lambda (_) (throw 'done 2)))
+                      (error 3))";
+        check_interpreter(test, 3, cx);
+        check_error("(handler-bind-1 #'(lambda () (car 1)) '(error) #'(lambda (_) nil))", cx);
+    }
+
+    #[test]
+    fn test_eval_lexical() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        check_interpreter("(funcall (eval '(let ((x 1)) (function (lambda () x))) t))", 1, cx);
+        check_interpreter("(funcall (eval '(let ((x 1)) #'(lambda () x)) '((x . 2))))", 1, cx);
+        check_interpreter("(eval '(let ((x 1)) x) '((x . 2)))", 1, cx);
+        check_interpreter("(eval 'x '((x . 2)))", 2, cx);
+        // with dynamic binding a lambda captures nothing
+        let test = "(eq (car (eval '(let ((x 1)) (function (lambda () x))))) 'lambda)";
+        check_interpreter(test, true, cx);
+        check_error("(funcall (eval '(let ((x 1)) (function (lambda () x)))))", cx);
+        // so a let is seen by the functions it calls
+        let test = "(progn (fset 'dyn-get (eval '(function (lambda () dyn-y))))
+                            (eval '(let ((dyn-y 3)) (dyn-get))))";
+        check_interpreter(test, 3, cx);
+        let test = "(progn (fset 'dyn-get (eval '(function (lambda () dyn-y))))
+                            (eval '(funcall (function (lambda (dyn-y) (dyn-get))) 4)))";
+        check_interpreter(test, 4, cx);
+        let test = "(eq (eval '(condition-case e (car 1) (error (car e)))) 'wrong-type-argument)";
+        check_interpreter(test, true, cx);
     }
 
     #[test]
@@ -1092,4 +1903,140 @@ mod test {
         check_error("(throw 1 2)", cx);
         check_error("(catch 2 (throw 3 4))", cx);
     }
+
+    #[test]
+    fn test_type_error_context() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        let obj = crate::reader::read("(let ((foo 1)) (list (car (+ foo 4))))", cx).unwrap().0;
+        root!(obj, cx);
+        let err = eval(obj, None, env, cx).unwrap_err();
+        let expect = "wrong-type-argument listp 5 for `car' in (car (+ foo 4))";
+        assert_eq!(err.to_string().trim_end(), expect);
+    }
+
+    #[test]
+    fn test_macroexpand() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        let define = "(progn
+                        (defalias 'mx-test-inner (cons 'macro #'(lambda (x) (list 'car x))))
+                        (defalias 'mx-test-outer
+                                  (cons 'macro #'(lambda (x) (list 'mx-test-inner x))))
+                        nil)";
+        check_interpreter(define, false, cx);
+        let once = "(equal (macroexpand-1 '(mx-test-outer y)) '(mx-test-inner y))";
+        check_interpreter(once, true, cx);
+        check_interpreter("(equal (macroexpand '(mx-test-outer y)) '(car y))", true, cx);
+        check_interpreter(
+            "(equal (macroexpand '(mx-test-outer y) '((mx-test-inner))) \
+                           '(mx-test-inner y))",
+            true,
+            cx,
+        );
+        let shadow = "(equal (macroexpand-1 '(mx-test-inner y)
+                                            (list (cons 'mx-test-inner #'(lambda (x) x))))
+                             'y)";
+        check_interpreter(shadow, true, cx);
+        check_interpreter("(macroexpand-1 1)", 1, cx);
+    }
+
+    #[test]
+    fn test_circular_list() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        let circular = |body: &str| {
+            format!(
+                "(condition-case nil
+                   (let ((x (list 1 2 3))) (setcdr (cdr (cdr x)) x) {body})
+                   (circular-list t))"
+            )
+        };
+        check_interpreter(&circular("(length x)"), true, cx);
+        check_interpreter(&circular("(member 4 x)"), true, cx);
+        check_interpreter(&circular("(eval (cons 'progn x))"), true, cx);
+        check_interpreter(&circular("(< (safe-length x) 5)"), true, cx);
+        check_interpreter("(safe-length '(1 2 . 3))", 2, cx);
+        // The list is the data of the error
+        let test = "(let ((x (list 1 2 3)))
+                      (setcdr (cdr (cdr x)) x)
+                      (list (condition-case err (length x) (circular-list (eq (nth 1 err) x)))
+                            (condition-case err (eval (cons 'progn x))
+                              (circular-list (consp (nth 1 err))))))";
+        check_interpreter(test, list![true, true; cx], cx);
+    }
+
+    #[test]
+    fn test_call_forms() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        check_interpreter("(funcall '(lambda (x) (* x 2)) 3)", 6, cx);
+        check_interpreter("(equal (apply '(lambda (&rest x) x) 1 '(2)) '(1 2))", true, cx);
+        check_interpreter("(funcall #'(lambda (x) (+ x 1)) 1)", 2, cx);
+        let test = "(condition-case err (funcall 5)
+                      (invalid-function (equal err '(invalid-function 5))))";
+        check_interpreter(test, true, cx);
+        let test = "(condition-case err (funcall '(1 2))
+                      (invalid-function (equal (cdr err) '((1 2)))))";
+        check_interpreter(test, true, cx);
+        check_error("(funcall '(lambda))", cx);
+    }
+
+    #[test]
+    fn test_save_excursion() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        let test = "(progn
+                      (set-buffer (get-buffer-create \"save-excursion-a\"))
+                      (insert \"hello\")
+                      (goto-char 2)
+                      (condition-case nil
+                          (save-excursion
+                            (goto-char 4)
+                            (set-buffer (get-buffer-create \"save-excursion-b\"))
+                            (car 1))
+                        (error nil))
+                      (equal (list (buffer-name) (point)) '(\"save-excursion-a\" 2)))";
+        check_interpreter(test, true, cx);
+        let test = "(progn
+                      (set-buffer (get-buffer-create \"save-excursion-a\"))
+                      (goto-char 2)
+                      (save-current-buffer
+                        (set-buffer (get-buffer-create \"save-excursion-b\")))
+                      (save-excursion (goto-char 4))
+                      (equal (list (buffer-name) (point)) '(\"save-excursion-a\" 2)))";
+        check_interpreter(test, true, cx);
+    }
+
+    #[test]
+    fn test_autoload() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        let dir = std::env::temp_dir();
+        let file = |name: &str, source: &str| {
+            let path = dir.join(format!("rune-autoload-{name}-{}.el", std::process::id()));
+            std::fs::write(&path, source).unwrap();
+            path.to_string_lossy().into_owned()
+        };
+        let func = file("func", "(defalias 'autoload-test-fn #'(lambda (x) (* x 2)))");
+        let mcro = file("macro", "(defalias 'autoload-test-macro (cons 'macro #'(lambda (x) x)))");
+        let empty = file("empty", "");
+        let test =
+            format!("(progn (autoload 'autoload-test-fn {func:?}) (funcall 'autoload-test-fn 3))");
+        check_interpreter(&test, 6, cx);
+        check_interpreter("(autoload-test-fn 4)", 8, cx);
+        let test = format!(
+            "(progn (autoload 'autoload-test-macro {mcro:?} nil nil 'macro)
+                    (autoload-test-macro (+ 1 2)))"
+        );
+        check_interpreter(&test, 3, cx);
+        let test = format!("(progn (autoload 'autoload-test-void {empty:?}) (autoload-test-void))");
+        check_error(&test, cx);
+        for path in [func, mcro, empty] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
 }
@@ -0,0 +1,578 @@
+//! Parsing and serializing JSON.
+use crate::core::{
+    cons::Cons,
+    env::{intern, sym},
+    gc::Context,
+    object::{HashTable, IntoObject, Keywords, Object, ObjectType, Symbol, TRUE},
+};
+use crate::fns::slice_into_list;
+use anyhow::{bail, ensure, Result};
+use rune_core::hashmap::HashSet;
+use rune_macros::{defun, FromKeywords};
+use std::fmt::Write as _;
+
+defsym!(KW_OBJECT_TYPE);
+defsym!(KW_ARRAY_TYPE);
+defsym!(KW_NULL_OBJECT);
+defsym!(KW_FALSE_OBJECT);
+defsym!(KW_NULL);
+defsym!(KW_FALSE);
+defsym!(ALIST);
+defsym!(PLIST);
+defsym!(ARRAY);
+
+/// Maximum nesting of arrays and objects. This keeps deeply nested input from
+/// overflowing the stack.
+const MAX_DEPTH: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ObjectKind {
+    HashTable,
+    Alist,
+    Plist,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArrayKind {
+    Array,
+    List,
+}
+
+struct Options<'ob> {
+    object_type: ObjectKind,
+    array_type: ArrayKind,
+    null: Object<'ob>,
+    false_: Object<'ob>,
+}
+
+#[derive(FromKeywords)]
+struct JsonArgs<'ob> {
+    object_type: Option<Symbol<'ob>>,
+    array_type: Option<Symbol<'ob>>,
+    null_object: Option<Object<'ob>>,
+    false_object: Option<Object<'ob>>,
+}
+
+impl<'ob> Options<'ob> {
+    fn new(args: &JsonArgs<'ob>) -> Result<Self> {
+        let object_type = match args.object_type {
+            None | Some(sym::HASH_TABLE) => ObjectKind::HashTable,
+            Some(sym::ALIST) => ObjectKind::Alist,
+            Some(sym::PLIST) => ObjectKind::Plist,
+            Some(other) => bail!("Invalid :object-type {other}"),
+        };
+        let array_type = match args.array_type {
+            None | Some(sym::ARRAY) => ArrayKind::Array,
+            Some(sym::LIST) => ArrayKind::List,
+            Some(other) => bail!("Invalid :array-type {other}"),
+        };
+        Ok(Options {
+            object_type,
+            array_type,
+            null: args.null_object.unwrap_or_else(|| sym::KW_NULL.into()),
+            false_: args.false_object.unwrap_or_else(|| sym::KW_FALSE.into()),
+        })
+    }
+}
+
+struct Parser<'a, 'ob> {
+    input: &'a str,
+    pos: usize,
+    opts: Options<'ob>,
+    cx: &'ob Context<'ob>,
+}
+
+impl<'a, 'ob> Parser<'a, 'ob> {
+    fn peek(&self) -> Option<u8> {
+        self.input.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<()> {
+        match self.peek() {
+            Some(b) if b == byte => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => self.error(&format!("expected `{}'", byte as char)),
+        }
+    }
+
+    fn error<T>(&self, msg: &str) -> Result<T> {
+        match self.peek() {
+            Some(_) => bail!("JSON parse error: {msg} at position {}", self.pos),
+            None => bail!("JSON parse error: {msg} at end of input"),
+        }
+    }
+
+    fn parse_literal(&mut self, literal: &str, value: Object<'ob>) -> Result<Object<'ob>> {
+        if self.input[self.pos..].starts_with(literal) {
+            self.pos += literal.len();
+            Ok(value)
+        } else {
+            self.error("invalid literal")
+        }
+    }
+
+    fn parse_value(&mut self, depth: usize) -> Result<Object<'ob>> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.parse_object(depth + 1),
+            Some(b'[') => self.parse_array(depth + 1),
+            Some(b'"') => {
+                let string = self.parse_string()?;
+                Ok(self.cx.add(string))
+            }
+            Some(b't') => self.parse_literal("true", TRUE),
+            Some(b'f') => self.parse_literal("false", self.opts.false_),
+            Some(b'n') => self.parse_literal("null", self.opts.null),
+            Some(b'-' | b'0'..=b'9') => self.parse_number(),
+            Some(_) => self.error("unexpected character"),
+            None => self.error("unexpected end of input"),
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Object<'ob>> {
+        let start = self.pos;
+        let digits = |parser: &mut Self| {
+            let begin = parser.pos;
+            while let Some(b'0'..=b'9') = parser.peek() {
+                parser.pos += 1;
+            }
+            parser.pos > begin
+        };
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        if !digits(self) {
+            return self.error("invalid number");
+        }
+        let mut float = false;
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            float = true;
+            if !digits(self) {
+                return self.error("invalid number");
+            }
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            self.pos += 1;
+            float = true;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.pos += 1;
+            }
+            if !digits(self) {
+                return self.error("invalid number");
+            }
+        }
+        let text = &self.input[start..self.pos];
+        if !float {
+            if let Ok(int) = text.parse::<i64>() {
+                return Ok(int.into());
+            }
+        }
+        match text.parse::<f64>() {
+            Ok(x) => Ok(self.cx.add(x)),
+            Err(_) => self.error("invalid number"),
+        }
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32> {
+        let Some(hex) = self.input.get(self.pos..self.pos + 4) else {
+            return self.error("invalid unicode escape");
+        };
+        match u32::from_str_radix(hex, 16) {
+            Ok(x) => {
+                self.pos += 4;
+                Ok(x)
+            }
+            Err(_) => self.error("invalid unicode escape"),
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String> {
+        self.expect(b'"')?;
+        let mut string = String::new();
+        loop {
+            let Some(chr) = self.input[self.pos..].chars().next() else {
+                return self.error("unterminated string");
+            };
+            self.pos += chr.len_utf8();
+            match chr {
+                '"' => return Ok(string),
+                '\\' => {
+                    let Some(escape) = self.peek() else {
+                        return self.error("unterminated string");
+                    };
+                    self.pos += 1;
+                    match escape {
+                        b'"' => string.push('"'),
+                        b'\\' => string.push('\\'),
+                        b'/' => string.push('/'),
+                        b'b' => string.push('\u{8}'),
+                        b'f' => string.push('\u{c}'),
+                        b'n' => string.push('\n'),
+                        b'r' => string.push('\r'),
+                        b't' => string.push('\t'),
+                        b'u' => {
+                            let mut code = self.parse_hex4()?;
+                            if (0xD800..0xDC00).contains(&code) {
+                                // surrogate pair
+                                if !self.input[self.pos..].starts_with("\\u") {
+                                    return self.error("unpaired surrogate");
+                                }
+                                self.pos += 2;
+                                let low = self.parse_hex4()?;
+                                if !(0xDC00..0xE000).contains(&low) {
+                                    return self.error("invalid surrogate pair");
+                                }
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            }
+                            match char::from_u32(code) {
+                                Some(chr) => string.push(chr),
+                                None => return self.error("invalid unicode escape"),
+                            }
+                        }
+                        _ => return self.error("invalid escape"),
+                    }
+                }
+                '\0'..='\u{1f}' => return self.error("control character in string"),
+                chr => string.push(chr),
+            }
+        }
+    }
+
+    fn parse_array(&mut self, depth: usize) -> Result<Object<'ob>> {
+        if depth > MAX_DEPTH {
+            return self.error("too deeply nested");
+        }
+        self.expect(b'[')?;
+        let mut elements = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+        } else {
+            loop {
+                elements.push(self.parse_value(depth)?);
+                self.skip_whitespace();
+                match self.peek() {
+                    Some(b',') => self.pos += 1,
+                    Some(b']') => {
+                        self.pos += 1;
+                        break;
+                    }
+                    _ => return self.error("expected `,' or `]'"),
+                }
+            }
+        }
+        Ok(match self.opts.array_type {
+            ArrayKind::Array => self.cx.add(elements),
+            ArrayKind::List => slice_into_list(&elements, None, self.cx),
+        })
+    }
+
+    fn parse_object(&mut self, depth: usize) -> Result<Object<'ob>> {
+        if depth > MAX_DEPTH {
+            return self.error("too deeply nested");
+        }
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+        } else {
+            loop {
+                self.skip_whitespace();
+                let key = self.parse_string()?;
+                self.skip_whitespace();
+                self.expect(b':')?;
+                let value = self.parse_value(depth)?;
+                members.push((key, value));
+                self.skip_whitespace();
+                match self.peek() {
+                    Some(b',') => self.pos += 1,
+                    Some(b'}') => {
+                        self.pos += 1;
+                        break;
+                    }
+                    _ => return self.error("expected `,' or `}'"),
+                }
+            }
+        }
+        let cx = self.cx;
+        Ok(match self.opts.object_type {
+            ObjectKind::HashTable => {
+                let mut table = HashTable::default();
+                for (key, value) in members {
+                    // later duplicates replace earlier ones
+                    table.insert(cx.add(key), value);
+                }
+                table.into_obj(cx).into()
+            }
+            ObjectKind::Alist => {
+                let mut seen = HashSet::default();
+                let pairs: Vec<Object> = members
+                    .into_iter()
+                    .filter(|(key, _)| seen.insert(key.clone()))
+                    .map(|(key, value)| Cons::new(intern(&key, cx), value, cx).into())
+                    .collect();
+                slice_into_list(&pairs, None, cx)
+            }
+            ObjectKind::Plist => {
+                let mut seen = HashSet::default();
+                let mut plist = Vec::new();
+                for (key, value) in members {
+                    if seen.insert(key.clone()) {
+                        plist.push(intern(&format!(":{key}"), cx).into());
+                        plist.push(value);
+                    }
+                }
+                slice_into_list(&plist, None, cx)
+            }
+        })
+    }
+}
+
+/// Return the name used for a JSON object key.
+fn object_key(key: Object, strip_colon: bool) -> Result<String> {
+    match key.untag() {
+        ObjectType::String(s) => Ok(String::from(&**s)),
+        ObjectType::Symbol(s) => {
+            let name = s.name();
+            Ok(match name.strip_prefix(':') {
+                Some(name) if strip_colon => name.to_owned(),
+                _ => name.to_owned(),
+            })
+        }
+        _ => bail!("Invalid JSON object key {key}"),
+    }
+}
+
+fn serialize_string(string: &str, out: &mut String) {
+    out.push('"');
+    for chr in string.chars() {
+        match chr {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            '\0'..='\u{1f}' => write!(out, "\\u{:04x}", chr as u32).unwrap(),
+            chr => out.push(chr),
+        }
+    }
+    out.push('"');
+}
+
+fn serialize_members<'ob>(
+    members: impl Iterator<Item = Result<(String, Object<'ob>)>>,
+    opts: &Options,
+    out: &mut String,
+    depth: usize,
+) -> Result<()> {
+    let mut seen = HashSet::default();
+    out.push('{');
+    for member in members {
+        let (key, value) = member?;
+        // Only the first occurrence of a key is used
+        if !seen.insert(key.clone()) {
+            continue;
+        }
+        if seen.len() > 1 {
+            out.push(',');
+        }
+        serialize_string(&key, out);
+        out.push(':');
+        serialize(value, opts, out, depth)?;
+    }
+    out.push('}');
+    Ok(())
+}
+
+fn serialize(obj: Object, opts: &Options, out: &mut String, depth: usize) -> Result<()> {
+    ensure!(depth <= MAX_DEPTH, "JSON serialization error: object is too deeply nested");
+    let depth = depth + 1;
+    if obj == opts.null {
+        out.push_str("null");
+        return Ok(());
+    }
+    if obj == opts.false_ {
+        out.push_str("false");
+        return Ok(());
+    }
+    match obj.untag() {
+        ObjectType::Symbol(sym::TRUE) => out.push_str("true"),
+        ObjectType::NIL => out.push_str("{}"),
+        ObjectType::Int(i) => write!(out, "{i}").unwrap(),
+        ObjectType::Float(f) => {
+            let f = **f;
+            ensure!(f.is_finite(), "JSON serialization error: invalid number {f}");
+            if f.fract() == 0.0 && f.abs() < 1e17 {
+                write!(out, "{f:.1}").unwrap();
+            } else {
+                write!(out, "{f}").unwrap();
+            }
+        }
+        ObjectType::String(s) => serialize_string(s, out),
+        ObjectType::Vec(vec) => {
+            out.push('[');
+            for (i, elem) in vec.iter().enumerate() {
+                if i != 0 {
+                    out.push(',');
+                }
+                serialize(elem.get(), opts, out, depth)?;
+            }
+            out.push(']');
+        }
+        ObjectType::HashTable(table) => {
+            let members = (0..table.len()).filter_map(|i| table.get_index(i)).map(|(k, v)| {
+                let key = object_key(k, false)?;
+                Ok((key, v))
+            });
+            serialize_members(members, opts, out, depth)?;
+        }
+        ObjectType::Cons(cons) => match cons.car().untag() {
+            // alist
+            ObjectType::Cons(_) => {
+                let members = obj.as_list()?.map(|elem| {
+                    let ObjectType::Cons(pair) = elem?.untag() else {
+                        bail!("JSON serialization error: invalid alist {obj}")
+                    };
+                    Ok((object_key(pair.car(), false)?, pair.cdr()))
+                });
+                serialize_members(members, opts, out, depth)?;
+            }
+            // plist
+            ObjectType::Symbol(_) => {
+                let elements: Vec<Object> = obj.as_list()?.collect::<Result<_, _>>()?;
+                ensure!(
+                    elements.len() % 2 == 0,
+                    "JSON serialization error: plist has odd length {obj}"
+                );
+                let members =
+                    elements.chunks(2).map(|pair| Ok((object_key(pair[0], true)?, pair[1])));
+                serialize_members(members, opts, out, depth)?;
+            }
+            _ => bail!("JSON serialization error: invalid list {obj}"),
+        },
+        _ => bail!("JSON serialization error: can't serialize {obj}"),
+    }
+    Ok(())
+}
+
+#[defun]
+fn json_parse_string<'ob>(
+    string: &str,
+    args: Keywords<JsonArgs<'ob>>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let opts = Options::new(&args)?;
+    let mut parser = Parser { input: string, pos: 0, opts, cx };
+    let value = parser.parse_value(0)?;
+    parser.skip_whitespace();
+    if parser.peek().is_some() {
+        return parser.error("trailing content");
+    }
+    Ok(value)
+}
+
+#[defun]
+fn json_serialize<'ob>(object: Object<'ob>, args: Keywords<JsonArgs<'ob>>) -> Result<String> {
+    let opts = Options::new(&args)?;
+    let mut out = String::new();
+    serialize(object, &opts, &mut out, 0)?;
+    Ok(out)
+}
+
+#[defun]
+fn json_available_p() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::{gc::RootSet, object::NIL};
+    use rune_core::macros::list;
+
+    fn kw<'ob>(args: &[Object<'ob>]) -> Keywords<JsonArgs<'ob>> {
+        Keywords::from_slice(args).unwrap()
+    }
+
+    fn parse<'ob>(string: &str, args: &[Object<'ob>], cx: &'ob Context) -> Object<'ob> {
+        json_parse_string(string, kw(args), cx).unwrap()
+    }
+
+    #[test]
+    fn test_json_parse() {
+        sym::init_symbols();
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        assert_eq!(parse("1", &[], cx), 1);
+        assert_eq!(parse(" -2.5e1 ", &[], cx), cx.add(-25.0));
+        let string = "\"a\\nb\\u00e9\\ud83d\\ude00\"";
+        assert_eq!(parse(string, &[], cx), cx.add("a\nb\u{e9}\u{1f600}"));
+        assert_eq!(parse("true", &[], cx), TRUE);
+        assert_eq!(parse("null", &[], cx), sym::KW_NULL);
+        assert_eq!(parse("false", &[], cx), sym::KW_FALSE);
+        let args = [sym::KW_NULL_OBJECT.into(), NIL, sym::KW_FALSE_OBJECT.into(), NIL];
+        assert_eq!(parse("[null, false]", &args, cx), cx.add(vec![NIL, NIL]));
+        let args = [sym::KW_ARRAY_TYPE.into(), sym::LIST.into()];
+        assert_eq!(parse("[1, [2]]", &args, cx), list![1, list![2; cx]; cx]);
+        let args = [sym::KW_OBJECT_TYPE.into(), sym::ALIST.into()];
+        let a = intern("a", cx);
+        let expect = list![Cons::new(a, 1, cx), Cons::new(intern("b", cx), 2, cx); cx];
+        assert_eq!(parse("{\"a\": 1, \"b\": 2, \"a\": 3}", &args, cx), expect);
+        let args = [sym::KW_OBJECT_TYPE.into(), sym::PLIST.into()];
+        assert_eq!(parse("{\"a\": 1}", &args, cx), list![intern(":a", cx), 1; cx]);
+        let table = parse("{\"a\": 1}", &[], cx);
+        assert!(matches!(table.untag(), ObjectType::HashTable(x) if x.len() == 1));
+    }
+
+    #[test]
+    fn test_json_parse_error() {
+        sym::init_symbols();
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        for input in ["", "[1,", "{\"a\" 1}", "tru", "01x", "\"\\ud800\"", "1 2", "[1,]"] {
+            assert!(json_parse_string(input, kw(&[]), cx).is_err(), "{input}");
+        }
+        let nested = "[".repeat(MAX_DEPTH + 1);
+        assert!(json_parse_string(&nested, kw(&[]), cx).is_err());
+        assert!(Keywords::<JsonArgs>::from_slice(&[sym::KW_TEST.into(), NIL]).is_err());
+        assert!(Keywords::<JsonArgs>::from_slice(&[sym::KW_NULL_OBJECT.into()]).is_err());
+    }
+
+    #[test]
+    fn test_json_serialize() {
+        sym::init_symbols();
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let ser = |obj: Object| json_serialize(obj, kw(&[])).unwrap();
+        assert_eq!(ser(1.into()), "1");
+        assert_eq!(ser(cx.add(1.5)), "1.5");
+        assert_eq!(ser(cx.add(2.0)), "2.0");
+        assert_eq!(ser(cx.add("a\"\n\u{1}")), "\"a\\\"\\n\\u0001\"");
+        assert_eq!(ser(TRUE), "true");
+        assert_eq!(ser(NIL), "{}");
+        assert_eq!(ser(sym::KW_NULL.into()), "null");
+        assert_eq!(ser(sym::KW_FALSE.into()), "false");
+        assert_eq!(ser(cx.add(vec![1.into(), TRUE])), "[1,true]");
+        let a = intern("a", cx);
+        let alist = list![Cons::new(a, 1, cx), Cons::new(a, 2, cx); cx];
+        assert_eq!(ser(alist), "{\"a\":1}");
+        let plist = list![intern(":a", cx), 1, intern(":b", cx), "x"; cx];
+        assert_eq!(ser(plist), "{\"a\":1,\"b\":\"x\"}");
+        let args = [sym::KW_NULL_OBJECT.into(), NIL];
+        assert_eq!(json_serialize(cx.add(vec![NIL]), kw(&args)).unwrap(), "[null]");
+        assert!(json_serialize(list![1, 2; cx], kw(&[])).is_err());
+        assert!(json_serialize(cx.add(f64::NAN), kw(&[])).is_err());
+    }
+}
@@ -1,21 +1,457 @@
 //! Keymap handling.
-use crate::core::object::Object;
+//!
+//! Keymaps are lists like in Emacs: `(keymap [PROMPT] (EVENT . BINDING)...)`.
+//! The parent of a keymap is the tail of the list that starts with another
+//! `keymap` symbol, so looking up an event in the whole list also searches the
+//! parents. An event is a character with modifier bits, or a symbol like `f1`
+//! or `C-f1` for function keys.
+use crate::core::{
+    cons::Cons,
+    env::{intern, sym},
+    error::{Type, TypeError},
+    gc::Context,
+    object::{Object, ObjectType, NIL},
+};
+use anyhow::{bail, ensure, Result};
 use rune_macros::defun;
 
-// TODO: implement keymaps
+defsym!(KEYMAP);
+defvar!(MINIBUFFER_LOCAL_MAP);
+
+const ALT: i64 = 1 << 22;
+const SUPER: i64 = 1 << 23;
+const HYPER: i64 = 1 << 24;
+const SHIFT: i64 = 1 << 25;
+const CTRL: i64 = 1 << 26;
+const META: i64 = 1 << 27;
+
+/// The modifier prefixes in the order that `key-description` prints them.
+const MODIFIERS: [(char, i64); 6] =
+    [('A', ALT), ('C', CTRL), ('H', HYPER), ('M', META), ('S', SHIFT), ('s', SUPER)];
+
+const MODIFIER_MASK: i64 = ALT | SUPER | HYPER | SHIFT | CTRL | META;
+
+/// The keymap `object` refers to, either directly or through the function cell
+/// of a symbol.
+fn get_keymap<'ob>(object: Object<'ob>, cx: &'ob Context) -> Option<&'ob Cons> {
+    let object = match object.untag() {
+        ObjectType::Symbol(symbol) => symbol.follow_indirect(cx)?.into(),
+        _ => object,
+    };
+    match object.untag() {
+        ObjectType::Cons(cons) if cons.car() == sym::KEYMAP => Some(cons),
+        _ => None,
+    }
+}
+
+fn expect_keymap<'ob>(object: Object<'ob>, cx: &'ob Context) -> Result<&'ob Cons> {
+    match get_keymap(object, cx) {
+        Some(keymap) => Ok(keymap),
+        None => bail!("Wrong type argument: keymapp, {object}"),
+    }
+}
+
+/// The events of a key sequence, which is either a string or a vector.
+fn key_events(key: Object) -> Result<Vec<Object>> {
+    match key.untag() {
+        ObjectType::String(string) => Ok(string.chars().map(|c| (c as i64).into()).collect()),
+        ObjectType::Vec(vec) => Ok(vec.iter().map(|x| x.get()).collect()),
+        _ => Err(TypeError::new(Type::Sequence, key).into()),
+    }
+}
+
+/// The cells of `keymap` that belong to it rather than to its parent. The first
+/// one is the `keymap` header.
+fn own_cells(keymap: &Cons) -> impl Iterator<Item = &Cons> {
+    let rest = keymap.conses().skip(1).map_while(|x| x.ok());
+    std::iter::once(keymap).chain(rest.take_while(|x| x.car() != sym::KEYMAP))
+}
+
+/// The binding cell for `event` in `keymap` itself, not its parents.
+fn own_binding<'ob>(keymap: &'ob Cons, event: Object) -> Option<&'ob Cons> {
+    own_cells(keymap).find_map(|cell| match cell.car().untag() {
+        ObjectType::Cons(binding) if binding.car() == event => Some(binding),
+        _ => None,
+    })
+}
+
+/// The binding of `event` in `keymap` or its parents.
+fn lookup_event<'ob>(keymap: &'ob Cons, event: Object) -> Object<'ob> {
+    for element in keymap.elements() {
+        let Ok(element) = element else { break };
+        if let ObjectType::Cons(binding) = element.untag() {
+            if binding.car() == event {
+                return binding.cdr();
+            }
+        }
+    }
+    NIL
+}
+
+/// Bind `event` to `def` in `keymap`, replacing any binding it already has.
+/// New bindings go after the header and the prompt.
+fn store_event(keymap: &Cons, event: Object, def: Object, cx: &Context) -> Result<()> {
+    if let Some(binding) = own_binding(keymap, event) {
+        return binding.set_cdr(def);
+    }
+    let header = match keymap.cdr().untag() {
+        ObjectType::Cons(prompt) if matches!(prompt.car().untag(), ObjectType::String(_)) => prompt,
+        _ => keymap,
+    };
+    let binding = Cons::new(event, def, cx);
+    header.set_cdr(Cons::new(binding, header.cdr(), cx).into())
+}
+
+/// Remove the binding of `event` from `keymap` itself.
+fn remove_event(keymap: &Cons, event: Object) -> Result<()> {
+    let mut cells = own_cells(keymap).peekable();
+    while let Some(cell) = cells.next() {
+        let Some(next) = cells.peek() else { break };
+        if matches!(next.car().untag(), ObjectType::Cons(binding) if binding.car() == event) {
+            return cell.set_cdr(next.cdr());
+        }
+    }
+    Ok(())
+}
+
+fn parent(keymap: &Cons) -> Option<&Cons> {
+    let last = own_cells(keymap).last()?;
+    match last.cdr().untag() {
+        ObjectType::Cons(parent) if parent.car() == sym::KEYMAP => Some(parent),
+        _ => None,
+    }
+}
+
 #[defun]
-fn make_keymap(_string: Option<&str>) {}
+fn make_keymap<'ob>(string: Option<Object<'ob>>, cx: &'ob Context) -> Object<'ob> {
+    // We don't have char tables, so full keymaps are the same as sparse ones
+    make_sparse_keymap(string, cx)
+}
 
 #[defun]
-fn make_sparse_keymap(_string: Option<&str>) {}
+fn make_sparse_keymap<'ob>(string: Option<Object<'ob>>, cx: &'ob Context) -> Object<'ob> {
+    match string {
+        Some(prompt) => Cons::new(sym::KEYMAP, Cons::new1(prompt, cx), cx).into(),
+        None => Cons::new1(sym::KEYMAP, cx).into(),
+    }
+}
+
+#[defun]
+fn keymapp(object: Object, cx: &Context) -> bool {
+    get_keymap(object, cx).is_some()
+}
 
 #[defun]
 fn use_global_map(_keymap: Object) {}
 
 #[defun]
-fn set_keymap_parent<'ob>(_keymap: Object<'ob>, _parent: Object<'ob>) {}
+fn keymap_parent<'ob>(keymap: Object<'ob>, cx: &'ob Context) -> Result<Object<'ob>> {
+    let keymap = expect_keymap(keymap, cx)?;
+    Ok(parent(keymap).map_or(NIL, Into::into))
+}
 
 #[defun]
-pub(crate) fn define_key<'ob>(_keymap: Object<'ob>, _key: Object<'ob>, _def: Object<'ob>) {}
+fn set_keymap_parent<'ob>(
+    keymap: Object<'ob>,
+    parent: Object<'ob>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let map = expect_keymap(keymap, cx)?;
+    if parent != NIL {
+        let mut ancestor = Some(expect_keymap(parent, cx)?);
+        while let Some(current) = ancestor {
+            ensure!(!std::ptr::eq(current, map), "Cyclic keymap inheritance");
+            ancestor = self::parent(current);
+        }
+    }
+    let last = own_cells(map).last().unwrap();
+    last.set_cdr(parent)?;
+    Ok(parent)
+}
 
-defvar!(MINIBUFFER_LOCAL_MAP);
+#[defun]
+pub(crate) fn define_key<'ob>(
+    keymap: Object<'ob>,
+    key: Object<'ob>,
+    def: Object<'ob>,
+    remove: Option<()>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let mut map = expect_keymap(keymap, cx)?;
+    let events = key_events(key)?;
+    let Some((last, prefix)) = events.split_last() else { bail!("Empty key sequence") };
+    for (i, event) in prefix.iter().enumerate() {
+        let binding = lookup_event(map, *event);
+        map = match get_keymap(binding, cx) {
+            Some(submap) if own_binding(map, *event).is_some() => submap,
+            // Prefix maps from the parent are inherited instead of modified
+            Some(inherited) => {
+                let submap = Cons::new(sym::KEYMAP, inherited, cx);
+                store_event(map, *event, submap.into(), cx)?;
+                submap
+            }
+            None if binding == NIL => {
+                let submap = Cons::new1(sym::KEYMAP, cx);
+                store_event(map, *event, submap.into(), cx)?;
+                submap
+            }
+            None => {
+                let key = describe_events(&events)?;
+                let prefix = describe_events(&events[..=i])?;
+                bail!("Key sequence {key} starts with non-prefix key {prefix}")
+            }
+        };
+    }
+    match remove {
+        Some(()) => remove_event(map, *last)?,
+        None => store_event(map, *last, def, cx)?,
+    }
+    Ok(def)
+}
+
+#[defun]
+fn lookup_key<'ob>(
+    keymap: Object<'ob>,
+    key: Object<'ob>,
+    _accept_default: Option<()>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let mut map = expect_keymap(keymap, cx)?;
+    let events = key_events(key)?;
+    for (i, event) in events.iter().enumerate() {
+        let binding = lookup_event(map, *event);
+        if i + 1 == events.len() {
+            return Ok(binding);
+        }
+        map = match get_keymap(binding, cx) {
+            Some(submap) => submap,
+            None if binding == NIL => return Ok(NIL),
+            // The key is too long, return how many events are a complete key
+            None => return Ok((i as i64 + 1).into()),
+        };
+    }
+    Ok(keymap)
+}
+
+/// The `key-description` of a single event.
+fn describe_event(event: Object) -> Result<String> {
+    let mut desc = String::new();
+    match event.untag() {
+        ObjectType::Int(code) => {
+            // ASCII control characters are described with a `C-` modifier
+            let (control, base) = match code & !MODIFIER_MASK {
+                base @ (9 | 13 | 27) => (0, base),
+                base @ 0..=31 => (CTRL, base + if base == 0 || base > 26 { 64 } else { 96 }),
+                base => (0, base),
+            };
+            for (prefix, bit) in MODIFIERS {
+                if (code | control) & bit != 0 {
+                    desc.push(prefix);
+                    desc.push('-');
+                }
+            }
+            match base {
+                9 => desc.push_str("TAB"),
+                13 => desc.push_str("RET"),
+                27 => desc.push_str("ESC"),
+                32 => desc.push_str("SPC"),
+                127 => desc.push_str("DEL"),
+                _ => match u32::try_from(base).ok().and_then(char::from_u32) {
+                    Some(c) => desc.push(c),
+                    None => bail!("Invalid key event: {event}"),
+                },
+            }
+        }
+        ObjectType::Symbol(symbol) => {
+            let mut name = symbol.name();
+            while let Some(rest) = strip_modifier(name).map(|(_, rest)| rest) {
+                desc.push_str(&name[..2]);
+                name = rest;
+            }
+            desc.push('<');
+            desc.push_str(name);
+            desc.push('>');
+        }
+        _ => bail!("Invalid key event: {event}"),
+    }
+    Ok(desc)
+}
+
+fn describe_events(events: &[Object]) -> Result<String> {
+    let descriptions = events.iter().map(|x| describe_event(*x)).collect::<Result<Vec<_>>>()?;
+    Ok(descriptions.join(" "))
+}
+
+#[defun]
+fn key_description(keys: Object, prefix: Option<Object>) -> Result<String> {
+    let mut events = match prefix {
+        Some(prefix) => key_events(prefix)?,
+        None => Vec::new(),
+    };
+    events.extend(key_events(keys)?);
+    describe_events(&events)
+}
+
+/// Split a modifier prefix like `C-` off the front of `word`. A lone `C-` is
+/// the key `C` followed by `-`, so something has to come after the prefix.
+fn strip_modifier(word: &str) -> Option<(i64, &str)> {
+    let mut chars = word.chars();
+    let prefix = chars.next()?;
+    let rest = chars.as_str().strip_prefix('-').filter(|rest| !rest.is_empty())?;
+    let (_, bit) = MODIFIERS.iter().find(|(c, _)| *c == prefix)?;
+    Some((*bit, rest))
+}
+
+/// Apply `modifiers` to the character `code`. Control is folded into the
+/// character when there is an ASCII control character for it.
+fn apply_modifiers(code: i64, modifiers: i64) -> i64 {
+    if modifiers & CTRL == 0 {
+        return code | modifiers;
+    }
+    let rest = modifiers & !CTRL;
+    match u8::try_from(code).map(char::from) {
+        Ok(c @ 'a'..='z') => (c as i64 - 96) | rest,
+        Ok(c @ 'A'..='Z') => (c as i64 - 64) | SHIFT | rest,
+        Ok(c @ ('@' | '[' | '\\' | ']' | '^' | '_')) => (c as i64 - 64) | rest,
+        Ok('?') => 127 | rest,
+        _ => code | modifiers,
+    }
+}
+
+/// Parse one word of a `kbd` string into `events`.
+fn parse_word<'ob>(word: &str, events: &mut Vec<Object<'ob>>, cx: &'ob Context) -> Result<()> {
+    let mut modifiers = 0;
+    let mut rest = word;
+    while let Some((bit, after)) = strip_modifier(rest) {
+        modifiers |= bit;
+        rest = after;
+    }
+    if let Some(name) = rest.strip_prefix('<').and_then(|x| x.strip_suffix('>')) {
+        ensure!(!name.is_empty(), "Invalid key: {word}");
+        let mut symbol = String::new();
+        for (prefix, bit) in MODIFIERS {
+            if modifiers & bit != 0 {
+                symbol.push(prefix);
+                symbol.push('-');
+            }
+        }
+        symbol.push_str(name);
+        events.push(intern(&symbol, cx).into());
+        return Ok(());
+    }
+    let code = match rest {
+        "NUL" => 0,
+        "TAB" => 9,
+        "LFD" => 10,
+        "RET" => 13,
+        "ESC" => 27,
+        "SPC" => 32,
+        "DEL" => 127,
+        _ => {
+            let mut chars = rest.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => c as i64,
+                // A word without modifiers is a sequence of characters
+                _ if modifiers == 0 => {
+                    events.extend(rest.chars().map(|c| Object::from(c as i64)));
+                    return Ok(());
+                }
+                _ => bail!("Invalid key: {word}"),
+            }
+        }
+    };
+    events.push(apply_modifiers(code, modifiers).into());
+    Ok(())
+}
+
+/// Parse `keys` in the format used by `key-description`. The result is a string
+/// if every event is an ASCII character, and a vector otherwise.
+pub(crate) fn parse_keys<'ob>(keys: &str, cx: &'ob Context) -> Result<Object<'ob>> {
+    let mut events = Vec::new();
+    for word in keys.split_whitespace() {
+        parse_word(word, &mut events, cx)?;
+    }
+    let ascii = events.iter().map(|x| match x.untag() {
+        ObjectType::Int(code) => u8::try_from(code).ok().filter(u8::is_ascii).map(char::from),
+        _ => None,
+    });
+    match ascii.collect::<Option<String>>() {
+        Some(string) => Ok(cx.add(string)),
+        None => Ok(cx.add(events)),
+    }
+}
+
+#[defun]
+fn kbd<'ob>(keys: &str, cx: &'ob Context) -> Result<Object<'ob>> {
+    parse_keys(keys, cx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+
+    fn describe(keys: &str, cx: &Context) -> String {
+        key_description(parse_keys(keys, cx).unwrap(), None).unwrap()
+    }
+
+    #[test]
+    fn test_parse_keys() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        assert_eq!(parse_keys("C-x C-f", cx).unwrap(), cx.add("\u{18}\u{6}"));
+        assert_eq!(parse_keys("a RET", cx).unwrap(), cx.add("a\r"));
+        assert_eq!(parse_keys("abc", cx).unwrap(), cx.add("abc"));
+        assert_eq!(parse_keys("M-x", cx).unwrap(), cx.add(vec![(META | 'x' as i64).into()]));
+        let f1 = parse_keys("C-<f1>", cx).unwrap();
+        assert_eq!(f1, cx.add(vec![intern("C-f1", cx).into()]));
+        assert!(parse_keys("C-<>", cx).is_err());
+        assert!(parse_keys("C-ab", cx).is_err());
+    }
+
+    #[test]
+    fn test_key_description() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        for keys in ["C-x C-f", "M-x", "C-M-<return>", "<f1> SPC", "C-@ TAB DEL", "C-c a", "C-\\"] {
+            assert_eq!(describe(keys, cx), keys);
+        }
+        assert_eq!(describe("C-A", cx), "C-S-a");
+    }
+
+    #[test]
+    fn test_define_key() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        sym::init_symbols();
+        let map = make_sparse_keymap(None, cx);
+        let find_file: Object = intern("find-file", cx).into();
+        let save: Object = intern("save-buffer", cx).into();
+        define_key(map, parse_keys("C-x C-f", cx).unwrap(), find_file, None, cx).unwrap();
+        define_key(map, parse_keys("C-x C-s", cx).unwrap(), save, None, cx).unwrap();
+        let lookup = |keys| lookup_key(map, parse_keys(keys, cx).unwrap(), None, cx).unwrap();
+        assert_eq!(lookup("C-x C-f"), find_file);
+        assert_eq!(lookup("C-x C-s"), save);
+        assert!(keymapp(lookup("C-x"), cx));
+        assert_eq!(lookup("C-x C-f a"), Object::from(2));
+        assert_eq!(lookup("C-c"), NIL);
+        assert!(define_key(map, parse_keys("C-x C-f a", cx).unwrap(), save, None, cx).is_err());
+
+        // Child maps see the bindings of their parent, and rebinding a prefix
+        // from the parent leaves the parent alone
+        let child = make_sparse_keymap(None, cx);
+        set_keymap_parent(child, map, cx).unwrap();
+        assert_eq!(keymap_parent(child, cx).unwrap(), map);
+        let save_key = parse_keys("C-x C-s", cx).unwrap();
+        assert_eq!(lookup_key(child, save_key, None, cx).unwrap(), save);
+        define_key(child, save_key, find_file, None, cx).unwrap();
+        assert_eq!(lookup_key(child, save_key, None, cx).unwrap(), find_file);
+        assert_eq!(lookup("C-x C-s"), save);
+        let find_key = parse_keys("C-x C-f", cx).unwrap();
+        assert_eq!(lookup_key(child, find_key, None, cx).unwrap(), find_file);
+        // Removing the binding from the child shows the parent's again
+        define_key(child, save_key, NIL, Some(()), cx).unwrap();
+        assert_eq!(lookup_key(child, save_key, None, cx).unwrap(), save);
+        assert!(set_keymap_parent(map, child, cx).is_err());
+    }
+}
@@ -0,0 +1,59 @@
+//! An Emacs Lisp runtime.
+//!
+//! The interpreter can be embedded in other programs through [`api`], which
+//! only exposes owned values, so nothing handed out can be invalidated by the
+//! garbage collector.
+#[macro_use]
+mod macros;
+#[macro_use]
+mod core;
+#[macro_use]
+mod debug;
+mod alloc;
+pub mod api;
+mod arith;
+mod buffer;
+mod bytecode;
+mod callint;
+mod casefiddle;
+mod character;
+mod cl_lib;
+mod cli;
+mod coding;
+mod command_line;
+mod coverage;
+mod data;
+mod decompress;
+mod destructure;
+mod dired;
+mod doc;
+mod editfns;
+mod emacs;
+mod ert;
+mod eval;
+mod fileio;
+mod filelock;
+mod floatfns;
+mod fns;
+mod http;
+mod interpreter;
+mod json;
+mod keymap;
+mod lread;
+mod module;
+mod pdump;
+mod pp;
+mod print;
+mod process;
+mod profiler;
+mod reader;
+mod sandbox;
+mod search;
+mod seq;
+mod stream;
+mod threads;
+mod timefns;
+mod xml;
+
+#[doc(hidden)]
+pub use cli::run;
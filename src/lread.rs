@@ -6,6 +6,7 @@ use crate::core::gc::{Context, Rt, Rto};
 use crate::core::object::{
     Function, Gc, LispString, Object, ObjectType, Symbol, WithLifetime, NIL, TRUE,
 };
+use crate::coding::Coding;
 use crate::reader;
 use crate::{interpreter, rooted_iter};
 use anyhow::{anyhow, Context as _};
@@ -38,6 +39,11 @@ fn check_upper_bounds(idx: Option<i64>, len: usize) -> Result<usize> {
     Ok(idx as usize)
 }
 
+/// The byte offset of character `idx` in `string`.
+fn byte_offset(string: &str, idx: usize) -> usize {
+    string.char_indices().nth(idx).map_or(string.len(), |(offset, _)| offset)
+}
+
 #[defun]
 pub(crate) fn read_from_string<'ob>(
     string: &str,
@@ -45,21 +51,167 @@ pub(crate) fn read_from_string<'ob>(
     end: Option<i64>,
     cx: &'ob Context,
 ) -> Result<Object<'ob>> {
-    let len = string.len();
+    let len = string.chars().count();
     let start = check_lower_bounds(start, len)?;
     let end = check_upper_bounds(end, len)?;
+    let start_byte = byte_offset(string, start);
+    let end_byte = byte_offset(string, end);
 
-    let (obj, new_pos) = match reader::read(&string[start..end], cx) {
+    let slice = &string[start_byte..end_byte];
+    let (obj, new_pos) = match reader::read(slice, cx) {
         Ok((obj, pos)) => (obj, pos),
         Err(mut e) => {
-            e.update_pos(start);
+            e.update_pos(start_byte);
             bail!(e);
         }
     };
+    // The end is returned as a character index into the whole string
+    let new_pos = start + slice[..new_pos].chars().count();
     Ok(Cons::new(obj, new_pos as i64, cx).into())
 }
 
+defvar!(STANDARD_INPUT, true);
+
+/// Whether reading `text` failed only because more input is needed.
+fn is_incomplete(result: &Result<(Object, usize), reader::Error>) -> bool {
+    use reader::Error as E;
+    matches!(
+        result,
+        Err(E::EmptyStream
+            | E::MissingCloseParen(_)
+            | E::MissingCloseBracket(_)
+            | E::MissingStringDel(_)
+            | E::MissingQuotedItem(_))
+    )
+}
+
+/// Where a complete object ends in `text`, if there is one. An object that runs
+/// to the end of the text might continue in the next character unless it ends
+/// with a closing delimiter.
+fn complete_object(text: &str, eof: bool, cx: &Context) -> Result<Option<usize>> {
+    let result = reader::read(text, cx);
+    if !eof && is_incomplete(&result) {
+        return Ok(None);
+    }
+    let (_, end) = result?;
+    let closed = text[..end].ends_with([')', ']', '"']);
+    Ok((eof || closed || end < text.len()).then_some(end))
+}
+
+/// Read from a function stream. It is called with no arguments to get the next
+/// character, or nil at the end, and with a character to unread it.
+fn read_from_function<'ob>(
+    function: &Rto<Function>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let mut text = String::new();
+    let end = loop {
+        let next = call!(function; env, cx)?;
+        let eof = match next.untag() {
+            ObjectType::Int(c) => {
+                let Some(c) = u32::try_from(c).ok().and_then(char::from_u32) else {
+                    bail!("Invalid character from read function: {c}")
+                };
+                text.push(c);
+                false
+            }
+            ObjectType::NIL => true,
+            _ => bail!(TypeError::new(Type::Char, next)),
+        };
+        if let Some(end) = complete_object(&text, eof, cx)? {
+            break end;
+        }
+    };
+    // Give back what was read past the object, which is at most a delimiter
+    for c in text[end..].chars().rev() {
+        let c: Object = (c as i64).into();
+        call!(function, c; env, cx)?;
+    }
+    Ok(reader::read(&text[..end], cx)?.0)
+}
+
+fn read_from_stdin<'ob>(cx: &'ob Context) -> Result<Object<'ob>> {
+    let mut text = String::new();
+    loop {
+        let eof = std::io::stdin().read_line(&mut text)? == 0;
+        if let Some(end) = complete_object(&text, eof, cx)? {
+            return Ok(reader::read(&text[..end], cx)?.0);
+        }
+    }
+}
+
+#[defun]
+pub(crate) fn read<'ob>(
+    stream: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let stream = match stream {
+        Some(stream) => stream.bind(cx),
+        None => env.vars.get(sym::STANDARD_INPUT).map_or(NIL, |x| x.bind(cx)),
+    };
+    match stream.untag() {
+        ObjectType::String(string) => Ok(reader::read(string, cx)?.0),
+        ObjectType::NIL | ObjectType::Symbol(sym::TRUE) => read_from_stdin(cx),
+        ObjectType::Buffer(buffer) => {
+            let result = env.with_buffer_mut(Some(buffer), |buffer| -> Result<Object<'ob>> {
+                let point = buffer.text.cursor();
+                let text = &buffer.text.as_str()[point.bytes()..];
+                let (obj, end) = reader::read(text, cx)?;
+                let chars = text[..end].chars().count();
+                buffer.text.set_cursor(point.chars() + chars);
+                Ok(obj)
+            });
+            result.unwrap_or_else(|| Err(anyhow!("Selecting deleted buffer")))
+        }
+        _ => {
+            let function: Function = stream.try_into()?;
+            root!(function, cx);
+            read_from_function(function, env, cx)
+        }
+    }
+}
+
+/// Whether the file `contents` asks for lexical binding with a
+/// `lexical-binding` variable in its `-*-` line. That is the first line, or
+/// the second if the first is a `#!` line.
+fn file_lexical_binding(contents: &str) -> bool {
+    let mut lines = contents.lines();
+    let mut line = lines.next().unwrap_or_default();
+    if line.starts_with("#!") {
+        line = lines.next().unwrap_or_default();
+    }
+    let Some(start) = line.find("-*-").map(|x| x + 3) else { return false };
+    let Some(len) = line[start..].find("-*-") else { return false };
+    for var in line[start..start + len].split(';') {
+        if let Some((name, value)) = var.split_once(':') {
+            if name.trim() == "lexical-binding" {
+                return value.trim() != "nil";
+            }
+        }
+    }
+    false
+}
+
+/// Evaluate `form` with lexical binding if `lexical`, or dynamic binding.
+fn eval_form<'ob>(
+    form: &Rto<Object>,
+    lexical: bool,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    if lexical {
+        interpreter::eval(form, None, env, cx)
+    } else {
+        interpreter::eval_dynamic(form, env, cx)
+    }
+}
+
+/// Evaluate the forms in `contents`. They use lexical binding unless
+/// `lexical-binding` is nil.
 pub(crate) fn load_internal(contents: &str, cx: &mut Context, env: &mut Rt<Env>) -> Result<bool> {
+    let lexical = env.vars.get(sym::LEXICAL_BINDING).map_or(true, |x| !x.bind(cx).is_nil());
     let mut pos = 0;
     let macroexpand: Option<Function> = None;
     root!(macroexpand, cx);
@@ -82,9 +234,9 @@ pub(crate) fn load_internal(contents: &str, cx: &mut Context, env: &mut Rt<Env>)
         }
         root!(obj, cx);
         let result = if let Some(fun) = macroexpand.as_ref() {
-            eager_expand(obj, fun, env, cx)
+            eager_expand(obj, fun, lexical, env, cx)
         } else {
-            interpreter::eval(obj, None, env, cx)
+            eval_form(obj, lexical, env, cx)
         };
         if let Err(e) = result {
             let content = &contents[pos..(new_pos + pos)];
@@ -100,6 +252,7 @@ pub(crate) fn load_internal(contents: &str, cx: &mut Context, env: &mut Rt<Env>)
 fn eager_expand<'ob>(
     obj: &Rto<Object>,
     macroexpand: &Rto<Function>,
+    lexical: bool,
     env: &mut Rt<Env>,
     cx: &'ob mut Context,
 ) -> Result<Object<'ob>, anyhow::Error> {
@@ -111,7 +264,7 @@ fn eager_expand<'ob>(
             root!(val, NIL, cx);
             rooted_iter!(forms, top.cdr(), cx);
             while let Some(form) = forms.next()? {
-                let result = eager_expand(form, macroexpand, env, cx)?;
+                let result = eager_expand(form, macroexpand, lexical, env, cx)?;
                 val.set(result);
             }
             return Ok(val.bind(cx));
@@ -119,7 +272,7 @@ fn eager_expand<'ob>(
     }
     let result = call!(macroexpand, obj, TRUE; name, env, cx)?;
     root!(result, cx);
-    interpreter::eval(result, None, env, cx)
+    eval_form(result, lexical, env, cx)
 }
 
 fn file_in_path(file: &str, path: &str) -> Option<PathBuf> {
@@ -153,7 +306,7 @@ fn find_file_in_load_path(file: &str, cx: &Context, env: &Rt<Env>) -> Result<Pat
     final_file.ok_or_else(|| anyhow!("Unable to find file `{file}' in load-path"))
 }
 
-#[defun]
+#[defun(io)]
 pub(crate) fn load(
     file: &Rto<Gc<&LispString>>,
     noerror: Option<()>,
@@ -164,6 +317,12 @@ pub(crate) fn load(
     let noerror = noerror.is_some();
     let nomessage = nomessage.is_some();
     let file: &str = file.untag(cx);
+    if let Some(handler) = crate::fileio::file_handler(file, sym::LOAD, env, cx)? {
+        root!(handler, cx);
+        let operation = Object::from(sym::LOAD);
+        let file = cx.add(file);
+        return Ok(!call!(handler, operation, file, noerror, nomessage; env, cx)?.is_nil());
+    }
     let final_file = if Path::new(file).exists() {
         PathBuf::from(file)
     } else {
@@ -189,10 +348,18 @@ pub(crate) fn load(
         None => NIL,
     };
     root!(prev_load_file, cx);
-    let result = match fs::read_to_string(&final_file)
+    let coding = Coding::from_variable(sym::CODING_SYSTEM_FOR_READ, env, cx)?;
+    let result = match fs::read(&final_file)
         .with_context(|| format!("Couldn't open file {:?}", final_file.as_os_str()))
     {
-        Ok(content) => load_internal(&content, cx, env),
+        Ok(content) => {
+            let content = coding.decode(&content);
+            let lexical = if file_lexical_binding(&content) { TRUE } else { NIL };
+            env.varbind(sym::LEXICAL_BINDING, lexical, cx);
+            let result = load_internal(&content, cx, env);
+            env.unbind(1, cx);
+            result
+        }
         Err(e) => match noerror {
             true => Ok(false),
             false => Err(e),
@@ -203,6 +370,14 @@ pub(crate) fn load(
         println!("Loading {filename} Done");
     }
     env.vars.insert(sym::LOAD_FILE_NAME, &*prev_load_file);
+    // Run the `eval-after-load' functions waiting for this file
+    if matches!(result, Ok(true)) {
+        if let Some(func) = sym::DO_AFTER_LOAD_EVALUATION.func(cx) {
+            root!(func, cx);
+            let file = cx.add(final_file.to_string_lossy().to_string());
+            call!(func, file; env, cx)?;
+        }
+    }
     result
 }
 
@@ -223,7 +398,7 @@ pub(crate) fn intern_soft(string: Object, obarray: Option<()>) -> Result<Symbol>
             }
         }
         ObjectType::String(string) => {
-            let map = crate::core::env::interned_symbols().lock().unwrap();
+            let map = crate::core::env::interned_symbols();
             match map.get(string) {
                 Some(sym) => Ok(unsafe { sym.with_lifetime() }),
                 None => Ok(sym::NIL),
@@ -234,6 +409,7 @@ pub(crate) fn intern_soft(string: Object, obarray: Option<()>) -> Result<Symbol>
 }
 
 defsym!(INTERNAL_MACROEXPAND_FOR_LOAD);
+defsym!(DO_AFTER_LOAD_EVALUATION);
 defvar!(LEXICAL_BINDING, true);
 defvar!(CURRENT_LOAD_LIST);
 defvar!(LOAD_HISTORY);
@@ -264,4 +440,48 @@ mod test {
         let val = interpreter::eval(obj, None, env, cx).unwrap();
         assert_eq!(val, 4.5);
     }
+
+    fn eval_str(source: &str, env: &mut Rt<Env>, cx: &mut Context) -> bool {
+        let obj = reader::read(source, cx).unwrap().0;
+        root!(obj, cx);
+        interpreter::eval(obj, None, env, cx).unwrap() != NIL
+    }
+
+    #[test]
+    fn test_read() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        assert!(eval_str("(equal (read-from-string \"é (a b)\" 1) '((a b) . 7))", env, cx));
+        assert!(eval_str("(equal (read-from-string \"(a) b\") '((a) . 3))", env, cx));
+        assert!(eval_str("(equal (eval (read \"(+ 1 2)\")) 3)", env, cx));
+        let stream = "(let* ((chars '(?f ?o ?o 32 ?b ?a ?r))
+                             (stream (lambda (&optional c)
+                                       (if c
+                                           (setq chars (cons c chars))
+                                         (prog1 (car chars) (setq chars (cdr chars)))))))
+                        (and (eq (read stream) 'foo) (equal chars '(32 ?b ?a ?r))
+                             (eq (read stream) 'bar) (null chars)))";
+        assert!(eval_str(stream, env, cx));
+
+        let buffer = crate::buffer::get_buffer_create(cx.add("test_read"), Some(NIL), cx).unwrap();
+        crate::buffer::set_buffer(buffer, env, cx).unwrap();
+        env.current_buffer.as_mut().unwrap().insert(cx.add("(a) b")).unwrap();
+        env.current_buffer.as_mut().unwrap().text.set_cursor(0);
+        root!(buffer, cx);
+        let obj = rebind!(read(Some(buffer), env, cx).unwrap());
+        assert_eq!(obj, reader::read("(a)", cx).unwrap().0);
+        assert_eq!(env.current_buffer.as_ref().unwrap().text.cursor().chars(), 3);
+    }
+
+    #[test]
+    fn test_file_lexical_binding() {
+        assert!(file_lexical_binding(";;; foo.el --- Foo -*- lexical-binding: t -*-\n"));
+        assert!(file_lexical_binding("#!/bin/rune\n;; -*- mode: lisp; lexical-binding:t; -*-"));
+        assert!(!file_lexical_binding(";;; foo.el -*- lexical-binding: nil -*-"));
+        assert!(!file_lexical_binding(";;; foo.el -*- mode: emacs-lisp -*-"));
+        assert!(!file_lexical_binding("(setq foo 1)\n;; -*- lexical-binding: t -*-"));
+        assert!(!file_lexical_binding(""));
+    }
 }
@@ -49,3 +49,24 @@ macro_rules! defvar {
     ($sym:ident, $value:expr) => {};
     ($sym:ident, $name:literal, $value:expr) => {};
 }
+
+/// Emit a `tracing` event. Without the `tracing` feature this expands to
+/// nothing, so the arguments are not evaluated.
+macro_rules! trace_event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::trace!($($arg)*);
+    };
+}
+
+/// Enter a `tracing` span that lasts until the returned guard is dropped.
+/// Without the `tracing` feature the guard is empty and the arguments are not
+/// evaluated.
+macro_rules! trace_span {
+    ($($arg:tt)*) => {
+        crate::debug::SpanGuard {
+            #[cfg(feature = "tracing")]
+            _span: tracing::trace_span!($($arg)*).entered(),
+        }
+    };
+}
@@ -0,0 +1,883 @@
+//! Loading dynamic modules written against `emacs-module.h`.
+//!
+//! A module is given an `emacs_env`, a table of function pointers that it
+//! uses for everything it does with lisp objects. The layout of the table has
+//! to match the C header exactly, up to the newest version we support (28).
+//!
+//! Modules refer to objects through opaque `emacs_value` handles. A handle is
+//! an index into a rooted vector that lives as long as the environment, so the
+//! objects stay alive and can be moved by the garbage collector without the
+//! module noticing. Global references are stored in the [`Env`] in the same
+//! way. The low bit of a handle tells the two apart.
+use crate::core::{
+    env::{sym, CallFrame, Env},
+    gc::{Context, Rt, Rto, Slot},
+    object::{Function, Gc, LispString, Object, ObjectType, NIL, TRUE},
+};
+use crate::eval::{ErrorType, EvalError};
+use crate::timefns::LispTime;
+use anyhow::{anyhow, bail, Result};
+use rune_core::macros::{list, root};
+use rune_macros::defun;
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::ptr::{self, null_mut};
+use std::sync::{Mutex, OnceLock};
+
+type EmacsValue = *mut c_void;
+type ModuleFn =
+    unsafe extern "C" fn(*mut EmacsEnv, isize, *mut EmacsValue, *mut c_void) -> EmacsValue;
+type Finalizer = Option<unsafe extern "C" fn(*mut c_void)>;
+
+const EXIT_RETURN: c_int = 0;
+const EXIT_SIGNAL: c_int = 1;
+const EXIT_THROW: c_int = 2;
+
+const PROCESS_INPUT_CONTINUE: c_int = 0;
+
+/// `max_arity` of a function that takes `&rest` arguments
+const VARIADIC: isize = -2;
+
+#[repr(C)]
+struct EmacsRuntime {
+    size: isize,
+    private_members: *mut c_void,
+    get_environment: extern "C" fn(*mut EmacsRuntime) -> *mut EmacsEnv,
+}
+
+#[repr(C)]
+#[rustfmt::skip]
+struct EmacsEnv {
+    size: isize,
+    private_members: *mut c_void,
+    // Version 25
+    make_global_ref: extern "C" fn(*mut EmacsEnv, EmacsValue) -> EmacsValue,
+    free_global_ref: extern "C" fn(*mut EmacsEnv, EmacsValue),
+    non_local_exit_check: extern "C" fn(*mut EmacsEnv) -> c_int,
+    non_local_exit_clear: extern "C" fn(*mut EmacsEnv),
+    non_local_exit_get: extern "C" fn(*mut EmacsEnv, *mut EmacsValue, *mut EmacsValue) -> c_int,
+    non_local_exit_signal: extern "C" fn(*mut EmacsEnv, EmacsValue, EmacsValue),
+    non_local_exit_throw: extern "C" fn(*mut EmacsEnv, EmacsValue, EmacsValue),
+    make_function: extern "C" fn(*mut EmacsEnv, isize, isize, Option<ModuleFn>, *const c_char, *mut c_void) -> EmacsValue,
+    funcall: extern "C" fn(*mut EmacsEnv, EmacsValue, isize, *mut EmacsValue) -> EmacsValue,
+    intern: extern "C" fn(*mut EmacsEnv, *const c_char) -> EmacsValue,
+    type_of: extern "C" fn(*mut EmacsEnv, EmacsValue) -> EmacsValue,
+    is_not_nil: extern "C" fn(*mut EmacsEnv, EmacsValue) -> bool,
+    eq: extern "C" fn(*mut EmacsEnv, EmacsValue, EmacsValue) -> bool,
+    extract_integer: extern "C" fn(*mut EmacsEnv, EmacsValue) -> i64,
+    make_integer: extern "C" fn(*mut EmacsEnv, i64) -> EmacsValue,
+    extract_float: extern "C" fn(*mut EmacsEnv, EmacsValue) -> f64,
+    make_float: extern "C" fn(*mut EmacsEnv, f64) -> EmacsValue,
+    copy_string_contents: extern "C" fn(*mut EmacsEnv, EmacsValue, *mut c_char, *mut isize) -> bool,
+    make_string: extern "C" fn(*mut EmacsEnv, *const c_char, isize) -> EmacsValue,
+    make_user_ptr: extern "C" fn(*mut EmacsEnv, Finalizer, *mut c_void) -> EmacsValue,
+    get_user_ptr: extern "C" fn(*mut EmacsEnv, EmacsValue) -> *mut c_void,
+    set_user_ptr: extern "C" fn(*mut EmacsEnv, EmacsValue, *mut c_void),
+    get_user_finalizer: extern "C" fn(*mut EmacsEnv, EmacsValue) -> Finalizer,
+    set_user_finalizer: extern "C" fn(*mut EmacsEnv, EmacsValue, Finalizer),
+    vec_get: extern "C" fn(*mut EmacsEnv, EmacsValue, isize) -> EmacsValue,
+    vec_set: extern "C" fn(*mut EmacsEnv, EmacsValue, isize, EmacsValue),
+    vec_size: extern "C" fn(*mut EmacsEnv, EmacsValue) -> isize,
+    // Version 26
+    should_quit: extern "C" fn(*mut EmacsEnv) -> bool,
+    // Version 27
+    process_input: extern "C" fn(*mut EmacsEnv) -> c_int,
+    extract_time: extern "C" fn(*mut EmacsEnv, EmacsValue) -> libc::timespec,
+    make_time: extern "C" fn(*mut EmacsEnv, libc::timespec) -> EmacsValue,
+    extract_big_integer: extern "C" fn(*mut EmacsEnv, EmacsValue, *mut c_int, *mut isize, *mut usize) -> bool,
+    make_big_integer: extern "C" fn(*mut EmacsEnv, c_int, isize, *const usize) -> EmacsValue,
+    // Version 28
+    get_function_finalizer: extern "C" fn(*mut EmacsEnv, EmacsValue) -> Finalizer,
+    set_function_finalizer: extern "C" fn(*mut EmacsEnv, EmacsValue, Finalizer),
+    open_channel: extern "C" fn(*mut EmacsEnv, EmacsValue) -> c_int,
+    make_interactive: extern "C" fn(*mut EmacsEnv, EmacsValue, EmacsValue),
+    make_unibyte_string: extern "C" fn(*mut EmacsEnv, *const c_char, isize) -> EmacsValue,
+}
+
+/// The data behind `private_members` of an environment. The pointers are only
+/// valid for the duration of [`with_module_env`].
+struct State {
+    env: *mut Rt<Env<'static>>,
+    cx: *mut Context<'static>,
+    values: *mut Rt<Vec<Slot<Object<'static>>>>,
+    exit: c_int,
+    exit_symbol: EmacsValue,
+    exit_data: EmacsValue,
+}
+
+struct Parts<'a> {
+    env: &'a mut Rt<Env<'static>>,
+    cx: &'a mut Context<'static>,
+    values: &'a mut Rt<Vec<Slot<Object<'static>>>>,
+}
+
+fn push(values: &mut Rt<Vec<Slot<Object>>>, obj: Object) -> EmacsValue {
+    values.push(obj);
+    (values.len() << 1) as EmacsValue
+}
+
+fn lookup<'ob>(
+    values: &Rt<Vec<Slot<Object>>>,
+    env: &Rt<Env>,
+    value: EmacsValue,
+    cx: &'ob Context,
+) -> Object<'ob> {
+    let handle = value as usize;
+    let idx = (handle >> 1).wrapping_sub(1);
+    let slot = if handle & 1 == 0 { values.get(idx) } else { env.module_refs.get(idx) };
+    slot.map_or(NIL, |x| x.bind(cx))
+}
+
+/// Run `f` with the state of `env`. If a non-local exit is pending nothing is
+/// done, and an error from `f` becomes a non-local exit. Either way `default`
+/// is returned to the module.
+fn with_state<T>(env: *mut EmacsEnv, default: T, f: impl FnOnce(&mut Parts) -> Result<T>) -> T {
+    // SAFETY: modules can only get an environment from `with_module_env`, and
+    // are not allowed to use it after the call that provided it returns.
+    let state = unsafe { &mut *(*env).private_members.cast::<State>() };
+    if state.exit != EXIT_RETURN {
+        return default;
+    }
+    let mut parts =
+        unsafe { Parts { env: &mut *state.env, cx: &mut *state.cx, values: &mut *state.values } };
+    match f(&mut parts) {
+        Ok(x) => x,
+        Err(e) => {
+            set_exit(state, &mut parts, e);
+            default
+        }
+    }
+}
+
+fn set_exit(state: &mut State, parts: &mut Parts, error: anyhow::Error) {
+    let cx = &*parts.cx;
+    let error = EvalError::from(error);
+    let condition = match &error.error {
+        ErrorType::Throw(id) => parts.env.get_exception(*id).map(|(tag, data)| {
+            (EXIT_THROW, tag.bind(cx), data.bind(cx))
+        }),
+        _ => error.condition(parts.env, cx).map(|(symbol, data)| (EXIT_SIGNAL, symbol, data)),
+    };
+    let (exit, tag, data) = condition.unwrap_or_else(|| {
+        (EXIT_SIGNAL, sym::ERROR.into(), list!["Non-local exit data was lost"; cx])
+    });
+    state.exit = exit;
+    state.exit_symbol = push(parts.values, tag);
+    state.exit_data = push(parts.values, data);
+}
+
+fn wrong_type<T>(predicate: Object, value: Object, env: &mut Rt<Env>, cx: &Context) -> Result<T> {
+    let data = list![predicate, value; cx];
+    Err(EvalError::signal(sym::WRONG_TYPE_ARGUMENT.into(), data, env).into())
+}
+
+/// Create an environment, and pass it to `f` along with handles for the
+/// elements of `args`. The object `f` returns a handle to is the result.
+fn with_module_env<'ob>(
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+    args: Option<&Rto<Object>>,
+    f: impl FnOnce(*mut EmacsEnv, &mut [EmacsValue]) -> EmacsValue,
+) -> Result<Object<'ob>> {
+    root!(values, new(Vec<Slot<Object>>), cx);
+    let mut handles = Vec::new();
+    if let Some(args) = args {
+        for arg in args.bind(cx).as_list()? {
+            handles.push(push(values, arg?));
+        }
+    }
+    let mut state = State {
+        env: ptr::from_mut(env).cast(),
+        cx: ptr::from_mut(cx).cast(),
+        values: ptr::from_mut(values).cast(),
+        exit: EXIT_RETURN,
+        exit_symbol: null_mut(),
+        exit_data: null_mut(),
+    };
+    let mut raw = new_env(&mut state);
+    let result = f(&mut raw, &mut handles);
+    let tag = lookup(values, env, state.exit_symbol, cx);
+    let data = lookup(values, env, state.exit_data, cx);
+    match state.exit {
+        EXIT_SIGNAL => Err(EvalError::signal(tag, data, env).into()),
+        EXIT_THROW => Err(EvalError::throw(tag, data, env).into()),
+        _ => {
+            let cx: &'ob Context = cx;
+            Ok(lookup(values, env, result, cx))
+        }
+    }
+}
+
+/// A function created by a module with `make_function`.
+struct ModuleFunction {
+    min_arity: isize,
+    max_arity: isize,
+    func: ModuleFn,
+    data: *mut c_void,
+}
+
+// SAFETY: the data pointer is owned by the module, and only used on the thread
+// that calls the function.
+unsafe impl Send for ModuleFunction {}
+
+static MODULE_FUNCTIONS: OnceLock<Mutex<Vec<ModuleFunction>>> = OnceLock::new();
+
+fn module_functions() -> &'static Mutex<Vec<ModuleFunction>> {
+    MODULE_FUNCTIONS.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+/// Call the module function with `id`. Functions created by modules are
+/// closures that pass their arguments here.
+#[defun]
+fn internal__module_call<'ob>(
+    id: usize,
+    args: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let (func, data, min, max) = {
+        let functions = module_functions().lock().unwrap();
+        let Some(f) = functions.get(id) else { bail!("No module function with id {id}") };
+        (f.func, f.data, f.min_arity, f.max_arity)
+    };
+    let count = args.bind(cx).as_list()?.count() as isize;
+    if count < min || (max != VARIADIC && count > max) {
+        let data = list![sym::INTERNAL__MODULE_CALL, count; cx];
+        return Err(EvalError::signal(sym::WRONG_NUMBER_OF_ARGUMENTS.into(), data, env).into());
+    }
+    with_module_env(env, cx, Some(args), |raw, handles| {
+        let len = handles.len() as isize;
+        // SAFETY: arity has been checked against what the module asked for
+        unsafe { func(raw, len, handles.as_mut_ptr(), data) }
+    })
+}
+
+defsym!(WRONG_TYPE_ARGUMENT);
+defsym!(WRONG_NUMBER_OF_ARGUMENTS);
+defsym!(ARGS_OUT_OF_RANGE);
+defsym!(OVERFLOW_ERROR);
+defsym!(MODULE_NOT_GPL_COMPATIBLE);
+
+/// Load the dynamic module in `file` and run its `emacs_module_init`.
+#[defun(io)]
+fn module_load(file: &Rto<Gc<&LispString>>, env: &mut Rt<Env>, cx: &mut Context) -> Result<bool> {
+    let path = file.untag(cx).to_string();
+    let handle = open_library(&path)?;
+    if library_symbol(handle, c"plugin_is_GPL_compatible").is_null() {
+        let data = list![cx.add(path); cx];
+        return Err(EvalError::signal(sym::MODULE_NOT_GPL_COMPATIBLE.into(), data, env).into());
+    }
+    let init = library_symbol(handle, c"emacs_module_init");
+    if init.is_null() {
+        bail!("Module {path} does not define emacs_module_init");
+    }
+    // SAFETY: this is the signature given by `emacs-module.h`
+    let init: extern "C" fn(*mut EmacsRuntime) -> c_int = unsafe { std::mem::transmute(init) };
+    let mut status = 0;
+    with_module_env(env, cx, None, |raw, _| {
+        let mut runtime = EmacsRuntime {
+            size: size_of::<EmacsRuntime>() as isize,
+            private_members: raw.cast(),
+            get_environment,
+        };
+        status = init(&mut runtime);
+        null_mut()
+    })?;
+    if status != 0 {
+        bail!("Module initialization failed for {path} with status {status}");
+    }
+    Ok(true)
+}
+
+/// Open a shared library. Modules are never unloaded.
+#[cfg(unix)]
+fn open_library(path: &str) -> Result<*mut c_void> {
+    let c_path = std::ffi::CString::new(path)?;
+    // SAFETY: we pass a valid C string
+    let handle = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_LAZY) };
+    if handle.is_null() {
+        // SAFETY: dlerror returns null or a valid C string
+        let msg = unsafe { libc::dlerror() };
+        if msg.is_null() {
+            bail!("Failed to load module {path}");
+        }
+        let msg = unsafe { CStr::from_ptr(msg) }.to_string_lossy();
+        bail!("Failed to load module {path}: {msg}");
+    }
+    Ok(handle)
+}
+
+#[cfg(not(unix))]
+fn open_library(path: &str) -> Result<*mut c_void> {
+    bail!("Dynamic modules are not supported on this platform: {path}")
+}
+
+#[cfg(unix)]
+fn library_symbol(handle: *mut c_void, name: &CStr) -> *mut c_void {
+    // SAFETY: handle came from dlopen
+    unsafe { libc::dlsym(handle, name.as_ptr()) }
+}
+
+#[cfg(not(unix))]
+fn library_symbol(_handle: *mut c_void, _name: &CStr) -> *mut c_void {
+    null_mut()
+}
+
+extern "C" fn get_environment(runtime: *mut EmacsRuntime) -> *mut EmacsEnv {
+    // SAFETY: the runtime is only valid during `emacs_module_init`
+    unsafe { (*runtime).private_members.cast() }
+}
+
+fn new_env(state: &mut State) -> EmacsEnv {
+    EmacsEnv {
+        size: size_of::<EmacsEnv>() as isize,
+        private_members: ptr::from_mut(state).cast(),
+        make_global_ref,
+        free_global_ref,
+        non_local_exit_check,
+        non_local_exit_clear,
+        non_local_exit_get,
+        non_local_exit_signal,
+        non_local_exit_throw,
+        make_function,
+        funcall,
+        intern,
+        type_of,
+        is_not_nil,
+        eq,
+        extract_integer,
+        make_integer,
+        extract_float,
+        make_float,
+        copy_string_contents,
+        make_string,
+        make_user_ptr,
+        get_user_ptr,
+        set_user_ptr,
+        get_user_finalizer,
+        set_user_finalizer,
+        vec_get,
+        vec_set,
+        vec_size,
+        should_quit,
+        process_input,
+        extract_time,
+        make_time,
+        extract_big_integer,
+        make_big_integer,
+        get_function_finalizer,
+        set_function_finalizer,
+        open_channel,
+        make_interactive,
+        make_unibyte_string,
+    }
+}
+
+fn state(env: *mut EmacsEnv) -> &'static mut State {
+    // SAFETY: see `with_state`
+    unsafe { &mut *(*env).private_members.cast::<State>() }
+}
+
+extern "C" fn make_global_ref(env: *mut EmacsEnv, value: EmacsValue) -> EmacsValue {
+    with_state(env, null_mut(), |p| {
+        let obj = lookup(p.values, p.env, value, p.cx);
+        let idx = match p.env.free_module_refs.pop() {
+            Some(idx) => {
+                p.env.module_refs[idx].set(obj);
+                p.env.module_refs_live[idx] = true;
+                idx
+            }
+            None => {
+                p.env.module_refs.push(obj);
+                p.env.module_refs_live.push(true);
+                p.env.module_refs.len() - 1
+            }
+        };
+        Ok((((idx + 1) << 1) | 1) as EmacsValue)
+    })
+}
+
+extern "C" fn free_global_ref(env: *mut EmacsEnv, value: EmacsValue) {
+    with_state(env, (), |p| {
+        let idx = ((value as usize) >> 1).wrapping_sub(1);
+        if value as usize & 1 == 1 && p.env.module_refs_live.get(idx) == Some(&true) {
+            p.env.module_refs[idx].set(NIL);
+            p.env.module_refs_live[idx] = false;
+            p.env.free_module_refs.push(idx);
+        }
+        Ok(())
+    });
+}
+
+extern "C" fn non_local_exit_check(env: *mut EmacsEnv) -> c_int {
+    state(env).exit
+}
+
+extern "C" fn non_local_exit_clear(env: *mut EmacsEnv) {
+    let state = state(env);
+    state.exit = EXIT_RETURN;
+    state.exit_symbol = null_mut();
+    state.exit_data = null_mut();
+}
+
+extern "C" fn non_local_exit_get(
+    env: *mut EmacsEnv,
+    symbol: *mut EmacsValue,
+    data: *mut EmacsValue,
+) -> c_int {
+    let state = state(env);
+    if state.exit != EXIT_RETURN {
+        // SAFETY: the module passes pointers it owns
+        unsafe {
+            *symbol = state.exit_symbol;
+            *data = state.exit_data;
+        }
+    }
+    state.exit
+}
+
+fn set_pending(env: *mut EmacsEnv, exit: c_int, tag: EmacsValue, data: EmacsValue) {
+    let state = state(env);
+    if state.exit == EXIT_RETURN {
+        state.exit = exit;
+        state.exit_symbol = tag;
+        state.exit_data = data;
+    }
+}
+
+extern "C" fn non_local_exit_signal(env: *mut EmacsEnv, symbol: EmacsValue, data: EmacsValue) {
+    set_pending(env, EXIT_SIGNAL, symbol, data);
+}
+
+extern "C" fn non_local_exit_throw(env: *mut EmacsEnv, tag: EmacsValue, value: EmacsValue) {
+    set_pending(env, EXIT_THROW, tag, value);
+}
+
+extern "C" fn make_function(
+    env: *mut EmacsEnv,
+    min_arity: isize,
+    max_arity: isize,
+    function: Option<ModuleFn>,
+    _documentation: *const c_char,
+    data: *mut c_void,
+) -> EmacsValue {
+    with_state(env, null_mut(), |p| {
+        let Some(func) = function else { bail!("Module function is null") };
+        if min_arity < 0 || (max_arity != VARIADIC && max_arity < min_arity) {
+            bail!("Invalid module function arity: {min_arity} {max_arity}");
+        }
+        let id = {
+            let mut functions = module_functions().lock().unwrap();
+            functions.push(ModuleFunction { min_arity, max_arity, func, data });
+            functions.len() - 1
+        };
+        let cx = &*p.cx;
+        let args = crate::core::env::intern("args", cx);
+        // (closure (t) (&rest args) (internal--module-call ID args))
+        let closure_env = list![TRUE; cx];
+        let arglist = list![sym::AND_REST, args; cx];
+        let body = list![sym::INTERNAL__MODULE_CALL, id, args; cx];
+        let closure = list![sym::CLOSURE, closure_env, arglist, body; cx];
+        Ok(push(p.values, closure))
+    })
+}
+
+extern "C" fn funcall(
+    env: *mut EmacsEnv,
+    function: EmacsValue,
+    nargs: isize,
+    args: *mut EmacsValue,
+) -> EmacsValue {
+    with_state(env, null_mut(), |p| {
+        let Parts { env, cx, values } = p;
+        let func: Function = lookup(values, env, function, cx).try_into()?;
+        // SAFETY: the module passes `nargs` valid handles
+        let handles = unsafe { std::slice::from_raw_parts(args, nargs.max(0) as usize) };
+        let args: Vec<Object> = handles.iter().map(|x| lookup(values, env, *x, cx)).collect();
+        root!(func, cx);
+        let frame = &mut CallFrame::new(env);
+        frame.push_arg_slice(&args);
+        let result = func.call(frame, None, cx).map_err(anyhow::Error::from)?;
+        Ok(push(values, result))
+    })
+}
+
+extern "C" fn intern(env: *mut EmacsEnv, name: *const c_char) -> EmacsValue {
+    with_state(env, null_mut(), |p| {
+        // SAFETY: the module passes a null terminated string
+        let name = unsafe { CStr::from_ptr(name) }.to_str()?;
+        let symbol = crate::core::env::intern(name, p.cx);
+        Ok(push(p.values, symbol.into()))
+    })
+}
+
+extern "C" fn type_of(env: *mut EmacsEnv, value: EmacsValue) -> EmacsValue {
+    with_state(env, null_mut(), |p| {
+        let obj = lookup(p.values, p.env, value, p.cx);
+        Ok(push(p.values, crate::data::type_of(obj)))
+    })
+}
+
+extern "C" fn is_not_nil(env: *mut EmacsEnv, value: EmacsValue) -> bool {
+    with_state(env, false, |p| Ok(!lookup(p.values, p.env, value, p.cx).is_nil()))
+}
+
+extern "C" fn eq(env: *mut EmacsEnv, a: EmacsValue, b: EmacsValue) -> bool {
+    with_state(env, false, |p| {
+        Ok(lookup(p.values, p.env, a, p.cx) == lookup(p.values, p.env, b, p.cx))
+    })
+}
+
+extern "C" fn extract_integer(env: *mut EmacsEnv, value: EmacsValue) -> i64 {
+    with_state(env, 0, |p| match lookup(p.values, p.env, value, p.cx).untag() {
+        ObjectType::Int(i) => Ok(i),
+        other => wrong_type(sym::INTEGERP.into(), other.into(), p.env, p.cx),
+    })
+}
+
+extern "C" fn make_integer(env: *mut EmacsEnv, n: i64) -> EmacsValue {
+    with_state(env, null_mut(), |p| {
+        let obj: Object = n.into();
+        if !matches!(obj.untag(), ObjectType::Int(i) if i == n) {
+            let data = list![p.cx.add(n as f64); p.cx];
+            return Err(EvalError::signal(sym::OVERFLOW_ERROR.into(), data, p.env).into());
+        }
+        Ok(push(p.values, obj))
+    })
+}
+
+extern "C" fn extract_float(env: *mut EmacsEnv, value: EmacsValue) -> f64 {
+    with_state(env, 0.0, |p| match lookup(p.values, p.env, value, p.cx).untag() {
+        ObjectType::Float(f) => Ok(**f),
+        other => wrong_type(sym::FLOATP.into(), other.into(), p.env, p.cx),
+    })
+}
+
+extern "C" fn make_float(env: *mut EmacsEnv, d: f64) -> EmacsValue {
+    with_state(env, null_mut(), |p| {
+        let obj = p.cx.add(d);
+        Ok(push(p.values, obj))
+    })
+}
+
+extern "C" fn copy_string_contents(
+    env: *mut EmacsEnv,
+    value: EmacsValue,
+    buffer: *mut c_char,
+    size: *mut isize,
+) -> bool {
+    with_state(env, false, |p| {
+        let obj = lookup(p.values, p.env, value, p.cx);
+        let bytes: &[u8] = match obj.untag() {
+            ObjectType::String(s) => s.as_bytes(),
+            ObjectType::ByteString(s) => s,
+            other => return wrong_type(sym::STRINGP.into(), other.into(), p.env, p.cx),
+        };
+        let needed = bytes.len() as isize + 1;
+        // SAFETY: the module passes a valid size pointer, and a buffer of at
+        // least that size if it is not null
+        unsafe {
+            if buffer.is_null() {
+                *size = needed;
+                return Ok(true);
+            }
+            if *size < needed {
+                *size = needed;
+                let data = list![obj, needed; p.cx];
+                return Err(EvalError::signal(sym::ARGS_OUT_OF_RANGE.into(), data, p.env).into());
+            }
+            ptr::copy_nonoverlapping(bytes.as_ptr(), buffer.cast::<u8>(), bytes.len());
+            *buffer.add(bytes.len()) = 0;
+            *size = needed;
+        }
+        Ok(true)
+    })
+}
+
+fn module_bytes<'a>(string: *const c_char, len: isize) -> &'a [u8] {
+    // SAFETY: the module passes a buffer of at least `len` bytes
+    unsafe { std::slice::from_raw_parts(string.cast::<u8>(), len.max(0) as usize) }
+}
+
+extern "C" fn make_string(env: *mut EmacsEnv, string: *const c_char, len: isize) -> EmacsValue {
+    with_state(env, null_mut(), |p| {
+        let string = std::str::from_utf8(module_bytes(string, len))?;
+        let obj = p.cx.add(string);
+        Ok(push(p.values, obj))
+    })
+}
+
+extern "C" fn make_unibyte_string(
+    env: *mut EmacsEnv,
+    string: *const c_char,
+    len: isize,
+) -> EmacsValue {
+    with_state(env, null_mut(), |p| {
+        let obj = p.cx.add(module_bytes(string, len).to_vec());
+        Ok(push(p.values, obj))
+    })
+}
+
+fn unsupported<T>(feature: &str) -> Result<T> {
+    Err(anyhow!("Dynamic modules can't use {feature} yet"))
+}
+
+extern "C" fn make_user_ptr(env: *mut EmacsEnv, _fin: Finalizer, _ptr: *mut c_void) -> EmacsValue {
+    with_state(env, null_mut(), |_| unsupported("user pointers"))
+}
+
+extern "C" fn get_user_ptr(env: *mut EmacsEnv, _value: EmacsValue) -> *mut c_void {
+    with_state(env, null_mut(), |_| unsupported("user pointers"))
+}
+
+extern "C" fn set_user_ptr(env: *mut EmacsEnv, _value: EmacsValue, _ptr: *mut c_void) {
+    with_state(env, (), |_| unsupported("user pointers"));
+}
+
+extern "C" fn get_user_finalizer(env: *mut EmacsEnv, _value: EmacsValue) -> Finalizer {
+    with_state(env, None, |_| unsupported("user pointers"))
+}
+
+extern "C" fn set_user_finalizer(env: *mut EmacsEnv, _value: EmacsValue, _fin: Finalizer) {
+    with_state(env, (), |_| unsupported("user pointers"));
+}
+
+fn vector_index(index: isize, len: usize) -> Option<usize> {
+    usize::try_from(index).ok().filter(|&i| i < len)
+}
+
+extern "C" fn vec_get(env: *mut EmacsEnv, vector: EmacsValue, index: isize) -> EmacsValue {
+    with_state(env, null_mut(), |p| {
+        let obj = lookup(p.values, p.env, vector, p.cx);
+        let ObjectType::Vec(vec) = obj.untag() else {
+            return wrong_type(sym::VECTORP.into(), obj, p.env, p.cx);
+        };
+        let Some(i) = vector_index(index, vec.len()) else {
+            let data = list![obj, index as i64; p.cx];
+            return Err(EvalError::signal(sym::ARGS_OUT_OF_RANGE.into(), data, p.env).into());
+        };
+        let elem = vec[i].get();
+        Ok(push(p.values, elem))
+    })
+}
+
+extern "C" fn vec_set(env: *mut EmacsEnv, vector: EmacsValue, index: isize, value: EmacsValue) {
+    with_state(env, (), |p| {
+        let obj = lookup(p.values, p.env, vector, p.cx);
+        let ObjectType::Vec(vec) = obj.untag() else {
+            return wrong_type(sym::VECTORP.into(), obj, p.env, p.cx);
+        };
+        let Some(i) = vector_index(index, vec.len()) else {
+            let data = list![obj, index as i64; p.cx];
+            return Err(EvalError::signal(sym::ARGS_OUT_OF_RANGE.into(), data, p.env).into());
+        };
+        vec.try_mut()?[i].set(lookup(p.values, p.env, value, p.cx));
+        Ok(())
+    });
+}
+
+extern "C" fn vec_size(env: *mut EmacsEnv, vector: EmacsValue) -> isize {
+    with_state(env, 0, |p| {
+        let obj = lookup(p.values, p.env, vector, p.cx);
+        match obj.untag() {
+            ObjectType::Vec(vec) => Ok(vec.len() as isize),
+            _ => wrong_type(sym::VECTORP.into(), obj, p.env, p.cx),
+        }
+    })
+}
+
+extern "C" fn should_quit(_env: *mut EmacsEnv) -> bool {
+    false
+}
+
+extern "C" fn process_input(_env: *mut EmacsEnv) -> c_int {
+    PROCESS_INPUT_CONTINUE
+}
+
+extern "C" fn extract_time(env: *mut EmacsEnv, value: EmacsValue) -> libc::timespec {
+    let zero = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    with_state(env, zero, |p| {
+        let time = LispTime::from_obj(Some(lookup(p.values, p.env, value, p.cx)))?;
+        Ok(libc::timespec { tv_sec: time.secs as _, tv_nsec: (time.psecs / 1000) as _ })
+    })
+}
+
+extern "C" fn make_time(env: *mut EmacsEnv, time: libc::timespec) -> EmacsValue {
+    with_state(env, null_mut(), |p| {
+        let time = LispTime::new(time.tv_sec as i64, time.tv_nsec as i64 * 1000);
+        let obj = time.into_obj(p.cx);
+        Ok(push(p.values, obj))
+    })
+}
+
+extern "C" fn extract_big_integer(
+    env: *mut EmacsEnv,
+    value: EmacsValue,
+    sign: *mut c_int,
+    count: *mut isize,
+    magnitude: *mut usize,
+) -> bool {
+    with_state(env, false, |p| {
+        let n = match lookup(p.values, p.env, value, p.cx).untag() {
+            ObjectType::Int(i) => i,
+            other => return wrong_type(sym::INTEGERP.into(), other.into(), p.env, p.cx),
+        };
+        let needed = isize::from(n != 0);
+        // SAFETY: the module passes valid pointers, and room for `*count`
+        // limbs in `magnitude` if it is not null
+        unsafe {
+            if !sign.is_null() {
+                *sign = n.signum() as c_int;
+            }
+            if magnitude.is_null() {
+                *count = needed;
+                return Ok(true);
+            }
+            if *count < needed {
+                *count = needed;
+                let data = list![n; p.cx];
+                return Err(EvalError::signal(sym::ARGS_OUT_OF_RANGE.into(), data, p.env).into());
+            }
+            *count = needed;
+            if n != 0 {
+                *magnitude = n.unsigned_abs() as usize;
+            }
+        }
+        Ok(true)
+    })
+}
+
+extern "C" fn make_big_integer(
+    env: *mut EmacsEnv,
+    sign: c_int,
+    count: isize,
+    magnitude: *const usize,
+) -> EmacsValue {
+    with_state(env, null_mut(), |p| {
+        // SAFETY: the module passes `count` limbs
+        let limbs = unsafe { std::slice::from_raw_parts(magnitude, count.max(0) as usize) };
+        let mut value: u128 = 0;
+        for (i, &limb) in limbs.iter().enumerate() {
+            let shifted = (limb as u128).checked_shl((i * usize::BITS as usize) as u32);
+            match shifted {
+                Some(x) if limb == 0 || x >> (i * usize::BITS as usize) == limb as u128 => {
+                    value |= x;
+                }
+                _ => return unsupported("integers larger than a fixnum"),
+            }
+        }
+        let Ok(value) = i64::try_from(value) else {
+            return unsupported("integers larger than a fixnum");
+        };
+        let n = if sign < 0 { -value } else { value };
+        let obj: Object = n.into();
+        if !matches!(obj.untag(), ObjectType::Int(i) if i == n) {
+            return unsupported("integers larger than a fixnum");
+        }
+        Ok(push(p.values, obj))
+    })
+}
+
+extern "C" fn get_function_finalizer(_env: *mut EmacsEnv, _value: EmacsValue) -> Finalizer {
+    None
+}
+
+extern "C" fn set_function_finalizer(_env: *mut EmacsEnv, _value: EmacsValue, _fin: Finalizer) {
+    // Module functions are never collected, so the finalizer would never run
+}
+
+extern "C" fn open_channel(env: *mut EmacsEnv, _process: EmacsValue) -> c_int {
+    with_state(env, -1, |_| unsupported("process channels"))
+}
+
+extern "C" fn make_interactive(env: *mut EmacsEnv, _function: EmacsValue, _spec: EmacsValue) {
+    with_state(env, (), |_| unsupported("interactive functions"));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+
+    extern "C" fn test_add(
+        env: *mut EmacsEnv,
+        nargs: isize,
+        args: *mut EmacsValue,
+        data: *mut c_void,
+    ) -> EmacsValue {
+        assert_eq!(data as usize, 7);
+        let funcs = unsafe { &*env };
+        let args = unsafe { std::slice::from_raw_parts(args, nargs as usize) };
+        let mut sum = 0;
+        for arg in args {
+            sum += (funcs.extract_integer)(env, *arg);
+        }
+        (funcs.make_integer)(env, sum)
+    }
+
+    #[test]
+    fn test_module_env() {
+        sym::init_symbols();
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        let func = with_module_env(env, cx, None, |raw, _| {
+            let funcs = unsafe { &*raw };
+            let func = (funcs.make_function)(raw, 1, VARIADIC, Some(test_add), null_mut(), 7 as _);
+            let global = (funcs.make_global_ref)(raw, func);
+            let name = (funcs.intern)(raw, c"module-test-add".as_ptr());
+            let fset = (funcs.intern)(raw, c"fset".as_ptr());
+            let mut args = [name, global];
+            (funcs.funcall)(raw, fset, 2, args.as_mut_ptr());
+            assert_eq!((funcs.non_local_exit_check)(raw), EXIT_RETURN);
+            (funcs.free_global_ref)(raw, global);
+
+            // a wrong type argument becomes a pending signal
+            let s = (funcs.make_string)(raw, c"abc".as_ptr(), 3);
+            assert_eq!((funcs.extract_integer)(raw, s), 0);
+            assert_eq!((funcs.non_local_exit_check)(raw), EXIT_SIGNAL);
+            (funcs.non_local_exit_clear)(raw);
+
+            let mut len = 0;
+            assert!((funcs.copy_string_contents)(raw, s, null_mut(), &mut len));
+            assert_eq!(len, 4);
+            name
+        })
+        .unwrap();
+        assert_eq!(func, crate::core::env::intern("module-test-add", cx));
+        assert!(env.module_refs_live.iter().all(|live| !live));
+
+        let form = crate::reader::read("(module-test-add 1 2 3)", cx).unwrap().0;
+        root!(form, cx);
+        let result = crate::interpreter::eval(form, None, env, cx).unwrap();
+        assert_eq!(result, 6);
+    }
+
+    #[test]
+    fn test_global_refs() {
+        sym::init_symbols();
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        with_module_env(env, cx, None, |raw, _| {
+            let funcs = unsafe { &*raw };
+            let nil = (funcs.intern)(raw, c"nil".as_ptr());
+            let nil_ref = (funcs.make_global_ref)(raw, nil);
+            let int_ref = (funcs.make_global_ref)(raw, (funcs.make_integer)(raw, 5));
+            (funcs.free_global_ref)(raw, int_ref);
+            // freeing twice does nothing
+            (funcs.free_global_ref)(raw, int_ref);
+            // a reference to nil is still live after the last one is freed
+            let reused = (funcs.make_global_ref)(raw, (funcs.make_integer)(raw, 6));
+            assert_eq!(reused, int_ref);
+            assert_ne!(reused, nil_ref);
+            assert_eq!((funcs.extract_integer)(raw, reused), 6);
+            assert!(!(funcs.is_not_nil)(raw, nil_ref));
+            (funcs.free_global_ref)(raw, nil_ref);
+            (funcs.free_global_ref)(raw, reused);
+            nil
+        })
+        .unwrap();
+        assert_eq!(env.module_refs.len(), 2);
+        assert!(env.module_refs_live.iter().all(|live| !live));
+    }
+}
@@ -0,0 +1,759 @@
+//! Saving the state of the runtime to a file and restoring it.
+//!
+//! Loading the standard library means reading and macroexpanding a lot of
+//! lisp. Instead the result can be dumped once with `dump-emacs-portable` and
+//! restored at startup with `--dump-file`, which only has to recreate the
+//! objects.
+//!
+//! A dump holds every object reachable from the function cells of interned
+//! symbols, the global variables, the symbol plists and the features. Objects
+//! are written to a table and refer to each other by index, so shared and
+//! circular structure survives the round trip. Builtin functions are stored by
+//! name and looked up again when restoring, which is why a dump can only be
+//! used by the same version of rune that wrote it.
+//!
+//! Some objects can't be dumped. A variable or property whose value is a
+//! buffer is left out, and a buffer inside another value is dumped as nil.
+//! Rust data attached to a record, like a plugin's foreign object, is lost,
+//! so the record is restored without it. Each of these is reported by
+//! [`dump`] instead of failing the whole dump.
+//!
+//! Restoring takes time linear in the size of the dump. The file is read and
+//! every object is created again in the heap, because objects have to live
+//! in a heap that the collector owns. Mapping the file into memory and using
+//! the objects in place would need a heap that can hold objects it didn't
+//! allocate, which this collector doesn't have.
+use crate::core::{
+    cons::Cons,
+    env::{intern, interned_symbols, Env},
+    gc::{Context, Rt},
+    object::{
+        ByteFn, FnArgs, FunctionType, HashTable, IntoObject, Object, ObjectType, RawObj,
+        RecordBuilder, Symbol, NIL,
+    },
+};
+use anyhow::{bail, ensure, Context as _, Result};
+use rune_core::hashmap::HashMap;
+use rune_macros::defun;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"RUNEDUMP";
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Tags for references
+const REF_INT: u8 = 0;
+const REF_OBJ: u8 = 1;
+
+// Tags for objects in the table
+const SYMBOL: u8 = 0;
+const UNINTERNED: u8 = 1;
+const FLOAT: u8 = 2;
+const STRING: u8 = 3;
+const BYTE_STRING: u8 = 4;
+const CONS: u8 = 5;
+const VECTOR: u8 = 6;
+const RECORD: u8 = 7;
+const HASH_TABLE: u8 = 8;
+const BYTE_FN: u8 = 9;
+const SUBR: u8 = 10;
+
+#[derive(Default)]
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn u8(&mut self, x: u8) {
+        self.0.push(x);
+    }
+
+    fn u64(&mut self, x: u64) {
+        self.0.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn len(&mut self, len: usize) {
+        self.u64(len as u64);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.len(bytes.len());
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn str(&mut self, s: &str) {
+        self.bytes(s.as_bytes());
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.data.len());
+        let Some(end) = end else { bail!("Dump file is truncated") };
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn len(&mut self) -> Result<usize> {
+        Ok(usize::try_from(self.u64()?)?)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.len()?;
+        self.take(len)
+    }
+
+    fn str(&mut self) -> Result<&'a str> {
+        Ok(std::str::from_utf8(self.bytes()?)?)
+    }
+
+    fn reference(&mut self) -> Result<Ref> {
+        match self.u8()? {
+            REF_INT => Ok(Ref::Int(self.u64()? as i64)),
+            REF_OBJ => Ok(Ref::Obj(self.len()?)),
+            tag => bail!("Invalid reference tag {tag} in dump file"),
+        }
+    }
+
+    fn references(&mut self) -> Result<Vec<Ref>> {
+        let len = self.len()?;
+        (0..len).map(|_| self.reference()).collect()
+    }
+}
+
+/// Assigns each object an index in the table as it is first referenced.
+struct Dumper<'ob> {
+    ids: HashMap<RawObj, usize>,
+    objects: Vec<Object<'ob>>,
+    /// The root each of `objects` was first found from, as an index in
+    /// `roots`, so that problems with it can be reported by the root.
+    owners: Vec<usize>,
+    /// Descriptions of the roots, like "variable `foo'"
+    roots: Vec<String>,
+    /// The root that is being written
+    current: usize,
+    /// What couldn't be dumped
+    warnings: BTreeSet<String>,
+}
+
+impl<'ob> Dumper<'ob> {
+    /// Start writing the root described by `name`.
+    fn root(&mut self, name: String) {
+        self.current = self.roots.len();
+        self.roots.push(name);
+    }
+
+    fn warn(&mut self, problem: &str) {
+        self.warnings.insert(format!("{} {problem}", self.roots[self.current]));
+    }
+
+    fn reference(&mut self, obj: Object<'ob>, out: &mut Encoder) {
+        let obj = match obj.untag() {
+            ObjectType::Int(i) => {
+                out.u8(REF_INT);
+                out.u64(i as u64);
+                return;
+            }
+            ObjectType::Buffer(_) => {
+                self.warn("contains a buffer, which was dumped as nil");
+                NIL
+            }
+            _ => obj,
+        };
+        let (objects, owners, current) = (&mut self.objects, &mut self.owners, self.current);
+        let id = *self.ids.entry(obj.into_raw()).or_insert_with(|| {
+            objects.push(obj);
+            owners.push(current);
+            objects.len() - 1
+        });
+        out.u8(REF_OBJ);
+        out.len(id);
+    }
+
+    fn references<I>(&mut self, objs: I, out: &mut Encoder)
+    where
+        I: ExactSizeIterator<Item = Object<'ob>>,
+    {
+        out.len(objs.len());
+        for obj in objs {
+            self.reference(obj, out);
+        }
+    }
+
+    fn object(&mut self, obj: Object<'ob>, out: &mut Encoder) {
+        match obj.untag() {
+            ObjectType::Symbol(sym) => {
+                out.u8(if sym.interned() { SYMBOL } else { UNINTERNED });
+                out.str(sym.name());
+            }
+            ObjectType::Float(f) => {
+                out.u8(FLOAT);
+                out.u64(f.to_bits());
+            }
+            ObjectType::String(s) => {
+                out.u8(STRING);
+                out.str(s);
+            }
+            ObjectType::ByteString(s) => {
+                out.u8(BYTE_STRING);
+                out.bytes(s.inner());
+            }
+            ObjectType::Cons(cons) => {
+                out.u8(CONS);
+                self.reference(cons.car(), out);
+                self.reference(cons.cdr(), out);
+            }
+            ObjectType::Vec(vec) => {
+                out.u8(VECTOR);
+                self.references(vec.iter().map(|x| x.get()), out);
+            }
+            ObjectType::Record(record) => {
+                if record.attached().is_some() {
+                    self.warn("contains a record with foreign data, which was dumped without it");
+                }
+                out.u8(RECORD);
+                self.references(record.iter().map(|x| x.get()), out);
+            }
+            ObjectType::HashTable(table) => {
+                out.u8(HASH_TABLE);
+                out.len(table.len());
+                for i in 0..table.len() {
+                    let (key, value) = table.get_index(i).unwrap();
+                    self.reference(key, out);
+                    self.reference(value, out);
+                }
+            }
+            ObjectType::ByteFn(func) => {
+                out.u8(BYTE_FN);
+                out.u64(func.args.into_arg_spec());
+                out.u8(u8::from(func.args.advice));
+                out.len(func.depth);
+                out.bytes(func.codes());
+                self.references(func.consts().iter().copied(), out);
+                out.u8(u8::from(func.doc().is_some()));
+                out.str(func.doc().unwrap_or_default());
+                match func.interactive() {
+                    Some(spec) => {
+                        out.u8(1);
+                        self.reference(spec, out);
+                    }
+                    None => out.u8(0),
+                }
+            }
+            ObjectType::SubrFn(func) => {
+                out.u8(SUBR);
+                out.str(func.name);
+            }
+            ObjectType::Buffer(_) | ObjectType::Int(_) => {
+                unreachable!("buffers and integers are not stored in the table")
+            }
+        }
+    }
+}
+
+/// Write the state of the runtime to `path`. Returns a description of each
+/// thing that couldn't be dumped, see the [module docs](self).
+pub(crate) fn dump(path: &Path, env: &Rt<Env>, cx: &Context) -> Result<Vec<String>> {
+    let mut dumper = Dumper {
+        ids: HashMap::default(),
+        objects: Vec::new(),
+        owners: Vec::new(),
+        roots: Vec::new(),
+        current: 0,
+        warnings: BTreeSet::new(),
+    };
+    let mut roots = Encoder::default();
+    let is_buffer = |obj: Object| matches!(obj.untag(), ObjectType::Buffer(_));
+
+    let symbols = interned_symbols().symbols(cx);
+    let mut functions = Vec::new();
+    let mut specials = Vec::new();
+    for sym in symbols {
+        if let Some(func) = sym.func(cx) {
+            // builtins are defined again when the runtime starts
+            let builtin = matches!(func.untag(), FunctionType::SubrFn(f) if f.name == sym.name());
+            if !builtin {
+                functions.push((sym, func));
+            }
+        }
+        if sym.is_special() {
+            specials.push(sym);
+        }
+    }
+    roots.len(functions.len());
+    for (sym, func) in functions {
+        dumper.root(format!("function `{sym}'"));
+        dumper.reference(sym.into(), &mut roots);
+        dumper.reference(func.into(), &mut roots);
+    }
+    dumper.root("special variables".to_owned());
+    dumper.references(specials.into_iter().map(Object::from), &mut roots);
+
+    let mut vars = Vec::new();
+    for (sym, value) in env.vars.iter() {
+        let (sym, value) = (sym.bind(cx), value.bind(cx));
+        if is_buffer(value) {
+            let warning = format!("variable `{sym}' holds a buffer and was left out");
+            dumper.warnings.insert(warning);
+        } else {
+            vars.push((sym, value));
+        }
+    }
+    roots.len(vars.len());
+    for (sym, value) in vars {
+        dumper.root(format!("variable `{sym}'"));
+        dumper.reference(sym.into(), &mut roots);
+        dumper.reference(value, &mut roots);
+    }
+
+    dumper.root("constants".to_owned());
+    let constants = env.constants.iter().map(|(x, _)| Object::from(x.bind(cx)));
+    dumper.references(constants, &mut roots);
+
+    let props: Vec<_> = env.props.iter().collect();
+    roots.len(props.len());
+    for (sym, plist) in props {
+        let sym = sym.bind(cx);
+        let mut kept = Vec::new();
+        for (prop, value) in plist.iter().map(|x| (x.0.bind(cx), x.1.bind(cx))) {
+            if is_buffer(value) {
+                let warning =
+                    format!("property `{prop}' of `{sym}' holds a buffer and was left out");
+                dumper.warnings.insert(warning);
+            } else {
+                kept.push((prop, value));
+            }
+        }
+        dumper.root(format!("plist of `{sym}'"));
+        dumper.reference(sym.into(), &mut roots);
+        roots.len(kept.len());
+        for (prop, value) in kept {
+            dumper.reference(prop.into(), &mut roots);
+            dumper.reference(value, &mut roots);
+        }
+    }
+
+    let features: Vec<Object> = {
+        let features = crate::data::features().lock().unwrap();
+        features.iter().map(|x| cx.bind(*x).into()).collect()
+    };
+    dumper.root("features".to_owned());
+    dumper.references(features.into_iter(), &mut roots);
+
+    // Objects found while writing the table are appended to it
+    let mut table = Encoder::default();
+    let mut i = 0;
+    while let Some(&obj) = dumper.objects.get(i) {
+        dumper.current = dumper.owners[i];
+        dumper.object(obj, &mut table);
+        i += 1;
+    }
+
+    let mut out = Encoder::default();
+    out.0.extend_from_slice(MAGIC);
+    out.str(VERSION);
+    out.len(dumper.objects.len());
+    out.0.extend(table.0);
+    out.0.extend(roots.0);
+    std::fs::write(path, out.0).with_context(|| format!("Couldn't write {}", path.display()))?;
+    Ok(dumper.warnings.into_iter().collect())
+}
+
+#[derive(Clone, Copy)]
+enum Ref {
+    Int(i64),
+    Obj(usize),
+}
+
+enum Entry<'a> {
+    Symbol(&'a str),
+    Uninterned(&'a str),
+    Float(f64),
+    String(&'a str),
+    ByteString(&'a [u8]),
+    Cons(Ref, Ref),
+    Vec(Vec<Ref>),
+    Record(Vec<Ref>),
+    HashTable(Vec<(Ref, Ref)>),
+    ByteFn {
+        args: FnArgs,
+        depth: usize,
+        codes: &'a [u8],
+        consts: Vec<Ref>,
+        doc: Option<&'a str>,
+        interactive: Option<Ref>,
+    },
+    Subr(&'a str),
+}
+
+fn decode_entry<'a>(data: &mut Decoder<'a>) -> Result<Entry<'a>> {
+    let entry = match data.u8()? {
+        SYMBOL => Entry::Symbol(data.str()?),
+        UNINTERNED => Entry::Uninterned(data.str()?),
+        FLOAT => Entry::Float(f64::from_bits(data.u64()?)),
+        STRING => Entry::String(data.str()?),
+        BYTE_STRING => Entry::ByteString(data.bytes()?),
+        CONS => Entry::Cons(data.reference()?, data.reference()?),
+        VECTOR => Entry::Vec(data.references()?),
+        RECORD => Entry::Record(data.references()?),
+        HASH_TABLE => {
+            let len = data.len()?;
+            let pairs = (0..len).map(|_| Ok((data.reference()?, data.reference()?)));
+            Entry::HashTable(pairs.collect::<Result<_>>()?)
+        }
+        BYTE_FN => {
+            let mut args = FnArgs::from_arg_spec(data.u64()?)?;
+            args.advice = data.u8()? != 0;
+            let depth = data.len()?;
+            let codes = data.bytes()?;
+            let consts = data.references()?;
+            let has_doc = data.u8()? != 0;
+            let doc = has_doc.then_some(data.str()?);
+            let interactive = if data.u8()? != 0 { Some(data.reference()?) } else { None };
+            Entry::ByteFn { args, depth, codes, consts, doc, interactive }
+        }
+        SUBR => Entry::Subr(data.str()?),
+        tag => bail!("Invalid object tag {tag} in dump file"),
+    };
+    Ok(entry)
+}
+
+struct Restorer<'a, 'ob> {
+    entries: Vec<Entry<'a>>,
+    objects: Vec<Option<Object<'ob>>>,
+}
+
+impl<'ob> Restorer<'_, 'ob> {
+    fn get(&self, reference: Ref) -> Result<Object<'ob>> {
+        match reference {
+            Ref::Int(i) => Ok(i.into()),
+            Ref::Obj(i) => match self.objects.get(i) {
+                Some(Some(obj)) => Ok(*obj),
+                Some(None) => bail!("Dump file object {i} is used before it is defined"),
+                None => bail!("Dump file object {i} is out of range"),
+            },
+        }
+    }
+
+    /// Create the object for entry `i`. The contents of mutable objects are
+    /// filled in later, so they can refer to each other.
+    fn create(&mut self, i: usize, cx: &'ob Context) -> Result<Object<'ob>> {
+        if matches!(self.entries[i], Entry::ByteFn { .. }) {
+            return self.create_byte_fn(i, cx, 0);
+        }
+        let obj = match &self.entries[i] {
+            Entry::Symbol(name) => intern(name, cx).into(),
+            Entry::Uninterned(name) => Symbol::new_uninterned(name, cx).into(),
+            Entry::Float(f) => cx.add(*f),
+            Entry::String(s) => cx.add(*s),
+            Entry::ByteString(s) => cx.add(s.to_vec()),
+            Entry::Cons(..) => Cons::new(NIL, NIL, cx).into(),
+            Entry::Vec(refs) => cx.add(vec![NIL; refs.len()]),
+            Entry::Record(refs) => {
+                let mut record = cx.vec_with_capacity(refs.len());
+                record.resize(refs.len(), NIL);
+                cx.add(RecordBuilder(record))
+            }
+            Entry::HashTable(_) => {
+                cx.add(HashTable::with_hasher(std::hash::BuildHasherDefault::default()))
+            }
+            Entry::ByteFn { .. } => unreachable!(),
+            Entry::Subr(name) => {
+                let func = intern(name, cx).func(cx);
+                match func.map(|x| x.untag()) {
+                    Some(FunctionType::SubrFn(f)) if f.name == *name => f.into(),
+                    _ => bail!("Dump file refers to unknown builtin `{name}'"),
+                }
+            }
+        };
+        self.objects[i] = Some(obj);
+        Ok(obj)
+    }
+
+    /// Functions are immutable, so the functions in their constants have to be
+    /// created first.
+    fn create_byte_fn(&mut self, i: usize, cx: &'ob Context, level: usize) -> Result<Object<'ob>> {
+        if let Some(obj) = self.objects[i] {
+            return Ok(obj);
+        }
+        ensure!(level < 1000, "Dump file has a cycle between functions");
+        let Entry::ByteFn { consts, .. } = &self.entries[i] else { unreachable!() };
+        let consts = consts.clone();
+        let mut constants = Vec::with_capacity(consts.len());
+        for reference in consts {
+            constants.push(self.function_part(reference, cx, level)?);
+        }
+        let Entry::ByteFn { interactive, .. } = &self.entries[i] else { unreachable!() };
+        let interactive = match *interactive {
+            Some(reference) => Some(self.function_part(reference, cx, level)?),
+            None => None,
+        };
+        let Entry::ByteFn { args, depth, codes, doc, .. } = &self.entries[i] else {
+            unreachable!()
+        };
+        // SAFETY: the constants and interactive spec are allocated in the same
+        // context
+        let func: Object = unsafe {
+            let mut func = ByteFn::make(codes, constants.into_obj(cx).untag(), *args, *depth);
+            func.doc = doc.map(Into::into);
+            if let Some(spec) = interactive {
+                func.set_interactive(spec);
+            }
+            func.into_obj(cx).into()
+        };
+        self.objects[i] = Some(func);
+        Ok(func)
+    }
+
+    /// An object used to build a function, which might be another function.
+    fn function_part(
+        &mut self,
+        reference: Ref,
+        cx: &'ob Context,
+        level: usize,
+    ) -> Result<Object<'ob>> {
+        match reference {
+            Ref::Obj(j) if matches!(self.entries.get(j), Some(Entry::ByteFn { .. })) => {
+                self.create_byte_fn(j, cx, level + 1)
+            }
+            _ => self.get(reference),
+        }
+    }
+
+    fn fill(&self, i: usize) -> Result<()> {
+        let obj = self.get(Ref::Obj(i))?;
+        match (&self.entries[i], obj.untag()) {
+            (Entry::Cons(car, cdr), ObjectType::Cons(cons)) => {
+                cons.set_car(self.get(*car)?)?;
+                cons.set_cdr(self.get(*cdr)?)?;
+            }
+            (Entry::Vec(refs), ObjectType::Vec(vec)) => {
+                for (cell, reference) in vec.try_mut()?.iter().zip(refs) {
+                    cell.set(self.get(*reference)?);
+                }
+            }
+            (Entry::Record(refs), ObjectType::Record(record)) => {
+                for (cell, reference) in record.try_mut()?.iter().zip(refs) {
+                    cell.set(self.get(*reference)?);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn fill_table(&self, i: usize) -> Result<()> {
+        if let (Entry::HashTable(pairs), ObjectType::HashTable(table)) =
+            (&self.entries[i], self.get(Ref::Obj(i))?.untag())
+        {
+            for (key, value) in pairs {
+                table.insert(self.get(*key)?, self.get(*value)?);
+            }
+        }
+        Ok(())
+    }
+
+    fn symbol(&self, data: &mut Decoder) -> Result<Symbol<'ob>> {
+        match self.get(data.reference()?)?.untag() {
+            ObjectType::Symbol(sym) => Ok(sym),
+            other => bail!("Expected a symbol in dump file, found {other}"),
+        }
+    }
+}
+
+/// Restore the state saved with [`dump`] from `path`.
+pub(crate) fn restore(path: &Path, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let contents =
+        std::fs::read(path).with_context(|| format!("Couldn't open {}", path.display()))?;
+    let data = &mut Decoder { data: &contents, pos: 0 };
+    ensure!(data.take(MAGIC.len())? == MAGIC, "{} is not a dump file", path.display());
+    let version = data.str()?;
+    ensure!(version == VERSION, "Dump file is from version {version}, expected {VERSION}");
+    let len = data.len()?;
+    let entries = (0..len).map(|_| decode_entry(data)).collect::<Result<Vec<_>>>()?;
+    let mut restorer = Restorer { entries, objects: vec![None; len] };
+
+    // Nothing is rooted while the objects are recreated. That is fine because
+    // the context is borrowed immutably, so it can't collect garbage.
+    for i in 0..len {
+        if restorer.objects[i].is_none() {
+            restorer.create(i, cx)?;
+        }
+    }
+    for i in 0..len {
+        restorer.fill(i)?;
+    }
+    // Tables are filled last, because keys are hashed by their contents
+    for i in 0..len {
+        restorer.fill_table(i)?;
+    }
+
+    for _ in 0..data.len()? {
+        let sym = restorer.symbol(data)?;
+        let func = restorer.get(data.reference()?)?;
+        crate::data::fset(sym, func)?;
+    }
+    for _ in 0..data.len()? {
+        restorer.symbol(data)?.make_special();
+    }
+    for _ in 0..data.len()? {
+        let sym = restorer.symbol(data)?;
+        let value = restorer.get(data.reference()?)?;
+        if !sym.is_const() {
+            env.set_var(sym, value)?;
+        }
+    }
+    for _ in 0..data.len()? {
+        let sym = restorer.symbol(data)?;
+        env.constants.insert(sym, ());
+    }
+    for _ in 0..data.len()? {
+        let sym = restorer.symbol(data)?;
+        for _ in 0..data.len()? {
+            let prop = restorer.symbol(data)?;
+            let value = restorer.get(data.reference()?)?;
+            env.set_prop(sym, prop, value);
+        }
+    }
+    for _ in 0..data.len()? {
+        crate::data::add_feature(restorer.symbol(data)?);
+    }
+    Ok(())
+}
+
+/// Dump the current state to FILENAME. It can be restored at startup with
+/// `--dump-file FILENAME` instead of loading the standard library again.
+/// Anything that couldn't be dumped, like a variable holding a buffer, is
+/// reported on stderr.
+#[defun(io)]
+fn dump_emacs_portable(
+    filename: &str,
+    _track_referrers: Option<()>,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    for warning in dump(Path::new(filename), env, cx)? {
+        eprintln!("Warning: {warning}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::env::sym;
+    use crate::core::gc::RootSet;
+    use rune_core::macros::{list, root};
+
+    #[test]
+    fn test_dump_restore() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        let path = std::env::temp_dir().join(format!("rune-pdump-{}", std::process::id()));
+
+        let var = intern("pdump-test-var", cx);
+        let shared = list!["shared", 2.5; cx];
+        let value = list![shared, shared, Symbol::new_uninterned("pdump-test-uninterned", cx); cx];
+        env.set_var(var, value).unwrap();
+        var.make_special();
+        let table = cx.add(HashTable::with_hasher(std::hash::BuildHasherDefault::default()));
+        let ObjectType::HashTable(map) = table.untag() else { unreachable!() };
+        map.insert(cx.add("key"), cx.add(vec![cx.add(1), NIL]));
+        env.set_prop(var, intern("pdump-test-prop", cx), table);
+        let func = intern("pdump-test-func", cx);
+        let body = list![sym::CLOSURE, list![sym::TRUE; cx], NIL, 7; cx];
+        crate::data::fset(func, body).unwrap();
+        crate::data::add_feature(intern("pdump-test-feature", cx));
+        let constant = intern("pdump-test-const", cx);
+        env.defconst(constant, cx.add(3)).unwrap();
+        dump(&path, env, cx).unwrap();
+
+        env.vars.remove(var);
+        env.props.remove(var);
+        func.unbind_func();
+        env.constants.remove(constant);
+        env.vars.remove(constant);
+        restore(&path, env, cx).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let restored = env.vars.get(var).unwrap().bind(cx);
+        assert_eq!(restored.to_string(), value.to_string());
+        let list: Vec<_> = restored.as_list().unwrap().map(|x| x.unwrap()).collect();
+        // shared structure is kept
+        assert!(list[0].ptr_eq(list[1]));
+        let ObjectType::Symbol(uninterned) = list[2].untag() else { unreachable!() };
+        assert!(!uninterned.interned());
+        let table = crate::data::get(var, intern("pdump-test-prop", cx), env, cx);
+        let ObjectType::HashTable(map) = table.untag() else { unreachable!() };
+        assert_eq!(map.get(cx.add("key")).unwrap().to_string(), "[1 nil]");
+        assert_eq!(Object::from(func.func(cx).unwrap()).to_string(), body.to_string());
+        let features = crate::data::features().lock().unwrap();
+        assert!(features.iter().any(|x| x.name() == "pdump-test-feature"));
+        assert!(var.is_special());
+        assert!(env.is_constant(constant));
+        assert_eq!(env.vars.get(constant).unwrap().bind(cx), 3);
+    }
+
+    #[test]
+    fn test_dump_unsupported() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        let path = std::env::temp_dir().join(format!("rune-pdump-skip-{}", std::process::id()));
+
+        let buffer = crate::buffer::get_buffer_create(cx.add("pdump-test"), None, cx).unwrap();
+        let direct = intern("pdump-test-buffer", cx);
+        env.set_var(direct, buffer).unwrap();
+        let nested = intern("pdump-test-buffer-list", cx);
+        env.set_var(nested, list![1, buffer; cx]).unwrap();
+        let mut slots = cx.vec_with_capacity(1);
+        slots.push(intern("pdump-test-type", cx).into());
+        let record = cx.add(RecordBuilder(slots));
+        let ObjectType::Record(inner) = record.untag() else { unreachable!() };
+        crate::core::gc::attach(inner, Box::new(5));
+        let foreign = intern("pdump-test-foreign", cx);
+        env.set_var(foreign, record).unwrap();
+        let warnings = dump(&path, env, cx).unwrap();
+        for warning in [
+            "variable `pdump-test-buffer' holds a buffer and was left out",
+            "variable `pdump-test-buffer-list' contains a buffer, which was dumped as nil",
+            "variable `pdump-test-foreign' contains a record with foreign data, which was dumped \
+             without it",
+        ] {
+            assert!(warnings.iter().any(|x| x == warning), "missing warning: {warning}");
+        }
+
+        env.vars.remove(direct);
+        env.vars.remove(nested);
+        restore(&path, env, cx).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(env.vars.get(direct).is_none());
+        assert_eq!(env.vars.get(nested).unwrap().bind(cx), list![1, NIL; cx]);
+    }
+
+    #[test]
+    fn test_restore_invalid() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        let path = std::env::temp_dir().join(format!("rune-pdump-invalid-{}", std::process::id()));
+        std::fs::write(&path, b"not a dump").unwrap();
+        assert!(restore(&path, env, cx).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,328 @@
+//! Pretty printing.
+//!
+//! A form that fits in the line width is printed on one line. Otherwise a
+//! list that starts with a symbol is broken up following the symbol's
+//! `lisp-indent-function` property, like the indentation of `lisp-mode`: the
+//! first N arguments stay on the first line if they fit, and the body is
+//! indented by two. Other calls have their arguments lined up under the first
+//! one, and lists of data have their elements lined up under each other.
+use crate::core::{
+    cons::Cons,
+    env::{sym, Env},
+    gc::{Context, Rt, Rto},
+    object::{LispVec, Object, ObjectType, Symbol},
+};
+use crate::print::write_to_stream;
+use anyhow::Result;
+use rune_core::hashmap::HashSet;
+use rune_macros::defun;
+
+defsym!(LISP_INDENT_FUNCTION);
+
+/// The line width used when `fill-column` is not an integer.
+const DEFAULT_WIDTH: usize = 70;
+
+/// The number of distinguished arguments of forms with no
+/// `lisp-indent-function` property. `lisp-mode.el` sets these, but it is not
+/// loaded.
+const BODY_INDENT: [(&str, usize); 24] = [
+    ("catch", 1),
+    ("cl-defun", 2),
+    ("closure", 2),
+    ("condition-case", 2),
+    ("defmacro", 2),
+    ("defsubst", 2),
+    ("defun", 2),
+    ("dolist", 1),
+    ("dotimes", 1),
+    ("if", 2),
+    ("lambda", 1),
+    ("let", 1),
+    ("let*", 1),
+    ("pcase", 1),
+    ("prog1", 1),
+    ("prog2", 2),
+    ("progn", 0),
+    ("save-current-buffer", 0),
+    ("save-excursion", 0),
+    ("unless", 1),
+    ("unwind-protect", 1),
+    ("when", 1),
+    ("while", 1),
+    ("with-current-buffer", 1),
+];
+
+/// The width of `text` in columns.
+fn width(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// The column after printing `text` starting at `column`.
+fn end_column(column: usize, text: &str) -> usize {
+    match text.rfind('\n') {
+        Some(i) => width(&text[i + 1..]),
+        None => column + width(text),
+    }
+}
+
+/// If `cons` is a form like `(quote x)`, return the prefix that is printed
+/// instead and `x`.
+fn quoted<'ob>(cons: &'ob Cons) -> Option<(&'static str, Object<'ob>)> {
+    let ObjectType::Symbol(head) = cons.car().untag() else { return None };
+    let prefix = match head {
+        sym::QUOTE => "'",
+        sym::FUNCTION => "#'",
+        sym::BACKQUOTE => "`",
+        sym::UNQUOTE => ",",
+        sym::SPLICE => ",@",
+        _ => return None,
+    };
+    let ObjectType::Cons(rest) = cons.cdr().untag() else { return None };
+    rest.cdr().is_nil().then(|| (prefix, rest.car()))
+}
+
+/// The elements of the list starting at `cons` and its final cdr. The list
+/// must not be circular.
+fn list_parts<'ob>(mut cons: &'ob Cons) -> (Vec<Object<'ob>>, Object<'ob>) {
+    let mut elements = Vec::new();
+    loop {
+        elements.push(cons.car());
+        match cons.cdr().untag() {
+            ObjectType::Cons(next) => cons = next,
+            _ => return (elements, cons.cdr()),
+        }
+    }
+}
+
+/// Whether `obj` contains itself. `path` holds the conses and vectors that
+/// contain `obj`.
+fn is_circular(obj: Object, path: &mut HashSet<*const u8>) -> bool {
+    let mut entered = Vec::new();
+    let mut children = Vec::new();
+    match obj.untag() {
+        ObjectType::Cons(mut cons) => loop {
+            let ptr = (cons as *const Cons).cast();
+            if !path.insert(ptr) {
+                return true;
+            }
+            entered.push(ptr);
+            children.push(cons.car());
+            match cons.cdr().untag() {
+                ObjectType::Cons(next) => cons = next,
+                _ => {
+                    children.push(cons.cdr());
+                    break;
+                }
+            }
+        },
+        ObjectType::Vec(vec) => {
+            let ptr = (vec as *const LispVec).cast();
+            if !path.insert(ptr) {
+                return true;
+            }
+            entered.push(ptr);
+            children.extend(vec.iter().map(|x| x.get()));
+        }
+        _ => return false,
+    }
+    if children.into_iter().any(|x| is_circular(x, path)) {
+        return true;
+    }
+    for ptr in entered {
+        path.remove(&ptr);
+    }
+    false
+}
+
+/// Print `objs` on one line, separated by spaces.
+fn flat_join<'ob>(objs: impl Iterator<Item = Object<'ob>>) -> String {
+    objs.map(flat).collect::<Vec<_>>().join(" ")
+}
+
+/// Print `obj` on one line.
+fn flat(obj: Object) -> String {
+    match obj.untag() {
+        ObjectType::Cons(cons) => {
+            if let Some((prefix, quoted)) = quoted(cons) {
+                return format!("{prefix}{}", flat(quoted));
+            }
+            let (elements, tail) = list_parts(cons);
+            let elements = flat_join(elements.into_iter());
+            if tail.is_nil() {
+                format!("({elements})")
+            } else {
+                format!("({elements} . {})", flat(tail))
+            }
+        }
+        ObjectType::Vec(vec) => format!("[{}]", flat_join(vec.iter().map(|x| x.get()))),
+        _ => obj.to_string(),
+    }
+}
+
+struct Printer<'brw, 'env, 'rt> {
+    width: usize,
+    env: &'brw Rt<Env<'env>>,
+    cx: &'brw Context<'rt>,
+}
+
+impl Printer<'_, '_, '_> {
+    /// The number of distinguished arguments of forms that start with
+    /// `symbol`, or `None` if they are function calls.
+    fn indent(&self, symbol: Symbol) -> Option<usize> {
+        match crate::data::get(symbol, sym::LISP_INDENT_FUNCTION, self.env, self.cx).untag() {
+            ObjectType::Int(n) => usize::try_from(n).ok(),
+            ObjectType::Symbol(sym::DEFUN) => Some(2),
+            _ => BODY_INDENT.iter().find(|x| x.0 == symbol.name()).map(|x| x.1),
+        }
+    }
+
+    /// Print `obj`, which starts at `column`.
+    fn layout(&self, obj: Object, column: usize) -> String {
+        let text = flat(obj);
+        if column + width(&text) <= self.width {
+            return text;
+        }
+        match obj.untag() {
+            ObjectType::Cons(cons) => self.layout_list(cons, column).unwrap_or(text),
+            ObjectType::Vec(vec) => {
+                let elements: Vec<_> = vec.iter().map(|x| x.get()).collect();
+                format!("[{}]", self.lines(&elements, column + 1))
+            }
+            _ => text,
+        }
+    }
+
+    /// Print `objs` on their own lines, all starting at `column`.
+    fn lines(&self, objs: &[Object], column: usize) -> String {
+        let separator = format!("\n{}", " ".repeat(column));
+        let lines: Vec<_> = objs.iter().map(|x| self.layout(*x, column)).collect();
+        lines.join(&separator)
+    }
+
+    fn layout_list(&self, cons: &Cons, column: usize) -> Option<String> {
+        if let Some((prefix, quoted)) = quoted(cons) {
+            return Some(format!("{prefix}{}", self.layout(quoted, column + prefix.len())));
+        }
+        let (elements, tail) = list_parts(cons);
+        // dotted lists stay on one line
+        if !tail.is_nil() {
+            return None;
+        }
+        let (head, args) = elements.split_first()?;
+        let ObjectType::Symbol(symbol) = head.untag() else {
+            return Some(format!("({})", self.lines(&elements, column + 1)));
+        };
+        let mut out = format!("({symbol}");
+        let mut current = column + width(&out);
+        if let Some(indent) = self.indent(symbol) {
+            let (distinguished, body) = args.split_at(indent.min(args.len()));
+            let flat_width: usize = distinguished.iter().map(|x| width(&flat(*x)) + 1).sum();
+            let fits = current + flat_width <= self.width;
+            for (i, arg) in distinguished.iter().enumerate() {
+                if i == 0 || fits {
+                    let text = self.layout(*arg, current + 1);
+                    current = end_column(current + 1, &text);
+                    out = format!("{out} {text}");
+                } else {
+                    let indent = " ".repeat(column + 4);
+                    out = format!("{out}\n{indent}{}", self.layout(*arg, column + 4));
+                }
+            }
+            if !body.is_empty() {
+                let indent = " ".repeat(column + 2);
+                out = format!("{out}\n{indent}{}", self.lines(body, column + 2));
+            }
+        } else if !args.is_empty() {
+            // arguments line up under the first one
+            out = format!("{out} {}", self.lines(args, current + 1));
+        }
+        out.push(')');
+        Some(out)
+    }
+}
+
+/// Pretty print `obj` within `width` columns where possible.
+pub(crate) fn pp_string(obj: Object, width: usize, env: &Rt<Env>, cx: &Context) -> String {
+    if is_circular(obj, &mut HashSet::default()) {
+        return format!("{obj}\n");
+    }
+    let printer = Printer { width, env, cx };
+    format!("{}\n", printer.layout(obj, 0))
+}
+
+/// Return a pretty printed representation of OBJECT, ending in a newline.
+/// Lines are kept within `fill-column` where possible. PP-FUNCTION is
+/// ignored.
+#[defun]
+fn pp_to_string(
+    object: Object,
+    _pp_function: Option<Object>,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> String {
+    let width = match env.vars.get(sym::FILL_COLUMN).map(|x| x.bind(cx).untag()) {
+        Some(ObjectType::Int(column)) => usize::try_from(column).unwrap_or(DEFAULT_WIDTH),
+        _ => DEFAULT_WIDTH,
+    };
+    pp_string(object, width, env, cx)
+}
+
+/// Output the pretty printed representation of OBJECT to STREAM, or to
+/// `standard-output` if it is nil.
+#[defun]
+fn pp(
+    object: &Rto<Object>,
+    stream: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let text = pp_to_string(object.bind(cx), None, env, cx);
+    write_to_stream(&text, stream, env, cx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::reader::read;
+    use rune_core::macros::root;
+
+    fn check(source: &str, width: usize, expect: &str) {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        let obj = read(source, cx).unwrap().0;
+        assert_eq!(pp_string(obj, width, env, cx), format!("{expect}\n"));
+    }
+
+    #[test]
+    fn test_pp() {
+        check("(a b c)", 70, "(a b c)");
+        check("(quote (a b))", 70, "'(a b)");
+        check("(function car)", 70, "#'car");
+        check("(a . b)", 2, "(a . b)");
+        let expect = "(let ((x 1) (y 2))\n  (foo x y)\n  (bar))";
+        check("(let ((x 1) (y 2)) (foo x y) (bar))", 20, expect);
+        check("(foo (bar 1 2) (baz 3 4))", 15, "(foo (bar 1 2)\n     (baz 3 4))");
+        check(
+            "(if (> x 1) (message \"big\") (message \"small\"))",
+            20,
+            "(if (> x 1)\n    (message \"big\")\n  (message \"small\"))",
+        );
+        check("(defun f (x) (car x))", 15, "(defun f (x)\n  (car x))");
+        check("[aaaa bbbb cccc]", 10, "[aaaa\n bbbb\n cccc]");
+        check("((a . 1) (b . 2))", 10, "((a . 1)\n (b . 2))");
+        check("'(a b c d)", 5, "'(a b\n    c\n    d)");
+    }
+
+    #[test]
+    fn test_pp_circular() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        let list = read("(1 2)", cx).unwrap().0;
+        let ObjectType::Cons(cons) = list.untag() else { unreachable!() };
+        cons.set_car(list).unwrap();
+        assert_eq!(pp_string(list, 2, env, cx), format!("{list}\n"));
+    }
+}
@@ -1,13 +1,362 @@
 //! Printing utilities.
-use crate::core::object::Object;
+//!
+//! The print functions send their output to a stream, which is either a
+//! buffer (text is inserted at point), a function (called with each
+//! character), or `t` for the terminal. When no stream is given the value of
+//! `standard-output` is used.
+use crate::alloc::opaque_record_id;
+use crate::core::{
+    env::{sym, Env},
+    error::{Type, TypeError},
+    gc::{Context, Rt, Rto},
+    object::{Function, Object, ObjectType, Record, Symbol, NIL},
+};
+use anyhow::{anyhow, bail, Result};
+use rune_core::hashmap::HashSet;
+use rune_core::macros::{call, root};
 use rune_macros::defun;
+use std::io::Write;
 
+defvar!(STANDARD_OUTPUT, true);
+defsym!(WITH_OUTPUT_TO_STRING);
+
+/// Write `text` to `printcharfun`, or to `standard-output` if it is nil.
+pub(crate) fn write_to_stream(
+    text: &str,
+    printcharfun: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let stream = match printcharfun {
+        Some(stream) => stream.bind(cx),
+        None => env.vars.get(sym::STANDARD_OUTPUT).map_or(NIL, |x| x.bind(cx)),
+    };
+    match stream.untag() {
+        ObjectType::NIL | ObjectType::Symbol(sym::TRUE) => {
+            let mut stdout = std::io::stdout();
+            stdout.write_all(text.as_bytes())?;
+            stdout.flush()?;
+        }
+        ObjectType::Buffer(buffer) => {
+            let inserted = env.with_buffer_mut(Some(buffer), |buffer| buffer.text.insert(text));
+            inserted.ok_or_else(|| anyhow!("Selecting deleted buffer"))?;
+        }
+        _ => {
+            let function: Function = stream.try_into()?;
+            root!(function, cx);
+            for c in text.chars() {
+                let c: Object = (c as i64).into();
+                call!(function, c; env, cx)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The printed representation of `object` without quoting. Only top level
+/// strings are printed raw for now.
+fn princ_string(object: Object) -> String {
+    match object.untag() {
+        ObjectType::String(string) => string.to_string(),
+        _ => format!("{object}"),
+    }
+}
+
+#[defun]
+fn princ<'ob>(
+    object: &Rto<Object>,
+    printcharfun: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let text = princ_string(object.bind(cx));
+    write_to_stream(&text, printcharfun, env, cx)?;
+    Ok(object.bind(cx))
+}
+
+#[defun]
+fn prin1<'ob>(
+    object: &Rto<Object>,
+    printcharfun: Option<&Rto<Object>>,
+    _overrides: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let text = format!("{}", object.bind(cx));
+    write_to_stream(&text, printcharfun, env, cx)?;
+    Ok(object.bind(cx))
+}
+
+#[defun]
+fn print<'ob>(
+    object: &Rto<Object>,
+    printcharfun: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let text = format!("\n{}\n", object.bind(cx));
+    write_to_stream(&text, printcharfun, env, cx)?;
+    Ok(object.bind(cx))
+}
+
+#[defun]
+fn terpri(
+    printcharfun: Option<&Rto<Object>>,
+    _ensure: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    write_to_stream("\n", printcharfun, env, cx)?;
+    Ok(true)
+}
+
+/// Create the buffer that collects the output of `with-output-to-string`.
+pub(crate) fn string_output_buffer<'ob>(cx: &'ob Context) -> Result<Object<'ob>> {
+    let name = crate::buffer::generate_new_buffer_name(" *string-output*", None);
+    crate::buffer::get_buffer_create(cx.add(name), Some(NIL), cx)
+}
+
+/// Kill the collector `buffer` and return everything that was printed to it.
+pub(crate) fn take_string_output(buffer: Object, env: &mut Rt<Env>) -> Result<String> {
+    let ObjectType::Buffer(buffer) = buffer.untag() else {
+        bail!(TypeError::new(Type::Buffer, buffer))
+    };
+    let text = env.with_buffer_mut(Some(buffer), |buffer| {
+        let text = buffer.text.as_str().to_string();
+        buffer.kill();
+        text
+    });
+    if env.current_buffer.as_ref().is_some_and(|current| current == buffer) {
+        env.current_buffer = None;
+    }
+    text.ok_or_else(|| anyhow!("Selecting deleted buffer"))
+}
+
+/// Convert an error value `(ERROR-SYMBOL . DATA)` to a message, the way it is
+/// shown when the error is not handled. The message comes from the
+/// `error-message` property of ERROR-SYMBOL, followed by the items of DATA.
+#[defun]
+fn error_message_string(obj: Object, env: &Rt<Env>, cx: &Context) -> Result<String> {
+    let (symbol, mut data) = match obj.untag() {
+        ObjectType::Cons(cons) => (cons.car(), cons.cdr()),
+        ObjectType::NIL => (NIL, NIL),
+        _ => bail!(TypeError::new(Type::List, obj)),
+    };
+    let mut file_error = false;
+    let mut message = match symbol.untag() {
+        // The message of a plain `error` is the first item
+        ObjectType::Symbol(sym::ERROR) => match data.untag() {
+            ObjectType::Cons(cons) => {
+                data = cons.cdr();
+                cons.car()
+            }
+            _ => NIL,
+        },
+        ObjectType::Symbol(symbol) => {
+            let conditions = crate::data::get(symbol, sym::ERROR_CONDITIONS, env, cx);
+            for condition in conditions.as_list()? {
+                file_error |= condition? == sym::FILE_ERROR;
+            }
+            crate::data::get(symbol, sym::ERROR_MESSAGE, env, cx)
+        }
+        _ => NIL,
+    };
+    // The items of a file error are all part of the message
+    if file_error {
+        if let ObjectType::Cons(cons) = data.untag() {
+            message = cons.car();
+            data = cons.cdr();
+        }
+    }
+    let raw = file_error || symbol == sym::END_OF_FILE || symbol == sym::USER_ERROR;
+    let mut text = String::new();
+    let mut separator = Some(": ");
+    match message.untag() {
+        ObjectType::String(message) if message.is_empty() => separator = None,
+        ObjectType::String(message) => text.push_str(message),
+        _ => text.push_str("peculiar error"),
+    }
+    if let ObjectType::Cons(items) = data.untag() {
+        for item in items {
+            let item = item?;
+            text.push_str(separator.unwrap_or_default());
+            separator = Some(", ");
+            if raw {
+                text.push_str(&princ_string(item));
+            } else {
+                text.push_str(&item.to_string());
+            }
+        }
+    }
+    Ok(text)
+}
+
+/// The printed form of a record that stands for a runtime object: a process,
+/// thread, mutex, or condition variable. These are records `#s(KIND ID NAME)`
+/// made by [`make_opaque_record`](crate::alloc::make_opaque_record), and print
+/// as `#<KIND NAME>`, or `#<KIND ID>` if they have no name.
+pub(crate) fn opaque_record(record: &Record) -> Option<String> {
+    const KINDS: [(Symbol<'static>, &str); 4] = [
+        (sym::PROCESS, "process"),
+        (sym::THREAD, "thread"),
+        (sym::MUTEX, "mutex"),
+        (sym::CONDITION_VARIABLE, "condvar"),
+    ];
+    let (id, kind) = KINDS
+        .iter()
+        .find_map(|(kind, name)| Some((opaque_record_id(record, *kind)?, name)))?;
+    match record[2].get().untag() {
+        ObjectType::String(name) => Some(format!("#<{kind} {name}>")),
+        _ => Some(format!("#<{kind} {id}>")),
+    }
+}
+
+/// Return true if the printed representation of `object` can be read back in.
+/// Opaque runtime objects (subrs, buffers, threads, etc) print as `#<...>` and objects
+/// seen twice print as `#0`, neither of which the reader accepts.
 #[defun]
-fn error_message_string(obj: Object) -> String {
-    // TODO: implement
-    format!("Error: {obj}")
+pub(crate) fn readablep(object: Object) -> bool {
+    is_readable(object, &mut HashSet::default())
+}
+
+fn is_readable(object: Object, seen: &mut HashSet<*const u8>) -> bool {
+    match object.untag() {
+        ObjectType::SubrFn(_) | ObjectType::Buffer(_) => false,
+        ObjectType::Cons(cons) => {
+            let mut cons = cons;
+            loop {
+                if !seen.insert((cons as *const _).cast()) {
+                    return false;
+                }
+                if !is_readable(cons.car(), seen) {
+                    return false;
+                }
+                match cons.cdr().untag() {
+                    ObjectType::Cons(tail) => cons = tail,
+                    _ => return is_readable(cons.cdr(), seen),
+                }
+            }
+        }
+        ObjectType::Vec(vec) => {
+            seen.insert((vec as *const _).cast()) && vec.iter().all(|x| is_readable(x.get(), seen))
+        }
+        ObjectType::Record(record) => {
+            opaque_record(record).is_none()
+                && seen.insert((record as *const _).cast())
+                && record.iter().all(|x| is_readable(x.get(), seen))
+        }
+        ObjectType::HashTable(table) => {
+            seen.insert((table as *const _).cast())
+                && (0..table.len()).all(|i| match table.get_index(i) {
+                    Some((k, v)) => is_readable(k, seen) && is_readable(v, seen),
+                    None => true,
+                })
+        }
+        _ => true,
+    }
 }
 
 defvar!(PRINT_LENGTH);
 defvar!(PRINT_LEVEL);
 defvar_bool!(PRINT_ESCAPE_NEWLINES, false);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::{
+        env::sym,
+        gc::{Context, RootSet},
+    };
+    use crate::interpreter::eval_to_string;
+    use rune_core::macros::list;
+
+    #[test]
+    fn test_readablep() {
+        sym::init_symbols();
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        assert!(readablep(cx.add(1)));
+        assert!(readablep(cx.add("foo")));
+        assert!(readablep(list![1, 2, 3; cx]));
+        let subr: Object = sym::CAR.func(cx).unwrap().into();
+        assert!(!readablep(subr));
+        assert!(!readablep(list![1, subr; cx]));
+        assert!(!readablep(cx.add(vec![subr])));
+    }
+
+    #[test]
+    fn test_opaque_records() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        let named = r##"(equal (format "%S" (make-mutex "lock")) "#<mutex lock>")"##;
+        assert_eq!(eval_to_string(named, env, cx).unwrap(), "t");
+        let unnamed = r##"(let ((mutex (make-mutex)))
+                           (equal (prin1-to-string mutex) (format "#<mutex %d>" (aref mutex 1))))"##;
+        assert_eq!(eval_to_string(unnamed, env, cx).unwrap(), "t");
+        let thread = r##"(let ((thread (make-thread (lambda () 1) "worker")))
+                          (thread-join thread)
+                          (equal (format "%S" thread) "#<thread worker>"))"##;
+        assert_eq!(eval_to_string(thread, env, cx).unwrap(), "t");
+        let cond = r##"(equal (format "%S" (make-condition-variable (make-mutex) "ready"))
+                             "#<condvar ready>")"##;
+        assert_eq!(eval_to_string(cond, env, cx).unwrap(), "t");
+        let nested = r##"(not (readablep (list 1 (make-mutex "lock"))))"##;
+        assert_eq!(eval_to_string(nested, env, cx).unwrap(), "t");
+        assert_eq!(eval_to_string("(readablep (record 'mutex 1 2 3))", env, cx).unwrap(), "t");
+        let forged = r##"(equal (format "%S" (record 'mutex 1 "lock")) "#s(mutex 1 \"lock\")")"##;
+        assert_eq!(eval_to_string(forged, env, cx).unwrap(), "t");
+    }
+
+    #[test]
+    fn test_print_streams() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        let string = r#"(equal (with-output-to-string (princ "a") (prin1 "b") (terpri) (princ 1))
+                               "a\"b\"\n1")"#;
+        assert_eq!(eval_to_string(string, env, cx).unwrap(), "t");
+        let print = r#"(equal (with-output-to-string (print 'x)) "\nx\n")"#;
+        assert_eq!(eval_to_string(print, env, cx).unwrap(), "t");
+        let nested = r#"(equal (with-output-to-string
+                                 (princ (with-output-to-string (princ "inner")))
+                                 (princ "outer"))
+                               "innerouter")"#;
+        assert_eq!(eval_to_string(nested, env, cx).unwrap(), "t");
+        let function = "(progn (defvar print-test-chars nil)
+                               (princ \"ab\" (lambda (c) (setq print-test-chars
+                                                               (cons c print-test-chars))))
+                               (equal print-test-chars '(98 97)))";
+        assert_eq!(eval_to_string(function, env, cx).unwrap(), "t");
+        assert_eq!(eval_to_string("(eq (prin1 'foo (lambda (_))) 'foo)", env, cx).unwrap(), "t");
+        assert!(eval_to_string("(with-output-to-string (error \"fail\"))", env, cx).is_err());
+        let special = "(special-form-p 'with-output-to-string)";
+        assert_eq!(eval_to_string(special, env, cx).unwrap(), "t");
+    }
+
+    #[test]
+    fn test_error_message_string() {
+        sym::init_symbols();
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        crate::eval::init_errors(env, cx);
+        let cases = [
+            ("(error \"Oops %s\" 1)", "Oops %s: 1"),
+            ("(error \"Oops\")", "Oops"),
+            ("(void-variable foo)", "Symbol's value as variable is void: foo"),
+            ("(wrong-type-argument listp \"x\")", "Wrong type argument: listp, \"x\""),
+            ("(user-error \"Plain\")", "Plain"),
+            (
+                "(file-missing \"Opening input file\" \"No such file\" \"/x\")",
+                "Opening input file: No such file, /x",
+            ),
+            ("(unknown-error 1)", "peculiar error: 1"),
+            ("nil", "peculiar error"),
+        ];
+        for (error, expect) in cases {
+            let error = crate::reader::read(error, cx).unwrap().0;
+            assert_eq!(error_message_string(error, env, cx).unwrap(), expect);
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//! Waiting for work done in the background.
+//!
+//! Rune has no command loop. Instead, work like the request made by
+//! `http-fetch-async` runs on its own thread, and its callback is called on
+//! the lisp thread that started it the next time that thread waits with
+//! `accept-process-output` or `sleep-for`. Until then the callback is held in
+//! the environment so the garbage collector can see it.
+//!
+//! Work that is started this way is returned to lisp as a process, a record
+//! `#s(process ID NAME)` made by
+//! [`make_opaque_record`](crate::alloc::make_opaque_record).
+use crate::alloc::{make_opaque_record, opaque_record_id};
+use crate::core::{
+    env::{sym, Env},
+    error::{Type, TypeError},
+    gc::{Context, Rt, Rto},
+    object::{Function, Object, ObjectType, NIL},
+};
+use anyhow::Result;
+use rune_core::macros::{call, root};
+use rune_macros::defun;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The result of background work, which is turned into the argument of its
+/// callback once it is back on the lisp thread.
+pub(crate) trait Finish: Send {
+    fn finish<'ob>(self: Box<Self>, env: &mut Rt<Env>, cx: &'ob Context) -> Result<Object<'ob>>;
+}
+
+struct Event {
+    /// Index of the callback in `Env::callbacks`
+    id: usize,
+    result: Box<dyn Finish>,
+}
+
+thread_local! {
+    static EVENTS: (Sender<Event>, Receiver<Event>) = mpsc::channel();
+}
+
+/// Run `work` on a new thread. When it is done, `callback` is called with the
+/// object its result finishes into. Returns the process for the work, called
+/// `name`.
+pub(crate) fn spawn<'ob, T: Finish + 'static>(
+    name: &str,
+    callback: Function,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+    work: impl FnOnce() -> T + Send + 'static,
+) -> Object<'ob> {
+    let id = env.callbacks.len();
+    env.callbacks.push(Object::from(callback));
+    let events = EVENTS.with(|x| x.0.clone());
+    thread::spawn(move || {
+        let result = Box::new(work());
+        // The receiver lives as long as the lisp thread
+        let _ = events.send(Event { id, result });
+    });
+    make_opaque_record(sym::PROCESS, cx.add(name), cx).0
+}
+
+/// Call the callbacks of work that finishes within `timeout`, or of the first
+/// work to finish if `timeout` is `None`. Returns true if any were called.
+pub(crate) fn run_events(
+    timeout: Option<Duration>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    if env.callbacks.is_empty() {
+        if let Some(timeout) = timeout {
+            thread::sleep(timeout);
+        }
+        return Ok(false);
+    }
+    let first = EVENTS.with(|(_, events)| match timeout {
+        Some(timeout) => events.recv_timeout(timeout).ok(),
+        None => events.recv().ok(),
+    });
+    let Some(first) = first else { return Ok(false) };
+    run_event(first, env, cx)?;
+    // Anything else that finished in the meantime
+    while let Some(event) = EVENTS.with(|(_, events)| events.try_recv().ok()) {
+        run_event(event, env, cx)?;
+    }
+    Ok(true)
+}
+
+fn run_event(event: Event, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let Some(callback) = env.callbacks.get(event.id) else { return Ok(()) };
+    let callback: Function = callback.bind(cx).try_into()?;
+    root!(callback, cx);
+    env.callbacks[event.id].set(NIL);
+    while env.callbacks.last().is_some_and(|x| x.bind(cx).is_nil()) {
+        let len = env.callbacks.len();
+        env.callbacks.truncate(len - 1);
+    }
+    let arg = event.result.finish(env, cx)?;
+    call!(callback, arg; env, cx)?;
+    Ok(())
+}
+
+/// A number of seconds as a duration. Negative numbers are zero.
+fn duration(seconds: Object) -> Result<Duration> {
+    let seconds = match seconds.untag() {
+        ObjectType::Int(x) => x as f64,
+        ObjectType::Float(x) => **x,
+        _ => return Err(TypeError::new(Type::Number, seconds).into()),
+    };
+    let seconds = seconds.clamp(0.0, f64::from(u32::MAX));
+    Ok(Duration::try_from_secs_f64(seconds).unwrap_or_default())
+}
+
+/// Wait for background work to finish, and call its callbacks. Wait at most
+/// SECONDS plus MILLISEC, or until some work finishes if SECONDS is nil.
+/// Return non-nil if any callback was called. Rune has no subprocesses, so
+/// PROCESS and JUST-THIS-ONE are ignored.
+#[defun]
+fn accept_process_output(
+    _process: Option<()>,
+    seconds: Option<&Rto<Object>>,
+    millisec: Option<i64>,
+    _just_this_one: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let timeout = match seconds {
+        Some(seconds) => {
+            let millisec = Duration::from_millis(millisec.unwrap_or(0).max(0) as u64);
+            Some(duration(seconds.bind(cx))? + millisec)
+        }
+        None => None,
+    };
+    run_events(timeout, env, cx)
+}
+
+/// Wait for SECONDS plus MILLISECONDS, calling the callbacks of background work
+/// that finishes in the meantime.
+#[defun]
+fn sleep_for(
+    seconds: &Rto<Object>,
+    milliseconds: Option<i64>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let milliseconds = Duration::from_millis(milliseconds.unwrap_or(0).max(0) as u64);
+    let deadline = Instant::now() + duration(seconds.bind(cx))? + milliseconds;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(());
+        }
+        run_events(Some(remaining), env, cx)?;
+    }
+}
+
+/// Return t if OBJECT is a process, the background work started by a
+/// function like `http-fetch-async`.
+#[defun]
+fn processp(object: Object) -> bool {
+    let ObjectType::Record(record) = object.untag() else { return false };
+    opaque_record_id(record, sym::PROCESS).is_some()
+}
+
+defsym!(PROCESS);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::env::{intern, sym};
+    use crate::core::gc::RootSet;
+
+    struct Answer(i64);
+
+    impl Finish for Answer {
+        fn finish<'ob>(self: Box<Self>, _: &mut Rt<Env>, cx: &'ob Context) -> Result<Object<'ob>> {
+            Ok(cx.add(self.0))
+        }
+    }
+
+    #[test]
+    fn test_callbacks() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        sym::init_symbols();
+        let source = "(defalias 'process-test-callback (lambda (x) (setq process-test-result x)))";
+        crate::lread::load_internal(source, cx, env).unwrap();
+        let callback = intern("process-test-callback", cx);
+        let process = spawn("answer", callback.into(), env, cx, || Answer(42));
+        assert!(processp(process));
+        assert_eq!(process.to_string(), "#<process answer>");
+        assert!(accept_process_output(None, None, None, None, env, cx).unwrap());
+        let result = intern("process-test-result", cx);
+        assert_eq!(env.vars.get(result).unwrap().bind(cx), 42);
+        assert!(env.callbacks.is_empty());
+
+        // nothing is left to wait for
+        assert!(!accept_process_output(None, None, None, None, env, cx).unwrap());
+    }
+}
@@ -0,0 +1,195 @@
+//! Profiling and benchmarking lisp functions.
+use crate::core::{
+    cons::Cons,
+    env::{intern, sym, Env},
+    error::{Type, TypeError},
+    gc::{profiler, Context, Rt, Rto},
+    object::{Function, Object, ObjectType, Symbol, NIL},
+};
+use crate::eval::EvalResult;
+use crate::interpreter::{Interpreter, SpecialForm};
+use anyhow::{bail, Result};
+use rune_core::macros::{call, list, rebind, root};
+use rune_macros::defun;
+use std::time::{Duration, Instant};
+
+defsym!(CPU);
+defsym!(MEM);
+defsym!(CPU_MEM, "cpu+mem");
+defsym!(BENCHMARK_RUN);
+
+/// The special forms for benchmarking. The interpreter registers them before
+/// it looks up its first special form.
+pub(crate) const SPECIAL_FORMS: [(Symbol<'static>, SpecialForm); 1] =
+    [(sym::BENCHMARK_RUN, benchmark_run)];
+
+/// Start profiling. MODE is `cpu` (the default) to record how often each
+/// function is called and how long it takes, `mem` to record allocations, or
+/// `cpu+mem` for both. Anything recorded before is discarded.
+#[defun]
+fn profiler_start(mode: Option<Symbol>) -> Result<bool> {
+    match mode.unwrap_or(sym::CPU) {
+        sym::CPU => profiler::start_calls(),
+        sym::MEM => profiler::start(),
+        sym::CPU_MEM => {
+            profiler::start_calls();
+            profiler::start();
+        }
+        mode => bail!("Invalid profiler mode: {mode}"),
+    }
+    Ok(true)
+}
+
+/// Stop all profilers. Returns nil if none were running.
+#[defun]
+fn profiler_stop() -> bool {
+    let calls = profiler::stop_calls();
+    let memory = profiler::stop();
+    calls || memory
+}
+
+/// Return the call tree recorded by the cpu profiler. Each function is
+/// `(FUNCTION CALLS TOTAL SELF CALLEES)`, where TOTAL is the time in seconds
+/// spent in the function including the functions it called, SELF is the time
+/// spent in the function itself, and CALLEES are the functions it called in
+/// the same format. Functions are sorted by TOTAL, largest first. Only calls
+/// that have returned are included. Use `memory-profiler-report` for the
+/// allocations.
+#[defun]
+fn profiler_report<'ob>(cx: &'ob Context) -> Object<'ob> {
+    call_tree(&profiler::call_report(), cx)
+}
+
+/// Call FUNC with no arguments REPETITIONS times, once by default. Return a
+/// list of the seconds that took, the number of garbage collections, and the
+/// seconds spent collecting garbage. If REPETITIONS is a float, FUNC is called
+/// until at least that many seconds have passed, and the number of calls is
+/// added to the front of the list.
+#[defun]
+fn benchmark_call<'ob>(
+    func: &Rto<Function>,
+    repetitions: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let (repetitions, min_time) = match repetitions.map(|x| x.untag(cx)) {
+        None | Some(ObjectType::NIL) => (1, None),
+        Some(ObjectType::Int(n)) => (n.max(1), None),
+        Some(ObjectType::Float(f)) => {
+            (1, Some(Duration::try_from_secs_f64(**f).unwrap_or_default()))
+        }
+        Some(x) => bail!(TypeError::new(Type::Number, x)),
+    };
+    let gc_start = *cx.gc_stats();
+    let start = Instant::now();
+    let mut calls: i64 = 0;
+    while calls < repetitions || min_time.is_some_and(|x| start.elapsed() < x) {
+        call!(func; env, cx)?;
+        calls += 1;
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    let gc_end = *cx.gc_stats();
+    let gcs = gc_end.collections - gc_start.collections;
+    let gc_elapsed = (gc_end.total_pause - gc_start.total_pause).as_secs_f64();
+    let cx: &'ob Context = cx;
+    let result = list![cx.add(elapsed), gcs, cx.add(gc_elapsed); cx];
+    match min_time {
+        Some(_) => Ok(Cons::new(calls, result, cx).into()),
+        None => Ok(result),
+    }
+}
+
+/// `(benchmark-run [REPETITIONS] FORMS...)`: Time FORMS with
+/// [`benchmark_call`]. REPETITIONS is evaluated if it is a natural number or a
+/// symbol. Otherwise it is the first of FORMS, which run once.
+fn benchmark_run<'ob>(
+    interp: &mut Interpreter<'_, '_>,
+    forms: &Rto<Object>,
+    cx: &'ob mut Context,
+) -> EvalResult<'ob> {
+    let (repetitions, body) = match forms.untag(cx) {
+        ObjectType::Cons(cons) => match cons.car().untag() {
+            ObjectType::Int(n) if n >= 0 => (cons.car(), cons.cdr()),
+            ObjectType::Symbol(sym) if sym != sym::NIL => (cons.car(), cons.cdr()),
+            _ => (Object::from(1), forms.bind(cx)),
+        },
+        _ => (Object::from(1), NIL),
+    };
+    let lambda = Cons::new(sym::LAMBDA, Cons::new(NIL, body, cx), cx);
+    let function = list![sym::FUNCTION, lambda; cx];
+    root!(repetitions, cx);
+    root!(function, cx);
+    let func: Function = rebind!(interp.eval_form(function, cx)?).try_into()?;
+    root!(func, cx);
+    let repetitions = rebind!(interp.eval_form(repetitions, cx)?);
+    root!(repetitions, cx);
+    Ok(benchmark_call(func, Some(repetitions), interp.env(), cx)?)
+}
+
+fn call_tree<'ob>(profiles: &[profiler::CallProfile], cx: &'ob Context) -> Object<'ob> {
+    let mut tree = NIL;
+    for profile in profiles.iter().rev() {
+        let name = intern(&profile.name, cx);
+        let total = cx.add(profile.total.as_secs_f64());
+        let self_time = cx.add(profile.self_time.as_secs_f64());
+        let callees = call_tree(&profile.children, cx);
+        let entry = list![name, profile.calls, total, self_time, callees; cx];
+        tree = Cons::new(entry, tree, cx).into();
+    }
+    tree
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::{env::Env, gc::RootSet};
+    use rune_core::macros::root;
+
+    #[test]
+    fn test_profiler_report() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        let src = "(progn (defalias 'profiler-test-inner #'(lambda () 1))
+                          (defalias 'profiler-test-outer
+                            #'(lambda () (profiler-test-inner) (profiler-test-inner)))
+                          (profiler-start)
+                          (profiler-test-outer)
+                          (profiler-stop)
+                          (let ((outer (assq 'profiler-test-outer (profiler-report))))
+                            (equal (list (nth 1 outer)
+                                         (nth 1 (assq 'profiler-test-inner (nth 4 outer))))
+                                   '(1 2))))";
+        let obj = crate::reader::read(src, cx).unwrap().0;
+        root!(obj, cx);
+        assert!(crate::interpreter::eval(obj, None, env, cx).unwrap() != NIL);
+        assert!(profiler_start(Some(intern("bogus", cx))).is_err());
+    }
+
+    #[test]
+    fn test_benchmark() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        let tests = [
+            "(let* ((n 0) (result (benchmark-call #'(lambda () (setq n (1+ n))) 3)))
+               (and (= n 3) (= (length result) 3)
+                    (floatp (nth 0 result)) (integerp (nth 1 result)) (floatp (nth 2 result))))",
+            "(let ((result (benchmark-call #'(lambda () nil) 0.001)))
+               (and (= (length result) 4) (>= (car result) 1) (>= (nth 1 result) 0.001)))",
+            "(let ((n 0)) (benchmark-run 4 (setq n (1+ n))) (= n 4))",
+            "(let ((n 0) (times 2)) (benchmark-run times (setq n (1+ n))) (= n 2))",
+            "(let ((n 0)) (benchmark-run (setq n (1+ n)) (setq n (1+ n))) (= n 2))",
+        ];
+        for src in tests {
+            let obj = crate::reader::read(src, cx).unwrap().0;
+            root!(obj, cx);
+            assert!(crate::interpreter::eval(obj, None, env, cx).unwrap() != NIL, "{src}");
+        }
+        let obj = crate::reader::read("(benchmark-call #'(lambda () nil) 'x)", cx).unwrap().0;
+        root!(obj, cx);
+        assert!(crate::interpreter::eval(obj, None, env, cx).is_err());
+    }
+}
@@ -1,8 +1,9 @@
 //! Lisp reader that reads an object from a string.
 use crate::core::{
+    cons::Cons,
     env::{intern, sym},
     gc::Context,
-    object::{Object, Symbol},
+    object::{parse_float, Object, ObjectType, Symbol, MAX_FIXNUM, MIN_FIXNUM},
 };
 use crate::fns;
 use rune_core::macros::list;
@@ -12,6 +13,10 @@ use std::{fmt, iter::Peekable, str::CharIndices};
 
 type Result<T> = std::result::Result<T, Error>;
 
+/// How deeply lists, vectors and quotes can nest. Reading recurses for each
+/// level, so this keeps malformed input from overflowing the stack.
+const MAX_DEPTH: usize = 512;
+
 /// Errors that can occur during reading a sexp from a string
 #[derive(PartialEq, Debug, Copy, Clone)]
 pub(crate) enum Error {
@@ -24,8 +29,10 @@ pub(crate) enum Error {
     ExtraCloseBracket(usize),
     UnexpectedChar(char, usize),
     UnknownMacroCharacter(char, usize),
+    UnreadableObject(usize),
     ParseInt(u8, usize),
     MalformedUnicdoe(usize),
+    NestingTooDeep(usize),
     EmptyStream,
 }
 
@@ -39,6 +46,7 @@ impl Display for Error {
             Error::ExtraCloseBracket(i) => write!(f, "Extra Closing brace: at {i}"),
             Error::UnexpectedChar(chr, i) => write!(f, "Unexpected character {chr}: at {i}"),
             Error::MalformedUnicdoe(i) => write!(f, "Malformed unicode: at {i}"),
+            Error::NestingTooDeep(i) => write!(f, "Nesting too deep: at {i}"),
             Error::EmptyStream => write!(f, "Empty Stream"),
             Error::ExtraItemInCdr(i) => write!(f, "Extra item in cdr: at {i}"),
             Error::MissingQuotedItem(i) => write!(f, "Missing element after quote: at {i}"),
@@ -48,6 +56,7 @@ impl Display for Error {
             Error::UnknownMacroCharacter(chr, i) => {
                 write!(f, "Unkown reader macro character {chr}: at {i}")
             }
+            Error::UnreadableObject(i) => write!(f, "Unreadable object #<...>: at {i}"),
         }
     }
 }
@@ -66,8 +75,10 @@ impl Error {
             | Error::ExtraItemInCdr(x)
             | Error::UnexpectedChar(_, x)
             | Error::MalformedUnicdoe(x)
+            | Error::NestingTooDeep(x)
             | Error::ParseInt(_, x)
-            | Error::UnknownMacroCharacter(_, x) => *x,
+            | Error::UnknownMacroCharacter(_, x)
+            | Error::UnreadableObject(x) => *x,
             Error::EmptyStream => 0,
         }
     }
@@ -79,11 +90,13 @@ impl Error {
             | Error::MissingStringDel(i)
             | Error::UnexpectedChar(_, i)
             | Error::MalformedUnicdoe(i)
+            | Error::NestingTooDeep(i)
             | Error::ExtraItemInCdr(i)
             | Error::ExtraCloseParen(i)
             | Error::ExtraCloseBracket(i)
             | Error::MissingQuotedItem(i)
             | Error::UnknownMacroCharacter(_, i)
+            | Error::UnreadableObject(i)
             | Error::ParseInt(_, i) => Some(i),
             Error::EmptyStream => None,
         }
@@ -325,18 +338,49 @@ fn intern_symbol<'ob>(symbol: &str, cx: &'ob Context) -> Symbol<'ob> {
     }
 }
 
+const fn is_fixnum(num: i64) -> bool {
+    MIN_FIXNUM <= num && num <= MAX_FIXNUM
+}
+
 /// Parse a symbol from a string. This will either by a true symbol or a number
-/// literal.
+/// literal. There are no bignums, so integers too large to be a fixnum are
+/// read as floats.
 fn parse_symbol<'a>(slice: &str, cx: &'a Context) -> Object<'a> {
-    match slice.parse::<i64>() {
-        Ok(num) => cx.add(num),
-        Err(_) => match slice.parse::<f64>() {
-            Ok(num) => cx.add(num),
-            Err(_) => cx.add(intern_symbol(slice, cx)),
+    // An integer can end with a `.`
+    match slice.strip_suffix('.').unwrap_or(slice).parse::<i64>() {
+        Ok(num) if is_fixnum(num) => cx.add(num),
+        _ => match parse_float(slice) {
+            Some(num) => cx.add(num),
+            None => cx.add(intern_symbol(slice, cx)),
         },
     }
 }
 
+/// Parse the digits of an integer in `radix`, which may start with a sign.
+/// Like [`parse_symbol`], integers too large to be a fixnum are read as
+/// floats.
+fn parse_radix<'a>(digits: &str, radix: u8, cx: &'a Context) -> Option<Object<'a>> {
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, digits.strip_prefix('+').unwrap_or(digits)),
+    };
+    if digits.is_empty() {
+        return None;
+    }
+    let mut int = Some(0_i64);
+    let mut float = 0.0;
+    for chr in digits.chars() {
+        let digit = chr.to_digit(radix.into())?;
+        int = int.and_then(|x| x.checked_mul(radix.into())?.checked_add(digit.into()));
+        float = float * f64::from(radix) + f64::from(digit);
+    }
+    let int = int.map(|x| if negative { -x } else { x });
+    match int {
+        Some(int) if is_fixnum(int) => Some(cx.add(int)),
+        _ => Some(cx.add(if negative { -float } else { float })),
+    }
+}
+
 /// process escape characters in the string slice and return the resulting
 /// string.
 fn unescape_string<'a>(string: &str, cx: &'a Context) -> Object<'a> {
@@ -359,6 +403,12 @@ fn unescape_string<'a>(string: &str, cx: &'a Context) -> Object<'a> {
             Some(c)
         }
     };
+    #[cfg(feature = "intern-literals")]
+    if !string.contains('\\') {
+        if let Some(literal) = crate::core::object::LiteralStr::intern(string) {
+            return cx.add(literal);
+        }
+    }
     let mut new = cx.string_with_capacity(string.len());
     for c in string.chars().filter_map(unescape) {
         new.push(c);
@@ -389,6 +439,10 @@ struct Reader<'a, 'ob> {
     tokens: Tokenizer<'a>,
     /// New objects are allocated in the context.
     cx: &'ob Context<'ob>,
+    /// The lists that were read, and their offsets, when positions are kept
+    positions: Option<Vec<(&'ob Cons, usize)>>,
+    /// How many sexps are being read, including the current one
+    depth: usize,
 }
 
 impl<'a, 'ob> Reader<'a, 'ob> {
@@ -416,7 +470,10 @@ impl<'a, 'ob> Reader<'a, 'ob> {
         let mut objects = Vec::new();
         while let Some(token) = self.tokens.next() {
             match token {
-                Token::CloseParen(_) => return Ok(fns::slice_into_list(&objects, None, self.cx)),
+                Token::CloseParen(_) => {
+                    let list = fns::slice_into_list(&objects, None, self.cx);
+                    return Ok(self.record_position(list, delim));
+                }
                 Token::Ident(".") => {
                     let cdr = self.read_cdr(delim)?;
                     if cdr.is_none() {
@@ -430,6 +487,17 @@ impl<'a, 'ob> Reader<'a, 'ob> {
         Err(Error::MissingCloseParen(delim))
     }
 
+    /// Keep the position of `list` if it starts with a symbol, which makes it
+    /// a form that could be evaluated.
+    fn record_position(&mut self, list: Object<'ob>, delim: usize) -> Object<'ob> {
+        if let (Some(positions), ObjectType::Cons(cons)) = (&mut self.positions, list.untag()) {
+            if let ObjectType::Symbol(_) = cons.car().untag() {
+                positions.push((cons, delim));
+            }
+        }
+        list
+    }
+
     fn read_vec(&mut self, delim: usize) -> Result<Object<'ob>> {
         let mut objects = self.cx.vec_new();
         while let Some(token) = self.tokens.next() {
@@ -453,10 +521,9 @@ impl<'a, 'ob> Reader<'a, 'ob> {
     /// Read number with specificed radix
     fn read_radix(&mut self, pos: usize, radix: u8) -> Result<Object<'ob>> {
         match self.tokens.next() {
-            Some(Token::Ident(ident)) => match usize::from_str_radix(ident, radix.into()) {
-                Ok(x) => Ok(self.cx.add(x as i64)),
-                Err(_) => Err(Error::ParseInt(radix, pos)),
-            },
+            Some(Token::Ident(ident)) => {
+                parse_radix(ident, radix, self.cx).ok_or(Error::ParseInt(radix, pos))
+            }
             _ => Err(Error::ParseInt(radix, pos)),
         }
     }
@@ -468,6 +535,14 @@ impl<'a, 'ob> Reader<'a, 'ob> {
             Some('\'') => match self.tokens.next() {
                 Some(Token::OpenParen(i)) => {
                     let list = self.read_list(i)?;
+                    // A quoted function is not a form, only its body is
+                    if let (Some(positions), ObjectType::Cons(lambda)) =
+                        (&mut self.positions, list.untag())
+                    {
+                        if positions.last().is_some_and(|(cons, _)| std::ptr::eq(*cons, lambda)) {
+                            positions.pop();
+                        }
+                    }
                     Ok(list!(sym::FUNCTION, list; self.cx))
                 }
                 Some(token) => {
@@ -479,12 +554,24 @@ impl<'a, 'ob> Reader<'a, 'ob> {
             Some('b') => self.read_radix(pos, 2),
             Some('o') => self.read_radix(pos, 8),
             Some('x') => self.read_radix(pos, 16),
+            // printed representation of opaque objects like `#<subr car>`
+            Some('<') => Err(Error::UnreadableObject(pos)),
             Some(chr) => Err(Error::UnknownMacroCharacter(chr, pos)),
             None => Err(Error::MissingQuotedItem(pos)),
         }
     }
 
     fn read_sexp(&mut self, token: Token<'a>) -> Result<Object<'ob>> {
+        if self.depth == MAX_DEPTH {
+            return Err(Error::NestingTooDeep(self.tokens.relative_pos(token)));
+        }
+        self.depth += 1;
+        let obj = self.read_token(token);
+        self.depth -= 1;
+        obj
+    }
+
+    fn read_token(&mut self, token: Token<'a>) -> Result<Object<'ob>> {
         match token {
             Token::OpenParen(i) => self.read_list(i),
             Token::CloseParen(i) => Err(Error::ExtraCloseParen(i)),
@@ -506,16 +593,35 @@ impl<'a, 'ob> Reader<'a, 'ob> {
 /// read a lisp object from `slice`. Return the object and index of next
 /// remaining character in the slice.
 pub(crate) fn read<'ob>(slice: &str, cx: &'ob Context) -> Result<(Object<'ob>, usize)> {
-    let mut reader = Reader { tokens: Tokenizer::new(slice), cx };
+    let mut reader = Reader { tokens: Tokenizer::new(slice), cx, positions: None, depth: 0 };
     match reader.tokens.next() {
         Some(t) => reader.read_sexp(t).map(|x| (x, reader.tokens.cur_pos())),
         None => Err(Error::EmptyStream),
     }
 }
 
+/// Like [`read`], but also return every list read that starts with a symbol,
+/// along with the offset of its open paren in `slice`. Those are the lists
+/// that can be evaluated as forms.
+pub(crate) fn read_with_positions<'ob>(
+    slice: &str,
+    cx: &'ob Context,
+) -> Result<(Object<'ob>, usize, Vec<(&'ob Cons, usize)>)> {
+    let tokens = Tokenizer::new(slice);
+    let mut reader = Reader { tokens, cx, positions: Some(Vec::new()), depth: 0 };
+    match reader.tokens.next() {
+        Some(t) => {
+            let obj = reader.read_sexp(t)?;
+            let end = reader.tokens.cur_pos();
+            Ok((obj, end, reader.positions.unwrap_or_default()))
+        }
+        None => Err(Error::EmptyStream),
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::core::{cons::Cons, gc::RootSet};
+    use crate::core::{cons::Cons, gc::RootSet, object::ObjectType};
 
     use super::*;
 
@@ -559,6 +665,18 @@ mod test {
         check_reader!(0x1, "#x001", cx);
         check_reader!(0x10, "#x10", cx);
         check_reader!(0xdead_beef_i64, "#xDeAdBeEf", cx);
+        check_reader!(-16, "#x-10", cx);
+        check_reader!(1e20, "100000000000000000000", cx);
+        check_reader!(-1e20, "-100000000000000000000", cx);
+        check_reader!(MAX_FIXNUM, &MAX_FIXNUM.to_string(), cx);
+        check_reader!(i64::MAX as f64, &i64::MAX.to_string(), cx);
+        check_reader!(2.0_f64.powi(64), "#x10000000000000000", cx);
+        check_reader!(1, "1.", cx);
+        check_reader!(1500.0, "1.5e3", cx);
+        check_reader!(f64::INFINITY, "1.0e+INF", cx);
+        check_reader!(f64::NEG_INFINITY, "-1.0e+INF", cx);
+        check_reader!(intern("inf", cx), "inf", cx);
+        check_reader!(intern("nan", cx), "nan", cx);
     }
 
     #[test]
@@ -614,6 +732,39 @@ mod test {
 baz""#,
             cx
         );
+        // strings are mutable, so equal literals are never shared
+        let (obj, _) = read(r#"("foo" "foo")"#, cx).unwrap();
+        let ObjectType::Cons(cons) = obj.untag() else { unreachable!() };
+        let ObjectType::Cons(rest) = cons.cdr().untag() else { unreachable!() };
+        let second = rest.car();
+        assert_eq!(cons.car(), second);
+        assert!(!cons.car().ptr_eq(second));
+    }
+
+    #[test]
+    #[cfg(feature = "intern-literals")]
+    fn test_intern_literals() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        use rune_core::macros::root;
+        let texts = |obj: Object| -> Vec<usize> {
+            let text = |x: Object| match x.untag() {
+                ObjectType::String(s) => s.as_ptr() as usize,
+                _ => unreachable!(),
+            };
+            obj.as_list().unwrap().map(|x| text(x.unwrap())).collect()
+        };
+        let (obj, _) = read(r#"("foo" "foo" "f\oo")"#, cx).unwrap();
+        let before = texts(obj);
+        // equal literals are separate objects with the same text
+        assert_eq!(before[0], before[1]);
+        assert_ne!(before[0], before[2]);
+        let ObjectType::Cons(cons) = obj.untag() else { unreachable!() };
+        assert!(!cons.car().ptr_eq(cons.cdr().as_cons().car()));
+        // the text is not moved by the collector
+        root!(obj, cx);
+        cx.garbage_collect(true);
+        assert_eq!(texts(obj.bind(cx))[..2], before[..2]);
     }
 
     #[test]
@@ -661,6 +812,8 @@ baz""#,
         assert_error("#", Error::MissingQuotedItem(0), cx);
         assert_error("#'", Error::MissingQuotedItem(0), cx);
         assert_error("#a", Error::UnknownMacroCharacter('a', 0), cx);
+        assert_error("#<subr car>", Error::UnreadableObject(0), cx);
+        assert_error(" (1 #<buffer foo>)", Error::UnreadableObject(4), cx);
     }
 
     #[test]
@@ -695,6 +848,34 @@ baz""#,
         assert_error(" '", Error::MissingQuotedItem(1), cx);
         assert_error(" )", Error::ExtraCloseParen(1), cx);
         assert_error("(1 . #o9 3)", Error::ParseInt(8, 5), cx);
+        assert_error("#x", Error::ParseInt(16, 0), cx);
+        let deep = format!("{}1{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert_error(&deep, Error::NestingTooDeep(MAX_DEPTH), cx);
+        let deep = format!("{}1", "'".repeat(MAX_DEPTH + 1));
+        assert_error(&deep, Error::NestingTooDeep(MAX_DEPTH), cx);
+        let nested = format!("{}1{}", "[".repeat(MAX_DEPTH - 1), "]".repeat(MAX_DEPTH - 1));
+        assert!(read(&nested, cx).is_ok());
+    }
+
+    /// Read random sequences of the characters that mean something to the
+    /// reader. Malformed input has to be an error, not a panic.
+    #[test]
+    #[allow(clippy::non_ascii_literal)]
+    fn read_random() {
+        use rand::{Rng, SeedableRng};
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let pieces = [
+            "(", ")", "[", "]", "'", "`", ",", ",@", "#", "#'", "#x", "#b", "#<", "?", "?\\",
+            "?\\u", "\"", "\\", ".", ";", "\n", " ", "-", "+", "1", "9", "e", "a", "é",
+            "99999999999999999999",
+        ];
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        for _ in 0..10_000 {
+            let len = rng.gen_range(1..20);
+            let input: String = (0..len).map(|_| pieces[rng.gen_range(0..pieces.len())]).collect();
+            let _ = read(&input, cx);
+        }
     }
 
     #[test]
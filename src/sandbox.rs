@@ -0,0 +1,268 @@
+//! Evaluating untrusted code against a checkpoint of the environment.
+//!
+//! Before the code runs, the variables, symbol properties and functions are
+//! saved. Afterwards they are either kept, or put back the way they were so
+//! nothing the code did is visible. Buffers and the global block are not
+//! checkpointed, so text inserted into a buffer stays even when the changes
+//! are discarded.
+//!
+//! While sandboxed code is running, calls to functions that touch files or
+//! the process are errors, and so is allocating more than the memory limit.
+//! The code can also be given a budget of bytecode instructions and
+//! interpreted forms, which comes out of the
+//! [fuel](crate::bytecode::set_fuel) of any enclosing sandbox. Once it runs
+//! out every step signals until the sandbox returns.
+use crate::core::{
+    env::{interned_symbols, Env},
+    gc::{Context, Rt, Rto, Slot},
+    object::{Function, FunctionType, Object, Symbol, WithLifetime},
+};
+use crate::{bytecode, interpreter};
+use anyhow::{bail, Result};
+use rune_core::hashmap::HashMap;
+use rune_core::macros::{rebind, root};
+use rune_macros::defun;
+use std::cell::Cell;
+
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct Limits {
+    /// Allow the builtins marked with `#[defun(io)]` to be called
+    pub(crate) allow_io: bool,
+    /// The most bytes the code can have allocated at once
+    pub(crate) max_bytes: Option<usize>,
+    /// The most bytecode instructions and interpreted forms the code can run
+    pub(crate) fuel: Option<usize>,
+}
+
+#[derive(Copy, Clone)]
+struct Active {
+    limits: Limits,
+    start_bytes: usize,
+}
+
+thread_local! {
+    /// The limits of the innermost sandbox on this thread.
+    static ACTIVE: Cell<Option<Active>> = const { Cell::new(None) };
+}
+
+/// Check that `func` can be called under the current limits.
+#[inline]
+pub(crate) fn check_call(func: Function, cx: &Context) -> Result<()> {
+    let Some(active) = ACTIVE.get() else { return Ok(()) };
+    if let FunctionType::SubrFn(f) = func.untag() {
+        let denied = !active.limits.allow_io && f.io;
+        // Sandboxed code can't lift its own limit
+        if denied || f.name == "set-bytecode-fuel" {
+            bail!("Calling `{}' is not allowed in a sandbox", f.name);
+        }
+    }
+    if let Some(max) = active.limits.max_bytes {
+        let used = cx.allocated_bytes().saturating_sub(active.start_bytes);
+        if used > max {
+            bail!("Sandbox memory limit of {max} bytes exceeded");
+        }
+    }
+    Ok(())
+}
+
+type Vars<'a> = Vec<(Slot<Symbol<'a>>, Slot<Object<'a>>)>;
+type Props<'a> = Vec<(Slot<Symbol<'a>>, (Slot<Symbol<'a>>, Slot<Object<'a>>))>;
+/// Interned symbols and their functions live in the global block, which is
+/// never collected, so they don't need to be rooted.
+type Functions = Vec<(Symbol<'static>, Option<Function<'static>>)>;
+
+fn save(vars: &mut Rt<Vars>, props: &mut Rt<Props>, env: &Rt<Env>, cx: &Context) -> Functions {
+    for (symbol, value) in env.vars.iter() {
+        vars.push((symbol.bind(cx), value.bind(cx)));
+    }
+    for (symbol, plist) in env.props.iter() {
+        for prop in plist.iter() {
+            props.push((symbol.bind(cx), (prop.0.bind(cx), prop.1.bind(cx))));
+        }
+    }
+    let map = interned_symbols();
+    let functions = map.symbols(cx).into_iter().map(|symbol| (symbol, symbol.func(cx)));
+    // SAFETY: See `Functions`
+    functions.map(|x| unsafe { x.with_lifetime() }).collect()
+}
+
+fn restore(
+    vars: &Rt<Vars>,
+    props: &Rt<Props>,
+    functions: &Functions,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let current: Vec<Symbol> = env.vars.iter().map(|x| x.0.bind(cx)).collect();
+    for symbol in current {
+        env.vars.remove(symbol);
+    }
+    for var in vars.iter() {
+        env.vars.insert(var.0.bind(cx), var.1.bind(cx));
+    }
+    let current: Vec<Symbol> = env.props.iter().map(|x| x.0.bind(cx)).collect();
+    for symbol in current {
+        env.props.remove(symbol);
+    }
+    for prop in props.iter() {
+        env.set_prop(prop.0.bind(cx), prop.1 .0.bind(cx), prop.1 .1.bind(cx));
+    }
+    let saved: HashMap<Symbol, Option<Function>> = functions.iter().copied().collect();
+    let map = interned_symbols();
+    for symbol in map.symbols(cx) {
+        let func = symbol.func(cx);
+        match saved.get(&symbol).copied().flatten() {
+            Some(saved) if func != Some(saved) => map.set_func(symbol, saved)?,
+            // Interned while sandboxed, or only defined there
+            None if func.is_some() => symbol.unbind_func(),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Evaluate `form` under `limits`. Unless `commit` is true, any changes to
+/// variables, symbol properties and functions are discarded afterwards, even
+/// if evaluation signals an error.
+pub(crate) fn eval<'ob>(
+    form: &Rto<Object>,
+    limits: Limits,
+    commit: bool,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    root!(vars, new(Vars), cx);
+    root!(props, new(Props), cx);
+    let functions = if commit { Vec::new() } else { save(vars, props, env, cx) };
+    let active = Active { limits, start_bytes: cx.allocated_bytes() };
+    let outer = ACTIVE.replace(Some(active));
+    let outer_fuel = bytecode::remaining_fuel();
+    let outer_sticky = bytecode::sticky_fuel();
+    let fuel = match (limits.fuel, outer_fuel) {
+        (Some(fuel), Some(outer)) => Some(fuel.min(outer)),
+        (fuel, outer) => fuel.or(outer),
+    };
+    // The fuel of a sandbox stays used up until it returns, so a handler
+    // inside it can't keep running
+    bytecode::set_fuel(fuel, outer_sticky || limits.fuel.is_some());
+    let result = interpreter::eval(form, None, env, cx);
+    ACTIVE.set(outer);
+    // What this sandbox used also comes out of the enclosing limit
+    let outer_left = match (fuel, bytecode::remaining_fuel()) {
+        // The enclosing limit ran out and was removed
+        (Some(_), None) => None,
+        (start, left) => {
+            let used = start.zip(left).map_or(0, |(start, left)| start.saturating_sub(left));
+            outer_fuel.map(|x| x.saturating_sub(used))
+        }
+    };
+    bytecode::set_fuel(outer_left, outer_sticky);
+    let value = match result {
+        Ok(value) => rebind!(value, cx),
+        Err(e) => {
+            if !commit {
+                restore(vars, props, &functions, env, cx)?;
+            }
+            return Err(e);
+        }
+    };
+    if !commit {
+        restore(vars, props, &functions, env, cx)?;
+    }
+    Ok(value)
+}
+
+/// Evaluate FORM in a sandbox and return its value. Files can't be read or
+/// written. If MAX-BYTES is non-nil it is the most memory the evaluation can
+/// use, and if FUEL is non-nil it is the most bytecode instructions and
+/// interpreted forms it can run before `excessive-eval` is signaled. Changes to variables, symbol
+/// properties and functions are thrown away afterwards, unless COMMIT is
+/// non-nil.
+#[defun]
+fn sandbox_eval<'ob>(
+    form: &Rto<Object>,
+    commit: Option<()>,
+    max_bytes: Option<usize>,
+    fuel: Option<usize>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let limits = Limits { allow_io: false, max_bytes, fuel };
+    eval(form, limits, commit.is_some(), env, cx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::{
+        env::{intern, sym},
+        gc::RootSet,
+    };
+    use crate::reader;
+
+    fn sandboxed(
+        src: &str,
+        limits: Limits,
+        commit: bool,
+        env: &mut Rt<Env>,
+        cx: &mut Context,
+    ) -> Result<String> {
+        let (form, _) = reader::read(src, cx).unwrap();
+        root!(form, cx);
+        Ok(eval(form, limits, commit, env, cx)?.to_string())
+    }
+
+    #[test]
+    fn test_discard() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        let src = "(progn (setq sandbox-test-var 1)
+                          (put 'sandbox-test-var 'sandbox-test-prop 2)
+                          (defalias 'sandbox-test-fn #'(lambda () 3))
+                          (+ sandbox-test-var (sandbox-test-fn)))";
+        assert_eq!(sandboxed(src, Limits::default(), false, env, cx).unwrap(), "4");
+        let var = intern("sandbox-test-var", cx);
+        assert!(env.vars.get(var).is_none());
+        assert!(env.props.get(var).is_none());
+        assert!(intern("sandbox-test-fn", cx).func(cx).is_none());
+
+        assert_eq!(sandboxed(src, Limits::default(), true, env, cx).unwrap(), "4");
+        let var = intern("sandbox-test-var", cx);
+        assert_eq!(env.vars.get(var).unwrap().bind(cx), 1);
+        let func = intern("sandbox-test-fn", cx).func(cx).unwrap().to_string();
+
+        let src = "(progn (setq sandbox-test-var 5)
+                          (defalias 'sandbox-test-fn #'(lambda () 6))
+                          (car 1))";
+        assert!(sandboxed(src, Limits::default(), false, env, cx).is_err());
+        let var = intern("sandbox-test-var", cx);
+        assert_eq!(env.vars.get(var).unwrap().bind(cx), 1);
+        assert_eq!(intern("sandbox-test-fn", cx).func(cx).unwrap().to_string(), func);
+    }
+
+    #[test]
+    fn test_limits() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        let err = sandboxed("(load \"init.el\")", Limits::default(), false, env, cx).unwrap_err();
+        assert!(err.to_string().contains("not allowed in a sandbox"), "{err}");
+        let src = "(memory-report \"/tmp/sandbox-test.dot\")";
+        assert!(sandboxed(src, Limits::default(), false, env, cx).is_err());
+
+        let src = "(list (make-vector 10000 nil) (make-vector 10000 nil))";
+        let limits = Limits { max_bytes: Some(1000), ..Limits::default() };
+        assert!(sandboxed(src, limits, false, env, cx).is_err());
+        assert!(sandboxed(src, Limits::default(), false, env, cx).is_ok());
+
+        // Interpreted code uses fuel too, and a handler can't outlast it
+        let limits = Limits { fuel: Some(100), ..Limits::default() };
+        assert!(sandboxed("(while t)", limits, false, env, cx).is_err());
+        let src = "(while t (condition-case nil (while t) (excessive-eval nil)))";
+        assert!(sandboxed(src, limits, false, env, cx).is_err());
+        assert_eq!(bytecode::remaining_fuel(), None);
+    }
+}
@@ -0,0 +1,369 @@
+//! Native versions of the `seq.el` functions.
+//!
+//! The functions here only see a sequence through the [`Sequence`] trait, so
+//! a new kind of sequence works with all of them once it implements the trait
+//! and [`as_sequence`] returns it. Functions that keep the type of their
+//! argument, like `seq-take`, build the result with [`Sequence::build`]. The
+//! rest return lists, like `seq.el` does. Bool-vectors are not implemented
+//! yet, so they are not sequences here.
+//!
+//! [Streams](crate::stream) are handled separately, because forcing them calls
+//! lisp.
+use crate::core::{
+    env::Env,
+    error::{Type, TypeError},
+    gc::{Context, Rt, Rto, Slot},
+    object::{ByteString, Function, LispString, LispVec, List, ListType, Object, ObjectType},
+};
+use crate::fns::{equal, slice_into_list};
+use crate::stream;
+use anyhow::Result;
+use rune_core::macros::{call, root};
+use rune_macros::defun;
+
+/// A lisp sequence.
+pub(crate) trait Sequence<'ob> {
+    /// The elements, in order.
+    fn elements(&self) -> Result<Vec<Object<'ob>>>;
+
+    /// A new sequence of the same type that holds `elements`.
+    fn build(&self, elements: &[Object<'ob>], cx: &'ob Context) -> Result<Object<'ob>>;
+
+    /// The first `n` elements, as a sequence of the same type.
+    fn take(&self, n: usize, cx: &'ob Context) -> Result<Object<'ob>> {
+        let elements = self.elements()?;
+        self.build(&elements[..n.min(elements.len())], cx)
+    }
+
+    /// All but the first `n` elements, as a sequence of the same type.
+    fn skip(&self, n: usize, cx: &'ob Context) -> Result<Object<'ob>> {
+        let elements = self.elements()?;
+        self.build(&elements[n.min(elements.len())..], cx)
+    }
+}
+
+impl<'ob> Sequence<'ob> for List<'ob> {
+    fn elements(&self) -> Result<Vec<Object<'ob>>> {
+        Ok((*self).elements().collect::<Result<_, _>>()?)
+    }
+
+    fn build(&self, elements: &[Object<'ob>], cx: &'ob Context) -> Result<Object<'ob>> {
+        Ok(slice_into_list(elements, None, cx))
+    }
+
+    /// The rest of a list shares structure with it, like `nthcdr`.
+    fn skip(&self, n: usize, _: &'ob Context) -> Result<Object<'ob>> {
+        let mut tail: Object = (*self).into();
+        for _ in 0..n {
+            match tail.untag() {
+                ObjectType::Cons(cons) => tail = cons.cdr(),
+                _ => break,
+            }
+        }
+        Ok(tail)
+    }
+}
+
+impl<'ob> Sequence<'ob> for &'ob LispVec {
+    fn elements(&self) -> Result<Vec<Object<'ob>>> {
+        let vec: &'ob LispVec = self;
+        Ok(vec.to_vec())
+    }
+
+    fn build(&self, elements: &[Object<'ob>], cx: &'ob Context) -> Result<Object<'ob>> {
+        Ok(cx.add(elements.to_vec()))
+    }
+}
+
+fn to_char(obj: Object) -> Result<char, TypeError> {
+    let c = match obj.untag() {
+        ObjectType::Int(c) => u32::try_from(c).ok().and_then(char::from_u32),
+        _ => None,
+    };
+    c.ok_or_else(|| TypeError::new(Type::Char, obj))
+}
+
+impl<'ob> Sequence<'ob> for &'ob LispString {
+    fn elements(&self) -> Result<Vec<Object<'ob>>> {
+        Ok(self.chars().map(|c| i64::from(u32::from(c)).into()).collect())
+    }
+
+    fn build(&self, elements: &[Object<'ob>], cx: &'ob Context) -> Result<Object<'ob>> {
+        let string: String = elements.iter().map(|x| to_char(*x)).collect::<Result<_, _>>()?;
+        Ok(cx.add(string))
+    }
+}
+
+impl<'ob> Sequence<'ob> for &'ob ByteString {
+    fn elements(&self) -> Result<Vec<Object<'ob>>> {
+        Ok(self.inner().iter().map(|b| i64::from(*b).into()).collect())
+    }
+
+    fn build(&self, elements: &[Object<'ob>], cx: &'ob Context) -> Result<Object<'ob>> {
+        let byte = |x: &Object| match x.untag() {
+            ObjectType::Int(b) => u8::try_from(b).map_err(|_| TypeError::new(Type::Char, *x)),
+            _ => Err(TypeError::new(Type::Char, *x)),
+        };
+        let bytes: Vec<u8> = elements.iter().map(byte).collect::<Result<_, _>>()?;
+        Ok(cx.add(bytes))
+    }
+}
+
+/// The sequence in `obj`, or an error if it isn't one.
+pub(crate) fn as_sequence<'ob>(obj: Object<'ob>) -> Result<Box<dyn Sequence<'ob> + 'ob>> {
+    match obj.untag() {
+        ObjectType::NIL => Ok(Box::new(ListType::empty())),
+        ObjectType::Cons(_) => Ok(Box::new(List::try_from(obj)?)),
+        ObjectType::Vec(vec) => Ok(Box::new(vec)),
+        ObjectType::String(s) => Ok(Box::new(s)),
+        ObjectType::ByteString(s) => Ok(Box::new(s)),
+        other => Err(TypeError::new(Type::Sequence, other).into()),
+    }
+}
+
+/// Push the elements of `sequence` onto `elems`. A stream is forced to its
+/// end.
+fn push_elements(
+    sequence: &Rto<Object>,
+    elems: &mut Rt<Vec<Slot<Object>>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    if stream::is_stream(sequence.bind(cx)) {
+        stream::take(sequence, usize::MAX, elems, env, cx)?;
+    } else {
+        elems.extend_from_slice(&as_sequence(sequence.bind(cx))?.elements()?);
+    }
+    Ok(())
+}
+
+/// Sort `elems` with `predicate`. This is a merge sort, so elements that
+/// are equal keep their order.
+fn merge_sort(
+    elems: &mut Rt<Vec<Slot<Object>>>,
+    predicate: &Rto<Function>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let len = elems.len();
+    root!(merged, new(Vec<Slot<Object>>), cx);
+    merged.extend_from_slice(Rt::bind_slice(elems, cx));
+    let mut width = 1;
+    while width < len {
+        for start in (0..len).step_by(2 * width) {
+            let mid = (start + width).min(len);
+            let end = (start + 2 * width).min(len);
+            let (mut left, mut right) = (start, mid);
+            for i in start..end {
+                // Only take from the right if it is strictly less
+                let take_right = right < end
+                    && (left == mid
+                        || !call!(predicate, &elems[right], &elems[left]; env, cx)?.is_nil());
+                let next = if take_right { &mut right } else { &mut left };
+                let elem = elems[*next].bind(cx);
+                merged[i].set(elem);
+                *next += 1;
+            }
+        }
+        for i in 0..len {
+            let elem = merged[i].bind(cx);
+            elems[i].set(elem);
+        }
+        width *= 2;
+    }
+    Ok(())
+}
+
+/// Return a list of the results of calling FUNCTION on each element of
+/// SEQUENCE.
+#[defun]
+fn seq_map<'ob>(
+    function: &Rto<Function>,
+    sequence: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    root!(elems, new(Vec<Slot<Object>>), cx);
+    push_elements(sequence, elems, env, cx)?;
+    root!(outputs, new(Vec<Slot<Object>>), cx);
+    for i in 0..elems.len() {
+        let output = call!(function, &elems[i]; env, cx)?;
+        outputs.push(output);
+    }
+    Ok(slice_into_list(Rt::bind_slice(outputs, cx), None, cx))
+}
+
+/// Return a list of the elements of SEQUENCE for which PRED is non-nil.
+#[defun]
+fn seq_filter<'ob>(
+    pred: &Rto<Function>,
+    sequence: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    root!(elems, new(Vec<Slot<Object>>), cx);
+    push_elements(sequence, elems, env, cx)?;
+    root!(kept, new(Vec<Slot<Object>>), cx);
+    for i in 0..elems.len() {
+        if !call!(pred, &elems[i]; env, cx)?.is_nil() {
+            kept.push(&elems[i]);
+        }
+    }
+    Ok(slice_into_list(Rt::bind_slice(kept, cx), None, cx))
+}
+
+/// Combine the elements of SEQUENCE from the left by calling FUNCTION with
+/// the result so far and the next element. The first result is
+/// INITIAL-VALUE, which is returned if SEQUENCE is empty.
+#[defun]
+fn seq_reduce<'ob>(
+    function: &Rto<Function>,
+    sequence: &Rto<Object>,
+    initial_value: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    root!(elems, new(Vec<Slot<Object>>), cx);
+    push_elements(sequence, elems, env, cx)?;
+    root!(acc, initial_value.bind(cx), cx);
+    for i in 0..elems.len() {
+        let result = call!(function, &*acc, &elems[i]; env, cx)?;
+        acc.set(result);
+    }
+    Ok(acc.bind(cx))
+}
+
+/// Return the first N elements of SEQUENCE, as a sequence of the same type.
+/// If N is zero or negative the result is empty.
+#[defun]
+fn seq_take<'ob>(
+    sequence: &Rto<Object>,
+    n: i64,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let n = usize::try_from(n).unwrap_or(0);
+    if stream::is_stream(sequence.bind(cx)) {
+        root!(elems, new(Vec<Slot<Object>>), cx);
+        stream::take(sequence, n, elems, env, cx)?;
+        return Ok(stream::from_elements(Rt::bind_slice(elems, cx), cx));
+    }
+    as_sequence(sequence.bind(cx))?.take(n, cx)
+}
+
+/// Return SEQUENCE without its first N elements, as a sequence of the same
+/// type. If N is zero or negative SEQUENCE is returned. The rest of a list
+/// is shared with it.
+#[defun]
+fn seq_drop<'ob>(
+    sequence: &Rto<Object>,
+    n: i64,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    match usize::try_from(n) {
+        Ok(n) if n > 0 && stream::is_stream(sequence.bind(cx)) => {
+            root!(elems, new(Vec<Slot<Object>>), cx);
+            stream::take(sequence, n, elems, env, cx)
+        }
+        Ok(n) if n > 0 => as_sequence(sequence.bind(cx))?.skip(n, cx),
+        _ => Ok(sequence.bind(cx)),
+    }
+}
+
+/// Return a list of the elements of SEQUENCE with duplicates removed. The
+/// first of each set of duplicates is kept. Elements are compared by calling
+/// TESTFN with the new element and an earlier one, or with `equal` if TESTFN
+/// is nil.
+#[defun]
+fn seq_uniq<'ob>(
+    sequence: &Rto<Object>,
+    testfn: Option<&Rto<Function>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    root!(elems, new(Vec<Slot<Object>>), cx);
+    push_elements(sequence, elems, env, cx)?;
+    root!(uniq, new(Vec<Slot<Object>>), cx);
+    'elems: for i in 0..elems.len() {
+        for j in 0..uniq.len() {
+            let duplicate = match testfn {
+                Some(testfn) => !call!(testfn, &elems[i], &uniq[j]; env, cx)?.is_nil(),
+                None => equal(elems[i].bind(cx), uniq[j].bind(cx)),
+            };
+            if duplicate {
+                continue 'elems;
+            }
+        }
+        uniq.push(&elems[i]);
+    }
+    Ok(slice_into_list(Rt::bind_slice(uniq, cx), None, cx))
+}
+
+/// Return a sorted copy of SEQUENCE, of the same type. PRED is called with
+/// two elements and returns non-nil if the first one should sort before the
+/// second. The sort is stable.
+#[defun]
+fn seq_sort<'ob>(
+    pred: &Rto<Function>,
+    sequence: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    root!(elems, new(Vec<Slot<Object>>), cx);
+    push_elements(sequence, elems, env, cx)?;
+    merge_sort(elems, pred, env, cx)?;
+    if stream::is_stream(sequence.bind(cx)) {
+        return Ok(stream::from_elements(Rt::bind_slice(elems, cx), cx));
+    }
+    as_sequence(sequence.bind(cx))?.build(Rt::bind_slice(elems, cx), cx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::{env::sym, gc::RootSet};
+    use crate::interpreter::eval_to_string;
+
+    #[test]
+    fn test_sequence() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let list = crate::fns::slice_into_list(&[1.into(), 2.into(), 3.into()], None, cx);
+        let string = cx.add("abc");
+        let vec = cx.add(vec![1.into(), 2.into()]);
+        assert_eq!(as_sequence(list).unwrap().take(2, cx).unwrap().to_string(), "(1 2)");
+        assert_eq!(as_sequence(list).unwrap().skip(1, cx).unwrap().to_string(), "(2 3)");
+        assert_eq!(as_sequence(string).unwrap().skip(1, cx).unwrap().to_string(), "\"bc\"");
+        assert_eq!(as_sequence(vec).unwrap().take(5, cx).unwrap().to_string(), "[1 2]");
+        let bytes = as_sequence(cx.add(vec![1u8, 2])).unwrap();
+        assert!(bytes.build(&[256.into()], cx).is_err());
+        assert!(as_sequence(cx.add(1)).is_err());
+    }
+
+    #[test]
+    fn test_seq_functions() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        assert_eq!(eval_to_string("(seq-map #'1+ [1 2 3])", env, cx).unwrap(), "(2 3 4)");
+        let src = "(seq-filter #'(lambda (c) (> c ?a)) \"abc\")";
+        assert_eq!(eval_to_string(src, env, cx).unwrap(), "(98 99)");
+        assert_eq!(eval_to_string("(seq-reduce #'+ '(1 2 3) 10)", env, cx).unwrap(), "16");
+        assert_eq!(eval_to_string("(seq-reduce #'+ nil 10)", env, cx).unwrap(), "10");
+        assert_eq!(eval_to_string("(seq-take \"abc\" 2)", env, cx).unwrap(), "\"ab\"");
+        assert_eq!(eval_to_string("(seq-take [1 2] -1)", env, cx).unwrap(), "[]");
+        assert_eq!(eval_to_string("(seq-drop '(1 2 3) 2)", env, cx).unwrap(), "(3)");
+        assert_eq!(eval_to_string("(seq-drop [1 2 3] 0)", env, cx).unwrap(), "[1 2 3]");
+        let src = "(seq-uniq '(1 \"a\" 1 \"a\" 2))";
+        assert_eq!(eval_to_string(src, env, cx).unwrap(), "(1 \"a\" 2)");
+        let src = "(seq-uniq '(1 2 3) #'(lambda (a b) (= (% a 2) (% b 2))))";
+        assert_eq!(eval_to_string(src, env, cx).unwrap(), "(1 2)");
+        assert_eq!(eval_to_string("(seq-sort #'< [3 1 2 5 4])", env, cx).unwrap(), "[1 2 3 4 5]");
+        assert_eq!(eval_to_string("(seq-sort #'> \"abc\")", env, cx).unwrap(), "\"cba\"");
+        let src = "(seq-sort #'(lambda (a b) (< (car a) (car b)))
+                             '((2 . a) (1 . b) (2 . c) (1 . d)))";
+        assert_eq!(eval_to_string(src, env, cx).unwrap(), "((1 . b) (1 . d) (2 . a) (2 . c))");
+    }
+}
@@ -0,0 +1,218 @@
+//! Lazy streams.
+//!
+//! A stream is a record of type `stream` that holds a thunk. The first time a
+//! stream is forced the thunk is called, and what it returned is kept for
+//! every later force: nil for an empty stream, or a cons of the first element
+//! and the rest of the stream. `stream-cons` delays both of its arguments, so
+//! a stream can be infinite as long as only part of it is forced.
+//!
+//! The seq functions accept streams. `seq-take` and `seq-drop` only force the
+//! elements they need, and the rest force the whole stream. `stream-cons` is a
+//! special form, so its arguments are only delayed in interpreted code.
+use crate::core::{
+    cons::Cons,
+    env::{sym, Env},
+    error::{ArgError, Type, TypeError},
+    gc::{Context, Rt, Rto, Slot},
+    object::{Function, Object, ObjectType, Record, RecordBuilder, Symbol, NIL},
+};
+use crate::eval::EvalResult;
+use crate::interpreter::{Interpreter, SpecialForm};
+use crate::seq::as_sequence;
+use anyhow::{ensure, Result};
+use rune_core::macros::{bail_err, call, list, rebind, root};
+use rune_macros::defun;
+
+defsym!(STREAM);
+defsym!(STREAM_CONS);
+
+/// The slot with the thunk, which is nil once the stream is forced.
+const THUNK: usize = 1;
+/// The slot with the value of the thunk.
+const VALUE: usize = 2;
+
+/// The special forms for streams. The interpreter registers them before it
+/// looks up its first special form.
+pub(crate) const SPECIAL_FORMS: [(Symbol<'static>, SpecialForm); 1] =
+    [(sym::STREAM_CONS, stream_cons)];
+
+fn make<'ob>(thunk: Object<'ob>, value: Object<'ob>, cx: &'ob Context) -> Object<'ob> {
+    let mut record = cx.vec_with_capacity(3);
+    record.extend_from_slice(&[sym::STREAM.into(), thunk, value]);
+    cx.add(RecordBuilder(record))
+}
+
+fn as_stream(obj: Object) -> Result<&Record, TypeError> {
+    match obj.untag() {
+        ObjectType::Record(record) if record.len() == 3 && record[0].get() == sym::STREAM => {
+            Ok(record)
+        }
+        _ => Err(TypeError::new(Type::Stream, obj)),
+    }
+}
+
+pub(crate) fn is_stream(obj: Object) -> bool {
+    as_stream(obj).is_ok()
+}
+
+/// A stream that has already been forced and holds `elements`.
+pub(crate) fn from_elements<'ob>(elements: &[Object<'ob>], cx: &'ob Context) -> Object<'ob> {
+    let mut stream = make(NIL, NIL, cx);
+    for elem in elements.iter().rev() {
+        stream = make(NIL, Cons::new(*elem, stream, cx).into(), cx);
+    }
+    stream
+}
+
+/// Force `stream`. Return nil if it is empty, or else a cons of its first
+/// element and the rest of it.
+pub(crate) fn force<'ob>(
+    stream: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let record = as_stream(stream.bind(cx))?;
+    let thunk = record[THUNK].get();
+    if thunk.is_nil() {
+        let value = record[VALUE].get();
+        return Ok(rebind!(value, cx));
+    }
+    let thunk: Function = thunk.try_into()?;
+    root!(thunk, cx);
+    let value = rebind!(call!(thunk; env, cx)?, cx);
+    let valid = match value.untag() {
+        ObjectType::NIL => true,
+        ObjectType::Cons(cons) => is_stream(cons.cdr()),
+        _ => false,
+    };
+    ensure!(valid, "Stream thunk returned {value}, which is not nil or a cons of a stream");
+    let record = as_stream(stream.bind(cx))?.try_mut()?;
+    record[VALUE].set(value);
+    record[THUNK].set(NIL);
+    Ok(value)
+}
+
+/// Force up to `n` elements of `stream` and push them onto `elems`. Return the
+/// rest of the stream.
+pub(crate) fn take<'ob>(
+    stream: &Rto<Object>,
+    n: usize,
+    elems: &mut Rt<Vec<Slot<Object>>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    root!(tail, stream.bind(cx), cx);
+    for _ in 0..n {
+        let ObjectType::Cons(cons) = force(tail, env, cx)?.untag() else { break };
+        elems.push(cons.car());
+        tail.set(cons.cdr());
+    }
+    Ok(tail.bind(cx))
+}
+
+fn stream_cons<'ob>(
+    interp: &mut Interpreter<'_, '_>,
+    forms: &Rto<Object>,
+    cx: &'ob mut Context,
+) -> EvalResult<'ob> {
+    // (stream-cons FIRST REST)
+    let forms: Vec<Object> = forms.bind(cx).as_list()?.collect::<Result<_, _>>()?;
+    let &[first, rest] = &forms[..] else {
+        bail_err!(ArgError::new(2, forms.len() as u16, "stream-cons"))
+    };
+    let lambda = list![sym::LAMBDA, NIL, list![sym::CONS, first, rest; cx]; cx];
+    let form = list![sym::FUNCTION, lambda; cx];
+    root!(form, cx);
+    let thunk = rebind!(interp.eval_form(form, cx)?);
+    Ok(make(thunk, NIL, cx))
+}
+
+/// Return t if OBJECT is a stream.
+#[defun]
+fn streamp(object: Object) -> bool {
+    is_stream(object)
+}
+
+/// Return a new empty stream.
+#[defun]
+fn stream_empty<'ob>(cx: &'ob Context) -> Object<'ob> {
+    make(NIL, NIL, cx)
+}
+
+/// Return a stream of the elements of SEQUENCE. Streams are returned
+/// unchanged.
+#[defun]
+fn stream<'ob>(sequence: Object<'ob>, cx: &'ob Context) -> Result<Object<'ob>> {
+    if is_stream(sequence) {
+        return Ok(sequence);
+    }
+    Ok(from_elements(&as_sequence(sequence)?.elements()?, cx))
+}
+
+/// Return t if STREAM has no elements.
+#[defun]
+fn stream_empty_p(stream: &Rto<Object>, env: &mut Rt<Env>, cx: &mut Context) -> Result<bool> {
+    Ok(force(stream, env, cx)?.is_nil())
+}
+
+/// Return the first element of STREAM, or nil if it is empty.
+#[defun]
+fn stream_car<'ob>(
+    stream: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    match force(stream, env, cx)?.untag() {
+        ObjectType::Cons(cons) => Ok(cons.car()),
+        _ => Ok(NIL),
+    }
+}
+
+/// Return STREAM without its first element. The rest of an empty stream is
+/// an empty stream.
+#[defun]
+fn stream_cdr<'ob>(
+    stream: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let value = rebind!(force(stream, env, cx)?);
+    match value.untag() {
+        ObjectType::Cons(cons) => Ok(cons.cdr()),
+        _ => Ok(stream.bind(cx)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::interpreter::eval_to_string;
+
+    #[test]
+    fn test_stream() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        eval_to_string("(setq stream-test-count 0)", env, cx).unwrap();
+        let src = "(defalias 'stream-test-from
+                     #'(lambda (n)
+                         (stream-cons (progn (setq stream-test-count (1+ stream-test-count)) n)
+                                      (stream-test-from (1+ n)))))";
+        eval_to_string(src, env, cx).unwrap();
+        eval_to_string("(setq stream-test (stream-test-from 1))", env, cx).unwrap();
+        assert_eq!(eval_to_string("stream-test-count", env, cx).unwrap(), "0");
+        assert_eq!(eval_to_string("(stream-car (stream-cdr stream-test))", env, cx).unwrap(), "2");
+        assert_eq!(eval_to_string("(stream-car (stream-cdr stream-test))", env, cx).unwrap(), "2");
+        // Forced elements are not computed again
+        assert_eq!(eval_to_string("stream-test-count", env, cx).unwrap(), "2");
+        let src = "(seq-map #'identity (seq-take (seq-drop stream-test 10) 3))";
+        assert_eq!(eval_to_string(src, env, cx).unwrap(), "(11 12 13)");
+        assert_eq!(eval_to_string("(streamp (seq-take stream-test 3))", env, cx).unwrap(), "t");
+        assert_eq!(eval_to_string("(seq-reduce #'+ (stream [1 2 3]) 0)", env, cx).unwrap(), "6");
+        let src = "(stream-empty-p (stream-cdr (stream '(1))))";
+        assert_eq!(eval_to_string(src, env, cx).unwrap(), "t");
+        assert_eq!(eval_to_string("(stream-car (stream-empty))", env, cx).unwrap(), "nil");
+    }
+}
@@ -1,14 +1,286 @@
 //! Multi-threaded elisp support.
+//!
+//! Threads run in parallel, each with its own heap and environment. The symbol
+//! table is shared, so functions defined in one thread can be called from any
+//! other, but variable bindings are local to a thread. Objects are copied when
+//! they cross between threads: the function passed to `make-thread` is cloned
+//! into the new heap, and its result is copied back out as a [`Value`] when it
+//! is joined.
+//!
+//! Threads, mutexes, and condition variables are records holding an id into a
+//! global table, so every copy of one refers to the same object. The id is also
+//! attached to the record, so lisp can't make one or change its id. Entries are
+//! never removed from the tables.
+use crate::alloc::{make_opaque_record, opaque_record_id};
+use crate::api::{Error, Value};
 use crate::core::{
-    env::Env,
-    gc::{Block, Context, RootSet},
-    object::{CloneIn, Object},
+    env::{sym, Env},
+    gc::{Block, Context, RootSet, Rt},
+    object::{CloneIn, Function, Gc, LispString, Object, ObjectType, Symbol, NIL},
 };
-use rune_core::macros::root;
+use crate::eval::EvalError;
+use anyhow::{bail, ensure, Result};
+use rune_core::hashmap::HashMap;
+use rune_core::macros::{call, list, root};
 use rune_macros::defun;
-use std::thread::{self, JoinHandle};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock};
+use std::thread::{self, JoinHandle, ThreadId};
+
+struct LispThread {
+    handle: Option<JoinHandle<Result<Value, Error>>>,
+    /// The result, once the thread has been joined
+    result: Option<Result<Value, Error>>,
+}
+
+#[derive(Default)]
+struct MutexState {
+    owner: Option<ThreadId>,
+    /// Number of times the owner has locked the mutex
+    count: usize,
+}
+
+/// A recursive mutex. Lisp locks and unlocks it in separate calls, so it
+/// can't be held as a guard.
+#[derive(Default)]
+struct LispMutex {
+    state: Mutex<MutexState>,
+    /// Notified when the mutex becomes free
+    unlocked: Condvar,
+}
+
+impl LispMutex {
+    fn lock_state(&self) -> MutexGuard<'_, MutexState> {
+        self.state.lock().unwrap()
+    }
+
+    /// Wait until the mutex is free or already held by this thread.
+    fn acquire<'a>(&self, state: MutexGuard<'a, MutexState>) -> MutexGuard<'a, MutexState> {
+        let me = thread::current().id();
+        let held_by_other = |s: &mut MutexState| s.owner.is_some_and(|x| x != me);
+        let mut state = self.unlocked.wait_while(state, held_by_other).unwrap();
+        state.owner = Some(me);
+        state
+    }
+
+    /// Lock the state, checking that this thread owns the mutex.
+    fn owned_state(&self) -> Result<MutexGuard<'_, MutexState>> {
+        let state = self.lock_state();
+        ensure!(
+            state.owner == Some(thread::current().id()),
+            "Mutex is not locked by the current thread"
+        );
+        Ok(state)
+    }
+}
+
+struct ConditionVariable {
+    mutex: Arc<LispMutex>,
+    cond: Condvar,
+}
+
+#[derive(Default)]
+struct Tables {
+    threads: HashMap<i64, Arc<Mutex<LispThread>>>,
+    mutexes: HashMap<i64, Arc<LispMutex>>,
+    conditions: HashMap<i64, Arc<ConditionVariable>>,
+}
+
+fn tables() -> MutexGuard<'static, Tables> {
+    static TABLES: OnceLock<Mutex<Tables>> = OnceLock::new();
+    TABLES.get_or_init(Mutex::default).lock().unwrap()
+}
+
+/// The id of `obj` if it is a record of type `kind`.
+fn record_id(obj: Object, kind: Symbol) -> Option<i64> {
+    let ObjectType::Record(record) = obj.untag() else { return None };
+    opaque_record_id(record, kind)
+}
+
+/// Look up `obj` in `table`, signaling `wrong-type-argument` with `predicate`
+/// if it is not there.
+fn lookup<T>(
+    obj: Object,
+    kind: Symbol,
+    predicate: Symbol,
+    table: impl FnOnce(&Tables) -> &HashMap<i64, Arc<T>>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<Arc<T>> {
+    let found = record_id(obj, kind).and_then(|id| table(&tables()).get(&id).cloned());
+    match found {
+        Some(x) => Ok(x),
+        None => {
+            let data = list![predicate, obj; cx];
+            Err(EvalError::signal(sym::WRONG_TYPE_ARGUMENT.into(), data, env).into())
+        }
+    }
+}
+
+fn get_mutex(obj: Object, env: &mut Rt<Env>, cx: &Context) -> Result<Arc<LispMutex>> {
+    lookup(obj, sym::MUTEX, sym::MUTEXP, |x| &x.mutexes, env, cx)
+}
+
+fn get_condition(obj: Object, env: &mut Rt<Env>, cx: &Context) -> Result<Arc<ConditionVariable>> {
+    lookup(obj, sym::CONDITION_VARIABLE, sym::CONDITION_VARIABLE_P, |x| &x.conditions, env, cx)
+}
+
+/// Start a thread that calls FUNCTION with no arguments. NAME is only for
+/// display.
+#[defun(io)]
+fn make_thread<'ob>(
+    function: Object,
+    name: Option<Gc<&'ob LispString>>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    Function::try_from(function)?;
+    let block = Block::new_local_unchecked();
+    let raw = function.clone_in(&block).into_raw();
+    let handle = thread::spawn(move || {
+        let roots = &RootSet::default();
+        let cx = &mut Context::from_block(block, roots);
+        root!(env, new(Env), cx);
+        let func = unsafe { Object::from_raw(raw) };
+        let func: Function = func.try_into().expect("thread function was checked");
+        root!(func, cx);
+        match call!(func; env, cx) {
+            Ok(value) => Value::from_object(value),
+            Err(e) => Err(Error::from_runtime(e, env, cx)),
+        }
+    });
+    let (record, id) = make_opaque_record(sym::THREAD, name.map_or(NIL, Into::into), cx);
+    let thread = LispThread { handle: Some(handle), result: None };
+    tables().threads.insert(id, Arc::new(Mutex::new(thread)));
+    Ok(record)
+}
+
+/// Wait for THREAD to finish and return the value of its function. If the
+/// function signaled an error, the same error is signaled here.
+#[defun]
+fn thread_join<'ob>(thread: Object, env: &mut Rt<Env>, cx: &'ob Context) -> Result<Object<'ob>> {
+    let thread = lookup(thread, sym::THREAD, sym::THREADP, |x| &x.threads, env, cx)?;
+    let mut state = thread.lock().unwrap();
+    if let Some(handle) = state.handle.take() {
+        if handle.thread().id() == thread::current().id() {
+            state.handle = Some(handle);
+            bail!("Cannot join the current thread");
+        }
+        let result = handle.join();
+        let result = result.unwrap_or_else(|_| Err(Error::Other("Thread panicked".to_owned())));
+        state.result = Some(result);
+    }
+    match state.result.clone().expect("joined thread had no result") {
+        Ok(value) => Ok(value.to_object(cx)?),
+        Err(e) => Err(e.into_runtime(env, cx)),
+    }
+}
+
+/// Let other threads run.
+#[defun]
+fn thread_yield() {
+    thread::yield_now();
+}
+
+#[defun]
+fn threadp(object: Object) -> bool {
+    record_id(object, sym::THREAD).is_some()
+}
+
+/// Make a mutex. A thread can lock a mutex it already holds, and must unlock
+/// it as many times.
+#[defun]
+fn make_mutex<'ob>(name: Option<Gc<&'ob LispString>>, cx: &'ob Context) -> Object<'ob> {
+    let (record, id) = make_opaque_record(sym::MUTEX, name.map_or(NIL, Into::into), cx);
+    tables().mutexes.insert(id, Arc::default());
+    record
+}
+
+/// Lock MUTEX, waiting for another thread to release it if needed.
+#[defun]
+fn mutex_lock(mutex: Object, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let mutex = get_mutex(mutex, env, cx)?;
+    mutex.acquire(mutex.lock_state()).count += 1;
+    Ok(())
+}
 
 #[defun]
+fn mutex_unlock(mutex: Object, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let mutex = get_mutex(mutex, env, cx)?;
+    let mut state = mutex.owned_state()?;
+    state.count -= 1;
+    if state.count == 0 {
+        state.owner = None;
+        mutex.unlocked.notify_one();
+    }
+    Ok(())
+}
+
+#[defun]
+fn mutexp(object: Object) -> bool {
+    record_id(object, sym::MUTEX).is_some()
+}
+
+/// Make a condition variable associated with MUTEX.
+#[defun]
+fn make_condition_variable<'ob>(
+    mutex: Object,
+    name: Option<Gc<&'ob LispString>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let mutex = get_mutex(mutex, env, cx)?;
+    let (record, id) =
+        make_opaque_record(sym::CONDITION_VARIABLE, name.map_or(NIL, Into::into), cx);
+    let cond = ConditionVariable { mutex, cond: Condvar::new() };
+    tables().conditions.insert(id, Arc::new(cond));
+    Ok(record)
+}
+
+/// Release the mutex of COND and wait to be notified, then lock the mutex
+/// again. The mutex must be held by the current thread. This can return
+/// without being notified, so the condition should be checked in a loop.
+#[defun]
+fn condition_wait(cond: Object, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let cond = get_condition(cond, env, cx)?;
+    let mutex = &cond.mutex;
+    let mut state = mutex.owned_state()?;
+    let count = state.count;
+    state.owner = None;
+    state.count = 0;
+    mutex.unlocked.notify_one();
+    let state = cond.cond.wait(state).unwrap();
+    mutex.acquire(state).count = count;
+    Ok(())
+}
+
+/// Wake a thread waiting on COND, or every waiting thread if ALL is non-nil.
+/// The mutex of COND must be held by the current thread.
+#[defun]
+fn condition_notify(
+    cond: Object,
+    all: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let cond = get_condition(cond, env, cx)?;
+    let _state = cond.mutex.owned_state()?;
+    if all.is_some() {
+        cond.cond.notify_all();
+    } else {
+        cond.cond.notify_one();
+    }
+    Ok(())
+}
+
+#[defun]
+fn condition_variable_p(object: Object) -> bool {
+    record_id(object, sym::CONDITION_VARIABLE).is_some()
+}
+
+defsym!(THREAD);
+defsym!(MUTEX);
+defsym!(CONDITION_VARIABLE);
+
+#[defun(io)]
 fn go(obj: Object) {
     go_internal(obj);
 }
@@ -31,6 +303,58 @@ fn go_internal(obj: Object) -> JoinHandle<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::interpreter::eval_to_string;
+
+    #[test]
+    fn test_thread_join() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        let join = "(let ((th (make-thread (lambda () (list 1 \"two\" 3.0)))))
+                      (and (threadp th) (equal (thread-join th) '(1 \"two\" 3.0))
+                           (equal (thread-join th) '(1 \"two\" 3.0))))";
+        assert_eq!(eval_to_string(join, env, cx).unwrap(), "t");
+        let signal = "(condition-case err
+                          (thread-join (make-thread (lambda () (signal 'thread-error '(1 2)))))
+                        (error (equal err '(thread-error 1 2))))";
+        assert_eq!(eval_to_string(signal, env, cx).unwrap(), "t");
+        assert!(eval_to_string("(thread-join 1)", env, cx).is_err());
+        assert!(eval_to_string("(make-thread 1)", env, cx).is_err());
+    }
+
+    #[test]
+    fn test_mutex() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        let recursive = "(let ((m (make-mutex)))
+                           (mutex-lock m) (mutex-lock m) (mutex-unlock m) (mutex-unlock m)
+                           (mutexp m))";
+        assert_eq!(eval_to_string(recursive, env, cx).unwrap(), "t");
+        assert!(eval_to_string("(mutex-unlock (make-mutex))", env, cx).is_err());
+        assert!(eval_to_string("(mutex-lock 'm)", env, cx).is_err());
+        // The new thread can only take the mutex once the main thread waits
+        // on the condition, so the notification can't be missed
+        let wait = "(progn
+                      (setq m (make-mutex \"m\"))
+                      (setq c (make-condition-variable m))
+                      (mutex-lock m)
+                      (setq th (make-thread (list 'lambda nil
+                                                  (list 'mutex-lock m)
+                                                  (list 'condition-notify c)
+                                                  (list 'mutex-unlock m)
+                                                  7)))
+                      (condition-wait c)
+                      (mutex-unlock m)
+                      (and (condition-variable-p c) (eq (thread-join th) 7)))";
+        assert_eq!(eval_to_string(wait, env, cx).unwrap(), "t");
+        let obj = cx.add(7);
+        assert!(!threadp(obj) && !mutexp(obj) && !condition_variable_p(obj));
+        // Only the records made by make-mutex are mutexes
+        assert_eq!(eval_to_string("(mutexp (record 'mutex 0 nil))", env, cx).unwrap(), "nil");
+        let changed = "(let ((m (make-mutex))) (aset m 1 (1+ (aref m 1))) (mutexp m))";
+        assert_eq!(eval_to_string(changed, env, cx).unwrap(), "nil");
+    }
 
     #[test]
     fn test_go() {
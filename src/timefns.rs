@@ -1,29 +1,637 @@
 //! Time analysis
 use crate::core::{
+    cons::Cons,
     env::{sym, Env},
     gc::{Context, Rt},
-    object::Object,
+    object::{Object, ObjectType, NIL},
 };
+use anyhow::{bail, ensure, Result};
 use rune_core::macros::list;
 use rune_macros::defun;
+use std::sync::Mutex;
 use std::time::SystemTime;
 
 defvar!(CURRENT_TIME_LIST, true);
 
+defsym!(WALL);
+
+const PSECS_PER_SEC: i64 = 1_000_000_000_000;
+const SECS_PER_DAY: i64 = 86_400;
+
+/// A lisp timestamp normalized to whole seconds since the epoch and
+/// picoseconds within that second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct LispTime {
+    pub(crate) secs: i64,
+    pub(crate) psecs: i64,
+}
+
+impl LispTime {
+    pub(crate) fn new(secs: i64, psecs: i64) -> Self {
+        let secs = secs + psecs.div_euclid(PSECS_PER_SEC);
+        Self { secs, psecs: psecs.rem_euclid(PSECS_PER_SEC) }
+    }
+
+    fn now() -> Self {
+        let duration = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("System time is before the epoch");
+        Self::new(duration.as_secs() as i64, i64::from(duration.subsec_nanos()) * 1000)
+    }
+
+    /// Convert any of the lisp time formats. `nil` is the current time, a
+    /// number is seconds since the epoch, `(TICKS . HZ)` is a rational number
+    /// of seconds and `(HIGH LOW USEC PSEC)` is the format returned by
+    /// `current-time`.
+    pub(crate) fn from_obj(time: Option<Object>) -> Result<Self> {
+        let Some(time) = time else { return Ok(Self::now()) };
+        match time.untag() {
+            ObjectType::NIL => Ok(Self::now()),
+            ObjectType::Int(secs) => Ok(Self::new(secs, 0)),
+            ObjectType::Float(float) => {
+                let float = **float;
+                ensure!(float.is_finite(), "Invalid time specification: {float}");
+                let secs = float.floor();
+                Ok(Self::new(secs as i64, ((float - secs) * PSECS_PER_SEC as f64) as i64))
+            }
+            ObjectType::Cons(cons) => match (cons.car().untag(), cons.cdr().untag()) {
+                (ObjectType::Int(ticks), ObjectType::Int(hz)) => {
+                    ensure!(hz > 0, "Invalid time frequency: {hz}");
+                    let (ticks, hz) = (i128::from(ticks), i128::from(hz));
+                    let secs = ticks.div_euclid(hz);
+                    let psecs = ticks.rem_euclid(hz) * i128::from(PSECS_PER_SEC) / hz;
+                    Ok(Self::new(secs as i64, psecs as i64))
+                }
+                _ => {
+                    let mut parts = [0; 4];
+                    let mut len = 0;
+                    for elem in cons {
+                        let elem = elem?;
+                        let ObjectType::Int(part) = elem.untag() else {
+                            bail!("Invalid time specification: {time}")
+                        };
+                        ensure!(len < parts.len(), "Invalid time specification: {time}");
+                        parts[len] = part;
+                        len += 1;
+                    }
+                    ensure!(len >= 2, "Invalid time specification: {time}");
+                    let [high, low, usecs, psecs] = parts;
+                    Ok(Self::new((high << 16) + low, usecs * 1_000_000 + psecs))
+                }
+            },
+            _ => bail!("Invalid time specification: {time}"),
+        }
+    }
+
+    /// Return the time in the `(HIGH LOW USEC PSEC)` format.
+    pub(crate) fn into_obj(self, cx: &Context) -> Object {
+        let high = self.secs >> 16;
+        let low = self.secs & 0xffff;
+        let usecs = self.psecs / 1_000_000;
+        let psecs = self.psecs % 1_000_000;
+        list![high, low, usecs, psecs; cx]
+    }
+
+    fn as_float(self) -> f64 {
+        self.secs as f64 + (self.psecs as f64 / PSECS_PER_SEC as f64)
+    }
+}
+
+/// A time zone rule as accepted by the `zone` argument of the time functions.
+#[derive(Debug, Clone, PartialEq)]
+enum Zone {
+    Local,
+    Utc,
+    Fixed(i64, Option<String>),
+}
+
+impl Zone {
+    fn from_obj(zone: Option<Object>) -> Result<Self> {
+        let Some(zone) = zone else { return Ok(Zone::Local) };
+        match zone.untag() {
+            ObjectType::NIL => Ok(Zone::Local),
+            ObjectType::Symbol(sym::TRUE) => Ok(Zone::Utc),
+            ObjectType::Symbol(sym::WALL) => Ok(Zone::Local),
+            ObjectType::Int(offset) => Ok(Zone::Fixed(offset, None)),
+            ObjectType::String(name) => match &**name {
+                "UTC" | "UTC0" | "GMT" | "GMT0" => Ok(Zone::Utc),
+                // TODO: parse POSIX TZ strings
+                _ => bail!("Unsupported time zone rule: {name}"),
+            },
+            ObjectType::Cons(cons) => {
+                let ObjectType::Int(offset) = cons.car().untag() else {
+                    bail!("Invalid time zone specification: {zone}")
+                };
+                let abbr = match cons.cdr().untag() {
+                    ObjectType::Cons(abbr) => match abbr.car().untag() {
+                        ObjectType::String(abbr) => Some(String::from(&**abbr)),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                Ok(Zone::Fixed(offset, abbr))
+            }
+            _ => bail!("Invalid time zone specification: {zone}"),
+        }
+    }
+
+    /// Return the UTC offset in seconds, the abbreviation, and whether
+    /// daylight saving time is in effect at `secs`.
+    fn info(&self, secs: i64) -> (i64, String, bool) {
+        match self {
+            Zone::Utc => (0, "UTC".to_owned(), false),
+            Zone::Fixed(offset, Some(abbr)) => (*offset, abbr.clone(), false),
+            Zone::Fixed(offset, None) => (*offset, format_offset(*offset, 0), false),
+            Zone::Local => match &*LOCAL_ZONE.lock().unwrap() {
+                Some(zone) => zone.info(secs),
+                None => local_zone_info(secs),
+            },
+        }
+    }
+}
+
+/// The rule set by `set-time-zone-rule`, used in place of the system's local
+/// time zone. This is process-wide, like `TZ`.
+static LOCAL_ZONE: Mutex<Option<Zone>> = Mutex::new(None);
+
+/// Set the local time zone using TZ, a time zone rule. nil means the system's
+/// local time zone.
+#[defun]
+pub(crate) fn set_time_zone_rule(tz: Option<Object>) -> Result<bool> {
+    let zone = match Zone::from_obj(tz)? {
+        Zone::Local => None,
+        zone => Some(zone),
+    };
+    *LOCAL_ZONE.lock().unwrap() = zone;
+    Ok(false)
+}
+
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // time_t and c_long are not i64 on all targets
+fn local_zone_info(secs: i64) -> (i64, String, bool) {
+    let time = secs as libc::time_t;
+    let mut tm = unsafe { std::mem::zeroed::<libc::tm>() };
+    let result = unsafe { libc::localtime_r(&time, &mut tm) };
+    if result.is_null() {
+        return (0, "UTC".to_owned(), false);
+    }
+    let name = if tm.tm_zone.is_null() {
+        String::new()
+    } else {
+        unsafe { std::ffi::CStr::from_ptr(tm.tm_zone) }.to_string_lossy().into_owned()
+    };
+    (tm.tm_gmtoff as i64, name, tm.tm_isdst > 0)
+}
+
+#[cfg(not(unix))]
+fn local_zone_info(_secs: i64) -> (i64, String, bool) {
+    // TODO: query the local time zone on non-unix systems
+    (0, "UTC".to_owned(), false)
+}
+
+/// Format a UTC offset as `+hhmm`. `colons` controls the `%:z` variants.
+fn format_offset(offset: i64, colons: usize) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let offset = offset.abs();
+    let (hours, mins, secs) = (offset / 3600, offset / 60 % 60, offset % 60);
+    match colons {
+        0 => format!("{sign}{hours:02}{mins:02}"),
+        1 => format!("{sign}{hours:02}:{mins:02}"),
+        2 => format!("{sign}{hours:02}:{mins:02}:{secs:02}"),
+        _ if secs != 0 => format!("{sign}{hours:02}:{mins:02}:{secs:02}"),
+        _ if mins != 0 => format!("{sign}{hours:02}:{mins:02}"),
+        _ => format!("{sign}{hours:02}"),
+    }
+}
+
+/// Days since the epoch of the proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Proleptic Gregorian date of the days since the epoch.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+/// A time broken down into calendar fields for a particular zone.
+#[derive(Debug, Clone, PartialEq)]
+struct DecodedTime {
+    sec: i64,
+    min: i64,
+    hour: i64,
+    day: i64,
+    month: i64,
+    year: i64,
+    /// 0 is Sunday
+    weekday: i64,
+    /// 0 is January 1st
+    yday: i64,
+    dst: bool,
+    offset: i64,
+    zone: String,
+    psecs: i64,
+    epoch: i64,
+}
+
+impl DecodedTime {
+    fn new(time: LispTime, zone: &Zone) -> Self {
+        let (offset, zone, dst) = zone.info(time.secs);
+        let local = time.secs + offset;
+        let days = local.div_euclid(SECS_PER_DAY);
+        let secs = local.rem_euclid(SECS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        Self {
+            sec: secs % 60,
+            min: secs / 60 % 60,
+            hour: secs / 3600,
+            day,
+            month,
+            year,
+            // 1970-01-01 was a Thursday
+            weekday: (days + 4).rem_euclid(7),
+            yday: days - days_from_civil(year, 1, 1),
+            dst,
+            offset,
+            zone,
+            psecs: time.psecs,
+            epoch: time.secs,
+        }
+    }
+
+    /// The ISO 8601 week-based year and week number.
+    fn iso_week(&self) -> (i64, i64) {
+        fn weeks_in_year(year: i64) -> i64 {
+            let p = |y: i64| (y + y.div_euclid(4) - y.div_euclid(100) + y.div_euclid(400)) % 7;
+            if p(year) == 4 || p(year - 1) == 3 {
+                53
+            } else {
+                52
+            }
+        }
+        let monday_based = (self.weekday + 6) % 7;
+        let week = (self.yday - monday_based + 10) / 7;
+        if week < 1 {
+            (self.year - 1, weeks_in_year(self.year - 1))
+        } else if week > weeks_in_year(self.year) {
+            (self.year + 1, 1)
+        } else {
+            (self.year, week)
+        }
+    }
+}
+
+const DAY_NAMES: [&str; 7] =
+    ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+const MONTH_NAMES: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+enum Directive {
+    Num(i64, usize, char),
+    Text(String),
+}
+
+/// Expand the `%` directives of `format-time-string`.
+#[allow(clippy::too_many_lines)]
+fn format_time(format: &str, time: &DecodedTime) -> String {
+    let mut out = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(chr) = chars.next() {
+        if chr != '%' {
+            out.push(chr);
+            continue;
+        }
+        let mut flag = None;
+        while let Some(&c @ ('-' | '_' | '0' | '^' | '#')) = chars.peek() {
+            flag = Some(c);
+            chars.next();
+        }
+        let mut width = None;
+        while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+            width = Some(width.unwrap_or(0) * 10 + digit as usize);
+            chars.next();
+        }
+        // The E and O modifiers select alternate locale representations
+        if let Some('E' | 'O') = chars.peek() {
+            chars.next();
+        }
+        let mut colons = 0;
+        while let Some(':') = chars.peek() {
+            colons += 1;
+            chars.next();
+        }
+        let Some(conv) = chars.next() else {
+            out.push('%');
+            break;
+        };
+        let hour12 = if time.hour % 12 == 0 { 12 } else { time.hour % 12 };
+        let directive = match conv {
+            'Y' => Directive::Num(time.year, 4, '0'),
+            'C' => Directive::Num(time.year.div_euclid(100), 2, '0'),
+            'y' => Directive::Num(time.year.rem_euclid(100), 2, '0'),
+            'G' => Directive::Num(time.iso_week().0, 4, '0'),
+            'g' => Directive::Num(time.iso_week().0.rem_euclid(100), 2, '0'),
+            'V' => Directive::Num(time.iso_week().1, 2, '0'),
+            'm' => Directive::Num(time.month, 2, '0'),
+            'd' => Directive::Num(time.day, 2, '0'),
+            'e' => Directive::Num(time.day, 2, ' '),
+            'H' => Directive::Num(time.hour, 2, '0'),
+            'k' => Directive::Num(time.hour, 2, ' '),
+            'I' => Directive::Num(hour12, 2, '0'),
+            'l' => Directive::Num(hour12, 2, ' '),
+            'M' => Directive::Num(time.min, 2, '0'),
+            'S' => Directive::Num(time.sec, 2, '0'),
+            'j' => Directive::Num(time.yday + 1, 3, '0'),
+            'u' => Directive::Num((time.weekday + 6) % 7 + 1, 1, '0'),
+            'w' => Directive::Num(time.weekday, 1, '0'),
+            'U' => Directive::Num((time.yday + 7 - time.weekday) / 7, 2, '0'),
+            'W' => Directive::Num((time.yday + 7 - (time.weekday + 6) % 7) / 7, 2, '0'),
+            'q' => Directive::Num((time.month - 1) / 3 + 1, 1, '0'),
+            's' => Directive::Num(time.epoch, 1, '0'),
+            'N' => {
+                // The width is the number of digits of precision
+                let digits = width.take().unwrap_or(9).min(12);
+                let psecs = format!("{:012}", time.psecs);
+                Directive::Text(psecs[..digits].to_owned())
+            }
+            'A' => Directive::Text(DAY_NAMES[time.weekday as usize].to_owned()),
+            'a' => Directive::Text(DAY_NAMES[time.weekday as usize][..3].to_owned()),
+            'B' => Directive::Text(MONTH_NAMES[time.month as usize - 1].to_owned()),
+            'b' | 'h' => Directive::Text(MONTH_NAMES[time.month as usize - 1][..3].to_owned()),
+            'p' => Directive::Text(if time.hour < 12 { "AM" } else { "PM" }.to_owned()),
+            'P' => Directive::Text(if time.hour < 12 { "am" } else { "pm" }.to_owned()),
+            'Z' => Directive::Text(time.zone.clone()),
+            'z' => Directive::Text(format_offset(time.offset, colons)),
+            'c' => Directive::Text(format_time("%a %b %e %H:%M:%S %Y", time)),
+            'D' | 'x' => Directive::Text(format_time("%m/%d/%y", time)),
+            'F' => Directive::Text(format_time("%Y-%m-%d", time)),
+            'T' | 'X' => Directive::Text(format_time("%H:%M:%S", time)),
+            'R' => Directive::Text(format_time("%H:%M", time)),
+            'r' => Directive::Text(format_time("%I:%M:%S %p", time)),
+            'n' => Directive::Text("\n".to_owned()),
+            't' => Directive::Text("\t".to_owned()),
+            '%' => Directive::Text("%".to_owned()),
+            unknown => {
+                // Unknown directives are copied through unchanged
+                out.push('%');
+                out.push(unknown);
+                continue;
+            }
+        };
+        match directive {
+            Directive::Num(num, default_width, default_pad) => {
+                let (width, pad) = match flag {
+                    Some('-') => (1, default_pad),
+                    Some('_') => (width.unwrap_or(default_width), ' '),
+                    Some('0') => (width.unwrap_or(default_width), '0'),
+                    _ => (width.unwrap_or(default_width), default_pad),
+                };
+                let digits = num.unsigned_abs().to_string();
+                let sign = if num < 0 { "-" } else { "" };
+                let fill = width.saturating_sub(digits.len() + sign.len());
+                if pad == '0' {
+                    out.push_str(sign);
+                    out.push_str(&"0".repeat(fill));
+                } else {
+                    out.push_str(&" ".repeat(fill));
+                    out.push_str(sign);
+                }
+                out.push_str(&digits);
+            }
+            Directive::Text(text) => {
+                let text = match flag {
+                    Some('^') => text.to_uppercase(),
+                    Some('#') if text.chars().any(char::is_lowercase) => text.to_uppercase(),
+                    Some('#') => text.to_lowercase(),
+                    _ => text,
+                };
+                let pad = if flag == Some('0') { "0" } else { " " };
+                let fill = width.unwrap_or(0).saturating_sub(text.chars().count());
+                out.push_str(&pad.repeat(fill));
+                out.push_str(&text);
+            }
+        }
+    }
+    out
+}
+
 #[defun]
 fn current_time<'ob>(cx: &'ob Context, env: &Rt<Env>) -> Object<'ob> {
     assert!(
         env.vars.get(sym::CURRENT_TIME_LIST).unwrap() == &sym::TRUE,
         "current-time-list is nil"
     );
-    let duration = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .expect("System time is before the epoch");
+    LispTime::now().into_obj(cx)
+}
+
+#[defun]
+fn float_time(specified_time: Option<Object>) -> Result<f64> {
+    Ok(LispTime::from_obj(specified_time)?.as_float())
+}
+
+#[defun]
+fn time_add<'ob>(a: Object, b: Object, cx: &'ob Context) -> Result<Object<'ob>> {
+    let a = LispTime::from_obj(Some(a))?;
+    let b = LispTime::from_obj(Some(b))?;
+    Ok(LispTime::new(a.secs + b.secs, a.psecs + b.psecs).into_obj(cx))
+}
+
+#[defun]
+fn time_subtract<'ob>(a: Object, b: Object, cx: &'ob Context) -> Result<Object<'ob>> {
+    let a = LispTime::from_obj(Some(a))?;
+    let b = LispTime::from_obj(Some(b))?;
+    Ok(LispTime::new(a.secs - b.secs, a.psecs - b.psecs).into_obj(cx))
+}
+
+#[defun]
+fn time_less_p(a: Object, b: Object) -> Result<bool> {
+    Ok(LispTime::from_obj(Some(a))? < LispTime::from_obj(Some(b))?)
+}
+
+#[defun]
+fn time_equal_p(a: Object, b: Object) -> Result<bool> {
+    Ok(LispTime::from_obj(Some(a))? == LispTime::from_obj(Some(b))?)
+}
+
+#[defun]
+fn format_time_string(
+    format_string: &str,
+    time: Option<Object>,
+    zone: Option<Object>,
+) -> Result<String> {
+    let time = LispTime::from_obj(time)?;
+    let zone = Zone::from_obj(zone)?;
+    Ok(format_time(format_string, &DecodedTime::new(time, &zone)))
+}
+
+#[defun]
+fn decode_time<'ob>(
+    time: Option<Object>,
+    zone: Option<Object>,
+    form: Option<Object>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let time = LispTime::from_obj(time)?;
+    let zone = Zone::from_obj(zone)?;
+    let decoded = DecodedTime::new(time, &zone);
+    // When FORM is t, the seconds keep their sub-second precision
+    let sec: Object = match form {
+        Some(form) if form != NIL && decoded.psecs != 0 => {
+            let ticks = decoded.sec * PSECS_PER_SEC + decoded.psecs;
+            Cons::new(ticks, PSECS_PER_SEC, cx).into()
+        }
+        _ => decoded.sec.into(),
+    };
+    let DecodedTime { min, hour, day, month, year, weekday, dst, offset, .. } = decoded;
+    Ok(list![sec, min, hour, day, month, year, weekday, dst, offset; cx])
+}
+
+#[defun]
+fn encode_time<'ob>(
+    time: Object,
+    obsolescent_arguments: &[Object],
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let mut fields = Vec::new();
+    let zone = if obsolescent_arguments.is_empty() {
+        // (SECOND MINUTE HOUR DAY MONTH YEAR IGNORED DST ZONE)
+        for elem in time.as_list()? {
+            fields.push(elem?);
+        }
+        ensure!(fields.len() >= 6, "Invalid decoded time: {time}");
+        fields.get(8).copied()
+    } else {
+        // SECOND MINUTE HOUR DAY MONTH YEAR &optional ... ZONE
+        fields.push(time);
+        fields.extend_from_slice(obsolescent_arguments);
+        ensure!(fields.len() >= 6, "encode-time requires at least 6 arguments");
+        if fields.len() > 6 {
+            fields.last().copied()
+        } else {
+            None
+        }
+    };
+    let sec = LispTime::from_obj(Some(fields[0]))?;
+    let mut parts = [0; 5];
+    for (part, field) in parts.iter_mut().zip(&fields[1..6]) {
+        let ObjectType::Int(int) = field.untag() else {
+            bail!("Invalid decoded time field: {field}")
+        };
+        *part = int;
+    }
+    let [min, hour, day, month, year] = parts;
+    // out of range months roll over into the year
+    let (year, month) = (year + (month - 1).div_euclid(12), (month - 1).rem_euclid(12) + 1);
+    let days = days_from_civil(year, month, 1) + day - 1;
+    let local = days * SECS_PER_DAY + hour * 3600 + min * 60 + sec.secs;
+    // TODO: use the DST flag to resolve ambiguous local times
+    let zone = Zone::from_obj(zone)?;
+    let guess = local - zone.info(local).0;
+    let secs = local - zone.info(guess).0;
+    Ok(LispTime::new(secs, sec.psecs).into_obj(cx))
+}
+
+#[defun]
+fn current_time_string(time: Option<Object>, zone: Option<Object>) -> Result<String> {
+    let time = LispTime::from_obj(time)?;
+    let zone = Zone::from_obj(zone)?;
+    Ok(format_time("%a %b %e %H:%M:%S %Y", &DecodedTime::new(time, &zone)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+
+    #[test]
+    fn test_civil_days() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11017), (2000, 3, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        for day in -1000..1000 {
+            let (year, month, date) = civil_from_days(day * 97);
+            assert_eq!(days_from_civil(year, month, date), day * 97);
+        }
+    }
+
+    #[test]
+    fn test_format_time() {
+        sym::init_symbols();
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let fmt = |format: &str, time: i64, zone: Object| {
+            format_time_string(format, Some(time.into()), Some(zone)).unwrap()
+        };
+        // 2009-02-13 23:31:30 UTC, a Friday
+        let time = 1_234_567_890;
+        assert_eq!(fmt("%Y-%m-%d %H:%M:%S", time, sym::TRUE.into()), "2009-02-13 23:31:30");
+        assert_eq!(fmt("%F %T %Z %z", time, sym::TRUE.into()), "2009-02-13 23:31:30 UTC +0000");
+        assert_eq!(fmt("%a %A %b %B", time, sym::TRUE.into()), "Fri Friday Feb February");
+        assert_eq!(fmt("%^a %-d %_m %e", time, sym::TRUE.into()), "FRI 13  2 13");
+        assert_eq!(fmt("%I %l %p %j %V", time, sym::TRUE.into()), "11 11 PM 044 07");
+        assert_eq!(fmt("%s %%", time, sym::TRUE.into()), "1234567890 %");
+        assert_eq!(fmt("%H:%M %:z", time, 3600.into()), "00:31 +01:00");
+        let zone = list![-18000, "EST"; cx];
+        assert_eq!(fmt("%F %R %Z", time, zone), "2009-02-13 18:31 EST");
+        let time = LispTime::new(0, 123_456_789_000).into_obj(cx);
+        let nanos = format_time_string("%N %3N", Some(time), Some(sym::TRUE.into())).unwrap();
+        assert_eq!(nanos, "123456789 123");
+    }
 
-    let secs = duration.as_secs();
-    let micros = duration.subsec_micros();
-    let low = secs & 0xffff;
-    let high = secs >> 16;
+    #[test]
+    fn test_time_arith() {
+        sym::init_symbols();
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let sum = time_add(1.into(), cx.add(0.5), cx).unwrap();
+        assert_eq!(float_time(Some(sum)).unwrap(), 1.5);
+        let diff = time_subtract(5.into(), Cons::new(3, 2, cx).into(), cx).unwrap();
+        assert_eq!(float_time(Some(diff)).unwrap(), 3.5);
+        assert!(time_less_p(1.into(), 2.into()).unwrap());
+        assert!(!time_less_p(2.into(), 2.into()).unwrap());
+        assert!(time_equal_p(list![0, 2; cx], 2.into()).unwrap());
+    }
 
-    list![high, low, micros, 0; cx]
+    #[test]
+    fn test_decode_encode() {
+        sym::init_symbols();
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let time = 1_234_567_890;
+        let decoded = decode_time(Some(time.into()), Some(sym::TRUE.into()), None, cx).unwrap();
+        assert_eq!(decoded, list![30, 31, 23, 13, 2, 2009, 5, false, 0; cx]);
+        let encoded = encode_time(decoded, &[], cx).unwrap();
+        assert!(time_equal_p(encoded, time.into()).unwrap());
+        let args = [31.into(), 23.into(), 13.into(), 2.into(), 2009.into(), 3600.into()];
+        let encoded = encode_time(30.into(), &args, cx).unwrap();
+        assert!(time_equal_p(encoded, (time - 3600).into()).unwrap());
+    }
 }
@@ -0,0 +1,612 @@
+//! Parsing XML and HTML.
+//!
+//! Documents are parsed into the lists returned by `libxml-parse-xml-region`.
+//! An element is `(TAG ATTRIBUTES . CHILDREN)`, where TAG is a symbol,
+//! ATTRIBUTES is an alist of symbols and strings, and the children are strings
+//! and elements. A comment is `(comment nil TEXT)`. If a document has more
+//! than one node at the top level, they are wrapped in a `top` element.
+//!
+//! The parser is lenient, like libxml. Elements that are never closed end with
+//! their parent, and end tags that don't match an open element are ignored.
+//! In HTML, names are case insensitive, void elements like `br` have no
+//! children, some elements like `p` and `li` close the one before them, and
+//! the document is always inside `html` and `body` elements.
+use crate::core::{
+    cons::Cons,
+    env::{intern, sym, Env},
+    gc::{Context, Rt},
+    object::{Object, NIL},
+};
+use crate::fns::slice_into_list;
+use anyhow::{bail, Result};
+use rune_core::macros::list;
+use rune_macros::defun;
+
+defsym!(COMMENT);
+
+/// HTML elements that never have children.
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// HTML elements whose contents are text up to their end tag.
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style", "textarea", "title"];
+
+/// HTML elements that close an open `p`.
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "div",
+    "dl",
+    "fieldset",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "ul",
+];
+
+const XML_ENTITIES: &[(&str, char)] =
+    &[("amp", '&'), ("apos", '\''), ("gt", '>'), ("lt", '<'), ("quot", '"')];
+
+const HTML_ENTITIES: &[(&str, char)] = &[
+    ("amp", '&'),
+    ("apos", '\''),
+    ("bull", '•'),
+    ("cent", '¢'),
+    ("copy", '©'),
+    ("deg", '°'),
+    ("divide", '÷'),
+    ("euro", '€'),
+    ("gt", '>'),
+    ("hellip", '…'),
+    ("laquo", '«'),
+    ("ldquo", '“'),
+    ("lsquo", '‘'),
+    ("lt", '<'),
+    ("mdash", '—'),
+    ("middot", '·'),
+    ("nbsp", '\u{a0}'),
+    ("ndash", '–'),
+    ("para", '¶'),
+    ("pound", '£'),
+    ("quot", '"'),
+    ("raquo", '»'),
+    ("rdquo", '”'),
+    ("reg", '®'),
+    ("rsquo", '’'),
+    ("sect", '§'),
+    ("times", '×'),
+    ("trade", '™'),
+    ("yen", '¥'),
+];
+
+enum Node {
+    Element(Element),
+    Text(String),
+    Comment(String),
+}
+
+struct Element {
+    name: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<Node>,
+}
+
+impl Element {
+    fn new(name: &str) -> Self {
+        Self { name: name.to_owned(), attrs: Vec::new(), children: Vec::new() }
+    }
+}
+
+/// The character of the entity at the start of `text`, and the length of the
+/// entity.
+fn entity(text: &str, html: bool) -> Option<(char, usize)> {
+    // Entity names are short, so don't look far for the end
+    let end = text.get(..32).unwrap_or(text).find(';')?;
+    let name = &text[1..end];
+    let c = match name.strip_prefix('#') {
+        Some(number) => {
+            let code = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => number.parse().ok()?,
+            };
+            char::from_u32(code)?
+        }
+        None => {
+            let entities = if html { HTML_ENTITIES } else { XML_ENTITIES };
+            entities.iter().find(|x| x.0 == name)?.1
+        }
+    };
+    Some((c, end + 1))
+}
+
+/// Replace the entities in `text`. Unknown entities are left as they are.
+fn decode_entities(text: &str, html: bool) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find('&') {
+        decoded.push_str(&rest[..i]);
+        rest = &rest[i..];
+        match entity(rest, html) {
+            Some((c, len)) => {
+                decoded.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    html: bool,
+    discard_comments: bool,
+    /// The open elements, outermost first. The first one holds the top level.
+    stack: Vec<Element>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str, html: bool, discard_comments: bool) -> Self {
+        Self { text, pos: 0, html, discard_comments, stack: vec![Element::new("")] }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn push(&mut self, node: Node) {
+        self.stack.last_mut().unwrap().children.push(node);
+    }
+
+    fn push_text(&mut self, text: &str) {
+        let children = &mut self.stack.last_mut().unwrap().children;
+        match children.last_mut() {
+            Some(Node::Text(prev)) => prev.push_str(text),
+            _ => children.push(Node::Text(text.to_owned())),
+        }
+    }
+
+    /// Close the innermost open element.
+    fn pop(&mut self) {
+        let element = self.stack.pop().unwrap();
+        self.push(Node::Element(element));
+    }
+
+    /// The text up to the first occurrence of `end`, and how far past `end`
+    /// that is. If `end` is not found, it is the rest of the text.
+    fn until(&self, end: &str) -> (&'a str, usize) {
+        let rest = self.rest();
+        match rest.find(end) {
+            Some(i) => (&rest[..i], i + end.len()),
+            None => (rest, rest.len()),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn name(&mut self) -> String {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '/' | '>' | '=' | '<'))
+            .unwrap_or(rest.len());
+        self.pos += len;
+        if self.html {
+            rest[..len].to_ascii_lowercase()
+        } else {
+            rest[..len].to_owned()
+        }
+    }
+
+    fn attr_value(&mut self) -> String {
+        let rest = self.rest();
+        let value = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                self.pos += 1;
+                let (value, len) = self.until(&quote.to_string());
+                self.pos += len;
+                value
+            }
+            _ => {
+                let len = rest.find(|c: char| c.is_whitespace() || c == '>').unwrap_or(rest.len());
+                self.pos += len;
+                &rest[..len]
+            }
+        };
+        decode_entities(value, self.html)
+    }
+
+    fn parse(mut self) -> Vec<Node> {
+        while !self.rest().is_empty() {
+            let rest = self.rest();
+            if rest.starts_with("<!--") {
+                self.pos += 4;
+                let (comment, len) = self.until("-->");
+                self.pos += len;
+                if !self.discard_comments {
+                    self.push(Node::Comment(comment.to_owned()));
+                }
+            } else if rest.starts_with("<![CDATA[") {
+                self.pos += 9;
+                let (data, len) = self.until("]]>");
+                self.pos += len;
+                self.push_text(data);
+            } else if rest.starts_with("<!") || rest.starts_with("<?") {
+                // Doctypes and processing instructions
+                self.pos += self.until(">").1;
+            } else if rest.starts_with("</") {
+                self.pos += 2;
+                let name = self.name();
+                self.pos += self.until(">").1;
+                self.end_tag(&name);
+            } else if rest.starts_with('<')
+                && rest[1..].starts_with(|c: char| c.is_alphabetic() || c == '_')
+            {
+                self.start_tag();
+            } else {
+                // A '<' that doesn't start a tag is text
+                let first = rest.chars().next().map_or(0, char::len_utf8);
+                let len = rest[first..].find('<').map_or(rest.len(), |i| i + first);
+                self.pos += len;
+                let text = decode_entities(&rest[..len], self.html);
+                self.push_text(&text);
+            }
+        }
+        while self.stack.len() > 1 {
+            self.pop();
+        }
+        self.stack.pop().unwrap().children
+    }
+
+    fn start_tag(&mut self) {
+        self.pos += 1;
+        let mut element = Element::new(&self.name());
+        let mut self_closing = false;
+        loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            if rest.is_empty() {
+                break;
+            } else if rest.starts_with("/>") {
+                self.pos += 2;
+                self_closing = true;
+                break;
+            } else if rest.starts_with('>') {
+                self.pos += 1;
+                break;
+            }
+            let name = self.name();
+            if name.is_empty() {
+                // Skip anything that can't start an attribute
+                self.pos += rest.chars().next().map_or(0, char::len_utf8);
+                continue;
+            }
+            self.skip_whitespace();
+            let value = if self.rest().starts_with('=') {
+                self.pos += 1;
+                self.skip_whitespace();
+                self.attr_value()
+            } else {
+                String::new()
+            };
+            // The first of a repeated attribute wins
+            if !element.attrs.iter().any(|x| x.0 == name) {
+                element.attrs.push((name, value));
+            }
+        }
+        if self.html {
+            self.close_implied(&element.name);
+        }
+        let name = element.name.as_str();
+        if self_closing || (self.html && VOID_ELEMENTS.contains(&name)) {
+            self.push(Node::Element(element));
+        } else if self.html && RAW_TEXT_ELEMENTS.contains(&name) {
+            let rest = self.rest();
+            let len = rest.to_ascii_lowercase().find(&format!("</{name}")).unwrap_or(rest.len());
+            self.pos += len;
+            if len > 0 {
+                let text = &rest[..len];
+                let text = if name == "textarea" || name == "title" {
+                    decode_entities(text, true)
+                } else {
+                    text.to_owned()
+                };
+                element.children.push(Node::Text(text));
+            }
+            self.stack.push(element);
+        } else {
+            self.stack.push(element);
+        }
+    }
+
+    /// Close the innermost open element named `name`, and everything inside of
+    /// it. If there isn't one, do nothing.
+    fn end_tag(&mut self, name: &str) {
+        if let Some(i) = self.stack.iter().skip(1).rposition(|x| x.name == name) {
+            while self.stack.len() > i + 1 {
+                self.pop();
+            }
+        }
+    }
+
+    /// Close the elements that HTML ends when an element named `name` starts,
+    /// like a `p` before a `div`. They are only closed if they are open inside
+    /// the nearest of the scope elements.
+    fn close_implied(&mut self, name: &str) {
+        let (closes, scope): (&[&str], &[&str]) = match name {
+            "li" => (&["li"], &["ol", "ul"]),
+            "dt" | "dd" => (&["dt", "dd"], &["dl"]),
+            "tr" => (&["tr"], &["table", "tbody", "tfoot", "thead"]),
+            "td" | "th" => (&["td", "th"], &["tr", "table"]),
+            "option" => (&["option"], &["select", "datalist"]),
+            _ if BLOCK_ELEMENTS.contains(&name) => {
+                (&["p"], &["body", "div", "li", "td", "th", "blockquote", "button"])
+            }
+            _ => return,
+        };
+        for i in (1..self.stack.len()).rev() {
+            let open = self.stack[i].name.as_str();
+            if closes.contains(&open) {
+                while self.stack.len() > i {
+                    self.pop();
+                }
+                return;
+            }
+            if scope.contains(&open) {
+                return;
+            }
+        }
+    }
+}
+
+/// Put an HTML document inside `html` and `body` elements, unless it already
+/// is. Comments at the top level stay outside.
+fn html_document(nodes: Vec<Node>) -> Vec<Node> {
+    let mut top = Vec::new();
+    let mut content = Vec::new();
+    for node in nodes {
+        match node {
+            Node::Comment(_) => top.push(node),
+            Node::Text(text) if text.trim().is_empty() => {}
+            _ => content.push(node),
+        }
+    }
+    let html = match content.pop() {
+        Some(Node::Element(element)) if content.is_empty() && element.name == "html" => element,
+        last => {
+            content.extend(last);
+            let mut html = Element::new("html");
+            let mut body = Element::new("body");
+            for node in content {
+                match node {
+                    Node::Element(element) if element.name == "head" => {
+                        html.children.push(Node::Element(element));
+                    }
+                    Node::Element(element) if element.name == "body" => {
+                        body.attrs.extend(element.attrs);
+                        body.children.extend(element.children);
+                    }
+                    _ => body.children.push(node),
+                }
+            }
+            html.children.push(Node::Element(body));
+            html
+        }
+    };
+    top.push(Node::Element(html));
+    top
+}
+
+fn node_to_obj<'ob>(node: &Node, cx: &'ob Context) -> Object<'ob> {
+    match node {
+        Node::Text(text) => cx.add(text.as_str()),
+        Node::Comment(text) => list![sym::COMMENT, NIL, text.as_str(); cx],
+        Node::Element(element) => {
+            let attrs: Vec<Object> = element
+                .attrs
+                .iter()
+                .map(|(name, value)| Cons::new(intern(name, cx), value.as_str(), cx).into())
+                .collect();
+            let children: Vec<Object> =
+                element.children.iter().map(|x| node_to_obj(x, cx)).collect();
+            let children = slice_into_list(&children, None, cx);
+            let attrs = slice_into_list(&attrs, None, cx);
+            Cons::new(intern(&element.name, cx), Cons::new(attrs, children, cx), cx).into()
+        }
+    }
+}
+
+/// Parse `text` and return the document. The result is nil if there is
+/// nothing but whitespace.
+fn parse<'ob>(text: &str, html: bool, discard_comments: bool, cx: &'ob Context) -> Object<'ob> {
+    let nodes = Parser::new(text, html, discard_comments).parse();
+    let mut nodes = if html {
+        html_document(nodes)
+    } else {
+        let blank = |x: &Node| matches!(x, Node::Text(text) if text.trim().is_empty());
+        nodes.into_iter().filter(|x| !blank(x)).collect()
+    };
+    match nodes.len() {
+        0 => NIL,
+        1 => node_to_obj(&nodes.pop().unwrap(), cx),
+        _ => {
+            let mut top = Element::new("top");
+            top.children = nodes;
+            node_to_obj(&Node::Element(top), cx)
+        }
+    }
+}
+
+/// Parse the text of the current buffer between START and END, which default
+/// to the whole buffer.
+fn parse_region<'ob>(
+    start: Option<usize>,
+    end: Option<usize>,
+    html: bool,
+    discard_comments: bool,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let Some(buffer) = env.current_buffer.as_ref() else { bail!("No current buffer") };
+    let start = start.unwrap_or(1);
+    let end = end.unwrap_or(buffer.text.len_chars() + 1);
+    let (s1, s2) = buffer.slice_with_gap(start.min(end), start.max(end))?;
+    Ok(parse(&format!("{s1}{s2}"), html, discard_comments, cx))
+}
+
+/// Return t, since XML and HTML parsing is always available.
+#[defun]
+fn libxml_available_p() -> bool {
+    true
+}
+
+/// Parse STRING as XML and return the document. If DISCARD-COMMENTS is
+/// non-nil, comments are left out.
+#[defun]
+fn parse_xml_string<'ob>(
+    string: &str,
+    discard_comments: Option<()>,
+    cx: &'ob Context,
+) -> Object<'ob> {
+    parse(string, false, discard_comments.is_some(), cx)
+}
+
+/// Parse STRING as HTML and return the document. If DISCARD-COMMENTS is
+/// non-nil, comments are left out.
+#[defun]
+fn parse_html_string<'ob>(
+    string: &str,
+    discard_comments: Option<()>,
+    cx: &'ob Context,
+) -> Object<'ob> {
+    parse(string, true, discard_comments.is_some(), cx)
+}
+
+/// Parse the region between START and END as XML and return the document.
+/// BASE-URL is ignored. If DISCARD-COMMENTS is non-nil, comments are left out.
+#[defun]
+fn libxml_parse_xml_region<'ob>(
+    start: Option<usize>,
+    end: Option<usize>,
+    _base_url: Option<()>,
+    discard_comments: Option<()>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    parse_region(start, end, false, discard_comments.is_some(), env, cx)
+}
+
+/// Parse the region between START and END as HTML and return the document.
+/// BASE-URL is ignored. If DISCARD-COMMENTS is non-nil, comments are left out.
+#[defun]
+fn libxml_parse_html_region<'ob>(
+    start: Option<usize>,
+    end: Option<usize>,
+    _base_url: Option<()>,
+    discard_comments: Option<()>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    parse_region(start, end, true, discard_comments.is_some(), env, cx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+
+    fn check(source: &str, html: bool, expect: &str) {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        assert_eq!(parse(source, html, false, cx).to_string(), expect);
+    }
+
+    #[test]
+    fn test_parse_xml() {
+        check("<a/>", false, "(a nil)");
+        check(
+            "<?xml version=\"1.0\"?>\n<a x='1' y=\"&lt;2&gt;\"><b>one &amp; two</b>text</a>",
+            false,
+            "(a ((x . \"1\") (y . \"<2>\")) (b nil \"one & two\") \"text\")",
+        );
+        check("<A><B/></A>", false, "(A nil (B nil))");
+        check("<a><![CDATA[<b>]]> &#65;&#x42;</a>", false, "(a nil \"<b> AB\")");
+        check("<!-- c --><a>\n</a>", false, "(top nil (comment nil \" c \") (a nil \"\n\"))");
+        check("<a><b>unclosed</a>", false, "(a nil (b nil \"unclosed\"))");
+        check("<a>stray</b> &bogus;</a>", false, "(a nil \"stray &bogus;\")");
+        check("  ", false, "nil");
+    }
+
+    #[test]
+    fn test_parse_html() {
+        check("<p>hi", true, "(html nil (body nil (p nil \"hi\")))");
+        check(
+            "<!DOCTYPE html><HTML><Head><title>a &amp; b</title></head>\
+             <body class=x><P>one<p>two<br>three</body></html>",
+            true,
+            "(html nil (head nil (title nil \"a & b\")) (body ((class . \"x\")) \
+             (p nil \"one\") (p nil \"two\" (br nil) \"three\")))",
+        );
+        check(
+            "<ul><li>a<li>b</ul><input disabled value=&quot;x>",
+            true,
+            "(html nil (body nil (ul nil (li nil \"a\") (li nil \"b\")) \
+             (input ((disabled . \"\") (value . \"\"x\")))))",
+        );
+        check(
+            "<script>if (a < b) {}</script>",
+            true,
+            "(html nil (body nil (script nil \"if (a < b) {}\")))",
+        );
+        check("<p>a<div>b</div>", true, "(html nil (body nil (p nil \"a\") (div nil \"b\")))");
+    }
+
+    #[test]
+    fn test_discard_comments() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let xml = parse_xml_string("<!-- c --><a><!-- d --></a>", Some(()), cx);
+        assert_eq!(xml.to_string(), "(a nil)");
+        let html = parse_html_string("<p>x<!-- d --></p>", None, cx);
+        assert_eq!(html.to_string(), "(html nil (body nil (p nil \"x\" (comment nil \" d \"))))");
+    }
+
+    #[test]
+    fn test_parse_region() {
+        use crate::buffer::{get_buffer_create, set_buffer};
+        use rune_core::macros::root;
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        let buffer = get_buffer_create(cx.add("test_xml_region"), Some(NIL), cx).unwrap();
+        set_buffer(buffer, env, cx).unwrap();
+        env.current_buffer.as_mut().unwrap().text.insert("junk<a>é</a>junk");
+        let xml = libxml_parse_xml_region(Some(5), Some(13), None, None, env, cx).unwrap();
+        assert_eq!(xml.to_string(), "(a nil \"é\")");
+    }
+}
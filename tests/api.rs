@@ -0,0 +1,81 @@
+//! The embedding interface, used the way another crate would use it.
+use rune::api::{serde, Error, ForeignType, ObjectView, Plugin, Registrar, Runtime, Value};
+use std::sync::Mutex;
+
+#[test]
+fn eval_and_call() {
+    Runtime::with(|rt| {
+        assert_eq!(rt.eval("(defvar api-test-x 2) (* api-test-x 21)"), Ok(Value::Int(42)));
+        assert_eq!(rt.variable("api-test-x"), Some(Value::Int(2)));
+        let args = [Value::Int(1), Value::String("a".to_owned())];
+        assert_eq!(rt.call("list", &args), Ok(Value::List(args.to_vec())));
+        assert!(matches!(rt.eval("(car 1"), Err(Error::Read(_))));
+        let err = rt.eval("(car 1)").unwrap_err();
+        let data = Value::List(vec![Value::Symbol("listp".to_owned()), Value::Int(1)]);
+        assert_eq!(err, Error::Signal { symbol: "wrong-type-argument".to_owned(), data });
+    });
+}
+
+#[test]
+fn native_functions() {
+    Runtime::with(|rt| {
+        rt.defun("api-test-add", |a: i64, b: Option<i64>| a + b.unwrap_or(1)).unwrap();
+        assert_eq!(rt.eval("(api-test-add 2 3)"), Ok(Value::Int(5)));
+        assert_eq!(rt.eval("(mapcar #'api-test-add '(1 2))"), rt.eval("'(2 3)"));
+        let value = rt.eval("'(1 (2 . 3))").unwrap();
+        let ObjectView::Cons(cons) = value.view() else { panic!("expected a list") };
+        assert_eq!(cons.car().to_value(), Value::Int(1));
+    });
+}
+
+#[test]
+fn serde_values() {
+    #[derive(::serde::Serialize, ::serde::Deserialize, Debug, PartialEq)]
+    struct Point {
+        x: i64,
+        y: i64,
+    }
+    Runtime::with(|rt| {
+        let point = serde::to_value(&Point { x: 1, y: 2 }).unwrap();
+        rt.set_variable("api-test-point", &point).unwrap();
+        let y = rt.eval("(plist-get api-test-point :y)");
+        assert_eq!(y, Ok(Value::Int(2)));
+        let back: Point = serde::from_value(rt.variable("api-test-point").unwrap()).unwrap();
+        assert_eq!(back, Point { x: 1, y: 2 });
+    });
+}
+
+struct Counter(Mutex<i64>);
+
+struct CounterPlugin;
+
+impl Plugin for CounterPlugin {
+    fn name(&self) -> &str {
+        "api-test-counter"
+    }
+
+    fn init(&self, registrar: &mut Registrar) -> Result<(), Error> {
+        let ty: ForeignType<Counter> = registrar.define_type("api-test-counter")?;
+        let make = ty.clone();
+        registrar.defun("api-test-counter-make", move || make.wrap(Counter(Mutex::new(0))))?;
+        registrar.defun("api-test-counter-incr", move |c: Value| -> Result<i64, Error> {
+            let counter = ty.get(&c)?;
+            let mut count = counter.0.lock().unwrap();
+            *count += 1;
+            Ok(*count)
+        })?;
+        Ok(())
+    }
+}
+
+#[test]
+fn plugins() {
+    Runtime::with(|rt| {
+        rt.add_plugin(&CounterPlugin).unwrap();
+        rt.eval("(require 'api-test-counter)").unwrap();
+        rt.eval("(setq c (api-test-counter-make))").unwrap();
+        rt.eval("(api-test-counter-incr c)").unwrap();
+        assert_eq!(rt.eval("(api-test-counter-incr c)"), Ok(Value::Int(2)));
+        assert_eq!(rt.eval("(api-test-counter-p c)"), Ok(Value::True));
+    });
+}
@@ -0,0 +1,22 @@
+//! The byte compiler, loaded by `bootstrap.el`.
+use rune::api::{Runtime, Value};
+
+/// Whether `form` compiles to the same bytecode as `expect`.
+fn same_bytecode(rt: &mut Runtime, form: &str, expect: &str) -> bool {
+    let source = format!(
+        "(equal (aref (byte-compile '{form}) 1)
+                (aref (byte-compile '{expect}) 1))"
+    );
+    rt.eval(&source) == Ok(Value::True)
+}
+
+#[test]
+fn unused_bindings_and_dead_stores() {
+    Runtime::with(|rt| {
+        rt.bootstrap().unwrap();
+        let unused = "(lambda (y) (let ((_x (car-safe y))) 5))";
+        assert!(same_bytecode(rt, unused, "(lambda (_y) 5)"));
+        let dead_store = "(lambda (y) (setq y 1) (setq y 2) y)";
+        assert!(same_bytecode(rt, dead_store, "(lambda (y) (setq y 2) y)"));
+    });
+}